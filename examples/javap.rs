@@ -0,0 +1,15 @@
+use std::env;
+use std::fs::File;
+use std::io::Read;
+
+fn main() {
+    for arg in env::args().skip(1) {
+        let mut file = File::open(&arg).unwrap();
+        let mut bytes = Vec::new();
+        file.read_to_end(&mut bytes).unwrap();
+        match cafebabe::parse_class(&bytes) {
+            Ok(class) => print!("{}", cafebabe::disassembler::disassemble(&class)),
+            Err(e) => println!("Error: {}", e),
+        };
+    }
+}
@@ -0,0 +1,10 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+use cafebabe::{parse_class_with_options, ParseOptions};
+
+fuzz_target!(|data: &[u8]| {
+    let mut opts = ParseOptions::default();
+    opts.parse_bytecode(true);
+    let _ = parse_class_with_options(data, &opts);
+});
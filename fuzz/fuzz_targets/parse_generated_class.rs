@@ -0,0 +1,19 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+use cafebabe::{parse_class_with_options, ParseOptions};
+use cafebabe_fuzz::GeneratedClass;
+
+fuzz_target!(|class: GeneratedClass| {
+    let bytes = class.to_bytes();
+    let mut opts = ParseOptions::default();
+    opts.parse_bytecode(class.parse_bytecode);
+    opts.lazy_attributes(class.lazy_attributes);
+    if let Ok(class) = parse_class_with_options(&bytes, &opts) {
+        let fields = class.fields.iter().flat_map(|field| &field.attributes);
+        let methods = class.methods.iter().flat_map(|method| &method.attributes);
+        for attr in class.attributes.iter().chain(fields).chain(methods) {
+            let _ = attr.decoded();
+        }
+    }
+});
@@ -0,0 +1,930 @@
+//! A structure-aware generator of class files for fuzzing. The generated classes always have a
+//! well-formed constant pool and member layout, and the references into the pool are mostly of
+//! the right kind, so that the fuzzer spends its time in attribute and bytecode parsing rather
+//! than failing the magic and constant pool checks.
+//!
+//! Run with `cargo fuzz run parse_generated_class` from the repository root.
+
+use std::collections::HashMap;
+
+use arbitrary::{Arbitrary, Result, Unstructured};
+
+const MAX_POOL_ENTRIES: usize = 64;
+const MAX_MEMBERS: usize = 8;
+const MAX_ATTRIBUTES: usize = 6;
+const MAX_INSTRUCTIONS: usize = 64;
+const MAX_ELEMENT_DEPTH: usize = 4;
+
+const CLASS_NAMES: &[&str] = &[
+    "java/lang/Object",
+    "java/lang/String",
+    "java/lang/Runnable",
+    "java/lang/Throwable",
+    "[I",
+    "[Ljava/lang/Object;",
+    "Test",
+    "Test$Inner",
+];
+
+const FIELD_DESCRIPTORS: &[&str] = &[
+    "I",
+    "J",
+    "D",
+    "Z",
+    "[B",
+    "Ljava/lang/String;",
+    "[[Ljava/lang/Object;",
+];
+
+const METHOD_DESCRIPTORS: &[&str] = &[
+    "()V",
+    "(I)I",
+    "(JD)V",
+    "([Ljava/lang/String;)V",
+    "(Ljava/lang/Object;)Ljava/lang/String;",
+];
+
+const FIELD_NAMES: &[&str] = &["run", "value", "x", "$"];
+const METHOD_NAMES: &[&str] = &["<init>", "<clinit>", "run", "value", "x", "$"];
+
+// Names that can appear in a NameAndType entry.
+const REFERENCE_NAMES: &[&str] = &["<init>", "run", "value", "x", "$"];
+
+const ANNOTATION_TYPES: &[&str] = &["Ljava/lang/Deprecated;", "LTest;", "LTest$Inner;"];
+
+const CLASS_FLAGS: &[u16] = &[0x0021, 0x0031, 0x0421, 0x0601, 0x2601, 0x4031, 0x1030];
+const FIELD_FLAGS: &[u16] = &[0x0000, 0x0001, 0x0002, 0x0008, 0x0019, 0x1012, 0x4019];
+const METHOD_FLAGS: &[u16] = &[0x0000, 0x0001, 0x0002, 0x0009, 0x0401, 0x0101, 0x1041, 0x0089];
+
+const CLASS_ATTRIBUTES: &[&str] = &[
+    "SourceFile",
+    "Signature",
+    "Deprecated",
+    "Synthetic",
+    "InnerClasses",
+    "EnclosingMethod",
+    "BootstrapMethods",
+    "NestHost",
+    "NestMembers",
+    "PermittedSubclasses",
+    "RuntimeVisibleAnnotations",
+    "RuntimeInvisibleTypeAnnotations",
+    "Record",
+    "Module",
+    "ModulePackages",
+    "ModuleMainClass",
+    "SourceDebugExtension",
+];
+
+const FIELD_ATTRIBUTES: &[&str] = &[
+    "ConstantValue",
+    "Signature",
+    "Synthetic",
+    "RuntimeVisibleAnnotations",
+];
+
+const METHOD_ATTRIBUTES: &[&str] = &[
+    "Exceptions",
+    "Signature",
+    "MethodParameters",
+    "AnnotationDefault",
+    "RuntimeVisibleParameterAnnotations",
+    "RuntimeInvisibleAnnotations",
+];
+
+const CODE_ATTRIBUTES: &[&str] = &[
+    "LineNumberTable",
+    "LocalVariableTable",
+    "LocalVariableTypeTable",
+    "StackMapTable",
+    "RuntimeVisibleTypeAnnotations",
+];
+
+// Opcodes that take no operands.
+const SIMPLE_OPCODES: &[u8] = &[
+    0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0a, 0x0b, 0x0c, 0x0d, 0x0e, 0x0f,
+    0x1a, 0x1e, 0x22, 0x26, 0x2a, 0x2e, 0x32, 0x3b, 0x4b, 0x4f, 0x53, 0x57, 0x58, 0x59, 0x5a, 0x5f,
+    0x60, 0x64, 0x68, 0x6c, 0x70, 0x74, 0x78, 0x7e, 0x85, 0x88, 0x8b, 0x94, 0x95, 0x97, 0xac, 0xad,
+    0xae, 0xaf, 0xb0, 0xb1, 0xbe, 0xbf, 0xc2, 0xc3,
+];
+
+// Opcodes that take a u16 constant pool index.
+const POOL_OPCODES: &[u8] = &[
+    0x13, 0x14, 0xb2, 0xb3, 0xb4, 0xb5, 0xb6, 0xb7, 0xb8, 0xbb, 0xbd, 0xc0, 0xc1,
+];
+
+// Opcodes that take an i16 branch offset.
+const BRANCH_OPCODES: &[u8] = &[
+    0x99, 0x9a, 0x9b, 0x9c, 0x9d, 0x9e, 0x9f, 0xa0, 0xa1, 0xa2, 0xa3, 0xa4, 0xa5, 0xa6, 0xa7, 0xa8,
+    0xc6, 0xc7,
+];
+
+// Opcodes that take a u8 local variable index.
+const LOCAL_OPCODES: &[u8] = &[
+    0x15, 0x16, 0x17, 0x18, 0x19, 0x36, 0x37, 0x38, 0x39, 0x3a, 0xa9,
+];
+
+/// A generated class file, along with the parse options to use on it.
+#[derive(Debug)]
+pub struct GeneratedClass {
+    bytes: Vec<u8>,
+    pub parse_bytecode: bool,
+    pub lazy_attributes: bool,
+}
+
+impl GeneratedClass {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.bytes.clone()
+    }
+}
+
+impl<'a> Arbitrary<'a> for GeneratedClass {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        let parse_bytecode = u.arbitrary()?;
+        let lazy_attributes = u.arbitrary()?;
+        let bytes = Generator::new(u)?.generate(u)?;
+        Ok(GeneratedClass {
+            bytes,
+            parse_bytecode,
+            lazy_attributes,
+        })
+    }
+}
+
+#[derive(Default)]
+struct Pool {
+    bytes: Vec<u8>,
+    count: u16,
+    utf8: Vec<u16>,
+    utf8_indices: HashMap<String, u16>,
+    classes: Vec<u16>,
+    name_and_types: Vec<u16>,
+    fields: Vec<u16>,
+    methods: Vec<u16>,
+    interface_methods: Vec<u16>,
+    integers: Vec<u16>,
+    floats: Vec<u16>,
+    longs: Vec<u16>,
+    doubles: Vec<u16>,
+    strings: Vec<u16>,
+    // Entries that ldc and ldc_w can load
+    loadables: Vec<u16>,
+    // Dynamic constants, which are loadable but not accepted as bootstrap method arguments
+    constant_dynamics: Vec<u16>,
+    method_handles: Vec<u16>,
+    dynamics: Vec<u16>,
+    needs_bootstrap_methods: bool,
+}
+
+impl Pool {
+    fn push(&mut self, tag: u8, body: &[u8], slots: u16) -> u16 {
+        let index = self.count + 1;
+        self.bytes.push(tag);
+        self.bytes.extend_from_slice(body);
+        self.count += slots;
+        index
+    }
+
+    fn add_utf8(&mut self, text: &str) -> u16 {
+        if let Some(&index) = self.utf8_indices.get(text) {
+            return index;
+        }
+        let mut body = Vec::new();
+        push_u16(&mut body, text.len() as u16);
+        body.extend_from_slice(text.as_bytes());
+        let index = self.push(1, &body, 1);
+        self.utf8.push(index);
+        self.utf8_indices.insert(text.to_string(), index);
+        index
+    }
+
+    fn add_class(&mut self, name: &str) -> u16 {
+        let name = self.add_utf8(name);
+        let index = self.push(7, &name.to_be_bytes(), 1);
+        self.classes.push(index);
+        self.loadables.push(index);
+        index
+    }
+
+    // Adds a handle to a static method, for bootstrap methods to refer to.
+    fn add_method_handle(&mut self) -> u16 {
+        let mut body = Vec::new();
+        push_u16(&mut body, self.add_class("Test"));
+        push_u16(&mut body, self.add_name_and_type("run", "()V"));
+        let method = self.push(10, &body, 1);
+        self.methods.push(method);
+        let index = self.push(15, &[6, (method >> 8) as u8, method as u8], 1);
+        self.method_handles.push(index);
+        self.loadables.push(index);
+        index
+    }
+
+    fn add_name_and_type(&mut self, name: &str, descriptor: &str) -> u16 {
+        let mut body = Vec::new();
+        push_u16(&mut body, self.add_utf8(name));
+        push_u16(&mut body, self.add_utf8(descriptor));
+        let index = self.push(12, &body, 1);
+        self.name_and_types.push(index);
+        index
+    }
+}
+
+struct Generator {
+    // Whether to occasionally generate invalid references, names, flags, constant pool entries and
+    // repeated attributes. This is decided once per class, since a single mistake is enough to
+    // reject the whole class.
+    corrupt: bool,
+    major_version: u16,
+    pool: Pool,
+    this_class: u16,
+    super_class: u16,
+}
+
+impl Generator {
+    fn new(u: &mut Unstructured) -> Result<Self> {
+        let corrupt = u.arbitrary()?;
+        let major_version = u.int_in_range(45..=65)?;
+        let mut pool = Pool::default();
+        let this_class = pool.add_class("Test");
+        let super_class = pool.add_class("java/lang/Object");
+        // Start with one entry of each of the simple kinds, so that most references can be valid
+        for kind in 1..=10 {
+            add_pool_entry(&mut pool, u, kind, major_version, corrupt)?;
+        }
+        for _ in 0..u.int_in_range(0..=MAX_POOL_ENTRIES)? {
+            let kind = u.int_in_range(0..=14)?;
+            add_pool_entry(&mut pool, u, kind, major_version, corrupt)?;
+        }
+        Ok(Generator {
+            corrupt,
+            major_version,
+            pool,
+            this_class,
+            super_class,
+        })
+    }
+
+    fn generate(mut self, u: &mut Unstructured) -> Result<Vec<u8>> {
+        let mut body = Vec::new();
+        let flags = self.access_flags(u, CLASS_FLAGS)?;
+        push_u16(&mut body, flags);
+        push_u16(&mut body, self.this_class);
+        push_u16(&mut body, self.super_class);
+        let interfaces = u.int_in_range(0..=3)?;
+        push_u16(&mut body, interfaces);
+        for _ in 0..interfaces {
+            let interface = self.class(u)?;
+            push_u16(&mut body, interface);
+        }
+        let is_interface = flags & 0x0200 != 0;
+        for &is_method in &[false, true] {
+            let (names, descriptors, attributes, member_flags) = if is_method {
+                (METHOD_NAMES, METHOD_DESCRIPTORS, METHOD_ATTRIBUTES, METHOD_FLAGS)
+            } else {
+                (FIELD_NAMES, FIELD_DESCRIPTORS, FIELD_ATTRIBUTES, FIELD_FLAGS)
+            };
+            let count = u.int_in_range(0..=MAX_MEMBERS)?;
+            push_u16(&mut body, count as u16);
+            let mut has_init = is_interface;
+            let mut has_clinit = false;
+            for i in 0..count {
+                let flags = self.access_flags(u, member_flags)?;
+                push_u16(&mut body, flags);
+                // Suffix the names so that members are not duplicates of each other
+                let (name, descriptor) = match *u.choose(names)? {
+                    "<clinit>" if !has_clinit => {
+                        has_clinit = true;
+                        ("<clinit>".to_string(), "()V")
+                    }
+                    "<init>" if !has_init => {
+                        has_init = true;
+                        ("<init>".to_string(), *u.choose(&["()V", "(JD)V"])?)
+                    }
+                    "<init>" | "<clinit>" => (format!("m{}", i), *u.choose(descriptors)?),
+                    name => (format!("{}{}", name, i), *u.choose(descriptors)?),
+                };
+                let name = if self.corrupt(u)? { self.text(u, names)? } else { self.pool.add_utf8(&name) };
+                push_u16(&mut body, name);
+                let descriptor = self.text(u, &[descriptor])?;
+                push_u16(&mut body, descriptor);
+                let mut member_attributes = self.attributes(u, attributes)?;
+                // Abstract and native methods must not have code
+                if is_method && flags & 0x0500 == 0 && u.ratio(7, 8)? {
+                    let code = self.code(u)?;
+                    member_attributes.push(self.attribute("Code", code));
+                }
+                push_attributes(&mut body, member_attributes);
+            }
+        }
+        let mut class_attributes = self.attributes(u, CLASS_ATTRIBUTES)?;
+        let bootstrap_methods = self.pool.add_utf8("BootstrapMethods");
+        if self.pool.needs_bootstrap_methods
+            && !class_attributes.iter().any(|attribute| attribute[..2] == bootstrap_methods.to_be_bytes())
+            && !self.corrupt(u)?
+        {
+            let data = self.attribute_data(u, "BootstrapMethods")?;
+            class_attributes.push(self.attribute("BootstrapMethods", data));
+        }
+        push_attributes(&mut body, class_attributes);
+
+        let mut bytes = vec![0xca, 0xfe, 0xba, 0xbe];
+        push_u16(&mut bytes, 0);
+        push_u16(&mut bytes, self.major_version);
+        push_u16(&mut bytes, self.pool.count + 1);
+        bytes.extend_from_slice(&self.pool.bytes);
+        bytes.extend_from_slice(&body);
+        Ok(bytes)
+    }
+
+    fn corrupt(&self, u: &mut Unstructured) -> Result<bool> {
+        Ok(self.corrupt && u.ratio(1, 16)?)
+    }
+
+    // Picks one of the given access flags, or occasionally arbitrary flags.
+    fn access_flags(&self, u: &mut Unstructured, choices: &[u16]) -> Result<u16> {
+        if self.corrupt(u)? {
+            return u.arbitrary();
+        }
+        Ok(*u.choose(choices)?)
+    }
+
+    // Picks an index from the given list of pool entries, or occasionally an arbitrary index so
+    // that mismatched references are exercised too.
+    fn index(&self, u: &mut Unstructured, indices: &[u16]) -> Result<u16> {
+        if indices.is_empty() || self.corrupt(u)? {
+            return u.int_in_range(0..=self.pool.count + 1);
+        }
+        Ok(*u.choose(indices)?)
+    }
+
+    fn class(&mut self, u: &mut Unstructured) -> Result<u16> {
+        if u.ratio(1, 4)? {
+            let name = *u.choose(CLASS_NAMES)?;
+            return Ok(self.pool.add_class(name));
+        }
+        let classes = self.pool.classes.clone();
+        self.index(u, &classes)
+    }
+
+    fn text(&mut self, u: &mut Unstructured, choices: &[&str]) -> Result<u16> {
+        if self.corrupt(u)? {
+            let utf8 = self.pool.utf8.clone();
+            return self.index(u, &utf8);
+        }
+        let text = *u.choose(choices)?;
+        Ok(self.pool.add_utf8(text))
+    }
+
+    fn attribute(&mut self, name: &str, data: Vec<u8>) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        push_u16(&mut bytes, self.pool.add_utf8(name));
+        push_u32(&mut bytes, data.len() as u32);
+        bytes.extend(data);
+        bytes
+    }
+
+    fn attributes(&mut self, u: &mut Unstructured, names: &[&str]) -> Result<Vec<Vec<u8>>> {
+        let mut attributes = Vec::new();
+        let mut chosen = Vec::new();
+        for _ in 0..u.int_in_range(0..=MAX_ATTRIBUTES)? {
+            let name = if self.corrupt(u)? {
+                let name: String = u.arbitrary()?;
+                name
+            } else {
+                u.choose(names)?.to_string()
+            };
+            // Most attributes may appear at most once
+            if chosen.contains(&name) && !self.corrupt(u)? {
+                continue;
+            }
+            chosen.push(name.clone());
+            let data = if self.corrupt(u)? {
+                let data: Vec<u8> = u.arbitrary()?;
+                data
+            } else {
+                self.attribute_data(u, &name)?
+            };
+            attributes.push(self.attribute(&name, data));
+        }
+        Ok(attributes)
+    }
+
+    fn attribute_data(&mut self, u: &mut Unstructured, name: &str) -> Result<Vec<u8>> {
+        let mut data = Vec::new();
+        match name {
+            "SourceFile" | "Signature" | "ModuleMainClass" | "NestHost" => {
+                let index = if name == "ModuleMainClass" || name == "NestHost" {
+                    self.class(u)?
+                } else {
+                    self.text(u, FIELD_DESCRIPTORS)?
+                };
+                push_u16(&mut data, index);
+            }
+            "Deprecated" | "Synthetic" => (),
+            "ConstantValue" => {
+                let constants = match u.int_in_range(0..=4)? {
+                    0 => self.pool.integers.clone(),
+                    1 => self.pool.floats.clone(),
+                    2 => self.pool.longs.clone(),
+                    3 => self.pool.doubles.clone(),
+                    _ => self.pool.strings.clone(),
+                };
+                push_u16(&mut data, self.index(u, &constants)?);
+            }
+            "NestMembers" | "PermittedSubclasses" | "Exceptions" => {
+                let count = u.int_in_range(0..=4)?;
+                push_u16(&mut data, count);
+                for _ in 0..count {
+                    let class = self.class(u)?;
+                    push_u16(&mut data, class);
+                }
+            }
+            "InnerClasses" => {
+                let count = u.int_in_range(0..=4)?;
+                push_u16(&mut data, count);
+                for _ in 0..count {
+                    for _ in 0..2 {
+                        let class = self.class(u)?;
+                        push_u16(&mut data, class);
+                    }
+                    let name = self.text(u, FIELD_NAMES)?;
+                    push_u16(&mut data, name);
+                    let flags = self.access_flags(u, &[0x0001, 0x0008, 0x0019, 0x0609])?;
+                    push_u16(&mut data, flags);
+                }
+            }
+            "EnclosingMethod" => {
+                let class = self.class(u)?;
+                push_u16(&mut data, class);
+                let name_and_types = self.pool.name_and_types.clone();
+                push_u16(&mut data, self.index(u, &name_and_types)?);
+            }
+            "BootstrapMethods" => {
+                // Method handles can only be in the constant pool from version 51
+                if self.pool.method_handles.is_empty() && self.major_version >= 51 {
+                    self.pool.add_method_handle();
+                }
+                let count = if self.pool.method_handles.is_empty() { 0 } else { u.int_in_range(2..=4)? };
+                push_u16(&mut data, count);
+                for _ in 0..count {
+                    let method_handles = self.pool.method_handles.clone();
+                    push_u16(&mut data, self.index(u, &method_handles)?);
+                    let arguments = u.int_in_range(0..=3)?;
+                    push_u16(&mut data, arguments);
+                    let pool = &self.pool;
+                    let loadables = pool.loadables.iter().filter(|index| !pool.constant_dynamics.contains(index)).copied().collect::<Vec<_>>();
+                    for _ in 0..arguments {
+                        push_u16(&mut data, self.index(u, &loadables)?);
+                    }
+                }
+            }
+            "MethodParameters" => {
+                let count = u.int_in_range(0..=4)?;
+                data.push(count);
+                for _ in 0..count {
+                    let name = self.text(u, FIELD_NAMES)?;
+                    push_u16(&mut data, name);
+                    let flags = self.access_flags(u, &[0x0000, 0x0010, 0x1000, 0x8000])?;
+                    push_u16(&mut data, flags);
+                }
+            }
+            "RuntimeVisibleAnnotations" | "RuntimeInvisibleAnnotations" => {
+                let count = u.int_in_range(0..=3)?;
+                push_u16(&mut data, count);
+                for _ in 0..count {
+                    self.annotation(u, &mut data, 0)?;
+                }
+            }
+            "RuntimeVisibleParameterAnnotations" => {
+                let parameters = u.int_in_range(0..=2)?;
+                data.push(parameters);
+                for _ in 0..parameters {
+                    let count = u.int_in_range(0..=2)?;
+                    push_u16(&mut data, count);
+                    for _ in 0..count {
+                        self.annotation(u, &mut data, 0)?;
+                    }
+                }
+            }
+            "AnnotationDefault" => self.element_value(u, &mut data, 0)?,
+            "RuntimeVisibleTypeAnnotations" | "RuntimeInvisibleTypeAnnotations" => {
+                let count = u.int_in_range(0..=2)?;
+                push_u16(&mut data, count);
+                for _ in 0..count {
+                    self.type_annotation(u, &mut data)?;
+                }
+            }
+            "Record" => {
+                let count = u.int_in_range(0..=3)?;
+                push_u16(&mut data, count);
+                for _ in 0..count {
+                    let name = self.text(u, FIELD_NAMES)?;
+                    push_u16(&mut data, name);
+                    let descriptor = self.text(u, FIELD_DESCRIPTORS)?;
+                    push_u16(&mut data, descriptor);
+                    let attributes = self.attributes(u, FIELD_ATTRIBUTES)?;
+                    push_attributes(&mut data, attributes);
+                }
+            }
+            "LineNumberTable" => {
+                let count = u.int_in_range(0..=4)?;
+                push_u16(&mut data, count);
+                for _ in 0..count {
+                    push_u16(&mut data, u.int_in_range(0..=32)?);
+                    push_u16(&mut data, u.arbitrary()?);
+                }
+            }
+            "LocalVariableTable" | "LocalVariableTypeTable" => {
+                let count = u.int_in_range(0..=4)?;
+                push_u16(&mut data, count);
+                for _ in 0..count {
+                    push_u16(&mut data, u.int_in_range(0..=32)?);
+                    push_u16(&mut data, u.int_in_range(0..=32)?);
+                    let name = self.text(u, FIELD_NAMES)?;
+                    push_u16(&mut data, name);
+                    let descriptor = self.text(u, FIELD_DESCRIPTORS)?;
+                    push_u16(&mut data, descriptor);
+                    push_u16(&mut data, u.int_in_range(0..=4)?);
+                }
+            }
+            _ => {
+                let raw: Vec<u8> = u.arbitrary()?;
+                data = raw;
+            }
+        }
+        Ok(data)
+    }
+
+    fn annotation(&mut self, u: &mut Unstructured, data: &mut Vec<u8>, depth: usize) -> Result<()> {
+        let type_index = self.text(u, ANNOTATION_TYPES)?;
+        push_u16(data, type_index);
+        let count = u.int_in_range(0..=3)?;
+        push_u16(data, count);
+        for _ in 0..count {
+            let name = self.text(u, FIELD_NAMES)?;
+            push_u16(data, name);
+            self.element_value(u, data, depth + 1)?;
+        }
+        Ok(())
+    }
+
+    fn element_value(&mut self, u: &mut Unstructured, data: &mut Vec<u8>, depth: usize) -> Result<()> {
+        let tags: &[u8] = if depth < MAX_ELEMENT_DEPTH {
+            b"BCDFIJSZsec@["
+        } else {
+            b"BCDFIJSZsec"
+        };
+        let tag = *u.choose(tags)?;
+        data.push(tag);
+        match tag {
+            b'e' => {
+                let type_name = self.text(u, ANNOTATION_TYPES)?;
+                push_u16(data, type_name);
+                let const_name = self.text(u, FIELD_NAMES)?;
+                push_u16(data, const_name);
+            }
+            b'c' => {
+                let index = self.text(u, FIELD_DESCRIPTORS)?;
+                push_u16(data, index);
+            }
+            b's' => {
+                let index = self.text(u, CLASS_NAMES)?;
+                push_u16(data, index);
+            }
+            b'@' => self.annotation(u, data, depth + 1)?,
+            b'[' => {
+                let count = u.int_in_range(0..=3)?;
+                push_u16(data, count);
+                for _ in 0..count {
+                    self.element_value(u, data, depth + 1)?;
+                }
+            }
+            _ => {
+                let constants = match tag {
+                    b'F' => self.pool.floats.clone(),
+                    b'J' => self.pool.longs.clone(),
+                    b'D' => self.pool.doubles.clone(),
+                    _ => self.pool.integers.clone(),
+                };
+                push_u16(data, self.index(u, &constants)?);
+            }
+        }
+        Ok(())
+    }
+
+    fn type_annotation(&mut self, u: &mut Unstructured, data: &mut Vec<u8>) -> Result<()> {
+        let target_type = *u.choose(&[0x00, 0x10, 0x13, 0x16, 0x17, 0x40, 0x42, 0x43, 0x47])?;
+        data.push(target_type);
+        match target_type {
+            0x00 | 0x16 => data.push(u.arbitrary()?),
+            0x10 | 0x17 | 0x42 | 0x43 => push_u16(data, u.arbitrary()?),
+            0x40 => {
+                let count = u.int_in_range(0..=2)?;
+                push_u16(data, count);
+                for _ in 0..count {
+                    for _ in 0..3 {
+                        push_u16(data, u.int_in_range(0..=16)?);
+                    }
+                }
+            }
+            0x47 => {
+                push_u16(data, u.arbitrary()?);
+                data.push(u.arbitrary()?);
+            }
+            _ => (),
+        }
+        let path_length = u.int_in_range(0..=2)?;
+        data.push(path_length);
+        for _ in 0..path_length {
+            data.push(u.int_in_range(0..=3)?);
+            data.push(u.arbitrary()?);
+        }
+        self.annotation(u, data, 0)
+    }
+
+    fn code(&mut self, u: &mut Unstructured) -> Result<Vec<u8>> {
+        let mut code = Vec::new();
+        let mut starts = Vec::new();
+        for _ in 0..u.int_in_range(1..=MAX_INSTRUCTIONS)? {
+            starts.push(code.len());
+            self.instruction(u, &mut code, &starts)?;
+        }
+
+        let mut data = Vec::new();
+        push_u16(&mut data, u.int_in_range(0..=8)?);
+        push_u16(&mut data, u.int_in_range(0..=8)?);
+        push_u32(&mut data, code.len() as u32);
+        data.extend(code);
+        let handlers = u.int_in_range(0..=2)?;
+        push_u16(&mut data, handlers);
+        for _ in 0..handlers {
+            for _ in 0..3 {
+                push_u16(&mut data, *u.choose(&starts)? as u16);
+            }
+            let catch_type = if u.arbitrary()? { self.class(u)? } else { 0 };
+            push_u16(&mut data, catch_type);
+        }
+        let attributes = self.attributes(u, CODE_ATTRIBUTES)?;
+        push_attributes(&mut data, attributes);
+        Ok(data)
+    }
+
+    fn instruction(&mut self, u: &mut Unstructured, code: &mut Vec<u8>, starts: &[usize]) -> Result<()> {
+        let start = code.len();
+        // Jump to the start of an earlier instruction most of the time, or anywhere nearby.
+        let offset = |u: &mut Unstructured| -> Result<i32> {
+            if u.ratio(3, 4)? {
+                Ok(*u.choose(starts)? as i32 - start as i32)
+            } else {
+                u.int_in_range(-8..=64)
+            }
+        };
+        match u.int_in_range(0..=11)? {
+            0..=3 => code.push(*u.choose(SIMPLE_OPCODES)?),
+            4 => {
+                let opcode = *u.choose(POOL_OPCODES)?;
+                let indices = match opcode {
+                    0x13 => self.pool.loadables.clone(),
+                    0x14 => [&self.pool.longs[..], &self.pool.doubles[..]].concat(),
+                    0xb2..=0xb5 => self.pool.fields.clone(),
+                    0xb6 => self.pool.methods.clone(),
+                    0xb7 | 0xb8 => [&self.pool.methods[..], &self.pool.interface_methods[..]].concat(),
+                    _ => self.pool.classes.clone(),
+                };
+                code.push(opcode);
+                push_u16(code, self.index(u, &indices)?);
+            }
+            5 => {
+                let loadables = self.pool.loadables.clone();
+                let index = self.index(u, &loadables)?;
+                code.push(0x12);
+                code.push(index as u8);
+            }
+            6 => {
+                let interface_methods = self.pool.interface_methods.clone();
+                let dynamics = self.pool.dynamics.clone();
+                if u.arbitrary()? {
+                    code.push(0xb9);
+                    push_u16(code, self.index(u, &interface_methods)?);
+                    code.push(u.int_in_range(1..=4)?);
+                    code.push(0);
+                } else {
+                    code.push(0xba);
+                    push_u16(code, self.index(u, &dynamics)?);
+                    push_u16(code, 0);
+                }
+            }
+            7 => {
+                code.push(*u.choose(BRANCH_OPCODES)?);
+                push_u16(code, offset(u)? as u16);
+            }
+            8 => {
+                let opcode = *u.choose(LOCAL_OPCODES)?;
+                if u.ratio(1, 8)? {
+                    code.push(0xc4);
+                    code.push(opcode);
+                    push_u16(code, u.int_in_range(0..=8)?);
+                } else {
+                    code.push(opcode);
+                    code.push(u.int_in_range(0..=8)?);
+                }
+            }
+            9 => match u.int_in_range(0..=4)? {
+                0 => {
+                    code.push(0x10);
+                    code.push(u.arbitrary()?);
+                }
+                1 => {
+                    code.push(0x11);
+                    push_u16(code, u.arbitrary()?);
+                }
+                2 => {
+                    code.push(0xbc);
+                    code.push(u.int_in_range(3..=12)?);
+                }
+                3 => {
+                    code.push(0x84);
+                    code.push(u.int_in_range(0..=8)?);
+                    code.push(u.arbitrary()?);
+                }
+                _ => {
+                    let class = self.class(u)?;
+                    code.push(0xc5);
+                    push_u16(code, class);
+                    code.push(u.int_in_range(0..=3)?);
+                }
+            },
+            10 => {
+                code.push(*u.choose(&[0xc8, 0xc9])?);
+                push_u32(code, offset(u)? as u32);
+            }
+            _ => {
+                let table = u.arbitrary()?;
+                code.push(if table { 0xaa } else { 0xab });
+                code.resize((code.len() + 3) & !3, 0);
+                push_u32(code, offset(u)? as u32);
+                let count = u.int_in_range(0..=4)?;
+                if table {
+                    let low: i32 = u.int_in_range(-4..=4)?;
+                    push_u32(code, low as u32);
+                    push_u32(code, (low + count - 1) as u32);
+                } else {
+                    push_u32(code, count as u32);
+                }
+                for key in 0..count {
+                    if !table {
+                        push_u32(code, key as u32);
+                    }
+                    push_u32(code, offset(u)? as u32);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn add_pool_entry(pool: &mut Pool, u: &mut Unstructured, kind: u8, major_version: u16, corrupt: bool) -> Result<()> {
+    match kind {
+        0 => {
+            let text: String = u.arbitrary()?;
+            pool.add_utf8(&text);
+        }
+        1 => {
+            let index = pool.push(3, &u.arbitrary::<[u8; 4]>()?, 1);
+            pool.integers.push(index);
+            pool.loadables.push(index);
+        }
+        2 => {
+            let index = pool.push(4, &u.arbitrary::<[u8; 4]>()?, 1);
+            pool.floats.push(index);
+            pool.loadables.push(index);
+        }
+        3 => {
+            let index = pool.push(5, &u.arbitrary::<[u8; 8]>()?, 2);
+            pool.longs.push(index);
+        }
+        4 => {
+            let index = pool.push(6, &u.arbitrary::<[u8; 8]>()?, 2);
+            pool.doubles.push(index);
+        }
+        5 => {
+            pool.add_class(u.choose(CLASS_NAMES)?);
+        }
+        6 => {
+            let text = pool.add_utf8(u.choose(CLASS_NAMES)?);
+            let index = pool.push(8, &text.to_be_bytes(), 1);
+            pool.strings.push(index);
+            pool.loadables.push(index);
+        }
+        7 => {
+            pool.add_name_and_type(u.choose(REFERENCE_NAMES)?, u.choose(FIELD_DESCRIPTORS)?);
+        }
+        8..=10 => {
+            let tag = u.int_in_range(9..=11)?;
+            let descriptors = if tag == 9 { FIELD_DESCRIPTORS } else { METHOD_DESCRIPTORS };
+            let mut body = Vec::new();
+            push_u16(&mut body, pool.add_class(u.choose(CLASS_NAMES)?));
+            push_u16(&mut body, pool.add_name_and_type(u.choose(REFERENCE_NAMES)?, u.choose(descriptors)?));
+            let index = pool.push(tag, &body, 1);
+            match tag {
+                9 => pool.fields.push(index),
+                10 => pool.methods.push(index),
+                _ => pool.interface_methods.push(index),
+            }
+        }
+        11..=13 if major_version < 51 => (),
+        11 => {
+            let kind = u.int_in_range(1..=9)?;
+            let members = match kind {
+                1..=4 => &pool.fields,
+                5 | 8 => &pool.methods,
+                // Interface methods are only allowed from version 52
+                6 | 7 if major_version < 52 || u.arbitrary()? => &pool.methods,
+                _ => &pool.interface_methods,
+            };
+            if let Ok(&member) = u.choose(members) {
+                let mut body = vec![kind];
+                push_u16(&mut body, member);
+                let index = pool.push(15, &body, 1);
+                pool.method_handles.push(index);
+                pool.loadables.push(index);
+            }
+        }
+        12 => {
+            let descriptor = pool.add_utf8(u.choose(METHOD_DESCRIPTORS)?);
+            let index = pool.push(16, &descriptor.to_be_bytes(), 1);
+            pool.loadables.push(index);
+        }
+        13 => {
+            let dynamic = major_version >= 55 && u.arbitrary()?;
+            let descriptors = if dynamic { FIELD_DESCRIPTORS } else { METHOD_DESCRIPTORS };
+            let mut body = Vec::new();
+            push_u16(&mut body, u.int_in_range(0..=1)?);
+            push_u16(&mut body, pool.add_name_and_type(u.choose(REFERENCE_NAMES)?, u.choose(descriptors)?));
+            let index = pool.push(if dynamic { 17 } else { 18 }, &body, 1);
+            if dynamic {
+                pool.loadables.push(index);
+                pool.constant_dynamics.push(index);
+            } else {
+                pool.dynamics.push(index);
+            }
+            pool.needs_bootstrap_methods = true;
+        }
+        // Module and Package entries are only valid in module-info classes
+        _ if corrupt && u.ratio(1, 8)? => {
+            let name = pool.add_utf8(u.choose(&["java.base", "java/lang", "test"])?);
+            pool.push(*u.choose(&[19, 20])?, &name.to_be_bytes(), 1);
+        }
+        _ => (),
+    }
+    Ok(())
+}
+
+fn push_u16(bytes: &mut Vec<u8>, value: u16) {
+    bytes.extend_from_slice(&value.to_be_bytes());
+}
+
+fn push_u32(bytes: &mut Vec<u8>, value: u32) {
+    bytes.extend_from_slice(&value.to_be_bytes());
+}
+
+fn push_attributes(bytes: &mut Vec<u8>, attributes: Vec<Vec<u8>>) {
+    push_u16(bytes, attributes.len() as u16);
+    for attribute in attributes {
+        bytes.extend(attribute);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cafebabe::{parse_class_with_options, ParseOptions};
+
+    #[test]
+    fn test_generated_classes_parse() {
+        let mut opts = ParseOptions::default();
+        opts.lazy_attributes(true);
+        // A fixed xorshift sequence stands in for the fuzzer's input
+        let mut state = 0x2545_f491_4f6c_dd1du64;
+        for _ in 0..200 {
+            let mut data = (0..4096)
+                .map(|_| {
+                    state ^= state << 13;
+                    state ^= state >> 7;
+                    state ^= state << 17;
+                    state as u8
+                })
+                .collect::<Vec<_>>();
+            // Turn off corruption, which is the third value the generator reads
+            data[2] &= !1;
+            let class = GeneratedClass::arbitrary(&mut Unstructured::new(&data)).unwrap();
+            let parsed = parse_class_with_options(&class.to_bytes(), &opts);
+            let parsed = parsed.unwrap_or_else(|e| panic!("{} for {:?}", e, class.to_bytes()));
+            let fields = parsed.fields.iter().flat_map(|field| &field.attributes);
+            let methods = parsed.methods.iter().flat_map(|method| &method.attributes);
+            for attr in parsed.attributes.iter().chain(fields).chain(methods) {
+                let _ = attr.decoded();
+            }
+        }
+    }
+}
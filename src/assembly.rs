@@ -0,0 +1,2219 @@
+//! A textual assembly format for class files, in the spirit of Jasmin and Krakatau.
+//!
+//! A class is written as a sequence of directives, one per line. Jump targets and other
+//! code offsets are written as labels, so instructions can be added or removed by hand
+//! without renumbering anything. Constant pool entries are never written out; they are
+//! created as needed when the text is assembled. A small example:
+//!
+//! ```text
+//! .version 52 0
+//! .class public super Hello
+//! .super java/lang/Object
+//!
+//! .method public static main ([Ljava/lang/String;)V
+//!     .code stack 2 locals 1
+//!         getstatic java/lang/System out Ljava/io/PrintStream;
+//!         ldc string "Hello, world"
+//!         invokevirtual java/io/PrintStream println (Ljava/lang/String;)V
+//!         return
+//!     .end code
+//! .end method
+//! .end class
+//! ```
+//!
+//! Tokens are separated by whitespace, and a `;` at the start of a token begins a comment
+//! that runs to the end of the line. Names that contain whitespace or would otherwise be
+//! ambiguous are written as double-quoted strings, which support the escapes `\\`, `\"`,
+//! `\n`, `\t`, `\r` and `\uXXXX`. A token ending with `:` at the start of a line inside a
+//! `.code` block defines a label; anywhere a code offset is expected, either a label or a
+//! plain number may be used.
+//!
+//! Instructions use the mnemonics from the JVM spec. The wide forms of the local variable
+//! instructions are written with a `_w` suffix (as in `iload_w 300`), and instructions
+//! that have several encodings (`iload_0` and `iload 0`, `ldc` and `ldc_w`, `goto` and
+//! `goto_w`) are always assembled using the one that is written. This makes it possible
+//! to write odd but valid bytecode, such as tests for parsers and verifiers need.
+//!
+//! Attributes are written as directives named after the attribute, such as `.sourcefile`
+//! or `.linenumbertable`, and attributes that are not otherwise supported can be written
+//! as raw bytes with `.attribute`. The output of [`print_assembly`] shows the syntax for
+//! each of them.
+
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::convert::TryFrom;
+use std::str::FromStr;
+
+use crate::attributes::{
+    Annotation, AnnotationElement, AnnotationElementValue, AttributeData, AttributeInfo, BootstrapMethodEntry,
+    CodeData, ExceptionTableEntry, InnerClassAccessFlags, InnerClassEntry, LineNumberEntry, LocalVariableEntry,
+    LocalVariableTypeEntry, MethodParameterAccessFlags, MethodParameterEntry, ModuleAccessFlags, ModuleData,
+    ModuleExportsEntry, ModuleExportsFlags, ModuleOpensEntry, ModuleOpensFlags, ModuleProvidesEntry,
+    ModuleRequireEntry, ModuleRequiresFlags, ParameterAnnotation, RecordComponentEntry, StackMapEntry,
+    TypeAnnotation, TypeAnnotationLocalVarTargetEntry, TypeAnnotationTarget, TypeAnnotationTargetPathEntry,
+    TypeAnnotationTargetPathKind, VerificationType,
+};
+use crate::bytecode::{ByteCode, Opcode};
+use crate::constant_pool::{
+    BootstrapArgument, ConstantPoolReader, Dynamic, InvokeDynamic, LiteralConstant, Loadable, MemberKind, MethodHandle, NameAndType,
+    ReferenceKind,
+};
+use crate::disassembler::mnemonic;
+use crate::writer::{encode_code, switch_padding, write_class_with_pool, ConstantPoolWriter};
+use crate::{parse_class, ClassAccessFlags, ClassFile, FieldAccessFlags, FieldInfo, MethodAccessFlags, MethodInfo, ParseError};
+
+const CLASS_FLAGS: &[(u16, &str)] = &[
+    (ClassAccessFlags::PUBLIC.bits(), "public"),
+    (ClassAccessFlags::FINAL.bits(), "final"),
+    (ClassAccessFlags::SUPER.bits(), "super"),
+    (ClassAccessFlags::INTERFACE.bits(), "interface"),
+    (ClassAccessFlags::ABSTRACT.bits(), "abstract"),
+    (ClassAccessFlags::SYNTHETIC.bits(), "synthetic"),
+    (ClassAccessFlags::ANNOTATION.bits(), "annotation"),
+    (ClassAccessFlags::ENUM.bits(), "enum"),
+    (ClassAccessFlags::MODULE.bits(), "module"),
+];
+
+const FIELD_FLAGS: &[(u16, &str)] = &[
+    (FieldAccessFlags::PUBLIC.bits(), "public"),
+    (FieldAccessFlags::PRIVATE.bits(), "private"),
+    (FieldAccessFlags::PROTECTED.bits(), "protected"),
+    (FieldAccessFlags::STATIC.bits(), "static"),
+    (FieldAccessFlags::FINAL.bits(), "final"),
+    (FieldAccessFlags::VOLATILE.bits(), "volatile"),
+    (FieldAccessFlags::TRANSIENT.bits(), "transient"),
+    (FieldAccessFlags::SYNTHETIC.bits(), "synthetic"),
+    (FieldAccessFlags::ENUM.bits(), "enum"),
+];
+
+const METHOD_FLAGS: &[(u16, &str)] = &[
+    (MethodAccessFlags::PUBLIC.bits(), "public"),
+    (MethodAccessFlags::PRIVATE.bits(), "private"),
+    (MethodAccessFlags::PROTECTED.bits(), "protected"),
+    (MethodAccessFlags::STATIC.bits(), "static"),
+    (MethodAccessFlags::FINAL.bits(), "final"),
+    (MethodAccessFlags::SYNCHRONIZED.bits(), "synchronized"),
+    (MethodAccessFlags::BRIDGE.bits(), "bridge"),
+    (MethodAccessFlags::VARARGS.bits(), "varargs"),
+    (MethodAccessFlags::NATIVE.bits(), "native"),
+    (MethodAccessFlags::ABSTRACT.bits(), "abstract"),
+    (MethodAccessFlags::STRICT.bits(), "strict"),
+    (MethodAccessFlags::SYNTHETIC.bits(), "synthetic"),
+];
+
+const INNER_CLASS_FLAGS: &[(u16, &str)] = &[
+    (InnerClassAccessFlags::PUBLIC.bits(), "public"),
+    (InnerClassAccessFlags::PRIVATE.bits(), "private"),
+    (InnerClassAccessFlags::PROTECTED.bits(), "protected"),
+    (InnerClassAccessFlags::STATIC.bits(), "static"),
+    (InnerClassAccessFlags::FINAL.bits(), "final"),
+    (InnerClassAccessFlags::INTERFACE.bits(), "interface"),
+    (InnerClassAccessFlags::ABSTRACT.bits(), "abstract"),
+    (InnerClassAccessFlags::SYNTHETIC.bits(), "synthetic"),
+    (InnerClassAccessFlags::ANNOTATION.bits(), "annotation"),
+    (InnerClassAccessFlags::ENUM.bits(), "enum"),
+];
+
+const METHOD_PARAMETER_FLAGS: &[(u16, &str)] = &[
+    (MethodParameterAccessFlags::FINAL.bits(), "final"),
+    (MethodParameterAccessFlags::SYNTHETIC.bits(), "synthetic"),
+    (MethodParameterAccessFlags::MANDATED.bits(), "mandated"),
+];
+
+const MODULE_FLAGS: &[(u16, &str)] = &[
+    (ModuleAccessFlags::OPEN.bits(), "open"),
+    (ModuleAccessFlags::SYNTHETIC.bits(), "synthetic"),
+    (ModuleAccessFlags::MANDATED.bits(), "mandated"),
+];
+
+const MODULE_REQUIRES_FLAGS: &[(u16, &str)] = &[
+    (ModuleRequiresFlags::TRANSITIVE.bits(), "transitive"),
+    (ModuleRequiresFlags::STATIC_PHASE.bits(), "static_phase"),
+    (ModuleRequiresFlags::SYNTHETIC.bits(), "synthetic"),
+    (ModuleRequiresFlags::MANDATED.bits(), "mandated"),
+];
+
+// The exports and opens flags are the same
+const MODULE_EXPORTS_FLAGS: &[(u16, &str)] = &[
+    (ModuleExportsFlags::SYNTHETIC.bits(), "synthetic"),
+    (ModuleExportsFlags::MANDATED.bits(), "mandated"),
+];
+
+/// Words that have a meaning of their own in some position, and so are quoted when they
+/// are used as names, in addition to all the flag names.
+const RESERVED_WORDS: &[&str] = &["-", "[", "]", "{", "}", "=", "any", "default", "to", "with", "version"];
+
+const REFERENCE_KINDS: &[(ReferenceKind, &str)] = &[
+    (ReferenceKind::GetField, "REF_getField"),
+    (ReferenceKind::GetStatic, "REF_getStatic"),
+    (ReferenceKind::PutField, "REF_putField"),
+    (ReferenceKind::PutStatic, "REF_putStatic"),
+    (ReferenceKind::InvokeVirtual, "REF_invokeVirtual"),
+    (ReferenceKind::InvokeStatic, "REF_invokeStatic"),
+    (ReferenceKind::InvokeSpecial, "REF_invokeSpecial"),
+    (ReferenceKind::NewInvokeSpecial, "REF_newInvokeSpecial"),
+    (ReferenceKind::InvokeInterface, "REF_invokeInterface"),
+];
+
+const PRIMITIVE_ARRAY_TYPES: &[(u8, &str)] = &[
+    (4, "boolean"),
+    (5, "char"),
+    (6, "float"),
+    (7, "double"),
+    (8, "byte"),
+    (9, "short"),
+    (10, "int"),
+    (11, "long"),
+];
+
+const OFFSET_TARGET_TYPES: &[(u8, &str)] = &[
+    (0x43, "instanceof"),
+    (0x44, "new"),
+    (0x45, "constructorreference"),
+    (0x46, "methodreference"),
+];
+
+const TYPE_ARGUMENT_TARGET_TYPES: &[(u8, &str)] = &[
+    (0x47, "cast"),
+    (0x48, "constructorinvocation"),
+    (0x49, "methodinvocation"),
+    (0x4a, "constructorreference"),
+    (0x4b, "methodreference"),
+];
+
+fn is_reserved(word: &str) -> bool {
+    RESERVED_WORDS.contains(&word)
+        || [CLASS_FLAGS, FIELD_FLAGS, METHOD_FLAGS, INNER_CLASS_FLAGS, METHOD_PARAMETER_FLAGS, MODULE_FLAGS, MODULE_REQUIRES_FLAGS]
+            .iter()
+            .any(|table| table.iter().any(|(_, name)| *name == word))
+}
+
+fn string_literal(text: &str) -> String {
+    let mut quoted = String::with_capacity(text.len() + 2);
+    quoted.push('"');
+    for c in text.chars() {
+        match c {
+            '\\' => quoted.push_str("\\\\"),
+            '"' => quoted.push_str("\\\""),
+            '\n' => quoted.push_str("\\n"),
+            '\t' => quoted.push_str("\\t"),
+            '\r' => quoted.push_str("\\r"),
+            c if c.is_control() || (c.is_whitespace() && c != ' ') => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Returns a name as a token, quoting it if it could not be read back as a plain token.
+fn quote(name: &str) -> String {
+    let plain = !name.is_empty()
+        && !name.starts_with(';')
+        && !name.ends_with(':')
+        && !name.chars().any(|c| c.is_whitespace() || c.is_control() || c == '"')
+        && !is_reserved(name);
+    if plain {
+        name.to_string()
+    } else {
+        string_literal(name)
+    }
+}
+
+fn quote_opt(name: &Option<String>) -> String {
+    match name {
+        Some(name) => quote(name),
+        None => "-".to_string(),
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn flags_text(bits: u16, names: &[(u16, &str)]) -> String {
+    names
+        .iter()
+        .filter(|(flag, _)| bits & flag != 0)
+        .map(|(_, name)| format!("{} ", name))
+        .collect()
+}
+
+fn float_text(v: f32) -> String {
+    if v.is_nan() && v.to_bits() != f32::NAN.to_bits() {
+        format!("floatbits 0x{:08x}", v.to_bits())
+    } else {
+        format!("float {:?}", v)
+    }
+}
+
+fn double_text(v: f64) -> String {
+    if v.is_nan() && v.to_bits() != f64::NAN.to_bits() {
+        format!("doublebits 0x{:016x}", v.to_bits())
+    } else {
+        format!("double {:?}", v)
+    }
+}
+
+fn literal_text(literal: &LiteralConstant) -> String {
+    match literal {
+        LiteralConstant::Integer(v) => format!("int {}", v),
+        LiteralConstant::Float(v) => float_text(*v),
+        LiteralConstant::Long(v) => format!("long {}", v),
+        LiteralConstant::Double(v) => double_text(*v),
+        LiteralConstant::String(s) => format!("string {}", string_literal(s)),
+        LiteralConstant::StringBytes(b) => format!("stringbytes {}", hex(b)),
+    }
+}
+
+fn member_kind_name(kind: MemberKind) -> &'static str {
+    match kind {
+        MemberKind::Field => "Field",
+        MemberKind::Method => "Method",
+        MemberKind::InterfaceMethod => "InterfaceMethod",
+    }
+}
+
+fn name_and_type_text(name_and_type: &NameAndType) -> String {
+    format!("{} {}", quote(&name_and_type.name), quote(&name_and_type.descriptor))
+}
+
+fn method_handle_text(handle: &MethodHandle) -> String {
+    let kind = REFERENCE_KINDS.iter().find(|(kind, _)| *kind == handle.kind).map(|(_, name)| *name).unwrap_or_default();
+    format!(
+        "{} {} {} {}",
+        kind,
+        member_kind_name(handle.member_kind),
+        quote(&handle.class_name),
+        name_and_type_text(&handle.member_ref)
+    )
+}
+
+fn loadable_text(loadable: &Loadable) -> String {
+    match loadable {
+        Loadable::LiteralConstant(literal) => literal_text(literal),
+        Loadable::ClassInfo(name) => format!("class {}", quote(name)),
+        Loadable::MethodHandle(handle) => format!("methodhandle {}", method_handle_text(handle)),
+        Loadable::MethodType(descriptor) => format!("methodtype {}", quote(descriptor)),
+        Loadable::Dynamic(dynamic) => format!("dynamic {} {}", dynamic.attr_index, name_and_type_text(&dynamic.name_and_type)),
+    }
+}
+
+fn bootstrap_argument_text(argument: &BootstrapArgument) -> String {
+    match argument {
+        BootstrapArgument::LiteralConstant(literal) => literal_text(literal),
+        BootstrapArgument::ClassInfo(name) => format!("class {}", quote(name)),
+        BootstrapArgument::MethodHandle(handle) => format!("methodhandle {}", method_handle_text(handle)),
+        BootstrapArgument::MethodType(descriptor) => format!("methodtype {}", quote(descriptor)),
+    }
+}
+
+fn element_value_text(value: &AnnotationElementValue) -> String {
+    match value {
+        AnnotationElementValue::ByteConstant(v) => format!("byte {}", v),
+        AnnotationElementValue::CharConstant(v) => format!("char {}", v),
+        AnnotationElementValue::DoubleConstant(v) => double_text(*v),
+        AnnotationElementValue::FloatConstant(v) => float_text(*v),
+        AnnotationElementValue::IntConstant(v) => format!("int {}", v),
+        AnnotationElementValue::LongConstant(v) => format!("long {}", v),
+        AnnotationElementValue::ShortConstant(v) => format!("short {}", v),
+        AnnotationElementValue::BooleanConstant(v) => format!("boolean {}", v),
+        AnnotationElementValue::StringConstant(s) => format!("string {}", string_literal(s)),
+        AnnotationElementValue::EnumConstant { type_name, const_name } => format!("enum {} {}", quote(type_name), quote(const_name)),
+        AnnotationElementValue::ClassLiteral { class_name } => format!("class {}", quote(class_name)),
+        AnnotationElementValue::AnnotationValue(annotation) => {
+            let mut text = format!("annotation {} {{", quote(&annotation.type_descriptor));
+            for element in &annotation.elements {
+                text.push_str(&format!(" {} = {}", quote(&element.name), element_value_text(&element.value)));
+            }
+            text.push_str(" }");
+            text
+        }
+        AnnotationElementValue::ArrayValue(values) => {
+            let mut text = "array [".to_string();
+            for value in values {
+                text.push(' ');
+                text.push_str(&element_value_text(value));
+            }
+            text.push_str(" ]");
+            text
+        }
+    }
+}
+
+fn type_path_text(path: &[TypeAnnotationTargetPathEntry]) -> String {
+    let mut text = "[".to_string();
+    for entry in path {
+        let kind = match entry.path_kind {
+            TypeAnnotationTargetPathKind::DeeperArray => "array",
+            TypeAnnotationTargetPathKind::DeeperNested => "nested",
+            TypeAnnotationTargetPathKind::WildcardTypeArgument => "wildcard",
+            TypeAnnotationTargetPathKind::TypeArgument => "typeargument",
+        };
+        text.push(' ');
+        text.push_str(kind);
+        // The index is only meaningful for type arguments, and zero otherwise
+        if matches!(entry.path_kind, TypeAnnotationTargetPathKind::TypeArgument) || entry.argument_index != 0 {
+            text.push_str(&format!(" {}", entry.argument_index));
+        }
+    }
+    text.push_str(" ]");
+    text
+}
+
+/// Returns true if the opcode is one of the forms of a local variable instruction that
+/// has the index built in, such as iload_0.
+fn is_short_local(opcode: u8) -> bool {
+    matches!(opcode, 0x1a..=0x2d | 0x3b..=0x4e)
+}
+
+struct Printer<'a> {
+    class: &'a ClassFile,
+    out: String,
+    /// The instruction boundaries of the code being printed, at which labels can be placed
+    boundaries: HashSet<usize>,
+    /// The boundaries that positions in the code being printed refer to
+    referenced: BTreeSet<usize>,
+}
+
+impl<'a> Printer<'a> {
+    fn line(&mut self, indent: usize, text: &str) {
+        for _ in 0..indent {
+            self.out.push(' ');
+        }
+        self.out.push_str(text);
+        self.out.push('\n');
+    }
+
+    fn position(&mut self, offset: i64) -> String {
+        match usize::try_from(offset) {
+            Ok(offset) if self.boundaries.contains(&offset) => {
+                self.referenced.insert(offset);
+                format!("L{}", offset)
+            }
+            _ => offset.to_string(),
+        }
+    }
+
+    fn print_class(&mut self) -> Result<(), ParseError> {
+        let class = self.class;
+        self.line(0, &format!(".version {} {}", class.major_version, class.minor_version));
+        self.line(0, &format!(".class {}{}", flags_text(class.access_flags.bits(), CLASS_FLAGS), quote(&class.this_class)));
+        if let Some(super_class) = &class.super_class {
+            self.line(0, &format!(".super {}", quote(super_class)));
+        }
+        if !class.interfaces.is_empty() {
+            let interfaces: Vec<String> = class.interfaces.iter().map(|name| quote(name)).collect();
+            self.line(0, &format!(".implements {}", interfaces.join(" ")));
+        }
+        self.attributes(&class.attributes, 0).map_err(|e| err!(e, "class"))?;
+        for (i, field) in class.fields.iter().enumerate() {
+            self.out.push('\n');
+            self.line(0, &format!(".field {}{} {}", flags_text(field.access_flags.bits(), FIELD_FLAGS), quote(&field.name), quote(&field.descriptor)));
+            self.attributes(&field.attributes, 4).map_err(|e| err!(e, "class field {}", i))?;
+            self.line(0, ".end field");
+        }
+        for (i, method) in class.methods.iter().enumerate() {
+            self.out.push('\n');
+            self.line(0, &format!(".method {}{} {}", flags_text(method.access_flags.bits(), METHOD_FLAGS), quote(&method.name), quote(&method.descriptor)));
+            self.attributes(&method.attributes, 4).map_err(|e| err!(e, "class method {}", i))?;
+            self.line(0, ".end method");
+        }
+        self.line(0, ".end class");
+        Ok(())
+    }
+
+    fn attributes(&mut self, attributes: &[AttributeInfo], indent: usize) -> Result<(), ParseError> {
+        for (i, attr) in attributes.iter().enumerate() {
+            self.attribute(attr, indent).map_err(|e| err!(e, "{} attribute {}", attr.name, i))?;
+        }
+        Ok(())
+    }
+
+    fn attribute(&mut self, attr: &AttributeInfo, indent: usize) -> Result<(), ParseError> {
+        let inner = indent + 4;
+        match attr.decoded()? {
+            AttributeData::ConstantValue(literal) => self.line(indent, &format!(".constantvalue {}", literal_text(literal))),
+            AttributeData::Code(code) => self.code(code, indent)?,
+            AttributeData::StackMapTable(entries) => {
+                self.line(indent, ".stackmaptable");
+                let mut offset: i64 = -1;
+                for entry in entries {
+                    let text = match entry {
+                        StackMapEntry::Same { offset_delta } => {
+                            offset += i64::from(*offset_delta) + 1;
+                            format!("same {}", self.position(offset))
+                        }
+                        StackMapEntry::SameLocals1StackItem { offset_delta, stack } => {
+                            offset += i64::from(*offset_delta) + 1;
+                            format!("same_locals_1_stack_item {} {}", self.position(offset), self.verification_type(stack))
+                        }
+                        StackMapEntry::Chop { offset_delta, chop_count } => {
+                            offset += i64::from(*offset_delta) + 1;
+                            format!("chop {} {}", self.position(offset), chop_count)
+                        }
+                        StackMapEntry::Append { offset_delta, locals } => {
+                            offset += i64::from(*offset_delta) + 1;
+                            format!("append {} {}", self.position(offset), self.verification_types(locals))
+                        }
+                        StackMapEntry::FullFrame { offset_delta, locals, stack } => {
+                            offset += i64::from(*offset_delta) + 1;
+                            format!(
+                                "full_frame {} locals [ {}] stack [ {}]",
+                                self.position(offset),
+                                self.verification_types(locals),
+                                self.verification_types(stack)
+                            )
+                        }
+                    };
+                    self.line(inner, text.trim_end());
+                }
+                self.line(indent, ".end stackmaptable");
+            }
+            AttributeData::Exceptions(names) => self.name_list(indent, ".exceptions", names),
+            AttributeData::InnerClasses(entries) => {
+                self.line(indent, ".innerclasses");
+                for entry in entries {
+                    let text = format!(
+                        "{} {} {} {}",
+                        quote(&entry.inner_class_info),
+                        quote_opt(&entry.outer_class_info),
+                        quote_opt(&entry.inner_name),
+                        flags_text(entry.access_flags.bits(), INNER_CLASS_FLAGS)
+                    );
+                    self.line(inner, text.trim_end());
+                }
+                self.line(indent, ".end innerclasses");
+            }
+            AttributeData::EnclosingMethod { class_name, method } => match method {
+                Some(method) => self.line(indent, &format!(".enclosingmethod {} {}", quote(class_name), name_and_type_text(method))),
+                None => self.line(indent, &format!(".enclosingmethod {}", quote(class_name))),
+            },
+            AttributeData::Synthetic => self.line(indent, ".synthetic"),
+            AttributeData::Signature(signature) => self.line(indent, &format!(".signature {}", quote(signature))),
+            AttributeData::SourceFile(name) => self.line(indent, &format!(".sourcefile {}", quote(name))),
+            AttributeData::SourceDebugExtension(text) => self.line(indent, &format!(".sourcedebugextension {}", string_literal(text))),
+            AttributeData::LineNumberTable(entries) => {
+                self.line(indent, ".linenumbertable");
+                for entry in entries {
+                    let text = format!("{} {}", self.position(i64::from(entry.start_pc)), entry.line_number);
+                    self.line(inner, &text);
+                }
+                self.line(indent, ".end linenumbertable");
+            }
+            AttributeData::LocalVariableTable(entries) => {
+                self.line(indent, ".localvariabletable");
+                for entry in entries {
+                    let start = i64::from(entry.start_pc);
+                    let text = format!(
+                        "{} {} {} {} {}",
+                        entry.index,
+                        quote(&entry.name),
+                        quote(&entry.descriptor),
+                        self.position(start),
+                        self.position(start + i64::from(entry.length))
+                    );
+                    self.line(inner, &text);
+                }
+                self.line(indent, ".end localvariabletable");
+            }
+            AttributeData::LocalVariableTypeTable(entries) => {
+                self.line(indent, ".localvariabletypetable");
+                for entry in entries {
+                    let start = i64::from(entry.start_pc);
+                    let text = format!(
+                        "{} {} {} {} {}",
+                        entry.index,
+                        quote(&entry.name),
+                        quote(&entry.signature),
+                        self.position(start),
+                        self.position(start + i64::from(entry.length))
+                    );
+                    self.line(inner, &text);
+                }
+                self.line(indent, ".end localvariabletypetable");
+            }
+            AttributeData::Deprecated => self.line(indent, ".deprecated"),
+            AttributeData::RuntimeVisibleAnnotations(annotations) => self.annotations(indent, "runtimevisibleannotations", annotations),
+            AttributeData::RuntimeInvisibleAnnotations(annotations) => self.annotations(indent, "runtimeinvisibleannotations", annotations),
+            AttributeData::RuntimeVisibleParameterAnnotations(parameters) => {
+                self.parameter_annotations(indent, "runtimevisibleparameterannotations", parameters)
+            }
+            AttributeData::RuntimeInvisibleParameterAnnotations(parameters) => {
+                self.parameter_annotations(indent, "runtimeinvisibleparameterannotations", parameters)
+            }
+            AttributeData::RuntimeVisibleTypeAnnotations(annotations) => self.type_annotations(indent, "runtimevisibletypeannotations", annotations),
+            AttributeData::RuntimeInvisibleTypeAnnotations(annotations) => self.type_annotations(indent, "runtimeinvisibletypeannotations", annotations),
+            AttributeData::AnnotationDefault(value) => self.line(indent, &format!(".annotationdefault {}", element_value_text(value))),
+            AttributeData::BootstrapMethods(entries) => {
+                self.line(indent, ".bootstrapmethods");
+                for entry in entries {
+                    self.line(inner, &format!(".bootstrap {}", method_handle_text(&entry.method)));
+                    for argument in &entry.arguments {
+                        self.line(inner + 4, &bootstrap_argument_text(argument));
+                    }
+                    self.line(inner, ".end bootstrap");
+                }
+                self.line(indent, ".end bootstrapmethods");
+            }
+            AttributeData::MethodParameters(entries) => {
+                self.line(indent, ".methodparameters");
+                for entry in entries {
+                    let text = format!("{} {}", quote_opt(&entry.name), flags_text(entry.access_flags.bits(), METHOD_PARAMETER_FLAGS));
+                    self.line(inner, text.trim_end());
+                }
+                self.line(indent, ".end methodparameters");
+            }
+            AttributeData::Module(module) => self.module(module, indent),
+            AttributeData::ModulePackages(packages) => self.name_list(indent, ".modulepackages", packages),
+            AttributeData::ModuleMainClass(name) => self.line(indent, &format!(".modulemainclass {}", quote(name))),
+            AttributeData::NestHost(name) => self.line(indent, &format!(".nesthost {}", quote(name))),
+            AttributeData::NestMembers(names) => self.name_list(indent, ".nestmembers", names),
+            AttributeData::Record(components) => {
+                self.line(indent, ".record");
+                for (i, component) in components.iter().enumerate() {
+                    self.line(inner, &format!(".component {} {}", quote(&component.name), quote(&component.descriptor)));
+                    self.attributes(&component.attributes, inner + 4).map_err(|e| err!(e, "entry {}", i))?;
+                    self.line(inner, ".end component");
+                }
+                self.line(indent, ".end record");
+            }
+            AttributeData::Other(bytes) => {
+                let text = format!(".attribute {} {}", quote(&attr.name), hex(bytes));
+                self.line(indent, text.trim_end());
+            }
+            AttributeData::Lazy(_) => fail!("Unexpected undecoded attribute"),
+        }
+        Ok(())
+    }
+
+    fn name_list(&mut self, indent: usize, directive: &str, names: &[String]) {
+        let mut text = directive.to_string();
+        for name in names {
+            text.push(' ');
+            text.push_str(&quote(name));
+        }
+        self.line(indent, &text);
+    }
+
+    fn verification_type(&mut self, verification_type: &VerificationType) -> String {
+        match verification_type {
+            VerificationType::Top => "top".to_string(),
+            VerificationType::Integer => "int".to_string(),
+            VerificationType::Float => "float".to_string(),
+            VerificationType::Long => "long".to_string(),
+            VerificationType::Double => "double".to_string(),
+            VerificationType::Null => "null".to_string(),
+            VerificationType::UninitializedThis => "uninitializedthis".to_string(),
+            VerificationType::Uninitialized { code_offset } => format!("uninitialized {}", self.position(i64::from(*code_offset))),
+            VerificationType::Object { class_name } => format!("object {}", quote(class_name)),
+        }
+    }
+
+    /// Returns the text for a list of verification types, with a trailing space after each.
+    fn verification_types(&mut self, verification_types: &[VerificationType]) -> String {
+        let mut text = String::new();
+        for verification_type in verification_types {
+            text.push_str(&self.verification_type(verification_type));
+            text.push(' ');
+        }
+        text
+    }
+
+    fn annotation(&mut self, indent: usize, directive: &str, annotation: &Annotation) {
+        self.line(indent, &format!("{} {}", directive, quote(&annotation.type_descriptor)));
+        for element in &annotation.elements {
+            self.line(indent + 4, &format!("{} = {}", quote(&element.name), element_value_text(&element.value)));
+        }
+        let name = directive[1..].split(' ').next().unwrap_or_default();
+        self.line(indent, &format!(".end {}", name));
+    }
+
+    fn annotations(&mut self, indent: usize, name: &str, annotations: &[Annotation]) {
+        self.line(indent, &format!(".{}", name));
+        for annotation in annotations {
+            self.annotation(indent + 4, ".annotation", annotation);
+        }
+        self.line(indent, &format!(".end {}", name));
+    }
+
+    fn parameter_annotations(&mut self, indent: usize, name: &str, parameters: &[ParameterAnnotation]) {
+        self.line(indent, &format!(".{}", name));
+        for parameter in parameters {
+            self.line(indent + 4, ".parameter");
+            for annotation in &parameter.annotations {
+                self.annotation(indent + 8, ".annotation", annotation);
+            }
+            self.line(indent + 4, ".end parameter");
+        }
+        self.line(indent, &format!(".end {}", name));
+    }
+
+    fn type_annotations(&mut self, indent: usize, name: &str, annotations: &[TypeAnnotation]) {
+        self.line(indent, &format!(".{}", name));
+        for annotation in annotations {
+            let target = match &annotation.target_type {
+                TypeAnnotationTarget::TypeParameter { index } => format!("typeparameter {}", index),
+                TypeAnnotationTarget::Supertype { index } => format!("supertype {}", index),
+                TypeAnnotationTarget::TypeParameterBound { type_parameter_index, bound_index } => {
+                    format!("typeparameterbound {} {}", type_parameter_index, bound_index)
+                }
+                TypeAnnotationTarget::Empty => "empty".to_string(),
+                TypeAnnotationTarget::FormalParameter { index } => format!("formalparameter {}", index),
+                TypeAnnotationTarget::Throws { index } => format!("throws {}", index),
+                TypeAnnotationTarget::LocalVar(entries) => {
+                    let mut text = "localvar [".to_string();
+                    for entry in entries {
+                        let start = i64::from(entry.start_pc);
+                        let end = start + i64::from(entry.length);
+                        text.push_str(&format!(" {} {} {}", self.position(start), self.position(end), entry.index));
+                    }
+                    text.push_str(" ]");
+                    text
+                }
+                TypeAnnotationTarget::Catch { exception_table_index } => format!("catch {}", exception_table_index),
+                TypeAnnotationTarget::Offset { target_type, offset } => {
+                    let kind = OFFSET_TARGET_TYPES.iter().find(|(t, _)| t == target_type).map(|(_, name)| *name).unwrap_or_default();
+                    format!("offset {} {}", kind, self.position(i64::from(*offset)))
+                }
+                TypeAnnotationTarget::TypeArgument { target_type, offset, type_argument_index } => {
+                    let kind = TYPE_ARGUMENT_TARGET_TYPES.iter().find(|(t, _)| t == target_type).map(|(_, name)| *name).unwrap_or_default();
+                    format!("typeargument {} {} {}", kind, self.position(i64::from(*offset)), type_argument_index)
+                }
+            };
+            let directive = format!(".typeannotation {} {}", target, type_path_text(&annotation.target_path));
+            self.annotation(indent + 4, &directive, &annotation.annotation);
+        }
+        self.line(indent, &format!(".end {}", name));
+    }
+
+    fn module(&mut self, module: &ModuleData, indent: usize) {
+        let inner = indent + 4;
+        let version = |version: &Option<String>| match version {
+            Some(version) => format!(" version {}", quote(version)),
+            None => String::new(),
+        };
+        self.line(indent, &format!(".module {}{}{}", flags_text(module.access_flags.bits(), MODULE_FLAGS), quote(&module.name), version(&module.version)));
+        for entry in &module.requires {
+            let text = format!(".requires {}{}{}", flags_text(entry.flags.bits(), MODULE_REQUIRES_FLAGS), quote(&entry.name), version(&entry.version));
+            self.line(inner, &text);
+        }
+        let targets = |directive: &str, names: &[String]| {
+            let mut text = String::new();
+            if !names.is_empty() {
+                text.push(' ');
+                text.push_str(directive);
+                for name in names {
+                    text.push(' ');
+                    text.push_str(&quote(name));
+                }
+            }
+            text
+        };
+        for entry in &module.exports {
+            let text = format!(".exports {}{}{}", flags_text(entry.flags.bits(), MODULE_EXPORTS_FLAGS), quote(&entry.package_name), targets("to", &entry.exports_to));
+            self.line(inner, &text);
+        }
+        for entry in &module.opens {
+            let text = format!(".opens {}{}{}", flags_text(entry.flags.bits(), MODULE_EXPORTS_FLAGS), quote(&entry.package_name), targets("to", &entry.opens_to));
+            self.line(inner, &text);
+        }
+        for name in &module.uses {
+            self.line(inner, &format!(".uses {}", quote(name)));
+        }
+        for entry in &module.provides {
+            self.line(inner, &format!(".provides {}{}", quote(&entry.service_interface_name), targets("with", &entry.provides_with)));
+        }
+        self.line(indent, ".end module");
+    }
+
+    fn code(&mut self, code: &CodeData, indent: usize) -> Result<(), ParseError> {
+        // The instructions are printed from the code bytes, which tell which of the
+        // encodings of an instruction was used, and which kind of constant pool entry
+        // invokespecial and invokestatic refer to.
+        let mut pool = ConstantPoolWriter::from_class(self.class);
+        let bytes = encode_code(code, &mut pool)?;
+        let parsed;
+        let opcodes = match &code.bytecode {
+            Some(bytecode) => &bytecode.opcodes,
+            None => {
+                parsed = ByteCode::from(&bytes, &ConstantPoolReader::unlimited(&self.class.constant_pool))?;
+                &parsed.opcodes
+            }
+        };
+        self.boundaries = opcodes.iter().map(|(offset, _)| *offset).collect();
+        self.boundaries.insert(bytes.len());
+        self.referenced.clear();
+
+        let mut instructions = Vec::with_capacity(opcodes.len());
+        for (offset, opcode) in opcodes {
+            let text = self.instruction(*offset, opcode, &bytes, &pool, indent + 4);
+            instructions.push((*offset, text));
+        }
+        // The exception table and attributes follow the instructions, but labels they refer
+        // to have to be known before the instructions are written out.
+        let outer = std::mem::take(&mut self.out);
+        for entry in &code.exception_table {
+            let catch_type = match &entry.catch_type {
+                Some(name) => quote(name),
+                None => "any".to_string(),
+            };
+            let text = format!(
+                ".catch {} {} {} {}",
+                catch_type,
+                self.position(i64::from(entry.start_pc)),
+                self.position(i64::from(entry.end_pc)),
+                self.position(i64::from(entry.handler_pc))
+            );
+            self.line(indent + 4, &text);
+        }
+        let result = self.attributes(&code.attributes, indent + 4);
+        let tail = std::mem::replace(&mut self.out, outer);
+        result?;
+
+        self.line(indent, &format!(".code stack {} locals {}", code.max_stack, code.max_locals));
+        for (offset, text) in instructions {
+            if self.referenced.contains(&offset) {
+                self.line(indent, &format!("L{}:", offset));
+            }
+            self.out.push_str(&text);
+        }
+        if self.referenced.contains(&bytes.len()) {
+            self.line(indent, &format!("L{}:", bytes.len()));
+        }
+        self.out.push_str(&tail);
+        self.line(indent, ".end code");
+        self.boundaries.clear();
+        self.referenced.clear();
+        Ok(())
+    }
+
+    fn instruction(&mut self, offset: usize, opcode: &Opcode, bytes: &[u8], pool: &ConstantPoolWriter, indent: usize) -> String {
+        let wide = bytes[offset] == 0xc4;
+        let byte = if wide { bytes[offset + 1] } else { bytes[offset] };
+        let mut text = mnemonic(byte).to_string();
+        if wide {
+            text.push_str("_w");
+        }
+        let mut body = Vec::new();
+        let operands = match opcode {
+            Opcode::Bipush(v) => v.to_string(),
+            Opcode::Sipush(v) => v.to_string(),
+            Opcode::Ldc(loadable) |
+            Opcode::LdcW(loadable) |
+            Opcode::Ldc2W(loadable) => loadable_text(loadable),
+            Opcode::Iload(n) |
+            Opcode::Lload(n) |
+            Opcode::Fload(n) |
+            Opcode::Dload(n) |
+            Opcode::Aload(n) |
+            Opcode::Istore(n) |
+            Opcode::Lstore(n) |
+            Opcode::Fstore(n) |
+            Opcode::Dstore(n) |
+            Opcode::Astore(n) |
+            Opcode::Ret(n) => {
+                if is_short_local(byte) {
+                    String::new()
+                } else {
+                    n.to_string()
+                }
+            }
+            Opcode::Iinc(n, v) => format!("{} {}", n, v),
+            Opcode::Ifeq(j) |
+            Opcode::Ifne(j) |
+            Opcode::Iflt(j) |
+            Opcode::Ifge(j) |
+            Opcode::Ifgt(j) |
+            Opcode::Ifle(j) |
+            Opcode::IfIcmpeq(j) |
+            Opcode::IfIcmpne(j) |
+            Opcode::IfIcmplt(j) |
+            Opcode::IfIcmpge(j) |
+            Opcode::IfIcmpgt(j) |
+            Opcode::IfIcmple(j) |
+            Opcode::IfAcmpeq(j) |
+            Opcode::IfAcmpne(j) |
+            Opcode::Goto(j) |
+            Opcode::Jsr(j) |
+            Opcode::Ifnull(j) |
+            Opcode::Ifnonnull(j) => self.position(offset as i64 + i64::from(*j)),
+            Opcode::Tableswitch(table) => {
+                for jump in &table.jumps {
+                    body.push(self.position(offset as i64 + i64::from(*jump)));
+                }
+                body.push(format!("default {}", self.position(offset as i64 + i64::from(table.default))));
+                table.low.to_string()
+            }
+            Opcode::Lookupswitch(table) => {
+                for (key, jump) in &table.match_offsets {
+                    body.push(format!("{} {}", key, self.position(offset as i64 + i64::from(*jump))));
+                }
+                body.push(format!("default {}", self.position(offset as i64 + i64::from(table.default))));
+                String::new()
+            }
+            Opcode::Getstatic(member) |
+            Opcode::Putstatic(member) |
+            Opcode::Getfield(member) |
+            Opcode::Putfield(member) |
+            Opcode::Invokevirtual(member) => format!("{} {}", quote(&member.class_name), name_and_type_text(&member.name_and_type)),
+            Opcode::Invokespecial(member) |
+            Opcode::Invokestatic(member) => {
+                let index = u16::from_be_bytes([bytes[offset + 1], bytes[offset + 2]]);
+                let marker = if pool.is_interface_method_ref(index) { "interface " } else { "" };
+                format!("{}{} {}", marker, quote(&member.class_name), name_and_type_text(&member.name_and_type))
+            }
+            Opcode::Invokeinterface(member, count) => {
+                format!("{} {} {}", quote(&member.class_name), name_and_type_text(&member.name_and_type), count)
+            }
+            Opcode::Invokedynamic(invoke_dynamic) => format!("{} {}", invoke_dynamic.attr_index, name_and_type_text(&invoke_dynamic.name_and_type)),
+            Opcode::New(class_name) |
+            Opcode::Anewarray(class_name) |
+            Opcode::Checkcast(class_name) |
+            Opcode::Instanceof(class_name) => quote(class_name),
+            Opcode::Newarray(_) => {
+                let atype = bytes[offset + 1];
+                PRIMITIVE_ARRAY_TYPES.iter().find(|(t, _)| *t == atype).map(|(_, name)| *name).unwrap_or_default().to_string()
+            }
+            Opcode::Multianewarray(class_name, dimensions) => format!("{} {}", quote(class_name), dimensions),
+            _ => String::new(),
+        };
+        if !operands.is_empty() {
+            text.push(' ');
+            text.push_str(&operands);
+        }
+        let mut out = String::new();
+        for (i, line) in std::iter::once(text).chain(body).enumerate() {
+            for _ in 0..indent + if i == 0 { 0 } else { 4 } {
+                out.push(' ');
+            }
+            out.push_str(&line);
+            out.push('\n');
+        }
+        out
+    }
+}
+
+/// Prints a class in the assembly format. The result can be turned back into an equivalent
+/// class file with [`assemble`]; the contents of attributes that are printed as raw bytes are
+/// kept as they are, so any constant pool indices in them will not be valid.
+pub fn print_assembly(class: &ClassFile) -> Result<String, ParseError> {
+    let mut printer = Printer {
+        class,
+        out: String::new(),
+        boundaries: HashSet::new(),
+        referenced: BTreeSet::new(),
+    };
+    printer.print_class()?;
+    Ok(printer.out)
+}
+
+#[derive(Clone, Debug)]
+struct Token {
+    text: String,
+    quoted: bool,
+}
+
+struct Line {
+    number: usize,
+    tokens: Vec<Token>,
+}
+
+fn read_quoted(chars: &mut std::iter::Peekable<std::str::Chars>) -> Result<String, ParseError> {
+    let mut text = String::new();
+    loop {
+        match chars.next() {
+            None => fail!("Unterminated string"),
+            Some('"') => return Ok(text),
+            Some('\\') => match chars.next() {
+                Some('\\') => text.push('\\'),
+                Some('"') => text.push('"'),
+                Some('n') => text.push('\n'),
+                Some('t') => text.push('\t'),
+                Some('r') => text.push('\r'),
+                Some('u') => {
+                    let digits: String = chars.by_ref().take(4).collect();
+                    match u32::from_str_radix(&digits, 16).ok().and_then(std::char::from_u32) {
+                        Some(c) if digits.len() == 4 => text.push(c),
+                        _ => fail!("Invalid escape \\u{}", digits),
+                    }
+                }
+                Some(c) => fail!("Invalid escape \\{}", c),
+                None => fail!("Unterminated string"),
+            },
+            Some(c) => text.push(c),
+        }
+    }
+}
+
+fn tokenize(text: &str) -> Result<Vec<Line>, ParseError> {
+    let mut lines = Vec::new();
+    for (i, raw) in text.lines().enumerate() {
+        let mut tokens = Vec::new();
+        let mut chars = raw.chars().peekable();
+        loop {
+            while chars.peek().is_some_and(|c| c.is_whitespace()) {
+                chars.next();
+            }
+            match chars.peek() {
+                None | Some(';') => break,
+                Some('"') => {
+                    chars.next();
+                    let text = read_quoted(&mut chars).map_err(|e| err!(e, "line {}", i + 1))?;
+                    tokens.push(Token { text, quoted: true });
+                }
+                Some(_) => {
+                    let mut text = String::new();
+                    while let Some(c) = chars.peek() {
+                        if c.is_whitespace() || *c == '"' {
+                            break;
+                        }
+                        text.push(*c);
+                        chars.next();
+                    }
+                    tokens.push(Token { text, quoted: false });
+                }
+            }
+        }
+        if !tokens.is_empty() {
+            lines.push(Line { number: i + 1, tokens });
+        }
+    }
+    Ok(lines)
+}
+
+fn parse_hex(text: &str) -> Result<Vec<u8>, ParseError> {
+    text.as_bytes()
+        .chunks(2)
+        .map(|pair| match std::str::from_utf8(pair).ok().filter(|_| pair.len() == 2) {
+            Some(digits) => u8::from_str_radix(digits, 16).map_err(|_| err!("Invalid hex bytes {}", text)),
+            None => Err(err!("Invalid hex bytes {}", text)),
+        })
+        .collect()
+}
+
+/// The tokens of a single line, consumed from the front.
+struct Cursor {
+    tokens: Vec<Token>,
+    ix: usize,
+}
+
+impl Cursor {
+    fn is_empty(&self) -> bool {
+        self.ix >= self.tokens.len()
+    }
+
+    fn next(&mut self) -> Result<Token, ParseError> {
+        match self.tokens.get(self.ix) {
+            Some(token) => {
+                self.ix += 1;
+                Ok(token.clone())
+            }
+            None => fail!("Unexpected end of line"),
+        }
+    }
+
+    fn peek_word(&self) -> Option<&str> {
+        self.tokens.get(self.ix).filter(|token| !token.quoted).map(|token| token.text.as_str())
+    }
+
+    /// Consumes the next token if it is the given unquoted word.
+    fn keyword(&mut self, word: &str) -> bool {
+        if self.peek_word() == Some(word) {
+            self.ix += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, word: &str) -> Result<(), ParseError> {
+        if !self.keyword(word) {
+            fail!("Expected {}", word);
+        }
+        Ok(())
+    }
+
+    fn word(&mut self) -> Result<String, ParseError> {
+        let token = self.next()?;
+        if token.quoted {
+            fail!("Unexpected string {}", string_literal(&token.text));
+        }
+        Ok(token.text)
+    }
+
+    fn name(&mut self) -> Result<String, ParseError> {
+        Ok(self.next()?.text)
+    }
+
+    fn opt_name(&mut self) -> Result<Option<String>, ParseError> {
+        if self.keyword("-") {
+            Ok(None)
+        } else {
+            Ok(Some(self.name()?))
+        }
+    }
+
+    fn names(&mut self) -> Result<Vec<String>, ParseError> {
+        let mut names = Vec::new();
+        while !self.is_empty() {
+            names.push(self.name()?);
+        }
+        Ok(names)
+    }
+
+    fn number<T: FromStr>(&mut self) -> Result<T, ParseError> {
+        let text = self.word()?;
+        match text.parse() {
+            Ok(value) => Ok(value),
+            Err(_) => fail!("Invalid number {}", text),
+        }
+    }
+
+    fn bits<T>(&mut self, from_str_radix: fn(&str, u32) -> Result<T, std::num::ParseIntError>) -> Result<T, ParseError> {
+        let text = self.word()?;
+        match text.strip_prefix("0x").map(|digits| from_str_radix(digits, 16)) {
+            Some(Ok(value)) => Ok(value),
+            _ => fail!("Invalid hex number {}", text),
+        }
+    }
+
+    fn flags(&mut self, names: &[(u16, &str)]) -> u16 {
+        let mut bits = 0;
+        while let Some((flag, _)) = self.peek_word().and_then(|word| names.iter().find(|(_, name)| *name == word)) {
+            bits |= flag;
+            self.ix += 1;
+        }
+        bits
+    }
+
+    /// Consumes a label definition, if the line starts with one.
+    fn label(&mut self) -> Option<String> {
+        let label = self.peek_word()?.strip_suffix(':').filter(|label| !label.is_empty())?.to_string();
+        self.ix += 1;
+        Some(label)
+    }
+
+    fn end(&self) -> Result<(), ParseError> {
+        match self.tokens.get(self.ix) {
+            Some(token) => fail!("Unexpected {} at end of line", token.text),
+            None => Ok(()),
+        }
+    }
+}
+
+struct Labels {
+    offsets: HashMap<String, usize>,
+    /// Set on the first pass over a block of code, which only collects the label offsets.
+    /// References to labels are treated as zero on that pass, and checks on the values
+    /// derived from them are skipped.
+    collecting: bool,
+}
+
+struct Parser {
+    lines: Vec<Line>,
+    pos: usize,
+    line_number: usize,
+    pool: ConstantPoolWriter,
+    labels: Option<Labels>,
+}
+
+impl Parser {
+    fn next_line(&mut self) -> Result<Cursor, ParseError> {
+        match self.lines.get(self.pos) {
+            Some(line) => {
+                self.pos += 1;
+                self.line_number = line.number;
+                Ok(Cursor {
+                    tokens: line.tokens.clone(),
+                    ix: 0,
+                })
+            }
+            None => fail!("Unexpected end of input"),
+        }
+    }
+
+    /// Returns the next line of a block, or None if it is the `.end` line of the block.
+    fn next_in_block(&mut self, block: &str) -> Result<Option<Cursor>, ParseError> {
+        let mut line = self.next_line()?;
+        if line.keyword(".end") {
+            if !line.keyword(block) {
+                fail!("Expected .end {}", block);
+            }
+            line.end()?;
+            return Ok(None);
+        }
+        Ok(Some(line))
+    }
+
+    fn collecting(&self) -> bool {
+        self.labels.as_ref().is_some_and(|labels| labels.collecting)
+    }
+
+    fn define_label(&mut self, label: String, offset: usize) -> Result<(), ParseError> {
+        match &mut self.labels {
+            Some(labels) if labels.collecting => {
+                if labels.offsets.insert(label.clone(), offset).is_some() {
+                    fail!("Label {} is defined more than once", label);
+                }
+            }
+            Some(_) => (),
+            None => fail!("Label {} is defined outside of code", label),
+        }
+        Ok(())
+    }
+
+    fn position(&self, line: &mut Cursor) -> Result<i64, ParseError> {
+        let text = line.word()?;
+        if let Ok(offset) = text.parse() {
+            return Ok(offset);
+        }
+        match &self.labels {
+            Some(labels) => match labels.offsets.get(&text) {
+                Some(offset) => Ok(*offset as i64),
+                None if labels.collecting => Ok(0),
+                None => fail!("Unknown label {}", text),
+            },
+            None => fail!("Label {} used outside of code", text),
+        }
+    }
+
+    fn code_offset(&self, line: &mut Cursor) -> Result<u16, ParseError> {
+        let offset = self.position(line)?;
+        match u16::try_from(offset) {
+            Ok(offset) => Ok(offset),
+            Err(_) => fail!("Code offset {} is out of range", offset),
+        }
+    }
+
+    /// Returns the distance between two code offsets, which must not be negative.
+    fn span(&self, start: u16, end: u16, what: &str) -> Result<u16, ParseError> {
+        match end.checked_sub(start) {
+            Some(span) => Ok(span),
+            None if self.collecting() => Ok(0),
+            None => fail!("Found {} at offset {} after offset {}", what, start, end),
+        }
+    }
+
+    /// Adds the constants loaded by ldc instructions to the pool before anything else, as
+    /// ldc can only refer to the first 256 entries.
+    fn reserve_ldc_constants(&mut self) -> Result<(), ParseError> {
+        let mut loadables = Vec::new();
+        for i in 0..self.lines.len() {
+            let mut line = Cursor {
+                tokens: self.lines[i].tokens.clone(),
+                ix: 0,
+            };
+            while line.label().is_some() {}
+            if line.keyword("ldc") {
+                self.line_number = self.lines[i].number;
+                loadables.push(self.constant(&mut line)?);
+            }
+        }
+        self.pool.add_low_loadables(&loadables.iter().collect::<Vec<_>>())
+    }
+
+    fn class(&mut self) -> Result<ClassFile, ParseError> {
+        let mut version = None;
+        let mut header = None;
+        let mut super_class = None;
+        let mut interfaces = Vec::new();
+        let mut fields = Vec::new();
+        let mut methods = Vec::new();
+        let mut attributes = Vec::new();
+        while let Some(mut line) = self.next_in_block("class")? {
+            let directive = line.word()?;
+            match directive.as_str() {
+                ".version" => version = Some((line.number()?, line.number()?)),
+                ".class" => {
+                    let access_flags = ClassAccessFlags::from_bits_truncate(line.flags(CLASS_FLAGS));
+                    header = Some((access_flags, line.name()?));
+                }
+                ".super" => super_class = Some(line.name()?),
+                ".implements" => interfaces.extend(line.names()?),
+                ".field" => {
+                    let access_flags = FieldAccessFlags::from_bits_truncate(line.flags(FIELD_FLAGS));
+                    let name = line.name()?;
+                    let descriptor = line.name()?;
+                    line.end()?;
+                    let attributes = self.attributes("field")?;
+                    fields.push(FieldInfo {
+                        access_flags,
+                        name,
+                        descriptor,
+                        attributes,
+                    });
+                }
+                ".method" => {
+                    let access_flags = MethodAccessFlags::from_bits_truncate(line.flags(METHOD_FLAGS));
+                    let name = line.name()?;
+                    let descriptor = line.name()?;
+                    line.end()?;
+                    let attributes = self.attributes("method")?;
+                    methods.push(MethodInfo {
+                        access_flags,
+                        name,
+                        descriptor,
+                        attributes,
+                    });
+                }
+                _ => attributes.push(self.attribute(&directive, &mut line)?),
+            }
+            line.end()?;
+        }
+        if let Some(line) = self.lines.get(self.pos) {
+            self.line_number = line.number;
+            fail!("Unexpected text after .end class");
+        }
+        let (major_version, minor_version) = version.ok_or_else(|| err!("Missing .version directive"))?;
+        let (access_flags, this_class) = header.ok_or_else(|| err!("Missing .class directive"))?;
+        Ok(ClassFile {
+            major_version,
+            minor_version,
+            constant_pool: Vec::new(),
+            access_flags,
+            this_class,
+            super_class,
+            interfaces,
+            fields,
+            methods,
+            attributes,
+        })
+    }
+
+    fn attributes(&mut self, block: &str) -> Result<Vec<AttributeInfo>, ParseError> {
+        let mut attributes = Vec::new();
+        while let Some(mut line) = self.next_in_block(block)? {
+            let directive = line.word()?;
+            attributes.push(self.attribute(&directive, &mut line)?);
+            line.end()?;
+        }
+        Ok(attributes)
+    }
+
+    fn attribute(&mut self, directive: &str, line: &mut Cursor) -> Result<AttributeInfo, ParseError> {
+        let (name, data) = match directive {
+            ".constantvalue" => {
+                let kind = line.word()?;
+                match self.literal(&kind, line)? {
+                    Some(literal) => ("ConstantValue", AttributeData::ConstantValue(literal)),
+                    None => fail!("Invalid constant kind {}", kind),
+                }
+            }
+            ".code" => {
+                line.expect("stack")?;
+                let max_stack = line.number()?;
+                line.expect("locals")?;
+                let max_locals = line.number()?;
+                line.end()?;
+                ("Code", AttributeData::Code(self.code(max_stack, max_locals)?))
+            }
+            ".stackmaptable" => ("StackMapTable", AttributeData::StackMapTable(self.stack_map_table()?)),
+            ".exceptions" => ("Exceptions", AttributeData::Exceptions(line.names()?)),
+            ".innerclasses" => {
+                let mut entries = Vec::new();
+                while let Some(mut line) = self.next_in_block("innerclasses")? {
+                    entries.push(InnerClassEntry {
+                        inner_class_info: line.name()?,
+                        outer_class_info: line.opt_name()?,
+                        inner_name: line.opt_name()?,
+                        access_flags: InnerClassAccessFlags::from_bits_truncate(line.flags(INNER_CLASS_FLAGS)),
+                    });
+                    line.end()?;
+                }
+                ("InnerClasses", AttributeData::InnerClasses(entries))
+            }
+            ".enclosingmethod" => {
+                let class_name = line.name()?;
+                let method = if line.is_empty() {
+                    None
+                } else {
+                    Some(NameAndType {
+                        name: line.name()?,
+                        descriptor: line.name()?,
+                    })
+                };
+                ("EnclosingMethod", AttributeData::EnclosingMethod { class_name, method })
+            }
+            ".synthetic" => ("Synthetic", AttributeData::Synthetic),
+            ".signature" => ("Signature", AttributeData::Signature(line.name()?)),
+            ".sourcefile" => ("SourceFile", AttributeData::SourceFile(line.name()?)),
+            ".sourcedebugextension" => ("SourceDebugExtension", AttributeData::SourceDebugExtension(line.name()?)),
+            ".linenumbertable" => {
+                let mut entries = Vec::new();
+                while let Some(mut line) = self.next_in_block("linenumbertable")? {
+                    entries.push(LineNumberEntry {
+                        start_pc: self.code_offset(&mut line)?,
+                        line_number: line.number()?,
+                    });
+                    line.end()?;
+                }
+                ("LineNumberTable", AttributeData::LineNumberTable(entries))
+            }
+            ".localvariabletable" => {
+                let mut entries = Vec::new();
+                while let Some(mut line) = self.next_in_block("localvariabletable")? {
+                    let index = line.number()?;
+                    let name = line.name()?;
+                    let descriptor = line.name()?;
+                    let start_pc = self.code_offset(&mut line)?;
+                    let length = self.span(start_pc, self.code_offset(&mut line)?, "local variable end")?;
+                    entries.push(LocalVariableEntry {
+                        start_pc,
+                        length,
+                        name,
+                        descriptor,
+                        index,
+                    });
+                    line.end()?;
+                }
+                ("LocalVariableTable", AttributeData::LocalVariableTable(entries))
+            }
+            ".localvariabletypetable" => {
+                let mut entries = Vec::new();
+                while let Some(mut line) = self.next_in_block("localvariabletypetable")? {
+                    let index = line.number()?;
+                    let name = line.name()?;
+                    let signature = line.name()?;
+                    let start_pc = self.code_offset(&mut line)?;
+                    let length = self.span(start_pc, self.code_offset(&mut line)?, "local variable end")?;
+                    entries.push(LocalVariableTypeEntry {
+                        start_pc,
+                        length,
+                        name,
+                        signature,
+                        index,
+                    });
+                    line.end()?;
+                }
+                ("LocalVariableTypeTable", AttributeData::LocalVariableTypeTable(entries))
+            }
+            ".deprecated" => ("Deprecated", AttributeData::Deprecated),
+            ".runtimevisibleannotations" => {
+                ("RuntimeVisibleAnnotations", AttributeData::RuntimeVisibleAnnotations(self.annotations("runtimevisibleannotations")?))
+            }
+            ".runtimeinvisibleannotations" => {
+                ("RuntimeInvisibleAnnotations", AttributeData::RuntimeInvisibleAnnotations(self.annotations("runtimeinvisibleannotations")?))
+            }
+            ".runtimevisibleparameterannotations" => (
+                "RuntimeVisibleParameterAnnotations",
+                AttributeData::RuntimeVisibleParameterAnnotations(self.parameter_annotations("runtimevisibleparameterannotations")?),
+            ),
+            ".runtimeinvisibleparameterannotations" => (
+                "RuntimeInvisibleParameterAnnotations",
+                AttributeData::RuntimeInvisibleParameterAnnotations(self.parameter_annotations("runtimeinvisibleparameterannotations")?),
+            ),
+            ".runtimevisibletypeannotations" => (
+                "RuntimeVisibleTypeAnnotations",
+                AttributeData::RuntimeVisibleTypeAnnotations(self.type_annotations("runtimevisibletypeannotations")?),
+            ),
+            ".runtimeinvisibletypeannotations" => (
+                "RuntimeInvisibleTypeAnnotations",
+                AttributeData::RuntimeInvisibleTypeAnnotations(self.type_annotations("runtimeinvisibletypeannotations")?),
+            ),
+            ".annotationdefault" => ("AnnotationDefault", AttributeData::AnnotationDefault(self.element_value(line)?)),
+            ".bootstrapmethods" => {
+                let mut entries = Vec::new();
+                while let Some(mut line) = self.next_in_block("bootstrapmethods")? {
+                    line.expect(".bootstrap")?;
+                    let method = self.method_handle(&mut line)?;
+                    line.end()?;
+                    let mut arguments = Vec::new();
+                    while let Some(mut line) = self.next_in_block("bootstrap")? {
+                        let argument = match self.constant(&mut line)? {
+                            Loadable::LiteralConstant(literal) => BootstrapArgument::LiteralConstant(literal),
+                            Loadable::ClassInfo(name) => BootstrapArgument::ClassInfo(name),
+                            Loadable::MethodHandle(handle) => BootstrapArgument::MethodHandle(handle),
+                            Loadable::MethodType(descriptor) => BootstrapArgument::MethodType(descriptor),
+                            Loadable::Dynamic(_) => fail!("Dynamic constants cannot be bootstrap method arguments"),
+                        };
+                        arguments.push(argument);
+                        line.end()?;
+                    }
+                    entries.push(BootstrapMethodEntry { method, arguments });
+                }
+                ("BootstrapMethods", AttributeData::BootstrapMethods(entries))
+            }
+            ".methodparameters" => {
+                let mut entries = Vec::new();
+                while let Some(mut line) = self.next_in_block("methodparameters")? {
+                    entries.push(MethodParameterEntry {
+                        name: line.opt_name()?,
+                        access_flags: MethodParameterAccessFlags::from_bits_truncate(line.flags(METHOD_PARAMETER_FLAGS)),
+                    });
+                    line.end()?;
+                }
+                ("MethodParameters", AttributeData::MethodParameters(entries))
+            }
+            ".module" => ("Module", AttributeData::Module(self.module(line)?)),
+            ".modulepackages" => ("ModulePackages", AttributeData::ModulePackages(line.names()?)),
+            ".modulemainclass" => ("ModuleMainClass", AttributeData::ModuleMainClass(line.name()?)),
+            ".nesthost" => ("NestHost", AttributeData::NestHost(line.name()?)),
+            ".nestmembers" => ("NestMembers", AttributeData::NestMembers(line.names()?)),
+            ".record" => {
+                let mut components = Vec::new();
+                while let Some(mut line) = self.next_in_block("record")? {
+                    line.expect(".component")?;
+                    let name = line.name()?;
+                    let descriptor = line.name()?;
+                    line.end()?;
+                    let attributes = self.attributes("component")?;
+                    components.push(RecordComponentEntry {
+                        name,
+                        descriptor,
+                        attributes,
+                    });
+                }
+                ("Record", AttributeData::Record(components))
+            }
+            ".attribute" => {
+                let name = line.name()?;
+                let bytes = if line.is_empty() { Vec::new() } else { parse_hex(&line.word()?)? };
+                return Ok(AttributeInfo {
+                    name,
+                    data: AttributeData::Other(bytes),
+                });
+            }
+            _ => fail!("Unknown directive {}", directive),
+        };
+        Ok(AttributeInfo {
+            name: name.to_string(),
+            data,
+        })
+    }
+
+    fn float(kind: &str, line: &mut Cursor) -> Result<f32, ParseError> {
+        match kind {
+            "floatbits" => Ok(f32::from_bits(line.bits(u32::from_str_radix)?)),
+            _ => line.number(),
+        }
+    }
+
+    fn double(kind: &str, line: &mut Cursor) -> Result<f64, ParseError> {
+        match kind {
+            "doublebits" => Ok(f64::from_bits(line.bits(u64::from_str_radix)?)),
+            _ => line.number(),
+        }
+    }
+
+    /// Parses the value of a literal constant of the given kind, or returns None if the
+    /// kind is not a kind of literal constant.
+    fn literal(&self, kind: &str, line: &mut Cursor) -> Result<Option<LiteralConstant>, ParseError> {
+        let literal = match kind {
+            "int" => LiteralConstant::Integer(line.number()?),
+            "float" | "floatbits" => LiteralConstant::Float(Self::float(kind, line)?),
+            "long" => LiteralConstant::Long(line.number()?),
+            "double" | "doublebits" => LiteralConstant::Double(Self::double(kind, line)?),
+            "string" => LiteralConstant::String(line.name()?),
+            "stringbytes" => LiteralConstant::StringBytes(parse_hex(&line.word()?)?),
+            _ => return Ok(None),
+        };
+        Ok(Some(literal))
+    }
+
+    fn method_handle(&self, line: &mut Cursor) -> Result<MethodHandle, ParseError> {
+        let kind_name = line.word()?;
+        let kind = match REFERENCE_KINDS.iter().find(|(_, name)| *name == kind_name) {
+            Some((kind, _)) => *kind,
+            None => fail!("Invalid reference kind {}", kind_name),
+        };
+        let member_kind = match line.word()?.as_str() {
+            "Field" => MemberKind::Field,
+            "Method" => MemberKind::Method,
+            "InterfaceMethod" => MemberKind::InterfaceMethod,
+            other => fail!("Invalid member kind {}", other),
+        };
+        Ok(MethodHandle {
+            kind,
+            class_name: line.name()?,
+            member_kind,
+            member_ref: NameAndType {
+                name: line.name()?,
+                descriptor: line.name()?,
+            },
+        })
+    }
+
+    fn constant(&self, line: &mut Cursor) -> Result<Loadable, ParseError> {
+        let kind = line.word()?;
+        if let Some(literal) = self.literal(&kind, line)? {
+            return Ok(Loadable::LiteralConstant(literal));
+        }
+        let loadable = match kind.as_str() {
+            "class" => Loadable::ClassInfo(line.name()?),
+            "methodtype" => Loadable::MethodType(line.name()?),
+            "methodhandle" => Loadable::MethodHandle(self.method_handle(line)?),
+            "dynamic" => Loadable::Dynamic(Dynamic {
+                attr_index: line.number()?,
+                name_and_type: NameAndType {
+                    name: line.name()?,
+                    descriptor: line.name()?,
+                },
+            }),
+            _ => fail!("Invalid constant kind {}", kind),
+        };
+        Ok(loadable)
+    }
+
+    fn element_value(&self, line: &mut Cursor) -> Result<AnnotationElementValue, ParseError> {
+        let kind = line.word()?;
+        let value = match kind.as_str() {
+            "byte" => AnnotationElementValue::ByteConstant(line.number()?),
+            "char" => AnnotationElementValue::CharConstant(line.number()?),
+            "double" | "doublebits" => AnnotationElementValue::DoubleConstant(Self::double(&kind, line)?),
+            "float" | "floatbits" => AnnotationElementValue::FloatConstant(Self::float(&kind, line)?),
+            "int" => AnnotationElementValue::IntConstant(line.number()?),
+            "long" => AnnotationElementValue::LongConstant(line.number()?),
+            "short" => AnnotationElementValue::ShortConstant(line.number()?),
+            "boolean" => AnnotationElementValue::BooleanConstant(line.number()?),
+            "string" => AnnotationElementValue::StringConstant(line.name()?),
+            "enum" => AnnotationElementValue::EnumConstant {
+                type_name: line.name()?,
+                const_name: line.name()?,
+            },
+            "class" => AnnotationElementValue::ClassLiteral { class_name: line.name()? },
+            "annotation" => {
+                let type_descriptor = line.name()?;
+                line.expect("{")?;
+                let mut elements = Vec::new();
+                while !line.keyword("}") {
+                    elements.push(self.annotation_element(line)?);
+                }
+                AnnotationElementValue::AnnotationValue(Annotation { type_descriptor, elements })
+            }
+            "array" => {
+                line.expect("[")?;
+                let mut values = Vec::new();
+                while !line.keyword("]") {
+                    values.push(self.element_value(line)?);
+                }
+                AnnotationElementValue::ArrayValue(values)
+            }
+            _ => fail!("Invalid element value kind {}", kind),
+        };
+        Ok(value)
+    }
+
+    fn annotation_element(&self, line: &mut Cursor) -> Result<AnnotationElement, ParseError> {
+        let name = line.name()?;
+        line.expect("=")?;
+        let value = self.element_value(line)?;
+        Ok(AnnotationElement { name, value })
+    }
+
+    fn annotation_elements(&mut self, block: &str) -> Result<Vec<AnnotationElement>, ParseError> {
+        let mut elements = Vec::new();
+        while let Some(mut line) = self.next_in_block(block)? {
+            elements.push(self.annotation_element(&mut line)?);
+            line.end()?;
+        }
+        Ok(elements)
+    }
+
+    fn annotations(&mut self, block: &str) -> Result<Vec<Annotation>, ParseError> {
+        let mut annotations = Vec::new();
+        while let Some(mut line) = self.next_in_block(block)? {
+            line.expect(".annotation")?;
+            let type_descriptor = line.name()?;
+            line.end()?;
+            let elements = self.annotation_elements("annotation")?;
+            annotations.push(Annotation { type_descriptor, elements });
+        }
+        Ok(annotations)
+    }
+
+    fn parameter_annotations(&mut self, block: &str) -> Result<Vec<ParameterAnnotation>, ParseError> {
+        let mut parameters = Vec::new();
+        while let Some(mut line) = self.next_in_block(block)? {
+            line.expect(".parameter")?;
+            line.end()?;
+            parameters.push(ParameterAnnotation {
+                annotations: self.annotations("parameter")?,
+            });
+        }
+        Ok(parameters)
+    }
+
+    fn type_annotation_target(&self, line: &mut Cursor) -> Result<TypeAnnotationTarget, ParseError> {
+        let kind = line.word()?;
+        let target = match kind.as_str() {
+            "typeparameter" => TypeAnnotationTarget::TypeParameter { index: line.number()? },
+            "supertype" => TypeAnnotationTarget::Supertype { index: line.number()? },
+            "typeparameterbound" => TypeAnnotationTarget::TypeParameterBound {
+                type_parameter_index: line.number()?,
+                bound_index: line.number()?,
+            },
+            "empty" => TypeAnnotationTarget::Empty,
+            "formalparameter" => TypeAnnotationTarget::FormalParameter { index: line.number()? },
+            "throws" => TypeAnnotationTarget::Throws { index: line.number()? },
+            "localvar" => {
+                line.expect("[")?;
+                let mut entries = Vec::new();
+                while !line.keyword("]") {
+                    let start_pc = self.code_offset(line)?;
+                    let length = self.span(start_pc, self.code_offset(line)?, "local variable end")?;
+                    entries.push(TypeAnnotationLocalVarTargetEntry {
+                        start_pc,
+                        length,
+                        index: line.number()?,
+                    });
+                }
+                TypeAnnotationTarget::LocalVar(entries)
+            }
+            "catch" => TypeAnnotationTarget::Catch {
+                exception_table_index: line.number()?,
+            },
+            "offset" => TypeAnnotationTarget::Offset {
+                target_type: Self::instruction_target_type(line, OFFSET_TARGET_TYPES)?,
+                offset: self.code_offset(line)?,
+            },
+            "typeargument" => TypeAnnotationTarget::TypeArgument {
+                target_type: Self::instruction_target_type(line, TYPE_ARGUMENT_TARGET_TYPES)?,
+                offset: self.code_offset(line)?,
+                type_argument_index: line.number()?,
+            },
+            _ => fail!("Invalid type annotation target {}", kind),
+        };
+        Ok(target)
+    }
+
+    fn instruction_target_type(line: &mut Cursor, table: &[(u8, &str)]) -> Result<u8, ParseError> {
+        let name = line.word()?;
+        match table.iter().find(|(_, kind)| *kind == name) {
+            Some((target_type, _)) => Ok(*target_type),
+            None => fail!("Invalid instruction type annotation kind {}", name),
+        }
+    }
+
+    fn type_annotations(&mut self, block: &str) -> Result<Vec<TypeAnnotation>, ParseError> {
+        let mut annotations = Vec::new();
+        while let Some(mut line) = self.next_in_block(block)? {
+            line.expect(".typeannotation")?;
+            let target_type = self.type_annotation_target(&mut line)?;
+            line.expect("[")?;
+            let mut target_path = Vec::new();
+            while !line.keyword("]") {
+                let kind = line.word()?;
+                let path_kind = match kind.as_str() {
+                    "array" => TypeAnnotationTargetPathKind::DeeperArray,
+                    "nested" => TypeAnnotationTargetPathKind::DeeperNested,
+                    "wildcard" => TypeAnnotationTargetPathKind::WildcardTypeArgument,
+                    "typeargument" => TypeAnnotationTargetPathKind::TypeArgument,
+                    _ => fail!("Invalid type path entry {}", kind),
+                };
+                let has_index = matches!(path_kind, TypeAnnotationTargetPathKind::TypeArgument)
+                    || line.peek_word().is_some_and(|word| word.parse::<u8>().is_ok());
+                let argument_index = if has_index { line.number()? } else { 0 };
+                target_path.push(TypeAnnotationTargetPathEntry { path_kind, argument_index });
+            }
+            let type_descriptor = line.name()?;
+            line.end()?;
+            let elements = self.annotation_elements("typeannotation")?;
+            annotations.push(TypeAnnotation {
+                target_type,
+                target_path,
+                annotation: Annotation { type_descriptor, elements },
+            });
+        }
+        Ok(annotations)
+    }
+
+    fn verification_type(&self, line: &mut Cursor) -> Result<VerificationType, ParseError> {
+        let kind = line.word()?;
+        let verification_type = match kind.as_str() {
+            "top" => VerificationType::Top,
+            "int" => VerificationType::Integer,
+            "float" => VerificationType::Float,
+            "long" => VerificationType::Long,
+            "double" => VerificationType::Double,
+            "null" => VerificationType::Null,
+            "uninitializedthis" => VerificationType::UninitializedThis,
+            "uninitialized" => VerificationType::Uninitialized {
+                code_offset: self.code_offset(line)?,
+            },
+            "object" => VerificationType::Object { class_name: line.name()? },
+            _ => fail!("Invalid verification type {}", kind),
+        };
+        Ok(verification_type)
+    }
+
+    fn verification_type_list(&self, line: &mut Cursor) -> Result<Vec<VerificationType>, ParseError> {
+        line.expect("[")?;
+        let mut verification_types = Vec::new();
+        while !line.keyword("]") {
+            verification_types.push(self.verification_type(line)?);
+        }
+        Ok(verification_types)
+    }
+
+    fn stack_map_table(&mut self) -> Result<Vec<StackMapEntry>, ParseError> {
+        let mut entries = Vec::new();
+        let mut previous: Option<u16> = None;
+        while let Some(mut line) = self.next_in_block("stackmaptable")? {
+            let kind = line.word()?;
+            let offset = self.code_offset(&mut line)?;
+            // Each frame after the first is at least one byte after the previous one
+            let offset_delta = match previous {
+                None => offset,
+                Some(previous) => self.span(previous.saturating_add(1), offset, "stack map frame")?,
+            };
+            previous = Some(offset);
+            let entry = match kind.as_str() {
+                "same" => StackMapEntry::Same { offset_delta },
+                "same_locals_1_stack_item" => StackMapEntry::SameLocals1StackItem {
+                    offset_delta,
+                    stack: self.verification_type(&mut line)?,
+                },
+                "chop" => StackMapEntry::Chop {
+                    offset_delta,
+                    chop_count: line.number()?,
+                },
+                "append" => {
+                    let mut locals = Vec::new();
+                    while !line.is_empty() {
+                        locals.push(self.verification_type(&mut line)?);
+                    }
+                    StackMapEntry::Append { offset_delta, locals }
+                }
+                "full_frame" => {
+                    line.expect("locals")?;
+                    let locals = self.verification_type_list(&mut line)?;
+                    line.expect("stack")?;
+                    let stack = self.verification_type_list(&mut line)?;
+                    StackMapEntry::FullFrame { offset_delta, locals, stack }
+                }
+                _ => fail!("Invalid stack map frame type {}", kind),
+            };
+            entries.push(entry);
+            line.end()?;
+        }
+        Ok(entries)
+    }
+
+    fn module(&mut self, line: &mut Cursor) -> Result<ModuleData, ParseError> {
+        fn version(line: &mut Cursor) -> Result<Option<String>, ParseError> {
+            if line.keyword("version") {
+                Ok(Some(line.name()?))
+            } else {
+                Ok(None)
+            }
+        }
+        fn targets(line: &mut Cursor, word: &str) -> Result<Vec<String>, ParseError> {
+            if line.keyword(word) {
+                line.names()
+            } else {
+                Ok(Vec::new())
+            }
+        }
+        let access_flags = ModuleAccessFlags::from_bits_truncate(line.flags(MODULE_FLAGS));
+        let mut module = ModuleData {
+            name: line.name()?,
+            access_flags,
+            version: version(line)?,
+            requires: Vec::new(),
+            exports: Vec::new(),
+            opens: Vec::new(),
+            uses: Vec::new(),
+            provides: Vec::new(),
+        };
+        while let Some(mut line) = self.next_in_block("module")? {
+            let directive = line.word()?;
+            match directive.as_str() {
+                ".requires" => {
+                    let flags = ModuleRequiresFlags::from_bits_truncate(line.flags(MODULE_REQUIRES_FLAGS));
+                    module.requires.push(ModuleRequireEntry {
+                        name: line.name()?,
+                        flags,
+                        version: version(&mut line)?,
+                    });
+                }
+                ".exports" => {
+                    let flags = ModuleExportsFlags::from_bits_truncate(line.flags(MODULE_EXPORTS_FLAGS));
+                    module.exports.push(ModuleExportsEntry {
+                        package_name: line.name()?,
+                        flags,
+                        exports_to: targets(&mut line, "to")?,
+                    });
+                }
+                ".opens" => {
+                    let flags = ModuleOpensFlags::from_bits_truncate(line.flags(MODULE_EXPORTS_FLAGS));
+                    module.opens.push(ModuleOpensEntry {
+                        package_name: line.name()?,
+                        flags,
+                        opens_to: targets(&mut line, "to")?,
+                    });
+                }
+                ".uses" => module.uses.push(line.name()?),
+                ".provides" => module.provides.push(ModuleProvidesEntry {
+                    service_interface_name: line.name()?,
+                    provides_with: targets(&mut line, "with")?,
+                }),
+                _ => fail!("Unknown module directive {}", directive),
+            }
+            line.end()?;
+        }
+        Ok(module)
+    }
+
+    fn code(&mut self, max_stack: u16, max_locals: u16) -> Result<CodeData, ParseError> {
+        if self.labels.is_some() {
+            fail!("Found .code inside of code");
+        }
+        // Labels may be used before they are defined, so the code is parsed twice. The first
+        // pass only collects the offsets of the labels, and adds its constants to a scratch
+        // pool. The size of every instruction is fixed by its mnemonic and position, so the
+        // offsets it finds are the same as those of the second pass.
+        let start = self.pos;
+        let pool = std::mem::replace(&mut self.pool, ConstantPoolWriter::new());
+        self.labels = Some(Labels {
+            offsets: HashMap::new(),
+            collecting: true,
+        });
+        let first_pass = self.code_body();
+        self.pool = pool;
+        let labels = self.labels.take();
+        first_pass?;
+
+        self.pos = start;
+        self.labels = labels.map(|labels| Labels {
+            offsets: labels.offsets,
+            collecting: false,
+        });
+        let second_pass = self.code_body();
+        self.labels = None;
+        let (code, exception_table, attributes) = second_pass?;
+        Ok(CodeData {
+            max_stack,
+            max_locals,
+            code,
+            bytecode: None,
+            exception_table,
+            attributes,
+        })
+    }
+
+    #[allow(clippy::type_complexity)]
+    fn code_body(&mut self) -> Result<(Vec<u8>, Vec<ExceptionTableEntry>, Vec<AttributeInfo>), ParseError> {
+        let mut code = Vec::new();
+        let mut exception_table = Vec::new();
+        let mut attributes = Vec::new();
+        while let Some(mut line) = self.next_in_block("code")? {
+            while let Some(label) = line.label() {
+                self.define_label(label, code.len())?;
+            }
+            if line.is_empty() {
+                continue;
+            }
+            let word = line.word()?;
+            if word == ".catch" {
+                let catch_type = if line.keyword("any") { None } else { Some(line.name()?) };
+                exception_table.push(ExceptionTableEntry {
+                    start_pc: self.code_offset(&mut line)?,
+                    end_pc: self.code_offset(&mut line)?,
+                    handler_pc: self.code_offset(&mut line)?,
+                    catch_type,
+                });
+            } else if word.starts_with('.') {
+                attributes.push(self.attribute(&word, &mut line)?);
+            } else {
+                self.instruction(&word, &mut line, &mut code)?;
+            }
+            line.end()?;
+        }
+        Ok((code, exception_table, attributes))
+    }
+
+    fn jump(&self, line: &mut Cursor, offset: usize) -> Result<i64, ParseError> {
+        Ok(self.position(line)? - offset as i64)
+    }
+
+    fn instruction(&mut self, word: &str, line: &mut Cursor, code: &mut Vec<u8>) -> Result<(), ParseError> {
+        let find = |name: &str| (0..=0xffu8).find(|op| *op != 0xc4 && mnemonic(*op) == name);
+        let (opcode, wide) = match find(word) {
+            Some(opcode) => (opcode, false),
+            None => match word.strip_suffix("_w").and_then(find) {
+                Some(opcode) if matches!(opcode, 0x15..=0x19 | 0x36..=0x3a | 0x84 | 0xa9) => (opcode, true),
+                _ => fail!("Unknown instruction {}", word),
+            },
+        };
+        let offset = code.len();
+        if wide {
+            code.push(0xc4);
+        }
+        code.push(opcode);
+        match opcode {
+            0x10 => code.push(line.number::<i8>()? as u8),
+            0x11 => code.extend_from_slice(&line.number::<i16>()?.to_be_bytes()),
+            0x12 => {
+                let loadable = self.constant(line)?;
+                let index = self.pool.loadable(&loadable)?;
+                match u8::try_from(index) {
+                    Ok(index) => code.push(index),
+                    Err(_) if self.collecting() => code.push(0),
+                    Err(_) => fail!("Constant pool index {} is too large for ldc; use ldc_w", index),
+                }
+            }
+            0x13 | 0x14 => {
+                let loadable = self.constant(line)?;
+                code.extend_from_slice(&self.pool.loadable(&loadable)?.to_be_bytes());
+            }
+            0x15..=0x19 | 0x36..=0x3a | 0xa9 => {
+                if wide {
+                    code.extend_from_slice(&line.number::<u16>()?.to_be_bytes());
+                } else {
+                    code.push(line.number::<u8>()?);
+                }
+            }
+            0x84 => {
+                if wide {
+                    code.extend_from_slice(&line.number::<u16>()?.to_be_bytes());
+                    code.extend_from_slice(&line.number::<i16>()?.to_be_bytes());
+                } else {
+                    code.push(line.number::<u8>()?);
+                    code.push(line.number::<i8>()? as u8);
+                }
+            }
+            0x99..=0xa8 | 0xc6 | 0xc7 => {
+                let jump = self.jump(line, offset)?;
+                let jump = match i16::try_from(jump) {
+                    Ok(jump) => jump,
+                    Err(_) if self.collecting() => 0,
+                    Err(_) => fail!("Jump offset {} is out of range for {}", jump, word),
+                };
+                code.extend_from_slice(&jump.to_be_bytes());
+            }
+            0xc8 | 0xc9 => {
+                let jump = self.jump(line, offset)?;
+                let jump = match i32::try_from(jump) {
+                    Ok(jump) => jump,
+                    Err(_) if self.collecting() => 0,
+                    Err(_) => fail!("Jump offset {} is out of range for {}", jump, word),
+                };
+                code.extend_from_slice(&jump.to_be_bytes());
+            }
+            0xaa | 0xab => self.switch(opcode, line, offset, code)?,
+            0xb2..=0xb9 => {
+                let kind = match opcode {
+                    0xb2..=0xb5 => MemberKind::Field,
+                    0xb9 => MemberKind::InterfaceMethod,
+                    0xb7 | 0xb8 if line.keyword("interface") => MemberKind::InterfaceMethod,
+                    _ => MemberKind::Method,
+                };
+                let class_name = line.name()?;
+                let name_and_type = NameAndType {
+                    name: line.name()?,
+                    descriptor: line.name()?,
+                };
+                code.extend_from_slice(&self.pool.member_ref(kind, &class_name, &name_and_type)?.to_be_bytes());
+                if opcode == 0xb9 {
+                    code.push(line.number()?);
+                    code.push(0);
+                }
+            }
+            0xba => {
+                let invoke_dynamic = InvokeDynamic {
+                    attr_index: line.number()?,
+                    name_and_type: NameAndType {
+                        name: line.name()?,
+                        descriptor: line.name()?,
+                    },
+                };
+                code.extend_from_slice(&self.pool.invoke_dynamic(&invoke_dynamic)?.to_be_bytes());
+                code.extend_from_slice(&[0, 0]);
+            }
+            0xbb | 0xbd | 0xc0 | 0xc1 | 0xc5 => {
+                code.extend_from_slice(&self.pool.class(&line.name()?)?.to_be_bytes());
+                if opcode == 0xc5 {
+                    code.push(line.number()?);
+                }
+            }
+            0xbc => {
+                let name = line.word()?;
+                match PRIMITIVE_ARRAY_TYPES.iter().find(|(_, type_name)| *type_name == name) {
+                    Some((atype, _)) => code.push(*atype),
+                    None => fail!("Invalid array type {}", name),
+                }
+            }
+            _ => (),
+        }
+        Ok(())
+    }
+
+    fn switch(&mut self, opcode: u8, line: &mut Cursor, offset: usize, code: &mut Vec<u8>) -> Result<(), ParseError> {
+        let low = if opcode == 0xaa { Some(line.number::<i32>()?) } else { None };
+        line.end()?;
+        let mut default = None;
+        let mut entries = Vec::new();
+        while default.is_none() {
+            let mut line = self.next_line()?;
+            let key = if line.keyword("default") {
+                None
+            } else if low.is_some() {
+                Some(0)
+            } else {
+                Some(line.number::<i32>()?)
+            };
+            let jump = self.jump(&mut line, offset)?;
+            let jump = match i32::try_from(jump) {
+                Ok(jump) => jump,
+                Err(_) if self.collecting() => 0,
+                Err(_) => fail!("Jump offset {} is out of range", jump),
+            };
+            match key {
+                Some(key) => entries.push((key, jump)),
+                None => default = Some(jump),
+            }
+            line.end()?;
+        }
+        switch_padding(code, offset);
+        code.extend_from_slice(&default.unwrap_or_default().to_be_bytes());
+        match low {
+            Some(low) => {
+                let high = i64::from(low) + entries.len() as i64 - 1;
+                match i32::try_from(high) {
+                    Ok(high) if !entries.is_empty() => {
+                        code.extend_from_slice(&low.to_be_bytes());
+                        code.extend_from_slice(&high.to_be_bytes());
+                    }
+                    _ => fail!("Invalid number of tableswitch jumps {}", entries.len()),
+                }
+                for (_, jump) in entries {
+                    code.extend_from_slice(&jump.to_be_bytes());
+                }
+            }
+            None => {
+                code.extend_from_slice(&(entries.len() as i32).to_be_bytes());
+                for (key, jump) in entries {
+                    code.extend_from_slice(&key.to_be_bytes());
+                    code.extend_from_slice(&jump.to_be_bytes());
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Assembles a class from its text in the assembly format, returning the bytes of the
+/// class file. The class file is not checked beyond what is needed to write it, so this
+/// can be used to create class files that are not valid.
+pub fn assemble(text: &str) -> Result<Vec<u8>, ParseError> {
+    let mut parser = Parser {
+        lines: tokenize(text)?,
+        pos: 0,
+        line_number: 0,
+        pool: ConstantPoolWriter::new(),
+        labels: None,
+    };
+    let result = parser
+        .reserve_ldc_constants()
+        .and_then(|_| parser.class())
+        .and_then(|class| write_class_with_pool(&class, &mut parser.pool));
+    result.map_err(|e| err!(e, "line {}", parser.line_number))
+}
+
+/// Assembles a class from its text in the assembly format and parses the result.
+pub fn parse_assembly(text: &str) -> Result<ClassFile, ParseError> {
+    parse_class(&assemble(text)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::writer::write_class;
+
+    const SAMPLE: &str = r#"
+.version 55 0
+.class public super Sample
+.super java/lang/Object
+.sourcefile "Sample.java"
+
+.field private static "odd name" [J
+    .synthetic
+.end field
+
+.method public static choose (I)I
+    .code stack 2 locals 1
+        iload_0
+        tableswitch 0
+            Zero
+            One
+            default Other
+Zero:   iconst_0
+        goto_w Done
+One:    ldc_w int 100000
+        goto Done
+Other:  iload_w 0
+        iinc_w 0 -1
+Done:   ireturn
+        .catch any Zero Done Other
+        .stackmaptable
+            same Zero
+            same One
+            same Other
+            same_locals_1_stack_item Done int
+        .end stackmaptable
+    .end code
+    .runtimevisibleannotations
+        .annotation LAnn;
+            value = array [ int 1 annotation LOther; { name = string "x\ty" } ]
+        .end annotation
+    .end runtimevisibleannotations
+    .runtimeinvisibletypeannotations
+        .typeannotation formalparameter 0 [ array typeargument 1 ] LNonNull;
+        .end typeannotation
+    .end runtimeinvisibletypeannotations
+.end method
+.end class
+"#;
+
+    #[test]
+    fn test_round_trip() {
+        let bytes = assemble(SAMPLE).unwrap();
+        let class = parse_class(&bytes).unwrap();
+        let text = print_assembly(&class).unwrap();
+        let reparsed = parse_assembly(&text).unwrap();
+        assert_eq!(print_assembly(&reparsed).unwrap(), text);
+        assert_eq!(write_class(&reparsed).unwrap(), assemble(&text).unwrap());
+
+        let field = &class.fields[0];
+        assert_eq!(field.name, "odd name");
+        let code = match &class.methods[0].attributes[0].data {
+            AttributeData::Code(code) => code,
+            _ => panic!("Expected a Code attribute"),
+        };
+        // The encodings are kept as written
+        assert!(text.contains("goto_w L"));
+        assert!(text.contains("ldc_w int 100000"));
+        assert!(text.contains("iload_w 0"));
+        assert_eq!(code.exception_table[0].catch_type, None);
+    }
+
+    #[test]
+    fn test_new_pool_ldc() {
+        let mut text = String::from(".version 52 0\n.class Test\n.super java/lang/Object\n");
+        for i in 0..300 {
+            text += &format!(".field static f{} I\n.end field\n", i);
+        }
+        text += ".method static m ()Ljava/lang/String;\n.code stack 1 locals 0\nldc string \"last\"\nareturn\n.end code\n.end method\n.end class\n";
+        let mut class = parse_assembly(&text).unwrap();
+        // Without the original pool, the constants are laid out anew, and the string
+        // must still be placed where ldc can reach it
+        class.constant_pool.clear();
+        let rewritten = parse_class(&write_class(&class).unwrap()).unwrap();
+        assert!(print_assembly(&rewritten).unwrap().contains("ldc string \"last\""));
+    }
+
+    #[test]
+    fn test_errors() {
+        let method = |body: &str| {
+            format!(".version 52 0\n.class Test\n.method static m ()V\n.code stack 1 locals 1\n{}\n.end code\n.end method\n.end class\n", body)
+        };
+        assert!(assemble(&method("return")).is_ok());
+        let err = assemble(&method("goto Nowhere")).unwrap_err().to_string();
+        assert_eq!(err, "Unknown label Nowhere for line 5");
+        let err = assemble(&method("A:\nA:\nreturn")).unwrap_err().to_string();
+        assert_eq!(err, "Label A is defined more than once for line 6");
+        let err = assemble(&method("frobnicate")).unwrap_err().to_string();
+        assert_eq!(err, "Unknown instruction frobnicate for line 5");
+        assert!(assemble(".version 52 0\n.class Test\n").is_err());
+    }
+}
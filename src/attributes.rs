@@ -1,16 +1,20 @@
 use std::borrow::Cow;
-use std::ops::Deref;
+use std::cell::{Cell, OnceCell};
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::ops::Range;
 use std::rc::Rc;
 
-use crate::{read_u1, read_u2, read_u4, AccessFlags, ParseError, ParseOptions};
+use crate::{bounded_capacity, read_u1, read_u2, read_u4, AccessFlags, ParseError, ParseOptions};
 use crate::bytecode::{ByteCode};
-use crate::constant_pool::{ConstantPoolEntry, NameAndType, LiteralConstant, MethodHandle, BootstrapArgument};
+use crate::constant_pool::{ConstantPoolEntry, ConstantPoolReader, NameAndType, LiteralConstant, MethodHandle, BootstrapArgument};
 use crate::constant_pool::{read_cp_utf8, read_cp_utf8_opt, read_cp_classinfo, read_cp_classinfo_opt, read_cp_nameandtype_opt,
     read_cp_literalconstant, read_cp_integer, read_cp_float, read_cp_long, read_cp_double, read_cp_methodhandle,
     read_cp_bootstrap_argument, read_cp_moduleinfo, read_cp_packageinfo};
 use crate::names::{is_field_descriptor, is_return_descriptor, is_unqualified_name};
 
-#[derive(Debug)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ExceptionTableEntry {
     pub start_pc: u16,
     pub end_pc: u16,
@@ -18,7 +22,8 @@ pub struct ExceptionTableEntry {
     pub catch_type: Option<String>,
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct CodeData {
     pub max_stack: u16,
     pub max_locals: u16,
@@ -28,7 +33,8 @@ pub struct CodeData {
     pub attributes: Vec<AttributeInfo>,
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum VerificationType {
     Top,
     Integer,
@@ -41,7 +47,8 @@ pub enum VerificationType {
     Object { class_name: String },
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum StackMapEntry {
     Same { offset_delta: u16 },
     SameLocals1StackItem { offset_delta: u16, stack: VerificationType },
@@ -65,7 +72,11 @@ bitflags! {
     }
 }
 
-#[derive(Debug)]
+#[cfg(feature = "serde")]
+serde_flags!(InnerClassAccessFlags, PUBLIC, PRIVATE, PROTECTED, STATIC, FINAL, INTERFACE, ABSTRACT, SYNTHETIC, ANNOTATION, ENUM);
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct InnerClassEntry {
     pub inner_class_info: String,
     pub outer_class_info: Option<String>,
@@ -73,13 +84,15 @@ pub struct InnerClassEntry {
     pub access_flags: InnerClassAccessFlags,
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct LineNumberEntry {
     pub start_pc: u16,
     pub line_number: u16,
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct LocalVariableEntry {
     pub start_pc: u16,
     pub length: u16,
@@ -88,7 +101,8 @@ pub struct LocalVariableEntry {
     pub index: u16,
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct LocalVariableTypeEntry {
     pub start_pc: u16,
     pub length: u16,
@@ -97,12 +111,13 @@ pub struct LocalVariableTypeEntry {
     pub index: u16,
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum AnnotationElementValue {
     ByteConstant(i32),
     CharConstant(i32),
-    DoubleConstant(f64),
-    FloatConstant(f32),
+    DoubleConstant(#[cfg_attr(feature = "serde", serde(with = "crate::serde_support::double"))] f64),
+    FloatConstant(#[cfg_attr(feature = "serde", serde(with = "crate::serde_support::float"))] f32),
     IntConstant(i32),
     LongConstant(i64),
     ShortConstant(i32),
@@ -114,31 +129,82 @@ pub enum AnnotationElementValue {
     ArrayValue(Vec<AnnotationElementValue>),
 }
 
-#[derive(Debug)]
+// As with LiteralConstant, floating point values are compared and hashed by their bits
+impl PartialEq for AnnotationElementValue {
+    fn eq(&self, other: &Self) -> bool {
+        use AnnotationElementValue::*;
+        match (self, other) {
+            (ByteConstant(a), ByteConstant(b)) => a == b,
+            (CharConstant(a), CharConstant(b)) => a == b,
+            (DoubleConstant(a), DoubleConstant(b)) => a.to_bits() == b.to_bits(),
+            (FloatConstant(a), FloatConstant(b)) => a.to_bits() == b.to_bits(),
+            (IntConstant(a), IntConstant(b)) => a == b,
+            (LongConstant(a), LongConstant(b)) => a == b,
+            (ShortConstant(a), ShortConstant(b)) => a == b,
+            (BooleanConstant(a), BooleanConstant(b)) => a == b,
+            (StringConstant(a), StringConstant(b)) => a == b,
+            (EnumConstant { type_name: a, const_name: b }, EnumConstant { type_name: c, const_name: d }) => a == c && b == d,
+            (ClassLiteral { class_name: a }, ClassLiteral { class_name: b }) => a == b,
+            (AnnotationValue(a), AnnotationValue(b)) => a == b,
+            (ArrayValue(a), ArrayValue(b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+impl Eq for AnnotationElementValue {}
+
+impl Hash for AnnotationElementValue {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        use AnnotationElementValue::*;
+        std::mem::discriminant(self).hash(state);
+        match self {
+            ByteConstant(v) | CharConstant(v) | IntConstant(v) | ShortConstant(v) | BooleanConstant(v) => v.hash(state),
+            DoubleConstant(v) => v.to_bits().hash(state),
+            FloatConstant(v) => v.to_bits().hash(state),
+            LongConstant(v) => v.hash(state),
+            StringConstant(v) => v.hash(state),
+            EnumConstant { type_name, const_name } => {
+                type_name.hash(state);
+                const_name.hash(state);
+            }
+            ClassLiteral { class_name } => class_name.hash(state),
+            AnnotationValue(v) => v.hash(state),
+            ArrayValue(v) => v.hash(state),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct AnnotationElement {
     pub name: String,
     pub value: AnnotationElementValue,
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Annotation {
     pub type_descriptor: String,
     pub elements: Vec<AnnotationElement>,
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ParameterAnnotation {
     pub annotations: Vec<Annotation>,
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct TypeAnnotationLocalVarTargetEntry {
     pub start_pc: u16,
     pub length: u16,
     pub index: u16,
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum TypeAnnotationTarget {
     TypeParameter { index: u8 },
     Supertype { index: u16 },
@@ -148,11 +214,16 @@ pub enum TypeAnnotationTarget {
     Throws { index: u16 },
     LocalVar(Vec<TypeAnnotationLocalVarTargetEntry>),
     Catch { exception_table_index: u16 },
-    Offset { offset: u16 },
-    TypeArgument { offset: u16, type_argument_index: u8 },
+    /// An instanceof (target_type 0x43), new (0x44), constructor reference (0x45) or method
+    /// reference (0x46) expression.
+    Offset { target_type: u8, offset: u16 },
+    /// A type argument of a cast (target_type 0x47), constructor invocation (0x48), method
+    /// invocation (0x49), constructor reference (0x4A) or method reference (0x4B).
+    TypeArgument { target_type: u8, offset: u16, type_argument_index: u8 },
 }
 
-#[derive(Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum TypeAnnotationTargetPathKind {
     DeeperArray,
     DeeperNested,
@@ -160,20 +231,23 @@ pub enum TypeAnnotationTargetPathKind {
     TypeArgument,
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct TypeAnnotationTargetPathEntry {
     pub path_kind: TypeAnnotationTargetPathKind,
     pub argument_index: u8,
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct TypeAnnotation {
     pub target_type: TypeAnnotationTarget,
     pub target_path: Vec<TypeAnnotationTargetPathEntry>,
     pub annotation: Annotation,
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct BootstrapMethodEntry {
     pub method: MethodHandle,
     pub arguments: Vec<BootstrapArgument>,
@@ -187,7 +261,11 @@ bitflags! {
     }
 }
 
-#[derive(Debug)]
+#[cfg(feature = "serde")]
+serde_flags!(MethodParameterAccessFlags, FINAL, SYNTHETIC, MANDATED);
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct MethodParameterEntry {
     pub name: Option<String>,
     pub access_flags: MethodParameterAccessFlags,
@@ -201,6 +279,9 @@ bitflags! {
     }
 }
 
+#[cfg(feature = "serde")]
+serde_flags!(ModuleAccessFlags, OPEN, SYNTHETIC, MANDATED);
+
 bitflags! {
     pub struct ModuleRequiresFlags: u16 {
         const TRANSITIVE = AccessFlags::TRANSITIVE.bits();
@@ -210,7 +291,11 @@ bitflags! {
     }
 }
 
-#[derive(Debug)]
+#[cfg(feature = "serde")]
+serde_flags!(ModuleRequiresFlags, TRANSITIVE, STATIC_PHASE, SYNTHETIC, MANDATED);
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ModuleRequireEntry {
     pub name: String,
     pub flags: ModuleRequiresFlags,
@@ -224,7 +309,11 @@ bitflags! {
     }
 }
 
-#[derive(Debug)]
+#[cfg(feature = "serde")]
+serde_flags!(ModuleExportsFlags, SYNTHETIC, MANDATED);
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ModuleExportsEntry {
     pub package_name: String,
     pub flags: ModuleExportsFlags,
@@ -238,20 +327,26 @@ bitflags! {
     }
 }
 
-#[derive(Debug)]
+#[cfg(feature = "serde")]
+serde_flags!(ModuleOpensFlags, SYNTHETIC, MANDATED);
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ModuleOpensEntry {
     pub package_name: String,
     pub flags: ModuleOpensFlags,
     pub opens_to: Vec<String>,
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ModuleProvidesEntry {
     pub service_interface_name: String,
     pub provides_with: Vec<String>,
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ModuleData {
     pub name: String,
     pub access_flags: ModuleAccessFlags,
@@ -263,14 +358,16 @@ pub struct ModuleData {
     pub provides: Vec<ModuleProvidesEntry>,
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct RecordComponentEntry {
     pub name: String,
     pub descriptor: String,
     pub attributes: Vec<AttributeInfo>,
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum AttributeData {
     ConstantValue(LiteralConstant),
     Code(CodeData),
@@ -302,14 +399,174 @@ pub enum AttributeData {
     NestMembers(Vec<String>),
     Record(Vec<RecordComponentEntry>),
     Other(Vec<u8>),
+    Lazy(LazyAttribute),
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct AttributeInfo {
     pub name: String,
     pub data: AttributeData,
 }
 
+impl AttributeInfo {
+    /// Returns the contents of this attribute, decoding them first if the
+    /// class was parsed with ParseOptions::lazy_attributes. The returned
+    /// data is never AttributeData::Lazy.
+    pub fn decoded(&self) -> Result<&AttributeData, ParseError> {
+        match &self.data {
+            AttributeData::Lazy(lazy) => lazy.decode(),
+            data => Ok(data),
+        }
+    }
+}
+
+// Attributes are compared by their decoded contents, so that an attribute
+// parsed lazily is equal to the same attribute parsed up front. Attributes
+// that fail to decode fall back to comparing their undecoded bytes.
+impl PartialEq for AttributeInfo {
+    fn eq(&self, other: &Self) -> bool {
+        if self.name != other.name {
+            return false;
+        }
+        match (self.decoded(), other.decoded()) {
+            (Ok(a), Ok(b)) => a == b,
+            (Err(_), Err(_)) => self.data == other.data,
+            _ => false,
+        }
+    }
+}
+
+impl Eq for AttributeInfo {}
+
+impl Hash for AttributeInfo {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.name.hash(state);
+        match self.decoded() {
+            Ok(data) => data.hash(state),
+            Err(_) => self.data.hash(state),
+        }
+    }
+}
+
+/// The class file bytes and constant pool shared by all the lazily decoded
+/// attributes of a class.
+pub(crate) struct LazySource {
+    pub(crate) bytes: Rc<[u8]>,
+    pub(crate) pool: Rc<[Rc<ConstantPoolEntry>]>,
+    pub(crate) opts: ParseOptions,
+    pub(crate) major_version: u16,
+    // what is left of ParseOptions::max_allocation, shared by all the attributes
+    pub(crate) budget: Cell<usize>,
+}
+
+#[derive(Clone)]
+struct LazyRange {
+    source: Rc<LazySource>,
+    name: String,
+    index: u16,
+    range: Range<usize>,
+    depth: usize,
+}
+
+/// An attribute whose contents have not necessarily been decoded yet. These
+/// are produced when parsing with ParseOptions::lazy_attributes; the contents
+/// are decoded on the first call to `decode` and kept for later calls.
+#[derive(Clone)]
+pub struct LazyAttribute {
+    source: Option<LazyRange>,
+    decoded: OnceCell<Box<AttributeData>>,
+}
+
+impl LazyAttribute {
+    /// Decodes the contents of the attribute, or returns the previously
+    /// decoded contents. Errors are not cached; decoding a malformed
+    /// attribute again returns the same error again.
+    pub fn decode(&self) -> Result<&AttributeData, ParseError> {
+        if let Some(data) = self.decoded.get() {
+            return Ok(data);
+        }
+        let lazy = match &self.source {
+            Some(lazy) => lazy,
+            None => fail!("Lazy attribute has neither contents nor source bytes"),
+        };
+        let source = &lazy.source;
+        let pool = ConstantPoolReader::new(&source.pool, source.budget.get(), lazy.depth, source.opts.max_nesting_depth);
+        let mut ix = lazy.range.start;
+        let data = read_attribute_data(&source.bytes, &mut ix, &pool, &source.opts, Some(source), &lazy.name, lazy.range.len(), lazy.index);
+        source.budget.set(pool.remaining_budget());
+        let data = data?;
+        if ix != lazy.range.end {
+            fail!("Length mismatch when reading attribute {}", lazy.index);
+        }
+        if source.opts.validate_attribute_placement {
+            validate_nested_placement(&data, source.major_version).map_err(|e| err!(e, "attribute {}", lazy.index))?;
+        }
+        Ok(self.decoded.get_or_init(|| Box::new(data)))
+    }
+
+    /// Returns true if the contents have already been decoded.
+    pub fn is_decoded(&self) -> bool {
+        self.decoded.get().is_some()
+    }
+
+    /// The undecoded bytes of the attribute, if it was parsed lazily.
+    pub(crate) fn raw_bytes(&self) -> Option<&[u8]> {
+        self.source.as_ref().map(|lazy| &lazy.source.bytes[lazy.range.clone()])
+    }
+}
+
+impl fmt::Debug for LazyAttribute {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut s = f.debug_struct("LazyAttribute");
+        if let Some(lazy) = &self.source {
+            s.field("name", &lazy.name).field("length", &lazy.range.len());
+        }
+        s.field("decoded", &self.decoded.get()).finish()
+    }
+}
+
+impl PartialEq for LazyAttribute {
+    fn eq(&self, other: &Self) -> bool {
+        match (self.decode(), other.decode()) {
+            (Ok(a), Ok(b)) => a == b,
+            (Err(_), Err(_)) => self.raw_bytes() == other.raw_bytes(),
+            _ => false,
+        }
+    }
+}
+
+impl Eq for LazyAttribute {}
+
+impl Hash for LazyAttribute {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        match self.decode() {
+            Ok(data) => data.hash(state),
+            Err(_) => self.raw_bytes().hash(state),
+        }
+    }
+}
+
+// Lazy attributes are serialized as their decoded contents, and come back
+// already decoded.
+#[cfg(feature = "serde")]
+impl serde::Serialize for LazyAttribute {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.decode().map_err(serde::ser::Error::custom)?.serialize(serializer)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for LazyAttribute {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let data = AttributeData::deserialize(deserializer)?;
+        Ok(LazyAttribute {
+            source: None,
+            decoded: OnceCell::from(Box::new(data)),
+        })
+    }
+}
+
 fn ensure_length(length: usize, expected: usize) -> Result<(), ParseError> {
     if length != expected {
         fail!("Unexpected length {}", length);
@@ -317,17 +574,20 @@ fn ensure_length(length: usize, expected: usize) -> Result<(), ParseError> {
     Ok(())
 }
 
-fn read_code_data(bytes: &[u8], ix: &mut usize, pool: &[Rc<ConstantPoolEntry>], opts: &ParseOptions) -> Result<CodeData, ParseError> {
+fn read_code_data(bytes: &[u8], ix: &mut usize, pool: &ConstantPoolReader, opts: &ParseOptions, lazy: Option<&Rc<LazySource>>) -> Result<CodeData, ParseError> {
     let max_stack = read_u2(bytes, ix)?;
     let max_locals = read_u2(bytes, ix)?;
     let code_length = read_u4(bytes, ix)? as usize;
-    if bytes.len() < *ix + code_length {
+    if code_length > opts.max_code_length {
+        fail!("Code length {} exceeds the maximum of {}", code_length, opts.max_code_length);
+    }
+    if bytes.len() - *ix < code_length {
         fail!("Unexpected end of stream reading code attribute at index {}", *ix);
     }
     let code = &bytes[*ix .. *ix + code_length];
     *ix += code_length;
     let exception_table_count = read_u2(bytes, ix)?;
-    let mut exception_table = Vec::with_capacity(exception_table_count.into());
+    let mut exception_table = Vec::with_capacity(bounded_capacity(exception_table_count, bytes, *ix));
     for i in 0..exception_table_count {
         let start_pc = read_u2(bytes, ix)?;
         let end_pc = read_u2(bytes, ix)?;
@@ -340,12 +600,22 @@ fn read_code_data(bytes: &[u8], ix: &mut usize, pool: &[Rc<ConstantPoolEntry>],
             catch_type,
         });
     }
-    let code_attributes = read_attributes(bytes, ix, pool, opts).map_err(|e| err!(e, "code attribute"))?;
-    let bytecode = if opts.parse_bytecode {
+    let code_attributes = read_attributes(bytes, ix, pool, opts, lazy).map_err(|e| err!(e, "code attribute"))?;
+    let bytecode = if opts.parse_bytecode || opts.check_static_constraints {
         Some(ByteCode::from(code, pool).map_err(|e| err!(e, "bytecode"))?)
     } else {
         None
     };
+    if opts.check_static_constraints {
+        if code.is_empty() || code.len() >= 65536 {
+            fail!("Invalid code length {}", code.len());
+        }
+        // This unwrap is safe because the bytecode is always parsed when checking
+        let checked = bytecode.as_ref().unwrap();
+        checked.validate_static_constraints(max_locals).map_err(|e| err!(e, "bytecode"))?;
+        validate_exception_table(&exception_table, checked, code.len())?;
+    }
+    let bytecode = if opts.parse_bytecode { bytecode } else { None };
     Ok(CodeData {
         max_stack,
         max_locals,
@@ -356,7 +626,25 @@ fn read_code_data(bytes: &[u8], ix: &mut usize, pool: &[Rc<ConstantPoolEntry>],
     })
 }
 
-fn read_stackmaptable_verification(bytes: &[u8], ix: &mut usize, pool: &[Rc<ConstantPoolEntry>]) -> Result<VerificationType, ParseError> {
+pub(crate) fn validate_exception_table(exception_table: &[ExceptionTableEntry], bytecode: &ByteCode, code_length: usize) -> Result<(), ParseError> {
+    for (i, entry) in exception_table.iter().enumerate() {
+        if entry.start_pc >= entry.end_pc {
+            fail!(("Start pc {} is not less than end pc {}", entry.start_pc, entry.end_pc), ("exception table entry {}", i));
+        }
+        if bytecode.get_opcode_index(entry.start_pc.into()).is_none() {
+            fail!(("Start pc {} is not on an instruction boundary", entry.start_pc), ("exception table entry {}", i));
+        }
+        if usize::from(entry.end_pc) != code_length && bytecode.get_opcode_index(entry.end_pc.into()).is_none() {
+            fail!(("End pc {} is not on an instruction boundary", entry.end_pc), ("exception table entry {}", i));
+        }
+        if bytecode.get_opcode_index(entry.handler_pc.into()).is_none() {
+            fail!(("Handler pc {} is not on an instruction boundary", entry.handler_pc), ("exception table entry {}", i));
+        }
+    }
+    Ok(())
+}
+
+fn read_stackmaptable_verification(bytes: &[u8], ix: &mut usize, pool: &ConstantPoolReader) -> Result<VerificationType, ParseError> {
     let verification_type = match read_u1(bytes, ix)? {
         0 => VerificationType::Top,
         1 => VerificationType::Integer,
@@ -378,9 +666,9 @@ fn read_stackmaptable_verification(bytes: &[u8], ix: &mut usize, pool: &[Rc<Cons
     Ok(verification_type)
 }
 
-fn read_stackmaptable_data(bytes: &[u8], ix: &mut usize, pool: &[Rc<ConstantPoolEntry>]) -> Result<Vec<StackMapEntry>, ParseError> {
+fn read_stackmaptable_data(bytes: &[u8], ix: &mut usize, pool: &ConstantPoolReader) -> Result<Vec<StackMapEntry>, ParseError> {
     let count = read_u2(bytes, ix)?;
-    let mut stackmapframes = Vec::with_capacity(count.into());
+    let mut stackmapframes = Vec::with_capacity(bounded_capacity(count, bytes, *ix));
     for i in 0..count {
         let entry = match read_u1(bytes, ix)? {
             v @ 0..=63 => StackMapEntry::Same { offset_delta: v.into() },
@@ -405,7 +693,7 @@ fn read_stackmaptable_data(bytes: &[u8], ix: &mut usize, pool: &[Rc<ConstantPool
             v @ 252..=254 => {
                 let offset_delta = read_u2(bytes, ix)?;
                 let verification_count = v - 251;
-                let mut locals = Vec::with_capacity(verification_count.into());
+                let mut locals = Vec::with_capacity(bounded_capacity(verification_count, bytes, *ix));
                 for j in 0..verification_count {
                     locals.push(read_stackmaptable_verification(bytes, ix, pool).map_err(|e| err!(e, "local entry {} of append stack map entry {}", j, i))?);
                 }
@@ -414,12 +702,12 @@ fn read_stackmaptable_data(bytes: &[u8], ix: &mut usize, pool: &[Rc<ConstantPool
             255 => {
                 let offset_delta = read_u2(bytes, ix)?;
                 let locals_count = read_u2(bytes, ix)?;
-                let mut locals = Vec::with_capacity(locals_count.into());
+                let mut locals = Vec::with_capacity(bounded_capacity(locals_count, bytes, *ix));
                 for j in 0..locals_count {
                     locals.push(read_stackmaptable_verification(bytes, ix, pool).map_err(|e| err!(e, "local entry {} of full-frame stack map entry {}", j, i))?);
                 }
                 let stack_count = read_u2(bytes, ix)?;
-                let mut stack = Vec::with_capacity(stack_count.into());
+                let mut stack = Vec::with_capacity(bounded_capacity(stack_count, bytes, *ix));
                 for j in 0..stack_count {
                     stack.push(read_stackmaptable_verification(bytes, ix, pool).map_err(|e| err!(e, "stack entry {} of full-frame stack map entry {}", j, i))?);
                 }
@@ -431,9 +719,9 @@ fn read_stackmaptable_data(bytes: &[u8], ix: &mut usize, pool: &[Rc<ConstantPool
     Ok(stackmapframes)
 }
 
-fn read_exceptions_data(bytes: &[u8], ix: &mut usize, pool: &[Rc<ConstantPoolEntry>]) -> Result<Vec<String>, ParseError> {
+fn read_exceptions_data(bytes: &[u8], ix: &mut usize, pool: &ConstantPoolReader) -> Result<Vec<String>, ParseError> {
     let count = read_u2(bytes, ix)?;
-    let mut exceptions = Vec::with_capacity(count.into());
+    let mut exceptions = Vec::with_capacity(bounded_capacity(count, bytes, *ix));
     for i in 0..count {
         let exception = read_cp_classinfo(bytes, ix, pool).map_err(|e| err!(e, "exception {}", i))?;
         exceptions.push(exception);
@@ -441,9 +729,9 @@ fn read_exceptions_data(bytes: &[u8], ix: &mut usize, pool: &[Rc<ConstantPoolEnt
     Ok(exceptions)
 }
 
-fn read_innerclasses_data(bytes: &[u8], ix: &mut usize, pool: &[Rc<ConstantPoolEntry>]) -> Result<Vec<InnerClassEntry>, ParseError> {
+fn read_innerclasses_data(bytes: &[u8], ix: &mut usize, pool: &ConstantPoolReader) -> Result<Vec<InnerClassEntry>, ParseError> {
     let count = read_u2(bytes, ix)?;
-    let mut innerclasses = Vec::with_capacity(count.into());
+    let mut innerclasses = Vec::with_capacity(bounded_capacity(count, bytes, *ix));
     for i in 0..count {
         let inner_class_info = read_cp_classinfo(bytes, ix, pool).map_err(|e| err!(e, "inner class info for inner class {}", i))?;
         let outer_class_info = read_cp_classinfo_opt(bytes, ix, pool).map_err(|e| err!(e, "outer class info for inner class {}", i))?;
@@ -461,7 +749,7 @@ fn read_innerclasses_data(bytes: &[u8], ix: &mut usize, pool: &[Rc<ConstantPoolE
 
 fn read_linenumber_data(bytes: &[u8], ix: &mut usize) -> Result<Vec<LineNumberEntry>, ParseError> {
     let count = read_u2(bytes, ix)?;
-    let mut linenumbers = Vec::with_capacity(count.into());
+    let mut linenumbers = Vec::with_capacity(bounded_capacity(count, bytes, *ix));
     for _i in 0..count {
         let start_pc = read_u2(bytes, ix)?;
         let line_number = read_u2(bytes, ix)?;
@@ -473,9 +761,9 @@ fn read_linenumber_data(bytes: &[u8], ix: &mut usize) -> Result<Vec<LineNumberEn
     Ok(linenumbers)
 }
 
-fn read_localvariable_data(bytes: &[u8], ix: &mut usize, pool: &[Rc<ConstantPoolEntry>]) -> Result<Vec<LocalVariableEntry>, ParseError> {
+fn read_localvariable_data(bytes: &[u8], ix: &mut usize, pool: &ConstantPoolReader) -> Result<Vec<LocalVariableEntry>, ParseError> {
     let count = read_u2(bytes, ix)?;
-    let mut localvariables = Vec::with_capacity(count.into());
+    let mut localvariables = Vec::with_capacity(bounded_capacity(count, bytes, *ix));
     for i in 0..count {
         let start_pc = read_u2(bytes, ix)?;
         let length = read_u2(bytes, ix)?;
@@ -499,9 +787,9 @@ fn read_localvariable_data(bytes: &[u8], ix: &mut usize, pool: &[Rc<ConstantPool
     Ok(localvariables)
 }
 
-fn read_localvariabletype_data(bytes: &[u8], ix: &mut usize, pool: &[Rc<ConstantPoolEntry>]) -> Result<Vec<LocalVariableTypeEntry>, ParseError> {
+fn read_localvariabletype_data(bytes: &[u8], ix: &mut usize, pool: &ConstantPoolReader) -> Result<Vec<LocalVariableTypeEntry>, ParseError> {
     let count = read_u2(bytes, ix)?;
-    let mut localvariabletypes = Vec::with_capacity(count.into());
+    let mut localvariabletypes = Vec::with_capacity(bounded_capacity(count, bytes, *ix));
     for i in 0..count {
         let start_pc = read_u2(bytes, ix)?;
         let length = read_u2(bytes, ix)?;
@@ -522,7 +810,7 @@ fn read_localvariabletype_data(bytes: &[u8], ix: &mut usize, pool: &[Rc<Constant
     Ok(localvariabletypes)
 }
 
-fn read_annotation_element_value(bytes: &[u8], ix: &mut usize, pool: &[Rc<ConstantPoolEntry>]) -> Result<AnnotationElementValue, ParseError> {
+fn read_annotation_element_value(bytes: &[u8], ix: &mut usize, pool: &ConstantPoolReader) -> Result<AnnotationElementValue, ParseError> {
     let value = match read_u1(bytes, ix)? as char {
         'B' => AnnotationElementValue::ByteConstant(read_cp_integer(bytes, ix, pool)?),
         'C' => AnnotationElementValue::CharConstant(read_cp_integer(bytes, ix, pool)?),
@@ -548,27 +836,27 @@ fn read_annotation_element_value(bytes: &[u8], ix: &mut usize, pool: &[Rc<Consta
             }
             AnnotationElementValue::ClassLiteral { class_name }
         }
-        '@' => AnnotationElementValue::AnnotationValue(read_annotation(bytes, ix, pool)?),
-        '[' => {
+        '@' => AnnotationElementValue::AnnotationValue(pool.nested(|| read_annotation(bytes, ix, pool))?),
+        '[' => pool.nested(|| {
             let count = read_u2(bytes, ix)?;
-            let mut array_values = Vec::with_capacity(count.into());
+            let mut array_values = Vec::with_capacity(bounded_capacity(count, bytes, *ix));
             for i in 0..count {
                 array_values.push(read_annotation_element_value(bytes, ix, pool).map_err(|e| err!(e, "array index {}", i))?);
             }
-            AnnotationElementValue::ArrayValue(array_values)
-        }
+            Ok(AnnotationElementValue::ArrayValue(array_values))
+        })?,
         v => fail!("Unrecognized discriminant {}", v),
     };
     Ok(value)
 }
 
-fn read_annotation(bytes: &[u8], ix: &mut usize, pool: &[Rc<ConstantPoolEntry>]) -> Result<Annotation, ParseError> {
+fn read_annotation(bytes: &[u8], ix: &mut usize, pool: &ConstantPoolReader) -> Result<Annotation, ParseError> {
     let type_descriptor = read_cp_utf8(bytes, ix, pool).map_err(|e| err!(e, "type descriptor field"))?;
     if !is_field_descriptor(&type_descriptor) {
         fail!("Invalid descriptor");
     }
     let element_count = read_u2(bytes, ix)?;
-    let mut elements = Vec::with_capacity(element_count.into());
+    let mut elements = Vec::with_capacity(bounded_capacity(element_count, bytes, *ix));
     for i in 0..element_count {
         let name = read_cp_utf8(bytes, ix, pool).map_err(|e| err!(e, "name of element {}", i))?;
         let value = read_annotation_element_value(bytes, ix, pool).map_err(|e| err!(e, "value of element {}", i))?;
@@ -583,21 +871,21 @@ fn read_annotation(bytes: &[u8], ix: &mut usize, pool: &[Rc<ConstantPoolEntry>])
     })
 }
 
-fn read_annotation_data(bytes: &[u8], ix: &mut usize, pool: &[Rc<ConstantPoolEntry>]) -> Result<Vec<Annotation>, ParseError> {
+fn read_annotation_data(bytes: &[u8], ix: &mut usize, pool: &ConstantPoolReader) -> Result<Vec<Annotation>, ParseError> {
     let count = read_u2(bytes, ix)?;
-    let mut annotations = Vec::with_capacity(count.into());
+    let mut annotations = Vec::with_capacity(bounded_capacity(count, bytes, *ix));
     for i in 0..count {
         annotations.push(read_annotation(bytes, ix, pool).map_err(|e| err!(e, "annotation {}", i))?);
     }
     Ok(annotations)
 }
 
-fn read_parameter_annotation_data(bytes: &[u8], ix: &mut usize, pool: &[Rc<ConstantPoolEntry>]) -> Result<Vec<ParameterAnnotation>, ParseError> {
+fn read_parameter_annotation_data(bytes: &[u8], ix: &mut usize, pool: &ConstantPoolReader) -> Result<Vec<ParameterAnnotation>, ParseError> {
     let count = read_u1(bytes, ix)?;
-    let mut parameters = Vec::with_capacity(count.into());
+    let mut parameters = Vec::with_capacity(bounded_capacity(count, bytes, *ix));
     for i in 0..count {
         let annotation_count = read_u2(bytes, ix)?;
-        let mut annotations = Vec::with_capacity(annotation_count.into());
+        let mut annotations = Vec::with_capacity(bounded_capacity(annotation_count, bytes, *ix));
         for j in 0..annotation_count {
             annotations.push(read_annotation(bytes, ix, pool).map_err(|e| err!(e, "annotation {} of parameter {}", j, i))?);
         }
@@ -608,9 +896,9 @@ fn read_parameter_annotation_data(bytes: &[u8], ix: &mut usize, pool: &[Rc<Const
     Ok(parameters)
 }
 
-fn read_type_annotation_data(bytes: &[u8], ix: &mut usize, pool: &[Rc<ConstantPoolEntry>]) -> Result<Vec<TypeAnnotation>, ParseError> {
+fn read_type_annotation_data(bytes: &[u8], ix: &mut usize, pool: &ConstantPoolReader) -> Result<Vec<TypeAnnotation>, ParseError> {
     let count = read_u2(bytes, ix)?;
-    let mut annotations = Vec::with_capacity(count.into());
+    let mut annotations = Vec::with_capacity(bounded_capacity(count, bytes, *ix));
     for i in 0..count {
         let target_type = match read_u1(bytes, ix)? {
             0x00 | 0x01 => TypeAnnotationTarget::TypeParameter { index: read_u1(bytes, ix)? },
@@ -621,7 +909,7 @@ fn read_type_annotation_data(bytes: &[u8], ix: &mut usize, pool: &[Rc<ConstantPo
             0x17 => TypeAnnotationTarget::Throws { index: read_u2(bytes, ix)? },
             0x40 | 0x41 => {
                 let localvar_count = read_u2(bytes, ix)?;
-                let mut localvars = Vec::with_capacity(localvar_count.into());
+                let mut localvars = Vec::with_capacity(bounded_capacity(localvar_count, bytes, *ix));
                 for _j in 0..localvar_count {
                     let start_pc = read_u2(bytes, ix)?;
                     let length = read_u2(bytes, ix)?;
@@ -635,12 +923,12 @@ fn read_type_annotation_data(bytes: &[u8], ix: &mut usize, pool: &[Rc<ConstantPo
                 TypeAnnotationTarget::LocalVar(localvars)
             }
             0x42 => TypeAnnotationTarget::Catch { exception_table_index: read_u2(bytes, ix)? },
-            0x43 | 0x44 | 0x45 | 0x46 => TypeAnnotationTarget::Offset { offset: read_u2(bytes, ix)? },
-            0x47 | 0x48 | 0x49 | 0x4A | 0x4B => TypeAnnotationTarget::TypeArgument { offset: read_u2(bytes, ix)?, type_argument_index: read_u1(bytes, ix)? },
+            target_type @ 0x43..=0x46 => TypeAnnotationTarget::Offset { target_type, offset: read_u2(bytes, ix)? },
+            target_type @ 0x47..=0x4B => TypeAnnotationTarget::TypeArgument { target_type, offset: read_u2(bytes, ix)?, type_argument_index: read_u1(bytes, ix)? },
             v => fail!(("Unrecognized target type {}", v), ("type annotation {}", i)),
         };
         let path_count = read_u1(bytes, ix)?;
-        let mut target_path = Vec::with_capacity(path_count.into());
+        let mut target_path = Vec::with_capacity(bounded_capacity(path_count, bytes, *ix));
         for j in 0..path_count {
             let path_kind = match read_u1(bytes, ix)? {
                 0 => TypeAnnotationTargetPathKind::DeeperArray,
@@ -665,13 +953,13 @@ fn read_type_annotation_data(bytes: &[u8], ix: &mut usize, pool: &[Rc<ConstantPo
     Ok(annotations)
 }
 
-fn read_bootstrapmethods_data(bytes: &[u8], ix: &mut usize, pool: &[Rc<ConstantPoolEntry>]) -> Result<Vec<BootstrapMethodEntry>, ParseError> {
+fn read_bootstrapmethods_data(bytes: &[u8], ix: &mut usize, pool: &ConstantPoolReader) -> Result<Vec<BootstrapMethodEntry>, ParseError> {
     let count = read_u2(bytes, ix)?;
-    let mut bootstrapmethods = Vec::with_capacity(count.into());
+    let mut bootstrapmethods = Vec::with_capacity(bounded_capacity(count, bytes, *ix));
     for i in 0..count {
         let method = read_cp_methodhandle(bytes, ix, pool).map_err(|e| err!(e, "method ref of bootstrap method {}", i))?;
         let arg_count = read_u2(bytes, ix)?;
-        let mut arguments = Vec::with_capacity(arg_count.into());
+        let mut arguments = Vec::with_capacity(bounded_capacity(arg_count, bytes, *ix));
         for j in 0..arg_count {
             let argument = read_cp_bootstrap_argument(bytes, ix, pool).map_err(|e| err!(e, "argument {} of bootstrap method {}", j, i))?;
             arguments.push(argument);
@@ -684,9 +972,9 @@ fn read_bootstrapmethods_data(bytes: &[u8], ix: &mut usize, pool: &[Rc<ConstantP
     Ok(bootstrapmethods)
 }
 
-fn read_methodparameters_data(bytes: &[u8], ix: &mut usize, pool: &[Rc<ConstantPoolEntry>]) -> Result<Vec<MethodParameterEntry>, ParseError> {
+fn read_methodparameters_data(bytes: &[u8], ix: &mut usize, pool: &ConstantPoolReader) -> Result<Vec<MethodParameterEntry>, ParseError> {
     let count = read_u1(bytes, ix)?;
-    let mut methodparameters = Vec::with_capacity(count.into());
+    let mut methodparameters = Vec::with_capacity(bounded_capacity(count, bytes, *ix));
     for i in 0..count {
         let name = read_cp_utf8_opt(bytes, ix, pool).map_err(|e| err!(e, "name of method parameter {}", i))?;
         if name.is_some() && !is_unqualified_name(name.as_ref().unwrap(), false, false) {
@@ -701,12 +989,12 @@ fn read_methodparameters_data(bytes: &[u8], ix: &mut usize, pool: &[Rc<ConstantP
     Ok(methodparameters)
 }
 
-fn read_module_data(bytes: &[u8], ix: &mut usize, pool: &[Rc<ConstantPoolEntry>]) -> Result<ModuleData, ParseError> {
+fn read_module_data(bytes: &[u8], ix: &mut usize, pool: &ConstantPoolReader) -> Result<ModuleData, ParseError> {
     let name = read_cp_moduleinfo(bytes, ix, pool).map_err(|e| err!(e, "name"))?;
     let access_flags = ModuleAccessFlags::from_bits(read_u2(bytes, ix)?).ok_or_else(|| err!("Invalid access flags found"))?;
     let version = read_cp_utf8_opt(bytes, ix, pool).map_err(|e| err!(e, "version"))?;
     let requires_count = read_u2(bytes, ix)?;
-    let mut requires = Vec::with_capacity(requires_count.into());
+    let mut requires = Vec::with_capacity(bounded_capacity(requires_count, bytes, *ix));
     for i in 0..requires_count {
         requires.push(ModuleRequireEntry {
             name: read_cp_moduleinfo(bytes, ix, pool).map_err(|e| err!(e, "name of requires entry {}", i))?,
@@ -715,12 +1003,12 @@ fn read_module_data(bytes: &[u8], ix: &mut usize, pool: &[Rc<ConstantPoolEntry>]
         });
     }
     let exports_count = read_u2(bytes, ix)?;
-    let mut exports = Vec::with_capacity(exports_count.into());
+    let mut exports = Vec::with_capacity(bounded_capacity(exports_count, bytes, *ix));
     for i in 0..exports_count {
         let package_name = read_cp_packageinfo(bytes, ix, pool).map_err(|e| err!(e, "package name of exports entry {}", i))?;
         let flags = ModuleExportsFlags::from_bits(read_u2(bytes, ix)?).ok_or_else(|| err!(("Invalid module exports flags"), ("entry {}", i)))?;
         let exports_to_count = read_u2(bytes, ix)?;
-        let mut exports_to = Vec::with_capacity(exports_to_count.into());
+        let mut exports_to = Vec::with_capacity(bounded_capacity(exports_to_count, bytes, *ix));
         for j in 0..exports_to_count {
             exports_to.push(read_cp_moduleinfo(bytes, ix, pool).map_err(|e| err!(e, "name of exports_to entry {} of exports entry {}", j, i))?);
         }
@@ -731,12 +1019,12 @@ fn read_module_data(bytes: &[u8], ix: &mut usize, pool: &[Rc<ConstantPoolEntry>]
         });
     }
     let opens_count = read_u2(bytes, ix)?;
-    let mut opens = Vec::with_capacity(opens_count.into());
+    let mut opens = Vec::with_capacity(bounded_capacity(opens_count, bytes, *ix));
     for i in 0..opens_count {
         let package_name = read_cp_packageinfo(bytes, ix, pool).map_err(|e| err!(e, "package name of opens entry {}", i))?;
         let flags = ModuleOpensFlags::from_bits(read_u2(bytes, ix)?).ok_or_else(|| err!(("Invalid module opens flags"), ("entry {}", i)))?;
         let opens_to_count = read_u2(bytes, ix)?;
-        let mut opens_to = Vec::with_capacity(opens_to_count.into());
+        let mut opens_to = Vec::with_capacity(bounded_capacity(opens_to_count, bytes, *ix));
         for j in 0..opens_to_count {
             opens_to.push(read_cp_moduleinfo(bytes, ix, pool).map_err(|e| err!(e, "name of opens_to entry {} of opens entry {}", j, i))?);
         }
@@ -747,16 +1035,16 @@ fn read_module_data(bytes: &[u8], ix: &mut usize, pool: &[Rc<ConstantPoolEntry>]
         });
     }
     let uses_count = read_u2(bytes, ix)?;
-    let mut uses = Vec::with_capacity(uses_count.into());
+    let mut uses = Vec::with_capacity(bounded_capacity(uses_count, bytes, *ix));
     for i in 0..uses_count {
         uses.push(read_cp_classinfo(bytes, ix, pool).map_err(|e| err!(e, "name of uses entry {}", i))?);
     }
     let provides_count = read_u2(bytes, ix)?;
-    let mut provides = Vec::with_capacity(provides_count.into());
+    let mut provides = Vec::with_capacity(bounded_capacity(provides_count, bytes, *ix));
     for i in 0..provides_count {
         let service_interface_name = read_cp_classinfo(bytes, ix, pool).map_err(|e| err!(e, "service interface name of provides entry {}", i))?;
         let provides_with_count = read_u2(bytes, ix)?;
-        let mut provides_with = Vec::with_capacity(provides_with_count.into());
+        let mut provides_with = Vec::with_capacity(bounded_capacity(provides_with_count, bytes, *ix));
         for j in 0..provides_with_count {
             provides_with.push(read_cp_classinfo(bytes, ix, pool).map_err(|e| err!(e, "provides_with entry {} of provides entry {}", j, i))?);
         }
@@ -777,27 +1065,27 @@ fn read_module_data(bytes: &[u8], ix: &mut usize, pool: &[Rc<ConstantPoolEntry>]
     })
 }
 
-fn read_modulepackages_data(bytes: &[u8], ix: &mut usize, pool: &[Rc<ConstantPoolEntry>]) -> Result<Vec<String>, ParseError> {
+fn read_modulepackages_data(bytes: &[u8], ix: &mut usize, pool: &ConstantPoolReader) -> Result<Vec<String>, ParseError> {
     let count = read_u2(bytes, ix)?;
-    let mut packages = Vec::with_capacity(count.into());
+    let mut packages = Vec::with_capacity(bounded_capacity(count, bytes, *ix));
     for i in 0..count {
         packages.push(read_cp_packageinfo(bytes, ix, pool).map_err(|e| err!(e, "package name {}", i))?);
     }
     Ok(packages)
 }
 
-fn read_nestmembers_data(bytes: &[u8], ix: &mut usize, pool: &[Rc<ConstantPoolEntry>]) -> Result<Vec<String>, ParseError> {
+fn read_nestmembers_data(bytes: &[u8], ix: &mut usize, pool: &ConstantPoolReader) -> Result<Vec<String>, ParseError> {
     let count = read_u2(bytes, ix)?;
-    let mut members = Vec::with_capacity(count.into());
+    let mut members = Vec::with_capacity(bounded_capacity(count, bytes, *ix));
     for i in 0..count {
         members.push(read_cp_classinfo(bytes, ix, pool).map_err(|e| err!(e, "class name {}", i))?);
     }
     Ok(members)
 }
 
-fn read_record_data(bytes: &[u8], ix: &mut usize, pool: &[Rc<ConstantPoolEntry>], opts: &ParseOptions) -> Result<Vec<RecordComponentEntry>, ParseError> {
+fn read_record_data(bytes: &[u8], ix: &mut usize, pool: &ConstantPoolReader, opts: &ParseOptions, lazy: Option<&Rc<LazySource>>) -> Result<Vec<RecordComponentEntry>, ParseError> {
     let count = read_u2(bytes, ix)?;
-    let mut components = Vec::with_capacity(count.into());
+    let mut components = Vec::with_capacity(bounded_capacity(count, bytes, *ix));
     for i in 0..count {
         let name = read_cp_utf8(bytes, ix, pool).map_err(|e| err!(e, "name of entry {}", i))?;
         if !is_unqualified_name(&name, false, false) {
@@ -807,7 +1095,7 @@ fn read_record_data(bytes: &[u8], ix: &mut usize, pool: &[Rc<ConstantPoolEntry>]
         if !is_field_descriptor(&descriptor) {
             fail!("Invalid descriptor for entry {}", i);
         }
-        let attributes = read_attributes(bytes, ix, pool, opts).map_err(|e| err!(e, "entry {}", i))?;
+        let attributes = read_attributes(bytes, ix, pool, opts, lazy).map_err(|e| err!(e, "entry {}", i))?;
         components.push(RecordComponentEntry {
             name,
             descriptor,
@@ -817,144 +1105,133 @@ fn read_record_data(bytes: &[u8], ix: &mut usize, pool: &[Rc<ConstantPoolEntry>]
     Ok(components)
 }
 
-pub(crate) fn read_attributes(bytes: &[u8], ix: &mut usize, pool: &[Rc<ConstantPoolEntry>], opts: &ParseOptions) -> Result<Vec<AttributeInfo>, ParseError> {
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum AttributeLocation {
+    ClassFile,
+    Field,
+    Method,
+    Code,
+    RecordComponent,
+}
+
+/// Returns the locations a predefined attribute may appear in, and the first class file
+/// major version it is defined for, per Table 4.7-C of the JVM spec.
+fn attribute_placement(name: &str) -> Option<(&'static [AttributeLocation], u16)> {
+    use AttributeLocation::*;
+    let placement: (&'static [AttributeLocation], u16) = match name {
+        "ConstantValue" => (&[Field], 45),
+        "Code" => (&[Method], 45),
+        "StackMapTable" => (&[Code], 50),
+        "Exceptions" => (&[Method], 45),
+        "InnerClasses" => (&[ClassFile], 45),
+        "EnclosingMethod" => (&[ClassFile], 49),
+        "Synthetic" => (&[ClassFile, Field, Method], 45),
+        "Signature" => (&[ClassFile, Field, Method, RecordComponent], 49),
+        "SourceFile" => (&[ClassFile], 45),
+        "SourceDebugExtension" => (&[ClassFile], 49),
+        "LineNumberTable" => (&[Code], 45),
+        "LocalVariableTable" => (&[Code], 45),
+        "LocalVariableTypeTable" => (&[Code], 49),
+        "Deprecated" => (&[ClassFile, Field, Method], 45),
+        "RuntimeVisibleAnnotations" |
+        "RuntimeInvisibleAnnotations" => (&[ClassFile, Field, Method, RecordComponent], 49),
+        "RuntimeVisibleParameterAnnotations" |
+        "RuntimeInvisibleParameterAnnotations" => (&[Method], 49),
+        "RuntimeVisibleTypeAnnotations" |
+        "RuntimeInvisibleTypeAnnotations" => (&[ClassFile, Field, Method, Code, RecordComponent], 52),
+        "AnnotationDefault" => (&[Method], 49),
+        "BootstrapMethods" => (&[ClassFile], 51),
+        "MethodParameters" => (&[Method], 52),
+        "Module" |
+        "ModulePackages" |
+        "ModuleMainClass" => (&[ClassFile], 53),
+        "NestHost" |
+        "NestMembers" => (&[ClassFile], 55),
+        "Record" => (&[ClassFile], 60),
+        "PermittedSubclasses" => (&[ClassFile], 61),
+        _ => return None,
+    };
+    Some(placement)
+}
+
+/// Checks that every predefined attribute is allowed in the given location and class file
+/// version, and that attributes which may appear at most once are not repeated. Attributes
+/// nested inside Code and Record attributes are checked as well.
+pub(crate) fn validate_attribute_placement(attributes: &[AttributeInfo], location: AttributeLocation, major_version: u16) -> Result<(), ParseError> {
+    let mut seen: Vec<&str> = Vec::new();
+    for (i, attr) in attributes.iter().enumerate() {
+        let (locations, first_version) = match attribute_placement(&attr.name) {
+            Some(placement) => placement,
+            None => continue,
+        };
+        if !locations.contains(&location) {
+            fail!(("Found {} attribute in invalid location {:?}", attr.name, location), ("attribute {}", i));
+        }
+        if major_version < first_version {
+            fail!(("Found {} attribute in class file of major version {}; requires at least {}", attr.name, major_version, first_version), ("attribute {}", i));
+        }
+        let repeatable = matches!(attr.name.as_str(), "LineNumberTable" | "LocalVariableTable" | "LocalVariableTypeTable" | "Synthetic" | "Deprecated");
+        if !repeatable && seen.contains(&attr.name.as_str()) {
+            fail!(("Found more than one {} attribute", attr.name), ("attribute {}", i));
+        }
+        seen.push(&attr.name);
+        // lazily parsed attributes have their contents checked when they are decoded
+        validate_nested_placement(&attr.data, major_version).map_err(|e| err!(e, "attribute {}", i))?;
+    }
+    if seen.contains(&"NestHost") && seen.contains(&"NestMembers") {
+        fail!("Found both NestHost and NestMembers attributes");
+    }
+    Ok(())
+}
+
+fn validate_nested_placement(data: &AttributeData, major_version: u16) -> Result<(), ParseError> {
+    match data {
+        AttributeData::Code(code_data) => {
+            validate_attribute_placement(&code_data.attributes, AttributeLocation::Code, major_version).map_err(|e| err!(e, "Code attribute"))?;
+        }
+        AttributeData::Record(components) => {
+            for (j, component) in components.iter().enumerate() {
+                validate_attribute_placement(&component.attributes, AttributeLocation::RecordComponent, major_version).map_err(|e| err!(e, "entry {} of Record attribute", j))?;
+            }
+        }
+        _ => (),
+    }
+    Ok(())
+}
+
+pub(crate) fn read_attributes(bytes: &[u8], ix: &mut usize, pool: &ConstantPoolReader, opts: &ParseOptions, lazy: Option<&Rc<LazySource>>) -> Result<Vec<AttributeInfo>, ParseError> {
     let count = read_u2(bytes, ix)?;
-    let mut attributes = Vec::with_capacity(count.into());
+    let mut attributes = Vec::with_capacity(bounded_capacity(count, bytes, *ix));
     for i in 0..count {
         let name = read_cp_utf8(bytes, ix, pool).map_err(|e| err!(e, "name field of attribute {}", i))?;
         let length = read_u4(bytes, ix)? as usize;
-        let expected_end_ix = *ix + length;
-        if bytes.len() < expected_end_ix {
+        if bytes.len() - *ix < length {
             fail!("Unexpected end of stream reading attributes at index {}", *ix);
         }
-        let data = match name.deref() {
-            "ConstantValue" => {
-                ensure_length(length, 2).map_err(|e| err!(e, "ConstantValue attribute {}", i))?;
-                AttributeData::ConstantValue(read_cp_literalconstant(bytes, ix, pool).map_err(|e| err!(e, "value field of ConstantValue attribute {}", i))?)
-            }
-            "Code" => {
-                let code_data = read_code_data(bytes, ix, pool, opts).map_err(|e| err!(e, "Code attribute {}", i))?;
-                AttributeData::Code(code_data)
-            }
-            "StackMapTable" => {
-                let stackmaptable_data = read_stackmaptable_data(bytes, ix, pool).map_err(|e| err!(e, "StackMapTable attribute {}", i))?;
-                AttributeData::StackMapTable(stackmaptable_data)
-            }
-            "Exceptions" => {
-                let exceptions_data = read_exceptions_data(bytes, ix, pool).map_err(|e| err!(e, "Exceptions attribute {}", i))?;
-                AttributeData::Exceptions(exceptions_data)
-            }
-            "InnerClasses" => {
-                let innerclasses_data = read_innerclasses_data(bytes, ix, pool).map_err(|e| err!(e, "InnerClasses attribute {}", i))?;
-                AttributeData::InnerClasses(innerclasses_data)
-            }
-            "EnclosingMethod" => {
-                ensure_length(length, 4).map_err(|e| err!(e, "EnclosingMethod attribute {}", i))?;
-                let class_name = read_cp_classinfo(bytes, ix, pool).map_err(|e| err!(e, "class info of EnclosingMethod attribute {}", i))?;
-                let method = read_cp_nameandtype_opt(bytes, ix, pool).map_err(|e| err!(e, "method info of EnclosingMethod attribute {}", i))?;
-                AttributeData::EnclosingMethod { class_name, method }
-            }
-            "Synthetic" => {
-                ensure_length(length, 0).map_err(|e| err!(e, "Synthetic attribute {}", i))?;
-                AttributeData::Synthetic
-            }
-            "Signature" => {
-                ensure_length(length, 2).map_err(|e| err!(e, "Signature attribute {}", i))?;
-                // TODO: validate signature
-                AttributeData::Signature(read_cp_utf8(bytes, ix, pool).map_err(|e| err!(e, "signature field of Signature attribute {}", i))?)
-            }
-            "SourceFile" => {
-                ensure_length(length, 2).map_err(|e| err!(e, "SourceFile attribute {}", i))?;
-                AttributeData::SourceFile(read_cp_utf8(bytes, ix, pool).map_err(|e| err!(e, "signature field of SourceFile attribute {}", i))?)
-            }
-            "SourceDebugExtension" => {
-                let modified_utf8_data = &bytes[*ix .. *ix + length];
-                *ix += length;
-                let debug_str = cesu8::from_java_cesu8(modified_utf8_data).map_err(|e| err!(("{}", e), ("modified utf8 data of SourceDebugExtension attribute {}", i)))?;
-                AttributeData::SourceDebugExtension(debug_str.to_string())
-            }
-            "LineNumberTable" => {
-                let linenumber_data = read_linenumber_data(bytes, ix).map_err(|e| err!(e, "LineNumberTable attribute {}", i))?;
-                AttributeData::LineNumberTable(linenumber_data)
-            }
-            "LocalVariableTable" => {
-                let localvariable_data = read_localvariable_data(bytes, ix, pool).map_err(|e| err!(e, "LocalVariableTable attribute {}", i))?;
-                AttributeData::LocalVariableTable(localvariable_data)
-            }
-            "LocalVariableTypeTable" => {
-                let localvariabletype_data = read_localvariabletype_data(bytes, ix, pool).map_err(|e| err!(e, "LocalVariableTypeTable attribute {}", i))?;
-                AttributeData::LocalVariableTypeTable(localvariabletype_data)
-            }
-            "Deprecated" => {
-                ensure_length(length, 0).map_err(|e| err!(e, "Deprecated attribute {}", i))?;
-                AttributeData::Deprecated
-            }
-            "RuntimeVisibleAnnotations" => {
-                let annotation_data = read_annotation_data(bytes, ix, pool).map_err(|e| err!(e, "RuntimeVisibleAnnotations attribute {}", i))?;
-                AttributeData::RuntimeVisibleAnnotations(annotation_data)
-            }
-            "RuntimeInvisibleAnnotations" => {
-                let annotation_data = read_annotation_data(bytes, ix, pool).map_err(|e| err!(e, "RuntimeInvisibleAnnotations attribute {}", i))?;
-                AttributeData::RuntimeInvisibleAnnotations(annotation_data)
-            }
-            "RuntimeVisibleParameterAnnotations" => {
-                let annotation_data = read_parameter_annotation_data(bytes, ix, pool).map_err(|e| err!(e, "RuntimeVisibleParameterAnnotations attribute {}", i))?;
-                AttributeData::RuntimeVisibleParameterAnnotations(annotation_data)
-            }
-            "RuntimeInvisibleParameterAnnotations" => {
-                let annotation_data = read_parameter_annotation_data(bytes, ix, pool).map_err(|e| err!(e, "RuntimeInvisibleParameterAnnotations attribute {}", i))?;
-                AttributeData::RuntimeInvisibleParameterAnnotations(annotation_data)
-            }
-            "RuntimeVisibleTypeAnnotations" => {
-                let annotation_data = read_type_annotation_data(bytes, ix, pool).map_err(|e| err!(e, "RuntimeVisibleTypeAnnotations attribute {}", i))?;
-                AttributeData::RuntimeVisibleTypeAnnotations(annotation_data)
-            }
-            "RuntimeInvisibleTypeAnnotations" => {
-                let annotation_data = read_type_annotation_data(bytes, ix, pool).map_err(|e| err!(e, "RuntimeInvisibleTypeAnnotations attribute {}", i))?;
-                AttributeData::RuntimeInvisibleTypeAnnotations(annotation_data)
-            }
-            "AnnotationDefault" => {
-                let element_value = read_annotation_element_value(bytes, ix, pool).map_err(|e| err!(e, "AnnotationDefault attribute {}", i))?;
-                AttributeData::AnnotationDefault(element_value)
-            }
-            "BootstrapMethods" => {
-                let bootstrapmethods_data = read_bootstrapmethods_data(bytes, ix, pool).map_err(|e| err!(e, "BootstrapMethods attribute {}", i))?;
-                AttributeData::BootstrapMethods(bootstrapmethods_data)
-            }
-            "MethodParameters" => {
-                let methodparameters_data = read_methodparameters_data(bytes, ix, pool).map_err(|e| err!(e, "MethodParameters attribute {}", i))?;
-                AttributeData::MethodParameters(methodparameters_data)
-            }
-            "Module" => {
-                let module_data = read_module_data(bytes, ix, pool).map_err(|e| err!(e, "Module attribute {}", i))?;
-                AttributeData::Module(module_data)
-            }
-            "ModulePackages" => {
-                let modulepackages_data = read_modulepackages_data(bytes, ix, pool).map_err(|e| err!(e, "ModulePackages attribute {}", i))?;
-                AttributeData::ModulePackages(modulepackages_data)
-            }
-            "ModuleMainClass" => {
-                ensure_length(length, 2).map_err(|e| err!(e, "ModuleMainClass attribute {}", i))?;
-                let main_class = read_cp_classinfo(bytes, ix, pool).map_err(|e| err!(e, "ModuleMainClass attribute {}", i))?;
-                AttributeData::ModuleMainClass(main_class)
-            }
-            "NestHost" => {
-                ensure_length(length, 2).map_err(|e| err!(e, "NestHost attribute {}", i))?;
-                let host_class = read_cp_classinfo(bytes, ix, pool).map_err(|e| err!(e, "NestHost attribute {}", i))?;
-                AttributeData::NestHost(host_class)
-            }
-            "NestMembers" => {
-                let nestmembers_data = read_nestmembers_data(bytes, ix, pool).map_err(|e| err!(e, "NestMembers attribute {}", i))?;
-                AttributeData::NestMembers(nestmembers_data)
-            }
-            "Record" => {
-                let record_data = read_record_data(bytes, ix, pool, opts).map_err(|e| err!(e, "Record attribute {}", i))?;
-                AttributeData::Record(record_data)
+        let expected_end_ix = *ix + length;
+        let data = match lazy {
+            _ if !opts.attribute_filter.includes(&name) => {
+                *ix = expected_end_ix;
+                if opts.drop_filtered_attributes {
+                    continue;
+                }
+                AttributeData::Other(bytes[expected_end_ix - length .. expected_end_ix].to_vec())
             }
-            _ => {
-                *ix += length;
-                AttributeData::Other((&bytes[*ix - length .. *ix]).to_vec())
+            Some(source) => {
+                let data = AttributeData::Lazy(LazyAttribute {
+                    source: Some(LazyRange {
+                        source: source.clone(),
+                        name: name.clone(),
+                        index: i,
+                        range: *ix .. expected_end_ix,
+                        depth: pool.depth(),
+                    }),
+                    decoded: OnceCell::new(),
+                });
+                *ix = expected_end_ix;
+                data
             }
+            None => read_attribute_data(bytes, ix, pool, opts, None, &name, length, i)?,
         };
         if expected_end_ix != *ix {
             fail!("Length mismatch when reading attribute {}", i);
@@ -966,3 +1243,161 @@ pub(crate) fn read_attributes(bytes: &[u8], ix: &mut usize, pool: &[Rc<ConstantP
     }
     Ok(attributes)
 }
+
+#[allow(clippy::too_many_arguments)]
+fn read_attribute_data(bytes: &[u8], ix: &mut usize, pool: &ConstantPoolReader, opts: &ParseOptions, lazy: Option<&Rc<LazySource>>, name: &str, length: usize, i: u16) -> Result<AttributeData, ParseError> {
+    let data = match name {
+        "ConstantValue" => {
+            ensure_length(length, 2).map_err(|e| err!(e, "ConstantValue attribute {}", i))?;
+            AttributeData::ConstantValue(read_cp_literalconstant(bytes, ix, pool).map_err(|e| err!(e, "value field of ConstantValue attribute {}", i))?)
+        }
+        "Code" => {
+            let code_data = pool.nested(|| read_code_data(bytes, ix, pool, opts, lazy)).map_err(|e| err!(e, "Code attribute {}", i))?;
+            AttributeData::Code(code_data)
+        }
+        "StackMapTable" => {
+            let stackmaptable_data = read_stackmaptable_data(bytes, ix, pool).map_err(|e| err!(e, "StackMapTable attribute {}", i))?;
+            AttributeData::StackMapTable(stackmaptable_data)
+        }
+        "Exceptions" => {
+            let exceptions_data = read_exceptions_data(bytes, ix, pool).map_err(|e| err!(e, "Exceptions attribute {}", i))?;
+            AttributeData::Exceptions(exceptions_data)
+        }
+        "InnerClasses" => {
+            let innerclasses_data = read_innerclasses_data(bytes, ix, pool).map_err(|e| err!(e, "InnerClasses attribute {}", i))?;
+            AttributeData::InnerClasses(innerclasses_data)
+        }
+        "EnclosingMethod" => {
+            ensure_length(length, 4).map_err(|e| err!(e, "EnclosingMethod attribute {}", i))?;
+            let class_name = read_cp_classinfo(bytes, ix, pool).map_err(|e| err!(e, "class info of EnclosingMethod attribute {}", i))?;
+            let method = read_cp_nameandtype_opt(bytes, ix, pool).map_err(|e| err!(e, "method info of EnclosingMethod attribute {}", i))?;
+            AttributeData::EnclosingMethod { class_name, method }
+        }
+        "Synthetic" => {
+            ensure_length(length, 0).map_err(|e| err!(e, "Synthetic attribute {}", i))?;
+            AttributeData::Synthetic
+        }
+        "Signature" => {
+            ensure_length(length, 2).map_err(|e| err!(e, "Signature attribute {}", i))?;
+            // TODO: validate signature
+            AttributeData::Signature(read_cp_utf8(bytes, ix, pool).map_err(|e| err!(e, "signature field of Signature attribute {}", i))?)
+        }
+        "SourceFile" => {
+            ensure_length(length, 2).map_err(|e| err!(e, "SourceFile attribute {}", i))?;
+            AttributeData::SourceFile(read_cp_utf8(bytes, ix, pool).map_err(|e| err!(e, "signature field of SourceFile attribute {}", i))?)
+        }
+        "SourceDebugExtension" => {
+            let modified_utf8_data = &bytes[*ix .. *ix + length];
+            *ix += length;
+            let debug_str = cesu8::from_java_cesu8(modified_utf8_data).map_err(|e| err!(("{}", e), ("modified utf8 data of SourceDebugExtension attribute {}", i)))?;
+            AttributeData::SourceDebugExtension(debug_str.to_string())
+        }
+        "LineNumberTable" => {
+            let linenumber_data = read_linenumber_data(bytes, ix).map_err(|e| err!(e, "LineNumberTable attribute {}", i))?;
+            AttributeData::LineNumberTable(linenumber_data)
+        }
+        "LocalVariableTable" => {
+            let localvariable_data = read_localvariable_data(bytes, ix, pool).map_err(|e| err!(e, "LocalVariableTable attribute {}", i))?;
+            AttributeData::LocalVariableTable(localvariable_data)
+        }
+        "LocalVariableTypeTable" => {
+            let localvariabletype_data = read_localvariabletype_data(bytes, ix, pool).map_err(|e| err!(e, "LocalVariableTypeTable attribute {}", i))?;
+            AttributeData::LocalVariableTypeTable(localvariabletype_data)
+        }
+        "Deprecated" => {
+            ensure_length(length, 0).map_err(|e| err!(e, "Deprecated attribute {}", i))?;
+            AttributeData::Deprecated
+        }
+        "RuntimeVisibleAnnotations" => {
+            let annotation_data = read_annotation_data(bytes, ix, pool).map_err(|e| err!(e, "RuntimeVisibleAnnotations attribute {}", i))?;
+            AttributeData::RuntimeVisibleAnnotations(annotation_data)
+        }
+        "RuntimeInvisibleAnnotations" => {
+            let annotation_data = read_annotation_data(bytes, ix, pool).map_err(|e| err!(e, "RuntimeInvisibleAnnotations attribute {}", i))?;
+            AttributeData::RuntimeInvisibleAnnotations(annotation_data)
+        }
+        "RuntimeVisibleParameterAnnotations" => {
+            let annotation_data = read_parameter_annotation_data(bytes, ix, pool).map_err(|e| err!(e, "RuntimeVisibleParameterAnnotations attribute {}", i))?;
+            AttributeData::RuntimeVisibleParameterAnnotations(annotation_data)
+        }
+        "RuntimeInvisibleParameterAnnotations" => {
+            let annotation_data = read_parameter_annotation_data(bytes, ix, pool).map_err(|e| err!(e, "RuntimeInvisibleParameterAnnotations attribute {}", i))?;
+            AttributeData::RuntimeInvisibleParameterAnnotations(annotation_data)
+        }
+        "RuntimeVisibleTypeAnnotations" => {
+            let annotation_data = read_type_annotation_data(bytes, ix, pool).map_err(|e| err!(e, "RuntimeVisibleTypeAnnotations attribute {}", i))?;
+            AttributeData::RuntimeVisibleTypeAnnotations(annotation_data)
+        }
+        "RuntimeInvisibleTypeAnnotations" => {
+            let annotation_data = read_type_annotation_data(bytes, ix, pool).map_err(|e| err!(e, "RuntimeInvisibleTypeAnnotations attribute {}", i))?;
+            AttributeData::RuntimeInvisibleTypeAnnotations(annotation_data)
+        }
+        "AnnotationDefault" => {
+            let element_value = read_annotation_element_value(bytes, ix, pool).map_err(|e| err!(e, "AnnotationDefault attribute {}", i))?;
+            AttributeData::AnnotationDefault(element_value)
+        }
+        "BootstrapMethods" => {
+            let bootstrapmethods_data = read_bootstrapmethods_data(bytes, ix, pool).map_err(|e| err!(e, "BootstrapMethods attribute {}", i))?;
+            AttributeData::BootstrapMethods(bootstrapmethods_data)
+        }
+        "MethodParameters" => {
+            let methodparameters_data = read_methodparameters_data(bytes, ix, pool).map_err(|e| err!(e, "MethodParameters attribute {}", i))?;
+            AttributeData::MethodParameters(methodparameters_data)
+        }
+        "Module" => {
+            let module_data = read_module_data(bytes, ix, pool).map_err(|e| err!(e, "Module attribute {}", i))?;
+            AttributeData::Module(module_data)
+        }
+        "ModulePackages" => {
+            let modulepackages_data = read_modulepackages_data(bytes, ix, pool).map_err(|e| err!(e, "ModulePackages attribute {}", i))?;
+            AttributeData::ModulePackages(modulepackages_data)
+        }
+        "ModuleMainClass" => {
+            ensure_length(length, 2).map_err(|e| err!(e, "ModuleMainClass attribute {}", i))?;
+            let main_class = read_cp_classinfo(bytes, ix, pool).map_err(|e| err!(e, "ModuleMainClass attribute {}", i))?;
+            AttributeData::ModuleMainClass(main_class)
+        }
+        "NestHost" => {
+            ensure_length(length, 2).map_err(|e| err!(e, "NestHost attribute {}", i))?;
+            let host_class = read_cp_classinfo(bytes, ix, pool).map_err(|e| err!(e, "NestHost attribute {}", i))?;
+            AttributeData::NestHost(host_class)
+        }
+        "NestMembers" => {
+            let nestmembers_data = read_nestmembers_data(bytes, ix, pool).map_err(|e| err!(e, "NestMembers attribute {}", i))?;
+            AttributeData::NestMembers(nestmembers_data)
+        }
+        "Record" => {
+            let record_data = pool.nested(|| read_record_data(bytes, ix, pool, opts, lazy)).map_err(|e| err!(e, "Record attribute {}", i))?;
+            AttributeData::Record(record_data)
+        }
+        _ => {
+            *ix += length;
+            AttributeData::Other((&bytes[*ix - length .. *ix]).to_vec())
+        }
+    };
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn attr(name: &str) -> AttributeInfo {
+        AttributeInfo {
+            name: name.to_string(),
+            data: AttributeData::Other(Vec::new()),
+        }
+    }
+
+    #[test]
+    fn test_attribute_placement() {
+        let class_attrs = [attr("SourceFile"), attr("Deprecated"), attr("Deprecated"), attr("CustomAttribute")];
+        assert!(validate_attribute_placement(&class_attrs, AttributeLocation::ClassFile, 45).is_ok());
+        assert!(validate_attribute_placement(&class_attrs, AttributeLocation::Field, 45).is_err());
+        assert!(validate_attribute_placement(&[attr("SourceFile"), attr("SourceFile")], AttributeLocation::ClassFile, 52).is_err());
+        assert!(validate_attribute_placement(&[attr("NestHost")], AttributeLocation::ClassFile, 54).is_err());
+        assert!(validate_attribute_placement(&[attr("NestHost")], AttributeLocation::ClassFile, 55).is_ok());
+        assert!(validate_attribute_placement(&[attr("NestHost"), attr("NestMembers")], AttributeLocation::ClassFile, 55).is_err());
+        assert!(validate_attribute_placement(&[attr("LineNumberTable"), attr("LineNumberTable")], AttributeLocation::Code, 50).is_ok());
+    }
+}
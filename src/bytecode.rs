@@ -1,20 +1,22 @@
 use std::borrow::Cow;
 use std::convert::TryFrom;
-use std::rc::Rc;
 
-use crate::{read_u1, read_u2, read_u4, ParseError};
+use crate::{bounded_capacity, read_u1, read_u2, read_u4, ParseError};
 use crate::constant_pool::{get_cp_loadable, read_cp_classinfo, read_cp_invokedynamic, read_cp_memberref};
-use crate::constant_pool::{ConstantPoolEntry, ConstantPoolEntryTypes, InvokeDynamic, Loadable, MemberRef};
+use crate::constant_pool::{ConstantPoolEntryTypes, ConstantPoolReader, InvokeDynamic, LiteralConstant, Loadable, MemberRef};
+use crate::names::split_method_descriptor;
 
 pub type JumpOffset = i32;
 
-#[derive(Debug)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct LookupTable {
     pub default: JumpOffset,
     pub match_offsets: Vec<(i32, JumpOffset)>,
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct RangeTable {
     pub default: JumpOffset,
     pub low: i32,
@@ -22,7 +24,8 @@ pub struct RangeTable {
     pub jumps: Vec<JumpOffset>,
 }
 
-#[derive(Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum PrimitiveArrayType {
     Boolean,
     Char,
@@ -34,7 +37,8 @@ pub enum PrimitiveArrayType {
     Long,
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Opcode {
     Aaload,
     Aastore,
@@ -164,9 +168,9 @@ pub enum Opcode {
     Lcmp,
     Lconst0,
     Lconst1,
-    Ldc(Loadable), // This doesn't validate the Loadable is not Long/Double types
-    LdcW(Loadable), // This doesn't validate the Loadable is not Long/Double types
-    Ldc2W(Loadable), // This doesn't validate the Loadable is only Long/Double types
+    Ldc(Loadable), // The Loadable is only validated to not be Long/Double types when checking static constraints
+    LdcW(Loadable), // The Loadable is only validated to not be Long/Double types when checking static constraints
+    Ldc2W(Loadable), // The Loadable is only validated to be Long/Double types when checking static constraints
     Ldiv,
     Lload(u16), // both wide and narrow
     Lmul,
@@ -200,7 +204,8 @@ pub enum Opcode {
     Tableswitch(RangeTable),
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ByteCode {
     /// This contains pairs of (offset, opcode) where offset is the offset of the start
     /// of the opcode in bytes from the beginning of the data section of the Code attribute.
@@ -210,7 +215,7 @@ pub struct ByteCode {
 }
 
 impl ByteCode {
-    pub(crate) fn from(code: &[u8], pool: &[Rc<ConstantPoolEntry>]) -> Result<Self, ParseError> {
+    pub(crate) fn from(code: &[u8], pool: &ConstantPoolReader) -> Result<Self, ParseError> {
         let bytecode = Self {
             opcodes: read_opcodes(code, pool)?
         };
@@ -242,7 +247,11 @@ impl ByteCode {
     }
 
     fn validate_jump(&self, source_offset: i32, jump: JumpOffset) -> Result<(), ParseError> {
-        let target_offset = usize::try_from(source_offset + jump).map_err(|_| err!("Invalid destination after applying jump"))?;
+        let target_offset = source_offset.checked_add(jump).and_then(|target| usize::try_from(target).ok());
+        let target_offset = match target_offset {
+            Some(target_offset) => target_offset,
+            None => fail!("Invalid destination after applying jump"),
+        };
         if self.get_opcode_index(target_offset).is_none() {
             fail!("Invalid opcode offset after applying jump");
         }
@@ -293,9 +302,98 @@ impl ByteCode {
         }
         Ok(())
     }
+
+    fn validate_local(index: u16, wide: bool, max_locals: u16) -> Result<(), ParseError> {
+        let last = u32::from(index) + if wide { 1 } else { 0 };
+        if last >= u32::from(max_locals) {
+            fail!("Local variable index {} out of range for max_locals {}", index, max_locals);
+        }
+        Ok(())
+    }
+
+    fn validate_opcode_constraints(opcode: &Opcode, max_locals: u16) -> Result<(), ParseError> {
+        match opcode {
+            Opcode::Iload(n) | Opcode::Fload(n) | Opcode::Aload(n) |
+            Opcode::Istore(n) | Opcode::Fstore(n) | Opcode::Astore(n) |
+            Opcode::Iinc(n, _) | Opcode::Ret(n) => Self::validate_local(*n, false, max_locals)?,
+            Opcode::Lload(n) | Opcode::Dload(n) |
+            Opcode::Lstore(n) | Opcode::Dstore(n) => Self::validate_local(*n, true, max_locals)?,
+            Opcode::Ldc(loadable) | Opcode::LdcW(loadable) if is_wide_loadable(loadable) => {
+                fail!("Long or double constant loaded by ldc or ldc_w");
+            }
+            Opcode::Ldc2W(loadable) if !is_wide_loadable(loadable) => {
+                fail!("Constant other than long or double loaded by ldc2_w");
+            }
+            Opcode::Invokespecial(member) => {
+                let name = &member.name_and_type.name;
+                if name.starts_with('<') && name != "<init>" {
+                    fail!("Invalid method name {} for invokespecial", name);
+                }
+                if name == "<init>" && !member.name_and_type.descriptor.ends_with(")V") {
+                    fail!("Non-void descriptor for instance initialization method");
+                }
+            }
+            Opcode::Invokevirtual(member) | Opcode::Invokestatic(member) if member.name_and_type.name.starts_with('<') => {
+                fail!("Invalid method name {}", member.name_and_type.name);
+            }
+            Opcode::Invokeinterface(member, count) => {
+                if member.name_and_type.name.starts_with('<') {
+                    fail!("Invalid method name {} for invokeinterface", member.name_and_type.name);
+                }
+                let params = match split_method_descriptor(&member.name_and_type.descriptor) {
+                    Some((params, _)) => params,
+                    None => fail!("Invalid method descriptor for invokeinterface"),
+                };
+                let arg_size = params.iter().map(|p| if *p == "J" || *p == "D" { 2 } else { 1 }).sum::<usize>() + 1;
+                if usize::from(*count) != arg_size {
+                    fail!("Count {} for invokeinterface does not match argument size {}", count, arg_size);
+                }
+            }
+            Opcode::Invokedynamic(indy) if indy.name_and_type.name.starts_with('<') => {
+                fail!("Invalid method name {} for invokedynamic", indy.name_and_type.name);
+            }
+            Opcode::New(class_name) if class_name.starts_with('[') => {
+                fail!("Array type {} used by new", class_name);
+            }
+            Opcode::Anewarray(class_name) if class_name.bytes().take_while(|b| *b == b'[').count() >= 255 => {
+                fail!("Array type created by anewarray has more than 255 dimensions");
+            }
+            Opcode::Multianewarray(class_name, dimensions) => {
+                if *dimensions == 0 {
+                    fail!("Zero dimensions for multianewarray");
+                }
+                if class_name.bytes().take_while(|b| *b == b'[').count() < usize::from(*dimensions) {
+                    fail!("Array type {} has fewer than {} dimensions for multianewarray", class_name, dimensions);
+                }
+            }
+            Opcode::Lookupswitch(table) if table.match_offsets.windows(2).any(|pair| pair[0].0 >= pair[1].0) => {
+                fail!("Match values of lookupswitch are not sorted in increasing order");
+            }
+            _ => (),
+        };
+        Ok(())
+    }
+
+    /// Checks the static constraints from section 4.9.1 of the JVM spec that apply to
+    /// individual instructions and were not already enforced while parsing.
+    pub(crate) fn validate_static_constraints(&self, max_locals: u16) -> Result<(), ParseError> {
+        for (offset, opcode) in &self.opcodes {
+            Self::validate_opcode_constraints(opcode, max_locals).map_err(|e| err!(e, "opcode at offset {}", offset))?;
+        }
+        Ok(())
+    }
+}
+
+fn is_wide_loadable(loadable: &Loadable) -> bool {
+    match loadable {
+        Loadable::LiteralConstant(LiteralConstant::Long(_)) |
+        Loadable::LiteralConstant(LiteralConstant::Double(_)) => true,
+        Loadable::Dynamic(dynamic) => dynamic.name_and_type.descriptor == "J" || dynamic.name_and_type.descriptor == "D",
+        _ => false,
+    }
 }
 
-fn read_opcodes(code: &[u8], pool: &[Rc<ConstantPoolEntry>]) -> Result<Vec<(usize, Opcode)>, ParseError> {
+fn read_opcodes(code: &[u8], pool: &ConstantPoolReader) -> Result<Vec<(usize, Opcode)>, ParseError> {
     let mut opcodes = Vec::new();
     let mut ix = 0;
     while ix < code.len() {
@@ -480,11 +578,11 @@ fn read_opcodes(code: &[u8], pool: &[Rc<ConstantPoolEntry>]) -> Result<Vec<(usiz
                 if low > high {
                     fail!("The low value must be less than or equal to the high value in tableswitch at index {}", ix - 4);
                 }
-                let jump_count = match usize::try_from(high - low + 1) {
+                let jump_count = match usize::try_from(i64::from(high) - i64::from(low) + 1) {
                     Ok(n) => n,
                     _ => fail!("Unable to convert range to usize in tableswitch at index {}", ix - 4),
                 };
-                let mut jumps = Vec::with_capacity(jump_count);
+                let mut jumps = Vec::with_capacity(bounded_capacity(jump_count, code, ix));
                 for _ in 0..jump_count {
                     jumps.push(read_u4(code, &mut ix)? as JumpOffset);
                 }
@@ -507,7 +605,7 @@ fn read_opcodes(code: &[u8], pool: &[Rc<ConstantPoolEntry>]) -> Result<Vec<(usiz
                     Ok(n) => n,
                     _ => fail!("Unable to convert number of pairs in lookupswitch to usize at index {}", ix - 4),
                 };
-                let mut match_offsets = Vec::with_capacity(pair_count);
+                let mut match_offsets = Vec::with_capacity(bounded_capacity(pair_count, code, ix));
                 for _ in 0..pair_count {
                     let match_part = read_u4(code, &mut ix)? as i32;
                     let offset_part = read_u4(code, &mut ix)? as JumpOffset;
@@ -648,4 +746,70 @@ mod tests {
         assert_eq!(bytecode.get_opcode_index(4), Some(2));
         assert_eq!(bytecode.get_opcode_index(5), None);
     }
+
+    #[test]
+    fn test_static_constraints() {
+        let bytecode = ByteCode {
+            opcodes: vec![
+                (0, Opcode::Lload(1)),
+                (1, Opcode::Ldc2W(Loadable::LiteralConstant(LiteralConstant::Long(5)))),
+                (4, Opcode::Multianewarray("[[I".to_string(), 2)),
+            ],
+        };
+        assert!(bytecode.validate_static_constraints(3).is_ok());
+        assert!(bytecode.validate_static_constraints(2).is_err());
+
+        let bytecode = ByteCode {
+            opcodes: vec![
+                (0, Opcode::Ldc(Loadable::LiteralConstant(LiteralConstant::Double(1.0)))),
+            ],
+        };
+        assert!(bytecode.validate_static_constraints(0).is_err());
+
+        let bytecode = ByteCode {
+            opcodes: vec![
+                (0, Opcode::New("[I".to_string())),
+            ],
+        };
+        assert!(bytecode.validate_static_constraints(0).is_err());
+
+        let bytecode = ByteCode {
+            opcodes: vec![
+                (0, Opcode::Multianewarray("[I".to_string(), 2)),
+            ],
+        };
+        assert!(bytecode.validate_static_constraints(0).is_err());
+    }
+
+    #[test]
+    fn test_exception_table() {
+        use crate::attributes::{validate_exception_table, ExceptionTableEntry};
+
+        let bytecode = ByteCode {
+            opcodes: vec![
+                (0, Opcode::Lload(1)),
+                (1, Opcode::Ldc2W(Loadable::LiteralConstant(LiteralConstant::Long(5)))),
+                (4, Opcode::Pop2),
+                (5, Opcode::Return),
+            ],
+        };
+        let check = |start_pc, end_pc, handler_pc| {
+            let entry = ExceptionTableEntry { start_pc, end_pc, handler_pc, catch_type: None };
+            validate_exception_table(&[entry], &bytecode, 6).map_err(|e| e.to_string())
+        };
+        assert!(check(0, 4, 5).is_ok());
+        assert!(check(1, 6, 0).is_ok());
+        for (start_pc, end_pc, handler_pc, message) in [
+            (4, 4, 5, "Start pc 4 is not less than end pc 4"),
+            (5, 1, 0, "Start pc 5 is not less than end pc 1"),
+            (2, 4, 5, "Start pc 2 is not on an instruction boundary"),
+            (0, 3, 5, "End pc 3 is not on an instruction boundary"),
+            (0, 7, 5, "End pc 7 is not on an instruction boundary"),
+            (0, 4, 2, "Handler pc 2 is not on an instruction boundary"),
+            (0, 4, 6, "Handler pc 6 is not on an instruction boundary"),
+        ] {
+            let error = check(start_pc, end_pc, handler_pc).unwrap_err();
+            assert!(error.contains(message), "{}", error);
+        }
+    }
 }
@@ -0,0 +1,617 @@
+//! Call graphs over the classes in a [`ClassRepository`], for finding the methods that can be
+//! reached from a program's entry points, and so the methods that are dead code.
+//!
+//! Calls are found in the bytecode of reachable methods. Calls with `invokestatic` and
+//! `invokespecial` have a single target, found by resolution. The targets of `invokevirtual`
+//! and `invokeinterface` depend on the class of the receiver, and are found by selecting the
+//! method that runs for each class that the receiver could be an instance of. Lambdas and method
+//! references created by `invokedynamic` through `LambdaMetafactory` are treated as calls to the
+//! method that implements them, from the method that creates them. Using a class for the first
+//! time also reaches its static initializer.
+//!
+//! Only the classes given as the program's classes are considered as receivers, so callbacks
+//! from classes outside of them, such as the JDK calling `Runnable.run`, are not seen. Calls
+//! through reflection and method handles are not seen either.
+
+use std::collections::{BTreeSet, HashSet};
+use std::fmt;
+
+use crate::attributes::{AttributeData, AttributeInfo};
+use crate::bytecode::Opcode;
+use crate::classpath::ClassRepository;
+use crate::constant_pool::{BootstrapArgument, InvokeDynamic, MemberKind, MemberRef, ReferenceKind};
+use crate::dispatch::{select_method, Selection};
+use crate::resolution::{resolve_field, resolve_interface_method, resolve_method, ResolvedMethod};
+use crate::{ClassAccessFlags, ClassFile, MethodAccessFlags, ParseError};
+
+const LAMBDA_METAFACTORY: &str = "java/lang/invoke/LambdaMetafactory";
+
+/// A method, named by the class that declares it.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct MethodId {
+    pub class_name: String,
+    pub name: String,
+    pub descriptor: String,
+}
+
+impl MethodId {
+    pub fn new(class_name: &str, name: &str, descriptor: &str) -> Self {
+        MethodId {
+            class_name: class_name.to_string(),
+            name: name.to_string(),
+            descriptor: descriptor.to_string(),
+        }
+    }
+
+    fn of(method: &ResolvedMethod) -> Self {
+        MethodId::new(&method.class.this_class, &method.method().name, &method.method().descriptor)
+    }
+
+    fn of_member(member: &MemberRef) -> Self {
+        MethodId::new(&member.class_name, &member.name_and_type.name, &member.name_and_type.descriptor)
+    }
+}
+
+impl fmt::Display for MethodId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}.{}{}", self.class_name, self.name, self.descriptor)
+    }
+}
+
+/// How the caller reaches the callee.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum CallKind {
+    Static,
+    Special,
+    Virtual,
+    Interface,
+    /// The caller creates a lambda or method reference that the callee implements.
+    Lambda,
+    /// The caller uses a class for the first time, which runs its static initializer.
+    Initialization,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct CallEdge {
+    pub caller: MethodId,
+    /// The offset of the instruction that makes the call in the bytecode of the caller.
+    pub offset: usize,
+    pub kind: CallKind,
+    pub callee: MethodId,
+}
+
+/// A call whose target could not be resolved, usually because the class it names is not on
+/// the class path.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct UnresolvedCall {
+    pub caller: MethodId,
+    pub offset: usize,
+    /// The method as named by the instruction.
+    pub target: MethodId,
+}
+
+/// How the targets of virtual and interface calls are found.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum CallGraphAlgorithm {
+    /// Class hierarchy analysis: a call can reach the method selected for any of the program's
+    /// classes that is a subtype of the type named by the call and is not abstract.
+    ClassHierarchy,
+    /// Rapid type analysis: like class hierarchy analysis, but only classes that a reachable
+    /// method instantiates are considered. This finds fewer spurious targets.
+    RapidType,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct CallGraph {
+    pub edges: Vec<CallEdge>,
+    /// The methods reachable from the entry points, including the entry points themselves.
+    pub reachable: BTreeSet<MethodId>,
+    /// The classes that reachable methods instantiate. Only computed by rapid type analysis.
+    pub instantiated: BTreeSet<String>,
+    pub unresolved: Vec<UnresolvedCall>,
+}
+
+impl CallGraph {
+    pub fn is_reachable(&self, method: &MethodId) -> bool {
+        self.reachable.contains(method)
+    }
+
+    /// Returns the calls made by a method.
+    pub fn callees<'a>(&'a self, method: &'a MethodId) -> impl Iterator<Item = &'a CallEdge> + 'a {
+        self.edges.iter().filter(move |edge| edge.caller == *method)
+    }
+
+    /// Returns the calls made to a method.
+    pub fn callers<'a>(&'a self, method: &'a MethodId) -> impl Iterator<Item = &'a CallEdge> + 'a {
+        self.edges.iter().filter(move |edge| edge.callee == *method)
+    }
+
+    /// Returns the methods with bytecode declared by the given classes that are not reachable,
+    /// in the order they are declared.
+    pub fn unreachable_methods(&self, repo: &ClassRepository, classes: &[String]) -> Result<Vec<MethodId>, ParseError> {
+        let mut result = Vec::new();
+        for class_name in classes {
+            let class = repo.load(class_name)?;
+            for method in &class.methods {
+                let id = MethodId::new(class_name, &method.name, &method.descriptor);
+                if has_code(method.access_flags) && !self.reachable.contains(&id) {
+                    result.push(id);
+                }
+            }
+        }
+        Ok(result)
+    }
+}
+
+fn has_code(flags: MethodAccessFlags) -> bool {
+    !flags.intersects(MethodAccessFlags::ABSTRACT | MethodAccessFlags::NATIVE)
+}
+
+// A virtual or interface call, kept by rapid type analysis to add targets as classes are
+// instantiated.
+struct VirtualCall {
+    caller: MethodId,
+    offset: usize,
+    kind: CallKind,
+    receiver_type: String,
+    resolved: ResolvedMethod,
+}
+
+struct Builder<'a> {
+    repo: &'a ClassRepository,
+    algorithm: CallGraphAlgorithm,
+    // The program's classes that can be instantiated
+    concrete_classes: Vec<String>,
+    graph: CallGraph,
+    worklist: Vec<MethodId>,
+    virtual_calls: Vec<VirtualCall>,
+    edges: HashSet<(MethodId, usize, MethodId)>,
+    initialized: HashSet<String>,
+}
+
+/// Builds the call graph of a program, given the names of its classes and the methods where it
+/// starts, such as its `main` method. Instance methods given as entry points are assumed to be
+/// called on instances of the class that declares them. Fails if an entry point cannot be
+/// found, or a class cannot be parsed.
+pub fn call_graph(
+    repo: &ClassRepository,
+    classes: &[String],
+    entry_points: &[MethodId],
+    algorithm: CallGraphAlgorithm,
+) -> Result<CallGraph, ParseError> {
+    let mut concrete_classes = Vec::new();
+    for class_name in classes {
+        let flags = repo.load(class_name)?.access_flags;
+        if !flags.intersects(ClassAccessFlags::INTERFACE | ClassAccessFlags::ABSTRACT) {
+            concrete_classes.push(class_name.clone());
+        }
+    }
+    let mut builder = Builder {
+        repo,
+        algorithm,
+        concrete_classes,
+        graph: CallGraph::default(),
+        worklist: Vec::new(),
+        virtual_calls: Vec::new(),
+        edges: HashSet::new(),
+        initialized: HashSet::new(),
+    };
+    for entry_point in entry_points {
+        let class = repo.load(&entry_point.class_name)?;
+        let method = class
+            .methods
+            .iter()
+            .find(|m| m.name == entry_point.name && m.descriptor == entry_point.descriptor)
+            .ok_or_else(|| err!("Entry point {} not found", entry_point))?;
+        if !method.access_flags.contains(MethodAccessFlags::STATIC) {
+            builder.instantiate(&entry_point.class_name);
+        }
+        builder.initialize(None, &entry_point.class_name);
+        builder.reach(entry_point.clone());
+    }
+    while let Some(method) = builder.worklist.pop() {
+        builder.scan(&method).map_err(|e| err!(e, "method {}", method))?;
+    }
+    Ok(builder.graph)
+}
+
+impl<'a> Builder<'a> {
+    fn reach(&mut self, method: MethodId) {
+        if self.graph.reachable.insert(method.clone()) {
+            self.worklist.push(method);
+        }
+    }
+
+    fn add_edge(&mut self, caller: &MethodId, offset: usize, kind: CallKind, callee: MethodId) {
+        if self.edges.insert((caller.clone(), offset, callee.clone())) {
+            self.graph.edges.push(CallEdge {
+                caller: caller.clone(),
+                offset,
+                kind,
+                callee: callee.clone(),
+            });
+        }
+        self.reach(callee);
+    }
+
+    fn unresolved(&mut self, caller: &MethodId, offset: usize, member: &MemberRef) {
+        self.graph.unresolved.push(UnresolvedCall {
+            caller: caller.clone(),
+            offset,
+            target: MethodId::of_member(member),
+        });
+    }
+
+    // Reaches the static initializers of a class and its superclasses, the first time the class
+    // is used. Classes that are not on the class path are skipped.
+    fn initialize(&mut self, caller: Option<(&MethodId, usize)>, class_name: &str) {
+        let mut current = Some(class_name.to_string());
+        while let Some(name) = current {
+            if !self.initialized.insert(name.clone()) {
+                break;
+            }
+            let class = match self.repo.load(&name) {
+                Ok(class) => class,
+                Err(_) => break,
+            };
+            if class.methods.iter().any(|m| m.name == "<clinit>") {
+                let clinit = MethodId::new(&name, "<clinit>", "()V");
+                match caller {
+                    Some((caller, offset)) => self.add_edge(caller, offset, CallKind::Initialization, clinit),
+                    None => self.reach(clinit),
+                }
+            }
+            current = class.super_class.clone().filter(|_| !class.access_flags.contains(ClassAccessFlags::INTERFACE));
+        }
+    }
+
+    fn instantiate(&mut self, class_name: &str) {
+        if self.algorithm != CallGraphAlgorithm::RapidType || !self.graph.instantiated.insert(class_name.to_string()) {
+            return;
+        }
+        for i in 0..self.virtual_calls.len() {
+            self.dispatch(i, class_name);
+        }
+    }
+
+    fn is_subtype(&self, sub: &str, sup: &str) -> bool {
+        self.repo.is_subtype_of(sub, sup).unwrap_or(false)
+    }
+
+    // Adds the target of a virtual call for a receiver of the given class.
+    fn dispatch(&mut self, call: usize, class_name: &str) {
+        let call = &self.virtual_calls[call];
+        if !self.is_subtype(class_name, &call.receiver_type) {
+            return;
+        }
+        let target = match select_method(self.repo, class_name, &call.resolved) {
+            Ok(Selection::Method(method)) if has_code(method.method().access_flags) => MethodId::of(&method),
+            _ => return,
+        };
+        let (caller, offset, kind) = (call.caller.clone(), call.offset, call.kind);
+        self.add_edge(&caller, offset, kind, target);
+    }
+
+    fn virtual_call(&mut self, caller: &MethodId, offset: usize, kind: CallKind, receiver_type: &str, resolved: ResolvedMethod) {
+        // Private methods and methods of arrays have a single target
+        let flags = resolved.method().access_flags;
+        if flags.contains(MethodAccessFlags::PRIVATE) || receiver_type.starts_with('[') {
+            if has_code(flags) {
+                self.add_edge(caller, offset, kind, MethodId::of(&resolved));
+            }
+            return;
+        }
+        self.virtual_calls.push(VirtualCall {
+            caller: caller.clone(),
+            offset,
+            kind,
+            receiver_type: receiver_type.to_string(),
+            resolved,
+        });
+        let call = self.virtual_calls.len() - 1;
+        let classes = match self.algorithm {
+            CallGraphAlgorithm::ClassHierarchy => self.concrete_classes.clone(),
+            CallGraphAlgorithm::RapidType => self.graph.instantiated.iter().cloned().collect(),
+        };
+        for class_name in classes {
+            self.dispatch(call, &class_name);
+        }
+        if self.algorithm == CallGraphAlgorithm::ClassHierarchy {
+            self.virtual_calls.pop();
+        }
+    }
+
+    fn scan(&mut self, method_id: &MethodId) -> Result<(), ParseError> {
+        let class = self.repo.load(&method_id.class_name)?;
+        let method = match class.methods.iter().find(|m| m.name == method_id.name && m.descriptor == method_id.descriptor) {
+            Some(method) => method,
+            None => fail!("Method not found"),
+        };
+        let code = method.attributes.iter().find(|attr| attr.name == "Code").map(AttributeInfo::decoded).transpose()?;
+        let code = match code {
+            Some(AttributeData::Code(code)) => code,
+            _ => return Ok(()),
+        };
+        let bytecode = match &code.bytecode {
+            Some(bytecode) => bytecode,
+            None => fail!("Bytecode was not parsed"),
+        };
+        for (offset, opcode) in &bytecode.opcodes {
+            let offset = *offset;
+            match opcode {
+                Opcode::Invokestatic(member) | Opcode::Invokespecial(member) => {
+                    let resolved = if self.repo.is_interface(&member.class_name).unwrap_or(false) {
+                        resolve_interface_method(self.repo, member)
+                    } else {
+                        resolve_method(self.repo, member)
+                    };
+                    let resolved = match resolved {
+                        Ok(resolved) => resolved,
+                        Err(_) => {
+                            self.unresolved(method_id, offset, member);
+                            continue;
+                        }
+                    };
+                    let kind = if let Opcode::Invokestatic(_) = opcode {
+                        self.initialize(Some((method_id, offset)), &resolved.class.this_class);
+                        CallKind::Static
+                    } else {
+                        CallKind::Special
+                    };
+                    if has_code(resolved.method().access_flags) {
+                        self.add_edge(method_id, offset, kind, MethodId::of(&resolved));
+                    }
+                }
+                Opcode::Invokevirtual(member) | Opcode::Invokeinterface(member, _) => {
+                    let (kind, resolved) = match opcode {
+                        Opcode::Invokevirtual(_) => (CallKind::Virtual, resolve_method(self.repo, member)),
+                        _ => (CallKind::Interface, resolve_interface_method(self.repo, member)),
+                    };
+                    match resolved {
+                        Ok(resolved) => self.virtual_call(method_id, offset, kind, &member.class_name, resolved),
+                        Err(_) => self.unresolved(method_id, offset, member),
+                    }
+                }
+                Opcode::Invokedynamic(dynamic) => self.lambda(&class, method_id, offset, dynamic)?,
+                Opcode::New(class_name) => {
+                    self.initialize(Some((method_id, offset)), class_name);
+                    self.instantiate(class_name);
+                }
+                Opcode::Getstatic(member) | Opcode::Putstatic(member) => {
+                    if let Ok(field) = resolve_field(self.repo, member) {
+                        let class_name = field.class.this_class.clone();
+                        self.initialize(Some((method_id, offset)), &class_name);
+                    }
+                }
+                _ => (),
+            }
+        }
+        Ok(())
+    }
+
+    // Adds the method that implements a lambda or method reference created by LambdaMetafactory.
+    fn lambda(&mut self, class: &ClassFile, caller: &MethodId, offset: usize, dynamic: &InvokeDynamic) -> Result<(), ParseError> {
+        let attr = class.attributes.iter().find(|attr| attr.name == "BootstrapMethods").map(AttributeInfo::decoded).transpose()?;
+        let entry = match attr {
+            Some(AttributeData::BootstrapMethods(entries)) => match entries.get(dynamic.attr_index as usize) {
+                Some(entry) => entry,
+                None => fail!("Bootstrap method {} not found", dynamic.attr_index),
+            },
+            _ => fail!("No BootstrapMethods attribute found"),
+        };
+        if entry.method.class_name != LAMBDA_METAFACTORY {
+            return Ok(());
+        }
+        let handle = match entry.arguments.get(1) {
+            Some(BootstrapArgument::MethodHandle(handle)) => handle,
+            _ => return Ok(()),
+        };
+        let member = MemberRef {
+            class_name: handle.class_name.clone(),
+            name_and_type: handle.member_ref.clone(),
+        };
+        let resolved = match handle.member_kind {
+            MemberKind::InterfaceMethod => resolve_interface_method(self.repo, &member),
+            _ => resolve_method(self.repo, &member),
+        };
+        let resolved = match resolved {
+            Ok(resolved) => resolved,
+            Err(_) => {
+                self.unresolved(caller, offset, &member);
+                return Ok(());
+            }
+        };
+        match handle.kind {
+            ReferenceKind::InvokeVirtual | ReferenceKind::InvokeInterface => {
+                self.virtual_call(caller, offset, CallKind::Lambda, &member.class_name, resolved)
+            }
+            kind => {
+                if kind == ReferenceKind::NewInvokeSpecial {
+                    self.initialize(Some((caller, offset)), &member.class_name);
+                    self.instantiate(&member.class_name);
+                } else if kind == ReferenceKind::InvokeStatic {
+                    self.initialize(Some((caller, offset)), &resolved.class.this_class);
+                }
+                if has_code(resolved.method().access_flags) {
+                    self.add_edge(caller, offset, CallKind::Lambda, MethodId::of(&resolved));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembly::assemble;
+    use crate::classpath::ClassPath;
+    use std::collections::HashMap;
+
+    const CLASSES: &[&str] = &[
+        r#"
+.class public java/lang/Object
+.method public <init> ()V
+    .code stack 0 locals 1
+        return
+    .end code
+.end method"#,
+        r#"
+.class public interface abstract Shape
+.super java/lang/Object
+.method public abstract area ()I
+.end method"#,
+        r#"
+.class public Square
+.super java/lang/Object
+.implements Shape
+.method public <init> ()V
+    .code stack 1 locals 1
+        aload_0
+        invokespecial java/lang/Object <init> ()V
+        return
+    .end code
+.end method
+.method public area ()I
+    .code stack 1 locals 1
+        iconst_1
+        ireturn
+    .end code
+.end method"#,
+        r#"
+.class public Circle
+.super java/lang/Object
+.implements Shape
+.method public <init> ()V
+    .code stack 1 locals 1
+        aload_0
+        invokespecial java/lang/Object <init> ()V
+        return
+    .end code
+.end method
+.method public area ()I
+    .code stack 1 locals 1
+        iconst_2
+        ireturn
+    .end code
+.end method"#,
+        r#"
+.class public Config
+.super java/lang/Object
+.field public static LIMIT I
+.end field
+.method static <clinit> ()V
+    .code stack 1 locals 0
+        bipush 10
+        putstatic Config LIMIT I
+        return
+    .end code
+.end method"#,
+        r#"
+.class public Main
+.super java/lang/Object
+.method public static main ([Ljava/lang/String;)V
+    .code stack 2 locals 1
+        new Square
+        dup
+        invokespecial Square <init> ()V
+        invokeinterface Shape area ()I 1
+        pop
+        getstatic Config LIMIT I
+        pop
+        invokedynamic 0 run "()Ljava/lang/Runnable;"
+        pop
+        invokestatic p/Missing call ()V
+        return
+    .end code
+.end method
+.method private static lambda$main$0 ()V
+    .code stack 0 locals 0
+        return
+    .end code
+.end method
+.method public static unused ()V
+    .code stack 0 locals 0
+        return
+    .end code
+.end method
+.bootstrapmethods
+    .bootstrap REF_invokeStatic Method java/lang/invoke/LambdaMetafactory metafactory "(Ljava/lang/invoke/MethodHandles$Lookup;Ljava/lang/String;Ljava/lang/invoke/MethodType;Ljava/lang/invoke/MethodType;Ljava/lang/invoke/MethodHandle;Ljava/lang/invoke/MethodType;)Ljava/lang/invoke/CallSite;"
+        methodtype "()V"
+        methodhandle REF_invokeStatic Method Main "lambda$main$0" "()V"
+        methodtype "()V"
+    .end bootstrap
+.end bootstrapmethods"#,
+    ];
+
+    fn build(algorithm: CallGraphAlgorithm) -> (ClassRepository, Vec<String>, CallGraph) {
+        let mut map = HashMap::new();
+        let mut names = Vec::new();
+        for text in CLASSES {
+            let name = text.lines().find_map(|line| line.trim().strip_prefix(".class")).unwrap().split_whitespace().last().unwrap();
+            map.insert(name.to_string(), assemble(&format!(".version 52 0\n{}\n.end class\n", text)).unwrap());
+            names.push(name.to_string());
+        }
+        let mut classpath = ClassPath::new();
+        classpath.push(map);
+        let repo = ClassRepository::new(classpath);
+        let main = MethodId::new("Main", "main", "([Ljava/lang/String;)V");
+        let graph = call_graph(&repo, &names, &[main], algorithm).unwrap();
+        (repo, names, graph)
+    }
+
+    fn method(text: &str) -> MethodId {
+        let (class_name, rest) = text.split_once('.').unwrap();
+        let ix = rest.find('(').unwrap();
+        MethodId::new(class_name, &rest[..ix], &rest[ix..])
+    }
+
+    fn unreachable(algorithm: CallGraphAlgorithm) -> Vec<String> {
+        let (repo, names, graph) = build(algorithm);
+        graph.unreachable_methods(&repo, &names).unwrap().iter().map(MethodId::to_string).collect()
+    }
+
+    #[test]
+    fn test_class_hierarchy() {
+        let (_, _, graph) = build(CallGraphAlgorithm::ClassHierarchy);
+        let main = method("Main.main([Ljava/lang/String;)V");
+        let mut callees: Vec<(usize, CallKind, String)> =
+            graph.callees(&main).map(|edge| (edge.offset, edge.kind, edge.callee.to_string())).collect();
+        callees.sort_by(|a, b| (a.0, &a.2).cmp(&(b.0, &b.2)));
+        assert_eq!(
+            callees,
+            vec![
+                (4, CallKind::Special, "Square.<init>()V".to_string()),
+                (7, CallKind::Interface, "Circle.area()I".to_string()),
+                (7, CallKind::Interface, "Square.area()I".to_string()),
+                (13, CallKind::Initialization, "Config.<clinit>()V".to_string()),
+                (17, CallKind::Lambda, "Main.lambda$main$0()V".to_string()),
+            ]
+        );
+        assert!(graph.instantiated.is_empty());
+        assert_eq!(graph.callers(&method("Square.area()I")).count(), 1);
+        let unresolved: Vec<String> = graph.unresolved.iter().map(|call| call.target.to_string()).collect();
+        assert_eq!(unresolved, vec!["p/Missing.call()V"]);
+        assert_eq!(unreachable(CallGraphAlgorithm::ClassHierarchy), vec!["Circle.<init>()V", "Main.unused()V"]);
+    }
+
+    #[test]
+    fn test_rapid_type() {
+        let (_, _, graph) = build(CallGraphAlgorithm::RapidType);
+        assert!(graph.is_reachable(&method("Square.area()I")));
+        assert!(!graph.is_reachable(&method("Circle.area()I")));
+        assert!(graph.is_reachable(&method("Main.lambda$main$0()V")));
+        assert_eq!(graph.instantiated.iter().collect::<Vec<_>>(), vec!["Square"]);
+        assert_eq!(
+            unreachable(CallGraphAlgorithm::RapidType),
+            vec!["Circle.<init>()V", "Circle.area()I", "Main.unused()V"]
+        );
+    }
+
+    #[test]
+    fn test_missing_entry_point() {
+        let (repo, names, _) = build(CallGraphAlgorithm::RapidType);
+        let entry = MethodId::new("Main", "start", "()V");
+        assert!(call_graph(&repo, &names, &[entry], CallGraphAlgorithm::RapidType).is_err());
+    }
+}
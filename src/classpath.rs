@@ -0,0 +1,480 @@
+//! Loading classes by binary name from directories, jar files and memory, and answering
+//! questions about the class hierarchy that they form.
+//!
+//! A [`ClassPath`] is an ordered list of [`ClassSource`]s, searched in order like the JVM's
+//! class path. A [`ClassRepository`] parses classes from a class path the first time they are
+//! asked for and keeps them, so that walking the hierarchy of many classes only parses each
+//! class once. Jar files are only supported with the `zip` feature enabled.
+
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::ErrorKind;
+#[cfg(feature = "zip")]
+use std::path::Path;
+use std::path::PathBuf;
+use std::rc::Rc;
+
+use crate::names::is_binary_name;
+use crate::verifier::SubtypeOracle;
+use crate::{parse_class_from_reader_with_options, parse_class_with_options, ClassAccessFlags, ClassFile, ParseError, ParseOptions};
+
+const OBJECT: &str = "java/lang/Object";
+const ARRAY_INTERFACES: [&str; 2] = ["java/lang/Cloneable", "java/io/Serializable"];
+
+/// Somewhere that class files can be found by binary name.
+pub trait ClassSource {
+    /// Parses the class with the given binary name, such as `java/lang/String`, or returns
+    /// `Ok(None)` if this source does not contain it.
+    fn load_class(&self, name: &str, opts: &ParseOptions) -> Result<Option<ClassFile>, ParseError>;
+}
+
+/// A directory holding class files in subdirectories named after their packages, such as
+/// the output directory of `javac -d`.
+#[derive(Clone, Debug)]
+pub struct ClassDirectory {
+    root: PathBuf,
+}
+
+impl ClassDirectory {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        ClassDirectory { root: root.into() }
+    }
+}
+
+impl ClassSource for ClassDirectory {
+    fn load_class(&self, name: &str, opts: &ParseOptions) -> Result<Option<ClassFile>, ParseError> {
+        // Binary names cannot contain `.`, so this also keeps lookups inside the directory
+        if !is_binary_name(name) {
+            return Ok(None);
+        }
+        let path = self.root.join(format!("{}.class", name));
+        let file = match File::open(&path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => fail!("Error opening {}: {}", path.display(), e),
+        };
+        parse_class_from_reader_with_options(file, opts).map(Some)
+    }
+}
+
+/// A jar file, or any other zip archive holding class files in directories named after
+/// their packages.
+#[cfg(feature = "zip")]
+pub struct JarFile {
+    archive: RefCell<zip::ZipArchive<File>>,
+}
+
+#[cfg(feature = "zip")]
+impl JarFile {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, ParseError> {
+        let path = path.as_ref();
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(e) => fail!("Error opening {}: {}", path.display(), e),
+        };
+        match zip::ZipArchive::new(file) {
+            Ok(archive) => Ok(JarFile { archive: RefCell::new(archive) }),
+            Err(e) => fail!("Error reading {}: {}", path.display(), e),
+        }
+    }
+}
+
+#[cfg(feature = "zip")]
+impl ClassSource for JarFile {
+    fn load_class(&self, name: &str, opts: &ParseOptions) -> Result<Option<ClassFile>, ParseError> {
+        let mut archive = self.archive.borrow_mut();
+        let entry = match archive.by_name(&format!("{}.class", name)) {
+            Ok(entry) => entry,
+            Err(zip::result::ZipError::FileNotFound) => return Ok(None),
+            Err(e) => fail!("Error reading {}.class from jar: {}", name, e),
+        };
+        parse_class_from_reader_with_options(entry, opts).map(Some)
+    }
+}
+
+/// Class files held in memory, keyed by binary name.
+impl ClassSource for HashMap<String, Vec<u8>> {
+    fn load_class(&self, name: &str, opts: &ParseOptions) -> Result<Option<ClassFile>, ParseError> {
+        match self.get(name) {
+            Some(bytes) => parse_class_with_options(bytes, opts).map(Some),
+            None => Ok(None),
+        }
+    }
+}
+
+/// An ordered list of class sources. A class is loaded from the first source that contains it.
+#[derive(Default)]
+pub struct ClassPath {
+    sources: Vec<Box<dyn ClassSource>>,
+}
+
+impl ClassPath {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a source to be searched after the ones already on the class path.
+    pub fn push(&mut self, source: impl ClassSource + 'static) -> &mut ClassPath {
+        self.sources.push(Box::new(source));
+        self
+    }
+
+    pub fn push_directory(&mut self, root: impl Into<PathBuf>) -> &mut ClassPath {
+        self.push(ClassDirectory::new(root))
+    }
+
+    #[cfg(feature = "zip")]
+    pub fn push_jar(&mut self, path: impl AsRef<Path>) -> Result<&mut ClassPath, ParseError> {
+        let jar = JarFile::open(path)?;
+        Ok(self.push(jar))
+    }
+}
+
+impl ClassSource for ClassPath {
+    fn load_class(&self, name: &str, opts: &ParseOptions) -> Result<Option<ClassFile>, ParseError> {
+        for source in &self.sources {
+            if let Some(class) = source.load_class(name, opts)? {
+                return Ok(Some(class));
+            }
+        }
+        Ok(None)
+    }
+}
+
+// Returns the component type of an array descriptor, or None if the name is not an array.
+fn array_component(name: &str) -> Option<&str> {
+    name.strip_prefix('[')
+}
+
+// Returns the class name or array descriptor for a reference component type, or None if the
+// component type is primitive.
+fn reference_type(component: &str) -> Option<&str> {
+    if component.starts_with('[') {
+        Some(component)
+    } else {
+        component.strip_prefix('L').and_then(|c| c.strip_suffix(';'))
+    }
+}
+
+fn array_of(name: &str) -> String {
+    if name.starts_with('[') {
+        format!("[{}", name)
+    } else {
+        format!("[L{};", name)
+    }
+}
+
+/// Loads classes from a class path on demand and answers questions about their hierarchy.
+/// Classes are named by their binary names, such as `java/lang/String`, and array types by
+/// their descriptors, such as `[Ljava/lang/String;`. Any class that is needed to answer a
+/// question but cannot be found on the class path is reported as an error.
+pub struct ClassRepository {
+    classpath: ClassPath,
+    opts: ParseOptions,
+    classes: RefCell<HashMap<String, Rc<ClassFile>>>,
+}
+
+impl ClassRepository {
+    pub fn new(classpath: ClassPath) -> Self {
+        Self::with_options(classpath, ParseOptions::default())
+    }
+
+    /// Creates a repository that parses the classes it loads with the given options.
+    pub fn with_options(classpath: ClassPath, opts: ParseOptions) -> Self {
+        ClassRepository {
+            classpath,
+            opts,
+            classes: RefCell::new(HashMap::new()),
+        }
+    }
+
+    /// Returns the class with the given binary name, parsing it if it has not been loaded yet.
+    pub fn load(&self, name: &str) -> Result<Rc<ClassFile>, ParseError> {
+        match self.find(name)? {
+            Some(class) => Ok(class),
+            None => fail!("Class {} not found on the class path", name),
+        }
+    }
+
+    /// Returns true if the class is on the class path. Classes that are found are loaded, and
+    /// errors parsing them are reported.
+    pub fn contains(&self, name: &str) -> Result<bool, ParseError> {
+        Ok(self.find(name)?.is_some())
+    }
+
+    fn find(&self, name: &str) -> Result<Option<Rc<ClassFile>>, ParseError> {
+        if let Some(class) = self.classes.borrow().get(name) {
+            return Ok(Some(class.clone()));
+        }
+        let class = match self.classpath.load_class(name, &self.opts) {
+            Ok(Some(class)) => class,
+            Ok(None) => return Ok(None),
+            Err(e) => return Err(err!(e, "class {}", name)),
+        };
+        if class.this_class != name {
+            fail!("Found class {} where class {} was expected", class.this_class, name);
+        }
+        let class = Rc::new(class);
+        self.classes.borrow_mut().insert(name.to_string(), class.clone());
+        Ok(Some(class))
+    }
+
+    /// Returns true if the name is an interface rather than a class or array type.
+    pub fn is_interface(&self, name: &str) -> Result<bool, ParseError> {
+        if array_component(name).is_some() {
+            return Ok(false);
+        }
+        Ok(self.load(name)?.access_flags.contains(ClassAccessFlags::INTERFACE))
+    }
+
+    /// Returns the superclasses of a class or array type, starting with its direct superclass
+    /// and ending with `java/lang/Object`. Interfaces have `java/lang/Object` as their only
+    /// superclass, and `java/lang/Object` has none.
+    pub fn superclasses(&self, name: &str) -> Result<Vec<String>, ParseError> {
+        if array_component(name).is_some() {
+            return Ok(vec![OBJECT.to_string()]);
+        }
+        let mut result: Vec<String> = Vec::new();
+        let mut current = self.load(name)?.super_class.clone();
+        while let Some(superclass) = current {
+            if superclass == name || result.contains(&superclass) {
+                fail!("Cyclic class hierarchy involving {}", superclass);
+            }
+            current = self.load(&superclass)?.super_class.clone();
+            result.push(superclass);
+        }
+        Ok(result)
+    }
+
+    /// Returns every interface that a class, interface or array type implements, directly or
+    /// through its superclasses and superinterfaces, each once. The interfaces declared by the
+    /// type itself come first, in declaration order.
+    pub fn all_interfaces(&self, name: &str) -> Result<Vec<String>, ParseError> {
+        if array_component(name).is_some() {
+            return Ok(ARRAY_INTERFACES.iter().map(|i| i.to_string()).collect());
+        }
+        let mut result = Vec::new();
+        let mut seen = HashSet::new();
+        let mut worklist = Vec::new();
+        for class in std::iter::once(name.to_string()).chain(self.superclasses(name)?) {
+            worklist.extend(self.load(&class)?.interfaces.iter().rev().cloned());
+            while let Some(interface) = worklist.pop() {
+                if interface == name || !seen.insert(interface.clone()) {
+                    continue;
+                }
+                worklist.extend(self.load(&interface)?.interfaces.iter().rev().cloned());
+                result.push(interface);
+            }
+        }
+        Ok(result)
+    }
+
+    /// Returns true if `sub` is the same type as `sup` or one of its subtypes, following the
+    /// rules for class, interface and array types in section 4.10.1.2 of the JVM spec.
+    pub fn is_subtype_of(&self, sub: &str, sup: &str) -> Result<bool, ParseError> {
+        if sub == sup || sup == OBJECT {
+            return Ok(true);
+        }
+        match (array_component(sub), array_component(sup)) {
+            (Some(sub), Some(sup)) => match (reference_type(sub), reference_type(sup)) {
+                (Some(sub), Some(sup)) => self.is_subtype_of(sub, sup),
+                _ => Ok(sub == sup),
+            },
+            (Some(_), None) => Ok(ARRAY_INTERFACES.contains(&sup)),
+            (None, Some(_)) => Ok(false),
+            (None, None) => {
+                let sup = sup.to_string();
+                Ok(self.superclasses(sub)?.contains(&sup) || self.all_interfaces(sub)?.contains(&sup))
+            }
+        }
+    }
+
+    /// Returns the most specific type that both `a` and `b` are subtypes of. Interfaces are only
+    /// returned when one type is a subtype of the other, since two classes can have several
+    /// unrelated interfaces in common; otherwise the result is their most specific common
+    /// superclass, as the JVM's verifier computes it.
+    pub fn least_common_supertype(&self, a: &str, b: &str) -> Result<String, ParseError> {
+        if self.is_subtype_of(a, b)? {
+            return Ok(b.to_string());
+        }
+        if self.is_subtype_of(b, a)? {
+            return Ok(a.to_string());
+        }
+        match (array_component(a), array_component(b)) {
+            (Some(a), Some(b)) => match (reference_type(a), reference_type(b)) {
+                (Some(a), Some(b)) => Ok(array_of(&self.least_common_supertype(a, b)?)),
+                _ => Ok(OBJECT.to_string()),
+            },
+            (None, None) if !self.is_interface(a)? && !self.is_interface(b)? => {
+                for superclass in self.superclasses(a)? {
+                    if self.is_subtype_of(b, &superclass)? {
+                        return Ok(superclass);
+                    }
+                }
+                Ok(OBJECT.to_string())
+            }
+            _ => Ok(OBJECT.to_string()),
+        }
+    }
+}
+
+/// Answers the verifier's questions from the class hierarchy. As in the JVM's verifier, any
+/// reference type is assignable to an interface type. Classes that cannot be loaded are
+/// treated as unrelated to every other type.
+impl SubtypeOracle for ClassRepository {
+    fn is_assignable(&self, sub: &str, sup: &str) -> bool {
+        if sub == sup {
+            return true;
+        }
+        match (array_component(sub), array_component(sup)) {
+            (Some(sub), Some(sup)) => match (reference_type(sub), reference_type(sup)) {
+                (Some(sub), Some(sup)) => self.is_assignable(sub, sup),
+                _ => sub == sup,
+            },
+            (_, None) if self.is_interface(sup).unwrap_or(false) => true,
+            _ => self.is_subtype_of(sub, sup).unwrap_or(false),
+        }
+    }
+
+    fn common_supertype(&self, a: &str, b: &str) -> String {
+        self.least_common_supertype(a, b).unwrap_or_else(|_| OBJECT.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembly::assemble;
+
+    fn class_map() -> HashMap<String, Vec<u8>> {
+        let classes = [
+            "java/lang/Object",
+            "interface abstract java/lang/Cloneable",
+            "interface abstract java/io/Serializable",
+            "interface abstract I",
+            "interface abstract J .implements I",
+            "interface abstract K",
+            "A .implements J",
+            "B .super A .implements K",
+            "C .super A .implements I Cloneable",
+            "D .super B .implements Missing",
+            "Cycle1 .super Cycle2",
+            "Cycle2 .super Cycle1",
+        ];
+        let mut map = HashMap::new();
+        for class in classes.iter() {
+            let mut parts = class.split(" .");
+            let header = parts.next().unwrap();
+            let name = header.rsplit(' ').next().unwrap().to_string();
+            let mut text = format!(".version 52 0\n.class public {}\n", header);
+            let directives: Vec<&str> = parts.collect();
+            if name != OBJECT && !directives.iter().any(|d| d.starts_with("super")) {
+                text.push_str(".super java/lang/Object\n");
+            }
+            for directive in directives {
+                text.push_str(&format!(".{}\n", directive.replace("Cloneable", "java/lang/Cloneable")));
+            }
+            text.push_str(".end class\n");
+            map.insert(name, assemble(&text).unwrap());
+        }
+        map
+    }
+
+    fn repository() -> ClassRepository {
+        let mut classpath = ClassPath::new();
+        classpath.push(class_map());
+        ClassRepository::new(classpath)
+    }
+
+    #[test]
+    fn test_hierarchy() {
+        let repo = repository();
+        assert_eq!(repo.superclasses("B").unwrap(), vec!["A", OBJECT]);
+        assert_eq!(repo.superclasses(OBJECT).unwrap(), Vec::<String>::new());
+        assert_eq!(repo.superclasses("[LB;").unwrap(), vec![OBJECT]);
+        assert_eq!(repo.all_interfaces("B").unwrap(), vec!["K", "J", "I"]);
+        assert_eq!(repo.all_interfaces("C").unwrap(), vec!["I", "java/lang/Cloneable", "J"]);
+        assert_eq!(repo.all_interfaces("J").unwrap(), vec!["I"]);
+
+        assert!(repo.is_subtype_of("B", "I").unwrap());
+        assert!(repo.is_subtype_of("B", "A").unwrap());
+        assert!(!repo.is_subtype_of("A", "B").unwrap());
+        assert!(!repo.is_subtype_of("C", "K").unwrap());
+        assert!(repo.is_subtype_of("[[LB;", "[[LI;").unwrap());
+        assert!(repo.is_subtype_of("[[I", "[Ljava/io/Serializable;").unwrap());
+        assert!(!repo.is_subtype_of("[I", "[J").unwrap());
+        assert!(!repo.is_subtype_of("[LA;", "[LB;").unwrap());
+
+        assert_eq!(repo.least_common_supertype("B", "C").unwrap(), "A");
+        assert_eq!(repo.least_common_supertype("B", "J").unwrap(), "J");
+        assert_eq!(repo.least_common_supertype("I", "K").unwrap(), OBJECT);
+        assert_eq!(repo.least_common_supertype("[LB;", "[LC;").unwrap(), "[LA;");
+        assert_eq!(repo.least_common_supertype("[[LB;", "[LC;").unwrap(), "[Ljava/lang/Object;");
+        assert_eq!(repo.least_common_supertype("[I", "[LC;").unwrap(), OBJECT);
+
+        assert!(repo.is_assignable("A", "K"));
+        assert!(!repo.is_assignable("A", "B"));
+        assert!(!repo.is_assignable("A", "Missing"));
+        assert_eq!(repo.common_supertype("[LB;", "[LC;"), "[LA;");
+    }
+
+    #[test]
+    fn test_hierarchy_errors() {
+        let repo = repository();
+        let err = repo.all_interfaces("D").unwrap_err();
+        assert_eq!(err.to_string(), "Class Missing not found on the class path");
+        let err = repo.superclasses("Cycle1").unwrap_err();
+        assert_eq!(err.to_string(), "Cyclic class hierarchy involving Cycle1");
+        assert!(!repo.contains("Missing").unwrap());
+        assert!(repo.contains("A").unwrap());
+
+        let mut map = HashMap::new();
+        map.insert("Wrong".to_string(), class_map().remove("A").unwrap());
+        map.insert("Broken".to_string(), vec![0xca, 0xfe]);
+        let mut classpath = ClassPath::new();
+        classpath.push(map);
+        let repo = ClassRepository::new(classpath);
+        assert_eq!(repo.load("Wrong").unwrap_err().to_string(), "Found class A where class Wrong was expected");
+        assert!(repo.load("Broken").unwrap_err().to_string().ends_with("for class Broken"));
+    }
+
+    #[test]
+    fn test_class_directory() {
+        let root = std::env::temp_dir().join(format!("cafebabe-classpath-{}", std::process::id()));
+        std::fs::create_dir_all(root.join("p")).unwrap();
+        let text = ".version 52 0\n.class public p/Foo\n.super java/lang/Object\n.end class\n";
+        std::fs::write(root.join("p/Foo.class"), assemble(text).unwrap()).unwrap();
+
+        let mut classpath = ClassPath::new();
+        classpath.push_directory(&root).push(class_map());
+        let repo = ClassRepository::new(classpath);
+        assert_eq!(repo.superclasses("p/Foo").unwrap(), vec![OBJECT]);
+        assert!(!repo.contains("../p/Foo").unwrap());
+        assert!(!repo.contains("p/Bar").unwrap());
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[cfg(feature = "zip")]
+    #[test]
+    fn test_jar_file() {
+        use std::io::Write;
+
+        let path = std::env::temp_dir().join(format!("cafebabe-classpath-{}.jar", std::process::id()));
+        let mut jar = zip::ZipWriter::new(File::create(&path).unwrap());
+        let options = zip::write::FileOptions::default().compression_method(zip::CompressionMethod::Deflated);
+        for (name, bytes) in class_map() {
+            jar.start_file(format!("{}.class", name), options).unwrap();
+            jar.write_all(&bytes).unwrap();
+        }
+        jar.finish().unwrap();
+
+        let mut classpath = ClassPath::new();
+        classpath.push_jar(&path).unwrap();
+        let repo = ClassRepository::new(classpath);
+        assert_eq!(repo.all_interfaces("B").unwrap(), vec!["K", "J", "I"]);
+        assert!(!repo.contains("Missing").unwrap());
+        std::fs::remove_file(&path).unwrap();
+        assert!(JarFile::open(&path).is_err());
+    }
+}
@@ -1,9 +1,11 @@
 use std::borrow::Cow;
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
 use std::ops::Deref;
 use std::rc::Rc;
 
-use crate::{read_u1, read_u2, read_u4, read_u8, ParseError};
+use crate::{bounded_capacity, read_u1, read_u2, read_u4, read_u8, ParseError};
 use crate::names::{is_array_descriptor, is_binary_name, is_field_descriptor, is_method_descriptor, is_module_name, is_unqualified_name};
 
 #[derive(Debug)]
@@ -53,7 +55,8 @@ impl RefCellDeref for RefCell<ConstantPoolRef> {
     }
 }
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum ReferenceKind {
     GetField,
     GetStatic,
@@ -199,8 +202,10 @@ impl ConstantPoolEntry {
             ConstantPoolEntry::NameAndType(x, y) => Ok(
                 x.ensure_type(ConstantPoolEntryTypes::UTF8)? &&
                 x.borrow().get().validate_unqualified_name()? &&
-                y.ensure_type(ConstantPoolEntryTypes::UTF8)?
-                // y is validated as part of FieldRef/MethodRef/InterfaceMethodRef/Dynamic/InvokeDynamic pool item validation
+                y.ensure_type(ConstantPoolEntryTypes::UTF8)? &&
+                // y is validated as a descriptor as part of FieldRef/MethodRef/InterfaceMethodRef/Dynamic/InvokeDynamic
+                // pool item validation, but has to be a string even if the entry is not referenced
+                y.borrow().get().validate_string()?
             ),
             ConstantPoolEntry::MethodHandle(x, y) => y.ensure_type(match x {
                 ReferenceKind::GetField |
@@ -245,6 +250,13 @@ impl ConstantPoolEntry {
         }
     }
 
+    fn validate_string(&self) -> Result<bool, ParseError> {
+        match self {
+            ConstantPoolEntry::Utf8(_) => Ok(true),
+            _ => fail!("Invalid modified UTF-8 data"),
+        }
+    }
+
     fn validate_classinfo_name(&self) -> Result<bool, ParseError> {
         match self {
             ConstantPoolEntry::Utf8(x) => {
@@ -255,7 +267,7 @@ impl ConstantPoolEntry {
                     fail!("Invalid binary name")
                 }
             }
-            _ => panic!("Attempting to get utf-8 data from non-utf8 constant pool entry!"),
+            _ => fail!("Unexpected constant pool reference type"),
         }
     }
 
@@ -268,7 +280,7 @@ impl ConstantPoolEntry {
                     fail!("Invalid binary name")
                 }
             }
-            _ => panic!("Attempting to get utf-8 data from non-utf8 constant pool entry!"),
+            _ => fail!("Unexpected constant pool reference type"),
         }
     }
 
@@ -281,7 +293,7 @@ impl ConstantPoolEntry {
                     fail!("Invalid unqualified name")
                 }
             }
-            _ => panic!("Attempting to get utf-8 data from non-utf8 constant pool entry!"),
+            _ => fail!("Unexpected constant pool reference type"),
         }
     }
 
@@ -294,7 +306,7 @@ impl ConstantPoolEntry {
                     fail!("Invalid module name")
                 }
             }
-            _ => panic!("Attempting to get utf-8 data from non-utf8 constant pool entry!"),
+            _ => fail!("Unexpected constant pool reference type"),
         }
     }
 
@@ -306,13 +318,12 @@ impl ConstantPoolEntry {
                 // points to a later entry in the constant pool that hasn't been validated yet.
                 // assert on the bool because we should never get Ok(false).
                 assert!(y.ensure_type(ConstantPoolEntryTypes::UTF8)?);
-                if is_field_descriptor(&y.borrow().get().utf8()) {
-                    Ok(true)
-                } else {
-                    fail!("Invalid field descriptor")
+                match y.borrow().get().deref() {
+                    ConstantPoolEntry::Utf8(x) if is_field_descriptor(x) => Ok(true),
+                    _ => fail!("Invalid field descriptor"),
                 }
             }
-            _ => panic!("Attempting to get descriptor from non-NameAndType constant pool entry!"),
+            _ => fail!("Unexpected constant pool reference type"),
         }
     }
 
@@ -333,7 +344,28 @@ impl ConstantPoolEntry {
                     fail!("Invalid method descriptor")
                 }
             }
-            _ => panic!("Attempting to get descriptor from non-NameAndType constant pool entry!"),
+            _ => fail!("Unexpected constant pool reference type"),
+        }
+    }
+
+    // The number of bytes of text copied out of the constant pool when reading this entry
+    fn text_len(&self) -> usize {
+        match self {
+            ConstantPoolEntry::Utf8(x) => x.len(),
+            ConstantPoolEntry::Utf8Bytes(x) => x.len(),
+            ConstantPoolEntry::ClassInfo(x) |
+            ConstantPoolEntry::String(x) |
+            ConstantPoolEntry::MethodHandle(_, x) |
+            ConstantPoolEntry::MethodType(x) |
+            ConstantPoolEntry::Dynamic(_, x) |
+            ConstantPoolEntry::InvokeDynamic(_, x) |
+            ConstantPoolEntry::ModuleInfo(x) |
+            ConstantPoolEntry::PackageInfo(x) => x.borrow().get().text_len(),
+            ConstantPoolEntry::FieldRef(x, y) |
+            ConstantPoolEntry::MethodRef(x, y) |
+            ConstantPoolEntry::InterfaceMethodRef(x, y) |
+            ConstantPoolEntry::NameAndType(x, y) => x.borrow().get().text_len() + y.borrow().get().text_len(),
+            _ => 0,
         }
     }
 
@@ -506,7 +538,7 @@ fn validate_constant_pool(constant_pool: &[Rc<ConstantPoolEntry>], major_version
 
 pub(crate) fn read_constant_pool(bytes: &Vec<u8>, ix: &mut usize, major_version: u16) -> Result<Vec<Rc<ConstantPoolEntry>>, ParseError> {
     let count = read_u2(&bytes, ix)?;
-    let mut constant_pool = Vec::with_capacity(count.into());
+    let mut constant_pool = Vec::with_capacity(bounded_capacity(count, bytes, *ix));
     constant_pool.push(Rc::new(ConstantPoolEntry::Zero));
     let mut cp_ix = 1;
     while cp_ix < count {
@@ -544,15 +576,155 @@ pub(crate) fn read_constant_pool(bytes: &Vec<u8>, ix: &mut usize, major_version:
     Ok(constant_pool)
 }
 
-fn read_cp_ref_any(bytes: &[u8], ix: &mut usize, pool: &[Rc<ConstantPoolEntry>]) -> Result<Rc<ConstantPoolEntry>, ParseError> {
+/// Finds where each entry of the constant pool starts without reading the entries, for when
+/// only a few of them are needed. Indices with no entry of their own (zero, and those following
+/// long and double entries) are given an offset of zero.
+pub(crate) fn scan_constant_pool(bytes: &[u8], ix: &mut usize, major_version: u16) -> Result<Vec<usize>, ParseError> {
+    let count = read_u2(bytes, ix)?;
+    let mut offsets = Vec::with_capacity(bounded_capacity(count, bytes, *ix));
+    offsets.push(0);
+    let mut cp_ix = 1;
+    while cp_ix < count {
+        offsets.push(*ix);
+        let constant_type = read_u1(bytes, ix)?;
+        let size = match constant_type {
+            1 => read_u2(bytes, ix)? as usize,
+            7 | 8 => 2,
+            3 | 4 | 9 | 10 | 11 | 12 => 4,
+            5 | 6 => 8,
+            15 if major_version >= 51 => 3,
+            16 if major_version >= 51 => 2,
+            17 if major_version >= 55 => 4,
+            18 if major_version >= 51 => 4,
+            19 | 20 if major_version >= 53 => 2,
+            n => fail!("Unexpected constant pool entry type {} at index {} for classfile major version {}", n, *ix - 1, major_version),
+        };
+        if bytes.len() < *ix + size {
+            fail!("Unexpected end of stream reading constant pool entry at index {}", *ix);
+        }
+        *ix += size;
+        cp_ix += 1;
+        if constant_type == 5 || constant_type == 6 {
+            cp_ix += 1;
+            offsets.push(0);
+        }
+    }
+    Ok(offsets)
+}
+
+fn scanned_entry_offset(bytes: &[u8], offsets: &[usize], cp_index: usize, constant_type: u8) -> Result<usize, ParseError> {
+    match offsets.get(cp_index) {
+        Some(&offset) if offset != 0 => {
+            if bytes[offset] != constant_type {
+                fail!("Unexpected constant pool reference type");
+            }
+            Ok(offset + 1)
+        }
+        _ => fail!("Out-of-bounds index {} in constant pool reference", cp_index),
+    }
+}
+
+/// Reads a reference to an optional CONSTANT_Class entry of a constant pool that was scanned by
+/// scan_constant_pool, decoding and validating only that entry and the name it refers to.
+pub(crate) fn read_scanned_classinfo_opt(bytes: &[u8], ix: &mut usize, offsets: &[usize]) -> Result<Option<String>, ParseError> {
+    let cp_index = read_u2(bytes, ix)? as usize;
+    if cp_index == 0 {
+        return Ok(None);
+    }
+    let mut class_ix = scanned_entry_offset(bytes, offsets, cp_index, 7)?;
+    let name_index = read_u2(bytes, &mut class_ix)? as usize;
+    let mut name_ix = scanned_entry_offset(bytes, offsets, name_index, 1)?;
+    match read_constant_utf8(bytes, &mut name_ix)? {
+        name @ ConstantPoolEntry::Utf8(_) => {
+            name.validate_classinfo_name().map_err(|e| err!(e, "constant pool entry {}", name_index))?;
+            Ok(Some(name.utf8()))
+        }
+        _ => fail!("Unexpected constant pool reference type"),
+    }
+}
+
+pub(crate) fn read_scanned_classinfo(bytes: &[u8], ix: &mut usize, offsets: &[usize]) -> Result<String, ParseError> {
+    match read_scanned_classinfo_opt(bytes, ix, offsets)? {
+        Some(name) => Ok(name),
+        None => fail!("Unexpected constant pool reference type"),
+    }
+}
+
+/// The constant pool of a class being parsed, along with the limits that parsing it is subject to.
+/// Strings are copied out of the constant pool each time they are referenced, so a parsed class
+/// can take up far more memory than the class file it came from; every reference read through
+/// the reader is charged to its allocation budget. The reader also keeps track of how deeply
+/// nested the annotations and attributes being read are, to bound the depth of recursion.
+pub(crate) struct ConstantPoolReader<'a> {
+    pool: &'a [Rc<ConstantPoolEntry>],
+    budget: Cell<usize>,
+    depth: Cell<usize>,
+    max_depth: usize,
+}
+
+impl<'a> ConstantPoolReader<'a> {
+    pub(crate) fn new(pool: &'a [Rc<ConstantPoolEntry>], budget: usize, depth: usize, max_depth: usize) -> Self {
+        ConstantPoolReader {
+            pool,
+            budget: Cell::new(budget),
+            depth: Cell::new(depth),
+            max_depth,
+        }
+    }
+
+    /// Creates a reader for a constant pool that is already in memory, such as that of a
+    /// parsed class, with no limits.
+    pub(crate) fn unlimited(pool: &'a [Rc<ConstantPoolEntry>]) -> Self {
+        Self::new(pool, usize::MAX, 0, usize::MAX)
+    }
+
+    pub(crate) fn remaining_budget(&self) -> usize {
+        self.budget.get()
+    }
+
+    pub(crate) fn depth(&self) -> usize {
+        self.depth.get()
+    }
+
+    fn charge(&self, entry: &ConstantPoolEntry) -> Result<(), ParseError> {
+        match self.budget.get().checked_sub(entry.text_len()) {
+            Some(remaining) => self.budget.set(remaining),
+            None => fail!("Exceeded the maximum allocation while copying strings from the constant pool"),
+        }
+        Ok(())
+    }
+
+    /// Runs `read` one level deeper in the nesting of annotations or attributes.
+    pub(crate) fn nested<T>(&self, read: impl FnOnce() -> Result<T, ParseError>) -> Result<T, ParseError> {
+        let depth = self.depth.get();
+        if depth >= self.max_depth {
+            fail!("Exceeded the maximum nesting depth of {}", self.max_depth);
+        }
+        self.depth.set(depth + 1);
+        let result = read();
+        self.depth.set(depth);
+        result
+    }
+}
+
+impl Deref for ConstantPoolReader<'_> {
+    type Target = [Rc<ConstantPoolEntry>];
+
+    fn deref(&self) -> &Self::Target {
+        self.pool
+    }
+}
+
+fn read_cp_ref_any(bytes: &[u8], ix: &mut usize, pool: &ConstantPoolReader) -> Result<Rc<ConstantPoolEntry>, ParseError> {
     let cp_index = read_u2(bytes, ix)? as usize;
     if cp_index >= pool.len() {
         fail!("Out-of-bounds index {} in constant pool reference", cp_index);
     }
+    pool.charge(&pool[cp_index])?;
     Ok(pool[cp_index].clone())
 }
 
-pub(crate) fn read_cp_utf8(bytes: &[u8], ix: &mut usize, pool: &[Rc<ConstantPoolEntry>]) -> Result<String, ParseError> {
+pub(crate) fn read_cp_utf8(bytes: &[u8], ix: &mut usize, pool: &ConstantPoolReader) -> Result<String, ParseError> {
     let cp_ref = read_cp_ref_any(bytes, ix, pool)?;
     match cp_ref.deref() {
         ConstantPoolEntry::Utf8(x) => Ok(x.clone()),
@@ -560,7 +732,7 @@ pub(crate) fn read_cp_utf8(bytes: &[u8], ix: &mut usize, pool: &[Rc<ConstantPool
     }
 }
 
-pub(crate) fn read_cp_utf8_opt(bytes: &[u8], ix: &mut usize, pool: &[Rc<ConstantPoolEntry>]) -> Result<Option<String>, ParseError> {
+pub(crate) fn read_cp_utf8_opt(bytes: &[u8], ix: &mut usize, pool: &ConstantPoolReader) -> Result<Option<String>, ParseError> {
     let cp_ref = read_cp_ref_any(bytes, ix, pool)?;
     match cp_ref.deref() {
         ConstantPoolEntry::Zero => Ok(None),
@@ -569,7 +741,7 @@ pub(crate) fn read_cp_utf8_opt(bytes: &[u8], ix: &mut usize, pool: &[Rc<Constant
     }
 }
 
-pub(crate) fn read_cp_classinfo(bytes: &[u8], ix: &mut usize, pool: &[Rc<ConstantPoolEntry>]) -> Result<String, ParseError> {
+pub(crate) fn read_cp_classinfo(bytes: &[u8], ix: &mut usize, pool: &ConstantPoolReader) -> Result<String, ParseError> {
     let cp_ref = read_cp_ref_any(bytes, ix, pool)?;
     match cp_ref.deref() {
         ConstantPoolEntry::ClassInfo(x) => Ok(x.borrow().get().utf8()),
@@ -577,7 +749,7 @@ pub(crate) fn read_cp_classinfo(bytes: &[u8], ix: &mut usize, pool: &[Rc<Constan
     }
 }
 
-pub(crate) fn read_cp_classinfo_opt(bytes: &[u8], ix: &mut usize, pool: &[Rc<ConstantPoolEntry>]) -> Result<Option<String>, ParseError> {
+pub(crate) fn read_cp_classinfo_opt(bytes: &[u8], ix: &mut usize, pool: &ConstantPoolReader) -> Result<Option<String>, ParseError> {
     let cp_ref = read_cp_ref_any(bytes, ix, pool)?;
     match cp_ref.deref() {
         ConstantPoolEntry::Zero => Ok(None),
@@ -586,7 +758,7 @@ pub(crate) fn read_cp_classinfo_opt(bytes: &[u8], ix: &mut usize, pool: &[Rc<Con
     }
 }
 
-pub(crate) fn read_cp_moduleinfo(bytes: &[u8], ix: &mut usize, pool: &[Rc<ConstantPoolEntry>]) -> Result<String, ParseError> {
+pub(crate) fn read_cp_moduleinfo(bytes: &[u8], ix: &mut usize, pool: &ConstantPoolReader) -> Result<String, ParseError> {
     let cp_ref = read_cp_ref_any(bytes, ix, pool)?;
     match cp_ref.deref() {
         ConstantPoolEntry::ModuleInfo(x) => Ok(x.borrow().get().utf8()),
@@ -594,7 +766,7 @@ pub(crate) fn read_cp_moduleinfo(bytes: &[u8], ix: &mut usize, pool: &[Rc<Consta
     }
 }
 
-pub(crate) fn read_cp_packageinfo(bytes: &[u8], ix: &mut usize, pool: &[Rc<ConstantPoolEntry>]) -> Result<String, ParseError> {
+pub(crate) fn read_cp_packageinfo(bytes: &[u8], ix: &mut usize, pool: &ConstantPoolReader) -> Result<String, ParseError> {
     let cp_ref = read_cp_ref_any(bytes, ix, pool)?;
     match cp_ref.deref() {
         ConstantPoolEntry::PackageInfo(x) => Ok(x.borrow().get().utf8()),
@@ -602,13 +774,14 @@ pub(crate) fn read_cp_packageinfo(bytes: &[u8], ix: &mut usize, pool: &[Rc<Const
     }
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct NameAndType {
     pub name: String,
     pub descriptor: String,
 }
 
-pub(crate) fn read_cp_nameandtype_opt(bytes: &[u8], ix: &mut usize, pool: &[Rc<ConstantPoolEntry>]) -> Result<Option<NameAndType>, ParseError> {
+pub(crate) fn read_cp_nameandtype_opt(bytes: &[u8], ix: &mut usize, pool: &ConstantPoolReader) -> Result<Option<NameAndType>, ParseError> {
     let cp_ref = read_cp_ref_any(bytes, ix, pool)?;
     match cp_ref.deref() {
         ConstantPoolEntry::Zero => Ok(None),
@@ -617,17 +790,51 @@ pub(crate) fn read_cp_nameandtype_opt(bytes: &[u8], ix: &mut usize, pool: &[Rc<C
     }
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum LiteralConstant {
     Integer(i32),
-    Float(f32),
+    Float(#[cfg_attr(feature = "serde", serde(with = "crate::serde_support::float"))] f32),
     Long(i64),
-    Double(f64),
+    Double(#[cfg_attr(feature = "serde", serde(with = "crate::serde_support::double"))] f64),
     String(String),
     StringBytes(Vec<u8>),
 }
 
-pub(crate) fn read_cp_literalconstant(bytes: &[u8], ix: &mut usize, pool: &[Rc<ConstantPoolEntry>]) -> Result<LiteralConstant, ParseError> {
+// Floating point values are compared by their bits, so that equality is consistent with
+// hashing and a NaN constant is equal to itself. This also tells 0.0 and -0.0 apart, as
+// they are different constants in a class file.
+impl PartialEq for LiteralConstant {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (LiteralConstant::Integer(a), LiteralConstant::Integer(b)) => a == b,
+            (LiteralConstant::Float(a), LiteralConstant::Float(b)) => a.to_bits() == b.to_bits(),
+            (LiteralConstant::Long(a), LiteralConstant::Long(b)) => a == b,
+            (LiteralConstant::Double(a), LiteralConstant::Double(b)) => a.to_bits() == b.to_bits(),
+            (LiteralConstant::String(a), LiteralConstant::String(b)) => a == b,
+            (LiteralConstant::StringBytes(a), LiteralConstant::StringBytes(b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+impl Eq for LiteralConstant {}
+
+impl Hash for LiteralConstant {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        std::mem::discriminant(self).hash(state);
+        match self {
+            LiteralConstant::Integer(v) => v.hash(state),
+            LiteralConstant::Float(v) => v.to_bits().hash(state),
+            LiteralConstant::Long(v) => v.hash(state),
+            LiteralConstant::Double(v) => v.to_bits().hash(state),
+            LiteralConstant::String(v) => v.hash(state),
+            LiteralConstant::StringBytes(v) => v.hash(state),
+        }
+    }
+}
+
+pub(crate) fn read_cp_literalconstant(bytes: &[u8], ix: &mut usize, pool: &ConstantPoolReader) -> Result<LiteralConstant, ParseError> {
     let cp_ref = read_cp_ref_any(bytes, ix, pool)?;
     match cp_ref.deref() {
         ConstantPoolEntry::Integer(v) => Ok(LiteralConstant::Integer(*v)),
@@ -639,7 +846,7 @@ pub(crate) fn read_cp_literalconstant(bytes: &[u8], ix: &mut usize, pool: &[Rc<C
     }
 }
 
-pub(crate) fn read_cp_integer(bytes: &[u8], ix: &mut usize, pool: &[Rc<ConstantPoolEntry>]) -> Result<i32, ParseError> {
+pub(crate) fn read_cp_integer(bytes: &[u8], ix: &mut usize, pool: &ConstantPoolReader) -> Result<i32, ParseError> {
     let cp_ref = read_cp_ref_any(bytes, ix, pool)?;
     match cp_ref.deref() {
         ConstantPoolEntry::Integer(v) => Ok(*v),
@@ -647,7 +854,7 @@ pub(crate) fn read_cp_integer(bytes: &[u8], ix: &mut usize, pool: &[Rc<ConstantP
     }
 }
 
-pub(crate) fn read_cp_float(bytes: &[u8], ix: &mut usize, pool: &[Rc<ConstantPoolEntry>]) -> Result<f32, ParseError> {
+pub(crate) fn read_cp_float(bytes: &[u8], ix: &mut usize, pool: &ConstantPoolReader) -> Result<f32, ParseError> {
     let cp_ref = read_cp_ref_any(bytes, ix, pool)?;
     match cp_ref.deref() {
         ConstantPoolEntry::Float(v) => Ok(*v),
@@ -655,7 +862,7 @@ pub(crate) fn read_cp_float(bytes: &[u8], ix: &mut usize, pool: &[Rc<ConstantPoo
     }
 }
 
-pub(crate) fn read_cp_long(bytes: &[u8], ix: &mut usize, pool: &[Rc<ConstantPoolEntry>]) -> Result<i64, ParseError> {
+pub(crate) fn read_cp_long(bytes: &[u8], ix: &mut usize, pool: &ConstantPoolReader) -> Result<i64, ParseError> {
     let cp_ref = read_cp_ref_any(bytes, ix, pool)?;
     match cp_ref.deref() {
         ConstantPoolEntry::Long(v) => Ok(*v),
@@ -663,7 +870,7 @@ pub(crate) fn read_cp_long(bytes: &[u8], ix: &mut usize, pool: &[Rc<ConstantPool
     }
 }
 
-pub(crate) fn read_cp_double(bytes: &[u8], ix: &mut usize, pool: &[Rc<ConstantPoolEntry>]) -> Result<f64, ParseError> {
+pub(crate) fn read_cp_double(bytes: &[u8], ix: &mut usize, pool: &ConstantPoolReader) -> Result<f64, ParseError> {
     let cp_ref = read_cp_ref_any(bytes, ix, pool)?;
     match cp_ref.deref() {
         ConstantPoolEntry::Double(v) => Ok(*v),
@@ -671,13 +878,14 @@ pub(crate) fn read_cp_double(bytes: &[u8], ix: &mut usize, pool: &[Rc<ConstantPo
     }
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct MemberRef {
     pub class_name: String,
     pub name_and_type: NameAndType
 }
 
-pub(crate) fn read_cp_memberref(bytes: &[u8], ix: &mut usize, pool: &[Rc<ConstantPoolEntry>], allowed: ConstantPoolEntryTypes) -> Result<MemberRef, ParseError> {
+pub(crate) fn read_cp_memberref(bytes: &[u8], ix: &mut usize, pool: &ConstantPoolReader, allowed: ConstantPoolEntryTypes) -> Result<MemberRef, ParseError> {
     let cp_ref = read_cp_ref_any(bytes, ix, pool)?;
     // The caller can restrict the specific member types allowed here such
     // that we return an Err if it's not one of the allowed types.
@@ -694,13 +902,14 @@ pub(crate) fn read_cp_memberref(bytes: &[u8], ix: &mut usize, pool: &[Rc<Constan
     }
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct InvokeDynamic {
     pub attr_index: u16,
     pub name_and_type: NameAndType,
 }
 
-pub(crate) fn read_cp_invokedynamic(bytes: &[u8], ix: &mut usize, pool: &[Rc<ConstantPoolEntry>]) -> Result<InvokeDynamic, ParseError> {
+pub(crate) fn read_cp_invokedynamic(bytes: &[u8], ix: &mut usize, pool: &ConstantPoolReader) -> Result<InvokeDynamic, ParseError> {
     let cp_ref = read_cp_ref_any(bytes, ix, pool)?;
     match cp_ref.deref() {
         ConstantPoolEntry::InvokeDynamic(x, y) => Ok(InvokeDynamic {
@@ -711,13 +920,15 @@ pub(crate) fn read_cp_invokedynamic(bytes: &[u8], ix: &mut usize, pool: &[Rc<Con
     }
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Dynamic {
-    attr_index: u16,
-    name_and_type: NameAndType,
+    pub attr_index: u16,
+    pub name_and_type: NameAndType,
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Loadable {
     LiteralConstant(LiteralConstant),
     ClassInfo(String),
@@ -726,10 +937,11 @@ pub enum Loadable {
     Dynamic(Dynamic),
 }
 
-pub(crate) fn get_cp_loadable(cp_index: usize, pool: &[Rc<ConstantPoolEntry>]) -> Result<Loadable, ParseError> {
+pub(crate) fn get_cp_loadable(cp_index: usize, pool: &ConstantPoolReader) -> Result<Loadable, ParseError> {
     if cp_index >= pool.len() {
         fail!("Out-of-bounds index {} in constant pool reference", cp_index);
     }
+    pool.charge(&pool[cp_index])?;
     match pool[cp_index].deref() {
         ConstantPoolEntry::Integer(v) => Ok(Loadable::LiteralConstant(LiteralConstant::Integer(*v))),
         ConstantPoolEntry::Float(v) => Ok(Loadable::LiteralConstant(LiteralConstant::Float(*v))),
@@ -744,14 +956,16 @@ pub(crate) fn get_cp_loadable(cp_index: usize, pool: &[Rc<ConstantPoolEntry>]) -
     }
 }
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum MemberKind {
     Field,
     Method,
     InterfaceMethod,
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct MethodHandle {
     pub kind: ReferenceKind,
     pub class_name: String,
@@ -774,7 +988,7 @@ fn make_method_handle(x: &ReferenceKind, y: &RefCell<ConstantPoolRef>) -> Result
     })
 }
 
-pub(crate) fn read_cp_methodhandle(bytes: &[u8], ix: &mut usize, pool: &[Rc<ConstantPoolEntry>]) -> Result<MethodHandle, ParseError> {
+pub(crate) fn read_cp_methodhandle(bytes: &[u8], ix: &mut usize, pool: &ConstantPoolReader) -> Result<MethodHandle, ParseError> {
     let cp_ref = read_cp_ref_any(bytes, ix, pool)?;
     match cp_ref.deref() {
         ConstantPoolEntry::MethodHandle(x, y) => make_method_handle(x, y),
@@ -782,7 +996,8 @@ pub(crate) fn read_cp_methodhandle(bytes: &[u8], ix: &mut usize, pool: &[Rc<Cons
     }
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum BootstrapArgument {
     LiteralConstant(LiteralConstant),
     ClassInfo(String),
@@ -790,7 +1005,7 @@ pub enum BootstrapArgument {
     MethodType(String),
 }
 
-pub(crate) fn read_cp_bootstrap_argument(bytes: &[u8], ix: &mut usize, pool: &[Rc<ConstantPoolEntry>]) -> Result<BootstrapArgument, ParseError> {
+pub(crate) fn read_cp_bootstrap_argument(bytes: &[u8], ix: &mut usize, pool: &ConstantPoolReader) -> Result<BootstrapArgument, ParseError> {
     let cp_ref = read_cp_ref_any(bytes, ix, pool)?;
     match cp_ref.deref() {
         ConstantPoolEntry::Integer(v) => Ok(BootstrapArgument::LiteralConstant(LiteralConstant::Integer(*v))),
@@ -805,7 +1020,8 @@ pub(crate) fn read_cp_bootstrap_argument(bytes: &[u8], ix: &mut usize, pool: &[R
     }
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum ConstantPoolItem {
     LiteralConstant(LiteralConstant),
     ClassInfo(String),
@@ -839,10 +1055,10 @@ impl Iterator for ConstantPoolIter {
     type Item = ConstantPoolItem;
 
     fn next(&mut self) -> Option<Self::Item> {
-        while self.index < self.constant_pool.len() {
+        while self.index + 1 < self.constant_pool.len() {
             self.index += 1;
             let item = match self.constant_pool[self.index].deref() {
-                ConstantPoolEntry::Zero => panic!("This iterator should never see a Zero item"),
+                ConstantPoolEntry::Zero => continue,
                 ConstantPoolEntry::Utf8(_) |
                 ConstantPoolEntry::Utf8Bytes(_) => continue,
                 ConstantPoolEntry::Integer(v) => ConstantPoolItem::LiteralConstant(LiteralConstant::Integer(*v)),
@@ -855,7 +1071,11 @@ impl Iterator for ConstantPoolIter {
                 ConstantPoolEntry::MethodRef(c, m) => ConstantPoolItem::MethodRef(MemberRef { class_name: c.borrow().get().classinfo(), name_and_type: m.borrow().get().name_and_type() }),
                 ConstantPoolEntry::InterfaceMethodRef(c, m) => ConstantPoolItem::InterfaceMethodRef(MemberRef { class_name: c.borrow().get().classinfo(), name_and_type: m.borrow().get().name_and_type() }),
                 ConstantPoolEntry::NameAndType(x, y) => ConstantPoolItem::NameAndType(NameAndType { name: x.borrow().get().utf8(), descriptor: y.borrow().get().utf8() }),
-                ConstantPoolEntry::MethodHandle(x, y) => ConstantPoolItem::MethodHandle(make_method_handle(x, y).unwrap()),
+                ConstantPoolEntry::MethodHandle(x, y) => match make_method_handle(x, y) {
+                    Ok(handle) => ConstantPoolItem::MethodHandle(handle),
+                    // not reached, since method handles are checked to refer to members when parsed
+                    Err(_) => continue,
+                },
                 ConstantPoolEntry::MethodType(x) => ConstantPoolItem::MethodType(x.borrow().get().utf8()),
                 ConstantPoolEntry::Dynamic(x, y) => ConstantPoolItem::Dynamic(Dynamic { attr_index: *x, name_and_type: y.borrow().get().name_and_type() }),
                 ConstantPoolEntry::InvokeDynamic(x, y) => ConstantPoolItem::InvokeDynamic(InvokeDynamic { attr_index: *x, name_and_type: y.borrow().get().name_and_type() }),
@@ -868,3 +1088,64 @@ impl Iterator for ConstantPoolIter {
         None
     }
 }
+
+/// A constant pool entry with its references to other entries given as constant pool
+/// indices, the way they are laid out in the class file. Floating point values are kept
+/// as their raw bits so that entries can be compared and hashed.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub(crate) enum IndexedEntry {
+    Utf8(String),
+    Utf8Bytes(Vec<u8>),
+    Integer(i32),
+    Float(u32),
+    Long(i64),
+    Double(u64),
+    ClassInfo(u16),
+    String(u16),
+    FieldRef(u16, u16),
+    MethodRef(u16, u16),
+    InterfaceMethodRef(u16, u16),
+    NameAndType(u16, u16),
+    MethodHandle(ReferenceKind, u16),
+    MethodType(u16),
+    Dynamic(BootstrapMethodRef, u16),
+    InvokeDynamic(BootstrapMethodRef, u16),
+    ModuleInfo(u16),
+    PackageInfo(u16),
+}
+
+/// Converts a resolved constant pool back into indexed form. The entry at index zero and
+/// the unused entries following Long and Double entries are returned as None.
+pub(crate) fn indexed_constant_pool(pool: &[Rc<ConstantPoolEntry>]) -> Vec<Option<IndexedEntry>> {
+    let indices: HashMap<*const ConstantPoolEntry, u16> = pool.iter().enumerate().map(|(i, entry)| (Rc::as_ptr(entry), i as u16)).collect();
+    let index_of = |cp_ref: &RefCell<ConstantPoolRef>| match &*cp_ref.borrow() {
+        ConstantPoolRef::Unresolved(ix) => *ix,
+        ConstantPoolRef::Resolved(target) => indices[&Rc::as_ptr(target)],
+    };
+    pool.iter().map(|entry| {
+        let indexed = match entry.deref() {
+            ConstantPoolEntry::Zero |
+            ConstantPoolEntry::Unused => return None,
+            ConstantPoolEntry::Utf8(x) => IndexedEntry::Utf8(x.clone()),
+            ConstantPoolEntry::Utf8Bytes(x) => IndexedEntry::Utf8Bytes(x.clone()),
+            ConstantPoolEntry::Integer(v) => IndexedEntry::Integer(*v),
+            ConstantPoolEntry::Float(v) => IndexedEntry::Float(v.to_bits()),
+            ConstantPoolEntry::Long(v) => IndexedEntry::Long(*v),
+            ConstantPoolEntry::Double(v) => IndexedEntry::Double(v.to_bits()),
+            ConstantPoolEntry::ClassInfo(x) => IndexedEntry::ClassInfo(index_of(x)),
+            ConstantPoolEntry::String(x) => IndexedEntry::String(index_of(x)),
+            ConstantPoolEntry::FieldRef(x, y) => IndexedEntry::FieldRef(index_of(x), index_of(y)),
+            ConstantPoolEntry::MethodRef(x, y) => IndexedEntry::MethodRef(index_of(x), index_of(y)),
+            ConstantPoolEntry::InterfaceMethodRef(x, y) => IndexedEntry::InterfaceMethodRef(index_of(x), index_of(y)),
+            ConstantPoolEntry::NameAndType(x, y) => IndexedEntry::NameAndType(index_of(x), index_of(y)),
+            ConstantPoolEntry::MethodHandle(x, y) => IndexedEntry::MethodHandle(*x, index_of(y)),
+            ConstantPoolEntry::MethodType(x) => IndexedEntry::MethodType(index_of(x)),
+            ConstantPoolEntry::Dynamic(x, y) => IndexedEntry::Dynamic(*x, index_of(y)),
+            ConstantPoolEntry::InvokeDynamic(x, y) => IndexedEntry::InvokeDynamic(*x, index_of(y)),
+            ConstantPoolEntry::ModuleInfo(x) => IndexedEntry::ModuleInfo(index_of(x)),
+            ConstantPoolEntry::PackageInfo(x) => IndexedEntry::PackageInfo(index_of(x)),
+        };
+        Some(indexed)
+    }).collect()
+}
@@ -0,0 +1,475 @@
+//! Dependency extraction, similar to `jdeps`: the classes that a class refers to, and the
+//! dependencies between the packages and modules of a set of classes.
+//!
+//! Class names are in internal form, such as `java/lang/String`, and package names likewise,
+//! such as `java/lang`. Array types are reported as the class of their elements, and primitive
+//! types are not reported.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use crate::attributes::{Annotation, AnnotationElementValue, AttributeData, AttributeInfo, StackMapEntry, VerificationType};
+use crate::bytecode::Opcode;
+use crate::constant_pool::{BootstrapArgument, ConstantPoolItem, Loadable, MemberRef, MethodHandle};
+use crate::names::signature_classes;
+use crate::{ClassAccessFlags, ClassFile, ParseError};
+
+/// The name given to the module of classes in packages that no named module contains.
+pub const UNNAMED_MODULE: &str = "ALL-UNNAMED";
+
+/// Where in a class file a dependency was found.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum DependencyKind {
+    Superclass,
+    Interface,
+    /// The descriptor of a field or record component.
+    FieldType,
+    /// The descriptor of a method.
+    MethodType,
+    /// A generic signature, of the class, a member or a local variable.
+    Signature,
+    /// The type of an annotation, or a class or enum constant used as an annotation value.
+    Annotation,
+    /// The type caught by an exception handler.
+    ExceptionHandler,
+    /// The `Exceptions` attribute of a method, from its `throws` clause.
+    ThrowsClause,
+    /// An operand of an instruction.
+    Bytecode,
+    /// A constant pool entry.
+    ConstantPool,
+    /// Another attribute, such as `InnerClasses`, `NestMembers` or `LocalVariableTable`.
+    Attribute,
+}
+
+/// Returns the classes a class depends on, each with the places it was found, excluding the
+/// class itself. Fails if an attribute cannot be decoded or a descriptor or signature is
+/// malformed.
+pub fn class_dependencies(class: &ClassFile) -> Result<BTreeMap<String, BTreeSet<DependencyKind>>, ParseError> {
+    let mut collector = Collector {
+        this_class: &class.this_class,
+        dependencies: BTreeMap::new(),
+    };
+    collector.collect(class).map_err(|e| err!(e, "class {}", class.this_class))?;
+    Ok(collector.dependencies)
+}
+
+/// Returns the package of a class, or the empty string for a class in the unnamed package.
+pub fn package_name(class_name: &str) -> &str {
+    class_name.rsplit_once('/').map_or("", |(package, _)| package)
+}
+
+struct Collector<'a> {
+    this_class: &'a str,
+    dependencies: BTreeMap<String, BTreeSet<DependencyKind>>,
+}
+
+impl<'a> Collector<'a> {
+    fn add(&mut self, class_name: &str, kind: DependencyKind) {
+        if class_name != self.this_class {
+            self.dependencies.entry(class_name.to_string()).or_default().insert(kind);
+        }
+    }
+
+    // Adds the classes in a field or method descriptor, or a generic signature
+    fn add_signature(&mut self, signature: &str, kind: DependencyKind) -> Result<(), ParseError> {
+        for class in signature_classes(signature)? {
+            self.add(&class.name, kind);
+        }
+        Ok(())
+    }
+
+    // Adds a class named as in a CONSTANT_Class entry, which names arrays by their descriptor
+    fn add_class_info(&mut self, class_name: &str, kind: DependencyKind) -> Result<(), ParseError> {
+        if class_name.starts_with('[') {
+            self.add_signature(class_name, kind)
+        } else {
+            self.add(class_name, kind);
+            Ok(())
+        }
+    }
+
+    fn add_member(&mut self, member: &MemberRef, kind: DependencyKind) -> Result<(), ParseError> {
+        self.add_class_info(&member.class_name, kind)?;
+        self.add_signature(&member.name_and_type.descriptor, kind)
+    }
+
+    fn add_method_handle(&mut self, handle: &MethodHandle, kind: DependencyKind) -> Result<(), ParseError> {
+        self.add_class_info(&handle.class_name, kind)?;
+        self.add_signature(&handle.member_ref.descriptor, kind)
+    }
+
+    fn add_loadable(&mut self, loadable: &Loadable, kind: DependencyKind) -> Result<(), ParseError> {
+        match loadable {
+            Loadable::ClassInfo(class_name) => self.add_class_info(class_name, kind),
+            Loadable::MethodHandle(handle) => self.add_method_handle(handle, kind),
+            Loadable::MethodType(descriptor) => self.add_signature(descriptor, kind),
+            Loadable::Dynamic(dynamic) => self.add_signature(&dynamic.name_and_type.descriptor, kind),
+            Loadable::LiteralConstant(_) => Ok(()),
+        }
+    }
+
+    fn collect(&mut self, class: &ClassFile) -> Result<(), ParseError> {
+        if let Some(super_class) = &class.super_class {
+            self.add(super_class, DependencyKind::Superclass);
+        }
+        for interface in &class.interfaces {
+            self.add(interface, DependencyKind::Interface);
+        }
+        for field in &class.fields {
+            self.add_signature(&field.descriptor, DependencyKind::FieldType)?;
+            self.add_attributes(&field.attributes).map_err(|e| err!(e, "field {}", field.name))?;
+        }
+        for method in &class.methods {
+            self.add_signature(&method.descriptor, DependencyKind::MethodType)?;
+            self.add_attributes(&method.attributes).map_err(|e| err!(e, "method {}", method.name))?;
+        }
+        self.add_attributes(&class.attributes)?;
+        for item in class.constantpool_iter() {
+            let kind = DependencyKind::ConstantPool;
+            match item {
+                ConstantPoolItem::ClassInfo(class_name) => self.add_class_info(&class_name, kind)?,
+                ConstantPoolItem::FieldRef(member)
+                | ConstantPoolItem::MethodRef(member)
+                | ConstantPoolItem::InterfaceMethodRef(member) => self.add_member(&member, kind)?,
+                ConstantPoolItem::MethodHandle(handle) => self.add_method_handle(&handle, kind)?,
+                ConstantPoolItem::MethodType(descriptor) => self.add_signature(&descriptor, kind)?,
+                ConstantPoolItem::Dynamic(dynamic) => self.add_signature(&dynamic.name_and_type.descriptor, kind)?,
+                ConstantPoolItem::InvokeDynamic(dynamic) => self.add_signature(&dynamic.name_and_type.descriptor, kind)?,
+                _ => (),
+            }
+        }
+        Ok(())
+    }
+
+    fn add_annotation(&mut self, annotation: &Annotation) -> Result<(), ParseError> {
+        self.add_signature(&annotation.type_descriptor, DependencyKind::Annotation)?;
+        for element in &annotation.elements {
+            self.add_element_value(&element.value)?;
+        }
+        Ok(())
+    }
+
+    fn add_element_value(&mut self, value: &AnnotationElementValue) -> Result<(), ParseError> {
+        match value {
+            AnnotationElementValue::EnumConstant { type_name, .. } => self.add_signature(type_name, DependencyKind::Annotation),
+            AnnotationElementValue::ClassLiteral { class_name } => self.add_signature(class_name, DependencyKind::Annotation),
+            AnnotationElementValue::AnnotationValue(annotation) => self.add_annotation(annotation),
+            AnnotationElementValue::ArrayValue(values) => {
+                for value in values {
+                    self.add_element_value(value)?;
+                }
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn add_verification_types(&mut self, types: &[VerificationType]) -> Result<(), ParseError> {
+        for verification_type in types {
+            if let VerificationType::Object { class_name } = verification_type {
+                self.add_class_info(class_name, DependencyKind::Attribute)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn add_attributes(&mut self, attributes: &[AttributeInfo]) -> Result<(), ParseError> {
+        for attr in attributes {
+            self.add_attribute(attr.decoded()?).map_err(|e| err!(e, "attribute {}", attr.name))?;
+        }
+        Ok(())
+    }
+
+    fn add_attribute(&mut self, data: &AttributeData) -> Result<(), ParseError> {
+        match data {
+            AttributeData::Code(code) => {
+                for entry in &code.exception_table {
+                    if let Some(catch_type) = &entry.catch_type {
+                        self.add(catch_type, DependencyKind::ExceptionHandler);
+                    }
+                }
+                if let Some(bytecode) = &code.bytecode {
+                    for (_, opcode) in &bytecode.opcodes {
+                        self.add_opcode(opcode)?;
+                    }
+                }
+                self.add_attributes(&code.attributes)?;
+            }
+            AttributeData::Exceptions(exceptions) => {
+                for exception in exceptions {
+                    self.add(exception, DependencyKind::ThrowsClause);
+                }
+            }
+            AttributeData::Signature(signature) => self.add_signature(signature, DependencyKind::Signature)?,
+            AttributeData::RuntimeVisibleAnnotations(annotations) | AttributeData::RuntimeInvisibleAnnotations(annotations) => {
+                for annotation in annotations {
+                    self.add_annotation(annotation)?;
+                }
+            }
+            AttributeData::RuntimeVisibleParameterAnnotations(parameters)
+            | AttributeData::RuntimeInvisibleParameterAnnotations(parameters) => {
+                for annotation in parameters.iter().flat_map(|parameter| &parameter.annotations) {
+                    self.add_annotation(annotation)?;
+                }
+            }
+            AttributeData::RuntimeVisibleTypeAnnotations(annotations) | AttributeData::RuntimeInvisibleTypeAnnotations(annotations) => {
+                for annotation in annotations {
+                    self.add_annotation(&annotation.annotation)?;
+                }
+            }
+            AttributeData::AnnotationDefault(value) => self.add_element_value(value)?,
+            AttributeData::StackMapTable(entries) => {
+                for entry in entries {
+                    match entry {
+                        StackMapEntry::SameLocals1StackItem { stack, .. } => {
+                            self.add_verification_types(std::slice::from_ref(stack))?
+                        }
+                        StackMapEntry::Append { locals, .. } => self.add_verification_types(locals)?,
+                        StackMapEntry::FullFrame { locals, stack, .. } => {
+                            self.add_verification_types(locals)?;
+                            self.add_verification_types(stack)?;
+                        }
+                        _ => (),
+                    }
+                }
+            }
+            AttributeData::LocalVariableTable(entries) => {
+                for entry in entries {
+                    self.add_signature(&entry.descriptor, DependencyKind::Attribute)?;
+                }
+            }
+            AttributeData::LocalVariableTypeTable(entries) => {
+                for entry in entries {
+                    self.add_signature(&entry.signature, DependencyKind::Signature)?;
+                }
+            }
+            AttributeData::InnerClasses(entries) => {
+                for entry in entries {
+                    self.add(&entry.inner_class_info, DependencyKind::Attribute);
+                    if let Some(outer) = &entry.outer_class_info {
+                        self.add(outer, DependencyKind::Attribute);
+                    }
+                }
+            }
+            AttributeData::EnclosingMethod { class_name, .. } => self.add(class_name, DependencyKind::Attribute),
+            AttributeData::NestHost(class_name) => self.add(class_name, DependencyKind::Attribute),
+            AttributeData::NestMembers(class_names) => {
+                for class_name in class_names {
+                    self.add(class_name, DependencyKind::Attribute);
+                }
+            }
+            AttributeData::BootstrapMethods(entries) => {
+                for entry in entries {
+                    self.add_method_handle(&entry.method, DependencyKind::Attribute)?;
+                    for argument in &entry.arguments {
+                        match argument {
+                            BootstrapArgument::ClassInfo(class_name) => self.add_class_info(class_name, DependencyKind::Attribute)?,
+                            BootstrapArgument::MethodHandle(handle) => self.add_method_handle(handle, DependencyKind::Attribute)?,
+                            BootstrapArgument::MethodType(descriptor) => self.add_signature(descriptor, DependencyKind::Attribute)?,
+                            BootstrapArgument::LiteralConstant(_) => (),
+                        }
+                    }
+                }
+            }
+            AttributeData::Record(components) => {
+                for component in components {
+                    self.add_signature(&component.descriptor, DependencyKind::FieldType)?;
+                    self.add_attributes(&component.attributes)?;
+                }
+            }
+            AttributeData::Module(module) => {
+                for class_name in module.uses.iter().chain(module.provides.iter().flat_map(|p| {
+                    std::iter::once(&p.service_interface_name).chain(&p.provides_with)
+                })) {
+                    self.add(class_name, DependencyKind::Attribute);
+                }
+            }
+            AttributeData::ModuleMainClass(class_name) => self.add(class_name, DependencyKind::Attribute),
+            _ => (),
+        }
+        Ok(())
+    }
+
+    fn add_opcode(&mut self, opcode: &Opcode) -> Result<(), ParseError> {
+        let kind = DependencyKind::Bytecode;
+        match opcode {
+            Opcode::Anewarray(class_name)
+            | Opcode::Checkcast(class_name)
+            | Opcode::Instanceof(class_name)
+            | Opcode::Multianewarray(class_name, _)
+            | Opcode::New(class_name) => self.add_class_info(class_name, kind),
+            Opcode::Getfield(member)
+            | Opcode::Getstatic(member)
+            | Opcode::Putfield(member)
+            | Opcode::Putstatic(member)
+            | Opcode::Invokeinterface(member, _)
+            | Opcode::Invokespecial(member)
+            | Opcode::Invokestatic(member)
+            | Opcode::Invokevirtual(member) => self.add_member(member, kind),
+            Opcode::Invokedynamic(dynamic) => self.add_signature(&dynamic.name_and_type.descriptor, kind),
+            Opcode::Ldc(loadable) | Opcode::LdcW(loadable) | Opcode::Ldc2W(loadable) => self.add_loadable(loadable, kind),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// The dependencies of a set of classes, aggregated by class, package and module.
+///
+/// Packages are assigned to modules by adding `module-info` classes, whose `ModulePackages`
+/// and `Module` attributes list the packages of their module, or with `module_package`.
+#[derive(Clone, Debug, Default)]
+pub struct DependencyGraph {
+    classes: BTreeMap<String, BTreeMap<String, BTreeSet<DependencyKind>>>,
+    modules: HashMap<String, String>,
+}
+
+impl DependencyGraph {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds the dependencies of a class. A `module-info` class instead assigns the packages
+    /// it lists to its module.
+    pub fn add_class(&mut self, class: &ClassFile) -> Result<&mut DependencyGraph, ParseError> {
+        if class.access_flags.contains(ClassAccessFlags::MODULE) {
+            let mut module_name = None;
+            let mut packages = Vec::new();
+            for attr in &class.attributes {
+                match attr.decoded()? {
+                    AttributeData::Module(module) => {
+                        module_name = Some(module.name.clone());
+                        packages.extend(module.exports.iter().map(|e| e.package_name.clone()));
+                        packages.extend(module.opens.iter().map(|o| o.package_name.clone()));
+                    }
+                    AttributeData::ModulePackages(names) => packages.extend(names.iter().cloned()),
+                    _ => (),
+                }
+            }
+            let module_name = module_name.ok_or_else(|| err!("No Module attribute found in {}", class.this_class))?;
+            for package in packages {
+                self.module_package(&package, &module_name);
+            }
+        } else {
+            let dependencies = class_dependencies(class)?;
+            self.classes.insert(class.this_class.clone(), dependencies);
+        }
+        Ok(self)
+    }
+
+    /// Assigns a package to a module, for packages of classes that are not added, such as
+    /// those of the JDK.
+    pub fn module_package(&mut self, package: &str, module: &str) -> &mut DependencyGraph {
+        self.modules.insert(package.to_string(), module.to_string());
+        self
+    }
+
+    /// Returns the module of a package, or `UNNAMED_MODULE` if no module contains it.
+    pub fn module_of(&self, package: &str) -> &str {
+        self.modules.get(package).map_or(UNNAMED_MODULE, String::as_str)
+    }
+
+    /// Returns the classes that were added, each with the classes it depends on.
+    pub fn classes(&self) -> &BTreeMap<String, BTreeMap<String, BTreeSet<DependencyKind>>> {
+        &self.classes
+    }
+
+    /// Returns the packages of the added classes, each with the other packages they depend on.
+    pub fn package_dependencies(&self) -> BTreeMap<String, BTreeSet<String>> {
+        self.aggregate(|class_name| package_name(class_name).to_string())
+    }
+
+    /// Returns the modules of the added classes, each with the other modules they depend on.
+    pub fn module_dependencies(&self) -> BTreeMap<String, BTreeSet<String>> {
+        self.aggregate(|class_name| self.module_of(package_name(class_name)).to_string())
+    }
+
+    fn aggregate(&self, group: impl Fn(&str) -> String) -> BTreeMap<String, BTreeSet<String>> {
+        let mut result: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
+        for (class_name, dependencies) in &self.classes {
+            let from = group(class_name);
+            let targets = result.entry(from.clone()).or_default();
+            for dependency in dependencies.keys() {
+                let to = group(dependency);
+                if to != from {
+                    targets.insert(to);
+                }
+            }
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembly::parse_assembly;
+
+    fn assembled(text: &str) -> ClassFile {
+        parse_assembly(&format!(".version 55 0\n{}\n.end class\n", text)).unwrap()
+    }
+
+    #[test]
+    fn test_class_dependencies() {
+        let class = assembled(
+            r#"
+.class public p/Service
+.super p/Base
+.implements q/Api
+.field private cache Ljava/util/Map;
+    .signature "Ljava/util/Map<Ljava/lang/String;Lq/Item;>;"
+.end field
+.method public run ([Lq/Request;)V
+    .exceptions java/io/IOException
+    .runtimevisibleannotations
+        .annotation Lr/Audited;
+            level = enum Lr/Level; HIGH
+            types = array [ class Lr/Checked; ]
+        .end annotation
+    .end runtimevisibleannotations
+    .code stack 2 locals 2
+    Start:
+        new s/Worker
+        invokestatic t/Util helper ()V
+        return
+    End:
+    Handler:
+        athrow
+        .catch u/Failure Start End Handler
+    .end code
+.end method
+"#,
+        );
+        let dependencies = class_dependencies(&class).unwrap();
+        let kinds = |name: &str| dependencies[name].iter().copied().collect::<Vec<_>>();
+        assert_eq!(kinds("p/Base"), vec![DependencyKind::Superclass, DependencyKind::ConstantPool]);
+        assert_eq!(kinds("q/Api"), vec![DependencyKind::Interface, DependencyKind::ConstantPool]);
+        assert_eq!(kinds("java/util/Map"), vec![DependencyKind::FieldType, DependencyKind::Signature]);
+        assert_eq!(kinds("q/Item"), vec![DependencyKind::Signature]);
+        assert_eq!(kinds("q/Request"), vec![DependencyKind::MethodType]);
+        assert_eq!(kinds("java/io/IOException"), vec![DependencyKind::ThrowsClause, DependencyKind::ConstantPool]);
+        assert_eq!(kinds("r/Level"), vec![DependencyKind::Annotation]);
+        assert_eq!(kinds("r/Checked"), vec![DependencyKind::Annotation]);
+        assert_eq!(kinds("s/Worker"), vec![DependencyKind::Bytecode, DependencyKind::ConstantPool]);
+        assert_eq!(kinds("t/Util"), vec![DependencyKind::Bytecode, DependencyKind::ConstantPool]);
+        assert_eq!(kinds("u/Failure"), vec![DependencyKind::ExceptionHandler, DependencyKind::ConstantPool]);
+        assert!(!dependencies.contains_key("p/Service"));
+
+        let module = assembled(
+            r#"
+.class module module-info
+.module app.core
+.end module
+.modulepackages p
+"#,
+        );
+        let mut graph = DependencyGraph::new();
+        graph.add_class(&class).unwrap().add_class(&module).unwrap().module_package("java/util", "java.base");
+        let packages = graph.package_dependencies();
+        assert_eq!(
+            packages["p"].iter().map(String::as_str).collect::<Vec<_>>(),
+            vec!["java/io", "java/lang", "java/util", "q", "r", "s", "t", "u"]
+        );
+        let modules = graph.module_dependencies();
+        assert_eq!(modules["app.core"].iter().map(String::as_str).collect::<Vec<_>>(), vec![UNNAMED_MODULE, "java.base"]);
+    }
+}
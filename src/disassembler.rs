@@ -0,0 +1,1702 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+
+use crate::attributes::{
+    Annotation, AnnotationElementValue, AttributeData, AttributeInfo, BootstrapMethodEntry, CodeData,
+    InnerClassAccessFlags, InnerClassEntry, LocalVariableEntry, LocalVariableTypeEntry, MethodParameterAccessFlags,
+    ModuleData, ParameterAnnotation, RecordComponentEntry, StackMapEntry, TypeAnnotation, TypeAnnotationTarget,
+    TypeAnnotationTargetPathKind, VerificationType,
+};
+use crate::bytecode::{ByteCode, Opcode, PrimitiveArrayType};
+use crate::constant_pool::{
+    indexed_constant_pool, BootstrapArgument, ConstantPoolReader, IndexedEntry, LiteralConstant, Loadable, MemberRef,
+    MethodHandle, ReferenceKind,
+};
+use crate::{read_u2, ClassAccessFlags, ClassFile, FieldAccessFlags, FieldInfo, MethodAccessFlags, MethodInfo};
+
+/// Column (relative to the indentation of the line) at which javap starts trailing comments.
+const COMMENT_COLUMN: usize = 40;
+
+/// Renders a class file the way `javap -c -v -p` does. The `Classfile`, `Last modified` and
+/// checksum lines at the top of javap output are omitted since they describe the file the class
+/// was read from rather than the class itself, so the output of this function should match
+/// javap's output from its fourth line onwards.
+///
+/// Constant pool indices (`#n`) for instruction and attribute operands are recovered by looking
+/// up the resolved values in the constant pool. If a class file contains duplicate constant pool
+/// entries the first matching index is shown, which may differ from the one the class file used.
+/// Type annotation targets are printed with the first target type name that matches the parsed
+/// target information.
+#[must_use]
+pub fn disassemble(class: &ClassFile) -> String {
+    let mut disassembler = Disassembler::new(class);
+    disassembler.write_class();
+    disassembler.out
+}
+
+struct Disassembler<'a> {
+    class: &'a ClassFile,
+    pool: Vec<Option<IndexedEntry>>,
+    lookup: HashMap<(&'static str, String), u16>,
+    out: String,
+}
+
+impl<'a> Disassembler<'a> {
+    fn new(class: &'a ClassFile) -> Self {
+        let mut disassembler = Disassembler {
+            class,
+            pool: indexed_constant_pool(&class.constant_pool),
+            lookup: HashMap::new(),
+            out: String::new(),
+        };
+        for i in 1..disassembler.pool.len() {
+            let index = i as u16;
+            if let Some(tag) = disassembler.tag(index) {
+                let key = (tag, disassembler.entry_value(index));
+                disassembler.lookup.entry(key).or_insert(index);
+            }
+        }
+        disassembler
+    }
+
+    fn line(&mut self, indent: usize, text: &str) {
+        for _ in 0..indent {
+            self.out.push(' ');
+        }
+        self.out.push_str(text);
+        self.out.push('\n');
+    }
+
+    fn line_with_comment(&mut self, indent: usize, text: &str, comment: &str) {
+        let mut line = text.to_string();
+        while line.len() < COMMENT_COLUMN {
+            line.push(' ');
+        }
+        if !line.ends_with(' ') {
+            line.push(' ');
+        }
+        line.push_str("// ");
+        line.push_str(comment);
+        self.line(indent, &line);
+    }
+
+    fn entry(&self, index: u16) -> Option<&IndexedEntry> {
+        self.pool.get(usize::from(index)).and_then(Option::as_ref)
+    }
+
+    fn tag(&self, index: u16) -> Option<&'static str> {
+        let tag = match self.entry(index)? {
+            IndexedEntry::Utf8(_) |
+            IndexedEntry::Utf8Bytes(_) => "Utf8",
+            IndexedEntry::Integer(_) => "Integer",
+            IndexedEntry::Float(_) => "Float",
+            IndexedEntry::Long(_) => "Long",
+            IndexedEntry::Double(_) => "Double",
+            IndexedEntry::ClassInfo(_) => "Class",
+            IndexedEntry::String(_) => "String",
+            IndexedEntry::FieldRef(_, _) => "Fieldref",
+            IndexedEntry::MethodRef(_, _) => "Methodref",
+            IndexedEntry::InterfaceMethodRef(_, _) => "InterfaceMethodref",
+            IndexedEntry::NameAndType(_, _) => "NameAndType",
+            IndexedEntry::MethodHandle(_, _) => "MethodHandle",
+            IndexedEntry::MethodType(_) => "MethodType",
+            IndexedEntry::Dynamic(_, _) => "Dynamic",
+            IndexedEntry::InvokeDynamic(_, _) => "InvokeDynamic",
+            IndexedEntry::ModuleInfo(_) => "Module",
+            IndexedEntry::PackageInfo(_) => "Package",
+        };
+        Some(tag)
+    }
+
+    fn utf8(&self, index: u16) -> Cow<'_, str> {
+        match self.entry(index) {
+            Some(IndexedEntry::Utf8(x)) => Cow::Borrowed(x),
+            Some(IndexedEntry::Utf8Bytes(x)) => String::from_utf8_lossy(x),
+            _ => Cow::Borrowed(""),
+        }
+    }
+
+    /// The value of a constant pool entry as javap prints it in comments.
+    fn entry_value(&self, index: u16) -> String {
+        match self.entry(index) {
+            None => String::new(),
+            Some(IndexedEntry::Utf8(x)) => escape(x),
+            Some(IndexedEntry::Utf8Bytes(x)) => escape(&String::from_utf8_lossy(x)),
+            Some(IndexedEntry::Integer(v)) => v.to_string(),
+            Some(IndexedEntry::Float(v)) => format!("{}f", java_float(f32::from_bits(*v))),
+            Some(IndexedEntry::Long(v)) => format!("{}l", v),
+            Some(IndexedEntry::Double(v)) => format!("{}d", java_double(f64::from_bits(*v))),
+            Some(IndexedEntry::ClassInfo(x)) => check_name(&self.utf8(*x)),
+            Some(IndexedEntry::String(x)) => escape(&self.utf8(*x)),
+            Some(IndexedEntry::FieldRef(x, y)) |
+            Some(IndexedEntry::MethodRef(x, y)) |
+            Some(IndexedEntry::InterfaceMethodRef(x, y)) => format!("{}.{}", self.entry_value(*x), self.entry_value(*y)),
+            Some(IndexedEntry::NameAndType(x, y)) => format!("{}:{}", check_name(&self.utf8(*x)), escape(&self.utf8(*y))),
+            Some(IndexedEntry::MethodHandle(kind, x)) => format!("{} {}", reference_kind_name(*kind), self.entry_value(*x)),
+            Some(IndexedEntry::MethodType(x)) => format!(" {}", escape(&self.utf8(*x))),
+            Some(IndexedEntry::Dynamic(bsm, x)) |
+            Some(IndexedEntry::InvokeDynamic(bsm, x)) => format!("#{}:{}", bsm, self.entry_value(*x)),
+            Some(IndexedEntry::ModuleInfo(x)) |
+            Some(IndexedEntry::PackageInfo(x)) => escape(&self.utf8(*x)),
+        }
+    }
+
+    fn index_of(&self, tag: &'static str, value: String) -> Option<u16> {
+        self.lookup.get(&(tag, value)).copied()
+    }
+
+    fn ref_to(&self, tag: &'static str, value: String) -> String {
+        match self.index_of(tag, value) {
+            Some(index) => format!("#{}", index),
+            None => "#?".to_string(),
+        }
+    }
+
+    fn utf8_ref(&self, value: &str) -> String {
+        self.ref_to("Utf8", escape(value))
+    }
+
+    fn class_ref(&self, class_name: &str) -> String {
+        self.ref_to("Class", check_name(class_name))
+    }
+
+    fn member_value(class_name: &str, name: &str, descriptor: &str) -> String {
+        format!("{}.{}:{}", check_name(class_name), check_name(name), escape(descriptor))
+    }
+
+    fn method_handle_value(handle: &MethodHandle) -> String {
+        format!(
+            "{} {}",
+            reference_kind_name(handle.kind),
+            Self::member_value(&handle.class_name, &handle.member_ref.name, &handle.member_ref.descriptor)
+        )
+    }
+
+    /// Returns the tag and value used to look up a literal constant in the constant pool.
+    fn literal_key(literal: &LiteralConstant) -> (&'static str, String) {
+        match literal {
+            LiteralConstant::Integer(v) => ("Integer", v.to_string()),
+            LiteralConstant::Float(v) => ("Float", format!("{}f", java_float(*v))),
+            LiteralConstant::Long(v) => ("Long", format!("{}l", v)),
+            LiteralConstant::Double(v) => ("Double", format!("{}d", java_double(*v))),
+            LiteralConstant::String(s) => ("String", escape(s)),
+            LiteralConstant::StringBytes(b) => ("String", escape(&String::from_utf8_lossy(b))),
+        }
+    }
+
+    fn literal_comment(literal: &LiteralConstant) -> String {
+        match literal {
+            LiteralConstant::Integer(v) => format!("int {}", v),
+            LiteralConstant::Float(v) => format!("float {}f", java_float(*v)),
+            LiteralConstant::Long(v) => format!("long {}l", v),
+            LiteralConstant::Double(v) => format!("double {}d", java_double(*v)),
+            LiteralConstant::String(s) => format!("String {}", escape(s)),
+            LiteralConstant::StringBytes(b) => format!("String {}", escape(&String::from_utf8_lossy(b))),
+        }
+    }
+
+    /// Returns the constant pool reference and comment for a constant loaded by an ldc instruction.
+    fn loadable_operand(&self, loadable: &Loadable) -> (String, String) {
+        match loadable {
+            Loadable::LiteralConstant(literal) => {
+                let (tag, value) = Self::literal_key(literal);
+                (self.ref_to(tag, value), Self::literal_comment(literal))
+            }
+            Loadable::ClassInfo(name) => (self.class_ref(name), format!("class {}", check_name(name))),
+            Loadable::MethodHandle(handle) => {
+                let value = Self::method_handle_value(handle);
+                (self.ref_to("MethodHandle", value.clone()), format!("MethodHandle {}", value))
+            }
+            Loadable::MethodType(descriptor) => {
+                (self.ref_to("MethodType", format!(" {}", escape(descriptor))), format!("MethodType {}", escape(descriptor)))
+            }
+            Loadable::Dynamic(dynamic) => {
+                let value = format!(
+                    "#{}:{}:{}",
+                    dynamic.attr_index,
+                    check_name(&dynamic.name_and_type.name),
+                    escape(&dynamic.name_and_type.descriptor)
+                );
+                (self.ref_to("Dynamic", value.clone()), format!("Dynamic {}", value))
+            }
+        }
+    }
+
+    /// Returns the constant pool reference and comment for a field or method referenced by an
+    /// instruction. Members of the class being disassembled are shown without the class name.
+    fn member_operand(&self, member: &MemberRef, tags: &[&'static str]) -> (String, String) {
+        let value = Self::member_value(&member.class_name, &member.name_and_type.name, &member.name_and_type.descriptor);
+        let mut found = None;
+        for tag in tags {
+            if let Some(index) = self.index_of(tag, value.clone()) {
+                found = Some((*tag, index));
+                break;
+            }
+        }
+        let (tag, reference) = match found {
+            Some((tag, index)) => (tag, format!("#{}", index)),
+            None => (tags[0], "#?".to_string()),
+        };
+        let kind = match tag {
+            "Fieldref" => "Field",
+            "InterfaceMethodref" => "InterfaceMethod",
+            _ => "Method",
+        };
+        let shown = if member.class_name == self.class.this_class {
+            format!("{}:{}", check_name(&member.name_and_type.name), escape(&member.name_and_type.descriptor))
+        } else {
+            value
+        };
+        (reference, format!("{} {}", kind, shown))
+    }
+
+    fn write_class(&mut self) {
+        let class = self.class;
+        for attr in &class.attributes {
+            if let Ok(AttributeData::SourceFile(source_file)) = attr.decoded() {
+                self.line(2, &format!("Compiled from \"{}\"", source_file));
+            }
+        }
+        let declaration = self.class_declaration();
+        self.line(0, &declaration);
+        self.line(2, &format!("minor version: {}", class.minor_version));
+        self.line(2, &format!("major version: {}", class.major_version));
+        self.line(2, &format!("flags: {}", flags_text(class.access_flags.bits(), CLASS_FLAG_NAMES)));
+        let this_ref = self.class_ref(&class.this_class);
+        self.line_with_comment(2, &format!("this_class: {}", this_ref), &check_name(&class.this_class));
+        match &class.super_class {
+            Some(super_class) => {
+                let super_ref = self.class_ref(super_class);
+                self.line_with_comment(2, &format!("super_class: {}", super_ref), &check_name(super_class));
+            }
+            None => self.line(2, "super_class: #0"),
+        }
+        self.line(2, &format!(
+            "interfaces: {}, fields: {}, methods: {}, attributes: {}",
+            class.interfaces.len(),
+            class.fields.len(),
+            class.methods.len(),
+            class.attributes.len()
+        ));
+        self.write_constant_pool();
+        self.line(0, "{");
+        let mut first = true;
+        for field in &class.fields {
+            if !first {
+                self.line(0, "");
+            }
+            first = false;
+            self.write_field(field);
+        }
+        for method in &class.methods {
+            if !first {
+                self.line(0, "");
+            }
+            first = false;
+            self.write_method(method);
+        }
+        self.line(0, "}");
+        self.write_attributes(0, &class.attributes, None);
+    }
+
+    fn class_declaration(&self) -> String {
+        let class = self.class;
+        let flags = class.access_flags;
+        let mut text = String::new();
+        if flags.contains(ClassAccessFlags::MODULE) {
+            if let Some(module) = class.attributes.iter().find_map(|attr| match attr.decoded() {
+                Ok(AttributeData::Module(module)) => Some(module),
+                _ => None,
+            }) {
+                if module.access_flags.contains(crate::attributes::ModuleAccessFlags::OPEN) {
+                    text.push_str("open ");
+                }
+                text.push_str("module ");
+                text.push_str(&module.name);
+                if let Some(version) = &module.version {
+                    text.push('@');
+                    text.push_str(version);
+                }
+                return text;
+            }
+        }
+        let is_interface = flags.contains(ClassAccessFlags::INTERFACE);
+        if flags.contains(ClassAccessFlags::PUBLIC) {
+            text.push_str("public ");
+        }
+        if flags.contains(ClassAccessFlags::FINAL) {
+            text.push_str("final ");
+        }
+        if flags.contains(ClassAccessFlags::ABSTRACT) && !is_interface {
+            text.push_str("abstract ");
+        }
+        text.push_str(if is_interface { "interface " } else { "class " });
+        text.push_str(&java_name(&class.this_class));
+        let signature = find_signature(&class.attributes).and_then(parse_class_signature);
+        match signature {
+            Some((type_params, super_class, interfaces)) => {
+                text.push_str(&type_params);
+                if is_interface {
+                    if !interfaces.is_empty() {
+                        text.push_str(" extends ");
+                        text.push_str(&interfaces.join(", "));
+                    }
+                } else {
+                    text.push_str(" extends ");
+                    text.push_str(&super_class);
+                    if !interfaces.is_empty() {
+                        text.push_str(" implements ");
+                        text.push_str(&interfaces.join(", "));
+                    }
+                }
+            }
+            None => {
+                if !is_interface {
+                    if let Some(super_class) = &class.super_class {
+                        if super_class != "java/lang/Object" {
+                            text.push_str(" extends ");
+                            text.push_str(&java_name(super_class));
+                        }
+                    }
+                }
+                for (i, interface) in class.interfaces.iter().enumerate() {
+                    text.push_str(match (i, is_interface) {
+                        (0, true) => " extends ",
+                        (0, false) => " implements ",
+                        _ => ",",
+                    });
+                    text.push_str(&java_name(interface));
+                }
+            }
+        }
+        text
+    }
+
+    fn write_constant_pool(&mut self) {
+        self.line(0, "Constant pool:");
+        let width = (self.pool.len().saturating_sub(1)).to_string().len() + 1;
+        for i in 1..self.pool.len() {
+            let index = i as u16;
+            let tag = match self.tag(index) {
+                Some(tag) => tag,
+                None => continue,
+            };
+            let args = match self.entry(index) {
+                Some(IndexedEntry::ClassInfo(x)) |
+                Some(IndexedEntry::String(x)) |
+                Some(IndexedEntry::MethodType(x)) |
+                Some(IndexedEntry::ModuleInfo(x)) |
+                Some(IndexedEntry::PackageInfo(x)) => format!("#{}", x),
+                Some(IndexedEntry::FieldRef(x, y)) |
+                Some(IndexedEntry::MethodRef(x, y)) |
+                Some(IndexedEntry::InterfaceMethodRef(x, y)) => format!("#{}.#{}", x, y),
+                Some(IndexedEntry::NameAndType(x, y)) => format!("#{}:#{}", x, y),
+                Some(IndexedEntry::MethodHandle(kind, x)) => format!("{}:#{}", reference_kind_number(*kind), x),
+                Some(IndexedEntry::Dynamic(bsm, x)) |
+                Some(IndexedEntry::InvokeDynamic(bsm, x)) => format!("#{}:#{}", bsm, x),
+                _ => {
+                    let value = self.entry_value(index);
+                    self.line(2, &format!("{:>width$} = {:<18} {}", format!("#{}", index), tag, value, width = width));
+                    continue;
+                }
+            };
+            let text = format!("{:>width$} = {:<18} {}", format!("#{}", index), tag, args, width = width);
+            let value = self.entry_value(index);
+            self.line_with_comment(2, &text, &value);
+        }
+    }
+
+    fn write_field(&mut self, field: &FieldInfo) {
+        let mut text = String::new();
+        for (flag, name) in FIELD_MODIFIERS {
+            if field.access_flags.bits() & flag != 0 {
+                text.push_str(name);
+                text.push(' ');
+            }
+        }
+        let field_type = find_signature(&field.attributes)
+            .and_then(parse_full_type)
+            .or_else(|| parse_full_type(&field.descriptor))
+            .unwrap_or_else(|| field.descriptor.clone());
+        text.push_str(&format!("{} {};", field_type, field.name));
+        self.line(2, &text);
+        self.line(4, &format!("descriptor: {}", field.descriptor));
+        self.line(4, &format!("flags: {}", flags_text(field.access_flags.bits(), FIELD_FLAG_NAMES)));
+        self.write_attributes(4, &field.attributes, None);
+    }
+
+    fn write_method(&mut self, method: &MethodInfo) {
+        let declaration = self.method_declaration(method);
+        self.line(2, &declaration);
+        self.line(4, &format!("descriptor: {}", method.descriptor));
+        self.line(4, &format!("flags: {}", flags_text(method.access_flags.bits(), METHOD_FLAG_NAMES)));
+        self.write_attributes(4, &method.attributes, Some(method));
+    }
+
+    fn method_declaration(&self, method: &MethodInfo) -> String {
+        let flags = method.access_flags;
+        let mut text = String::new();
+        for (flag, name) in METHOD_MODIFIERS {
+            if flags.bits() & flag != 0 {
+                text.push_str(name);
+                text.push(' ');
+            }
+        }
+        let in_interface = self.class.access_flags.contains(ClassAccessFlags::INTERFACE);
+        if in_interface && !flags.intersects(MethodAccessFlags::ABSTRACT | MethodAccessFlags::STATIC | MethodAccessFlags::PRIVATE) && method.name != "<clinit>" {
+            text.push_str("default ");
+        }
+        if method.name == "<clinit>" {
+            text.push_str("{};");
+            return text;
+        }
+        let signature = find_signature(&method.attributes)
+            .and_then(parse_method_signature)
+            .or_else(|| parse_method_signature(&method.descriptor));
+        let (type_params, mut params, return_type, mut throws) = match signature {
+            Some(parsed) => parsed,
+            None => return format!("{}{};", text, method.name),
+        };
+        if throws.is_empty() {
+            for attr in &method.attributes {
+                if let Ok(AttributeData::Exceptions(exceptions)) = attr.decoded() {
+                    throws = exceptions.iter().map(|e| java_name(e)).collect();
+                }
+            }
+        }
+        if flags.contains(MethodAccessFlags::VARARGS) {
+            if let Some(last) = params.last_mut() {
+                if last.ends_with("[]") {
+                    last.truncate(last.len() - 2);
+                    last.push_str("...");
+                }
+            }
+        }
+        if !type_params.is_empty() {
+            text.push_str(&type_params);
+            text.push(' ');
+        }
+        if method.name == "<init>" {
+            text.push_str(&java_name(&self.class.this_class));
+        } else {
+            text.push_str(&return_type);
+            text.push(' ');
+            text.push_str(&method.name);
+        }
+        text.push('(');
+        text.push_str(&params.join(", "));
+        text.push(')');
+        if !throws.is_empty() {
+            text.push_str(" throws ");
+            text.push_str(&throws.join(", "));
+        }
+        text.push(';');
+        text
+    }
+
+    fn write_attributes(&mut self, indent: usize, attributes: &[AttributeInfo], method: Option<&MethodInfo>) {
+        for attr in attributes {
+            self.write_attribute(indent, attr, method);
+        }
+    }
+
+    fn write_attribute(&mut self, indent: usize, attr: &AttributeInfo, method: Option<&MethodInfo>) {
+        let data = match attr.decoded() {
+            Ok(data) => data,
+            Err(_) => {
+                self.line(indent, &format!("{}: <invalid attribute>", attr.name));
+                return;
+            }
+        };
+        match data {
+            AttributeData::ConstantValue(literal) => {
+                let text = format!("ConstantValue: {}", Self::literal_comment(literal));
+                self.line(indent, &text);
+            }
+            AttributeData::Code(code) => {
+                if let Some(method) = method {
+                    self.write_code(indent, method, code);
+                }
+            }
+            AttributeData::StackMapTable(entries) => self.write_stack_map_table(indent, entries),
+            AttributeData::Exceptions(exceptions) => {
+                self.line(indent, "Exceptions:");
+                let names: Vec<String> = exceptions.iter().map(|e| java_name(e)).collect();
+                self.line(indent + 2, &format!("throws {}", names.join(", ")));
+            }
+            AttributeData::InnerClasses(entries) => self.write_inner_classes(indent, entries),
+            AttributeData::EnclosingMethod { class_name, method } => {
+                let class_ref = self.class_ref(class_name);
+                let (method_ref, method_comment) = match method {
+                    Some(nat) => {
+                        let value = format!("{}:{}", check_name(&nat.name), escape(&nat.descriptor));
+                        (self.ref_to("NameAndType", value), format!(".{}", nat.name))
+                    }
+                    None => ("#0".to_string(), String::new()),
+                };
+                let text = format!("EnclosingMethod: {}.{}", class_ref, method_ref);
+                self.line_with_comment(indent, &text, &format!("{}{}", java_name(class_name), method_comment));
+            }
+            AttributeData::Synthetic => self.line(indent, "Synthetic: true"),
+            AttributeData::Deprecated => self.line(indent, "Deprecated: true"),
+            AttributeData::Signature(signature) => {
+                let text = format!("Signature: {}", self.utf8_ref(signature));
+                self.line_with_comment(indent, &text, &escape(signature));
+            }
+            AttributeData::SourceFile(source_file) => self.line(indent, &format!("SourceFile: \"{}\"", source_file)),
+            AttributeData::SourceDebugExtension(text) => {
+                self.line(indent, "SourceDebugExtension:");
+                for line in text.lines() {
+                    self.line(indent + 2, line);
+                }
+            }
+            AttributeData::LineNumberTable(entries) => {
+                self.line(indent, "LineNumberTable:");
+                for entry in entries {
+                    self.line(indent + 2, &format!("line {}: {}", entry.line_number, entry.start_pc));
+                }
+            }
+            AttributeData::LocalVariableTable(entries) => self.write_local_variable_table(indent, entries),
+            AttributeData::LocalVariableTypeTable(entries) => self.write_local_variable_type_table(indent, entries),
+            AttributeData::RuntimeVisibleAnnotations(annotations) |
+            AttributeData::RuntimeInvisibleAnnotations(annotations) => {
+                self.line(indent, &format!("{}:", attr.name));
+                self.write_annotations(indent + 2, annotations);
+            }
+            AttributeData::RuntimeVisibleParameterAnnotations(parameters) |
+            AttributeData::RuntimeInvisibleParameterAnnotations(parameters) => {
+                self.line(indent, &format!("{}:", attr.name));
+                self.write_parameter_annotations(indent + 2, parameters);
+            }
+            AttributeData::RuntimeVisibleTypeAnnotations(annotations) |
+            AttributeData::RuntimeInvisibleTypeAnnotations(annotations) => {
+                self.line(indent, &format!("{}:", attr.name));
+                let in_class = indent == 0;
+                for (i, annotation) in annotations.iter().enumerate() {
+                    self.write_type_annotation(indent + 2, i, annotation, in_class, method.is_some());
+                }
+            }
+            AttributeData::AnnotationDefault(value) => {
+                self.line(indent, "AnnotationDefault:");
+                let raw = self.raw_element_value(value);
+                self.line(indent + 2, &format!("default_value: {}", raw));
+                let pretty = self.pretty_element_value(value, indent + 4);
+                self.line(indent + 4, &pretty);
+            }
+            AttributeData::BootstrapMethods(entries) => self.write_bootstrap_methods(indent, entries),
+            AttributeData::MethodParameters(entries) => {
+                self.line(indent, "MethodParameters:");
+                self.line(indent + 2, &format!("{:<31}{}", "Name", "Flags"));
+                for entry in entries {
+                    let name = entry.name.as_deref().unwrap_or("<no name>");
+                    let mut flags = Vec::new();
+                    if entry.access_flags.contains(MethodParameterAccessFlags::FINAL) {
+                        flags.push("final");
+                    }
+                    if entry.access_flags.contains(MethodParameterAccessFlags::SYNTHETIC) {
+                        flags.push("synthetic");
+                    }
+                    if entry.access_flags.contains(MethodParameterAccessFlags::MANDATED) {
+                        flags.push("mandated");
+                    }
+                    let text = format!("{:<31}{}", name, flags.join(" "));
+                    self.line(indent + 2, text.trim_end());
+                }
+            }
+            AttributeData::Module(module) => self.write_module(indent, module),
+            AttributeData::ModulePackages(packages) => {
+                self.line(indent, "ModulePackages: ");
+                for package in packages {
+                    let package_ref = self.ref_to("Package", escape(package));
+                    self.line_with_comment(indent + 2, &package_ref, &escape(package));
+                }
+            }
+            AttributeData::ModuleMainClass(main_class) => {
+                let text = format!("ModuleMainClass: {}", self.class_ref(main_class));
+                self.line_with_comment(indent, &text, &check_name(main_class));
+            }
+            AttributeData::NestHost(host) => self.line(indent, &format!("NestHost: class {}", check_name(host))),
+            AttributeData::NestMembers(members) => {
+                self.line(indent, "NestMembers:");
+                for member in members {
+                    self.line(indent + 2, &check_name(member));
+                }
+            }
+            AttributeData::Record(components) => {
+                self.line(indent, "Record:");
+                for component in components {
+                    self.write_record_component(indent + 2, component);
+                    self.line(0, "");
+                }
+            }
+            AttributeData::Other(bytes) => self.write_other_attribute(indent, &attr.name, bytes),
+            // not returned by decoded
+            AttributeData::Lazy(_) => (),
+        }
+    }
+
+    fn write_code(&mut self, indent: usize, method: &MethodInfo, code: &CodeData) {
+        self.line(indent, "Code:");
+        let mut args_size = parse_method_signature(&method.descriptor).map_or(0, |(_, params, _, _)| params.len());
+        if !method.access_flags.contains(MethodAccessFlags::STATIC) {
+            args_size += 1;
+        }
+        self.line(indent + 2, &format!("stack={}, locals={}, args_size={}", code.max_stack, code.max_locals, args_size));
+        let parsed;
+        let bytecode = match &code.bytecode {
+            Some(bytecode) => Some(bytecode),
+            None => {
+                parsed = ByteCode::from(&code.code, &ConstantPoolReader::unlimited(&self.class.constant_pool)).ok();
+                parsed.as_ref()
+            }
+        };
+        match bytecode {
+            Some(bytecode) => {
+                for (offset, opcode) in &bytecode.opcodes {
+                    self.write_instruction(indent + 2, &code.code, *offset, opcode);
+                }
+            }
+            None => self.line(indent + 2, "<invalid bytecode>"),
+        }
+        if !code.exception_table.is_empty() {
+            self.line(indent + 2, "Exception table:");
+            self.line(indent + 2, "   from    to  target type");
+            for entry in &code.exception_table {
+                let catch_type = match &entry.catch_type {
+                    Some(class_name) => format!("Class {}", check_name(class_name)),
+                    None => "any".to_string(),
+                };
+                self.line(indent + 2, &format!("{:>8} {:>5} {:>5}   {}", entry.start_pc, entry.end_pc, entry.handler_pc, catch_type));
+            }
+        }
+        self.write_attributes(indent + 2, &code.attributes, Some(method));
+    }
+
+    fn write_instruction(&mut self, indent: usize, code: &[u8], offset: usize, opcode: &Opcode) {
+        let raw = code.get(offset).copied().unwrap_or(0);
+        let (mnemonic, wide) = if raw == 0xc4 {
+            let modified = code.get(offset + 1).copied().unwrap_or(0);
+            (format!("{}_w", mnemonic(modified)), true)
+        } else {
+            (mnemonic(raw).to_string(), false)
+        };
+        let prefix = format!("{:>4}: ", offset);
+        let target = |jump: i32| i64::try_from(offset).unwrap_or(0) + i64::from(jump);
+        let (operands, comment) = match opcode {
+            Opcode::Aload(n) | Opcode::Astore(n) | Opcode::Dload(n) | Opcode::Dstore(n) |
+            Opcode::Fload(n) | Opcode::Fstore(n) | Opcode::Iload(n) | Opcode::Istore(n) |
+            Opcode::Lload(n) | Opcode::Lstore(n) | Opcode::Ret(n) => {
+                if wide || matches!(raw, 0x15..=0x19 | 0x36..=0x3a | 0xa9) {
+                    (n.to_string(), None)
+                } else {
+                    (String::new(), None)
+                }
+            }
+            Opcode::Bipush(v) => (v.to_string(), None),
+            Opcode::Sipush(v) => (v.to_string(), None),
+            Opcode::Iinc(n, v) => (format!("{}, {}", n, v), None),
+            Opcode::Newarray(array_type) => (format!(" {}", primitive_array_type_name(array_type)), None),
+            Opcode::Ldc(loadable) | Opcode::LdcW(loadable) | Opcode::Ldc2W(loadable) => {
+                let (reference, comment) = self.loadable_operand(loadable);
+                (reference, Some(comment))
+            }
+            Opcode::Anewarray(class_name) | Opcode::Checkcast(class_name) |
+            Opcode::Instanceof(class_name) | Opcode::New(class_name) => {
+                (self.class_ref(class_name), Some(format!("class {}", check_name(class_name))))
+            }
+            Opcode::Multianewarray(class_name, dimensions) => {
+                (format!("{},  {}", self.class_ref(class_name), dimensions), Some(format!("class {}", check_name(class_name))))
+            }
+            Opcode::Getfield(member) | Opcode::Getstatic(member) |
+            Opcode::Putfield(member) | Opcode::Putstatic(member) => {
+                let (reference, comment) = self.member_operand(member, &["Fieldref"]);
+                (reference, Some(comment))
+            }
+            Opcode::Invokespecial(member) | Opcode::Invokestatic(member) | Opcode::Invokevirtual(member) => {
+                let (reference, comment) = self.member_operand(member, &["Methodref", "InterfaceMethodref"]);
+                (reference, Some(comment))
+            }
+            Opcode::Invokeinterface(member, count) => {
+                let (reference, comment) = self.member_operand(member, &["InterfaceMethodref"]);
+                (format!("{},  {}", reference, count), Some(comment))
+            }
+            Opcode::Invokedynamic(indy) => {
+                let value = format!(
+                    "#{}:{}:{}",
+                    indy.attr_index,
+                    check_name(&indy.name_and_type.name),
+                    escape(&indy.name_and_type.descriptor)
+                );
+                (format!("{},  0", self.ref_to("InvokeDynamic", value.clone())), Some(format!("InvokeDynamic {}", value)))
+            }
+            Opcode::Goto(j) | Opcode::IfAcmpeq(j) | Opcode::IfAcmpne(j) | Opcode::IfIcmpeq(j) |
+            Opcode::IfIcmpge(j) | Opcode::IfIcmpgt(j) | Opcode::IfIcmple(j) | Opcode::IfIcmplt(j) |
+            Opcode::IfIcmpne(j) | Opcode::Ifeq(j) | Opcode::Ifge(j) | Opcode::Ifgt(j) |
+            Opcode::Ifle(j) | Opcode::Iflt(j) | Opcode::Ifne(j) | Opcode::Ifnonnull(j) |
+            Opcode::Ifnull(j) | Opcode::Jsr(j) => (target(*j).to_string(), None),
+            Opcode::Tableswitch(table) => {
+                self.line(indent, &format!("{}{:<13} {{ // {} to {}", prefix, mnemonic, table.low, table.high));
+                for (i, jump) in table.jumps.iter().enumerate() {
+                    let key = i64::from(table.low) + i as i64;
+                    self.line(indent, &format!("{:>18}: {}", key, target(*jump)));
+                }
+                self.line(indent, &format!("{:>18}: {}", "default", target(table.default)));
+                self.line(indent, "      }");
+                return;
+            }
+            Opcode::Lookupswitch(table) => {
+                self.line(indent, &format!("{}{:<13} {{ // {}", prefix, mnemonic, table.match_offsets.len()));
+                for (key, jump) in &table.match_offsets {
+                    self.line(indent, &format!("{:>18}: {}", key, target(*jump)));
+                }
+                self.line(indent, &format!("{:>18}: {}", "default", target(table.default)));
+                self.line(indent, "      }");
+                return;
+            }
+            _ => (String::new(), None),
+        };
+        if operands.is_empty() {
+            self.line(indent, &format!("{}{}", prefix, mnemonic));
+            return;
+        }
+        let text = format!("{}{:<13} {}", prefix, mnemonic, operands);
+        match comment {
+            Some(comment) => self.line_with_comment(indent, &text, &comment),
+            None => self.line(indent, &text),
+        }
+    }
+
+    fn write_stack_map_table(&mut self, indent: usize, entries: &[StackMapEntry]) {
+        self.line(indent, &format!("StackMapTable: number_of_entries = {}", entries.len()));
+        for entry in entries {
+            match entry {
+                StackMapEntry::Same { offset_delta } if *offset_delta < 64 => {
+                    self.line(indent + 2, &format!("frame_type = {} /* same */", offset_delta));
+                }
+                StackMapEntry::Same { offset_delta } => {
+                    self.line(indent + 2, "frame_type = 251 /* same_frame_extended */");
+                    self.line(indent + 4, &format!("offset_delta = {}", offset_delta));
+                }
+                StackMapEntry::SameLocals1StackItem { offset_delta, stack } => {
+                    if *offset_delta < 64 {
+                        self.line(indent + 2, &format!("frame_type = {} /* same_locals_1_stack_item */", offset_delta + 64));
+                    } else {
+                        self.line(indent + 2, "frame_type = 247 /* same_locals_1_stack_item_frame_extended */");
+                        self.line(indent + 4, &format!("offset_delta = {}", offset_delta));
+                    }
+                    let text = format!("stack = {}", verification_types(std::slice::from_ref(stack)));
+                    self.line(indent + 4, &text);
+                }
+                StackMapEntry::Chop { offset_delta, chop_count } => {
+                    self.line(indent + 2, &format!("frame_type = {} /* chop */", 251 - chop_count));
+                    self.line(indent + 4, &format!("offset_delta = {}", offset_delta));
+                }
+                StackMapEntry::Append { offset_delta, locals } => {
+                    self.line(indent + 2, &format!("frame_type = {} /* append */", 251 + locals.len()));
+                    self.line(indent + 4, &format!("offset_delta = {}", offset_delta));
+                    self.line(indent + 4, &format!("locals = {}", verification_types(locals)));
+                }
+                StackMapEntry::FullFrame { offset_delta, locals, stack } => {
+                    self.line(indent + 2, "frame_type = 255 /* full_frame */");
+                    self.line(indent + 4, &format!("offset_delta = {}", offset_delta));
+                    self.line(indent + 4, &format!("locals = {}", verification_types(locals)));
+                    self.line(indent + 4, &format!("stack = {}", verification_types(stack)));
+                }
+            }
+        }
+    }
+
+    fn write_inner_classes(&mut self, indent: usize, entries: &[InnerClassEntry]) {
+        self.line(indent, "InnerClasses:");
+        for entry in entries {
+            let mut flags = entry.access_flags;
+            if flags.contains(InnerClassAccessFlags::INTERFACE) {
+                flags.remove(InnerClassAccessFlags::ABSTRACT);
+            }
+            let mut text = String::new();
+            for (flag, name) in INNER_CLASS_MODIFIERS {
+                if flags.bits() & flag != 0 {
+                    text.push_str(name);
+                    text.push(' ');
+                }
+            }
+            let mut comment = String::new();
+            if let Some(inner_name) = &entry.inner_name {
+                text.push_str(&format!("{}= ", self.utf8_ref(inner_name)));
+                comment.push_str(&format!("{}=", inner_name));
+            }
+            text.push_str(&self.class_ref(&entry.inner_class_info));
+            comment.push_str(&format!("class {}", check_name(&entry.inner_class_info)));
+            if let Some(outer) = &entry.outer_class_info {
+                text.push_str(&format!(" of {}", self.class_ref(outer)));
+                comment.push_str(&format!(" of class {}", check_name(outer)));
+            }
+            text.push(';');
+            self.line_with_comment(indent + 2, &text, &comment);
+        }
+    }
+
+    fn write_local_variable_table(&mut self, indent: usize, entries: &[LocalVariableEntry]) {
+        self.line(indent, "LocalVariableTable:");
+        self.line(indent + 2, "Start  Length  Slot  Name   Signature");
+        for entry in entries {
+            self.line(indent + 2, &format!("{:>5} {:>7} {:>5} {:>5}   {}", entry.start_pc, entry.length, entry.index, entry.name, entry.descriptor));
+        }
+    }
+
+    fn write_local_variable_type_table(&mut self, indent: usize, entries: &[LocalVariableTypeEntry]) {
+        self.line(indent, "LocalVariableTypeTable:");
+        self.line(indent + 2, "Start  Length  Slot  Name   Signature");
+        for entry in entries {
+            self.line(indent + 2, &format!("{:>5} {:>7} {:>5} {:>5}   {}", entry.start_pc, entry.length, entry.index, entry.name, entry.signature));
+        }
+    }
+
+    fn write_annotations(&mut self, indent: usize, annotations: &[Annotation]) {
+        for (i, annotation) in annotations.iter().enumerate() {
+            let raw = self.raw_annotation(annotation);
+            self.line(indent, &format!("{}: {}", i, raw));
+            let pretty = self.pretty_annotation(annotation, indent + 2);
+            self.line(indent + 2, &pretty);
+        }
+    }
+
+    fn write_parameter_annotations(&mut self, indent: usize, parameters: &[ParameterAnnotation]) {
+        for (i, parameter) in parameters.iter().enumerate() {
+            self.line(indent, &format!("parameter {}:", i));
+            self.write_annotations(indent + 2, &parameter.annotations);
+        }
+    }
+
+    fn write_type_annotation(&mut self, indent: usize, i: usize, annotation: &TypeAnnotation, in_class: bool, in_method: bool) {
+        let mut text = format!("{}: {}: ", i, self.raw_annotation(&annotation.annotation));
+        let method_level = if in_class { "CLASS" } else { "METHOD" };
+        match &annotation.target_type {
+            TypeAnnotationTarget::TypeParameter { index } => {
+                text.push_str(&format!("{}_TYPE_PARAMETER, param_index={}", method_level, index));
+            }
+            TypeAnnotationTarget::Supertype { index } => text.push_str(&format!("CLASS_EXTENDS, type_index={}", index)),
+            TypeAnnotationTarget::TypeParameterBound { type_parameter_index, bound_index } => {
+                text.push_str(&format!(
+                    "{}_TYPE_PARAMETER_BOUND, param_index={}, bound_index={}",
+                    method_level, type_parameter_index, bound_index
+                ));
+            }
+            TypeAnnotationTarget::Empty => text.push_str(if in_method { "METHOD_RETURN" } else { "FIELD" }),
+            TypeAnnotationTarget::FormalParameter { index } => {
+                text.push_str(&format!("METHOD_FORMAL_PARAMETER, param_index={}", index));
+            }
+            TypeAnnotationTarget::Throws { index } => text.push_str(&format!("THROWS, type_index={}", index)),
+            TypeAnnotationTarget::LocalVar(entries) => {
+                let ranges: Vec<String> = entries
+                    .iter()
+                    .map(|e| format!("start_pc={}, length={}, index={}", e.start_pc, e.length, e.index))
+                    .collect();
+                text.push_str(&format!("LOCAL_VARIABLE, {{{}}}", ranges.join("; ")));
+            }
+            TypeAnnotationTarget::Catch { exception_table_index } => {
+                text.push_str(&format!("EXCEPTION_PARAMETER, exception_index={}", exception_table_index));
+            }
+            TypeAnnotationTarget::Offset { target_type, offset } => {
+                text.push_str(&format!("{}, offset={}", instruction_target_name(*target_type), offset));
+            }
+            TypeAnnotationTarget::TypeArgument { target_type, offset, type_argument_index } => {
+                text.push_str(&format!(
+                    "{}, offset={}, type_index={}",
+                    instruction_target_name(*target_type),
+                    offset,
+                    type_argument_index
+                ));
+            }
+        }
+        if !annotation.target_path.is_empty() {
+            let path: Vec<String> = annotation
+                .target_path
+                .iter()
+                .map(|entry| match entry.path_kind {
+                    TypeAnnotationTargetPathKind::DeeperArray => "ARRAY".to_string(),
+                    TypeAnnotationTargetPathKind::DeeperNested => "INNER_TYPE".to_string(),
+                    TypeAnnotationTargetPathKind::WildcardTypeArgument => "WILDCARD".to_string(),
+                    TypeAnnotationTargetPathKind::TypeArgument => format!("TYPE_ARGUMENT({})", entry.argument_index),
+                })
+                .collect();
+            text.push_str(&format!(", location=[{}]", path.join(", ")));
+        }
+        self.line(indent, &text);
+        let pretty = self.pretty_annotation(&annotation.annotation, indent + 2);
+        self.line(indent + 2, &pretty);
+    }
+
+    fn raw_annotation(&self, annotation: &Annotation) -> String {
+        let elements: Vec<String> = annotation
+            .elements
+            .iter()
+            .map(|element| format!("{}={}", self.utf8_ref(&element.name), self.raw_element_value(&element.value)))
+            .collect();
+        format!("{}({})", self.utf8_ref(&annotation.type_descriptor), elements.join(","))
+    }
+
+    fn raw_element_value(&self, value: &AnnotationElementValue) -> String {
+        match value {
+            AnnotationElementValue::ByteConstant(v) => format!("B{}", self.ref_to("Integer", v.to_string())),
+            AnnotationElementValue::CharConstant(v) => format!("C{}", self.ref_to("Integer", v.to_string())),
+            AnnotationElementValue::DoubleConstant(v) => format!("D{}", self.ref_to("Double", format!("{}d", java_double(*v)))),
+            AnnotationElementValue::FloatConstant(v) => format!("F{}", self.ref_to("Float", format!("{}f", java_float(*v)))),
+            AnnotationElementValue::IntConstant(v) => format!("I{}", self.ref_to("Integer", v.to_string())),
+            AnnotationElementValue::LongConstant(v) => format!("J{}", self.ref_to("Long", format!("{}l", v))),
+            AnnotationElementValue::ShortConstant(v) => format!("S{}", self.ref_to("Integer", v.to_string())),
+            AnnotationElementValue::BooleanConstant(v) => format!("Z{}", self.ref_to("Integer", v.to_string())),
+            AnnotationElementValue::StringConstant(s) => format!("s{}", self.utf8_ref(s)),
+            AnnotationElementValue::EnumConstant { type_name, const_name } => {
+                format!("e{}.{}", self.utf8_ref(type_name), self.utf8_ref(const_name))
+            }
+            AnnotationElementValue::ClassLiteral { class_name } => format!("c{}", self.utf8_ref(class_name)),
+            AnnotationElementValue::AnnotationValue(annotation) => format!("@{}", self.raw_annotation(annotation)),
+            AnnotationElementValue::ArrayValue(values) => {
+                let values: Vec<String> = values.iter().map(|v| self.raw_element_value(v)).collect();
+                format!("[{}]", values.join(","))
+            }
+        }
+    }
+
+    /// Formats an annotation the way javap does below the raw constant pool form. Annotations
+    /// with elements span multiple lines, which are indented relative to `indent`.
+    fn pretty_annotation(&self, annotation: &Annotation, indent: usize) -> String {
+        let name = descriptor_java_name(&annotation.type_descriptor);
+        if annotation.elements.is_empty() {
+            return name;
+        }
+        let mut text = format!("{}(\n", name);
+        for element in &annotation.elements {
+            text.push_str(&" ".repeat(indent + 2));
+            text.push_str(&format!("{}={}\n", element.name, self.pretty_element_value(&element.value, indent + 2)));
+        }
+        text.push_str(&" ".repeat(indent));
+        text.push(')');
+        text
+    }
+
+    fn pretty_element_value(&self, value: &AnnotationElementValue, indent: usize) -> String {
+        match value {
+            AnnotationElementValue::ByteConstant(v) => format!("(byte) {}", v),
+            AnnotationElementValue::CharConstant(v) => {
+                let c = u32::try_from(*v).ok().and_then(std::char::from_u32).unwrap_or('?');
+                format!("'{}'", escape(&c.to_string()))
+            }
+            AnnotationElementValue::DoubleConstant(v) => format!("{}d", java_double(*v)),
+            AnnotationElementValue::FloatConstant(v) => format!("{}f", java_float(*v)),
+            AnnotationElementValue::IntConstant(v) => v.to_string(),
+            AnnotationElementValue::LongConstant(v) => format!("{}l", v),
+            AnnotationElementValue::ShortConstant(v) => format!("(short) {}", v),
+            AnnotationElementValue::BooleanConstant(v) => (*v != 0).to_string(),
+            AnnotationElementValue::StringConstant(s) => format!("\"{}\"", escape(s)),
+            AnnotationElementValue::EnumConstant { type_name, const_name } => format!("{}.{}", type_name, const_name),
+            AnnotationElementValue::ClassLiteral { class_name } => format!("class {}", class_name),
+            AnnotationElementValue::AnnotationValue(annotation) => format!("@{}", self.pretty_annotation(annotation, indent)),
+            AnnotationElementValue::ArrayValue(values) => {
+                let values: Vec<String> = values.iter().map(|v| self.pretty_element_value(v, indent)).collect();
+                format!("[{}]", values.join(","))
+            }
+        }
+    }
+
+    fn bootstrap_argument(&self, argument: &BootstrapArgument) -> (String, String) {
+        match argument {
+            BootstrapArgument::LiteralConstant(literal) => {
+                let (tag, value) = Self::literal_key(literal);
+                (self.ref_to(tag, value.clone()), value)
+            }
+            BootstrapArgument::ClassInfo(name) => (self.class_ref(name), check_name(name)),
+            BootstrapArgument::MethodHandle(handle) => {
+                let value = Self::method_handle_value(handle);
+                (self.ref_to("MethodHandle", value.clone()), value)
+            }
+            BootstrapArgument::MethodType(descriptor) => {
+                (self.ref_to("MethodType", format!(" {}", escape(descriptor))), escape(descriptor))
+            }
+        }
+    }
+
+    fn write_bootstrap_methods(&mut self, indent: usize, entries: &[BootstrapMethodEntry]) {
+        self.line(indent, "BootstrapMethods:");
+        for (i, entry) in entries.iter().enumerate() {
+            let value = Self::method_handle_value(&entry.method);
+            let method_ref = self.ref_to("MethodHandle", value.clone());
+            self.line(indent + 2, &format!("{}: {} {}", i, method_ref, value));
+            self.line(indent + 4, "Method arguments:");
+            for argument in &entry.arguments {
+                let (reference, value) = self.bootstrap_argument(argument);
+                self.line(indent + 6, &format!("{} {}", reference, value));
+            }
+        }
+    }
+
+    fn write_module(&mut self, indent: usize, module: &ModuleData) {
+        self.line(indent, "Module:");
+        let module_ref = |d: &Self, name: &str| d.ref_to("Module", escape(name));
+        let version_ref = |d: &Self, version: &Option<String>| match version {
+            Some(version) => d.utf8_ref(version),
+            None => "#0".to_string(),
+        };
+        let text = format!("{},{:x}", module_ref(self, &module.name), module.access_flags.bits());
+        self.line_with_comment(indent + 2, &text, &format!("\"{}\"", module.name));
+        let text = version_ref(self, &module.version);
+        self.line(indent + 2, &text);
+        self.line_with_comment(indent + 2, &module.requires.len().to_string(), "requires");
+        for require in &module.requires {
+            let text = format!("{},{:x}", module_ref(self, &require.name), require.flags.bits());
+            self.line_with_comment(indent + 4, &text, &format!("\"{}\"", require.name));
+            let text = version_ref(self, &require.version);
+            self.line(indent + 4, &text);
+        }
+        self.line_with_comment(indent + 2, &module.exports.len().to_string(), "exports");
+        for export in &module.exports {
+            let text = format!("{},{:x}", self.ref_to("Package", escape(&export.package_name)), export.flags.bits());
+            self.line_with_comment(indent + 4, &text, &export.package_name);
+            if !export.exports_to.is_empty() {
+                self.line_with_comment(indent + 6, &export.exports_to.len().to_string(), "exports to");
+                for target in &export.exports_to {
+                    let text = module_ref(self, target);
+                    self.line_with_comment(indent + 8, &text, &format!("\"{}\"", target));
+                }
+            }
+        }
+        self.line_with_comment(indent + 2, &module.opens.len().to_string(), "opens");
+        for open in &module.opens {
+            let text = format!("{},{:x}", self.ref_to("Package", escape(&open.package_name)), open.flags.bits());
+            self.line_with_comment(indent + 4, &text, &open.package_name);
+            if !open.opens_to.is_empty() {
+                self.line_with_comment(indent + 6, &open.opens_to.len().to_string(), "opens to");
+                for target in &open.opens_to {
+                    let text = module_ref(self, target);
+                    self.line_with_comment(indent + 8, &text, &format!("\"{}\"", target));
+                }
+            }
+        }
+        self.line_with_comment(indent + 2, &module.uses.len().to_string(), "uses");
+        for service in &module.uses {
+            let text = self.class_ref(service);
+            self.line_with_comment(indent + 4, &text, &check_name(service));
+        }
+        self.line_with_comment(indent + 2, &module.provides.len().to_string(), "provides");
+        for provide in &module.provides {
+            let text = self.class_ref(&provide.service_interface_name);
+            self.line_with_comment(indent + 4, &text, &check_name(&provide.service_interface_name));
+            self.line_with_comment(indent + 4, &provide.provides_with.len().to_string(), "with");
+            for implementation in &provide.provides_with {
+                let text = self.class_ref(implementation);
+                self.line_with_comment(indent + 6, &text, &check_name(implementation));
+            }
+        }
+    }
+
+    fn write_record_component(&mut self, indent: usize, component: &RecordComponentEntry) {
+        let component_type = find_signature(&component.attributes)
+            .and_then(parse_full_type)
+            .or_else(|| parse_full_type(&component.descriptor))
+            .unwrap_or_else(|| component.descriptor.clone());
+        self.line(indent, &format!("{} {};", component_type, component.name));
+        self.line(indent + 2, &format!("descriptor: {}", component.descriptor));
+        self.write_attributes(indent + 2, &component.attributes, None);
+    }
+
+    fn write_other_attribute(&mut self, indent: usize, name: &str, bytes: &[u8]) {
+        if name == "PermittedSubclasses" {
+            let mut ix = 0;
+            if let Ok(count) = read_u2(bytes, &mut ix) {
+                let mut subclasses = Vec::new();
+                for _ in 0..count {
+                    match read_u2(bytes, &mut ix).ok().and_then(|index| self.entry(index)) {
+                        Some(IndexedEntry::ClassInfo(x)) => subclasses.push(check_name(&self.utf8(*x))),
+                        _ => break,
+                    }
+                }
+                if subclasses.len() == usize::from(count) && ix == bytes.len() {
+                    self.line(indent, "PermittedSubclasses:");
+                    for subclass in subclasses {
+                        self.line(indent + 2, &subclass);
+                    }
+                    return;
+                }
+            }
+        }
+        self.line(indent, &format!("{}: length = 0x{:X} (unknown attribute)", name, bytes.len()));
+        for chunk in bytes.chunks(16) {
+            let hex: Vec<String> = chunk.iter().map(|b| format!("{:02x}", b)).collect();
+            self.line(indent, &format!("   {}", hex.join(" ")));
+        }
+    }
+}
+
+fn find_signature(attributes: &[AttributeInfo]) -> Option<&str> {
+    attributes.iter().find_map(|attr| match attr.decoded() {
+        Ok(AttributeData::Signature(signature)) => Some(signature.as_str()),
+        _ => None,
+    })
+}
+
+const CLASS_FLAG_NAMES: &[(u16, &str)] = &[
+    (0x0001, "ACC_PUBLIC"),
+    (0x0010, "ACC_FINAL"),
+    (0x0020, "ACC_SUPER"),
+    (0x0200, "ACC_INTERFACE"),
+    (0x0400, "ACC_ABSTRACT"),
+    (0x1000, "ACC_SYNTHETIC"),
+    (0x2000, "ACC_ANNOTATION"),
+    (0x4000, "ACC_ENUM"),
+    (0x8000, "ACC_MODULE"),
+];
+
+const FIELD_FLAG_NAMES: &[(u16, &str)] = &[
+    (0x0001, "ACC_PUBLIC"),
+    (0x0002, "ACC_PRIVATE"),
+    (0x0004, "ACC_PROTECTED"),
+    (0x0008, "ACC_STATIC"),
+    (0x0010, "ACC_FINAL"),
+    (0x0040, "ACC_VOLATILE"),
+    (0x0080, "ACC_TRANSIENT"),
+    (0x1000, "ACC_SYNTHETIC"),
+    (0x4000, "ACC_ENUM"),
+];
+
+const METHOD_FLAG_NAMES: &[(u16, &str)] = &[
+    (0x0001, "ACC_PUBLIC"),
+    (0x0002, "ACC_PRIVATE"),
+    (0x0004, "ACC_PROTECTED"),
+    (0x0008, "ACC_STATIC"),
+    (0x0010, "ACC_FINAL"),
+    (0x0020, "ACC_SYNCHRONIZED"),
+    (0x0040, "ACC_BRIDGE"),
+    (0x0080, "ACC_VARARGS"),
+    (0x0100, "ACC_NATIVE"),
+    (0x0400, "ACC_ABSTRACT"),
+    (0x0800, "ACC_STRICT"),
+    (0x1000, "ACC_SYNTHETIC"),
+];
+
+const FIELD_MODIFIERS: &[(u16, &str)] = &[
+    (FieldAccessFlags::PUBLIC.bits(), "public"),
+    (FieldAccessFlags::PRIVATE.bits(), "private"),
+    (FieldAccessFlags::PROTECTED.bits(), "protected"),
+    (FieldAccessFlags::STATIC.bits(), "static"),
+    (FieldAccessFlags::FINAL.bits(), "final"),
+    (FieldAccessFlags::VOLATILE.bits(), "volatile"),
+    (FieldAccessFlags::TRANSIENT.bits(), "transient"),
+];
+
+const METHOD_MODIFIERS: &[(u16, &str)] = &[
+    (MethodAccessFlags::PUBLIC.bits(), "public"),
+    (MethodAccessFlags::PRIVATE.bits(), "private"),
+    (MethodAccessFlags::PROTECTED.bits(), "protected"),
+    (MethodAccessFlags::STATIC.bits(), "static"),
+    (MethodAccessFlags::FINAL.bits(), "final"),
+    (MethodAccessFlags::SYNCHRONIZED.bits(), "synchronized"),
+    (MethodAccessFlags::NATIVE.bits(), "native"),
+    (MethodAccessFlags::ABSTRACT.bits(), "abstract"),
+];
+
+const INNER_CLASS_MODIFIERS: &[(u16, &str)] = &[
+    (InnerClassAccessFlags::PUBLIC.bits(), "public"),
+    (InnerClassAccessFlags::PRIVATE.bits(), "private"),
+    (InnerClassAccessFlags::PROTECTED.bits(), "protected"),
+    (InnerClassAccessFlags::STATIC.bits(), "static"),
+    (InnerClassAccessFlags::FINAL.bits(), "final"),
+    (InnerClassAccessFlags::ABSTRACT.bits(), "abstract"),
+];
+
+fn flags_text(bits: u16, names: &[(u16, &str)]) -> String {
+    let set: Vec<&str> = names.iter().filter(|(flag, _)| bits & flag != 0).map(|(_, name)| *name).collect();
+    if set.is_empty() {
+        format!("(0x{:04x})", bits)
+    } else {
+        format!("(0x{:04x}) {}", bits, set.join(", "))
+    }
+}
+
+fn reference_kind_number(kind: ReferenceKind) -> u8 {
+    match kind {
+        ReferenceKind::GetField => 1,
+        ReferenceKind::GetStatic => 2,
+        ReferenceKind::PutField => 3,
+        ReferenceKind::PutStatic => 4,
+        ReferenceKind::InvokeVirtual => 5,
+        ReferenceKind::InvokeStatic => 6,
+        ReferenceKind::InvokeSpecial => 7,
+        ReferenceKind::NewInvokeSpecial => 8,
+        ReferenceKind::InvokeInterface => 9,
+    }
+}
+
+fn reference_kind_name(kind: ReferenceKind) -> &'static str {
+    match kind {
+        ReferenceKind::GetField => "REF_getField",
+        ReferenceKind::GetStatic => "REF_getStatic",
+        ReferenceKind::PutField => "REF_putField",
+        ReferenceKind::PutStatic => "REF_putStatic",
+        ReferenceKind::InvokeVirtual => "REF_invokeVirtual",
+        ReferenceKind::InvokeStatic => "REF_invokeStatic",
+        ReferenceKind::InvokeSpecial => "REF_invokeSpecial",
+        ReferenceKind::NewInvokeSpecial => "REF_newInvokeSpecial",
+        ReferenceKind::InvokeInterface => "REF_invokeInterface",
+    }
+}
+
+fn primitive_array_type_name(array_type: &PrimitiveArrayType) -> &'static str {
+    match array_type {
+        PrimitiveArrayType::Boolean => "boolean",
+        PrimitiveArrayType::Char => "char",
+        PrimitiveArrayType::Float => "float",
+        PrimitiveArrayType::Double => "double",
+        PrimitiveArrayType::Byte => "byte",
+        PrimitiveArrayType::Short => "short",
+        PrimitiveArrayType::Int => "int",
+        PrimitiveArrayType::Long => "long",
+    }
+}
+
+fn verification_types(types: &[VerificationType]) -> String {
+    let names: Vec<String> = types
+        .iter()
+        .map(|t| match t {
+            VerificationType::Top => "top".to_string(),
+            VerificationType::Integer => "int".to_string(),
+            VerificationType::Float => "float".to_string(),
+            VerificationType::Long => "long".to_string(),
+            VerificationType::Double => "double".to_string(),
+            VerificationType::Null => "null".to_string(),
+            VerificationType::UninitializedThis => "this".to_string(),
+            VerificationType::Uninitialized { code_offset } => format!("uninitialized {}", code_offset),
+            VerificationType::Object { class_name } => format!("class {}", check_name(class_name)),
+        })
+        .collect();
+    if names.is_empty() {
+        "[]".to_string()
+    } else {
+        format!("[ {} ]", names.join(", "))
+    }
+}
+
+pub(crate) fn mnemonic(opcode: u8) -> &'static str {
+    const MNEMONICS: [&str; 203] = [
+        "nop", "aconst_null", "iconst_m1", "iconst_0", "iconst_1", "iconst_2", "iconst_3", "iconst_4",
+        "iconst_5", "lconst_0", "lconst_1", "fconst_0", "fconst_1", "fconst_2", "dconst_0", "dconst_1",
+        "bipush", "sipush", "ldc", "ldc_w", "ldc2_w", "iload", "lload", "fload",
+        "dload", "aload", "iload_0", "iload_1", "iload_2", "iload_3", "lload_0", "lload_1",
+        "lload_2", "lload_3", "fload_0", "fload_1", "fload_2", "fload_3", "dload_0", "dload_1",
+        "dload_2", "dload_3", "aload_0", "aload_1", "aload_2", "aload_3", "iaload", "laload",
+        "faload", "daload", "aaload", "baload", "caload", "saload", "istore", "lstore",
+        "fstore", "dstore", "astore", "istore_0", "istore_1", "istore_2", "istore_3", "lstore_0",
+        "lstore_1", "lstore_2", "lstore_3", "fstore_0", "fstore_1", "fstore_2", "fstore_3", "dstore_0",
+        "dstore_1", "dstore_2", "dstore_3", "astore_0", "astore_1", "astore_2", "astore_3", "iastore",
+        "lastore", "fastore", "dastore", "aastore", "bastore", "castore", "sastore", "pop",
+        "pop2", "dup", "dup_x1", "dup_x2", "dup2", "dup2_x1", "dup2_x2", "swap",
+        "iadd", "ladd", "fadd", "dadd", "isub", "lsub", "fsub", "dsub",
+        "imul", "lmul", "fmul", "dmul", "idiv", "ldiv", "fdiv", "ddiv",
+        "irem", "lrem", "frem", "drem", "ineg", "lneg", "fneg", "dneg",
+        "ishl", "lshl", "ishr", "lshr", "iushr", "lushr", "iand", "land",
+        "ior", "lor", "ixor", "lxor", "iinc", "i2l", "i2f", "i2d",
+        "l2i", "l2f", "l2d", "f2i", "f2l", "f2d", "d2i", "d2l",
+        "d2f", "i2b", "i2c", "i2s", "lcmp", "fcmpl", "fcmpg", "dcmpl",
+        "dcmpg", "ifeq", "ifne", "iflt", "ifge", "ifgt", "ifle", "if_icmpeq",
+        "if_icmpne", "if_icmplt", "if_icmpge", "if_icmpgt", "if_icmple", "if_acmpeq", "if_acmpne", "goto",
+        "jsr", "ret", "tableswitch", "lookupswitch", "ireturn", "lreturn", "freturn", "dreturn",
+        "areturn", "return", "getstatic", "putstatic", "getfield", "putfield", "invokevirtual", "invokespecial",
+        "invokestatic", "invokeinterface", "invokedynamic", "new", "newarray", "anewarray", "arraylength", "athrow",
+        "checkcast", "instanceof", "monitorenter", "monitorexit", "wide", "multianewarray", "ifnull", "ifnonnull",
+        "goto_w", "jsr_w", "breakpoint",
+    ];
+    match opcode {
+        0xfe => "impdep1",
+        0xff => "impdep2",
+        _ => MNEMONICS.get(usize::from(opcode)).copied().unwrap_or("unknown"),
+    }
+}
+
+/// Quotes names the way javap does when they are not made up of Java identifiers separated
+/// by slashes.
+fn check_name(name: &str) -> String {
+    let mut previous = '/';
+    for c in name.chars() {
+        let start = c.is_alphabetic() || c == '_' || c == '$';
+        let part = start || c.is_numeric();
+        if (previous == '/' && !start) || (c != '/' && !part) {
+            return format!("\"{}\"", escape(name));
+        }
+        previous = c;
+    }
+    if name.is_empty() {
+        return "\"\"".to_string();
+    }
+    name.to_string()
+}
+
+/// Returns the name javap uses for the target_type of a type annotation on an instruction.
+fn instruction_target_name(target_type: u8) -> &'static str {
+    match target_type {
+        0x43 => "INSTANCEOF",
+        0x44 => "NEW",
+        0x45 => "CONSTRUCTOR_REFERENCE",
+        0x46 => "METHOD_REFERENCE",
+        0x47 => "CAST",
+        0x48 => "CONSTRUCTOR_INVOCATION_TYPE_ARGUMENT",
+        0x49 => "METHOD_INVOCATION_TYPE_ARGUMENT",
+        0x4a => "CONSTRUCTOR_REFERENCE_TYPE_ARGUMENT",
+        _ => "METHOD_REFERENCE_TYPE_ARGUMENT",
+    }
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\t' => escaped.push_str("\\t"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\u{8}' => escaped.push_str("\\b"),
+            '\u{c}' => escaped.push_str("\\f"),
+            '"' => escaped.push_str("\\\""),
+            '\'' => escaped.push_str("\\'"),
+            '\\' => escaped.push_str("\\\\"),
+            ' '..='~' => escaped.push(c),
+            _ => {
+                for unit in c.encode_utf16(&mut [0; 2]) {
+                    escaped.push_str(&format!("\\u{:04x}", unit));
+                }
+            }
+        }
+    }
+    escaped
+}
+
+/// Formats a double the way Java's `Double.toString` does.
+fn java_double(v: f64) -> String {
+    if v.is_nan() {
+        return "NaN".to_string();
+    }
+    if v.is_infinite() {
+        return if v > 0.0 { "Infinity" } else { "-Infinity" }.to_string();
+    }
+    let magnitude = v.abs();
+    if magnitude == 0.0 || (1e-3..1e7).contains(&magnitude) {
+        format!("{:?}", v)
+    } else {
+        java_exponent(&format!("{:e}", v))
+    }
+}
+
+/// Formats a float the way Java's `Float.toString` does.
+fn java_float(v: f32) -> String {
+    if v.is_nan() {
+        return "NaN".to_string();
+    }
+    if v.is_infinite() {
+        return if v > 0.0 { "Infinity" } else { "-Infinity" }.to_string();
+    }
+    let magnitude = v.abs();
+    if magnitude == 0.0 || (1e-3..1e7).contains(&magnitude) {
+        format!("{:?}", v)
+    } else {
+        java_exponent(&format!("{:e}", v))
+    }
+}
+
+fn java_exponent(formatted: &str) -> String {
+    let (mantissa, exponent) = formatted.split_at(formatted.find('e').unwrap_or(formatted.len()));
+    let exponent = exponent.trim_start_matches('e');
+    if mantissa.contains('.') {
+        format!("{}E{}", mantissa, exponent)
+    } else {
+        format!("{}.0E{}", mantissa, exponent)
+    }
+}
+
+fn java_name(internal_name: &str) -> String {
+    internal_name.replace('/', ".")
+}
+
+fn descriptor_java_name(descriptor: &str) -> String {
+    parse_full_type(descriptor).unwrap_or_else(|| descriptor.to_string())
+}
+
+/// A reader for descriptors and generic signatures (JVMS 4.7.9.1) that produces Java source
+/// syntax for the types in them.
+struct SignatureReader<'s> {
+    sig: &'s str,
+    pos: usize,
+}
+
+impl<'s> SignatureReader<'s> {
+    fn new(sig: &'s str) -> Self {
+        SignatureReader { sig, pos: 0 }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.sig[self.pos..].chars().next()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += c.len_utf8();
+        Some(c)
+    }
+
+    fn expect(&mut self, expected: char) -> Option<()> {
+        if self.next()? == expected {
+            Some(())
+        } else {
+            None
+        }
+    }
+
+    fn at_end(&self) -> bool {
+        self.pos == self.sig.len()
+    }
+
+    fn identifier(&mut self, terminators: &[char]) -> Option<&'s str> {
+        let start = self.pos;
+        while !terminators.contains(&self.peek()?) {
+            self.next();
+        }
+        Some(&self.sig[start..self.pos])
+    }
+
+    fn type_params(&mut self) -> Option<String> {
+        if self.peek() != Some('<') {
+            return Some(String::new());
+        }
+        self.next();
+        let mut params = Vec::new();
+        while self.peek()? != '>' {
+            let name = self.identifier(&[':'])?;
+            let mut bounds = Vec::new();
+            self.expect(':')?;
+            if self.peek()? != ':' && self.peek()? != '>' {
+                bounds.push(self.reference_type()?);
+            }
+            while self.peek()? == ':' {
+                self.next();
+                bounds.push(self.reference_type()?);
+            }
+            if bounds.is_empty() {
+                params.push(name.to_string());
+            } else {
+                params.push(format!("{} extends {}", name, bounds.join(" & ")));
+            }
+        }
+        self.next();
+        Some(format!("<{}>", params.join(", ")))
+    }
+
+    fn full_type(&mut self) -> Option<String> {
+        let name = match self.next()? {
+            'B' => "byte",
+            'C' => "char",
+            'D' => "double",
+            'F' => "float",
+            'I' => "int",
+            'J' => "long",
+            'S' => "short",
+            'Z' => "boolean",
+            'V' => "void",
+            _ => {
+                self.pos -= 1;
+                return self.reference_type();
+            }
+        };
+        Some(name.to_string())
+    }
+
+    fn reference_type(&mut self) -> Option<String> {
+        match self.next()? {
+            '[' => Some(format!("{}[]", self.full_type()?)),
+            'T' => {
+                let name = self.identifier(&[';'])?;
+                self.next();
+                Some(name.to_string())
+            }
+            'L' => {
+                let mut text = java_name(self.identifier(&['<', '.', ';'])?);
+                loop {
+                    if self.peek()? == '<' {
+                        self.next();
+                        let mut args = Vec::new();
+                        while self.peek()? != '>' {
+                            args.push(match self.peek()? {
+                                '*' => {
+                                    self.next();
+                                    "?".to_string()
+                                }
+                                '+' => {
+                                    self.next();
+                                    format!("? extends {}", self.reference_type()?)
+                                }
+                                '-' => {
+                                    self.next();
+                                    format!("? super {}", self.reference_type()?)
+                                }
+                                _ => self.reference_type()?,
+                            });
+                        }
+                        self.next();
+                        text.push_str(&format!("<{}>", args.join(", ")));
+                    }
+                    match self.next()? {
+                        ';' => return Some(text),
+                        '.' => {
+                            text.push('.');
+                            text.push_str(self.identifier(&['<', '.', ';'])?);
+                        }
+                        _ => return None,
+                    }
+                }
+            }
+            _ => None,
+        }
+    }
+}
+
+fn parse_full_type(sig: &str) -> Option<String> {
+    let mut reader = SignatureReader::new(sig);
+    let result = reader.full_type()?;
+    if reader.at_end() {
+        Some(result)
+    } else {
+        None
+    }
+}
+
+/// Parses a class signature into its type parameters, superclass and superinterfaces.
+fn parse_class_signature(sig: &str) -> Option<(String, String, Vec<String>)> {
+    let mut reader = SignatureReader::new(sig);
+    let type_params = reader.type_params()?;
+    let super_class = reader.reference_type()?;
+    let mut interfaces = Vec::new();
+    while !reader.at_end() {
+        interfaces.push(reader.reference_type()?);
+    }
+    Some((type_params, super_class, interfaces))
+}
+
+/// Parses a method descriptor or signature into its type parameters, parameter types,
+/// return type and thrown types.
+#[allow(clippy::type_complexity)]
+fn parse_method_signature(sig: &str) -> Option<(String, Vec<String>, String, Vec<String>)> {
+    let mut reader = SignatureReader::new(sig);
+    let type_params = reader.type_params()?;
+    reader.expect('(')?;
+    let mut params = Vec::new();
+    while reader.peek()? != ')' {
+        params.push(reader.full_type()?);
+    }
+    reader.next();
+    let return_type = reader.full_type()?;
+    let mut throws = Vec::new();
+    while !reader.at_end() {
+        reader.expect('^')?;
+        throws.push(reader.reference_type()?);
+    }
+    Some((type_params, params, return_type, throws))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembly::parse_assembly;
+
+    #[test]
+    fn test_signatures() {
+        assert_eq!(parse_full_type("[[Ljava/lang/String;"), Some("java.lang.String[][]".to_string()));
+        assert_eq!(parse_full_type("Ljava/util/Map<-TK;[TV;>;"), Some("java.util.Map<? super K, V[]>".to_string()));
+        assert_eq!(parse_full_type("La/Outer<TT;>.Inner<*>;"), Some("a.Outer<T>.Inner<?>".to_string()));
+        assert_eq!(
+            parse_method_signature("<T:Ljava/lang/Number;:Ljava/lang/Runnable;>(JI)Ljava/util/List<+TT;>;^TE;"),
+            Some((
+                "<T extends java.lang.Number & java.lang.Runnable>".to_string(),
+                vec!["long".to_string(), "int".to_string()],
+                "java.util.List<? extends T>".to_string(),
+                vec!["E".to_string()],
+            ))
+        );
+        assert_eq!(parse_full_type("Ljava/lang/String"), None);
+    }
+
+    #[test]
+    fn test_instruction_type_annotations() {
+        let class = parse_assembly(
+            r#"
+.version 52 0
+.class public Test
+.super java/lang/Object
+.method public static run (Ljava/lang/Object;)V
+    .code stack 2 locals 1
+    New:
+        new java/lang/Object
+        dup
+    Init:
+        invokespecial java/lang/Object <init> ()V
+        pop
+    Call:
+        invokestatic java/util/List of ()Ljava/util/List;
+        pop
+        aload_0
+    Check:
+        instanceof java/lang/String
+        pop
+        return
+        .runtimevisibletypeannotations
+            .typeannotation offset new New [ ] LA;
+            .end typeannotation
+            .typeannotation typeargument constructorinvocation Init 0 [ ] LA;
+            .end typeannotation
+            .typeannotation typeargument methodinvocation Call 1 [ ] LA;
+            .end typeannotation
+            .typeannotation offset instanceof Check [ ] LA;
+            .end typeannotation
+            .typeannotation typeargument methodinvocation Init 2 [ ] LA;
+            .end typeannotation
+            .typeannotation offset constructorreference Call [ ] LA;
+            .end typeannotation
+            .typeannotation typeargument constructorreference Call 0 [ ] LA;
+            .end typeannotation
+        .end runtimevisibletypeannotations
+    .end code
+.end method
+.end class
+"#,
+        )
+        .unwrap();
+        let text = disassemble(&class);
+        for target in [
+            "NEW, offset=0",
+            "CONSTRUCTOR_INVOCATION_TYPE_ARGUMENT, offset=4, type_index=0",
+            "METHOD_INVOCATION_TYPE_ARGUMENT, offset=8, type_index=1",
+            "INSTANCEOF, offset=13",
+            "METHOD_INVOCATION_TYPE_ARGUMENT, offset=4, type_index=2",
+            "CONSTRUCTOR_REFERENCE, offset=8",
+            "CONSTRUCTOR_REFERENCE_TYPE_ARGUMENT, offset=8, type_index=0",
+        ] {
+            assert!(text.contains(target), "{} not found in {}", target, text);
+        }
+    }
+
+    #[test]
+    fn test_escape() {
+        assert_eq!(escape("a\tb\"c'\\"), "a\\tb\\\"c\\'\\\\");
+        assert_eq!(escape("\u{0}\u{1f}\u{7f}é"), "\\u0000\\u001f\\u007f\\u00e9");
+        assert_eq!(escape("kotlin/Function\u{1}\u{1f600}"), "kotlin/Function\\u0001\\ud83d\\ude00");
+    }
+
+    #[test]
+    fn test_java_numbers() {
+        assert_eq!(java_double(1.5), "1.5");
+        assert_eq!(java_double(3e20), "3.0E20");
+        assert_eq!(java_double(-1.25e-5), "-1.25E-5");
+        assert_eq!(java_float(2.0), "2.0");
+        assert_eq!(java_float(1e-5), "1.0E-5");
+        assert_eq!(java_float(f32::NEG_INFINITY), "-Infinity");
+        assert_eq!(check_name("java/lang/Object"), "java/lang/Object");
+        assert_eq!(check_name("<init>"), "\"<init>\"");
+        assert_eq!(check_name("[[I"), "\"[[I\"");
+    }
+}
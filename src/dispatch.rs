@@ -0,0 +1,443 @@
+//! Method selection and dispatch table layouts for virtual and interface calls.
+//!
+//! [`select_method`] implements the method selection of section 5.4.6 of the JVM spec, which
+//! decides which method an `invokevirtual` or `invokeinterface` of a resolved method runs for a
+//! given receiver class. [`vtable`] and [`itable`] precompute the same decision for every method
+//! a class can be called through, in the style of the tables used by JVM implementations.
+//!
+//! Overriding follows section 5.4.5: a package-private method is only overridden by methods in
+//! the same run-time package, directly or through an intermediate override. All classes are
+//! assumed to be loaded by the same class loader, so run-time packages are identified by package
+//! name alone. Methods marked `ACC_BRIDGE` or `ACC_SYNTHETIC` override and are overridden like
+//! any other method; the bridges that compilers generate are what make a covariant or generic
+//! override dispatch correctly, since the JVM only matches methods with identical descriptors.
+
+use std::rc::Rc;
+
+use crate::classpath::ClassRepository;
+use crate::resolution::{maximally_specific_methods, ResolvedMethod};
+use crate::{ClassAccessFlags, ClassFile, MethodAccessFlags, MethodInfo, ParseError};
+
+/// The outcome of selecting the method to invoke.
+#[derive(Clone, Debug)]
+pub enum Selection {
+    /// The method that is invoked. If it is abstract, invoking it throws `AbstractMethodError`.
+    Method(ResolvedMethod),
+    /// More than one maximally-specific default method applies and none overrides the others,
+    /// so invoking the method throws `IncompatibleClassChangeError`.
+    Conflict(Vec<ResolvedMethod>),
+}
+
+impl Selection {
+    /// Returns the selected method, unless there is a conflict or the method is abstract.
+    pub fn method(&self) -> Option<&ResolvedMethod> {
+        match self {
+            Selection::Method(method) if !method.method().access_flags.contains(MethodAccessFlags::ABSTRACT) => {
+                Some(method)
+            }
+            _ => None,
+        }
+    }
+
+    // True for selections from interfaces, which have to be redone when a subclass adds
+    // interfaces, as opposed to methods inherited from superclasses.
+    fn is_from_interface(&self) -> bool {
+        match self {
+            Selection::Method(method) => method.class.access_flags.contains(ClassAccessFlags::INTERFACE),
+            Selection::Conflict(_) => true,
+        }
+    }
+}
+
+/// A slot in a vtable or itable.
+#[derive(Clone, Debug)]
+pub struct DispatchEntry {
+    pub name: String,
+    pub descriptor: String,
+    /// The classes and interfaces whose methods this slot dispatches, starting with the one that
+    /// introduced the slot and followed by those that override it, in order.
+    pub declared_by: Vec<String>,
+    pub target: Selection,
+}
+
+/// The virtual methods of a class, each in its own slot. A subclass's vtable starts with the
+/// slots of its superclass's vtable, so a method keeps its slot number in every subclass.
+#[derive(Clone, Debug)]
+pub struct VTable {
+    pub class_name: String,
+    pub entries: Vec<DispatchEntry>,
+}
+
+impl VTable {
+    /// Returns the slot that dispatches the given method, such as one found by resolving the
+    /// method reference of an `invokevirtual` instruction.
+    pub fn slot(&self, class_name: &str, name: &str, descriptor: &str) -> Option<usize> {
+        self.entries.iter().position(|entry| {
+            entry.name == name && entry.descriptor == descriptor && entry.declared_by.iter().any(|c| c == class_name)
+        })
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ITableInterface {
+    pub name: String,
+    /// One entry for each abstract or default method of the interface, in declaration order.
+    pub entries: Vec<DispatchEntry>,
+}
+
+/// The methods that a class implements for each of its interfaces, including those it
+/// inherits from superclasses and superinterfaces.
+#[derive(Clone, Debug)]
+pub struct ITable {
+    pub class_name: String,
+    pub interfaces: Vec<ITableInterface>,
+}
+
+impl ITable {
+    pub fn interface(&self, name: &str) -> Option<&ITableInterface> {
+        self.interfaces.iter().find(|i| i.name == name)
+    }
+}
+
+fn package(class_name: &str) -> &str {
+    class_name.rsplit_once('/').map_or("", |(package, _)| package)
+}
+
+fn is_virtual(method: &MethodInfo) -> bool {
+    !method.access_flags.intersects(MethodAccessFlags::STATIC | MethodAccessFlags::PRIVATE)
+        && method.name != "<init>"
+        && method.name != "<clinit>"
+}
+
+// Whether a method declared in class `c` can override one declared in class `a`, by the rules
+// of section 5.4.5 that do not involve an intermediate override.
+fn can_override(c: &ClassFile, method: &MethodInfo, a: &ClassFile, overridden: &MethodInfo) -> bool {
+    if method.name != overridden.name || method.descriptor != overridden.descriptor || !is_virtual(method) {
+        return false;
+    }
+    let flags = overridden.access_flags;
+    if flags.intersects(MethodAccessFlags::STATIC | MethodAccessFlags::PRIVATE) {
+        return false;
+    }
+    flags.intersects(MethodAccessFlags::PUBLIC | MethodAccessFlags::PROTECTED) || package(&c.this_class) == package(&a.this_class)
+}
+
+fn methods_named<'a>(class: &'a Rc<ClassFile>, name: &'a str, descriptor: &'a str) -> impl Iterator<Item = ResolvedMethod> + 'a {
+    class.methods.iter().enumerate().filter(move |(_, m)| m.name == name && m.descriptor == descriptor).map(
+        move |(index, _)| ResolvedMethod {
+            class: class.clone(),
+            index,
+            signature_polymorphic: false,
+        },
+    )
+}
+
+/// Selects the method that an invocation of the resolved method runs when the receiver is an
+/// instance of the given class, as described in section 5.4.6 of the JVM spec. Private methods
+/// select themselves. Otherwise the receiver class and its superclasses are searched for an
+/// override of the resolved method, and failing that, the maximally-specific default methods of
+/// the receiver class's superinterfaces are considered. Fails if no method applies at all, which
+/// the JVM reports as an `AbstractMethodError`.
+pub fn select_method(repo: &ClassRepository, class_name: &str, resolved: &ResolvedMethod) -> Result<Selection, ParseError> {
+    let name = &resolved.method().name;
+    let descriptor = &resolved.method().descriptor;
+    if resolved.method().access_flags.contains(MethodAccessFlags::PRIVATE) {
+        return Ok(Selection::Method(resolved.clone()));
+    }
+    let mut chain = vec![class_name.to_string()];
+    chain.extend(repo.superclasses(class_name)?);
+    match chain.iter().position(|c| c == &resolved.class.this_class) {
+        Some(position) => {
+            // Walk down from the resolved method's class, so that a method that overrides it
+            // through an intermediate override in the same package is found too.
+            let mut overrides = vec![resolved.clone()];
+            for superclass in chain[..position].iter().rev() {
+                let class = repo.load(superclass)?;
+                for method in methods_named(&class, name, descriptor) {
+                    if overrides.iter().any(|o| can_override(&class, method.method(), &o.class, o.method())) {
+                        overrides.push(method);
+                    }
+                }
+            }
+            if let Some(method) = overrides.pop() {
+                return Ok(Selection::Method(method));
+            }
+        }
+        None => {
+            for superclass in &chain {
+                let class = repo.load(superclass)?;
+                for method in methods_named(&class, name, descriptor) {
+                    if can_override(&class, method.method(), &resolved.class, resolved.method()) {
+                        return Ok(Selection::Method(method));
+                    }
+                }
+            }
+        }
+    }
+    let maximal = maximally_specific_methods(repo, class_name, name, descriptor)?;
+    let (concrete, abstract_): (Vec<ResolvedMethod>, Vec<ResolvedMethod>) =
+        maximal.into_iter().partition(|m| !m.method().access_flags.contains(MethodAccessFlags::ABSTRACT));
+    match (concrete.len(), abstract_.into_iter().next()) {
+        (1, _) => Ok(Selection::Method(concrete.into_iter().next().unwrap())),
+        (0, Some(method)) => Ok(Selection::Method(method)),
+        (0, None) => fail!("No method {}{} to select in {}", name, descriptor, class_name),
+        _ => Ok(Selection::Conflict(concrete)),
+    }
+}
+
+// Adds a slot for each interface method that the class does not already have a slot for, and
+// redoes the selection for slots that were filled from interfaces, which may now have a more
+// specific default method.
+fn add_interface_slots(repo: &ClassRepository, class_name: &str, entries: &mut Vec<DispatchEntry>) -> Result<(), ParseError> {
+    for interface_name in repo.all_interfaces(class_name)? {
+        let interface = repo.load(&interface_name)?;
+        for (index, method) in interface.methods.iter().enumerate() {
+            if !is_virtual(method) {
+                continue;
+            }
+            let resolved = ResolvedMethod {
+                class: interface.clone(),
+                index,
+                signature_polymorphic: false,
+            };
+            let existing = entries.iter().position(|e| e.name == method.name && e.descriptor == method.descriptor);
+            match existing {
+                Some(slot) if entries[slot].target.is_from_interface() => {
+                    if !entries[slot].declared_by.contains(&interface_name) {
+                        entries[slot].declared_by.push(interface_name.clone());
+                    }
+                    entries[slot].target = select_method(repo, class_name, &resolved)?;
+                }
+                Some(_) => (),
+                None => entries.push(DispatchEntry {
+                    name: method.name.clone(),
+                    descriptor: method.descriptor.clone(),
+                    declared_by: vec![interface_name.clone()],
+                    target: select_method(repo, class_name, &resolved)?,
+                }),
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Computes the vtable of a class. It starts with the slots of the superclass's vtable, with
+/// those that the class overrides pointing at its own methods, followed by a new slot for each
+/// virtual method it declares that overrides nothing. Interface methods that the class inherits
+/// no implementation of from its superclasses get slots too, pointing at the selected default
+/// method, or at the abstract interface method if there is none. Array types have the vtable of
+/// `java/lang/Object`. Fails for interfaces, and for classes that override a final method, which
+/// the JVM rejects when loading the class.
+pub fn vtable(repo: &ClassRepository, class_name: &str) -> Result<VTable, ParseError> {
+    let class_name = if class_name.starts_with('[') { "java/lang/Object" } else { class_name };
+    if repo.is_interface(class_name)? {
+        fail!("Interface {} has no vtable", class_name);
+    }
+    let mut chain = repo.superclasses(class_name)?;
+    chain.reverse();
+    chain.push(class_name.to_string());
+    let mut entries: Vec<DispatchEntry> = Vec::new();
+    for class_name in &chain {
+        let class = repo.load(class_name)?;
+        for (index, method) in class.methods.iter().enumerate() {
+            if !is_virtual(method) {
+                continue;
+            }
+            let mut overrides = false;
+            for entry in entries.iter_mut() {
+                if entry.name != method.name || entry.descriptor != method.descriptor {
+                    continue;
+                }
+                let mut overridden = None;
+                for declaring_class in &entry.declared_by {
+                    let declaring_class = repo.load(declaring_class)?;
+                    let other = methods_named(&declaring_class, &method.name, &method.descriptor).next();
+                    if let Some(other) = other.filter(|o| can_override(&class, method, &o.class, o.method())) {
+                        overridden = Some(other);
+                        break;
+                    }
+                }
+                if let Some(other) = overridden {
+                    if other.method().access_flags.contains(MethodAccessFlags::FINAL) {
+                        fail!(
+                            "Method {}{} in {} overrides final method in {}",
+                            method.name,
+                            method.descriptor,
+                            class_name,
+                            other.class.this_class
+                        );
+                    }
+                    entry.declared_by.push(class_name.clone());
+                    entry.target = Selection::Method(ResolvedMethod {
+                        class: class.clone(),
+                        index,
+                        signature_polymorphic: false,
+                    });
+                    overrides = true;
+                }
+            }
+            if !overrides {
+                entries.push(DispatchEntry {
+                    name: method.name.clone(),
+                    descriptor: method.descriptor.clone(),
+                    declared_by: vec![class_name.clone()],
+                    target: Selection::Method(ResolvedMethod {
+                        class: class.clone(),
+                        index,
+                        signature_polymorphic: false,
+                    }),
+                });
+            }
+        }
+        add_interface_slots(repo, class_name, &mut entries)?;
+    }
+    Ok(VTable {
+        class_name: class_name.to_string(),
+        entries,
+    })
+}
+
+/// Computes the itable of a class: for each interface the class implements, directly or
+/// indirectly, the method selected for each of the interface's abstract and default methods.
+/// Fails for interfaces.
+pub fn itable(repo: &ClassRepository, class_name: &str) -> Result<ITable, ParseError> {
+    if repo.is_interface(class_name)? {
+        fail!("Interface {} has no itable", class_name);
+    }
+    let mut interfaces = Vec::new();
+    for interface_name in repo.all_interfaces(class_name)? {
+        let interface = repo.load(&interface_name)?;
+        let mut entries = Vec::new();
+        for (index, method) in interface.methods.iter().enumerate() {
+            if !is_virtual(method) {
+                continue;
+            }
+            let resolved = ResolvedMethod {
+                class: interface.clone(),
+                index,
+                signature_polymorphic: false,
+            };
+            entries.push(DispatchEntry {
+                name: method.name.clone(),
+                descriptor: method.descriptor.clone(),
+                declared_by: vec![interface_name.clone()],
+                target: select_method(repo, class_name, &resolved)?,
+            });
+        }
+        interfaces.push(ITableInterface {
+            name: interface_name,
+            entries,
+        });
+    }
+    Ok(ITable {
+        class_name: class_name.to_string(),
+        interfaces,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resolution::tests::repository;
+
+    fn targets(entries: &[DispatchEntry]) -> Vec<String> {
+        entries
+            .iter()
+            .map(|entry| match &entry.target {
+                Selection::Method(m) => format!("{}{} {}", entry.name, entry.descriptor, m.class.this_class),
+                Selection::Conflict(methods) => {
+                    let classes: Vec<&str> = methods.iter().map(|m| m.class.this_class.as_str()).collect();
+                    format!("{}{} conflict {}", entry.name, entry.descriptor, classes.join(" "))
+                }
+            })
+            .collect()
+    }
+
+    fn method(repo: &ClassRepository, class_name: &str, name: &str) -> ResolvedMethod {
+        let class = repo.load(class_name).unwrap();
+        let index = class.methods.iter().position(|m| m.name == name).unwrap();
+        ResolvedMethod {
+            class,
+            index,
+            signature_polymorphic: false,
+        }
+    }
+
+    #[test]
+    fn test_vtable_overriding() {
+        let repo = repository(&[
+            "java/lang/Object, method public hashCode ()I, method private p ()V, method public static s ()V",
+            "p/A, method m ()V, method public n ()V, method public final f ()V, method public <init> ()V",
+            "q/B extends p/A, method public m ()V, method public n ()V",
+            "p/C extends q/B, method public m ()V",
+            "q/D extends p/A, method public f ()V",
+            "G, method public get ()Ljava/lang/Object;",
+            "H extends G, method public get ()Ljava/lang/String;, method public bridge synthetic get ()Ljava/lang/Object;",
+        ]);
+        let table = vtable(&repo, "p/A").unwrap();
+        assert_eq!(targets(&table.entries), vec!["hashCode()I java/lang/Object", "m()V p/A", "n()V p/A", "f()V p/A"]);
+        // q/B.m cannot override the package-private p/A.m, so it gets a new slot
+        let table = vtable(&repo, "q/B").unwrap();
+        assert_eq!(targets(&table.entries[1..]), vec!["m()V p/A", "n()V q/B", "f()V p/A", "m()V q/B"]);
+        assert_eq!(table.slot("p/A", "m", "()V"), Some(1));
+        assert_eq!(table.slot("q/B", "m", "()V"), Some(4));
+        assert_eq!(table.slot("q/B", "n", "()V"), Some(2));
+        // p/C.m overrides both, one by being in the same package and the other by being public
+        let table = vtable(&repo, "p/C").unwrap();
+        assert_eq!(targets(&table.entries[1..]), vec!["m()V p/C", "n()V q/B", "f()V p/A", "m()V p/C"]);
+        assert_eq!(table.entries[1].declared_by, vec!["p/A", "p/C"]);
+
+        let a_m = method(&repo, "p/A", "m");
+        let selected = |class_name| match select_method(&repo, class_name, &a_m).unwrap() {
+            Selection::Method(m) => m.class.this_class.clone(),
+            x => panic!("Unexpected selection {:?}", x),
+        };
+        assert_eq!(selected("q/B"), "p/A");
+        assert_eq!(selected("p/C"), "p/C");
+
+        let err = vtable(&repo, "q/D").unwrap_err();
+        assert_eq!(err.to_string(), "Method f()V in q/D overrides final method in p/A");
+
+        // The bridge takes over the slot of G.get, and the covariant override gets its own
+        let table = vtable(&repo, "H").unwrap();
+        assert_eq!(
+            targets(&table.entries[1..]),
+            vec!["get()Ljava/lang/Object; H", "get()Ljava/lang/String; H"]
+        );
+        assert!(table.entries[1].target.method().unwrap().method().access_flags.contains(MethodAccessFlags::BRIDGE));
+        assert_eq!(targets(&vtable(&repo, "[I").unwrap().entries), vec!["hashCode()I java/lang/Object"]);
+    }
+
+    #[test]
+    fn test_default_methods() {
+        let repo = repository(&[
+            "java/lang/Object",
+            "interface abstract I, method public d ()V, method public abstract a ()V, method public static s ()V",
+            "interface abstract J extends java/lang/Object I, method public d ()V",
+            "interface abstract K, method public d ()V",
+            "abstract D extends java/lang/Object J",
+            "abstract E extends D K",
+            "F extends E, method public d ()V, method public a ()V",
+        ]);
+        let table = vtable(&repo, "D").unwrap();
+        assert_eq!(targets(&table.entries), vec!["d()V J", "a()V I"]);
+        assert!(table.entries[1].target.method().is_none());
+        let table = vtable(&repo, "E").unwrap();
+        assert_eq!(targets(&table.entries), vec!["d()V conflict K J", "a()V I"]);
+        assert_eq!(table.entries[0].declared_by, vec!["J", "I", "K"]);
+        let table = vtable(&repo, "F").unwrap();
+        assert_eq!(targets(&table.entries), vec!["d()V F", "a()V F"]);
+
+        let table = itable(&repo, "E").unwrap();
+        let names: Vec<&str> = table.interfaces.iter().map(|i| i.name.as_str()).collect();
+        assert_eq!(names, vec!["K", "J", "I"]);
+        assert_eq!(targets(&table.interface("I").unwrap().entries), vec!["d()V conflict K J", "a()V I"]);
+        assert_eq!(targets(&table.interface("J").unwrap().entries), vec!["d()V conflict K J"]);
+        let table = itable(&repo, "F").unwrap();
+        assert_eq!(targets(&table.interface("I").unwrap().entries), vec!["d()V F", "a()V F"]);
+
+        assert_eq!(vtable(&repo, "I").unwrap_err().to_string(), "Interface I has no vtable");
+        let i_d = method(&repo, "I", "d");
+        assert!(matches!(select_method(&repo, "D", &i_d).unwrap(), Selection::Method(m) if m.class.this_class == "J"));
+    }
+}
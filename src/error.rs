@@ -28,6 +28,9 @@ macro_rules! fail {
     (($msg:literal), ($context:literal)) => {
         return Err(ParseError::with_context(ParseError::new($msg.to_string()), $context.to_string()))
     };
+    (($msg:literal), ($contextfmt:literal, $($contextargs:tt)*)) => {
+        return Err(ParseError::with_context(ParseError::new($msg.to_string()), format!($contextfmt, $($contextargs)*)))
+    };
     ($fmtstr:literal, $($args:tt)*) => {
         return Err(ParseError::new(format!($fmtstr, $($args)*)))
     };
@@ -0,0 +1,2211 @@
+//! An interpreter for running static methods and class initializers, for evaluating code such as
+//! the `<clinit>` of a class that builds lookup tables, or a pure helper method.
+//!
+//! Classes are loaded from a [`ClassRepository`] and initialized on first use, as described in
+//! section 5.5 of the JVM spec. Fields and methods are resolved with the [`crate::resolution`]
+//! module, and virtual calls are dispatched with [`select_method`]. Methods are run from their
+//! bytecode, so the classes must be parsed with bytecode enabled. Native methods, and methods of
+//! classes that are not in the repository such as those of the JDK, are left to a
+//! [`NativeMethods`] hook, which by default is [`BasicNatives`].
+//!
+//! Exceptions are objects on the interpreter's [`Heap`], and are caught by the exception tables
+//! of the running methods. The exceptions that the interpreter throws itself, such as
+//! `java/lang/ArithmeticException`, do not need to be in the repository. Bytecode is not verified
+//! before it runs; an instruction that finds the wrong kind of value on the operand stack fails
+//! with an error. Threads, monitors, reflection and `invokedynamic` other than string
+//! concatenation are not supported.
+
+use std::collections::{HashMap, HashSet};
+use std::convert::TryFrom;
+use std::rc::Rc;
+
+use crate::attributes::{AttributeData, AttributeInfo, CodeData};
+use crate::bytecode::{ByteCode, Opcode, PrimitiveArrayType};
+use crate::classpath::ClassRepository;
+use crate::constant_pool::{BootstrapArgument, InvokeDynamic, LiteralConstant, Loadable, MemberRef};
+use crate::dispatch::{select_method, Selection};
+use crate::names::split_method_descriptor;
+use crate::resolution::{resolve_field, resolve_interface_method, resolve_method};
+use crate::{ClassAccessFlags, ClassFile, FieldAccessFlags, MethodAccessFlags, ParseError};
+
+const OBJECT: &str = "java/lang/Object";
+const STRING: &str = "java/lang/String";
+const NULL_POINTER: &str = "java/lang/NullPointerException";
+const ARITHMETIC: &str = "java/lang/ArithmeticException";
+const ARRAY_INDEX: &str = "java/lang/ArrayIndexOutOfBoundsException";
+const NEGATIVE_ARRAY_SIZE: &str = "java/lang/NegativeArraySizeException";
+
+// The superclasses of the exceptions the interpreter throws, for when the JDK's classes are not
+// in the repository.
+const BUILTIN_SUPERCLASSES: &[(&str, &str)] = &[
+    (NULL_POINTER, "java/lang/RuntimeException"),
+    (ARITHMETIC, "java/lang/RuntimeException"),
+    (ARRAY_INDEX, "java/lang/IndexOutOfBoundsException"),
+    ("java/lang/StringIndexOutOfBoundsException", "java/lang/IndexOutOfBoundsException"),
+    ("java/lang/IndexOutOfBoundsException", "java/lang/RuntimeException"),
+    (NEGATIVE_ARRAY_SIZE, "java/lang/RuntimeException"),
+    ("java/lang/ClassCastException", "java/lang/RuntimeException"),
+    ("java/lang/RuntimeException", "java/lang/Exception"),
+    ("java/lang/Exception", "java/lang/Throwable"),
+    ("java/lang/AbstractMethodError", "java/lang/IncompatibleClassChangeError"),
+    ("java/lang/InstantiationError", "java/lang/IncompatibleClassChangeError"),
+    ("java/lang/IncompatibleClassChangeError", "java/lang/LinkageError"),
+    ("java/lang/ExceptionInInitializerError", "java/lang/LinkageError"),
+    ("java/lang/NoClassDefFoundError", "java/lang/LinkageError"),
+    ("java/lang/LinkageError", "java/lang/Error"),
+    ("java/lang/Error", "java/lang/Throwable"),
+    ("java/lang/Throwable", OBJECT),
+];
+const STRING_INTERFACES: &[&str] = &["java/io/Serializable", "java/lang/Comparable", "java/lang/CharSequence"];
+
+/// A value in a local variable, on the operand stack, or in a field or array element. As in the
+/// JVM, `boolean`, `byte`, `char` and `short` values are ints.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Value {
+    Int(i32),
+    Long(i64),
+    Float(f32),
+    Double(f64),
+    Null,
+    /// A reference to an object on the [`Heap`].
+    Reference(usize),
+    /// The offset of an instruction, pushed by `jsr` for `ret` to return to.
+    ReturnAddress(usize),
+}
+
+impl Value {
+    /// Returns the value that a field or array element with the given descriptor starts with.
+    pub fn default_for(descriptor: &str) -> Value {
+        match descriptor.as_bytes().first() {
+            Some(b'J') => Value::Long(0),
+            Some(b'F') => Value::Float(0.0),
+            Some(b'D') => Value::Double(0.0),
+            Some(b'L') | Some(b'[') => Value::Null,
+            _ => Value::Int(0),
+        }
+    }
+
+    // Longs and doubles take two local variable slots.
+    pub(crate) fn is_wide(&self) -> bool {
+        matches!(self, Value::Long(_) | Value::Double(_))
+    }
+}
+
+// Narrows a value stored to a field or array element of the given type, as the JVM does.
+pub(crate) fn narrow(descriptor: &str, value: Value) -> Value {
+    match (descriptor, value) {
+        ("Z", Value::Int(v)) => Value::Int(v & 1),
+        ("B", Value::Int(v)) => Value::Int(v as i8 as i32),
+        ("C", Value::Int(v)) => Value::Int(v as u16 as i32),
+        ("S", Value::Int(v)) => Value::Int(v as i16 as i32),
+        _ => value,
+    }
+}
+
+// Returns the descriptors of the operands and the result of an instruction that computes a value
+// from the values on top of the operand stack, which are the arithmetic, bitwise, conversion and
+// comparison instructions, or None for other instructions.
+pub(crate) fn operation(opcode: &Opcode) -> Option<(&'static [&'static str], &'static str)> {
+    Some(match opcode {
+        Opcode::Iadd | Opcode::Isub | Opcode::Imul | Opcode::Idiv | Opcode::Irem => (&["I", "I"], "I"),
+        Opcode::Ishl | Opcode::Ishr | Opcode::Iushr | Opcode::Iand | Opcode::Ior | Opcode::Ixor => (&["I", "I"], "I"),
+        Opcode::Ladd | Opcode::Lsub | Opcode::Lmul | Opcode::Ldiv | Opcode::Lrem => (&["J", "J"], "J"),
+        Opcode::Land | Opcode::Lor | Opcode::Lxor => (&["J", "J"], "J"),
+        Opcode::Lshl | Opcode::Lshr | Opcode::Lushr => (&["J", "I"], "J"),
+        Opcode::Fadd | Opcode::Fsub | Opcode::Fmul | Opcode::Fdiv | Opcode::Frem => (&["F", "F"], "F"),
+        Opcode::Dadd | Opcode::Dsub | Opcode::Dmul | Opcode::Ddiv | Opcode::Drem => (&["D", "D"], "D"),
+        Opcode::Ineg | Opcode::I2b | Opcode::I2c | Opcode::I2s => (&["I"], "I"),
+        Opcode::Lneg => (&["J"], "J"),
+        Opcode::Fneg => (&["F"], "F"),
+        Opcode::Dneg => (&["D"], "D"),
+        Opcode::I2l => (&["I"], "J"),
+        Opcode::I2f => (&["I"], "F"),
+        Opcode::I2d => (&["I"], "D"),
+        Opcode::L2i => (&["J"], "I"),
+        Opcode::L2f => (&["J"], "F"),
+        Opcode::L2d => (&["J"], "D"),
+        Opcode::F2i => (&["F"], "I"),
+        Opcode::F2l => (&["F"], "J"),
+        Opcode::F2d => (&["F"], "D"),
+        Opcode::D2i => (&["D"], "I"),
+        Opcode::D2l => (&["D"], "J"),
+        Opcode::D2f => (&["D"], "F"),
+        Opcode::Lcmp => (&["J", "J"], "I"),
+        Opcode::Fcmpl | Opcode::Fcmpg => (&["F", "F"], "I"),
+        Opcode::Dcmpl | Opcode::Dcmpg => (&["D", "D"], "I"),
+        _ => return None,
+    })
+}
+
+// Computes the result of an instruction that `operation` describes from its operands, in the
+// order they were pushed. Returns None for an integer division by zero, which throws an
+// `ArithmeticException`.
+pub(crate) fn compute(opcode: &Opcode, operands: &[Value]) -> Result<Option<Value>, ParseError> {
+    macro_rules! binary {
+        ($in:ident, $in2:ident => $out:ident, |$a:ident, $b:ident| $e:expr) => {
+            match *operands {
+                [Value::$in($a), Value::$in2($b)] => Value::$out($e),
+                _ => fail!("Invalid operands {:?} for {:?}", operands, opcode),
+            }
+        };
+        ($in:ident => $out:ident, |$a:ident, $b:ident| $e:expr) => {
+            binary!($in, $in => $out, |$a, $b| $e)
+        };
+        ($variant:ident, |$a:ident, $b:ident| $e:expr) => {
+            binary!($variant, $variant => $variant, |$a, $b| $e)
+        };
+    }
+    macro_rules! divide {
+        ($variant:ident, |$a:ident, $b:ident| $e:expr) => {
+            match *operands {
+                [Value::$variant(_), Value::$variant(0)] => return Ok(None),
+                _ => binary!($variant, |$a, $b| $e),
+            }
+        };
+    }
+    macro_rules! unary {
+        ($in:ident => $out:ident, |$a:ident| $e:expr) => {
+            match *operands {
+                [Value::$in($a)] => Value::$out($e),
+                _ => fail!("Invalid operands {:?} for {:?}", operands, opcode),
+            }
+        };
+    }
+
+    Ok(Some(match opcode {
+        Opcode::Iadd => binary!(Int, |a, b| a.wrapping_add(b)),
+        Opcode::Ladd => binary!(Long, |a, b| a.wrapping_add(b)),
+        Opcode::Fadd => binary!(Float, |a, b| a + b),
+        Opcode::Dadd => binary!(Double, |a, b| a + b),
+        Opcode::Isub => binary!(Int, |a, b| a.wrapping_sub(b)),
+        Opcode::Lsub => binary!(Long, |a, b| a.wrapping_sub(b)),
+        Opcode::Fsub => binary!(Float, |a, b| a - b),
+        Opcode::Dsub => binary!(Double, |a, b| a - b),
+        Opcode::Imul => binary!(Int, |a, b| a.wrapping_mul(b)),
+        Opcode::Lmul => binary!(Long, |a, b| a.wrapping_mul(b)),
+        Opcode::Fmul => binary!(Float, |a, b| a * b),
+        Opcode::Dmul => binary!(Double, |a, b| a * b),
+        Opcode::Idiv => divide!(Int, |a, b| a.wrapping_div(b)),
+        Opcode::Ldiv => divide!(Long, |a, b| a.wrapping_div(b)),
+        Opcode::Fdiv => binary!(Float, |a, b| a / b),
+        Opcode::Ddiv => binary!(Double, |a, b| a / b),
+        Opcode::Irem => divide!(Int, |a, b| a.wrapping_rem(b)),
+        Opcode::Lrem => divide!(Long, |a, b| a.wrapping_rem(b)),
+        Opcode::Frem => binary!(Float, |a, b| a % b),
+        Opcode::Drem => binary!(Double, |a, b| a % b),
+        Opcode::Ineg => unary!(Int => Int, |a| a.wrapping_neg()),
+        Opcode::Lneg => unary!(Long => Long, |a| a.wrapping_neg()),
+        Opcode::Fneg => unary!(Float => Float, |a| -a),
+        Opcode::Dneg => unary!(Double => Double, |a| -a),
+        Opcode::Ishl => binary!(Int, |a, b| a.wrapping_shl(b as u32)),
+        Opcode::Ishr => binary!(Int, |a, b| a.wrapping_shr(b as u32)),
+        Opcode::Iushr => binary!(Int, |a, b| (a as u32).wrapping_shr(b as u32) as i32),
+        Opcode::Lshl => binary!(Long, Int => Long, |a, b| a.wrapping_shl(b as u32)),
+        Opcode::Lshr => binary!(Long, Int => Long, |a, b| a.wrapping_shr(b as u32)),
+        Opcode::Lushr => binary!(Long, Int => Long, |a, b| (a as u64).wrapping_shr(b as u32) as i64),
+        Opcode::Iand => binary!(Int, |a, b| a & b),
+        Opcode::Land => binary!(Long, |a, b| a & b),
+        Opcode::Ior => binary!(Int, |a, b| a | b),
+        Opcode::Lor => binary!(Long, |a, b| a | b),
+        Opcode::Ixor => binary!(Int, |a, b| a ^ b),
+        Opcode::Lxor => binary!(Long, |a, b| a ^ b),
+        Opcode::I2l => unary!(Int => Long, |a| a as i64),
+        Opcode::I2f => unary!(Int => Float, |a| a as f32),
+        Opcode::I2d => unary!(Int => Double, |a| a as f64),
+        Opcode::L2i => unary!(Long => Int, |a| a as i32),
+        Opcode::L2f => unary!(Long => Float, |a| a as f32),
+        Opcode::L2d => unary!(Long => Double, |a| a as f64),
+        Opcode::F2i => unary!(Float => Int, |a| a as i32),
+        Opcode::F2l => unary!(Float => Long, |a| a as i64),
+        Opcode::F2d => unary!(Float => Double, |a| a as f64),
+        Opcode::D2i => unary!(Double => Int, |a| a as i32),
+        Opcode::D2l => unary!(Double => Long, |a| a as i64),
+        Opcode::D2f => unary!(Double => Float, |a| a as f32),
+        Opcode::I2b => unary!(Int => Int, |a| a as i8 as i32),
+        Opcode::I2c => unary!(Int => Int, |a| a as u16 as i32),
+        Opcode::I2s => unary!(Int => Int, |a| a as i16 as i32),
+        Opcode::Lcmp => binary!(Long => Int, |a, b| a.cmp(&b) as i32),
+        Opcode::Fcmpl => binary!(Float => Int, |a, b| a.partial_cmp(&b).map_or(-1, |o| o as i32)),
+        Opcode::Fcmpg => binary!(Float => Int, |a, b| a.partial_cmp(&b).map_or(1, |o| o as i32)),
+        Opcode::Dcmpl => binary!(Double => Int, |a, b| a.partial_cmp(&b).map_or(-1, |o| o as i32)),
+        Opcode::Dcmpg => binary!(Double => Int, |a, b| a.partial_cmp(&b).map_or(1, |o| o as i32)),
+        _ => fail!("{:?} does not compute a value from its operands", opcode),
+    }))
+}
+
+// Returns the number of elements in all the arrays that allocating a multidimensional array with
+// the given lengths creates.
+pub(crate) fn array_size(lengths: &[usize]) -> u64 {
+    let mut arrays = 1u64;
+    let mut size = 0u64;
+    for length in lengths {
+        arrays = arrays.saturating_mul(*length as u64);
+        size = size.saturating_add(arrays);
+    }
+    size
+}
+
+// Allocates an array with the given descriptor, and arrays for its elements for each length after
+// the first, passing the component type and elements of each one to `alloc`.
+pub(crate) fn alloc_array(descriptor: &str, lengths: &[usize], alloc: &mut dyn FnMut(&str, Vec<Value>) -> Value) -> Result<Value, ParseError> {
+    let (length, rest) = match lengths.split_first() {
+        Some((length, rest)) if descriptor.bytes().take_while(|b| *b == b'[').count() > rest.len() => (*length, rest),
+        _ => fail!("Invalid dimensions {} for array type {}", lengths.len(), descriptor),
+    };
+    let component = &descriptor[1..];
+    let elements = if rest.is_empty() {
+        vec![Value::default_for(component); length]
+    } else {
+        (0..length).map(|_| alloc_array(component, rest, alloc)).collect::<Result<_, _>>()?
+    };
+    Ok(alloc(component, elements))
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct FieldValue {
+    /// The class that declares the field, which may be a superclass of the object's class.
+    pub class_name: String,
+    pub name: String,
+    pub descriptor: String,
+    pub value: Value,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum HeapObject {
+    /// An instance of a class, with the instance fields of the class and its superclasses.
+    Object { class_name: String, fields: Vec<FieldValue> },
+    /// An array, with the descriptor of its component type, such as `I` for an `int[]`.
+    Array { component: String, elements: Vec<Value> },
+    String(String),
+}
+
+impl HeapObject {
+    /// Returns the name of the object's class, such as `[I` for an `int[]`.
+    pub fn class_name(&self) -> String {
+        match self {
+            HeapObject::Object { class_name, .. } => class_name.clone(),
+            HeapObject::Array { component, .. } => format!("[{}", component),
+            HeapObject::String(_) => STRING.to_string(),
+        }
+    }
+
+    /// Returns the value of the instance field with the given name. If a subclass declares a
+    /// field with the same name as a superclass, the subclass's field is returned.
+    pub fn field(&self, name: &str) -> Option<Value> {
+        match self {
+            HeapObject::Object { fields, .. } => fields.iter().rev().find(|f| f.name == name).map(|f| f.value),
+            _ => None,
+        }
+    }
+}
+
+/// The objects created by an [`Interpreter`]. Objects are never freed.
+#[derive(Clone, Debug, Default)]
+pub struct Heap {
+    objects: Vec<HeapObject>,
+    interned: HashMap<String, usize>,
+}
+
+impl Heap {
+    pub fn alloc(&mut self, object: HeapObject) -> Value {
+        self.objects.push(object);
+        Value::Reference(self.objects.len() - 1)
+    }
+
+    /// Returns the object that a value refers to, or None for null and values that are not
+    /// references.
+    pub fn get(&self, value: Value) -> Option<&HeapObject> {
+        match value {
+            Value::Reference(index) => self.objects.get(index),
+            _ => None,
+        }
+    }
+
+    pub fn get_mut(&mut self, value: Value) -> Option<&mut HeapObject> {
+        match value {
+            Value::Reference(index) => self.objects.get_mut(index),
+            _ => None,
+        }
+    }
+
+    /// Returns the interned string with the given contents, which is what `ldc` loads for string
+    /// constants.
+    pub fn intern(&mut self, s: &str) -> Value {
+        if let Some(index) = self.interned.get(s) {
+            return Value::Reference(*index);
+        }
+        let value = self.alloc(HeapObject::String(s.to_string()));
+        if let Value::Reference(index) = value {
+            self.interned.insert(s.to_string(), index);
+        }
+        value
+    }
+
+    /// Returns the contents of a string, or None if the value does not refer to a string.
+    pub fn string(&self, value: Value) -> Option<&str> {
+        match self.get(value) {
+            Some(HeapObject::String(s)) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.objects.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.objects.is_empty()
+    }
+}
+
+/// How a method invocation or class initialization completed.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Completion {
+    /// The method returned, with a value unless its return type is `void`.
+    Return(Option<Value>),
+    /// The method threw the referenced exception.
+    Throw(Value),
+}
+
+/// A hook for running methods that have no bytecode, such as native methods and methods of the
+/// JDK when it is not in the repository.
+pub trait NativeMethods {
+    /// Runs a method, or returns None to leave it to the interpreter. This is called before every
+    /// invocation. For `invokevirtual` and `invokeinterface` the class name is that of the
+    /// receiver object, and otherwise it is the class named by the instruction. For instance
+    /// methods the receiver is the first argument. Longs and doubles take a single argument.
+    fn invoke(
+        &self,
+        heap: &mut Heap,
+        class_name: &str,
+        name: &str,
+        descriptor: &str,
+        args: &[Value],
+    ) -> Option<Result<Completion, ParseError>>;
+}
+
+/// Implements `Object.<init>`, cloning arrays, some of the methods of `java.lang.String`, and
+/// the int versions of `Math.abs`, `Math.max` and `Math.min`. Hooks that implement other methods
+/// can delegate to this for the rest.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct BasicNatives;
+
+impl NativeMethods for BasicNatives {
+    fn invoke(
+        &self,
+        heap: &mut Heap,
+        class_name: &str,
+        name: &str,
+        descriptor: &str,
+        args: &[Value],
+    ) -> Option<Result<Completion, ParseError>> {
+        let int = |i: usize| match args.get(i) {
+            Some(Value::Int(v)) => Some(*v),
+            _ => None,
+        };
+        let value = match (class_name, name, descriptor) {
+            (OBJECT, "<init>", "()V") => None,
+            (_, "clone", "()Ljava/lang/Object;") if class_name.starts_with('[') => {
+                let array = heap.get(*args.first()?)?.clone();
+                Some(heap.alloc(array))
+            }
+            ("java/lang/Math", "abs", "(I)I") => Some(Value::Int(int(0)?.wrapping_abs())),
+            ("java/lang/Math", "max", "(II)I") => Some(Value::Int(int(0)?.max(int(1)?))),
+            ("java/lang/Math", "min", "(II)I") => Some(Value::Int(int(0)?.min(int(1)?))),
+            (STRING, "valueOf", _) => {
+                let (params, _) = split_method_descriptor(descriptor)?;
+                let s = match java_string(heap, params.first()?, *args.first()?) {
+                    Ok(s) => s,
+                    Err(e) => return Some(Err(e)),
+                };
+                Some(heap.alloc(HeapObject::String(s)))
+            }
+            (STRING, _, _) => return string_method(heap, name, descriptor, args),
+            _ => return None,
+        };
+        Some(Ok(Completion::Return(value)))
+    }
+}
+
+fn string_method(heap: &mut Heap, name: &str, descriptor: &str, args: &[Value]) -> Option<Result<Completion, ParseError>> {
+    let this = args.first().copied()?;
+    let s = heap.string(this)?.to_string();
+    let value = match (name, descriptor) {
+        ("length", "()I") => Value::Int(s.encode_utf16().count() as i32),
+        ("isEmpty", "()Z") => Value::Int(s.is_empty() as i32),
+        ("charAt", "(I)C") => {
+            let index = match args.get(1) {
+                Some(Value::Int(index)) => usize::try_from(*index).ok(),
+                _ => return None,
+            };
+            match index.and_then(|index| s.encode_utf16().nth(index)) {
+                Some(c) => Value::Int(c as i32),
+                None => {
+                    let exception = HeapObject::Object {
+                        class_name: "java/lang/StringIndexOutOfBoundsException".to_string(),
+                        fields: Vec::new(),
+                    };
+                    return Some(Ok(Completion::Throw(heap.alloc(exception))));
+                }
+            }
+        }
+        ("equals", "(Ljava/lang/Object;)Z") => Value::Int((heap.string(*args.get(1)?) == Some(&s)) as i32),
+        ("hashCode", "()I") => Value::Int(s.encode_utf16().fold(0i32, |h, c| h.wrapping_mul(31).wrapping_add(c as i32))),
+        ("concat", "(Ljava/lang/String;)Ljava/lang/String;") => {
+            let other = heap.string(*args.get(1)?)?;
+            let result = format!("{}{}", s, other);
+            heap.alloc(HeapObject::String(result))
+        }
+        ("toString", "()Ljava/lang/String;") => this,
+        ("intern", "()Ljava/lang/String;") => heap.intern(&s),
+        _ => return None,
+    };
+    Some(Ok(Completion::Return(Some(value))))
+}
+
+// Formats a value the way `String.valueOf` does.
+fn java_string(heap: &Heap, descriptor: &str, value: Value) -> Result<String, ParseError> {
+    Ok(match (descriptor, value) {
+        ("Z", Value::Int(v)) => (v != 0).to_string(),
+        ("C", Value::Int(v)) => String::from_utf16_lossy(&[v as u16]),
+        (_, Value::Int(v)) => v.to_string(),
+        (_, Value::Long(v)) => v.to_string(),
+        (_, Value::Float(v)) => java_float(v),
+        (_, Value::Double(v)) => java_double(v),
+        (_, Value::Null) => "null".to_string(),
+        (_, value) => match heap.get(value) {
+            Some(HeapObject::String(s)) => s.clone(),
+            Some(object) => fail!("Converting an instance of {} to a string is not supported", object.class_name()),
+            None => fail!("Invalid reference {:?}", value),
+        },
+    })
+}
+
+fn literal_string(literal: &LiteralConstant) -> String {
+    match literal {
+        LiteralConstant::Integer(v) => v.to_string(),
+        LiteralConstant::Float(v) => java_float(*v),
+        LiteralConstant::Long(v) => v.to_string(),
+        LiteralConstant::Double(v) => java_double(*v),
+        LiteralConstant::String(s) => s.clone(),
+        LiteralConstant::StringBytes(b) => String::from_utf8_lossy(b).into_owned(),
+    }
+}
+
+// Java prints the same shortest digits as Rust, but switches to scientific notation outside of
+// 10^-3 to 10^7, and always has a digit after the decimal point.
+fn java_decimal(plain: String, scientific: String, magnitude: f64) -> String {
+    if plain.contains("NaN") || plain.contains("inf") {
+        return plain.replace("inf", "Infinity");
+    }
+    if magnitude == 0.0 || (1e-3..1e7).contains(&magnitude) {
+        return plain;
+    }
+    match scientific.split_once('e') {
+        Some((mantissa, exponent)) if mantissa.contains('.') => format!("{}E{}", mantissa, exponent),
+        Some((mantissa, exponent)) => format!("{}.0E{}", mantissa, exponent),
+        None => plain,
+    }
+}
+
+pub(crate) fn java_float(v: f32) -> String {
+    java_decimal(format!("{:?}", v), format!("{:e}", v), v.abs() as f64)
+}
+
+pub(crate) fn java_double(v: f64) -> String {
+    java_decimal(format!("{:?}", v), format!("{:e}", v), v.abs())
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum InitState {
+    InProgress,
+    Done,
+    Failed,
+}
+
+struct ClassState {
+    state: InitState,
+    statics: Vec<FieldValue>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Invoke {
+    Static,
+    Special,
+    Virtual,
+    Interface,
+}
+
+// What happens after an instruction runs.
+enum Flow {
+    Next,
+    Jump(i32),
+    Goto(usize),
+    Return(Option<Value>),
+    Throw(Value),
+    // Runs a method or class initializer before continuing
+    Push(Frame),
+}
+
+// Why a frame was pushed, which decides what happens to the frame below it when it completes.
+enum FrameKind {
+    // A method invocation, whose result is pushed onto the caller's operand stack
+    Call,
+    // The <clinit> of a class, after which the instruction that needed the class runs again.
+    // The subclasses waiting on the class fail along with it.
+    Initializer { class_name: String, waiting: Vec<String> },
+}
+
+// The outcome of starting a method invocation.
+enum Call {
+    Done(Completion),
+    Push(Frame),
+}
+
+// Whether a class can be used, or what has to happen first.
+enum Init {
+    Ready,
+    Failed(Value),
+    Pending(Frame),
+}
+
+impl Init {
+    // Returns the flow for an instruction that cannot run until the class is initialized.
+    fn flow(self) -> Option<Flow> {
+        match self {
+            Init::Ready => None,
+            Init::Failed(exception) => Some(Flow::Throw(exception)),
+            Init::Pending(frame) => Some(Flow::Push(frame)),
+        }
+    }
+}
+
+// Returns the code of a method, which must have its bytecode parsed to run.
+fn method_code(class: &ClassFile, index: usize) -> Result<(&CodeData, &ByteCode), ParseError> {
+    let method = &class.methods[index];
+    let code = method.attributes.iter().find(|attr| attr.name == "Code").map(AttributeInfo::decoded).transpose()?;
+    let code = match code {
+        Some(AttributeData::Code(code)) => code,
+        _ => fail!("No Code attribute found for method {}.{}{}", class.this_class, method.name, method.descriptor),
+    };
+    match &code.bytecode {
+        Some(bytecode) => Ok((code, bytecode)),
+        None => fail!("Bytecode was not parsed for method {}.{}{}", class.this_class, method.name, method.descriptor),
+    }
+}
+
+struct Frame {
+    class: Rc<ClassFile>,
+    method: usize,
+    kind: FrameKind,
+    // The index of the current instruction in the bytecode
+    pc: usize,
+    locals: Vec<Value>,
+    stack: Vec<Value>,
+}
+
+impl Frame {
+    fn new(class: Rc<ClassFile>, method: usize, args: &[Value], kind: FrameKind) -> Result<Frame, ParseError> {
+        let (code, _) = method_code(&class, method)?;
+        let locals = vec![Value::Null; code.max_locals as usize];
+        let stack = Vec::with_capacity(code.max_stack as usize);
+        let mut frame = Frame { class, method, kind, pc: 0, locals, stack };
+        let mut slot = 0;
+        for arg in args {
+            frame.store(slot, *arg).map_err(|e| err!(e, "arguments of method {}", frame.method_name()))?;
+            slot += if arg.is_wide() { 2 } else { 1 };
+        }
+        Ok(frame)
+    }
+
+    fn method_name(&self) -> String {
+        let method = &self.class.methods[self.method];
+        format!("{}.{}{}", self.class.this_class, method.name, method.descriptor)
+    }
+
+    fn push(&mut self, value: Value) {
+        self.stack.push(value);
+    }
+
+    fn pop(&mut self) -> Result<Value, ParseError> {
+        self.stack.pop().ok_or_else(|| err!("Operand stack underflow"))
+    }
+
+    fn pop_int(&mut self) -> Result<i32, ParseError> {
+        match self.pop()? {
+            Value::Int(v) => Ok(v),
+            value => fail!("Expected an int on the operand stack but found {:?}", value),
+        }
+    }
+
+    fn pop_long(&mut self) -> Result<i64, ParseError> {
+        match self.pop()? {
+            Value::Long(v) => Ok(v),
+            value => fail!("Expected a long on the operand stack but found {:?}", value),
+        }
+    }
+
+    fn pop_float(&mut self) -> Result<f32, ParseError> {
+        match self.pop()? {
+            Value::Float(v) => Ok(v),
+            value => fail!("Expected a float on the operand stack but found {:?}", value),
+        }
+    }
+
+    fn pop_double(&mut self) -> Result<f64, ParseError> {
+        match self.pop()? {
+            Value::Double(v) => Ok(v),
+            value => fail!("Expected a double on the operand stack but found {:?}", value),
+        }
+    }
+
+    fn pop_reference(&mut self) -> Result<Value, ParseError> {
+        match self.pop()? {
+            value @ Value::Null | value @ Value::Reference(_) => Ok(value),
+            value => fail!("Expected a reference on the operand stack but found {:?}", value),
+        }
+    }
+
+    fn pop_arguments(&mut self, count: usize) -> Result<Vec<Value>, ParseError> {
+        if self.stack.len() < count {
+            fail!("Operand stack underflow");
+        }
+        Ok(self.stack.split_off(self.stack.len() - count))
+    }
+
+    fn load(&self, index: u16) -> Result<Value, ParseError> {
+        self.locals.get(index as usize).copied().ok_or_else(|| err!("Local variable {} out of range", index))
+    }
+
+    fn store(&mut self, index: usize, value: Value) -> Result<(), ParseError> {
+        let end = if value.is_wide() { index + 2 } else { index + 1 };
+        if end > self.locals.len() {
+            fail!("Local variable {} out of range", index);
+        }
+        self.locals[index] = value;
+        if value.is_wide() {
+            self.locals[index + 1] = Value::Null;
+        }
+        Ok(())
+    }
+
+    // Pushes the result of a call, checking it against the descriptor of the method.
+    fn push_result(&mut self, class_name: &str, name: &str, descriptor: &str, value: Option<Value>) -> Result<(), ParseError> {
+        let (_, return_type) = split_method_descriptor(descriptor).ok_or_else(|| err!("Invalid method descriptor {}", descriptor))?;
+        match value {
+            Some(value) if return_type != "V" => self.push(value),
+            None if return_type == "V" => (),
+            value => fail!("Method {}.{}{} returned {:?}", class_name, name, descriptor, value),
+        }
+        Ok(())
+    }
+}
+
+/// Runs methods of the classes in a repository, keeping the state of their static fields and
+/// the objects they create between calls.
+pub struct Interpreter<'a> {
+    repo: &'a ClassRepository,
+    natives: &'a dyn NativeMethods,
+    heap: Heap,
+    classes: HashMap<String, ClassState>,
+    max_steps: u64,
+    max_depth: usize,
+    steps: u64,
+}
+
+impl<'a> Interpreter<'a> {
+    pub fn new(repo: &'a ClassRepository) -> Self {
+        Self {
+            repo,
+            natives: &BasicNatives,
+            heap: Heap::default(),
+            classes: HashMap::new(),
+            max_steps: 1_000_000,
+            max_depth: 256,
+            steps: 0,
+        }
+    }
+
+    /// Sets the hook for running methods without bytecode. The default is BasicNatives.
+    pub fn natives(&mut self, natives: &'a dyn NativeMethods) -> &mut Interpreter<'a> {
+        self.natives = natives;
+        self
+    }
+
+    /// Sets the number of instructions that may run, in total over all calls, before running
+    /// fails with an error. Allocating an array counts as one step per element. The default
+    /// is 1,000,000.
+    pub fn max_steps(&mut self, steps: u64) -> &mut Interpreter<'a> {
+        self.max_steps = steps;
+        self
+    }
+
+    /// Sets how deeply methods may call each other, counting class initializers as calls, before
+    /// running fails with an error. Calls do not recurse on the native stack, so this only limits
+    /// the memory used. The default is 256.
+    pub fn max_depth(&mut self, depth: usize) -> &mut Interpreter<'a> {
+        self.max_depth = depth;
+        self
+    }
+
+    pub fn heap(&self) -> &Heap {
+        &self.heap
+    }
+
+    pub fn heap_mut(&mut self) -> &mut Heap {
+        &mut self.heap
+    }
+
+    /// Returns the number of steps taken so far.
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    /// Initializes a class, along with its superclasses, if that has not been done already. A
+    /// class whose initialization threw an exception throws `NoClassDefFoundError` when it is
+    /// used again.
+    pub fn initialize_class(&mut self, class_name: &str) -> Result<Completion, ParseError> {
+        loop {
+            match self.initialize(class_name)? {
+                Init::Ready => return Ok(Completion::Return(None)),
+                Init::Failed(exception) => return Ok(Completion::Throw(exception)),
+                Init::Pending(frame) => {
+                    if let Completion::Throw(exception) = self.execute(frame)? {
+                        return Ok(Completion::Throw(exception));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Returns the static fields of a class, or None if the class has not been initialized.
+    pub fn static_fields(&self, class_name: &str) -> Option<&[FieldValue]> {
+        self.classes.get(class_name).map(|class| class.statics.as_slice())
+    }
+
+    pub fn static_field(&self, class_name: &str, name: &str) -> Option<Value> {
+        self.static_fields(class_name)?.iter().find(|f| f.name == name).map(|f| f.value)
+    }
+
+    /// Invokes a static method, as `invokestatic` does, initializing its class first.
+    pub fn invoke_static(
+        &mut self,
+        class_name: &str,
+        name: &str,
+        descriptor: &str,
+        args: &[Value],
+    ) -> Result<Completion, ParseError> {
+        let member = MemberRef {
+            class_name: class_name.to_string(),
+            name_and_type: crate::constant_pool::NameAndType {
+                name: name.to_string(),
+                descriptor: descriptor.to_string(),
+            },
+        };
+        loop {
+            match self.invoke(&member, Invoke::Static, args)? {
+                Call::Done(completion) => return Ok(completion),
+                Call::Push(frame) => {
+                    let initializer = matches!(frame.kind, FrameKind::Initializer { .. });
+                    let completion = self.execute(frame)?;
+                    if !initializer || matches!(completion, Completion::Throw(_)) {
+                        return Ok(completion);
+                    }
+                }
+            }
+        }
+    }
+
+    fn tick(&mut self, steps: u64) -> Result<(), ParseError> {
+        self.steps = self.steps.saturating_add(steps);
+        if self.steps > self.max_steps {
+            fail!("Exceeded the maximum of {} steps", self.max_steps);
+        }
+        Ok(())
+    }
+
+    // Starts initializing a class and its superclasses, if that has not been done already.
+    fn initialize(&mut self, class_name: &str) -> Result<Init, ParseError> {
+        // The classes to initialize, from the class itself up to the first superclass that has
+        // been or is being initialized. Superclasses that are missing, usually because the JDK is
+        // not in the repository, are treated as having nothing to initialize
+        let mut waiting: Vec<Rc<ClassFile>> = Vec::new();
+        let mut current = Some(class_name.to_string());
+        while let Some(name) = current {
+            match self.classes.get(&name).map(|class| class.state) {
+                Some(InitState::Failed) => {
+                    for class in &waiting {
+                        let state = self.class_state(class, InitState::Failed)?;
+                        self.classes.insert(class.this_class.clone(), state);
+                    }
+                    return Ok(Init::Failed(self.exception("java/lang/NoClassDefFoundError")));
+                }
+                Some(_) => break,
+                None => (),
+            }
+            if waiting.iter().any(|class| class.this_class == name) {
+                fail!("Cyclic class hierarchy involving {}", name);
+            }
+            if name != class_name && !self.repo.contains(&name)? {
+                break;
+            }
+            let class = self.repo.load(&name)?;
+            current = match &class.super_class {
+                Some(superclass) if !class.access_flags.contains(ClassAccessFlags::INTERFACE) => Some(superclass.clone()),
+                _ => None,
+            };
+            waiting.push(class);
+        }
+        while let Some(class) = waiting.pop() {
+            let state = self.class_state(&class, InitState::InProgress)?;
+            let class_name = class.this_class.clone();
+            self.classes.insert(class_name.clone(), state);
+            let index = match class.methods.iter().position(|m| m.name == "<clinit>") {
+                Some(index) if class_name != OBJECT => index,
+                _ => {
+                    self.set_state(&class_name, InitState::Done);
+                    continue;
+                }
+            };
+            let waiting = waiting.iter().map(|class| class.this_class.clone()).collect();
+            let kind = FrameKind::Initializer { class_name: class_name.clone(), waiting };
+            return match Frame::new(class, index, &[], kind) {
+                Ok(frame) => Ok(Init::Pending(frame)),
+                Err(e) => {
+                    self.set_state(&class_name, InitState::Failed);
+                    Err(e)
+                }
+            };
+        }
+        Ok(Init::Ready)
+    }
+
+    // The state of a class that is starting to be initialized, with its static fields set to
+    // their ConstantValue or default values.
+    fn class_state(&mut self, class: &ClassFile, state: InitState) -> Result<ClassState, ParseError> {
+        let mut statics = Vec::new();
+        for field in class.fields.iter().filter(|f| f.access_flags.contains(FieldAccessFlags::STATIC)) {
+            let mut value = Value::default_for(&field.descriptor);
+            for attr in field.attributes.iter().filter(|attr| attr.name == "ConstantValue") {
+                if let AttributeData::ConstantValue(literal) = attr.decoded()? {
+                    value = self.literal_value(literal);
+                }
+            }
+            statics.push(FieldValue {
+                class_name: class.this_class.clone(),
+                name: field.name.clone(),
+                descriptor: field.descriptor.clone(),
+                value,
+            });
+        }
+        Ok(ClassState { state, statics })
+    }
+
+    fn set_state(&mut self, class_name: &str, state: InitState) {
+        if let Some(class) = self.classes.get_mut(class_name) {
+            class.state = state;
+        }
+    }
+
+    // Records the outcome of a class initializer that completed, and returns the completion to
+    // pass on. Exceptions other than errors are wrapped in ExceptionInInitializerError.
+    fn initialized(&mut self, class_name: &str, waiting: &[String], completion: Completion) -> Result<Completion, ParseError> {
+        let exception = match completion {
+            Completion::Return(_) => {
+                self.set_state(class_name, InitState::Done);
+                return Ok(Completion::Return(None));
+            }
+            Completion::Throw(exception) if self.is_instance(exception, "java/lang/Error") => exception,
+            Completion::Throw(exception) => {
+                let error = self.exception("java/lang/ExceptionInInitializerError");
+                if let Some(HeapObject::Object { class_name, fields }) = self.heap.get_mut(error) {
+                    if !fields.iter().any(|f| f.name == "exception") {
+                        fields.push(FieldValue {
+                            class_name: class_name.clone(),
+                            name: "exception".to_string(),
+                            descriptor: "Ljava/lang/Throwable;".to_string(),
+                            value: exception,
+                        });
+                    }
+                }
+                error
+            }
+        };
+        self.set_state(class_name, InitState::Failed);
+        for name in waiting {
+            let class = self.repo.load(name)?;
+            let state = self.class_state(&class, InitState::Failed)?;
+            self.classes.insert(name.clone(), state);
+        }
+        Ok(Completion::Throw(exception))
+    }
+
+    fn literal_value(&mut self, literal: &LiteralConstant) -> Value {
+        match literal {
+            LiteralConstant::Integer(v) => Value::Int(*v),
+            LiteralConstant::Float(v) => Value::Float(*v),
+            LiteralConstant::Long(v) => Value::Long(*v),
+            LiteralConstant::Double(v) => Value::Double(*v),
+            LiteralConstant::String(s) => self.heap.intern(s),
+            LiteralConstant::StringBytes(b) => self.heap.intern(&String::from_utf8_lossy(b)),
+        }
+    }
+
+    // The instance fields of a class and its superclasses, with their default values.
+    fn instance_fields(&self, class_name: &str) -> Result<Vec<FieldValue>, ParseError> {
+        let mut chain = Vec::new();
+        let mut seen = HashSet::new();
+        let mut current = Some(class_name.to_string());
+        while let Some(name) = current {
+            if !seen.insert(name.clone()) {
+                fail!("Cyclic class hierarchy involving {}", name);
+            }
+            if name != class_name && !self.repo.contains(&name)? {
+                break;
+            }
+            let class = self.repo.load(&name)?;
+            current = class.super_class.clone();
+            chain.push(class);
+        }
+        let mut fields = Vec::new();
+        for class in chain.iter().rev() {
+            for field in class.fields.iter().filter(|f| !f.access_flags.contains(FieldAccessFlags::STATIC)) {
+                fields.push(FieldValue {
+                    class_name: class.this_class.clone(),
+                    name: field.name.clone(),
+                    descriptor: field.descriptor.clone(),
+                    value: Value::default_for(&field.descriptor),
+                });
+            }
+        }
+        Ok(fields)
+    }
+
+    // Creates an exception for the interpreter to throw. Exceptions that are not in the repository
+    // are created without fields.
+    fn exception(&mut self, class_name: &str) -> Value {
+        let fields = self.instance_fields(class_name).unwrap_or_default();
+        self.heap.alloc(HeapObject::Object {
+            class_name: class_name.to_string(),
+            fields,
+        })
+    }
+
+    fn throw(&mut self, class_name: &str) -> Result<Flow, ParseError> {
+        Ok(Flow::Throw(self.exception(class_name)))
+    }
+
+    fn superclass_of(&self, class_name: &str) -> Option<String> {
+        match self.repo.load(class_name) {
+            Ok(class) => class.super_class.clone(),
+            Err(_) => BUILTIN_SUPERCLASSES.iter().find(|(name, _)| *name == class_name).map(|(_, s)| s.to_string()),
+        }
+    }
+
+    fn is_instance(&self, value: Value, class_name: &str) -> bool {
+        match self.heap.get(value) {
+            Some(object) => self.is_assignable(&object.class_name(), class_name),
+            None => false,
+        }
+    }
+
+    fn is_assignable(&self, from: &str, to: &str) -> bool {
+        if from == to || to == OBJECT {
+            return true;
+        }
+        if let Ok(result) = self.repo.is_subtype_of(from, to) {
+            return result;
+        }
+        // Fall back to the superclasses we know of when some of the classes are missing
+        if let Some(from_component) = from.strip_prefix('[') {
+            return match to.strip_prefix('[') {
+                Some(to_component) => match (reference_type(from_component), reference_type(to_component)) {
+                    (Some(from), Some(to)) => self.is_assignable(from, to),
+                    _ => from_component == to_component,
+                },
+                None => to == "java/lang/Cloneable" || to == "java/io/Serializable",
+            };
+        }
+        if from == STRING && STRING_INTERFACES.contains(&to) {
+            return true;
+        }
+        let mut seen = HashSet::new();
+        let mut current = self.superclass_of(from);
+        while let Some(name) = current {
+            if name == to {
+                return true;
+            }
+            if !seen.insert(name.clone()) {
+                break;
+            }
+            current = self.superclass_of(&name);
+        }
+        false
+    }
+
+    fn invoke(&mut self, member: &MemberRef, kind: Invoke, args: &[Value]) -> Result<Call, ParseError> {
+        let name = &member.name_and_type.name;
+        let descriptor = &member.name_and_type.descriptor;
+        let target_class = match (kind, args.first()) {
+            (Invoke::Static, _) => member.class_name.clone(),
+            (_, Some(Value::Null)) => return Ok(Call::Done(Completion::Throw(self.exception(NULL_POINTER)))),
+            (Invoke::Special, Some(_)) => member.class_name.clone(),
+            (_, Some(receiver)) => match self.heap.get(*receiver) {
+                Some(object) => object.class_name(),
+                None => fail!("Invalid receiver {:?} calling {}.{}{}", receiver, member.class_name, name, descriptor),
+            },
+            (_, None) => fail!("Missing receiver calling {}.{}{}", member.class_name, name, descriptor),
+        };
+        if let Some(result) = self.natives.invoke(&mut self.heap, &target_class, name, descriptor, args) {
+            return result.map(Call::Done);
+        }
+        let resolved = match kind {
+            Invoke::Virtual => resolve_method(self.repo, member)?,
+            Invoke::Interface => resolve_interface_method(self.repo, member)?,
+            Invoke::Static | Invoke::Special if self.repo.is_interface(&member.class_name)? => {
+                resolve_interface_method(self.repo, member)?
+            }
+            Invoke::Static | Invoke::Special => resolve_method(self.repo, member)?,
+        };
+        if resolved.signature_polymorphic {
+            fail!("Calling signature polymorphic method {}.{} is not supported", member.class_name, name);
+        }
+        if resolved.is_static() != (kind == Invoke::Static) {
+            fail!(
+                "Method {}.{}{} is {}static",
+                resolved.class.this_class,
+                name,
+                descriptor,
+                if resolved.is_static() { "" } else { "not " }
+            );
+        }
+        let method = match kind {
+            Invoke::Static => match self.initialize(&resolved.class.this_class)? {
+                Init::Ready => resolved,
+                Init::Failed(exception) => return Ok(Call::Done(Completion::Throw(exception))),
+                Init::Pending(frame) => return Ok(Call::Push(frame)),
+            },
+            Invoke::Special => resolved,
+            Invoke::Virtual | Invoke::Interface => match select_method(self.repo, &target_class, &resolved)? {
+                Selection::Method(method) => method,
+                Selection::Conflict(_) => return Ok(Call::Done(Completion::Throw(self.exception("java/lang/IncompatibleClassChangeError")))),
+            },
+        };
+        let flags = method.method().access_flags;
+        if flags.contains(MethodAccessFlags::ABSTRACT) {
+            return Ok(Call::Done(Completion::Throw(self.exception("java/lang/AbstractMethodError"))));
+        }
+        if flags.contains(MethodAccessFlags::NATIVE) {
+            if method.class.this_class != target_class {
+                let declaring_class = method.class.this_class.clone();
+                if let Some(result) = self.natives.invoke(&mut self.heap, &declaring_class, name, descriptor, args) {
+                    return result.map(Call::Done);
+                }
+            }
+            fail!("Native method {}.{}{} is not implemented", method.class.this_class, name, descriptor);
+        }
+        Ok(Call::Push(Frame::new(method.class.clone(), method.index, args, FrameKind::Call)?))
+    }
+
+    // Runs a frame, and the frames it pushes in turn, until it completes. Calls push frames rather
+    // than recursing, so that deep Java call stacks do not use up the native stack.
+    fn execute(&mut self, frame: Frame) -> Result<Completion, ParseError> {
+        let mut frames = Vec::new();
+        self.push_frame(&mut frames, frame)?;
+        let result = self.run_frames(&mut frames);
+        if result.is_err() {
+            // Initializers that were cut short by an error have failed
+            for frame in frames {
+                if let FrameKind::Initializer { class_name, .. } = frame.kind {
+                    self.set_state(&class_name, InitState::Failed);
+                }
+            }
+        }
+        result
+    }
+
+    fn push_frame(&mut self, frames: &mut Vec<Frame>, frame: Frame) -> Result<(), ParseError> {
+        if frames.len() >= self.max_depth {
+            match frame.kind {
+                FrameKind::Call => fail!("Exceeded the maximum call depth of {} calling {}", self.max_depth, frame.method_name()),
+                FrameKind::Initializer { class_name, .. } => {
+                    self.set_state(&class_name, InitState::Failed);
+                    fail!("Exceeded the maximum call depth of {} initializing {}", self.max_depth, class_name)
+                }
+            }
+        }
+        frames.push(frame);
+        Ok(())
+    }
+
+    fn run_frames(&mut self, frames: &mut Vec<Frame>) -> Result<Completion, ParseError> {
+        loop {
+            let frame = match frames.last_mut() {
+                Some(frame) => frame,
+                None => fail!("No frame to run"),
+            };
+            let class = frame.class.clone();
+            let method = &class.methods[frame.method];
+            let (_, bytecode) = method_code(&class, frame.method)?;
+            let (offset, opcode) = match bytecode.opcodes.get(frame.pc) {
+                Some((offset, opcode)) => (*offset, opcode),
+                None => fail!("Ran off the end of the code of method {}.{}{}", class.this_class, method.name, method.descriptor),
+            };
+            let pc = frame.pc;
+            let flow = self.tick(1).and_then(|_| self.step(&class, bytecode, pc, opcode, frame)).map_err(|e| {
+                err!(e, "instruction at offset {} of method {}.{}{}", offset, class.this_class, method.name, method.descriptor)
+            })?;
+            let completion = match flow {
+                Flow::Next => {
+                    frame.pc += 1;
+                    continue;
+                }
+                Flow::Jump(jump) => {
+                    let target = usize::try_from(offset as i64 + jump as i64).unwrap_or(usize::MAX);
+                    frame.pc = self.jump_target(&class, frame.method, target)?;
+                    continue;
+                }
+                Flow::Goto(target) => {
+                    frame.pc = self.jump_target(&class, frame.method, target)?;
+                    continue;
+                }
+                Flow::Push(callee) => {
+                    self.push_frame(frames, callee).map_err(|e| {
+                        err!(e, "instruction at offset {} of method {}.{}{}", offset, class.this_class, method.name, method.descriptor)
+                    })?;
+                    continue;
+                }
+                Flow::Return(value) => Completion::Return(value),
+                Flow::Throw(exception) => {
+                    if self.catch(frame, exception)? {
+                        continue;
+                    }
+                    Completion::Throw(exception)
+                }
+            };
+            if let Some(completion) = self.unwind(frames, completion)? {
+                return Ok(completion);
+            }
+        }
+    }
+
+    // Pops the frame that completed and passes its completion to the frame below, unwinding
+    // further while an exception is not caught. Returns the completion of the last frame once
+    // the stack is empty.
+    fn unwind(&mut self, frames: &mut Vec<Frame>, mut completion: Completion) -> Result<Option<Completion>, ParseError> {
+        while let Some(done) = frames.pop() {
+            if let FrameKind::Initializer { class_name, waiting } = &done.kind {
+                completion = self.initialized(class_name, waiting, completion)?;
+            }
+            let caller = match frames.last_mut() {
+                Some(caller) => caller,
+                None => return Ok(Some(completion)),
+            };
+            match completion {
+                Completion::Return(value) => {
+                    // After an initializer, the instruction that needed the class runs again
+                    if let FrameKind::Call = done.kind {
+                        let method = &done.class.methods[done.method];
+                        caller.push_result(&done.class.this_class, &method.name, &method.descriptor, value)?;
+                        caller.pc += 1;
+                    }
+                    return Ok(None);
+                }
+                Completion::Throw(exception) => {
+                    if self.catch(caller, exception)? {
+                        return Ok(None);
+                    }
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    // Returns the index of the instruction at a jump target.
+    fn jump_target(&self, class: &ClassFile, method: usize, target: usize) -> Result<usize, ParseError> {
+        let (_, bytecode) = method_code(class, method)?;
+        match bytecode.get_opcode_index(target) {
+            Some(pc) => Ok(pc),
+            None => {
+                let method = &class.methods[method];
+                fail!("Invalid jump to offset {} in method {}.{}{}", target, class.this_class, method.name, method.descriptor)
+            }
+        }
+    }
+
+    // Moves a frame to the handler for an exception thrown by its current instruction, if it has one.
+    fn catch(&self, frame: &mut Frame, exception: Value) -> Result<bool, ParseError> {
+        let (code, bytecode) = method_code(&frame.class, frame.method)?;
+        let offset = match bytecode.opcodes.get(frame.pc) {
+            Some((offset, _)) => *offset,
+            None => return Ok(false),
+        };
+        let handler = match self.find_handler(code, offset, exception) {
+            Some(handler) => handler,
+            None => return Ok(false),
+        };
+        frame.pc = self.jump_target(&frame.class, frame.method, handler)?;
+        frame.stack.clear();
+        frame.push(exception);
+        Ok(true)
+    }
+
+    fn find_handler(&self, code: &CodeData, offset: usize, exception: Value) -> Option<usize> {
+        code.exception_table
+            .iter()
+            .find(|entry| {
+                (entry.start_pc as usize..entry.end_pc as usize).contains(&offset)
+                    && match &entry.catch_type {
+                        Some(catch_type) => self.is_instance(exception, catch_type),
+                        None => true,
+                    }
+            })
+            .map(|entry| entry.handler_pc as usize)
+    }
+
+    fn step(
+        &mut self,
+        class: &Rc<ClassFile>,
+        bytecode: &ByteCode,
+        pc: usize,
+        opcode: &Opcode,
+        frame: &mut Frame,
+    ) -> Result<Flow, ParseError> {
+        macro_rules! branch {
+            ($condition:expr, $jump:expr) => {
+                if $condition {
+                    return Ok(Flow::Jump(*$jump));
+                }
+            };
+        }
+        macro_rules! null_check {
+            ($value:expr) => {
+                if $value == Value::Null {
+                    return self.throw(NULL_POINTER);
+                }
+            };
+        }
+
+        if let Some((params, _)) = operation(opcode) {
+            let operands = frame.pop_arguments(params.len())?;
+            match compute(opcode, &operands)? {
+                Some(value) => frame.push(value),
+                None => return self.throw(ARITHMETIC),
+            }
+            return Ok(Flow::Next);
+        }
+        match opcode {
+            Opcode::Nop => (),
+            Opcode::AconstNull => frame.push(Value::Null),
+            Opcode::IconstM1 => frame.push(Value::Int(-1)),
+            Opcode::Iconst0 => frame.push(Value::Int(0)),
+            Opcode::Iconst1 => frame.push(Value::Int(1)),
+            Opcode::Iconst2 => frame.push(Value::Int(2)),
+            Opcode::Iconst3 => frame.push(Value::Int(3)),
+            Opcode::Iconst4 => frame.push(Value::Int(4)),
+            Opcode::Iconst5 => frame.push(Value::Int(5)),
+            Opcode::Lconst0 => frame.push(Value::Long(0)),
+            Opcode::Lconst1 => frame.push(Value::Long(1)),
+            Opcode::Fconst0 => frame.push(Value::Float(0.0)),
+            Opcode::Fconst1 => frame.push(Value::Float(1.0)),
+            Opcode::Fconst2 => frame.push(Value::Float(2.0)),
+            Opcode::Dconst0 => frame.push(Value::Double(0.0)),
+            Opcode::Dconst1 => frame.push(Value::Double(1.0)),
+            Opcode::Bipush(v) => frame.push(Value::Int(*v as i32)),
+            Opcode::Sipush(v) => frame.push(Value::Int(*v as i32)),
+            Opcode::Ldc(loadable) | Opcode::LdcW(loadable) | Opcode::Ldc2W(loadable) => {
+                let value = match loadable {
+                    Loadable::LiteralConstant(literal) => self.literal_value(literal),
+                    Loadable::ClassInfo(name) => fail!("Loading the class constant {} is not supported", name),
+                    _ => fail!("Loading method handle, method type and dynamic constants is not supported"),
+                };
+                frame.push(value);
+            }
+            Opcode::Iload(n) | Opcode::Lload(n) | Opcode::Fload(n) | Opcode::Dload(n) | Opcode::Aload(n) => {
+                let value = frame.load(*n)?;
+                frame.push(value);
+            }
+            Opcode::Istore(n) => {
+                let value = frame.pop_int()?;
+                frame.store(*n as usize, Value::Int(value))?;
+            }
+            Opcode::Lstore(n) => {
+                let value = frame.pop_long()?;
+                frame.store(*n as usize, Value::Long(value))?;
+            }
+            Opcode::Fstore(n) => {
+                let value = frame.pop_float()?;
+                frame.store(*n as usize, Value::Float(value))?;
+            }
+            Opcode::Dstore(n) => {
+                let value = frame.pop_double()?;
+                frame.store(*n as usize, Value::Double(value))?;
+            }
+            Opcode::Astore(n) => {
+                // astore also stores the return addresses pushed by jsr
+                let value = frame.pop()?;
+                frame.store(*n as usize, value)?;
+            }
+            Opcode::Iinc(n, delta) => match frame.load(*n)? {
+                Value::Int(v) => frame.store(*n as usize, Value::Int(v.wrapping_add(*delta as i32)))?,
+                value => fail!("Expected an int in local variable {} but found {:?}", n, value),
+            },
+            Opcode::Iaload | Opcode::Laload | Opcode::Faload | Opcode::Daload | Opcode::Aaload | Opcode::Baload | Opcode::Caload | Opcode::Saload => {
+                let index = frame.pop_int()?;
+                let array = frame.pop_reference()?;
+                null_check!(array);
+                let element = match self.heap.get(array) {
+                    Some(HeapObject::Array { elements, .. }) => usize::try_from(index).ok().and_then(|i| elements.get(i)).copied(),
+                    Some(object) => fail!("Expected an array but found an instance of {}", object.class_name()),
+                    None => fail!("Invalid reference {:?}", array),
+                };
+                match element {
+                    Some(element) => frame.push(element),
+                    None => return self.throw(ARRAY_INDEX),
+                }
+            }
+            Opcode::Iastore | Opcode::Bastore | Opcode::Castore | Opcode::Sastore => {
+                let value = Value::Int(frame.pop_int()?);
+                return self.array_store(frame, value);
+            }
+            Opcode::Lastore => {
+                let value = Value::Long(frame.pop_long()?);
+                return self.array_store(frame, value);
+            }
+            Opcode::Fastore => {
+                let value = Value::Float(frame.pop_float()?);
+                return self.array_store(frame, value);
+            }
+            Opcode::Dastore => {
+                let value = Value::Double(frame.pop_double()?);
+                return self.array_store(frame, value);
+            }
+            Opcode::Aastore => {
+                let value = frame.pop_reference()?;
+                return self.array_store(frame, value);
+            }
+            Opcode::Pop => {
+                frame.pop()?;
+            }
+            Opcode::Pop2 => {
+                if !frame.pop()?.is_wide() {
+                    frame.pop()?;
+                }
+            }
+            Opcode::Dup => {
+                let v1 = frame.pop()?;
+                frame.stack.extend_from_slice(&[v1, v1]);
+            }
+            Opcode::DupX1 => {
+                let v1 = frame.pop()?;
+                let v2 = frame.pop()?;
+                frame.stack.extend_from_slice(&[v1, v2, v1]);
+            }
+            Opcode::DupX2 => {
+                let v1 = frame.pop()?;
+                let v2 = frame.pop()?;
+                if v2.is_wide() {
+                    frame.stack.extend_from_slice(&[v1, v2, v1]);
+                } else {
+                    let v3 = frame.pop()?;
+                    frame.stack.extend_from_slice(&[v1, v3, v2, v1]);
+                }
+            }
+            Opcode::Dup2 => {
+                let v1 = frame.pop()?;
+                if v1.is_wide() {
+                    frame.stack.extend_from_slice(&[v1, v1]);
+                } else {
+                    let v2 = frame.pop()?;
+                    frame.stack.extend_from_slice(&[v2, v1, v2, v1]);
+                }
+            }
+            Opcode::Dup2X1 => {
+                let v1 = frame.pop()?;
+                let v2 = frame.pop()?;
+                if v1.is_wide() {
+                    frame.stack.extend_from_slice(&[v1, v2, v1]);
+                } else {
+                    let v3 = frame.pop()?;
+                    frame.stack.extend_from_slice(&[v2, v1, v3, v2, v1]);
+                }
+            }
+            Opcode::Dup2X2 => {
+                let v1 = frame.pop()?;
+                let v2 = frame.pop()?;
+                match (v1.is_wide(), v2.is_wide()) {
+                    (true, true) => frame.stack.extend_from_slice(&[v1, v2, v1]),
+                    (true, false) => {
+                        let v3 = frame.pop()?;
+                        frame.stack.extend_from_slice(&[v1, v3, v2, v1]);
+                    }
+                    (false, _) => {
+                        let v3 = frame.pop()?;
+                        if v3.is_wide() {
+                            frame.stack.extend_from_slice(&[v2, v1, v3, v2, v1]);
+                        } else {
+                            let v4 = frame.pop()?;
+                            frame.stack.extend_from_slice(&[v2, v1, v4, v3, v2, v1]);
+                        }
+                    }
+                }
+            }
+            Opcode::Swap => {
+                let v1 = frame.pop()?;
+                let v2 = frame.pop()?;
+                frame.stack.extend_from_slice(&[v1, v2]);
+            }
+            Opcode::Ifeq(jump) => branch!(frame.pop_int()? == 0, jump),
+            Opcode::Ifne(jump) => branch!(frame.pop_int()? != 0, jump),
+            Opcode::Iflt(jump) => branch!(frame.pop_int()? < 0, jump),
+            Opcode::Ifge(jump) => branch!(frame.pop_int()? >= 0, jump),
+            Opcode::Ifgt(jump) => branch!(frame.pop_int()? > 0, jump),
+            Opcode::Ifle(jump) => branch!(frame.pop_int()? <= 0, jump),
+            Opcode::IfIcmpeq(jump) | Opcode::IfIcmpne(jump) | Opcode::IfIcmplt(jump) | Opcode::IfIcmpge(jump) | Opcode::IfIcmpgt(jump) | Opcode::IfIcmple(jump) => {
+                let b = frame.pop_int()?;
+                let a = frame.pop_int()?;
+                let taken = match opcode {
+                    Opcode::IfIcmpeq(_) => a == b,
+                    Opcode::IfIcmpne(_) => a != b,
+                    Opcode::IfIcmplt(_) => a < b,
+                    Opcode::IfIcmpge(_) => a >= b,
+                    Opcode::IfIcmpgt(_) => a > b,
+                    _ => a <= b,
+                };
+                branch!(taken, jump);
+            }
+            Opcode::IfAcmpeq(jump) | Opcode::IfAcmpne(jump) => {
+                let b = frame.pop_reference()?;
+                let a = frame.pop_reference()?;
+                branch!((a == b) == matches!(opcode, Opcode::IfAcmpeq(_)), jump);
+            }
+            Opcode::Ifnull(jump) => branch!(frame.pop_reference()? == Value::Null, jump),
+            Opcode::Ifnonnull(jump) => branch!(frame.pop_reference()? != Value::Null, jump),
+            Opcode::Goto(jump) => return Ok(Flow::Jump(*jump)),
+            Opcode::Jsr(jump) => {
+                let next = match bytecode.opcodes.get(pc + 1) {
+                    Some((offset, _)) => *offset,
+                    None => fail!("jsr at the end of the code"),
+                };
+                frame.push(Value::ReturnAddress(next));
+                return Ok(Flow::Jump(*jump));
+            }
+            Opcode::Ret(n) => match frame.load(*n)? {
+                Value::ReturnAddress(target) => return Ok(Flow::Goto(target)),
+                value => fail!("Expected a return address in local variable {} but found {:?}", n, value),
+            },
+            Opcode::Tableswitch(table) => {
+                let index = frame.pop_int()? as i64 - table.low as i64;
+                let jump = usize::try_from(index).ok().and_then(|i| table.jumps.get(i)).unwrap_or(&table.default);
+                return Ok(Flow::Jump(*jump));
+            }
+            Opcode::Lookupswitch(table) => {
+                let key = frame.pop_int()?;
+                let jump = table.match_offsets.iter().find(|(k, _)| *k == key).map_or(table.default, |(_, jump)| *jump);
+                return Ok(Flow::Jump(jump));
+            }
+            Opcode::Ireturn => return Ok(Flow::Return(Some(Value::Int(frame.pop_int()?)))),
+            Opcode::Lreturn => return Ok(Flow::Return(Some(Value::Long(frame.pop_long()?)))),
+            Opcode::Freturn => return Ok(Flow::Return(Some(Value::Float(frame.pop_float()?)))),
+            Opcode::Dreturn => return Ok(Flow::Return(Some(Value::Double(frame.pop_double()?)))),
+            Opcode::Areturn => return Ok(Flow::Return(Some(frame.pop_reference()?))),
+            Opcode::Return => return Ok(Flow::Return(None)),
+            Opcode::Getstatic(member) => {
+                let (class_name, index) = match self.static_slot(member)? {
+                    Ok(slot) => slot,
+                    Err(flow) => return Ok(flow),
+                };
+                frame.push(self.classes[&class_name].statics[index].value);
+            }
+            Opcode::Putstatic(member) => {
+                let (class_name, index) = match self.static_slot(member)? {
+                    Ok(slot) => slot,
+                    Err(flow) => return Ok(flow),
+                };
+                let value = frame.pop()?;
+                if let Some(class) = self.classes.get_mut(&class_name) {
+                    let field = &mut class.statics[index];
+                    field.value = narrow(&field.descriptor, value);
+                }
+            }
+            Opcode::Getfield(member) => {
+                let object = frame.pop_reference()?;
+                null_check!(object);
+                let declaring_class = self.instance_field_class(member)?;
+                let value = match self.heap.get(object) {
+                    Some(HeapObject::Object { fields, .. }) => {
+                        fields.iter().find(|f| f.class_name == declaring_class && f.name == member.name_and_type.name).map(|f| f.value)
+                    }
+                    _ => None,
+                };
+                match value {
+                    Some(value) => frame.push(value),
+                    None => fail!("{:?} has no field {}.{}", object, declaring_class, member.name_and_type.name),
+                }
+            }
+            Opcode::Putfield(member) => {
+                let value = frame.pop()?;
+                let object = frame.pop_reference()?;
+                null_check!(object);
+                let declaring_class = self.instance_field_class(member)?;
+                let field = match self.heap.get_mut(object) {
+                    Some(HeapObject::Object { fields, .. }) => {
+                        fields.iter_mut().find(|f| f.class_name == declaring_class && f.name == member.name_and_type.name)
+                    }
+                    _ => None,
+                };
+                match field {
+                    Some(field) => field.value = narrow(&field.descriptor, value),
+                    None => fail!("{:?} has no field {}.{}", object, declaring_class, member.name_and_type.name),
+                }
+            }
+            Opcode::Invokestatic(member) => return self.invoke_instruction(frame, member, Invoke::Static),
+            Opcode::Invokespecial(member) => return self.invoke_instruction(frame, member, Invoke::Special),
+            Opcode::Invokevirtual(member) => return self.invoke_instruction(frame, member, Invoke::Virtual),
+            Opcode::Invokeinterface(member, _) => return self.invoke_instruction(frame, member, Invoke::Interface),
+            Opcode::Invokedynamic(dynamic) => {
+                let descriptor = &dynamic.name_and_type.descriptor;
+                let (params, _) = split_method_descriptor(descriptor).ok_or_else(|| err!("Invalid method descriptor {}", descriptor))?;
+                let args = frame.pop_arguments(params.len())?;
+                let value = self.concat(class, dynamic, &params, &args)?;
+                frame.push(value);
+            }
+            Opcode::New(class_name) => {
+                if let Some(flow) = self.initialize(class_name)?.flow() {
+                    return Ok(flow);
+                }
+                let flags = self.repo.load(class_name)?.access_flags;
+                if flags.intersects(ClassAccessFlags::INTERFACE | ClassAccessFlags::ABSTRACT) {
+                    return self.throw("java/lang/InstantiationError");
+                }
+                let fields = self.instance_fields(class_name)?;
+                let object = self.heap.alloc(HeapObject::Object {
+                    class_name: class_name.clone(),
+                    fields,
+                });
+                frame.push(object);
+            }
+            Opcode::Newarray(array_type) => {
+                let count = frame.pop_int()?;
+                let component = match array_type {
+                    PrimitiveArrayType::Boolean => "Z",
+                    PrimitiveArrayType::Char => "C",
+                    PrimitiveArrayType::Float => "F",
+                    PrimitiveArrayType::Double => "D",
+                    PrimitiveArrayType::Byte => "B",
+                    PrimitiveArrayType::Short => "S",
+                    PrimitiveArrayType::Int => "I",
+                    PrimitiveArrayType::Long => "J",
+                };
+                return self.new_array(frame, &format!("[{}", component), &[count]);
+            }
+            Opcode::Anewarray(class_name) if class_name.starts_with('[') => {
+                let count = frame.pop_int()?;
+                return self.new_array(frame, &format!("[{}", class_name), &[count]);
+            }
+            Opcode::Anewarray(class_name) => {
+                let count = frame.pop_int()?;
+                return self.new_array(frame, &format!("[L{};", class_name), &[count]);
+            }
+            Opcode::Multianewarray(descriptor, dimensions) => {
+                let mut counts = Vec::new();
+                for value in frame.pop_arguments(*dimensions as usize)? {
+                    match value {
+                        Value::Int(count) => counts.push(count),
+                        value => fail!("Expected an int on the operand stack but found {:?}", value),
+                    }
+                }
+                return self.new_array(frame, descriptor, &counts);
+            }
+            Opcode::Arraylength => {
+                let array = frame.pop_reference()?;
+                null_check!(array);
+                match self.heap.get(array) {
+                    Some(HeapObject::Array { elements, .. }) => frame.push(Value::Int(elements.len() as i32)),
+                    _ => fail!("Expected an array but found {:?}", array),
+                }
+            }
+            Opcode::Athrow => {
+                let exception = frame.pop_reference()?;
+                null_check!(exception);
+                return Ok(Flow::Throw(exception));
+            }
+            Opcode::Checkcast(class_name) => {
+                let value = frame.pop_reference()?;
+                if value != Value::Null && !self.is_instance(value, class_name) {
+                    return self.throw("java/lang/ClassCastException");
+                }
+                frame.push(value);
+            }
+            Opcode::Instanceof(class_name) => {
+                let value = frame.pop_reference()?;
+                frame.push(Value::Int(self.is_instance(value, class_name) as i32));
+            }
+            Opcode::Monitorenter | Opcode::Monitorexit => {
+                let object = frame.pop_reference()?;
+                null_check!(object);
+            }
+            _ => fail!("Unsupported instruction {:?}", opcode),
+        }
+        Ok(Flow::Next)
+    }
+
+    fn array_store(&mut self, frame: &mut Frame, value: Value) -> Result<Flow, ParseError> {
+        let index = frame.pop_int()?;
+        let array = frame.pop_reference()?;
+        if array == Value::Null {
+            return self.throw(NULL_POINTER);
+        }
+        let stored = match self.heap.get_mut(array) {
+            Some(HeapObject::Array { component, elements }) => match usize::try_from(index).ok().and_then(|i| elements.get_mut(i)) {
+                Some(element) => {
+                    *element = narrow(component, value);
+                    true
+                }
+                None => false,
+            },
+            _ => fail!("Expected an array but found {:?}", array),
+        };
+        if !stored {
+            return self.throw(ARRAY_INDEX);
+        }
+        Ok(Flow::Next)
+    }
+
+    fn new_array(&mut self, frame: &mut Frame, descriptor: &str, counts: &[i32]) -> Result<Flow, ParseError> {
+        if counts.iter().any(|count| *count < 0) {
+            return self.throw(NEGATIVE_ARRAY_SIZE);
+        }
+        let lengths = counts.iter().map(|count| *count as usize).collect::<Vec<_>>();
+        self.tick(array_size(&lengths))?;
+        let heap = &mut self.heap;
+        let array = alloc_array(descriptor, &lengths, &mut |component, elements| {
+            heap.alloc(HeapObject::Array {
+                component: component.to_string(),
+                elements,
+            })
+        })?;
+        frame.push(array);
+        Ok(Flow::Next)
+    }
+
+    // Returns the declaring class and index of a static field, or the flow for initializing the
+    // class first.
+    fn static_slot(&mut self, member: &MemberRef) -> Result<Result<(String, usize), Flow>, ParseError> {
+        let resolved = resolve_field(self.repo, member)?;
+        if !resolved.is_static() {
+            fail!("Field {}.{} is not static", resolved.class.this_class, member.name_and_type.name);
+        }
+        let class_name = resolved.class.this_class.clone();
+        if let Some(flow) = self.initialize(&class_name)?.flow() {
+            return Ok(Err(flow));
+        }
+        let field = resolved.field();
+        let index = self.classes[&class_name]
+            .statics
+            .iter()
+            .position(|f| f.name == field.name && f.descriptor == field.descriptor)
+            .ok_or_else(|| err!("Static field {}.{} not found", class_name, field.name))?;
+        Ok(Ok((class_name, index)))
+    }
+
+    fn instance_field_class(&self, member: &MemberRef) -> Result<String, ParseError> {
+        let resolved = resolve_field(self.repo, member)?;
+        if resolved.is_static() {
+            fail!("Field {}.{} is static", resolved.class.this_class, member.name_and_type.name);
+        }
+        Ok(resolved.class.this_class.clone())
+    }
+
+    fn invoke_instruction(&mut self, frame: &mut Frame, member: &MemberRef, kind: Invoke) -> Result<Flow, ParseError> {
+        let descriptor = &member.name_and_type.descriptor;
+        let (params, _) = split_method_descriptor(descriptor).ok_or_else(|| err!("Invalid method descriptor {}", descriptor))?;
+        let receiver = if kind == Invoke::Static { 0 } else { 1 };
+        let args = frame.pop_arguments(params.len() + receiver)?;
+        match self.invoke(member, kind, &args)? {
+            Call::Done(Completion::Return(value)) => frame.push_result(&member.class_name, &member.name_and_type.name, descriptor, value)?,
+            Call::Done(Completion::Throw(exception)) => return Ok(Flow::Throw(exception)),
+            Call::Push(callee) => {
+                // The instruction runs again once the class is initialized, so it needs its arguments back
+                if let FrameKind::Initializer { .. } = callee.kind {
+                    frame.stack.extend(args);
+                }
+                return Ok(Flow::Push(callee));
+            }
+        }
+        Ok(Flow::Next)
+    }
+
+    fn concat(&mut self, class: &ClassFile, dynamic: &InvokeDynamic, params: &[&str], args: &[Value]) -> Result<Value, ParseError> {
+        let args = params.iter().zip(args).map(|(descriptor, value)| java_string(&self.heap, descriptor, *value)).collect::<Result<Vec<_>, _>>()?;
+        let result = string_concat(class, dynamic, &args)?;
+        Ok(self.heap.alloc(HeapObject::String(result)))
+    }
+}
+
+// Runs the string concatenation that javac compiles `+` on strings to since Java 9, which is the
+// only use of invokedynamic supported, given the arguments already converted to strings.
+pub(crate) fn string_concat(class: &ClassFile, dynamic: &InvokeDynamic, args: &[String]) -> Result<String, ParseError> {
+    let attr = class.attributes.iter().find(|attr| attr.name == "BootstrapMethods").map(AttributeInfo::decoded).transpose()?;
+    let entry = match attr {
+        Some(AttributeData::BootstrapMethods(entries)) => {
+            entries.get(dynamic.attr_index as usize).ok_or_else(|| err!("Bootstrap method {} not found", dynamic.attr_index))?
+        }
+        _ => fail!("No BootstrapMethods attribute found"),
+    };
+    let handle = &entry.method;
+    if handle.class_name != "java/lang/invoke/StringConcatFactory" {
+        fail!("Unsupported bootstrap method {}.{}", handle.class_name, handle.member_ref.name);
+    }
+    let (recipe, constants) = match (handle.member_ref.name.as_str(), entry.arguments.split_first()) {
+        ("makeConcatWithConstants", Some((BootstrapArgument::LiteralConstant(LiteralConstant::String(recipe)), constants))) => {
+            (recipe.clone(), constants)
+        }
+        ("makeConcat", _) => ("\u{1}".repeat(args.len()), &[][..]),
+        (name, _) => fail!("Unsupported bootstrap method {}.{}", handle.class_name, name),
+    };
+    let mut args = args.iter();
+    let mut constants = constants.iter();
+    let mut result = String::new();
+    for c in recipe.chars() {
+        match c {
+            '\u{1}' => match args.next() {
+                Some(arg) => result.push_str(arg),
+                None => fail!("Too few arguments for string concatenation recipe {:?}", recipe),
+            },
+            '\u{2}' => match constants.next() {
+                Some(BootstrapArgument::LiteralConstant(literal)) => result.push_str(&literal_string(literal)),
+                _ => fail!("Missing constant for string concatenation recipe {:?}", recipe),
+            },
+            c => result.push(c),
+        }
+    }
+    Ok(result)
+}
+
+fn reference_type(descriptor: &str) -> Option<&str> {
+    if descriptor.starts_with('[') {
+        Some(descriptor)
+    } else {
+        descriptor.strip_prefix('L')?.strip_suffix(';')
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembly::assemble;
+    use crate::classpath::ClassPath;
+
+    fn classes(texts: &[&str]) -> ClassRepository {
+        let mut map = HashMap::new();
+        map.insert(OBJECT.to_string(), assemble(".version 52 0\n.class public java/lang/Object\n.end class\n").unwrap());
+        for text in texts {
+            let name = text.lines().find_map(|line| line.trim().strip_prefix(".class")).unwrap().split_whitespace().last().unwrap();
+            let text = format!(".version 52 0\n{}\n.end class\n", text);
+            map.insert(name.to_string(), assemble(&text).unwrap());
+        }
+        let mut classpath = ClassPath::new();
+        classpath.push(map);
+        ClassRepository::new(classpath)
+    }
+
+    fn returned(completion: Completion) -> Value {
+        match completion {
+            Completion::Return(Some(value)) => value,
+            completion => panic!("Unexpected completion {:?}", completion),
+        }
+    }
+
+    fn thrown_class(interpreter: &Interpreter, completion: Completion) -> String {
+        match completion {
+            Completion::Throw(exception) => interpreter.heap().get(exception).unwrap().class_name(),
+            completion => panic!("Unexpected completion {:?}", completion),
+        }
+    }
+
+    const MATH: &str = r#"
+.class public MathUtil
+.super java/lang/Object
+.method public static sum (I)I
+    .code stack 2 locals 2
+        iconst_0
+        istore_1
+Loop:   iload_0
+        ifle Done
+        iload_1
+        iload_0
+        iadd
+        istore_1
+        iinc 0 -1
+        goto Loop
+Done:   iload_1
+        ireturn
+    .end code
+.end method
+.method public static divide (II)I
+    .code stack 2 locals 2
+Start:  iload_0
+        iload_1
+        idiv
+End:    ireturn
+Handler: pop
+        iconst_m1
+        ireturn
+        .catch java/lang/RuntimeException Start End Handler
+    .end code
+.end method
+.method public static remainder (JJ)J
+    .code stack 4 locals 4
+        lload_0
+        lload_2
+        lrem
+        lreturn
+    .end code
+.end method
+.method public static squares (I)[I
+    .code stack 4 locals 2
+        iload_0
+        newarray int
+        astore_1
+Loop:   iload_0
+        ifle Done
+        iinc 0 -1
+        aload_1
+        iload_0
+        iload_0
+        iload_0
+        imul
+        iastore
+        goto Loop
+Done:   aload_1
+        areturn
+    .end code
+.end method
+.method public static grid (II)[[I
+    .code stack 2 locals 2
+        iload_0
+        iload_1
+        multianewarray [[I 2
+        areturn
+    .end code
+.end method
+.method public static spin ()V
+    .code stack 0 locals 0
+Loop:   goto Loop
+    .end code
+.end method
+.method public static recurse ()V
+    .code stack 0 locals 0
+        invokestatic MathUtil recurse ()V
+        return
+    .end code
+.end method
+"#;
+
+    #[test]
+    fn test_arithmetic_and_arrays() {
+        let repo = classes(&[MATH]);
+        let mut interpreter = Interpreter::new(&repo);
+        let completion = interpreter.invoke_static("MathUtil", "sum", "(I)I", &[Value::Int(10)]).unwrap();
+        assert_eq!(returned(completion), Value::Int(55));
+        let completion = interpreter.invoke_static("MathUtil", "divide", "(II)I", &[Value::Int(i32::MIN), Value::Int(-1)]).unwrap();
+        assert_eq!(returned(completion), Value::Int(i32::MIN));
+        // The ArithmeticException is caught as a RuntimeException, though neither is in the repository
+        let completion = interpreter.invoke_static("MathUtil", "divide", "(II)I", &[Value::Int(1), Value::Int(0)]).unwrap();
+        assert_eq!(returned(completion), Value::Int(-1));
+        let completion = interpreter.invoke_static("MathUtil", "remainder", "(JJ)J", &[Value::Long(7), Value::Long(0)]).unwrap();
+        assert_eq!(thrown_class(&interpreter, completion), ARITHMETIC);
+        let completion = interpreter.invoke_static("MathUtil", "remainder", "(JJ)J", &[Value::Long(-7), Value::Long(3)]).unwrap();
+        assert_eq!(returned(completion), Value::Long(-1));
+
+        let array = returned(interpreter.invoke_static("MathUtil", "squares", "(I)[I", &[Value::Int(4)]).unwrap());
+        match interpreter.heap().get(array).unwrap() {
+            HeapObject::Array { component, elements } => {
+                assert_eq!(component, "I");
+                assert_eq!(elements, &[Value::Int(0), Value::Int(1), Value::Int(4), Value::Int(9)]);
+            }
+            object => panic!("Unexpected object {:?}", object),
+        }
+        let completion = interpreter.invoke_static("MathUtil", "squares", "(I)[I", &[Value::Int(-1)]).unwrap();
+        assert_eq!(thrown_class(&interpreter, completion), NEGATIVE_ARRAY_SIZE);
+
+        assert!(interpreter.invoke_static("MathUtil", "sum", "(J)J", &[Value::Long(1)]).is_err());
+        assert!(interpreter.invoke_static("MathUtil", "sum", "(I)I", &[Value::Long(1)]).is_err());
+        let error = Interpreter::new(&repo).max_steps(1000).invoke_static("MathUtil", "spin", "()V", &[]).unwrap_err();
+        assert!(error.to_string().contains("Exceeded the maximum of 1000 steps"), "{}", error);
+        let error = Interpreter::new(&repo).max_depth(20).invoke_static("MathUtil", "recurse", "()V", &[]).unwrap_err();
+        assert!(error.to_string().contains("Exceeded the maximum call depth of 20"), "{}", error);
+        let error = Interpreter::new(&repo).invoke_static("MathUtil", "recurse", "()V", &[]).unwrap_err();
+        assert!(error.to_string().contains("Exceeded the maximum call depth of 256"), "{}", error);
+    }
+
+    #[test]
+    fn test_multianewarray() {
+        let repo = classes(&[MATH]);
+        let mut interpreter = Interpreter::new(&repo);
+        let args = [Value::Int(2), Value::Int(3)];
+        let array = returned(interpreter.invoke_static("MathUtil", "grid", "(II)[[I", &args).unwrap());
+        match interpreter.heap().get(array).unwrap() {
+            HeapObject::Array { component, elements } => {
+                assert_eq!(component, "[I");
+                assert_eq!(elements.len(), 2);
+            }
+            object => panic!("Unexpected object {:?}", object),
+        }
+
+        // Classes built in memory are not checked as parsed ones are
+        let mut class = (*repo.load("MathUtil").unwrap()).clone();
+        let index = class.methods.iter().position(|m| m.name == "grid").unwrap();
+        for (descriptor, dimensions) in [("[[I", 0), ("[I", 2)] {
+            if let AttributeData::Code(CodeData { bytecode: Some(bytecode), .. }) = &mut class.methods[index].attributes[0].data {
+                bytecode.opcodes[2].1 = Opcode::Multianewarray(descriptor.to_string(), dimensions);
+            }
+            let frame = Frame::new(Rc::new(class.clone()), index, &args, FrameKind::Call).unwrap();
+            let error = interpreter.execute(frame).unwrap_err();
+            assert!(error.to_string().contains("Invalid dimensions"), "{}", error);
+        }
+    }
+
+    const SHAPES: &str = r#"
+.class public abstract Shape
+.super java/lang/Object
+.field protected static count I
+.end field
+.method public <init> ()V
+    .code stack 2 locals 1
+        aload_0
+        invokespecial java/lang/Object <init> ()V
+        getstatic Shape count I
+        iconst_1
+        iadd
+        putstatic Shape count I
+        return
+    .end code
+.end method
+.method public abstract area ()I
+.end method
+.method public static totalArea (I)I
+    .code stack 3 locals 1
+        new Square
+        dup
+        iload_0
+        invokespecial Square <init> (I)V
+        invokevirtual Shape area ()I
+        new Square
+        dup
+        iconst_1
+        invokespecial Square <init> (I)V
+        invokevirtual Shape area ()I
+        iadd
+        ireturn
+    .end code
+.end method
+.method public static nullArea ()I
+    .code stack 1 locals 0
+        aconst_null
+        checkcast Square
+        invokevirtual Shape area ()I
+        ireturn
+    .end code
+.end method
+"#;
+
+    const SQUARE: &str = r#"
+.class public Square
+.super Shape
+.field private side I
+.end field
+.method public <init> (I)V
+    .code stack 2 locals 2
+        aload_0
+        invokespecial Shape <init> ()V
+        aload_0
+        iload_1
+        putfield Square side I
+        return
+    .end code
+.end method
+.method public area ()I
+    .code stack 2 locals 1
+        aload_0
+        getfield Square side I
+        dup
+        imul
+        ireturn
+    .end code
+.end method
+"#;
+
+    #[test]
+    fn test_objects() {
+        let repo = classes(&[SHAPES, SQUARE]);
+        let mut interpreter = Interpreter::new(&repo);
+        let completion = interpreter.invoke_static("Shape", "totalArea", "(I)I", &[Value::Int(3)]).unwrap();
+        assert_eq!(returned(completion), Value::Int(10));
+        assert_eq!(interpreter.static_field("Shape", "count"), Some(Value::Int(2)));
+        assert_eq!(interpreter.static_field("Square", "count"), None);
+        let completion = interpreter.invoke_static("Shape", "nullArea", "()I", &[]).unwrap();
+        assert_eq!(thrown_class(&interpreter, completion), NULL_POINTER);
+        let squares = (0..interpreter.heap().len())
+            .filter_map(|i| interpreter.heap().get(Value::Reference(i)))
+            .filter(|object| object.class_name() == "Square")
+            .map(|object| object.field("side"))
+            .collect::<Vec<_>>();
+        assert_eq!(squares, vec![Some(Value::Int(3)), Some(Value::Int(1))]);
+    }
+
+    const CONFIG: &str = r#"
+.class public Config
+.super java/lang/Object
+.field public static final NAME Ljava/lang/String;
+.end field
+.field public static final LIMIT I
+.end field
+.field public static final FLAG Z
+.end field
+.method static <clinit> ()V
+    .code stack 2 locals 0
+        ldc string "config"
+        putstatic Config NAME Ljava/lang/String;
+        bipush 6
+        bipush 7
+        imul
+        putstatic Config LIMIT I
+        iconst_3
+        putstatic Config FLAG Z
+        return
+    .end code
+.end method
+"#;
+
+    const BROKEN: &str = r#"
+.class public Broken
+.super java/lang/Object
+.field public static VALUE I
+.end field
+.method static <clinit> ()V
+    .code stack 2 locals 0
+        iconst_1
+        iconst_0
+        idiv
+        putstatic Broken VALUE I
+        return
+    .end code
+.end method
+.method public static value ()I
+    .code stack 1 locals 0
+        getstatic Broken VALUE I
+        ireturn
+    .end code
+.end method
+"#;
+
+    #[test]
+    fn test_class_initialization() {
+        let repo = classes(&[CONFIG, BROKEN]);
+        let mut interpreter = Interpreter::new(&repo);
+        assert_eq!(interpreter.initialize_class("Config").unwrap(), Completion::Return(None));
+        let name = interpreter.static_field("Config", "NAME").unwrap();
+        assert_eq!(interpreter.heap().string(name), Some("config"));
+        assert_eq!(interpreter.heap_mut().intern("config"), name);
+        assert_eq!(interpreter.static_field("Config", "LIMIT"), Some(Value::Int(42)));
+        assert_eq!(interpreter.static_field("Config", "FLAG"), Some(Value::Int(1)));
+
+        let completion = interpreter.invoke_static("Broken", "value", "()I", &[]).unwrap();
+        let error = match completion {
+            Completion::Throw(error) => error,
+            completion => panic!("Unexpected completion {:?}", completion),
+        };
+        let error = interpreter.heap().get(error).unwrap();
+        assert_eq!(error.class_name(), "java/lang/ExceptionInInitializerError");
+        let cause = error.field("exception").unwrap();
+        assert_eq!(interpreter.heap().get(cause).unwrap().class_name(), ARITHMETIC);
+        let completion = interpreter.invoke_static("Broken", "value", "()I", &[]).unwrap();
+        assert_eq!(thrown_class(&interpreter, completion), "java/lang/NoClassDefFoundError");
+        assert!(interpreter.initialize_class("Missing").is_err());
+
+        // Each initializer reads a static field of the next class, running its initializer
+        let chain = (0..10)
+            .map(|i| {
+                let next = if i < 9 { format!("getstatic Chain{} value I\npop\n", i + 1) } else { String::new() };
+                format!(
+                    ".class public Chain{}\n.super java/lang/Object\n.field static value I\n.end field\n.method static <clinit> ()V\n.code stack 1 locals 0\n{}return\n.end code\n.end method\n",
+                    i, next
+                )
+            })
+            .collect::<Vec<_>>();
+        let repo = classes(&chain.iter().map(String::as_str).collect::<Vec<_>>());
+        let error = Interpreter::new(&repo).max_depth(5).initialize_class("Chain0").unwrap_err();
+        assert!(error.to_string().contains("Exceeded the maximum call depth of 5"), "{}", error);
+        assert_eq!(Interpreter::new(&repo).initialize_class("Chain0").unwrap(), Completion::Return(None));
+    }
+
+    const STRINGS: &str = r#"
+.class public Strings
+.super java/lang/Object
+.method public static describe (IDC)Ljava/lang/String;
+    .code stack 5 locals 4
+        iload_0
+        dload_1
+        iload_3
+        invokedynamic 0 makeConcatWithConstants "(IDC)Ljava/lang/String;"
+        areturn
+    .end code
+.end method
+.method public static nameLength ()I
+    .code stack 2 locals 0
+        ldc string "héllo"
+        invokevirtual java/lang/String length ()I
+        iconst_2
+        invokestatic Host twice (I)I
+        iadd
+        ireturn
+    .end code
+.end method
+.bootstrapmethods
+    .bootstrap REF_invokeStatic Method java/lang/invoke/StringConcatFactory makeConcatWithConstants "(Ljava/lang/invoke/MethodHandles$Lookup;Ljava/lang/String;Ljava/lang/invoke/MethodType;Ljava/lang/String;[Ljava/lang/Object;)Ljava/lang/invoke/CallSite;"
+        string "n=\u0001, d=\u0001, c=\u0001\u0002"
+        int 7
+    .end bootstrap
+.end bootstrapmethods
+"#;
+
+    struct Host;
+
+    impl NativeMethods for Host {
+        fn invoke(
+            &self,
+            heap: &mut Heap,
+            class_name: &str,
+            name: &str,
+            descriptor: &str,
+            args: &[Value],
+        ) -> Option<Result<Completion, ParseError>> {
+            match (class_name, name, args) {
+                ("Host", "twice", [Value::Int(v)]) => Some(Ok(Completion::Return(Some(Value::Int(v * 2))))),
+                _ => BasicNatives.invoke(heap, class_name, name, descriptor, args),
+            }
+        }
+    }
+
+    #[test]
+    fn test_strings_and_natives() {
+        let repo = classes(&[STRINGS]);
+        let mut interpreter = Interpreter::new(&repo);
+        let args = [Value::Int(-3), Value::Double(1e10), Value::Int('x' as i32)];
+        let result = returned(interpreter.invoke_static("Strings", "describe", "(IDC)Ljava/lang/String;", &args).unwrap());
+        assert_eq!(interpreter.heap().string(result), Some("n=-3, d=1.0E10, c=x7"));
+        assert!(interpreter.invoke_static("Strings", "nameLength", "()I", &[]).is_err());
+        interpreter.natives(&Host);
+        let completion = interpreter.invoke_static("Strings", "nameLength", "()I", &[]).unwrap();
+        assert_eq!(returned(completion), Value::Int(9));
+
+        assert_eq!(java_double(0.001), "0.001");
+        assert_eq!(java_double(1234567.0), "1234567.0");
+        assert_eq!(java_double(-1.5e-5), "-1.5E-5");
+        assert_eq!(java_float(0.1), "0.1");
+        assert_eq!(java_float(f32::NEG_INFINITY), "-Infinity");
+        assert_eq!(java_double(f64::NAN), "NaN");
+    }
+}
@@ -0,0 +1,1637 @@
+//! Decoding of the Kotlin metadata that the Kotlin compiler stores in the `kotlin.Metadata`
+//! annotation of every class it generates. The metadata describes the Kotlin-level declarations
+//! (nullability, visibility, properties, extension receivers and so on) that cannot be recovered
+//! from the JVM members alone.
+
+use std::convert::TryFrom;
+
+use crate::attributes::{Annotation, AnnotationElementValue, AttributeData};
+use crate::{ClassFile, ParseError};
+
+const METADATA_DESCRIPTOR: &str = "Lkotlin/Metadata;";
+
+// Kotlin types can refer to each other through type tables, so they are not guaranteed to be
+// finite. Give up on anything nested deeper than this.
+const MAX_TYPE_DEPTH: usize = 64;
+
+// Strings that the string table can refer to by index rather than storing in d2.
+const PREDEFINED_STRINGS: &[&str] = &[
+    "kotlin/Any",
+    "kotlin/Nothing",
+    "kotlin/Unit",
+    "kotlin/Throwable",
+    "kotlin/Number",
+    "kotlin/Byte",
+    "kotlin/Double",
+    "kotlin/Float",
+    "kotlin/Int",
+    "kotlin/Long",
+    "kotlin/Short",
+    "kotlin/Boolean",
+    "kotlin/Char",
+    "kotlin/CharSequence",
+    "kotlin/String",
+    "kotlin/Comparable",
+    "kotlin/Enum",
+    "kotlin/Array",
+    "kotlin/ByteArray",
+    "kotlin/DoubleArray",
+    "kotlin/FloatArray",
+    "kotlin/IntArray",
+    "kotlin/LongArray",
+    "kotlin/ShortArray",
+    "kotlin/BooleanArray",
+    "kotlin/CharArray",
+    "kotlin/Cloneable",
+    "kotlin/Annotation",
+    "kotlin/collections/Iterable",
+    "kotlin/collections/MutableIterable",
+    "kotlin/collections/Collection",
+    "kotlin/collections/MutableCollection",
+    "kotlin/collections/List",
+    "kotlin/collections/MutableList",
+    "kotlin/collections/Set",
+    "kotlin/collections/MutableSet",
+    "kotlin/collections/Map",
+    "kotlin/collections/MutableMap",
+    "kotlin/collections/Map.Entry",
+    "kotlin/collections/MutableMap.MutableEntry",
+    "kotlin/collections/Iterator",
+    "kotlin/collections/MutableIterator",
+    "kotlin/collections/ListIterator",
+    "kotlin/collections/MutableListIterator",
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum KotlinMetadataKind {
+    Class,
+    File,
+    SyntheticClass,
+    MultiFileClassFacade,
+    MultiFileClassPart,
+    Unknown(i32),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum KotlinVisibility {
+    Internal,
+    Private,
+    Protected,
+    Public,
+    PrivateToThis,
+    Local,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum KotlinModality {
+    Final,
+    Open,
+    Abstract,
+    Sealed,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum KotlinClassKind {
+    Class,
+    Interface,
+    EnumClass,
+    EnumEntry,
+    AnnotationClass,
+    Object,
+    CompanionObject,
+}
+
+/// How a member came to be part of a class: declared in source, inherited without being
+/// overridden, generated for interface delegation, or synthesized (e.g. data class methods).
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum KotlinMemberKind {
+    Declaration,
+    FakeOverride,
+    Delegation,
+    Synthesized,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum KotlinVariance {
+    In,
+    Out,
+    Invariant,
+}
+
+/// What a Kotlin type refers to. Class and type alias names use `/` to separate packages and `.`
+/// to separate nested classes, e.g. `kotlin/collections/Map.Entry`. Type parameters are usually
+/// referred to by an id that is resolved against the enclosing declarations; the name is `None`
+/// if the parameter is declared somewhere that is not part of this metadata, such as an outer
+/// class.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum KotlinClassifier {
+    Class(String),
+    TypeAlias(String),
+    TypeParameter { id: Option<i32>, name: Option<String> },
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum KotlinTypeProjection {
+    Star,
+    Type { variance: KotlinVariance, ty: KotlinType },
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct KotlinType {
+    pub flags: i32,
+    pub classifier: KotlinClassifier,
+    pub arguments: Vec<KotlinTypeProjection>,
+    pub nullable: bool,
+    /// For the type of an inner class, the type of the outer class with its type arguments.
+    pub outer_type: Option<Box<KotlinType>>,
+    /// If this type was written using a type alias, the type as written.
+    pub abbreviated_type: Option<Box<KotlinType>>,
+    /// For platform types coming from Java, where this type is the lower bound.
+    pub flexible_upper_bound: Option<Box<KotlinType>>,
+}
+
+impl KotlinType {
+    pub fn is_suspend(&self) -> bool {
+        self.flags & 0x1 != 0
+    }
+
+    pub fn is_definitely_non_null(&self) -> bool {
+        self.flags & 0x2 != 0
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct KotlinTypeParameter {
+    pub id: i32,
+    pub name: String,
+    pub reified: bool,
+    pub variance: KotlinVariance,
+    pub upper_bounds: Vec<KotlinType>,
+}
+
+/// The name and descriptor of the JVM member that a Kotlin declaration compiles to. The
+/// descriptor is omitted by the compiler when it can be derived from the Kotlin types.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct JvmMemberSignature {
+    pub name: String,
+    pub descriptor: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct KotlinValueParameter {
+    pub flags: i32,
+    pub name: String,
+    pub ty: KotlinType,
+    /// For a vararg parameter, the type of each element; `ty` is then the array type.
+    pub vararg_element_type: Option<KotlinType>,
+}
+
+impl KotlinValueParameter {
+    pub fn declares_default_value(&self) -> bool {
+        self.flags & (1 << 1) != 0
+    }
+
+    pub fn is_crossinline(&self) -> bool {
+        self.flags & (1 << 2) != 0
+    }
+
+    pub fn is_noinline(&self) -> bool {
+        self.flags & (1 << 3) != 0
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct KotlinConstructor {
+    pub flags: i32,
+    pub visibility: KotlinVisibility,
+    pub value_parameters: Vec<KotlinValueParameter>,
+    pub jvm_signature: Option<JvmMemberSignature>,
+}
+
+impl KotlinConstructor {
+    pub fn is_secondary(&self) -> bool {
+        self.flags & (1 << 4) != 0
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct KotlinFunction {
+    pub flags: i32,
+    pub name: String,
+    pub visibility: KotlinVisibility,
+    pub modality: KotlinModality,
+    pub kind: KotlinMemberKind,
+    pub type_parameters: Vec<KotlinTypeParameter>,
+    /// The receiver type of an extension function.
+    pub receiver_type: Option<KotlinType>,
+    pub value_parameters: Vec<KotlinValueParameter>,
+    pub return_type: KotlinType,
+    pub jvm_signature: Option<JvmMemberSignature>,
+}
+
+impl KotlinFunction {
+    pub fn is_operator(&self) -> bool {
+        self.flags & (1 << 8) != 0
+    }
+
+    pub fn is_infix(&self) -> bool {
+        self.flags & (1 << 9) != 0
+    }
+
+    pub fn is_inline(&self) -> bool {
+        self.flags & (1 << 10) != 0
+    }
+
+    pub fn is_tailrec(&self) -> bool {
+        self.flags & (1 << 11) != 0
+    }
+
+    pub fn is_external(&self) -> bool {
+        self.flags & (1 << 12) != 0
+    }
+
+    pub fn is_suspend(&self) -> bool {
+        self.flags & (1 << 13) != 0
+    }
+
+    pub fn is_expect(&self) -> bool {
+        self.flags & (1 << 14) != 0
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct KotlinProperty {
+    pub flags: i32,
+    pub name: String,
+    pub visibility: KotlinVisibility,
+    pub modality: KotlinModality,
+    pub kind: KotlinMemberKind,
+    pub type_parameters: Vec<KotlinTypeParameter>,
+    /// The receiver type of an extension property.
+    pub receiver_type: Option<KotlinType>,
+    pub return_type: KotlinType,
+    pub setter_parameter: Option<KotlinValueParameter>,
+    pub getter_visibility: KotlinVisibility,
+    pub setter_visibility: KotlinVisibility,
+    /// The backing field, if the property has one.
+    pub jvm_field: Option<JvmMemberSignature>,
+    pub jvm_getter: Option<JvmMemberSignature>,
+    pub jvm_setter: Option<JvmMemberSignature>,
+}
+
+impl KotlinProperty {
+    pub fn is_var(&self) -> bool {
+        self.flags & (1 << 8) != 0
+    }
+
+    pub fn has_getter(&self) -> bool {
+        self.flags & (1 << 9) != 0
+    }
+
+    pub fn has_setter(&self) -> bool {
+        self.flags & (1 << 10) != 0
+    }
+
+    pub fn is_const(&self) -> bool {
+        self.flags & (1 << 11) != 0
+    }
+
+    pub fn is_lateinit(&self) -> bool {
+        self.flags & (1 << 12) != 0
+    }
+
+    pub fn is_external(&self) -> bool {
+        self.flags & (1 << 14) != 0
+    }
+
+    pub fn is_delegated(&self) -> bool {
+        self.flags & (1 << 15) != 0
+    }
+
+    pub fn is_expect(&self) -> bool {
+        self.flags & (1 << 16) != 0
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct KotlinTypeAlias {
+    pub flags: i32,
+    pub name: String,
+    pub visibility: KotlinVisibility,
+    pub type_parameters: Vec<KotlinTypeParameter>,
+    pub underlying_type: KotlinType,
+    pub expanded_type: KotlinType,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct KotlinClass {
+    pub flags: i32,
+    pub name: String,
+    pub visibility: KotlinVisibility,
+    pub modality: KotlinModality,
+    pub kind: KotlinClassKind,
+    pub type_parameters: Vec<KotlinTypeParameter>,
+    pub supertypes: Vec<KotlinType>,
+    pub constructors: Vec<KotlinConstructor>,
+    pub functions: Vec<KotlinFunction>,
+    pub properties: Vec<KotlinProperty>,
+    pub type_aliases: Vec<KotlinTypeAlias>,
+    /// The simple name of the companion object, if there is one.
+    pub companion_object: Option<String>,
+    /// The simple names of the nested classes.
+    pub nested_classes: Vec<String>,
+    pub enum_entries: Vec<String>,
+    pub sealed_subclasses: Vec<String>,
+    pub module_name: Option<String>,
+}
+
+impl KotlinClass {
+    pub fn is_inner(&self) -> bool {
+        self.flags & (1 << 9) != 0
+    }
+
+    pub fn is_data(&self) -> bool {
+        self.flags & (1 << 10) != 0
+    }
+
+    pub fn is_external(&self) -> bool {
+        self.flags & (1 << 11) != 0
+    }
+
+    pub fn is_expect(&self) -> bool {
+        self.flags & (1 << 12) != 0
+    }
+
+    /// True for value (formerly inline) classes.
+    pub fn is_value(&self) -> bool {
+        self.flags & (1 << 13) != 0
+    }
+
+    pub fn is_fun_interface(&self) -> bool {
+        self.flags & (1 << 14) != 0
+    }
+}
+
+/// The top-level declarations of a Kotlin file, or of one part of a multi-file class.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct KotlinPackage {
+    pub functions: Vec<KotlinFunction>,
+    pub properties: Vec<KotlinProperty>,
+    pub type_aliases: Vec<KotlinTypeAlias>,
+    pub module_name: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum KotlinDeclarations {
+    Class(KotlinClass),
+    Package(KotlinPackage),
+    /// The function that a synthetic lambda class implements.
+    Lambda(KotlinFunction),
+    /// The internal names of the classes that make up a multi-file class facade.
+    FacadeParts(Vec<String>),
+    None,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct KotlinMetadata {
+    pub kind: KotlinMetadataKind,
+    pub metadata_version: Vec<i32>,
+    pub extra_int: i32,
+    pub extra_string: Option<String>,
+    pub package_name: Option<String>,
+    pub declarations: KotlinDeclarations,
+}
+
+impl KotlinMetadata {
+    /// Finds the `kotlin.Metadata` annotation among the class's runtime visible annotations and
+    /// decodes it. Returns `Ok(None)` for classes that were not compiled from Kotlin.
+    pub fn from_class(class: &ClassFile) -> Result<Option<KotlinMetadata>, ParseError> {
+        for attr in &class.attributes {
+            if let AttributeData::RuntimeVisibleAnnotations(annotations) = attr.decoded()? {
+                if let Some(metadata) = Self::from_annotations(annotations)? {
+                    return Ok(Some(metadata));
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    /// Finds the `kotlin.Metadata` annotation in the given list and decodes it. Returns `Ok(None)`
+    /// if the list has no such annotation.
+    pub fn from_annotations(annotations: &[Annotation]) -> Result<Option<KotlinMetadata>, ParseError> {
+        match annotations.iter().find(|a| a.type_descriptor == METADATA_DESCRIPTOR) {
+            Some(annotation) => Self::from_annotation(annotation).map(Some).map_err(|e| err!(e, "Kotlin metadata")),
+            None => Ok(None),
+        }
+    }
+
+    fn from_annotation(annotation: &Annotation) -> Result<KotlinMetadata, ParseError> {
+        let mut kind = 1;
+        let mut metadata_version = Vec::new();
+        let mut d1 = Vec::new();
+        let mut d2 = Vec::new();
+        let mut extra_int = 0;
+        let mut extra_string = None;
+        let mut package_name = None;
+        for element in &annotation.elements {
+            match (element.name.as_str(), &element.value) {
+                ("k", AnnotationElementValue::IntConstant(x)) => kind = *x,
+                ("mv", AnnotationElementValue::ArrayValue(values)) => {
+                    for value in values {
+                        match value {
+                            AnnotationElementValue::IntConstant(x) => metadata_version.push(*x),
+                            _ => fail!("Unexpected element type in mv"),
+                        }
+                    }
+                }
+                ("d1", AnnotationElementValue::ArrayValue(values)) => d1 = string_array(values).map_err(|e| err!(e, "d1"))?,
+                ("d2", AnnotationElementValue::ArrayValue(values)) => d2 = string_array(values).map_err(|e| err!(e, "d2"))?,
+                ("xi", AnnotationElementValue::IntConstant(x)) => extra_int = *x,
+                ("xs", AnnotationElementValue::StringConstant(x)) => extra_string = Some(x.clone()),
+                ("pn", AnnotationElementValue::StringConstant(x)) => package_name = Some(x.clone()),
+                ("k", _) | ("mv", _) | ("d1", _) | ("d2", _) | ("xi", _) | ("xs", _) | ("pn", _) => {
+                    fail!("Unexpected element type for {}", element.name)
+                }
+                _ => continue,
+            }
+        }
+
+        let kind = match kind {
+            1 => KotlinMetadataKind::Class,
+            2 => KotlinMetadataKind::File,
+            3 => KotlinMetadataKind::SyntheticClass,
+            4 => KotlinMetadataKind::MultiFileClassFacade,
+            5 => KotlinMetadataKind::MultiFileClassPart,
+            x => KotlinMetadataKind::Unknown(x),
+        };
+        let declarations = match kind {
+            KotlinMetadataKind::Class if !d1.is_empty() => {
+                let bytes = decode_bytes(&d1);
+                let (mut decoder, reader) = Decoder::new(&bytes, d2)?;
+                KotlinDeclarations::Class(decoder.class(reader).map_err(|e| err!(e, "class"))?)
+            }
+            KotlinMetadataKind::File | KotlinMetadataKind::MultiFileClassPart if !d1.is_empty() => {
+                let bytes = decode_bytes(&d1);
+                let (mut decoder, reader) = Decoder::new(&bytes, d2)?;
+                KotlinDeclarations::Package(decoder.package(reader).map_err(|e| err!(e, "package"))?)
+            }
+            KotlinMetadataKind::SyntheticClass if !d1.is_empty() => {
+                let bytes = decode_bytes(&d1);
+                let (mut decoder, reader) = Decoder::new(&bytes, d2)?;
+                let types = TypeTable::default();
+                KotlinDeclarations::Lambda(decoder.function(reader.bytes, &types).map_err(|e| err!(e, "lambda"))?)
+            }
+            KotlinMetadataKind::MultiFileClassFacade => KotlinDeclarations::FacadeParts(d1),
+            _ => KotlinDeclarations::None,
+        };
+        Ok(KotlinMetadata {
+            kind,
+            metadata_version,
+            extra_int,
+            extra_string,
+            package_name,
+            declarations,
+        })
+    }
+}
+
+fn string_array(values: &[AnnotationElementValue]) -> Result<Vec<String>, ParseError> {
+    values
+        .iter()
+        .map(|value| match value {
+            AnnotationElementValue::StringConstant(x) => Ok(x.clone()),
+            _ => fail!("Unexpected element type in string array"),
+        })
+        .collect()
+}
+
+// The protobuf messages in d1 are stored as strings. Newer compilers put a single \0 marker at
+// the start and use one char per byte; older compilers pack 7 bits per char.
+fn decode_bytes(d1: &[String]) -> Vec<u8> {
+    let mut chars = d1.iter().flat_map(|s| s.chars()).peekable();
+    if chars.peek() == Some(&'\u{0}') {
+        chars.next();
+        return chars.map(|c| c as u32 as u8).collect();
+    }
+    if chars.peek() == Some(&'\u{1}') {
+        chars.next();
+    }
+    let mut bytes = Vec::new();
+    let mut buffer = 0u32;
+    let mut bits = 0;
+    for c in chars {
+        buffer |= ((c as u32 + 0x7f) & 0x7f) << bits;
+        bits += 7;
+        if bits >= 8 {
+            bytes.push(buffer as u8);
+            buffer >>= 8;
+            bits -= 8;
+        }
+    }
+    bytes
+}
+
+const WIRE_VARINT: u8 = 0;
+const WIRE_FIXED64: u8 = 1;
+const WIRE_LENGTH_DELIMITED: u8 = 2;
+const WIRE_FIXED32: u8 = 5;
+
+#[derive(Clone, Copy)]
+struct ProtoReader<'a> {
+    bytes: &'a [u8],
+    ix: usize,
+}
+
+impl<'a> ProtoReader<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        ProtoReader { bytes, ix: 0 }
+    }
+
+    fn read_varint(&mut self) -> Result<u64, ParseError> {
+        let mut result = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = match self.bytes.get(self.ix) {
+                Some(byte) => *byte,
+                None => fail!("Unexpected end of protobuf data at index {}", self.ix),
+            };
+            self.ix += 1;
+            result |= u64::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(result);
+            }
+        }
+        fail!("Invalid protobuf varint ending at index {}", self.ix)
+    }
+
+    fn read_i32(&mut self) -> Result<i32, ParseError> {
+        Ok(self.read_varint()? as i32)
+    }
+
+    // Returns the field number and wire type of the next field, or None at the end of the message.
+    fn read_field(&mut self) -> Result<Option<(u64, u8)>, ParseError> {
+        if self.ix >= self.bytes.len() {
+            return Ok(None);
+        }
+        let key = self.read_varint()?;
+        Ok(Some((key >> 3, (key & 0x7) as u8)))
+    }
+
+    fn read_bytes(&mut self) -> Result<&'a [u8], ParseError> {
+        let length = self.read_varint()?;
+        if ((self.bytes.len() - self.ix) as u64) < length {
+            fail!("Unexpected end of protobuf data reading {} bytes at index {}", length, self.ix);
+        }
+        let start = self.ix;
+        self.ix += length as usize;
+        Ok(&self.bytes[start..self.ix])
+    }
+
+    fn read_string(&mut self) -> Result<String, ParseError> {
+        match std::str::from_utf8(self.read_bytes()?) {
+            Ok(s) => Ok(s.to_string()),
+            Err(_) => fail!("Invalid UTF-8 in protobuf string"),
+        }
+    }
+
+    // Reads a repeated int32 field, which may or may not be packed.
+    fn read_repeated_i32(&mut self, wire_type: u8, values: &mut Vec<i32>) -> Result<(), ParseError> {
+        if wire_type == WIRE_LENGTH_DELIMITED {
+            let mut packed = ProtoReader::new(self.read_bytes()?);
+            while packed.ix < packed.bytes.len() {
+                values.push(packed.read_i32()?);
+            }
+        } else {
+            values.push(self.read_i32()?);
+        }
+        Ok(())
+    }
+
+    fn skip(&mut self, wire_type: u8) -> Result<(), ParseError> {
+        let length = match wire_type {
+            WIRE_VARINT => return self.read_varint().map(|_| ()),
+            WIRE_LENGTH_DELIMITED => return self.read_bytes().map(|_| ()),
+            WIRE_FIXED64 => 8,
+            WIRE_FIXED32 => 4,
+            _ => fail!("Unsupported protobuf wire type {}", wire_type),
+        };
+        if self.bytes.len() - self.ix < length {
+            fail!("Unexpected end of protobuf data at index {}", self.ix);
+        }
+        self.ix += length;
+        Ok(())
+    }
+}
+
+// A range of entries in the string table that share the same transformation.
+struct StringRecord {
+    end: u64,
+    string: Option<String>,
+    predefined_index: Option<usize>,
+    operation: i32,
+    substring_index: Vec<i32>,
+    replace_char: Vec<i32>,
+}
+
+// A type given either inline or as an index into the type table.
+enum TypeRef<'a> {
+    Inline(&'a [u8]),
+    Id(i32),
+}
+
+#[derive(Default)]
+struct TypeTable<'a> {
+    types: Vec<&'a [u8]>,
+    first_nullable: Option<usize>,
+}
+
+impl<'a> TypeTable<'a> {
+    fn read(bytes: &'a [u8]) -> Result<Self, ParseError> {
+        let mut reader = ProtoReader::new(bytes);
+        let mut table = TypeTable::default();
+        while let Some((field, wire_type)) = reader.read_field()? {
+            match (field, wire_type) {
+                (1, WIRE_LENGTH_DELIMITED) => table.types.push(reader.read_bytes()?),
+                (2, WIRE_VARINT) => table.first_nullable = usize::try_from(reader.read_i32()?).ok(),
+                _ => reader.skip(wire_type)?,
+            }
+        }
+        Ok(table)
+    }
+}
+
+struct Decoder {
+    strings: Vec<String>,
+    records: Vec<StringRecord>,
+    // The ids and names of the type parameters in scope, innermost last
+    type_parameters: Vec<(i32, String)>,
+    depth: usize,
+}
+
+impl Decoder {
+    // Reads the string table at the start of the decoded d1 bytes, and returns a reader for the
+    // message that follows it.
+    fn new(bytes: &[u8], strings: Vec<String>) -> Result<(Decoder, ProtoReader<'_>), ParseError> {
+        let mut reader = ProtoReader::new(bytes);
+        let mut table = ProtoReader::new(reader.read_bytes().map_err(|e| err!(e, "string table"))?);
+        let mut records = Vec::new();
+        let mut end = 0u64;
+        while let Some((field, wire_type)) = table.read_field()? {
+            match (field, wire_type) {
+                (1, WIRE_LENGTH_DELIMITED) => {
+                    let mut record = ProtoReader::new(table.read_bytes()?);
+                    let mut range = 1;
+                    let mut result = StringRecord {
+                        end: 0,
+                        string: None,
+                        predefined_index: None,
+                        operation: 0,
+                        substring_index: Vec::new(),
+                        replace_char: Vec::new(),
+                    };
+                    while let Some((field, wire_type)) = record.read_field()? {
+                        match (field, wire_type) {
+                            (1, WIRE_VARINT) => range = record.read_varint()? as u32,
+                            (2, WIRE_VARINT) => result.predefined_index = usize::try_from(record.read_i32()?).ok(),
+                            (3, WIRE_VARINT) => result.operation = record.read_i32()?,
+                            (4, _) => record.read_repeated_i32(wire_type, &mut result.substring_index)?,
+                            (5, _) => record.read_repeated_i32(wire_type, &mut result.replace_char)?,
+                            (6, WIRE_LENGTH_DELIMITED) => result.string = Some(record.read_string()?),
+                            _ => record.skip(wire_type)?,
+                        }
+                    }
+                    end += u64::from(range);
+                    result.end = end;
+                    records.push(result);
+                }
+                _ => table.skip(wire_type)?,
+            }
+        }
+        let decoder = Decoder {
+            strings,
+            records,
+            type_parameters: Vec::new(),
+            depth: 0,
+        };
+        Ok((decoder, ProtoReader::new(&bytes[reader.ix..])))
+    }
+
+    fn string(&self, index: i32) -> Result<String, ParseError> {
+        let index = match usize::try_from(index) {
+            Ok(index) => index,
+            Err(_) => fail!("Invalid string index {}", index),
+        };
+        let plain = || match self.strings.get(index) {
+            Some(s) => Ok(s.clone()),
+            None => fail!("String index {} out of range", index),
+        };
+        let position = self.records.partition_point(|r| r.end <= index as u64);
+        let record = match self.records.get(position) {
+            Some(record) => record,
+            None => return plain(),
+        };
+        let mut string = match (&record.string, record.predefined_index.and_then(|i| PREDEFINED_STRINGS.get(i))) {
+            (Some(s), _) => s.clone(),
+            (None, Some(s)) => s.to_string(),
+            (None, None) => plain()?,
+        };
+        if let [begin, end, ..] = record.substring_index[..] {
+            let chars: Vec<char> = string.chars().collect();
+            if 0 <= begin && begin <= end && end as usize <= chars.len() {
+                string = chars[begin as usize..end as usize].iter().collect();
+            }
+        }
+        if let [from, to, ..] = record.replace_char[..] {
+            if let (Some(from), Some(to)) = (char::from_u32(from as u32), char::from_u32(to as u32)) {
+                string = string.replace(from, &to.to_string());
+            }
+        }
+        match record.operation {
+            // INTERNAL_TO_CLASS_ID
+            1 => string = string.replace('$', "."),
+            // DESC_TO_CLASS_ID
+            2 => {
+                if string.chars().count() >= 2 {
+                    let mut chars = string.chars();
+                    chars.next();
+                    chars.next_back();
+                    string = chars.as_str().to_string();
+                }
+                string = string.replace('$', ".");
+            }
+            _ => (),
+        }
+        Ok(string)
+    }
+
+    fn class(&mut self, mut reader: ProtoReader) -> Result<KotlinClass, ParseError> {
+        let mut flags = 6;
+        let mut name = None;
+        let mut companion_object = None;
+        let mut type_parameters = Vec::new();
+        let mut supertypes = Vec::new();
+        let mut supertype_ids = Vec::new();
+        let mut nested_classes = Vec::new();
+        let mut constructors = Vec::new();
+        let mut functions = Vec::new();
+        let mut properties = Vec::new();
+        let mut type_aliases = Vec::new();
+        let mut enum_entries = Vec::new();
+        let mut sealed_subclasses = Vec::new();
+        let mut type_table = None;
+        let mut module_name = None;
+        while let Some((field, wire_type)) = reader.read_field()? {
+            match (field, wire_type) {
+                (1, WIRE_VARINT) => flags = reader.read_i32()?,
+                (2, _) => reader.read_repeated_i32(wire_type, &mut supertype_ids)?,
+                (3, WIRE_VARINT) => name = Some(self.string(reader.read_i32()?)?),
+                (4, WIRE_VARINT) => companion_object = Some(self.string(reader.read_i32()?)?),
+                (5, WIRE_LENGTH_DELIMITED) => type_parameters.push(reader.read_bytes()?),
+                (6, WIRE_LENGTH_DELIMITED) => supertypes.push(TypeRef::Inline(reader.read_bytes()?)),
+                (7, _) => reader.read_repeated_i32(wire_type, &mut nested_classes)?,
+                (8, WIRE_LENGTH_DELIMITED) => constructors.push(reader.read_bytes()?),
+                (9, WIRE_LENGTH_DELIMITED) => functions.push(reader.read_bytes()?),
+                (10, WIRE_LENGTH_DELIMITED) => properties.push(reader.read_bytes()?),
+                (11, WIRE_LENGTH_DELIMITED) => type_aliases.push(reader.read_bytes()?),
+                (13, WIRE_LENGTH_DELIMITED) => enum_entries.push(self.enum_entry(reader.read_bytes()?)?),
+                (16, _) => reader.read_repeated_i32(wire_type, &mut sealed_subclasses)?,
+                (30, WIRE_LENGTH_DELIMITED) => type_table = Some(TypeTable::read(reader.read_bytes()?)?),
+                (101, WIRE_VARINT) => module_name = Some(self.string(reader.read_i32()?)?),
+                _ => reader.skip(wire_type)?,
+            }
+        }
+        let name = match name {
+            Some(name) => name,
+            None => fail!("Missing class name"),
+        };
+        let types = type_table.unwrap_or_default();
+        supertypes.extend(supertype_ids.into_iter().map(TypeRef::Id));
+
+        let scope = self.type_parameters.len();
+        let type_parameters = self.type_parameters(&type_parameters, &types)?;
+        let result = KotlinClass {
+            flags,
+            name,
+            visibility: visibility(flags)?,
+            modality: modality(flags)?,
+            kind: match (flags >> 6) & 0x7 {
+                0 => KotlinClassKind::Class,
+                1 => KotlinClassKind::Interface,
+                2 => KotlinClassKind::EnumClass,
+                3 => KotlinClassKind::EnumEntry,
+                4 => KotlinClassKind::AnnotationClass,
+                5 => KotlinClassKind::Object,
+                6 => KotlinClassKind::CompanionObject,
+                x => fail!("Invalid class kind {}", x),
+            },
+            type_parameters,
+            supertypes: supertypes.into_iter().enumerate().map(|(i, ty)| {
+                self.resolve_type(ty, &types).map_err(|e| err!(e, "supertype {}", i))
+            }).collect::<Result<_, _>>()?,
+            constructors: constructors.into_iter().enumerate().map(|(i, bytes)| {
+                self.constructor(bytes, &types).map_err(|e| err!(e, "constructor {}", i))
+            }).collect::<Result<_, _>>()?,
+            functions: self.functions(&functions, &types)?,
+            properties: self.properties(&properties, &types)?,
+            type_aliases: self.type_aliases(&type_aliases, &types)?,
+            companion_object,
+            nested_classes: nested_classes.into_iter().map(|i| self.string(i)).collect::<Result<_, _>>()?,
+            enum_entries,
+            sealed_subclasses: sealed_subclasses.into_iter().map(|i| self.string(i)).collect::<Result<_, _>>()?,
+            module_name,
+        };
+        self.type_parameters.truncate(scope);
+        Ok(result)
+    }
+
+    fn package(&mut self, mut reader: ProtoReader) -> Result<KotlinPackage, ParseError> {
+        let mut functions = Vec::new();
+        let mut properties = Vec::new();
+        let mut type_aliases = Vec::new();
+        let mut type_table = None;
+        let mut module_name = None;
+        while let Some((field, wire_type)) = reader.read_field()? {
+            match (field, wire_type) {
+                (3, WIRE_LENGTH_DELIMITED) => functions.push(reader.read_bytes()?),
+                (4, WIRE_LENGTH_DELIMITED) => properties.push(reader.read_bytes()?),
+                (5, WIRE_LENGTH_DELIMITED) => type_aliases.push(reader.read_bytes()?),
+                (30, WIRE_LENGTH_DELIMITED) => type_table = Some(TypeTable::read(reader.read_bytes()?)?),
+                (101, WIRE_VARINT) => module_name = Some(self.string(reader.read_i32()?)?),
+                _ => reader.skip(wire_type)?,
+            }
+        }
+        let types = type_table.unwrap_or_default();
+        Ok(KotlinPackage {
+            functions: self.functions(&functions, &types)?,
+            properties: self.properties(&properties, &types)?,
+            type_aliases: self.type_aliases(&type_aliases, &types)?,
+            module_name,
+        })
+    }
+
+    fn functions(&mut self, functions: &[&[u8]], types: &TypeTable) -> Result<Vec<KotlinFunction>, ParseError> {
+        functions.iter().enumerate().map(|(i, bytes)| {
+            self.function(bytes, types).map_err(|e| err!(e, "function {}", i))
+        }).collect()
+    }
+
+    fn properties(&mut self, properties: &[&[u8]], types: &TypeTable) -> Result<Vec<KotlinProperty>, ParseError> {
+        properties.iter().enumerate().map(|(i, bytes)| {
+            self.property(bytes, types).map_err(|e| err!(e, "property {}", i))
+        }).collect()
+    }
+
+    fn type_aliases(&mut self, type_aliases: &[&[u8]], types: &TypeTable) -> Result<Vec<KotlinTypeAlias>, ParseError> {
+        type_aliases.iter().enumerate().map(|(i, bytes)| {
+            self.type_alias(bytes, types).map_err(|e| err!(e, "type alias {}", i))
+        }).collect()
+    }
+
+    fn enum_entry(&self, bytes: &[u8]) -> Result<String, ParseError> {
+        let mut reader = ProtoReader::new(bytes);
+        let mut name = None;
+        while let Some((field, wire_type)) = reader.read_field()? {
+            match (field, wire_type) {
+                (1, WIRE_VARINT) => name = Some(self.string(reader.read_i32()?)?),
+                _ => reader.skip(wire_type)?,
+            }
+        }
+        match name {
+            Some(name) => Ok(name),
+            None => fail!("Missing enum entry name"),
+        }
+    }
+
+    fn constructor(&mut self, bytes: &[u8], types: &TypeTable) -> Result<KotlinConstructor, ParseError> {
+        let mut reader = ProtoReader::new(bytes);
+        let mut flags = 6;
+        let mut value_parameters = Vec::new();
+        let mut jvm_signature = None;
+        while let Some((field, wire_type)) = reader.read_field()? {
+            match (field, wire_type) {
+                (1, WIRE_VARINT) => flags = reader.read_i32()?,
+                (2, WIRE_LENGTH_DELIMITED) => value_parameters.push(reader.read_bytes()?),
+                (100, WIRE_LENGTH_DELIMITED) => jvm_signature = Some(self.jvm_signature(reader.read_bytes()?, "<init>")?),
+                _ => reader.skip(wire_type)?,
+            }
+        }
+        Ok(KotlinConstructor {
+            flags,
+            visibility: visibility(flags)?,
+            value_parameters: self.value_parameters(&value_parameters, types)?,
+            jvm_signature,
+        })
+    }
+
+    fn function(&mut self, bytes: &[u8], types: &TypeTable) -> Result<KotlinFunction, ParseError> {
+        let mut reader = ProtoReader::new(bytes);
+        let mut flags = None;
+        let mut old_flags = 6;
+        let mut name = None;
+        let mut return_type = None;
+        let mut type_parameters = Vec::new();
+        let mut receiver_type = None;
+        let mut value_parameters = Vec::new();
+        let mut type_table = None;
+        let mut signature = None;
+        while let Some((field, wire_type)) = reader.read_field()? {
+            match (field, wire_type) {
+                (1, WIRE_VARINT) => old_flags = reader.read_i32()?,
+                (2, WIRE_VARINT) => name = Some(self.string(reader.read_i32()?)?),
+                (3, WIRE_LENGTH_DELIMITED) => return_type = Some(TypeRef::Inline(reader.read_bytes()?)),
+                (4, WIRE_LENGTH_DELIMITED) => type_parameters.push(reader.read_bytes()?),
+                (5, WIRE_LENGTH_DELIMITED) => receiver_type = Some(TypeRef::Inline(reader.read_bytes()?)),
+                (6, WIRE_LENGTH_DELIMITED) => value_parameters.push(reader.read_bytes()?),
+                (7, WIRE_VARINT) => return_type = Some(TypeRef::Id(reader.read_i32()?)),
+                (8, WIRE_VARINT) => receiver_type = Some(TypeRef::Id(reader.read_i32()?)),
+                (9, WIRE_VARINT) => flags = Some(reader.read_i32()?),
+                (30, WIRE_LENGTH_DELIMITED) => type_table = Some(TypeTable::read(reader.read_bytes()?)?),
+                (100, WIRE_LENGTH_DELIMITED) => signature = Some(reader.read_bytes()?),
+                _ => reader.skip(wire_type)?,
+            }
+        }
+        let flags = flags.unwrap_or_else(|| convert_old_flags(old_flags));
+        let name = match name {
+            Some(name) => name,
+            None => fail!("Missing function name"),
+        };
+        let types = type_table.as_ref().unwrap_or(types);
+        let return_type = match return_type {
+            Some(ty) => ty,
+            None => fail!("Missing return type for function {}", name),
+        };
+        let jvm_signature = match signature {
+            Some(bytes) => Some(self.jvm_signature(bytes, &name)?),
+            None => None,
+        };
+
+        let scope = self.type_parameters.len();
+        let result = KotlinFunction {
+            flags,
+            visibility: visibility(flags)?,
+            modality: modality(flags)?,
+            kind: member_kind(flags),
+            type_parameters: self.type_parameters(&type_parameters, types)?,
+            receiver_type: match receiver_type {
+                Some(ty) => Some(self.resolve_type(ty, types).map_err(|e| err!(e, "receiver type"))?),
+                None => None,
+            },
+            value_parameters: self.value_parameters(&value_parameters, types)?,
+            return_type: self.resolve_type(return_type, types).map_err(|e| err!(e, "return type"))?,
+            name,
+            jvm_signature,
+        };
+        self.type_parameters.truncate(scope);
+        Ok(result)
+    }
+
+    fn property(&mut self, bytes: &[u8], types: &TypeTable) -> Result<KotlinProperty, ParseError> {
+        let mut reader = ProtoReader::new(bytes);
+        let mut flags = None;
+        let mut old_flags = 2054;
+        let mut name = None;
+        let mut return_type = None;
+        let mut type_parameters = Vec::new();
+        let mut receiver_type = None;
+        let mut setter_parameter = None;
+        let mut getter_flags = None;
+        let mut setter_flags = None;
+        let mut signature = None;
+        while let Some((field, wire_type)) = reader.read_field()? {
+            match (field, wire_type) {
+                (1, WIRE_VARINT) => old_flags = reader.read_i32()?,
+                (2, WIRE_VARINT) => name = Some(self.string(reader.read_i32()?)?),
+                (3, WIRE_LENGTH_DELIMITED) => return_type = Some(TypeRef::Inline(reader.read_bytes()?)),
+                (4, WIRE_LENGTH_DELIMITED) => type_parameters.push(reader.read_bytes()?),
+                (5, WIRE_LENGTH_DELIMITED) => receiver_type = Some(TypeRef::Inline(reader.read_bytes()?)),
+                (6, WIRE_LENGTH_DELIMITED) => setter_parameter = Some(reader.read_bytes()?),
+                (7, WIRE_VARINT) => getter_flags = Some(reader.read_i32()?),
+                (8, WIRE_VARINT) => setter_flags = Some(reader.read_i32()?),
+                (9, WIRE_VARINT) => return_type = Some(TypeRef::Id(reader.read_i32()?)),
+                (10, WIRE_VARINT) => receiver_type = Some(TypeRef::Id(reader.read_i32()?)),
+                (11, WIRE_VARINT) => flags = Some(reader.read_i32()?),
+                (100, WIRE_LENGTH_DELIMITED) => signature = Some(reader.read_bytes()?),
+                _ => reader.skip(wire_type)?,
+            }
+        }
+        let flags = flags.unwrap_or_else(|| convert_old_flags(old_flags));
+        let name = match name {
+            Some(name) => name,
+            None => fail!("Missing property name"),
+        };
+        let return_type = match return_type {
+            Some(ty) => ty,
+            None => fail!("Missing return type for property {}", name),
+        };
+        let (jvm_field, jvm_getter, jvm_setter) = match signature {
+            Some(bytes) => self.jvm_property_signature(bytes, &name)?,
+            None => (None, None, None),
+        };
+
+        let scope = self.type_parameters.len();
+        let result = KotlinProperty {
+            flags,
+            visibility: visibility(flags)?,
+            modality: modality(flags)?,
+            kind: member_kind(flags),
+            type_parameters: self.type_parameters(&type_parameters, types)?,
+            receiver_type: match receiver_type {
+                Some(ty) => Some(self.resolve_type(ty, types).map_err(|e| err!(e, "receiver type"))?),
+                None => None,
+            },
+            return_type: self.resolve_type(return_type, types).map_err(|e| err!(e, "return type"))?,
+            setter_parameter: match setter_parameter {
+                Some(bytes) => Some(self.value_parameter(bytes, types).map_err(|e| err!(e, "setter parameter"))?),
+                None => None,
+            },
+            getter_visibility: visibility(getter_flags.unwrap_or(flags))?,
+            setter_visibility: visibility(setter_flags.unwrap_or(flags))?,
+            name,
+            jvm_field,
+            jvm_getter,
+            jvm_setter,
+        };
+        self.type_parameters.truncate(scope);
+        Ok(result)
+    }
+
+    fn type_alias(&mut self, bytes: &[u8], types: &TypeTable) -> Result<KotlinTypeAlias, ParseError> {
+        let mut reader = ProtoReader::new(bytes);
+        let mut flags = 6;
+        let mut name = None;
+        let mut type_parameters = Vec::new();
+        let mut underlying_type = None;
+        let mut expanded_type = None;
+        while let Some((field, wire_type)) = reader.read_field()? {
+            match (field, wire_type) {
+                (1, WIRE_VARINT) => flags = reader.read_i32()?,
+                (2, WIRE_VARINT) => name = Some(self.string(reader.read_i32()?)?),
+                (3, WIRE_LENGTH_DELIMITED) => type_parameters.push(reader.read_bytes()?),
+                (4, WIRE_LENGTH_DELIMITED) => underlying_type = Some(TypeRef::Inline(reader.read_bytes()?)),
+                (5, WIRE_VARINT) => underlying_type = Some(TypeRef::Id(reader.read_i32()?)),
+                (6, WIRE_LENGTH_DELIMITED) => expanded_type = Some(TypeRef::Inline(reader.read_bytes()?)),
+                (7, WIRE_VARINT) => expanded_type = Some(TypeRef::Id(reader.read_i32()?)),
+                _ => reader.skip(wire_type)?,
+            }
+        }
+        let (name, underlying_type, expanded_type) = match (name, underlying_type, expanded_type) {
+            (Some(name), Some(underlying_type), Some(expanded_type)) => (name, underlying_type, expanded_type),
+            _ => fail!("Missing name or type of type alias"),
+        };
+
+        let scope = self.type_parameters.len();
+        let result = KotlinTypeAlias {
+            flags,
+            visibility: visibility(flags)?,
+            type_parameters: self.type_parameters(&type_parameters, types)?,
+            underlying_type: self.resolve_type(underlying_type, types).map_err(|e| err!(e, "underlying type"))?,
+            expanded_type: self.resolve_type(expanded_type, types).map_err(|e| err!(e, "expanded type"))?,
+            name,
+        };
+        self.type_parameters.truncate(scope);
+        Ok(result)
+    }
+
+    fn value_parameters(&mut self, parameters: &[&[u8]], types: &TypeTable) -> Result<Vec<KotlinValueParameter>, ParseError> {
+        parameters.iter().enumerate().map(|(i, bytes)| {
+            self.value_parameter(bytes, types).map_err(|e| err!(e, "value parameter {}", i))
+        }).collect()
+    }
+
+    fn value_parameter(&mut self, bytes: &[u8], types: &TypeTable) -> Result<KotlinValueParameter, ParseError> {
+        let mut reader = ProtoReader::new(bytes);
+        let mut flags = 0;
+        let mut name = None;
+        let mut ty = None;
+        let mut vararg_element_type = None;
+        while let Some((field, wire_type)) = reader.read_field()? {
+            match (field, wire_type) {
+                (1, WIRE_VARINT) => flags = reader.read_i32()?,
+                (2, WIRE_VARINT) => name = Some(self.string(reader.read_i32()?)?),
+                (3, WIRE_LENGTH_DELIMITED) => ty = Some(TypeRef::Inline(reader.read_bytes()?)),
+                (4, WIRE_LENGTH_DELIMITED) => vararg_element_type = Some(TypeRef::Inline(reader.read_bytes()?)),
+                (5, WIRE_VARINT) => ty = Some(TypeRef::Id(reader.read_i32()?)),
+                (6, WIRE_VARINT) => vararg_element_type = Some(TypeRef::Id(reader.read_i32()?)),
+                _ => reader.skip(wire_type)?,
+            }
+        }
+        let (name, ty) = match (name, ty) {
+            (Some(name), Some(ty)) => (name, ty),
+            _ => fail!("Missing name or type of value parameter"),
+        };
+        Ok(KotlinValueParameter {
+            flags,
+            name,
+            ty: self.resolve_type(ty, types)?,
+            vararg_element_type: match vararg_element_type {
+                Some(ty) => Some(self.resolve_type(ty, types)?),
+                None => None,
+            },
+        })
+    }
+
+    // Decodes the given type parameters and brings them into scope. The caller is responsible for
+    // removing them from scope again. All the names are brought into scope before any bounds are
+    // decoded, since the bounds can refer to any of the parameters.
+    fn type_parameters(&mut self, parameters: &[&[u8]], types: &TypeTable) -> Result<Vec<KotlinTypeParameter>, ParseError> {
+        let mut headers = Vec::with_capacity(parameters.len());
+        for (i, bytes) in parameters.iter().enumerate() {
+            let mut reader = ProtoReader::new(bytes);
+            let mut id = None;
+            let mut name = None;
+            let mut reified = false;
+            let mut variance = KotlinVariance::Invariant;
+            let mut upper_bounds = Vec::new();
+            while let Some((field, wire_type)) = reader.read_field()? {
+                match (field, wire_type) {
+                    (1, WIRE_VARINT) => id = Some(reader.read_i32()?),
+                    (2, WIRE_VARINT) => name = Some(self.string(reader.read_i32()?)?),
+                    (3, WIRE_VARINT) => reified = reader.read_varint()? != 0,
+                    (4, WIRE_VARINT) => variance = self::variance(reader.read_i32()?)?,
+                    (5, WIRE_LENGTH_DELIMITED) => upper_bounds.push(TypeRef::Inline(reader.read_bytes()?)),
+                    (6, _) => {
+                        let mut ids = Vec::new();
+                        reader.read_repeated_i32(wire_type, &mut ids)?;
+                        upper_bounds.extend(ids.into_iter().map(TypeRef::Id));
+                    }
+                    _ => reader.skip(wire_type)?,
+                }
+            }
+            let (id, name) = match (id, name) {
+                (Some(id), Some(name)) => (id, name),
+                _ => fail!("Missing id or name of type parameter {}", i),
+            };
+            self.type_parameters.push((id, name.clone()));
+            headers.push((id, name, reified, variance, upper_bounds));
+        }
+        let mut result = Vec::with_capacity(headers.len());
+        for (i, (id, name, reified, variance, upper_bounds)) in headers.into_iter().enumerate() {
+            let upper_bounds = upper_bounds
+                .into_iter()
+                .map(|ty| self.resolve_type(ty, types))
+                .collect::<Result<_, _>>()
+                .map_err(|e| err!(e, "type parameter {}", i))?;
+            result.push(KotlinTypeParameter {
+                id,
+                name,
+                reified,
+                variance,
+                upper_bounds,
+            });
+        }
+        Ok(result)
+    }
+
+    fn resolve_type(&mut self, ty: TypeRef, types: &TypeTable) -> Result<KotlinType, ParseError> {
+        match ty {
+            TypeRef::Inline(bytes) => self.type_(bytes, types),
+            TypeRef::Id(id) => {
+                let bytes = match usize::try_from(id).ok().and_then(|i| types.types.get(i)) {
+                    Some(bytes) => *bytes,
+                    None => fail!("Type id {} out of range", id),
+                };
+                let mut ty = self.type_(bytes, types)?;
+                if types.first_nullable.is_some_and(|first| id as usize >= first) {
+                    ty.nullable = true;
+                }
+                Ok(ty)
+            }
+        }
+    }
+
+    fn type_(&mut self, bytes: &[u8], types: &TypeTable) -> Result<KotlinType, ParseError> {
+        if self.depth >= MAX_TYPE_DEPTH {
+            fail!("Exceeded the maximum nesting depth of {} for Kotlin types", MAX_TYPE_DEPTH);
+        }
+        self.depth += 1;
+        let result = self.type_inner(bytes, types);
+        self.depth -= 1;
+        result
+    }
+
+    fn type_inner(&mut self, bytes: &[u8], types: &TypeTable) -> Result<KotlinType, ParseError> {
+        let mut reader = ProtoReader::new(bytes);
+        let mut flags = 0;
+        let mut classifier = None;
+        let mut arguments = Vec::new();
+        let mut nullable = false;
+        let mut outer_type = None;
+        let mut abbreviated_type = None;
+        let mut flexible_upper_bound = None;
+        while let Some((field, wire_type)) = reader.read_field()? {
+            match (field, wire_type) {
+                (1, WIRE_VARINT) => flags = reader.read_i32()?,
+                (2, WIRE_LENGTH_DELIMITED) => arguments.push(reader.read_bytes()?),
+                (3, WIRE_VARINT) => nullable = reader.read_varint()? != 0,
+                (5, WIRE_LENGTH_DELIMITED) => flexible_upper_bound = Some(TypeRef::Inline(reader.read_bytes()?)),
+                (6, WIRE_VARINT) => classifier = Some(KotlinClassifier::Class(self.string(reader.read_i32()?)?)),
+                (7, WIRE_VARINT) => {
+                    let id = reader.read_i32()?;
+                    let name = self.type_parameters.iter().rev().find(|(x, _)| *x == id).map(|(_, name)| name.clone());
+                    classifier = Some(KotlinClassifier::TypeParameter { id: Some(id), name });
+                }
+                (8, WIRE_VARINT) => flexible_upper_bound = Some(TypeRef::Id(reader.read_i32()?)),
+                (9, WIRE_VARINT) => {
+                    let name = self.string(reader.read_i32()?)?;
+                    classifier = Some(KotlinClassifier::TypeParameter { id: None, name: Some(name) });
+                }
+                (10, WIRE_LENGTH_DELIMITED) => outer_type = Some(TypeRef::Inline(reader.read_bytes()?)),
+                (11, WIRE_VARINT) => outer_type = Some(TypeRef::Id(reader.read_i32()?)),
+                (12, WIRE_VARINT) => classifier = Some(KotlinClassifier::TypeAlias(self.string(reader.read_i32()?)?)),
+                (13, WIRE_LENGTH_DELIMITED) => abbreviated_type = Some(TypeRef::Inline(reader.read_bytes()?)),
+                (14, WIRE_VARINT) => abbreviated_type = Some(TypeRef::Id(reader.read_i32()?)),
+                _ => reader.skip(wire_type)?,
+            }
+        }
+        let classifier = match classifier {
+            Some(classifier) => classifier,
+            None => fail!("Missing classifier of type"),
+        };
+        Ok(KotlinType {
+            flags,
+            classifier,
+            nullable,
+            outer_type: self.resolve_boxed_type(outer_type, types)?,
+            abbreviated_type: self.resolve_boxed_type(abbreviated_type, types)?,
+            flexible_upper_bound: self.resolve_boxed_type(flexible_upper_bound, types)?,
+            arguments: arguments.into_iter().map(|bytes| self.type_argument(bytes, types)).collect::<Result<_, _>>()?,
+        })
+    }
+
+    fn resolve_boxed_type(&mut self, ty: Option<TypeRef>, types: &TypeTable) -> Result<Option<Box<KotlinType>>, ParseError> {
+        match ty {
+            Some(ty) => Ok(Some(Box::new(self.resolve_type(ty, types)?))),
+            None => Ok(None),
+        }
+    }
+
+    fn type_argument(&mut self, bytes: &[u8], types: &TypeTable) -> Result<KotlinTypeProjection, ParseError> {
+        let mut reader = ProtoReader::new(bytes);
+        let mut projection = 2;
+        let mut ty = None;
+        while let Some((field, wire_type)) = reader.read_field()? {
+            match (field, wire_type) {
+                (1, WIRE_VARINT) => projection = reader.read_i32()?,
+                (2, WIRE_LENGTH_DELIMITED) => ty = Some(TypeRef::Inline(reader.read_bytes()?)),
+                (3, WIRE_VARINT) => ty = Some(TypeRef::Id(reader.read_i32()?)),
+                _ => reader.skip(wire_type)?,
+            }
+        }
+        if projection == 3 {
+            return Ok(KotlinTypeProjection::Star);
+        }
+        let ty = match ty {
+            Some(ty) => self.resolve_type(ty, types)?,
+            None => fail!("Missing type of type argument"),
+        };
+        Ok(KotlinTypeProjection::Type {
+            variance: variance(projection)?,
+            ty,
+        })
+    }
+
+    fn jvm_signature(&self, bytes: &[u8], default_name: &str) -> Result<JvmMemberSignature, ParseError> {
+        let mut reader = ProtoReader::new(bytes);
+        let mut name = None;
+        let mut descriptor = None;
+        while let Some((field, wire_type)) = reader.read_field()? {
+            match (field, wire_type) {
+                (1, WIRE_VARINT) => name = Some(self.string(reader.read_i32()?)?),
+                (2, WIRE_VARINT) => descriptor = Some(self.string(reader.read_i32()?)?),
+                _ => reader.skip(wire_type)?,
+            }
+        }
+        Ok(JvmMemberSignature {
+            name: name.unwrap_or_else(|| default_name.to_string()),
+            descriptor,
+        })
+    }
+
+    // Returns the backing field, getter and setter signatures of a property.
+    #[allow(clippy::type_complexity)]
+    fn jvm_property_signature(
+        &self,
+        bytes: &[u8],
+        name: &str,
+    ) -> Result<(Option<JvmMemberSignature>, Option<JvmMemberSignature>, Option<JvmMemberSignature>), ParseError> {
+        let mut reader = ProtoReader::new(bytes);
+        let mut field_signature = None;
+        let mut getter = None;
+        let mut setter = None;
+        while let Some((field, wire_type)) = reader.read_field()? {
+            match (field, wire_type) {
+                (1, WIRE_LENGTH_DELIMITED) => field_signature = Some(self.jvm_signature(reader.read_bytes()?, name)?),
+                (3, WIRE_LENGTH_DELIMITED) => getter = Some(self.jvm_signature(reader.read_bytes()?, name)?),
+                (4, WIRE_LENGTH_DELIMITED) => setter = Some(self.jvm_signature(reader.read_bytes()?, name)?),
+                _ => reader.skip(wire_type)?,
+            }
+        }
+        Ok((field_signature, getter, setter))
+    }
+}
+
+// Flags written by compilers before 1.1 had an extra byte in the middle.
+fn convert_old_flags(old_flags: i32) -> i32 {
+    (old_flags & 0x3f) + ((old_flags >> 8) << 6)
+}
+
+fn visibility(flags: i32) -> Result<KotlinVisibility, ParseError> {
+    Ok(match (flags >> 1) & 0x7 {
+        0 => KotlinVisibility::Internal,
+        1 => KotlinVisibility::Private,
+        2 => KotlinVisibility::Protected,
+        3 => KotlinVisibility::Public,
+        4 => KotlinVisibility::PrivateToThis,
+        5 => KotlinVisibility::Local,
+        x => fail!("Invalid visibility {}", x),
+    })
+}
+
+fn modality(flags: i32) -> Result<KotlinModality, ParseError> {
+    Ok(match (flags >> 4) & 0x3 {
+        0 => KotlinModality::Final,
+        1 => KotlinModality::Open,
+        2 => KotlinModality::Abstract,
+        _ => KotlinModality::Sealed,
+    })
+}
+
+fn member_kind(flags: i32) -> KotlinMemberKind {
+    match (flags >> 6) & 0x3 {
+        0 => KotlinMemberKind::Declaration,
+        1 => KotlinMemberKind::FakeOverride,
+        2 => KotlinMemberKind::Delegation,
+        _ => KotlinMemberKind::Synthesized,
+    }
+}
+
+fn variance(value: i32) -> Result<KotlinVariance, ParseError> {
+    Ok(match value {
+        0 => KotlinVariance::In,
+        1 => KotlinVariance::Out,
+        2 => KotlinVariance::Invariant,
+        x => fail!("Invalid variance {}", x),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::attributes::{AnnotationElement, AttributeInfo};
+
+    fn varint(out: &mut Vec<u8>, mut value: u64) {
+        while value >= 0x80 {
+            out.push((value as u8) | 0x80);
+            value >>= 7;
+        }
+        out.push(value as u8);
+    }
+
+    fn int_field(out: &mut Vec<u8>, field: u64, value: i32) {
+        varint(out, field << 3);
+        varint(out, value as i64 as u64);
+    }
+
+    fn message_field(out: &mut Vec<u8>, field: u64, message: &[u8]) {
+        varint(out, (field << 3) | 2);
+        varint(out, message.len() as u64);
+        out.extend_from_slice(message);
+    }
+
+    fn message(fields: &[(u64, i32)], messages: &[(u64, Vec<u8>)]) -> Vec<u8> {
+        let mut out = Vec::new();
+        for (field, value) in fields {
+            int_field(&mut out, *field, *value);
+        }
+        for (field, bytes) in messages {
+            message_field(&mut out, *field, bytes);
+        }
+        out
+    }
+
+    // Packs bytes 7 bits per char, the way compilers before 1.4 wrote d1.
+    fn encode_8to7(bytes: &[u8]) -> String {
+        let mut result = String::new();
+        let mut buffer = 0u32;
+        let mut bits = 0;
+        for byte in bytes {
+            buffer |= u32::from(*byte) << bits;
+            bits += 8;
+            while bits >= 7 {
+                result.push(char::from(((buffer & 0x7f) as u8 + 1) & 0x7f));
+                buffer >>= 7;
+                bits -= 7;
+            }
+        }
+        if bits > 0 {
+            result.push(char::from(((buffer & 0x7f) as u8 + 1) & 0x7f));
+        }
+        result
+    }
+
+    fn metadata_annotation(kind: i32, d1: Vec<String>, d2: &[&str]) -> Annotation {
+        let strings = |values: Vec<String>| {
+            AnnotationElementValue::ArrayValue(values.into_iter().map(AnnotationElementValue::StringConstant).collect())
+        };
+        let element = |name: &str, value| AnnotationElement { name: name.to_string(), value };
+        Annotation {
+            type_descriptor: METADATA_DESCRIPTOR.to_string(),
+            elements: vec![
+                element("mv", AnnotationElementValue::ArrayValue(vec![AnnotationElementValue::IntConstant(1), AnnotationElementValue::IntConstant(9)])),
+                element("k", AnnotationElementValue::IntConstant(kind)),
+                element("xi", AnnotationElementValue::IntConstant(48)),
+                element("d1", strings(d1)),
+                element("d2", strings(d2.iter().map(|s| s.to_string()).collect())),
+            ],
+        }
+    }
+
+    // The d1 bytes and d2 strings for:
+    //   data class Foo.Bar<T>(val name: String) : String { var name: String; private set
+    //       suspend operator fun String.greet(name: T? = null): Int? }
+    fn class_metadata() -> (Vec<u8>, Vec<&'static str>) {
+        let d2 = vec!["LFoo$Bar;", "", "name", "greet", "T", "kotlin/Int", "getName", "()Ljava/lang/String;", "(Ljava/lang/String;)V"];
+        let string_table = message(&[], &[
+            (1, message(&[(1, 1), (3, 2)], &[])),
+            (1, message(&[(1, 1), (2, 14)], &[])),
+            (1, message(&[(1, 7)], &[])),
+        ]);
+        let type_table = message(&[(2, 2)], &[
+            (1, message(&[(6, 1)], &[])),
+            (1, message(&[(6, 5)], &[])),
+            (1, message(&[(7, 0)], &[])),
+        ]);
+        let constructor = message(&[], &[
+            (2, message(&[(2, 2), (5, 0)], &[])),
+            (100, message(&[(2, 8)], &[])),
+        ]);
+        let function = message(&[(2, 3), (8, 0), (9, 6 | (1 << 8) | (1 << 13))], &[
+            (3, message(&[(6, 5), (3, 1)], &[])),
+            (6, message(&[(1, 2), (2, 2), (5, 2)], &[])),
+        ]);
+        let property = message(&[(2, 2), (9, 0), (11, 518 | (1 << 8) | (1 << 10)), (8, 2)], &[
+            (100, message(&[], &[(1, vec![]), (3, message(&[(1, 6), (2, 7)], &[]))])),
+        ]);
+        let class = message(&[(1, 6 | (1 << 10)), (3, 0), (2, 0)], &[
+            (5, message(&[(1, 0), (2, 4)], &[])),
+            (8, constructor),
+            (9, function),
+            (10, property),
+            (30, type_table),
+        ]);
+        let mut bytes = Vec::new();
+        varint(&mut bytes, string_table.len() as u64);
+        bytes.extend(string_table);
+        bytes.extend(class);
+        (bytes, d2)
+    }
+
+    fn check_class(metadata: &KotlinMetadata) {
+        assert_eq!(metadata.kind, KotlinMetadataKind::Class);
+        assert_eq!(metadata.metadata_version, vec![1, 9]);
+        assert_eq!(metadata.extra_int, 48);
+        let class = match &metadata.declarations {
+            KotlinDeclarations::Class(class) => class,
+            x => panic!("Unexpected declarations {:?}", x),
+        };
+        assert_eq!(class.name, "Foo.Bar");
+        assert!(class.is_data());
+        assert_eq!(class.visibility, KotlinVisibility::Public);
+        assert_eq!(class.modality, KotlinModality::Final);
+        assert_eq!(class.kind, KotlinClassKind::Class);
+        assert_eq!(class.type_parameters[0].name, "T");
+        assert_eq!(class.supertypes[0].classifier, KotlinClassifier::Class("kotlin/String".to_string()));
+        assert!(!class.supertypes[0].nullable);
+
+        let constructor = &class.constructors[0];
+        assert_eq!(constructor.value_parameters[0].name, "name");
+        assert_eq!(
+            constructor.jvm_signature,
+            Some(JvmMemberSignature { name: "<init>".to_string(), descriptor: Some("(Ljava/lang/String;)V".to_string()) })
+        );
+
+        let function = &class.functions[0];
+        assert_eq!(function.name, "greet");
+        assert!(function.is_suspend() && function.is_operator() && !function.is_inline());
+        assert_eq!(function.kind, KotlinMemberKind::Declaration);
+        assert_eq!(function.receiver_type.as_ref().unwrap().classifier, KotlinClassifier::Class("kotlin/String".to_string()));
+        assert_eq!(function.return_type.classifier, KotlinClassifier::Class("kotlin/Int".to_string()));
+        assert!(function.return_type.nullable);
+        let parameter = &function.value_parameters[0];
+        assert!(parameter.declares_default_value());
+        assert_eq!(parameter.ty.classifier, KotlinClassifier::TypeParameter { id: Some(0), name: Some("T".to_string()) });
+        assert!(parameter.ty.nullable);
+
+        let property = &class.properties[0];
+        assert_eq!(property.name, "name");
+        assert!(property.is_var() && property.has_getter() && property.has_setter());
+        assert_eq!(property.visibility, KotlinVisibility::Public);
+        assert_eq!(property.getter_visibility, KotlinVisibility::Public);
+        assert_eq!(property.setter_visibility, KotlinVisibility::Private);
+        assert_eq!(property.jvm_field, Some(JvmMemberSignature { name: "name".to_string(), descriptor: None }));
+        assert_eq!(
+            property.jvm_getter,
+            Some(JvmMemberSignature { name: "getName".to_string(), descriptor: Some("()Ljava/lang/String;".to_string()) })
+        );
+        assert_eq!(property.jvm_setter, None);
+    }
+
+    #[test]
+    fn test_kotlin_class() {
+        let (bytes, d2) = class_metadata();
+        let mut d1 = String::from("\u{0}");
+        d1.extend(bytes.iter().map(|b| char::from(*b)));
+        let annotation = metadata_annotation(1, vec![d1], &d2);
+
+        let text = ".version 52 0\n.class Test\n.end class\n";
+        let mut class = crate::assembly::parse_assembly(text).unwrap();
+        assert_eq!(KotlinMetadata::from_class(&class).unwrap(), None);
+        class.attributes.push(AttributeInfo {
+            name: "RuntimeVisibleAnnotations".to_string(),
+            data: AttributeData::RuntimeVisibleAnnotations(vec![annotation]),
+        });
+        check_class(&KotlinMetadata::from_class(&class).unwrap().unwrap());
+
+        // The same metadata in the older 8-to-7 bit encoding, split across strings
+        let encoded = encode_8to7(&bytes);
+        let (first, second) = encoded.split_at(10);
+        let annotation = metadata_annotation(1, vec![first.to_string(), second.to_string()], &d2);
+        check_class(&KotlinMetadata::from_annotations(&[annotation]).unwrap().unwrap());
+    }
+
+    #[test]
+    fn test_kotlin_file() {
+        let d2 = ["main", "kotlin/Unit", "args", "kotlin/Array", "kotlin/String", "Names", "kotlin/collections/List"];
+        let string_type = message(&[(6, 4)], &[]);
+        let function = message(&[(2, 0), (9, 6)], &[
+            (3, message(&[(6, 1)], &[])),
+            (6, message(&[(2, 2)], &[(3, message(&[(6, 3)], &[(2, message(&[(1, 1)], &[(2, string_type.clone())]))]))])),
+        ]);
+        let type_alias = message(&[(2, 5)], &[
+            (4, message(&[(6, 6)], &[(2, message(&[(1, 3)], &[]))])),
+            (6, message(&[(6, 6)], &[(2, message(&[], &[(2, string_type)]))])),
+        ]);
+        let package = message(&[], &[(3, function), (5, type_alias)]);
+        let mut bytes = vec![0];
+        bytes.extend(package);
+        let mut d1 = String::from("\u{0}");
+        d1.extend(bytes.iter().map(|b| char::from(*b)));
+        let annotation = metadata_annotation(2, vec![d1], &d2);
+
+        let metadata = KotlinMetadata::from_annotations(&[annotation]).unwrap().unwrap();
+        let package = match metadata.declarations {
+            KotlinDeclarations::Package(package) => package,
+            x => panic!("Unexpected declarations {:?}", x),
+        };
+        let main = &package.functions[0];
+        assert_eq!(main.name, "main");
+        let args = &main.value_parameters[0].ty;
+        assert_eq!(args.classifier, KotlinClassifier::Class("kotlin/Array".to_string()));
+        match &args.arguments[0] {
+            KotlinTypeProjection::Type { variance, ty } => {
+                assert_eq!(*variance, KotlinVariance::Out);
+                assert_eq!(ty.classifier, KotlinClassifier::Class("kotlin/String".to_string()));
+            }
+            x => panic!("Unexpected projection {:?}", x),
+        }
+        let alias = &package.type_aliases[0];
+        assert_eq!(alias.name, "Names");
+        assert_eq!(alias.underlying_type.arguments, vec![KotlinTypeProjection::Star]);
+        assert!(matches!(alias.expanded_type.arguments[0], KotlinTypeProjection::Type { variance: KotlinVariance::Invariant, .. }));
+    }
+
+    #[test]
+    fn test_invalid_kotlin_metadata() {
+        // A type that refers to itself through the type table
+        let d2 = ["Foo"];
+        let type_table = message(&[], &[(1, message(&[(6, 0)], &[(2, message(&[(3, 0)], &[]))]))]);
+        let class = message(&[(3, 0), (2, 0)], &[(30, type_table)]);
+        let mut d1 = String::from("\u{0}\u{0}");
+        d1.extend(class.iter().map(|b| char::from(*b)));
+        let annotation = metadata_annotation(1, vec![d1], &d2);
+        let err = KotlinMetadata::from_annotations(&[annotation]).unwrap_err();
+        assert!(err.to_string().starts_with("Exceeded the maximum nesting depth"));
+
+        let annotation = metadata_annotation(1, vec!["\u{0}\u{5}\u{1}".to_string()], &d2);
+        assert!(KotlinMetadata::from_annotations(&[annotation]).is_err());
+    }
+}
@@ -0,0 +1,321 @@
+//! Computing where the fields of a class are stored in memory, the way a JVM lays out objects,
+//! for estimating how much memory objects use.
+//!
+//! Every object starts with a header, made up of a mark word and a pointer to its class,
+//! followed by the instance fields of its superclasses and then its own. Fields are aligned
+//! to their size, and the object as a whole to [`LayoutOptions::object_alignment`]. Fields that
+//! the JVM injects into some classes, and the padding added for `@Contended` fields, are not
+//! accounted for.
+
+use crate::classpath::ClassRepository;
+use crate::{FieldAccessFlags, ParseError};
+
+/// How the fields of each class are ordered.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum LayoutStrategy {
+    /// Fields are placed one after another in the order they are declared.
+    DeclarationOrder,
+    /// Fields are grouped by size, largest first with references last, and each is placed in the
+    /// first gap left by alignment that it fits in, including gaps left in the fields of
+    /// superclasses. This is how HotSpot lays out fields since JDK 15.
+    HotSpot,
+}
+
+#[derive(Clone, Debug)]
+pub struct LayoutOptions {
+    strategy: LayoutStrategy,
+    compressed_oops: bool,
+    compressed_class_pointers: bool,
+    object_alignment: usize,
+}
+
+impl Default for LayoutOptions {
+    fn default() -> Self {
+        Self {
+            strategy: LayoutStrategy::HotSpot,
+            compressed_oops: true,
+            compressed_class_pointers: true,
+            object_alignment: 8,
+        }
+    }
+}
+
+impl LayoutOptions {
+    /// Sets how fields are ordered. The default is LayoutStrategy::HotSpot.
+    pub fn strategy(&mut self, strategy: LayoutStrategy) -> &mut LayoutOptions {
+        self.strategy = strategy;
+        self
+    }
+
+    /// Turns on or off compressed references, which makes fields and array elements that hold
+    /// references 4 bytes rather than 8. HotSpot enables them by default for heaps smaller than
+    /// 32GB, and so does this.
+    pub fn compressed_oops(&mut self, compressed: bool) -> &mut LayoutOptions {
+        self.compressed_oops = compressed;
+        self
+    }
+
+    /// Turns on or off compressed class pointers, which makes the pointer to the class in each
+    /// object header 4 bytes rather than 8, so that the header takes 12 bytes rather than 16.
+    /// Enabled by default.
+    pub fn compressed_class_pointers(&mut self, compressed: bool) -> &mut LayoutOptions {
+        self.compressed_class_pointers = compressed;
+        self
+    }
+
+    /// Sets the alignment in bytes of the size of every object. HotSpot's default, and the
+    /// default here, is 8.
+    pub fn object_alignment(&mut self, alignment: usize) -> &mut LayoutOptions {
+        self.object_alignment = alignment.max(1);
+        self
+    }
+
+    /// Returns the size in bytes of the header at the start of every object.
+    pub fn header_size(&self) -> usize {
+        if self.compressed_class_pointers {
+            12
+        } else {
+            16
+        }
+    }
+
+    /// Returns the size in bytes of a field or array element with the given field descriptor.
+    pub fn field_size(&self, descriptor: &str) -> Result<usize, ParseError> {
+        match descriptor.as_bytes().first() {
+            Some(b'B') | Some(b'Z') => Ok(1),
+            Some(b'C') | Some(b'S') => Ok(2),
+            Some(b'I') | Some(b'F') => Ok(4),
+            Some(b'J') | Some(b'D') => Ok(8),
+            Some(b'L') | Some(b'[') if self.compressed_oops => Ok(4),
+            Some(b'L') | Some(b'[') => Ok(8),
+            _ => fail!("Invalid field descriptor {}", descriptor),
+        }
+    }
+
+    /// Returns the size in bytes of an array with the given component type descriptor and length,
+    /// such as `I` for an `int[]`. Arrays have a 4 byte length after the object header, followed
+    /// by the elements aligned to their size.
+    pub fn array_size(&self, component: &str, length: usize) -> Result<usize, ParseError> {
+        let element_size = self.field_size(component)?;
+        let elements = align(self.header_size() + 4, element_size);
+        Ok(align(elements + element_size * length, self.object_alignment))
+    }
+}
+
+fn align(offset: usize, alignment: usize) -> usize {
+    offset + (alignment - offset % alignment) % alignment
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct FieldLayout {
+    /// The class that declares the field, which may be a superclass of the laid out class.
+    pub class_name: String,
+    pub name: String,
+    pub descriptor: String,
+    pub offset: usize,
+    pub size: usize,
+}
+
+/// Where the fields of a class are stored.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct ObjectLayout {
+    pub class_name: String,
+    pub header_size: usize,
+    /// The instance fields of the class and its superclasses, ordered by offset from the start
+    /// of the object.
+    pub fields: Vec<FieldLayout>,
+    /// The size of an instance in bytes, including the header and the padding at the end.
+    pub instance_size: usize,
+    /// The static fields declared by the class, ordered by offset from the start of the block
+    /// holding them. HotSpot stores this block inside the class's `java.lang.Class` object.
+    pub static_fields: Vec<FieldLayout>,
+    /// The number of bytes that the static fields take, including padding between them.
+    pub static_size: usize,
+}
+
+// Places fields one at a time, keeping track of the gaps that alignment leaves between them.
+struct Packer {
+    end: usize,
+    gaps: Vec<(usize, usize)>,
+    fill_gaps: bool,
+}
+
+impl Packer {
+    fn place(&mut self, size: usize) -> usize {
+        if self.fill_gaps {
+            for i in 0..self.gaps.len() {
+                let (start, end) = self.gaps[i];
+                let offset = align(start, size);
+                if offset + size <= end {
+                    self.gaps.remove(i);
+                    if offset + size < end {
+                        self.gaps.insert(i, (offset + size, end));
+                    }
+                    if start < offset {
+                        self.gaps.insert(i, (start, offset));
+                    }
+                    return offset;
+                }
+            }
+        }
+        let offset = align(self.end, size);
+        if offset > self.end {
+            self.gaps.push((self.end, offset));
+        }
+        self.end = offset + size;
+        offset
+    }
+
+    // Lays out the given fields, which must be in declaration order.
+    fn place_all(
+        &mut self,
+        class_name: &str,
+        mut fields: Vec<(&str, &str, usize)>,
+        opts: &LayoutOptions,
+    ) -> Vec<FieldLayout> {
+        if opts.strategy == LayoutStrategy::HotSpot {
+            // Stable, so fields of the same size stay in declaration order
+            let is_reference = |descriptor: &str| descriptor.starts_with('L') || descriptor.starts_with('[');
+            fields.sort_by_key(|(_, descriptor, size)| (is_reference(descriptor), std::cmp::Reverse(*size)));
+        }
+        fields
+            .into_iter()
+            .map(|(name, descriptor, size)| FieldLayout {
+                class_name: class_name.to_string(),
+                name: name.to_string(),
+                descriptor: descriptor.to_string(),
+                offset: self.place(size),
+                size,
+            })
+            .collect()
+    }
+}
+
+/// Computes the layout of the instance fields of a class and its superclasses, and of the static
+/// fields it declares. Interfaces only have static fields. Fails for array types, whose size
+/// is given by [`LayoutOptions::array_size`], and if a superclass cannot be loaded.
+pub fn object_layout(repo: &ClassRepository, class_name: &str, opts: &LayoutOptions) -> Result<ObjectLayout, ParseError> {
+    if class_name.starts_with('[') {
+        fail!("Array type {} has no field layout", class_name);
+    }
+    let mut chain = repo.superclasses(class_name)?;
+    chain.reverse();
+    chain.push(class_name.to_string());
+    let mut packer = Packer {
+        end: opts.header_size(),
+        gaps: Vec::new(),
+        fill_gaps: opts.strategy == LayoutStrategy::HotSpot,
+    };
+    let mut fields = Vec::new();
+    let mut static_fields = Vec::new();
+    let mut static_packer = Packer {
+        end: 0,
+        gaps: Vec::new(),
+        fill_gaps: packer.fill_gaps,
+    };
+    for superclass in &chain {
+        let class = repo.load(superclass)?;
+        let mut instance = Vec::new();
+        let mut statics = Vec::new();
+        for field in &class.fields {
+            let size = opts.field_size(&field.descriptor).map_err(|e| err!(e, "field {} of class {}", field.name, superclass))?;
+            if field.access_flags.contains(FieldAccessFlags::STATIC) {
+                statics.push((field.name.as_str(), field.descriptor.as_str(), size));
+            } else {
+                instance.push((field.name.as_str(), field.descriptor.as_str(), size));
+            }
+        }
+        fields.extend(packer.place_all(superclass, instance, opts));
+        if superclass == class_name {
+            static_fields = static_packer.place_all(superclass, statics, opts);
+        }
+    }
+    fields.sort_by_key(|f| f.offset);
+    static_fields.sort_by_key(|f| f.offset);
+    Ok(ObjectLayout {
+        class_name: class_name.to_string(),
+        header_size: opts.header_size(),
+        fields,
+        instance_size: align(packer.end, opts.object_alignment),
+        static_fields,
+        static_size: static_packer.end,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resolution::tests::repository;
+
+    fn offsets(layout: &[FieldLayout]) -> Vec<(&str, usize)> {
+        layout.iter().map(|f| (f.name.as_str(), f.offset)).collect()
+    }
+
+    fn classes() -> ClassRepository {
+        repository(&[
+            "java/lang/Object",
+            "A, field a I, field c B, field b J, field s S, field r Ljava/lang/Object;, field static S J, field static T I",
+            "B extends A, field x I, field y Z",
+            "interface abstract I, field public static final X I",
+        ])
+    }
+
+    #[test]
+    fn test_hotspot_layout() {
+        let repo = classes();
+        let opts = LayoutOptions::default();
+        let layout = object_layout(&repo, "A", &opts).unwrap();
+        // The int fills the gap that aligning the long leaves after the header
+        assert_eq!(offsets(&layout.fields), vec![("a", 12), ("b", 16), ("s", 24), ("c", 26), ("r", 28)]);
+        assert_eq!(layout.instance_size, 32);
+        assert_eq!(offsets(&layout.static_fields), vec![("S", 0), ("T", 8)]);
+        assert_eq!(layout.static_size, 12);
+
+        // The boolean fills the gap left before the reference in A
+        let layout = object_layout(&repo, "B", &opts).unwrap();
+        assert_eq!(
+            offsets(&layout.fields),
+            vec![("a", 12), ("b", 16), ("s", 24), ("c", 26), ("y", 27), ("r", 28), ("x", 32)]
+        );
+        assert_eq!(layout.fields[6].class_name, "B");
+        assert_eq!(layout.instance_size, 40);
+        assert!(layout.static_fields.is_empty());
+
+        let layout = object_layout(&repo, "java/lang/Object", &opts).unwrap();
+        assert_eq!(layout.instance_size, 16);
+        let layout = object_layout(&repo, "I", &opts).unwrap();
+        assert_eq!((layout.fields.len(), layout.static_fields.len()), (0, 1));
+    }
+
+    #[test]
+    fn test_declaration_order_layout() {
+        let repo = classes();
+        let mut opts = LayoutOptions::default();
+        opts.strategy(LayoutStrategy::DeclarationOrder);
+        let layout = object_layout(&repo, "B", &opts).unwrap();
+        assert_eq!(
+            offsets(&layout.fields),
+            vec![("a", 12), ("c", 16), ("b", 24), ("s", 32), ("r", 36), ("x", 40), ("y", 44)]
+        );
+        assert_eq!(layout.instance_size, 48);
+
+        opts.compressed_oops(false).compressed_class_pointers(false).object_alignment(16);
+        let layout = object_layout(&repo, "A", &opts).unwrap();
+        assert_eq!(offsets(&layout.fields), vec![("a", 16), ("c", 20), ("b", 24), ("s", 32), ("r", 40)]);
+        assert_eq!(layout.instance_size, 48);
+        assert!(object_layout(&repo, "[I", &opts).is_err());
+    }
+
+    #[test]
+    fn test_array_size() {
+        let mut opts = LayoutOptions::default();
+        assert_eq!(opts.array_size("B", 0).unwrap(), 16);
+        assert_eq!(opts.array_size("I", 3).unwrap(), 32);
+        assert_eq!(opts.array_size("J", 1).unwrap(), 24);
+        assert_eq!(opts.array_size("Ljava/lang/String;", 2).unwrap(), 24);
+        opts.compressed_oops(false).compressed_class_pointers(false);
+        assert_eq!(opts.array_size("Ljava/lang/String;", 2).unwrap(), 40);
+        assert_eq!(opts.array_size("I", 1).unwrap(), 24);
+        assert!(opts.array_size("V", 1).is_err());
+    }
+}
@@ -4,21 +4,46 @@ extern crate cesu8;
 
 #[macro_use]
 pub mod error;
+#[cfg(feature = "serde")]
+#[macro_use]
+mod serde_support;
 
+pub mod assembly;
 pub mod attributes;
 pub mod bytecode;
+pub mod callgraph;
+pub mod classpath;
 pub mod constant_pool;
+pub mod dependencies;
+pub mod disassembler;
+pub mod dispatch;
+pub mod interpreter;
+pub mod kotlin;
+pub mod layout;
 pub mod names;
+pub mod remapper;
+pub mod resolution;
+pub mod scala;
+pub mod static_init;
+pub mod verifier;
+pub mod writer;
 
 use std::borrow::Cow;
+use std::cell::Cell;
 use std::collections::HashSet;
+use std::hash::{Hash, Hasher};
+use std::io::Read;
 use std::ops::Deref;
 use std::rc::Rc;
 
-use crate::attributes::{read_attributes, AttributeData, AttributeInfo};
+use crate::attributes::{
+    read_attributes, validate_attribute_placement, AttributeData, AttributeInfo, AttributeLocation,
+    LazySource,
+};
 use crate::constant_pool::{
-    read_constant_pool, read_cp_classinfo, read_cp_classinfo_opt, read_cp_utf8, ConstantPoolEntry,
-    ConstantPoolIter,
+    read_constant_pool, read_cp_classinfo, read_cp_classinfo_opt, read_cp_utf8,
+    read_scanned_classinfo, read_scanned_classinfo_opt, scan_constant_pool, ConstantPoolEntry,
+    ConstantPoolIter, ConstantPoolReader,
 };
 pub use crate::error::ParseError;
 use crate::names::{is_field_descriptor, is_method_descriptor, is_unqualified_name};
@@ -69,13 +94,20 @@ pub(crate) fn read_u8(bytes: &[u8], ix: &mut usize) -> Result<u64, ParseError> {
     Ok(result)
 }
 
+/// Returns how much space to preallocate for a list that the input says has `count` items, when
+/// the items start at index `ix`. Each item takes up at least one byte, so the count is capped at
+/// the number of bytes left, to keep hostile input from triggering huge allocations.
+pub(crate) fn bounded_capacity(count: impl Into<usize>, bytes: &[u8], ix: usize) -> usize {
+    count.into().min(bytes.len().saturating_sub(ix))
+}
+
 fn read_interfaces(
     bytes: &[u8],
     ix: &mut usize,
-    pool: &[Rc<ConstantPoolEntry>],
+    pool: &ConstantPoolReader,
 ) -> Result<Vec<String>, ParseError> {
     let count = read_u2(bytes, ix)?;
-    let mut interfaces = Vec::with_capacity(count.into());
+    let mut interfaces = Vec::with_capacity(bounded_capacity(count, bytes, *ix));
     for i in 0..count {
         interfaces
             .push(read_cp_classinfo(bytes, ix, pool).map_err(|e| err!(e, "interface {}", i))?);
@@ -125,7 +157,11 @@ bitflags! {
     }
 }
 
-#[derive(Debug)]
+#[cfg(feature = "serde")]
+serde_flags!(FieldAccessFlags, PUBLIC, PRIVATE, PROTECTED, STATIC, FINAL, VOLATILE, TRANSIENT, SYNTHETIC, ENUM);
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct FieldInfo {
     pub access_flags: FieldAccessFlags,
     pub name: String,
@@ -133,17 +169,56 @@ pub struct FieldInfo {
     pub attributes: Vec<AttributeInfo>,
 }
 
+fn validate_field_access_flags(
+    raw_flags: u16,
+    in_interface: bool,
+    major_version: u16,
+) -> Result<(), ParseError> {
+    let unknown_bits = raw_flags & !FieldAccessFlags::all().bits();
+    if unknown_bits != 0 {
+        fail!("Found unknown access flag bits {:#06x}", unknown_bits);
+    }
+    let flags = FieldAccessFlags::from_bits_truncate(raw_flags);
+    let visibility = flags & (FieldAccessFlags::PUBLIC | FieldAccessFlags::PRIVATE | FieldAccessFlags::PROTECTED);
+    if visibility.bits().count_ones() > 1 {
+        fail!("Found more than one of PUBLIC, PRIVATE and PROTECTED in {:?}", flags);
+    }
+    if flags.contains(FieldAccessFlags::FINAL | FieldAccessFlags::VOLATILE) {
+        fail!("Found both FINAL and VOLATILE in {:?}", flags);
+    }
+    if in_interface {
+        let required = FieldAccessFlags::PUBLIC | FieldAccessFlags::STATIC | FieldAccessFlags::FINAL;
+        let mut allowed = required | FieldAccessFlags::SYNTHETIC;
+        if major_version < 49 {
+            // ENUM had no meaning before Java 5, so it is ignored in older class files
+            allowed |= FieldAccessFlags::ENUM;
+        }
+        if !flags.contains(required) || !allowed.contains(flags) {
+            fail!("Found invalid access flags {:?} for interface field", flags);
+        }
+    }
+    Ok(())
+}
+
 fn read_fields(
     bytes: &[u8],
     ix: &mut usize,
-    pool: &[Rc<ConstantPoolEntry>],
+    pool: &ConstantPoolReader,
     opts: &ParseOptions,
+    lazy: Option<&Rc<LazySource>>,
+    in_interface: bool,
+    major_version: u16,
 ) -> Result<Vec<FieldInfo>, ParseError> {
     let count = read_u2(bytes, ix)?;
-    let mut fields = Vec::with_capacity(count.into());
+    let mut fields = Vec::with_capacity(bounded_capacity(count, bytes, *ix));
     let mut unique_ids: HashSet<(String, String)> = HashSet::new();
     for i in 0..count {
-        let access_flags = FieldAccessFlags::from_bits_truncate(read_u2(bytes, ix)?);
+        let raw_flags = read_u2(bytes, ix)?;
+        if opts.validate_access_flags {
+            validate_field_access_flags(raw_flags, in_interface, major_version)
+                .map_err(|e| err!(e, "class field {}", i))?;
+        }
+        let access_flags = FieldAccessFlags::from_bits_truncate(raw_flags);
         let name =
             read_cp_utf8(bytes, ix, pool).map_err(|e| err!(e, "name of class field {}", i))?;
         if !is_unqualified_name(&name, false, false) {
@@ -162,7 +237,7 @@ fn read_fields(
             );
         }
         let attributes =
-            read_attributes(bytes, ix, pool, opts).map_err(|e| err!(e, "class field {}", i))?;
+            read_attributes(bytes, ix, pool, opts, lazy).map_err(|e| err!(e, "class field {}", i))?;
         fields.push(FieldInfo {
             access_flags,
             name,
@@ -190,7 +265,11 @@ bitflags! {
     }
 }
 
-#[derive(Debug)]
+#[cfg(feature = "serde")]
+serde_flags!(MethodAccessFlags, PUBLIC, PRIVATE, PROTECTED, STATIC, FINAL, SYNCHRONIZED, BRIDGE, VARARGS, NATIVE, ABSTRACT, STRICT, SYNTHETIC);
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct MethodInfo {
     pub access_flags: MethodAccessFlags,
     pub name: String,
@@ -198,25 +277,97 @@ pub struct MethodInfo {
     pub attributes: Vec<AttributeInfo>,
 }
 
+fn validate_method_access_flags(
+    raw_flags: u16,
+    name: &str,
+    in_interface: bool,
+    major_version: u16,
+) -> Result<(), ParseError> {
+    let unknown_bits = raw_flags & !MethodAccessFlags::all().bits();
+    if unknown_bits != 0 {
+        fail!("Found unknown access flag bits {:#06x}", unknown_bits);
+    }
+    let flags = MethodAccessFlags::from_bits_truncate(raw_flags);
+    if name == "<clinit>" {
+        // Other flags on class initialization methods are ignored
+        if major_version >= 51 && !flags.contains(MethodAccessFlags::STATIC) {
+            fail!("Found class initialization method without STATIC flag");
+        }
+        return Ok(());
+    }
+    let visibility = flags & (MethodAccessFlags::PUBLIC | MethodAccessFlags::PRIVATE | MethodAccessFlags::PROTECTED);
+    if visibility.bits().count_ones() > 1 {
+        fail!("Found more than one of PUBLIC, PRIVATE and PROTECTED in {:?}", flags);
+    }
+    let strict_has_meaning = (46..=60).contains(&major_version);
+    if in_interface {
+        if major_version < 52 {
+            let required = MethodAccessFlags::PUBLIC | MethodAccessFlags::ABSTRACT;
+            let allowed = required | MethodAccessFlags::BRIDGE | MethodAccessFlags::VARARGS | MethodAccessFlags::SYNTHETIC;
+            if !flags.contains(required) || !allowed.contains(flags) {
+                fail!("Found invalid access flags {:?} for interface method in class file of major version {}", flags, major_version);
+            }
+        } else {
+            if visibility != MethodAccessFlags::PUBLIC && visibility != MethodAccessFlags::PRIVATE {
+                fail!("Found invalid access flags {:?}; interface methods must be exactly one of PUBLIC and PRIVATE", flags);
+            }
+            if flags.intersects(MethodAccessFlags::PROTECTED | MethodAccessFlags::FINAL | MethodAccessFlags::SYNCHRONIZED | MethodAccessFlags::NATIVE) {
+                fail!("Found invalid access flags {:?} for interface method", flags);
+            }
+        }
+    }
+    if flags.contains(MethodAccessFlags::ABSTRACT) {
+        let mut disallowed = MethodAccessFlags::PRIVATE
+            | MethodAccessFlags::STATIC
+            | MethodAccessFlags::FINAL
+            | MethodAccessFlags::SYNCHRONIZED
+            | MethodAccessFlags::NATIVE;
+        if strict_has_meaning {
+            disallowed |= MethodAccessFlags::STRICT;
+        }
+        if flags.intersects(disallowed) {
+            fail!("Found invalid access flags {:?} for abstract method", flags);
+        }
+    }
+    if name == "<init>" {
+        let allowed = MethodAccessFlags::PUBLIC
+            | MethodAccessFlags::PRIVATE
+            | MethodAccessFlags::PROTECTED
+            | MethodAccessFlags::VARARGS
+            | MethodAccessFlags::STRICT
+            | MethodAccessFlags::SYNTHETIC;
+        if !allowed.contains(flags) {
+            fail!("Found invalid access flags {:?} for instance initialization method", flags);
+        }
+    }
+    Ok(())
+}
+
 fn read_methods(
     bytes: &[u8],
     ix: &mut usize,
-    pool: &[Rc<ConstantPoolEntry>],
+    pool: &ConstantPoolReader,
     opts: &ParseOptions,
+    lazy: Option<&Rc<LazySource>>,
     in_interface: bool,
     major_version: u16,
 ) -> Result<Vec<MethodInfo>, ParseError> {
     let count = read_u2(bytes, ix)?;
-    let mut methods = Vec::with_capacity(count.into());
+    let mut methods = Vec::with_capacity(bounded_capacity(count, bytes, *ix));
     let mut unique_ids: HashSet<(String, String)> = HashSet::new();
     for i in 0..count {
-        let access_flags = MethodAccessFlags::from_bits_truncate(read_u2(bytes, ix)?);
+        let raw_flags = read_u2(bytes, ix)?;
+        let access_flags = MethodAccessFlags::from_bits_truncate(raw_flags);
         let name =
             read_cp_utf8(bytes, ix, pool).map_err(|e| err!(e, "name of class method {}", i))?;
         let allow_init = !in_interface;
         if !is_unqualified_name(&name, allow_init, true) {
             fail!("Invalid unqualified name for class method {}", i);
         }
+        if opts.validate_access_flags {
+            validate_method_access_flags(raw_flags, &name, in_interface, major_version)
+                .map_err(|e| err!(e, "class method {}", i))?;
+        }
         let descriptor = read_cp_utf8(bytes, ix, pool)
             .map_err(|e| err!(e, "descriptor of class method {}", i))?;
         if !is_method_descriptor(&descriptor) {
@@ -241,7 +392,7 @@ fn read_methods(
             );
         }
         let attributes =
-            read_attributes(bytes, ix, pool, opts).map_err(|e| err!(e, "class method {}", i))?;
+            read_attributes(bytes, ix, pool, opts, lazy).map_err(|e| err!(e, "class method {}", i))?;
         methods.push(MethodInfo {
             access_flags,
             name,
@@ -266,6 +417,40 @@ bitflags! {
     }
 }
 
+#[cfg(feature = "serde")]
+serde_flags!(ClassAccessFlags, PUBLIC, FINAL, SUPER, INTERFACE, ABSTRACT, SYNTHETIC, ANNOTATION, ENUM, MODULE);
+
+fn validate_class_access_flags(raw_flags: u16, major_version: u16) -> Result<(), ParseError> {
+    let unknown_bits = raw_flags & !ClassAccessFlags::all().bits();
+    if unknown_bits != 0 {
+        fail!("Found unknown class access flag bits {:#06x}", unknown_bits);
+    }
+    let flags = ClassAccessFlags::from_bits_truncate(raw_flags);
+    if flags.contains(ClassAccessFlags::MODULE) {
+        // Module flags are validated as part of regular parsing
+        return Ok(());
+    }
+    if flags.contains(ClassAccessFlags::FINAL | ClassAccessFlags::ABSTRACT) {
+        fail!("Found both FINAL and ABSTRACT in class access flags {:?}", flags);
+    }
+    // ANNOTATION and ENUM were given meaning in Java 5, and older class files may use those bits freely
+    let java5 = major_version >= 49;
+    if flags.contains(ClassAccessFlags::INTERFACE) {
+        if !flags.contains(ClassAccessFlags::ABSTRACT) {
+            fail!("Found INTERFACE without ABSTRACT in class access flags {:?}", flags);
+        }
+        if flags.contains(ClassAccessFlags::FINAL) {
+            fail!("Found both INTERFACE and FINAL in class access flags {:?}", flags);
+        }
+        if java5 && flags.intersects(ClassAccessFlags::SUPER | ClassAccessFlags::ENUM) {
+            fail!("Found SUPER or ENUM with INTERFACE in class access flags {:?}", flags);
+        }
+    } else if java5 && flags.contains(ClassAccessFlags::ANNOTATION) {
+        fail!("Found ANNOTATION without INTERFACE in class access flags {:?}", flags);
+    }
+    Ok(())
+}
+
 fn validate_bootstrap_methods(
     pool: &[Rc<ConstantPoolEntry>],
     attributes: &[AttributeInfo],
@@ -274,8 +459,8 @@ fn validate_bootstrap_methods(
         match cp_entry.deref() {
             ConstantPoolEntry::Dynamic(x, _) | ConstantPoolEntry::InvokeDynamic(x, _) => {
                 let mut found = 0;
-                for attr in attributes {
-                    match &attr.data {
+                for attr in attributes.iter().filter(|attr| attr.name == "BootstrapMethods") {
+                    match attr.decoded()? {
                         AttributeData::BootstrapMethods(methods) => {
                             found += 1;
                             if usize::from(*x) >= methods.len() {
@@ -295,10 +480,41 @@ fn validate_bootstrap_methods(
     Ok(())
 }
 
-#[derive(Debug)]
+fn validate_member_attributes(
+    fields: &[FieldInfo],
+    methods: &[MethodInfo],
+    major_version: u16,
+    check_code: bool,
+) -> Result<(), ParseError> {
+    for (i, field) in fields.iter().enumerate() {
+        validate_attribute_placement(&field.attributes, AttributeLocation::Field, major_version)
+            .map_err(|e| err!(e, "class field {}", i))?;
+    }
+    for (i, method) in methods.iter().enumerate() {
+        validate_attribute_placement(&method.attributes, AttributeLocation::Method, major_version)
+            .map_err(|e| err!(e, "class method {}", i))?;
+        let has_code = method.attributes.iter().any(|attr| attr.name == "Code");
+        let needs_code = !method
+            .access_flags
+            .intersects(MethodAccessFlags::ABSTRACT | MethodAccessFlags::NATIVE);
+        if check_code && has_code != needs_code {
+            fail!(
+                "Found {} Code attribute for class method {} with access flags {:?}",
+                if has_code { "unexpected" } else { "no" },
+                i,
+                method.access_flags
+            );
+        }
+    }
+    Ok(())
+}
+
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ClassFile {
     pub major_version: u16,
     pub minor_version: u16,
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_support::constant_pool"))]
     constant_pool: Vec<Rc<ConstantPoolEntry>>,
     pub access_flags: ClassAccessFlags,
     pub this_class: String,
@@ -310,21 +526,125 @@ pub struct ClassFile {
 }
 
 impl ClassFile {
+    fn key(&self) -> impl PartialEq + Hash + '_ {
+        (
+            self.major_version,
+            self.minor_version,
+            self.access_flags,
+            &self.this_class,
+            &self.super_class,
+            &self.interfaces,
+            &self.fields,
+            &self.methods,
+            &self.attributes,
+        )
+    }
+
     #[must_use]
     pub fn constantpool_iter(&self) -> ConstantPoolIter {
         ConstantPoolIter::new(&self.constant_pool)
     }
 }
 
-#[derive(Debug)]
+// Classes are compared by their fields other than the constant pool. Raw data that refers
+// to the pool by index, such as the code of a method, is still compared as it is.
+impl PartialEq for ClassFile {
+    fn eq(&self, other: &Self) -> bool {
+        self.key() == other.key()
+    }
+}
+
+impl Eq for ClassFile {}
+
+impl Hash for ClassFile {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.key().hash(state);
+    }
+}
+
+/// The version, access flags and class names from the start of a class file, as returned by
+/// parse_class_header.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ClassHeader {
+    pub major_version: u16,
+    pub minor_version: u16,
+    pub access_flags: ClassAccessFlags,
+    pub this_class: String,
+    pub super_class: Option<String>,
+    pub interfaces: Vec<String>,
+}
+
+/// Selects attributes by name, for ParseOptions::attribute_filter.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum AttributeFilter {
+    /// Selects all attributes.
+    All,
+    /// Selects only the attributes with the given names.
+    Allow(Vec<String>),
+    /// Selects all attributes except those with the given names.
+    Deny(Vec<String>),
+}
+
+impl AttributeFilter {
+    pub fn allow(names: &[&str]) -> Self {
+        AttributeFilter::Allow(names.iter().map(|name| name.to_string()).collect())
+    }
+
+    pub fn deny(names: &[&str]) -> Self {
+        AttributeFilter::Deny(names.iter().map(|name| name.to_string()).collect())
+    }
+
+    /// A filter that skips the attributes holding debugging information, which are not
+    /// needed to load or run the class: LineNumberTable, LocalVariableTable,
+    /// LocalVariableTypeTable and StackMapTable.
+    pub fn without_debug_info() -> Self {
+        Self::deny(&[
+            "LineNumberTable",
+            "LocalVariableTable",
+            "LocalVariableTypeTable",
+            "StackMapTable",
+        ])
+    }
+
+    pub fn includes(&self, name: &str) -> bool {
+        match self {
+            AttributeFilter::All => true,
+            AttributeFilter::Allow(names) => names.iter().any(|n| n == name),
+            AttributeFilter::Deny(names) => !names.iter().any(|n| n == name),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
 pub struct ParseOptions {
     parse_bytecode: bool,
+    check_static_constraints: bool,
+    validate_access_flags: bool,
+    validate_attribute_placement: bool,
+    lazy_attributes: bool,
+    attribute_filter: AttributeFilter,
+    drop_filtered_attributes: bool,
+    max_class_size: usize,
+    max_code_length: usize,
+    max_nesting_depth: usize,
+    max_allocation: usize,
 }
 
 impl ParseOptions {
     pub fn default() -> Self {
         Self {
             parse_bytecode: true,
+            check_static_constraints: false,
+            validate_access_flags: false,
+            validate_attribute_placement: false,
+            lazy_attributes: false,
+            attribute_filter: AttributeFilter::All,
+            drop_filtered_attributes: false,
+            max_class_size: usize::MAX,
+            max_code_length: 65535,
+            max_nesting_depth: 32,
+            max_allocation: usize::MAX,
         }
     }
 
@@ -338,6 +658,109 @@ impl ParseOptions {
         self.parse_bytecode = parse;
         self
     }
+
+    /// Turns on or off checking of the static constraints on the Code attributes of methods
+    /// described in section 4.9.1 of the JVM spec, beyond what is needed to parse the bytecode.
+    /// This includes checking local variable indices against max_locals, exception table ranges
+    /// against instruction boundaries, and the constant pool entries referenced by individual
+    /// instructions. The bytecode is parsed for the check even if bytecode parsing is disabled.
+    /// Checking is disabled by default.
+    pub fn check_static_constraints(&mut self, check: bool) -> &mut ParseOptions {
+        self.check_static_constraints = check;
+        self
+    }
+
+    /// Turns on or off validation of the access flags of the class and its fields and methods
+    /// against the rules in sections 4.1, 4.5 and 4.6 of the JVM spec, such as interfaces having
+    /// to be abstract or abstract methods not being allowed to be private. The rules applied
+    /// depend on the class file version. Bits that have no assigned meaning, which are otherwise
+    /// silently dropped, are also reported as errors. Validation is disabled by default.
+    pub fn validate_access_flags(&mut self, validate: bool) -> &mut ParseOptions {
+        self.validate_access_flags = validate;
+        self
+    }
+
+    /// Turns on or off validation of where attributes appear, per section 4.7 of the JVM spec.
+    /// If enabled, parsing fails if a predefined attribute is found in a location it is not
+    /// defined for (such as a Code attribute on a field), in a class file older than the version
+    /// that introduced it, or more than once where at most one is allowed. Methods are also
+    /// checked to have a Code attribute exactly when they are neither abstract nor native.
+    /// Attributes not defined by the spec are never reported. Validation is disabled by default.
+    pub fn validate_attribute_placement(&mut self, validate: bool) -> &mut ParseOptions {
+        self.validate_attribute_placement = validate;
+        self
+    }
+
+    /// Turns on or off lazy decoding of attributes. If enabled, the attributes of the class and
+    /// its fields and methods are only located during parsing, and each one is stored as an
+    /// AttributeData::Lazy holding onto a copy of the class file bytes. Its contents are decoded
+    /// the first time they are accessed through AttributeInfo::decoded, which makes parsing much
+    /// cheaper when only some of the attributes are needed. Any errors in the contents, including
+    /// those found by check_static_constraints and by validate_attribute_placement for attributes
+    /// nested in Code and Record attributes, are then reported by AttributeInfo::decoded rather
+    /// than by parsing. Lazy decoding is disabled by default.
+    pub fn lazy_attributes(&mut self, lazy: bool) -> &mut ParseOptions {
+        self.lazy_attributes = lazy;
+        self
+    }
+
+    /// Sets which attributes are decoded, by name. This applies to the attributes of the class,
+    /// its fields and methods, and those nested in Code and Record attributes. Attributes that
+    /// are not selected by the filter are not decoded at all; they are kept as AttributeData::Other
+    /// holding their raw bytes, or left out entirely if drop_filtered_attributes is enabled.
+    /// Filtering out the BootstrapMethods attribute skips checking that it matches the constant
+    /// pool, and filtering out the Code attribute skips checking which methods should have one.
+    /// All attributes are decoded by default.
+    pub fn attribute_filter(&mut self, filter: AttributeFilter) -> &mut ParseOptions {
+        self.attribute_filter = filter;
+        self
+    }
+
+    /// Turns on or off dropping the attributes not selected by attribute_filter, rather than
+    /// keeping their raw bytes. Dropped attributes are also not seen by validate_attribute_placement,
+    /// and are missing from the class if it is written back out. Dropping is disabled by default.
+    pub fn drop_filtered_attributes(&mut self, drop: bool) -> &mut ParseOptions {
+        self.drop_filtered_attributes = drop;
+        self
+    }
+
+    /// Sets the maximum size in bytes of the class files to parse. Larger class files are rejected
+    /// before any of their contents are parsed, and when parsing from a reader, nothing past the
+    /// limit is read. Setting a limit guards against running out of memory on hostile input read
+    /// from a stream, since the size of a class file is not known before it has been read in full.
+    /// There is no limit by default.
+    pub fn max_class_size(&mut self, max: usize) -> &mut ParseOptions {
+        self.max_class_size = max;
+        self
+    }
+
+    /// Sets the maximum length in bytes of the code of a method. Section 4.7.3 of the JVM spec
+    /// requires the code to be shorter than 65536 bytes, which is the default limit.
+    pub fn max_code_length(&mut self, max: usize) -> &mut ParseOptions {
+        self.max_code_length = max;
+        self
+    }
+
+    /// Sets how deeply annotations and attributes may be nested, counting each annotation or
+    /// array nested in an annotation and each Code or Record attribute containing further
+    /// attributes as one level. Deeper nesting is reported as an error rather than risking
+    /// overflowing the stack. Class files produced by compilers have at most a few levels of
+    /// nesting; the default limit is 32.
+    pub fn max_nesting_depth(&mut self, max: usize) -> &mut ParseOptions {
+        self.max_nesting_depth = max;
+        self
+    }
+
+    /// Sets a limit on the total length of the strings copied out of the constant pool while
+    /// parsing. Each reference to a string in the constant pool results in a new copy of the
+    /// string, so a small class file can expand into a very large parsed class, while everything
+    /// else parsing allocates is proportional to the size of the class file. When attributes are
+    /// decoded lazily, the limit applies to the parsing and all the decoding combined. There is
+    /// no limit by default.
+    pub fn max_allocation(&mut self, max: usize) -> &mut ParseOptions {
+        self.max_allocation = max;
+        self
+    }
 }
 
 pub fn parse_class(raw_bytes: &[u8]) -> Result<ClassFile, ParseError> {
@@ -348,6 +771,12 @@ pub fn parse_class_with_options(
     raw_bytes: &[u8],
     opts: &ParseOptions,
 ) -> Result<ClassFile, ParseError> {
+    if raw_bytes.len() > opts.max_class_size {
+        fail!(
+            "Class file is larger than the maximum size of {} bytes",
+            opts.max_class_size
+        );
+    }
     let mut ix = 0;
     if read_u4(&raw_bytes, &mut ix)? != 0xCAFE_BABE {
         fail!("Unexpected magic header");
@@ -356,7 +785,11 @@ pub fn parse_class_with_options(
     let major_version = read_u2(&raw_bytes.to_vec(), &mut ix)?;
     let constant_pool = read_constant_pool(&raw_bytes.to_vec(), &mut ix, major_version)?;
 
-    let access_flags = ClassAccessFlags::from_bits_truncate(read_u2(&raw_bytes.to_vec(), &mut ix)?);
+    let raw_access_flags = read_u2(&raw_bytes.to_vec(), &mut ix)?;
+    if opts.validate_access_flags {
+        validate_class_access_flags(raw_access_flags, major_version)?;
+    }
+    let access_flags = ClassAccessFlags::from_bits_truncate(raw_access_flags);
     let is_module = access_flags.contains(ClassAccessFlags::MODULE);
     if is_module {
         if major_version < 53 {
@@ -372,22 +805,51 @@ pub fn parse_class_with_options(
             );
         }
     }
-    let this_class = read_cp_classinfo(&raw_bytes, &mut ix, &constant_pool)
+    let pool = ConstantPoolReader::new(
+        &constant_pool,
+        opts.max_allocation,
+        0,
+        opts.max_nesting_depth,
+    );
+    let this_class = read_cp_classinfo(&raw_bytes, &mut ix, &pool)
         .map_err(|e| err!(e, "this_class"))?;
-    let super_class = read_cp_classinfo_opt(&raw_bytes, &mut ix, &constant_pool)
+    let super_class = read_cp_classinfo_opt(&raw_bytes, &mut ix, &pool)
         .map_err(|e| err!(e, "super_class"))?;
-    let interfaces = read_interfaces(&raw_bytes, &mut ix, &constant_pool)?;
-    let fields = read_fields(&raw_bytes, &mut ix, &constant_pool, opts)?;
+    let interfaces = read_interfaces(&raw_bytes, &mut ix, &pool)?;
+    let lazy = if opts.lazy_attributes {
+        Some(Rc::new(LazySource {
+            bytes: raw_bytes.into(),
+            pool: constant_pool.as_slice().into(),
+            opts: opts.clone(),
+            major_version,
+            budget: Cell::new(0),
+        }))
+    } else {
+        None
+    };
+    let fields = read_fields(
+        &raw_bytes,
+        &mut ix,
+        &pool,
+        opts,
+        lazy.as_ref(),
+        access_flags.contains(ClassAccessFlags::INTERFACE),
+        major_version,
+    )?;
     let methods = read_methods(
         &raw_bytes,
         &mut ix,
-        &constant_pool,
+        &pool,
         opts,
+        lazy.as_ref(),
         access_flags.contains(ClassAccessFlags::INTERFACE),
         major_version,
     )?;
-    let attributes = read_attributes(&raw_bytes, &mut ix, &constant_pool, opts)
+    let attributes = read_attributes(&raw_bytes, &mut ix, &pool, opts, lazy.as_ref())
         .map_err(|e| err!(e, "class"))?;
+    if let Some(source) = &lazy {
+        source.budget.set(pool.remaining_budget());
+    }
     // Section 4.8 "Format Checking" says the class file must not have extra bytes at the end
     if ix != raw_bytes.len() {
         fail!("Extra bytes found at index {} after reading class file", ix);
@@ -414,7 +876,16 @@ pub fn parse_class_with_options(
         }
     }
 
-    validate_bootstrap_methods(&constant_pool, &attributes)?;
+    if opts.attribute_filter.includes("BootstrapMethods") {
+        validate_bootstrap_methods(&constant_pool, &attributes)?;
+    }
+
+    if opts.validate_attribute_placement {
+        let check_code = opts.attribute_filter.includes("Code");
+        validate_member_attributes(&fields, &methods, major_version, check_code)?;
+        validate_attribute_placement(&attributes, AttributeLocation::ClassFile, major_version)
+            .map_err(|e| err!(e, "class"))?;
+    }
 
     let class_file = ClassFile {
         major_version,
@@ -430,3 +901,349 @@ pub fn parse_class_with_options(
     };
     Ok(class_file)
 }
+
+pub fn parse_class_from_reader<R: Read>(reader: R) -> Result<ClassFile, ParseError> {
+    parse_class_from_reader_with_options(reader, &ParseOptions::default())
+}
+
+/// Reads a class file from a reader, such as a file or an entry of a zip archive, and parses it.
+/// The reader is read to its end, but the magic header is checked first so that reading stops early
+/// on input that is not a class file, and reading stops as soon as the input is found to exceed
+/// ParseOptions::max_class_size. Input that ends before the class file does is reported the same
+/// way as by parse_class, and errors from the reader are reported as parse errors.
+pub fn parse_class_from_reader_with_options<R: Read>(
+    mut reader: R,
+    opts: &ParseOptions,
+) -> Result<ClassFile, ParseError> {
+    let mut raw_bytes = Vec::new();
+    read_limited(&mut reader, &mut raw_bytes, 4)?;
+    if raw_bytes.len() == 4 && raw_bytes != [0xCA, 0xFE, 0xBA, 0xBE] {
+        fail!("Unexpected magic header");
+    }
+    // Read one byte more than the limit, if there is one, to tell whether the input exceeds it
+    let limit = (opts.max_class_size as u64).saturating_add(1);
+    read_limited(&mut reader, &mut raw_bytes, limit.saturating_sub(4))?;
+    parse_class_with_options(&raw_bytes, opts)
+}
+
+fn read_limited<R: Read>(reader: &mut R, buf: &mut Vec<u8>, limit: u64) -> Result<(), ParseError> {
+    match reader.take(limit).read_to_end(buf) {
+        Ok(_) => Ok(()),
+        Err(e) => fail!("Error reading class file at index {}: {}", buf.len(), e),
+    }
+}
+
+/// Parses only the part of a class file up to and including its list of interfaces, which is
+/// much faster than parsing the whole class when only its name and supertypes are needed. The
+/// constant pool is scanned to find its entries, but only the entries naming the classes are
+/// decoded and validated, and the fields, methods and attributes are not read at all. As a
+/// result, a class file that parse_class rejects may still have its header parsed successfully.
+pub fn parse_class_header(raw_bytes: &[u8]) -> Result<ClassHeader, ParseError> {
+    let mut ix = 0;
+    if read_u4(raw_bytes, &mut ix)? != 0xCAFE_BABE {
+        fail!("Unexpected magic header");
+    }
+    let minor_version = read_u2(raw_bytes, &mut ix)?;
+    let major_version = read_u2(raw_bytes, &mut ix)?;
+    let offsets = scan_constant_pool(raw_bytes, &mut ix, major_version)?;
+    let access_flags = ClassAccessFlags::from_bits_truncate(read_u2(raw_bytes, &mut ix)?);
+    let this_class = read_scanned_classinfo(raw_bytes, &mut ix, &offsets)
+        .map_err(|e| err!(e, "this_class"))?;
+    let super_class = read_scanned_classinfo_opt(raw_bytes, &mut ix, &offsets)
+        .map_err(|e| err!(e, "super_class"))?;
+    let count = read_u2(raw_bytes, &mut ix)?;
+    let mut interfaces = Vec::with_capacity(bounded_capacity(count, raw_bytes, ix));
+    for i in 0..count {
+        interfaces.push(
+            read_scanned_classinfo(raw_bytes, &mut ix, &offsets)
+                .map_err(|e| err!(e, "interface {}", i))?,
+        );
+    }
+    Ok(ClassHeader {
+        major_version,
+        minor_version,
+        access_flags,
+        this_class,
+        super_class,
+        interfaces,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_class_access_flags() {
+        assert!(validate_class_access_flags(0x0021, 52).is_ok());
+        assert!(validate_class_access_flags(0x0601, 52).is_ok());
+        assert!(validate_class_access_flags(0x0201, 52).is_err());
+        assert!(validate_class_access_flags(0x0411, 52).is_err());
+        assert!(validate_class_access_flags(0x0621, 52).is_err());
+        assert!(validate_class_access_flags(0x0621, 48).is_ok());
+        assert!(validate_class_access_flags(0x2001, 52).is_err());
+        assert!(validate_class_access_flags(0x0101, 52).is_err());
+    }
+
+    #[test]
+    fn test_field_access_flags() {
+        assert!(validate_field_access_flags(0x0019, true, 52).is_ok());
+        assert!(validate_field_access_flags(0x0009, true, 52).is_err());
+        assert!(validate_field_access_flags(0x0003, false, 52).is_err());
+        assert!(validate_field_access_flags(0x0050, false, 52).is_err());
+    }
+
+    #[test]
+    fn test_method_access_flags() {
+        assert!(validate_method_access_flags(0x0401, "run", true, 51).is_ok());
+        assert!(validate_method_access_flags(0x0009, "run", true, 51).is_err());
+        assert!(validate_method_access_flags(0x0009, "run", true, 52).is_ok());
+        assert!(validate_method_access_flags(0x0402, "run", false, 52).is_err());
+        assert!(validate_method_access_flags(0x0c01, "run", false, 52).is_err());
+        assert!(validate_method_access_flags(0x0c01, "run", false, 61).is_ok());
+        assert!(validate_method_access_flags(0x0009, "<init>", false, 52).is_err());
+        assert!(validate_method_access_flags(0x0000, "<clinit>", false, 51).is_err());
+        assert!(validate_method_access_flags(0x0000, "<clinit>", false, 50).is_ok());
+    }
+
+    #[test]
+    fn test_equality() {
+        use crate::constant_pool::LiteralConstant;
+
+        assert_eq!(LiteralConstant::Float(f32::NAN), LiteralConstant::Float(f32::NAN));
+        assert_ne!(LiteralConstant::Double(0.0), LiteralConstant::Double(-0.0));
+        assert_ne!(LiteralConstant::Integer(1), LiteralConstant::Long(1));
+        let constants: HashSet<LiteralConstant> =
+            vec![LiteralConstant::Double(f64::NAN), LiteralConstant::Double(f64::NAN)].into_iter().collect();
+        assert_eq!(constants.len(), 1);
+
+        let text = ".version 52 0\n.class Test\n.field static x I\n.constantvalue int 1\n.end field\n.end class\n";
+        let class = crate::assembly::parse_assembly(text).unwrap();
+        let mut other = class.clone();
+        assert_eq!(class, other);
+        other.fields[0].attributes[0].data = AttributeData::ConstantValue(LiteralConstant::Integer(2));
+        assert_ne!(class, other);
+    }
+
+    #[test]
+    fn test_lazy_attributes() {
+        let text = "\
+.version 52 0
+.class public super Test
+.super java/lang/Object
+.method public static ok ()I
+    .code stack 1 locals 1
+        iload_0
+        ireturn
+    .end code
+.end method
+.method public static bad ()V
+    .code stack 1 locals 1
+        iload 5
+        pop
+        return
+    .end code
+.end method
+.end class
+";
+        let bytes = crate::assembly::assemble(text).unwrap();
+        let mut opts = ParseOptions::default();
+        opts.check_static_constraints(true);
+        assert!(parse_class_with_options(&bytes, &opts).is_err());
+
+        opts.lazy_attributes(true);
+        let class = parse_class_with_options(&bytes, &opts).unwrap();
+        let lazy = match &class.methods[0].attributes[0].data {
+            AttributeData::Lazy(lazy) => lazy,
+            data => panic!("expected lazy attribute, found {:?}", data),
+        };
+        assert!(!lazy.is_decoded());
+        match class.methods[0].attributes[0].decoded().unwrap() {
+            AttributeData::Code(code) => assert_eq!(code.code, vec![0x1a, 0xac]),
+            data => panic!("expected Code attribute, found {:?}", data),
+        }
+        assert!(lazy.is_decoded());
+        assert!(class.methods[1].attributes[0].decoded().is_err());
+
+        opts.check_static_constraints(false);
+        let class = parse_class_with_options(&bytes, &opts).unwrap();
+        assert_eq!(class, parse_class(&bytes).unwrap());
+    }
+
+    #[test]
+    fn test_attribute_filter() {
+        let text = "\
+.version 52 0
+.class public super Test
+.super java/lang/Object
+.sourcefile \"Test.java\"
+.method public static f ()V
+    .code stack 0 locals 0
+        return
+        .linenumbertable
+            0 1
+        .end linenumbertable
+    .end code
+.end method
+.end class
+";
+        let bytes = crate::assembly::assemble(text).unwrap();
+        let mut opts = ParseOptions::default();
+        opts.attribute_filter(AttributeFilter::without_debug_info()).validate_attribute_placement(true);
+        let class = parse_class_with_options(&bytes, &opts).unwrap();
+        assert!(matches!(class.attributes[0].data, AttributeData::SourceFile(_)));
+        let code = match &class.methods[0].attributes[0].data {
+            AttributeData::Code(code) => code,
+            data => panic!("expected Code attribute, found {:?}", data),
+        };
+        assert_eq!(code.attributes[0].name, "LineNumberTable");
+        assert_eq!(code.attributes[0].data, AttributeData::Other(vec![0, 1, 0, 0, 0, 1]));
+
+        opts.attribute_filter(AttributeFilter::allow(&["SourceFile"])).drop_filtered_attributes(true);
+        let class = parse_class_with_options(&bytes, &opts).unwrap();
+        assert_eq!(class.attributes.len(), 1);
+        assert!(class.methods[0].attributes.is_empty());
+    }
+
+    #[test]
+    fn test_parse_class_header() {
+        let text = "\
+.version 52 0
+.class public super Test
+.super java/lang/Object
+.implements java/lang/Runnable
+.method public run ()V
+    .code stack 0 locals 1
+        return
+    .end code
+.end method
+.end class
+";
+        let bytes = crate::assembly::assemble(text).unwrap();
+        let header = parse_class_header(&bytes).unwrap();
+        assert_eq!(header.major_version, 52);
+        assert_eq!(header.access_flags, ClassAccessFlags::PUBLIC | ClassAccessFlags::SUPER);
+        assert_eq!(header.this_class, "Test");
+        assert_eq!(header.super_class.as_deref(), Some("java/lang/Object"));
+        assert_eq!(header.interfaces, vec!["java/lang/Runnable".to_string()]);
+
+        // nothing after the interfaces is read
+        let truncated = &bytes[..bytes.len() - 1];
+        assert!(parse_class(truncated).is_err());
+        assert_eq!(parse_class_header(truncated).unwrap(), header);
+    }
+
+    #[test]
+    fn test_parse_class_from_reader() {
+        let text = ".version 52 0\n.class Test\n.super java/lang/Object\n.end class\n";
+        let bytes = crate::assembly::assemble(text).unwrap();
+        assert_eq!(parse_class_from_reader(&bytes[..]).unwrap(), parse_class(&bytes).unwrap());
+
+        let err = parse_class_from_reader(&bytes[..bytes.len() - 1]).unwrap_err();
+        assert!(err.to_string().starts_with("Unexpected end of stream"));
+        let err = parse_class_from_reader(std::io::repeat(0)).unwrap_err();
+        assert_eq!(err.to_string(), "Unexpected magic header");
+
+        let mut opts = ParseOptions::default();
+        opts.max_class_size(bytes.len());
+        assert!(parse_class_from_reader_with_options(&bytes[..], &opts).is_ok());
+        // stops reading at the limit rather than at the end of the input
+        let endless = (&bytes[..]).chain(std::io::repeat(0));
+        let err = parse_class_from_reader_with_options(endless, &opts).unwrap_err();
+        assert!(err.to_string().starts_with("Class file is larger than the maximum size"));
+    }
+
+    // Builds a class with a single method whose Code attribute has the given contents, and a
+    // single class attribute named by constant pool entry 3.
+    fn hostile_class(attribute_name: &str, attribute: &[u8], code: &[u8]) -> Vec<u8> {
+        let mut bytes = vec![0xCA, 0xFE, 0xBA, 0xBE, 0, 0, 0, 52, 0, 8];
+        bytes.extend_from_slice(&[1, 0, 4]);
+        bytes.extend_from_slice(b"Test");
+        bytes.extend_from_slice(&[7, 0, 1]);
+        for text in &[attribute_name, "LA;", "Code", "m", "()V"] {
+            bytes.extend_from_slice(&[1, 0, text.len() as u8]);
+            bytes.extend_from_slice(text.as_bytes());
+        }
+        // access flags, this_class, super_class, interfaces, fields, one method with a Code attribute
+        bytes.extend_from_slice(&[0, 0x21, 0, 2, 0, 0, 0, 0, 0, 0, 0, 1, 0, 9, 0, 6, 0, 7, 0, 1, 0, 5]);
+        bytes.extend_from_slice(&(code.len() as u32).to_be_bytes());
+        bytes.extend_from_slice(code);
+        bytes.extend_from_slice(&[0, 1, 0, 3]);
+        bytes.extend_from_slice(&(attribute.len() as u32).to_be_bytes());
+        bytes.extend_from_slice(attribute);
+        bytes
+    }
+
+    #[test]
+    fn test_hostile_input() {
+        let code = [0, 0, 0, 0, 0, 0, 0, 1, 0xb1, 0, 0, 0, 0];
+        let bytes = hostile_class("Foo", &[], &code);
+        let class = parse_class(&bytes).unwrap();
+        assert_eq!(class.constantpool_iter().count(), 1);
+        let mut opts = ParseOptions::default();
+        opts.max_code_length(0);
+        assert!(parse_class_with_options(&bytes, &opts).is_err());
+
+        // an annotation whose value is an array nested 100 deep
+        let mut annotations = vec![0, 1, 0, 4, 0, 1, 0, 6];
+        for _ in 0..100 {
+            annotations.extend_from_slice(&[b'[', 0, 1]);
+        }
+        annotations.extend_from_slice(&[b'[', 0, 0]);
+        let bytes = hostile_class("RuntimeVisibleAnnotations", &annotations, &code);
+        let err = parse_class(&bytes).unwrap_err();
+        assert!(err.to_string().starts_with("Exceeded the maximum nesting depth of 32"));
+        opts = ParseOptions::default();
+        opts.max_nesting_depth(101);
+        assert!(parse_class_with_options(&bytes, &opts).is_ok());
+
+        // an annotation with 1000 copies of a string constant
+        let mut annotations = vec![0, 1, 0, 4, 0, 1, 0, 6, b'[', 0x03, 0xe8];
+        for _ in 0..1000 {
+            annotations.extend_from_slice(&[b's', 0, 4]);
+        }
+        let bytes = hostile_class("RuntimeVisibleAnnotations", &annotations, &code);
+        opts = ParseOptions::default();
+        assert!(parse_class_with_options(&bytes, &opts).is_ok());
+        opts.max_allocation(3000);
+        let err = parse_class_with_options(&bytes, &opts).unwrap_err();
+        assert!(err.to_string().starts_with("Exceeded the maximum allocation"));
+
+        // a tableswitch covering all ints, with no jump offsets
+        let mut code = vec![0, 0, 0, 0, 0, 0, 0, 16, 0xaa, 0, 0, 0, 0, 0, 0, 0];
+        code.extend_from_slice(&[0x80, 0, 0, 0, 0x7f, 0xff, 0xff, 0xff, 0, 0, 0, 0]);
+        let bytes = hostile_class("Foo", &[], &code);
+        assert!(parse_class(&bytes).is_err());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde_round_trip() {
+        let text = "\
+.version 52 0
+.class public super Test
+.super java/lang/Object
+.field static final NAN F
+    .constantvalue floatbits 0x7fc00001
+.end field
+.method public static main ([Ljava/lang/String;)V
+    .code stack 2 locals 1
+        getstatic java/lang/System out Ljava/io/PrintStream;
+        ldc stringbytes eda080
+        invokevirtual java/io/PrintStream println (Ljava/lang/String;)V
+        return
+    .end code
+.end method
+.end class
+";
+        let class = crate::assembly::parse_assembly(text).unwrap();
+        let json = serde_json::to_string(&class).unwrap();
+        assert!(json.contains(r#""access_flags":["PUBLIC","SUPER"]"#));
+        assert!(json.contains(r#"{"StringBytes":[237,160,128]}"#));
+        assert!(json.contains(r#"{"Float":"0x7fc00001"}"#));
+        let deserialized: ClassFile = serde_json::from_str(&json).unwrap();
+        let bytes = crate::writer::write_class(&deserialized).unwrap();
+        let reparsed = parse_class(&bytes).unwrap();
+        assert_eq!(crate::assembly::print_assembly(&reparsed).unwrap(), crate::assembly::print_assembly(&class).unwrap());
+    }
+}
@@ -1,5 +1,8 @@
+use std::ops::Range;
 use std::str::Chars;
 
+use crate::ParseError;
+
 pub(crate) fn is_binary_name(name: &str) -> bool {
     for segment in name.split('/') {
         if !is_unqualified_name(segment, false, false) {
@@ -139,6 +142,192 @@ pub(crate) fn is_return_descriptor(name: &str) -> bool {
     }
 }
 
+/// Splits a method descriptor into its parameter descriptors and its return descriptor.
+/// Returns None if the descriptor is not a valid method descriptor.
+pub(crate) fn split_method_descriptor(descriptor: &str) -> Option<(Vec<&str>, &str)> {
+    if !is_method_descriptor(descriptor) {
+        return None;
+    }
+    let end = descriptor.find(')')?;
+    let mut params = Vec::new();
+    let mut start = 1;
+    while start < end {
+        let mut chars = descriptor[start..end].chars();
+        match consume_field_descriptor(&mut chars) {
+            (true, _) => {
+                let len = (end - start) - chars.as_str().len();
+                params.push(&descriptor[start..start + len]);
+                start += len;
+            }
+            (false, _) => return None,
+        }
+    }
+    Some((params, &descriptor[end + 1..]))
+}
+
+/// A class named in a descriptor or generic signature. The range is the part of the signature
+/// that names the class, or its outermost class for an inner class of a parameterized type,
+/// such as `Lp/Outer<TT;>.Inner;`, whose name is `p/Outer$Inner`.
+pub(crate) struct SignatureClass {
+    pub(crate) name: String,
+    pub(crate) range: Range<usize>,
+}
+
+/// Returns the classes named in a field or method descriptor, or in a generic signature of a
+/// class, member or local variable, in the order they appear.
+pub(crate) fn signature_classes(signature: &str) -> Result<Vec<SignatureClass>, ParseError> {
+    let mut parser = SignatureParser {
+        bytes: signature.as_bytes(),
+        ix: 0,
+        classes: Vec::new(),
+    };
+    parser.parse().map_err(|e| err!(e, "signature {}", signature))?;
+    // Classes are found when their type arguments have been parsed, after the classes in them
+    parser.classes.sort_by_key(|class| class.range.start);
+    Ok(parser.classes)
+}
+
+// A descriptor is also a valid signature, so both are parsed the same way
+struct SignatureParser<'a> {
+    bytes: &'a [u8],
+    ix: usize,
+    classes: Vec<SignatureClass>,
+}
+
+impl<'a> SignatureParser<'a> {
+    fn peek(&self) -> Result<u8, ParseError> {
+        match self.bytes.get(self.ix) {
+            Some(b) => Ok(*b),
+            None => fail!("Unexpected end of signature"),
+        }
+    }
+
+    fn expect(&mut self, b: u8) -> Result<(), ParseError> {
+        if self.peek()? != b {
+            fail!("Expected '{}' at offset {}", b as char, self.ix);
+        }
+        self.ix += 1;
+        Ok(())
+    }
+
+    fn identifier(&mut self) -> Result<&'a str, ParseError> {
+        let start = self.ix;
+        while !matches!(self.peek()?, b';' | b'<' | b'>' | b'.' | b':') {
+            self.ix += 1;
+        }
+        if start == self.ix {
+            fail!("Expected identifier at offset {}", self.ix);
+        }
+        std::str::from_utf8(&self.bytes[start..self.ix]).map_err(|_| err!("Invalid UTF-8 in signature"))
+    }
+
+    fn parse(&mut self) -> Result<(), ParseError> {
+        if self.peek()? == b'<' {
+            self.type_parameters()?;
+        }
+        if self.peek()? == b'(' {
+            self.ix += 1;
+            while self.peek()? != b')' {
+                self.java_type()?;
+            }
+            self.ix += 1;
+            self.java_type()?;
+            while self.ix < self.bytes.len() {
+                self.expect(b'^')?;
+                self.reference_type()?;
+            }
+        } else {
+            // A field or local variable type, or a class's superclass and interfaces
+            while self.ix < self.bytes.len() {
+                self.java_type()?;
+            }
+        }
+        Ok(())
+    }
+
+    fn type_parameters(&mut self) -> Result<(), ParseError> {
+        self.expect(b'<')?;
+        while self.peek()? != b'>' {
+            self.identifier()?;
+            self.expect(b':')?;
+            if !matches!(self.peek()?, b':' | b'>') {
+                self.reference_type()?;
+            }
+            while self.peek()? == b':' {
+                self.ix += 1;
+                self.reference_type()?;
+            }
+        }
+        self.ix += 1;
+        Ok(())
+    }
+
+    fn java_type(&mut self) -> Result<(), ParseError> {
+        match self.peek()? {
+            b'B' | b'C' | b'D' | b'F' | b'I' | b'J' | b'S' | b'Z' | b'V' => {
+                self.ix += 1;
+                Ok(())
+            }
+            _ => self.reference_type(),
+        }
+    }
+
+    fn reference_type(&mut self) -> Result<(), ParseError> {
+        match self.peek()? {
+            b'L' => self.class_type(),
+            b'T' => {
+                self.ix += 1;
+                self.identifier()?;
+                self.expect(b';')
+            }
+            b'[' => {
+                self.ix += 1;
+                self.java_type()
+            }
+            b => fail!("Unexpected '{}' at offset {}", b as char, self.ix),
+        }
+    }
+
+    fn class_type(&mut self) -> Result<(), ParseError> {
+        self.expect(b'L')?;
+        let start = self.ix;
+        let mut class_name = self.identifier()?.to_string();
+        let range = start..self.ix;
+        loop {
+            if self.peek()? == b'<' {
+                self.type_arguments()?;
+            }
+            if self.peek()? != b'.' {
+                break;
+            }
+            // An inner class of a parameterized type, named by its simple name
+            self.ix += 1;
+            class_name.push('$');
+            class_name.push_str(self.identifier()?);
+        }
+        self.expect(b';')?;
+        self.classes.push(SignatureClass { name: class_name, range });
+        Ok(())
+    }
+
+    fn type_arguments(&mut self) -> Result<(), ParseError> {
+        self.expect(b'<')?;
+        while self.peek()? != b'>' {
+            match self.peek()? {
+                b'*' => self.ix += 1,
+                b'+' | b'-' => {
+                    self.ix += 1;
+                    self.reference_type()?;
+                }
+                _ => self.reference_type()?,
+            }
+        }
+        self.ix += 1;
+        Ok(())
+    }
+}
+
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -176,4 +365,32 @@ mod tests {
         assert!(!is_method_descriptor("()II"));
         assert!(!is_method_descriptor("()ILjava/lang/Object;"));
     }
+
+    #[test]
+    fn test_split_method_descriptors() {
+        assert_eq!(split_method_descriptor("()V"), Some((vec![], "V")));
+        assert_eq!(split_method_descriptor("(I[JLjava/lang/Object;)[Z"), Some((vec!["I", "[J", "Ljava/lang/Object;"], "[Z")));
+        assert_eq!(split_method_descriptor("(V)V"), None);
+        assert_eq!(split_method_descriptor("I"), None);
+    }
+
+    fn class_names(signature: &str) -> Vec<String> {
+        signature_classes(signature).unwrap().into_iter().map(|class| class.name).collect()
+    }
+
+    #[test]
+    fn test_signature_classes() {
+        assert_eq!(class_names("[[I"), Vec::<String>::new());
+        assert_eq!(class_names("(I[Ljava/lang/String;J)Lp/R;"), vec!["java/lang/String", "p/R"]);
+        assert_eq!(
+            class_names("<L:Ljava/lang/Object;T::Ljava/lang/Comparable<-TT;>;>Lp/Base<TL;>;Lp/I;"),
+            vec!["java/lang/Object", "java/lang/Comparable", "p/Base", "p/I"]
+        );
+        let signature = "<X:Ljava/lang/Exception;>(Ljava/util/Map<*+Lp/K;>.Entry<Lp/V;>;)V^TX;^Lp/E;";
+        assert_eq!(class_names(signature), vec!["java/lang/Exception", "java/util/Map$Entry", "p/K", "p/V", "p/E"]);
+        let entry = &signature_classes(signature).unwrap()[1];
+        assert_eq!(&signature[entry.range.clone()], "java/util/Map");
+        assert!(signature_classes("(Lp/A;").is_err());
+        assert!(signature_classes("Lp/A<;").is_err());
+    }
 }
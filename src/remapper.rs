@@ -0,0 +1,639 @@
+//! Class name remapping, for shading: relocating the classes of a library into another package
+//! and rewriting the classes that refer to them.
+//!
+//! Every class reference in a class is rewritten: the class itself and its supertypes, the
+//! constant pool, descriptors and generic signatures, annotations, the instructions of its
+//! methods, exception handlers and the attributes that name classes, such as `InnerClasses`,
+//! `EnclosingMethod`, `NestHost` and `NestMembers`. String constants can be rewritten too.
+//!
+//! The constant pool of a parsed class is rewritten in place, so constant pool indices found in
+//! raw data, such as attributes that are not recognized, stay valid and refer to the new names.
+
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use crate::attributes::{Annotation, AnnotationElementValue, AttributeData, AttributeInfo, StackMapEntry, VerificationType};
+use crate::bytecode::Opcode;
+use crate::constant_pool::{
+    indexed_constant_pool, read_constant_pool, BootstrapArgument, ConstantPoolEntry, IndexedEntry, LiteralConstant,
+    Loadable, MemberRef, MethodHandle, NameAndType,
+};
+use crate::names::signature_classes;
+use crate::writer::{write_class, ConstantPoolWriter};
+use crate::{parse_class, ClassFile, ParseError};
+
+#[derive(Clone, Debug)]
+struct Relocation {
+    from: String,
+    to: String,
+    // True to relocate every class whose name starts with `from`, false for a single class
+    prefix: bool,
+}
+
+/// Rewrites the class names in classes according to a list of relocations.
+#[derive(Clone, Debug, Default)]
+pub struct Remapper {
+    relocations: Vec<Relocation>,
+    remap_strings: bool,
+}
+
+// How a constant pool entry uses the Utf8 entry it refers to
+#[derive(Clone, Copy, PartialEq)]
+enum Usage {
+    ClassName,
+    Descriptor,
+    Package,
+    String,
+    Other,
+}
+
+impl Remapper {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a relocation. A pattern ending in `**`, such as `com/google/common/**`, relocates
+    /// every class whose name starts with what comes before it, which is replaced by what comes
+    /// before the `**` in the replacement, such as `ours/shaded/guava/**`. Any other pattern is
+    /// the name of a single class, which is renamed along with its nested classes. Names are in
+    /// internal form. The first relocation that matches a class is used.
+    pub fn relocate(&mut self, pattern: &str, replacement: &str) -> &mut Remapper {
+        let relocation = match pattern.strip_suffix("**") {
+            Some(from) => Relocation {
+                from: from.to_string(),
+                to: replacement.strip_suffix("**").unwrap_or(replacement).to_string(),
+                prefix: true,
+            },
+            None => Relocation {
+                from: pattern.to_string(),
+                to: replacement.to_string(),
+                prefix: false,
+            },
+        };
+        self.relocations.push(relocation);
+        self
+    }
+
+    /// Turns on or off rewriting of string constants, including string values of annotations,
+    /// that name relocated classes or packages in internal or binary form, such as
+    /// `com/google/common/base/Strings` or `com.google.common.base`. Strings are often used to
+    /// load classes and resources by name, but can also happen to match a relocation, so this is
+    /// disabled by default.
+    pub fn remap_strings(&mut self, remap: bool) -> &mut Remapper {
+        self.remap_strings = remap;
+        self
+    }
+
+    /// Returns the new name of a class, or None if no relocation applies to it.
+    pub fn map_class_name(&self, class_name: &str) -> Option<String> {
+        self.relocations.iter().find_map(|relocation| relocate(relocation, class_name))
+    }
+
+    // Maps a class named as in a CONSTANT_Class entry, which names arrays by their descriptor
+    fn map_class_info(&self, class_name: &str) -> Result<String, ParseError> {
+        if class_name.starts_with('[') {
+            self.map_signature(class_name)
+        } else {
+            Ok(self.map_class_name(class_name).unwrap_or_else(|| class_name.to_string()))
+        }
+    }
+
+    /// Returns a field or method descriptor, or a generic signature, with the classes in it
+    /// renamed. Fails if it is malformed.
+    pub fn map_signature(&self, signature: &str) -> Result<String, ParseError> {
+        let mut result = String::with_capacity(signature.len());
+        let mut end = 0;
+        for class in signature_classes(signature)? {
+            let outer = &signature[class.range.clone()];
+            if let Some(mapped) = self.map_class_name(outer) {
+                result.push_str(&signature[end..class.range.start]);
+                result.push_str(&mapped);
+                end = class.range.end;
+            }
+        }
+        result.push_str(&signature[end..]);
+        Ok(result)
+    }
+
+    fn map_package(&self, package: &str) -> Option<String> {
+        let with_separator = format!("{}/", package);
+        self.relocations.iter().filter(|relocation| relocation.prefix).find_map(|relocation| {
+            with_separator.strip_prefix(&relocation.from).map(|rest| format!("{}{}", relocation.to, rest).trim_end_matches('/').to_string())
+        })
+    }
+
+    fn map_string(&self, value: &str) -> Option<String> {
+        if !self.remap_strings {
+            return None;
+        }
+        self.relocations.iter().find_map(|relocation| {
+            relocate(relocation, value).or_else(|| {
+                let dotted = Relocation {
+                    from: relocation.from.replace('/', "."),
+                    to: relocation.to.replace('/', "."),
+                    prefix: relocation.prefix,
+                };
+                relocate(&dotted, value)
+            })
+        })
+    }
+
+    /// Returns a copy of a class with its class references renamed. Fails if a descriptor or
+    /// signature is malformed, or an attribute cannot be decoded.
+    pub fn remap_class(&self, class: &ClassFile) -> Result<ClassFile, ParseError> {
+        self.remap(class).map_err(|e| err!(e, "class {}", class.this_class))
+    }
+
+    /// Parses a class file, renames its class references and writes the result.
+    pub fn remap_bytes(&self, bytes: &[u8]) -> Result<Vec<u8>, ParseError> {
+        write_class(&self.remap_class(&parse_class(bytes)?)?)
+    }
+
+    fn remap(&self, class: &ClassFile) -> Result<ClassFile, ParseError> {
+        let mut class = class.clone();
+        if !class.constant_pool.is_empty() {
+            class.constant_pool = self.remap_constant_pool(&class.constant_pool)?;
+        }
+        class.this_class = self.map_class_info(&class.this_class)?;
+        if let Some(super_class) = &mut class.super_class {
+            *super_class = self.map_class_info(super_class)?;
+        }
+        for interface in &mut class.interfaces {
+            *interface = self.map_class_info(interface)?;
+        }
+        for field in &mut class.fields {
+            field.descriptor = self.map_signature(&field.descriptor)?;
+            self.remap_attributes(&mut field.attributes).map_err(|e| err!(e, "field {}", field.name))?;
+        }
+        for method in &mut class.methods {
+            method.descriptor = self.map_signature(&method.descriptor)?;
+            self.remap_attributes(&mut method.attributes).map_err(|e| err!(e, "method {}", method.name))?;
+        }
+        self.remap_attributes(&mut class.attributes)?;
+        Ok(class)
+    }
+
+    // Rewrites the Utf8 entries that the constant pool uses as class names, descriptors, package
+    // names and strings. An entry used in ways that call for different values keeps its value,
+    // and the uses that need another value are pointed at new entries added at the end. Utf8
+    // entries not used by other entries are the descriptors and signatures of members and
+    // attributes, which are rewritten if they are valid descriptors or signatures.
+    fn remap_constant_pool(&self, pool: &[Rc<ConstantPoolEntry>]) -> Result<Vec<Rc<ConstantPoolEntry>>, ParseError> {
+        let mut entries = indexed_constant_pool(pool);
+        let mut uses: HashMap<u16, Vec<(usize, usize, Usage)>> = HashMap::new();
+        for (i, entry) in entries.iter().enumerate() {
+            let refs: &[(usize, u16, Usage)] = match entry {
+                Some(IndexedEntry::ClassInfo(x)) => &[(0, *x, Usage::ClassName)],
+                Some(IndexedEntry::NameAndType(x, y)) => &[(0, *x, Usage::Other), (1, *y, Usage::Descriptor)],
+                Some(IndexedEntry::MethodType(x)) => &[(0, *x, Usage::Descriptor)],
+                Some(IndexedEntry::String(x)) => &[(0, *x, Usage::String)],
+                Some(IndexedEntry::PackageInfo(x)) => &[(0, *x, Usage::Package)],
+                Some(IndexedEntry::ModuleInfo(x)) => &[(0, *x, Usage::Other)],
+                _ => &[],
+            };
+            for (slot, utf8, usage) in refs {
+                uses.entry(*utf8).or_default().push((i, *slot, *usage));
+            }
+        }
+        let mut redirects = Vec::new();
+        for (i, entry) in entries.iter_mut().enumerate() {
+            let value = match entry {
+                Some(IndexedEntry::Utf8(value)) => value,
+                _ => continue,
+            };
+            let mapped_values: Vec<(usize, usize, Option<String>)> = match uses.get(&(i as u16)) {
+                Some(users) => users.iter().map(|(user, slot, usage)| (*user, *slot, self.map_usage(value, *usage))).collect(),
+                None => vec![(0, 0, self.map_signature(value).ok().filter(|mapped| mapped != value))],
+            };
+            let first = &mapped_values[0].2;
+            if mapped_values.iter().all(|(_, _, mapped)| mapped == first) {
+                if let Some(mapped) = first {
+                    *value = mapped.clone();
+                }
+            } else {
+                redirects.extend(mapped_values.into_iter().filter_map(|(user, slot, mapped)| Some((user, slot, mapped?))));
+            }
+        }
+        let mut utf8_indices: HashMap<String, u16> = HashMap::new();
+        for (i, entry) in entries.iter().enumerate() {
+            if let Some(IndexedEntry::Utf8(value)) = entry {
+                utf8_indices.entry(value.clone()).or_insert(i as u16);
+            }
+        }
+        for (user, slot, value) in redirects {
+            let index = match utf8_indices.get(&value) {
+                Some(index) => *index,
+                None => {
+                    if entries.len() >= usize::from(u16::MAX) {
+                        fail!("Too many constant pool entries to write");
+                    }
+                    let index = entries.len() as u16;
+                    entries.push(Some(IndexedEntry::Utf8(value.clone())));
+                    utf8_indices.insert(value, index);
+                    index
+                }
+            };
+            match (&mut entries[user], slot) {
+                (Some(IndexedEntry::ClassInfo(x)), _)
+                | (Some(IndexedEntry::MethodType(x)), _)
+                | (Some(IndexedEntry::String(x)), _)
+                | (Some(IndexedEntry::PackageInfo(x)), _)
+                | (Some(IndexedEntry::NameAndType(_, x)), 1) => *x = index,
+                _ => (),
+            }
+        }
+        let mut bytes = Vec::new();
+        ConstantPoolWriter::from_entries(entries).write(&mut bytes);
+        read_constant_pool(&bytes, &mut 0, u16::MAX)
+    }
+
+    fn map_usage(&self, value: &str, usage: Usage) -> Option<String> {
+        match usage {
+            Usage::ClassName => self.map_class_info(value).ok().filter(|mapped| mapped != value),
+            Usage::Descriptor => self.map_signature(value).ok().filter(|mapped| mapped != value),
+            Usage::Package => self.map_package(value),
+            Usage::String => self.map_string(value),
+            Usage::Other => None,
+        }
+    }
+
+    fn remap_member(&self, member: &mut MemberRef) -> Result<(), ParseError> {
+        member.class_name = self.map_class_info(&member.class_name)?;
+        self.remap_name_and_type(&mut member.name_and_type)
+    }
+
+    fn remap_name_and_type(&self, name_and_type: &mut NameAndType) -> Result<(), ParseError> {
+        name_and_type.descriptor = self.map_signature(&name_and_type.descriptor)?;
+        Ok(())
+    }
+
+    fn remap_method_handle(&self, handle: &mut MethodHandle) -> Result<(), ParseError> {
+        handle.class_name = self.map_class_info(&handle.class_name)?;
+        self.remap_name_and_type(&mut handle.member_ref)
+    }
+
+    fn remap_literal(&self, literal: &mut LiteralConstant) {
+        if let LiteralConstant::String(value) = literal {
+            if let Some(mapped) = self.map_string(value) {
+                *value = mapped;
+            }
+        }
+    }
+
+    fn remap_loadable(&self, loadable: &mut Loadable) -> Result<(), ParseError> {
+        match loadable {
+            Loadable::LiteralConstant(literal) => self.remap_literal(literal),
+            Loadable::ClassInfo(class_name) => *class_name = self.map_class_info(class_name)?,
+            Loadable::MethodHandle(handle) => self.remap_method_handle(handle)?,
+            Loadable::MethodType(descriptor) => *descriptor = self.map_signature(descriptor)?,
+            Loadable::Dynamic(dynamic) => self.remap_name_and_type(&mut dynamic.name_and_type)?,
+        }
+        Ok(())
+    }
+
+    fn remap_annotation(&self, annotation: &mut Annotation) -> Result<(), ParseError> {
+        annotation.type_descriptor = self.map_signature(&annotation.type_descriptor)?;
+        for element in &mut annotation.elements {
+            self.remap_element_value(&mut element.value)?;
+        }
+        Ok(())
+    }
+
+    fn remap_element_value(&self, value: &mut AnnotationElementValue) -> Result<(), ParseError> {
+        match value {
+            AnnotationElementValue::StringConstant(value) => {
+                if let Some(mapped) = self.map_string(value) {
+                    *value = mapped;
+                }
+            }
+            AnnotationElementValue::EnumConstant { type_name, .. } => *type_name = self.map_signature(type_name)?,
+            AnnotationElementValue::ClassLiteral { class_name } => *class_name = self.map_signature(class_name)?,
+            AnnotationElementValue::AnnotationValue(annotation) => self.remap_annotation(annotation)?,
+            AnnotationElementValue::ArrayValue(values) => {
+                for value in values {
+                    self.remap_element_value(value)?;
+                }
+            }
+            _ => (),
+        }
+        Ok(())
+    }
+
+    fn remap_verification_types(&self, types: &mut [VerificationType]) -> Result<(), ParseError> {
+        for verification_type in types {
+            if let VerificationType::Object { class_name } = verification_type {
+                *class_name = self.map_class_info(class_name)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn remap_class_names(&self, class_names: &mut [String]) -> Result<(), ParseError> {
+        for class_name in class_names {
+            *class_name = self.map_class_info(class_name)?;
+        }
+        Ok(())
+    }
+
+    fn remap_packages(&self, packages: &mut [String]) {
+        for package in packages {
+            if let Some(mapped) = self.map_package(package) {
+                *package = mapped;
+            }
+        }
+    }
+
+    fn remap_attributes(&self, attributes: &mut [AttributeInfo]) -> Result<(), ParseError> {
+        for attr in attributes {
+            if let AttributeData::Lazy(lazy) = &attr.data {
+                attr.data = lazy.decode()?.clone();
+            }
+            self.remap_attribute(&mut attr.data).map_err(|e| err!(e, "attribute {}", attr.name))?;
+        }
+        Ok(())
+    }
+
+    fn remap_attribute(&self, data: &mut AttributeData) -> Result<(), ParseError> {
+        match data {
+            AttributeData::ConstantValue(literal) => self.remap_literal(literal),
+            AttributeData::Code(code) => {
+                for entry in &mut code.exception_table {
+                    if let Some(catch_type) = &mut entry.catch_type {
+                        *catch_type = self.map_class_info(catch_type)?;
+                    }
+                }
+                if let Some(bytecode) = &mut code.bytecode {
+                    for (_, opcode) in &mut bytecode.opcodes {
+                        self.remap_opcode(opcode)?;
+                    }
+                }
+                self.remap_attributes(&mut code.attributes)?;
+            }
+            AttributeData::StackMapTable(entries) => {
+                for entry in entries {
+                    match entry {
+                        StackMapEntry::SameLocals1StackItem { stack, .. } => {
+                            self.remap_verification_types(std::slice::from_mut(stack))?
+                        }
+                        StackMapEntry::Append { locals, .. } => self.remap_verification_types(locals)?,
+                        StackMapEntry::FullFrame { locals, stack, .. } => {
+                            self.remap_verification_types(locals)?;
+                            self.remap_verification_types(stack)?;
+                        }
+                        _ => (),
+                    }
+                }
+            }
+            AttributeData::Exceptions(class_names) | AttributeData::NestMembers(class_names) => {
+                self.remap_class_names(class_names)?
+            }
+            AttributeData::InnerClasses(entries) => {
+                for entry in entries {
+                    entry.inner_class_info = self.map_class_info(&entry.inner_class_info)?;
+                    if let Some(outer) = &mut entry.outer_class_info {
+                        *outer = self.map_class_info(outer)?;
+                    }
+                }
+            }
+            AttributeData::EnclosingMethod { class_name, method } => {
+                *class_name = self.map_class_info(class_name)?;
+                if let Some(method) = method {
+                    self.remap_name_and_type(method)?;
+                }
+            }
+            AttributeData::Signature(signature) => *signature = self.map_signature(signature)?,
+            AttributeData::LocalVariableTable(entries) => {
+                for entry in entries {
+                    entry.descriptor = self.map_signature(&entry.descriptor)?;
+                }
+            }
+            AttributeData::LocalVariableTypeTable(entries) => {
+                for entry in entries {
+                    entry.signature = self.map_signature(&entry.signature)?;
+                }
+            }
+            AttributeData::RuntimeVisibleAnnotations(annotations) | AttributeData::RuntimeInvisibleAnnotations(annotations) => {
+                for annotation in annotations {
+                    self.remap_annotation(annotation)?;
+                }
+            }
+            AttributeData::RuntimeVisibleParameterAnnotations(parameters)
+            | AttributeData::RuntimeInvisibleParameterAnnotations(parameters) => {
+                for annotation in parameters.iter_mut().flat_map(|parameter| &mut parameter.annotations) {
+                    self.remap_annotation(annotation)?;
+                }
+            }
+            AttributeData::RuntimeVisibleTypeAnnotations(annotations) | AttributeData::RuntimeInvisibleTypeAnnotations(annotations) => {
+                for annotation in annotations {
+                    self.remap_annotation(&mut annotation.annotation)?;
+                }
+            }
+            AttributeData::AnnotationDefault(value) => self.remap_element_value(value)?,
+            AttributeData::BootstrapMethods(entries) => {
+                for entry in entries {
+                    self.remap_method_handle(&mut entry.method)?;
+                    for argument in &mut entry.arguments {
+                        match argument {
+                            BootstrapArgument::LiteralConstant(literal) => self.remap_literal(literal),
+                            BootstrapArgument::ClassInfo(class_name) => *class_name = self.map_class_info(class_name)?,
+                            BootstrapArgument::MethodHandle(handle) => self.remap_method_handle(handle)?,
+                            BootstrapArgument::MethodType(descriptor) => *descriptor = self.map_signature(descriptor)?,
+                        }
+                    }
+                }
+            }
+            AttributeData::Module(module) => {
+                for entry in &mut module.exports {
+                    if let Some(mapped) = self.map_package(&entry.package_name) {
+                        entry.package_name = mapped;
+                    }
+                }
+                for entry in &mut module.opens {
+                    if let Some(mapped) = self.map_package(&entry.package_name) {
+                        entry.package_name = mapped;
+                    }
+                }
+                self.remap_class_names(&mut module.uses)?;
+                for entry in &mut module.provides {
+                    entry.service_interface_name = self.map_class_info(&entry.service_interface_name)?;
+                    self.remap_class_names(&mut entry.provides_with)?;
+                }
+            }
+            AttributeData::ModulePackages(packages) => self.remap_packages(packages),
+            AttributeData::ModuleMainClass(class_name) | AttributeData::NestHost(class_name) => {
+                *class_name = self.map_class_info(class_name)?
+            }
+            AttributeData::Record(components) => {
+                for component in components {
+                    component.descriptor = self.map_signature(&component.descriptor)?;
+                    self.remap_attributes(&mut component.attributes)?;
+                }
+            }
+            _ => (),
+        }
+        Ok(())
+    }
+
+    fn remap_opcode(&self, opcode: &mut Opcode) -> Result<(), ParseError> {
+        match opcode {
+            Opcode::Anewarray(class_name)
+            | Opcode::Checkcast(class_name)
+            | Opcode::Instanceof(class_name)
+            | Opcode::Multianewarray(class_name, _)
+            | Opcode::New(class_name) => *class_name = self.map_class_info(class_name)?,
+            Opcode::Getfield(member)
+            | Opcode::Getstatic(member)
+            | Opcode::Putfield(member)
+            | Opcode::Putstatic(member)
+            | Opcode::Invokeinterface(member, _)
+            | Opcode::Invokespecial(member)
+            | Opcode::Invokestatic(member)
+            | Opcode::Invokevirtual(member) => self.remap_member(member)?,
+            Opcode::Invokedynamic(dynamic) => self.remap_name_and_type(&mut dynamic.name_and_type)?,
+            Opcode::Ldc(loadable) | Opcode::LdcW(loadable) | Opcode::Ldc2W(loadable) => self.remap_loadable(loadable)?,
+            _ => (),
+        }
+        Ok(())
+    }
+}
+
+fn relocate(relocation: &Relocation, name: &str) -> Option<String> {
+    if relocation.prefix {
+        return name.strip_prefix(&relocation.from).map(|rest| format!("{}{}", relocation.to, rest));
+    }
+    match name.strip_prefix(&relocation.from) {
+        Some("") => Some(relocation.to.clone()),
+        Some(rest) if rest.starts_with('$') => Some(format!("{}{}", relocation.to, rest)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembly::assemble;
+    use crate::constant_pool::ConstantPoolItem;
+
+    const CLASS: &str = r#"
+.version 55 0
+.class public app/Main
+.super java/lang/Object
+.implements com/google/common/base/Supplier
+.field private cache Lcom/google/common/cache/Cache;
+    .signature "Lcom/google/common/cache/Cache<Ljava/lang/String;Lcom/google/common/collect/ImmutableList<Lapp/Main;>;>;"
+.end field
+.method public get ()Ljava/lang/Object;
+    .runtimevisibleannotations
+        .annotation Lcom/google/common/annotations/Beta;
+            value = class Lcom/google/common/base/Optional;
+        .end annotation
+    .end runtimevisibleannotations
+    .code stack 2 locals 1
+        new com/google/common/base/Joiner
+        ldc string "com/google/common/base/Joiner"
+        ldc string "com.google.common.base.Joiner"
+        ldc class [Lcom/google/common/base/Joiner;
+        invokestatic com/google/common/base/Optional absent ()Lcom/google/common/base/Optional;
+        areturn
+    .end code
+.end method
+.innerclasses
+    com/google/common/cache/Cache$Entry com/google/common/cache/Cache Entry public static interface abstract
+.end innerclasses
+.nesthost com/google/common/base/Outer
+.end class
+"#;
+
+    fn remapped(remapper: &Remapper) -> ClassFile {
+        let bytes = remapper.remap_bytes(&assemble(CLASS).unwrap()).unwrap();
+        parse_class(&bytes).unwrap()
+    }
+
+    fn strings(class: &ClassFile) -> Vec<String> {
+        class
+            .constantpool_iter()
+            .filter_map(|item| match item {
+                ConstantPoolItem::LiteralConstant(LiteralConstant::String(value)) => Some(value),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_remap_class() {
+        let mut remapper = Remapper::new();
+        remapper.relocate("com/google/common/**", "ours/shaded/guava/**").relocate("app/Main", "app/Start");
+        assert_eq!(remapper.map_class_name("com/google/common/base/Joiner").as_deref(), Some("ours/shaded/guava/base/Joiner"));
+        assert_eq!(remapper.map_class_name("app/Main$1").as_deref(), Some("app/Start$1"));
+        assert_eq!(remapper.map_class_name("app/MainTest"), None);
+        assert_eq!(
+            remapper.map_signature("(Lcom/google/common/cache/Cache<TK;>.Entry;[Lapp/Main;)V").unwrap(),
+            "(Lours/shaded/guava/cache/Cache<TK;>.Entry;[Lapp/Start;)V"
+        );
+
+        let class = remapped(&remapper);
+        assert_eq!(class.this_class, "app/Start");
+        assert_eq!(class.interfaces, vec!["ours/shaded/guava/base/Supplier"]);
+        assert_eq!(class.fields[0].descriptor, "Lours/shaded/guava/cache/Cache;");
+        assert_eq!(
+            class.fields[0].attributes[0].data,
+            AttributeData::Signature(
+                "Lours/shaded/guava/cache/Cache<Ljava/lang/String;Lours/shaded/guava/collect/ImmutableList<Lapp/Start;>;>;".to_string()
+            )
+        );
+        assert_eq!(class.methods[0].descriptor, "()Ljava/lang/Object;");
+        let annotation = match &class.methods[0].attributes[0].data {
+            AttributeData::RuntimeVisibleAnnotations(annotations) => &annotations[0],
+            data => panic!("Unexpected attribute {:?}", data),
+        };
+        assert_eq!(annotation.type_descriptor, "Lours/shaded/guava/annotations/Beta;");
+        assert_eq!(
+            annotation.elements[0].value,
+            AnnotationElementValue::ClassLiteral {
+                class_name: "Lours/shaded/guava/base/Optional;".to_string()
+            }
+        );
+        let code = match &class.methods[0].attributes[1].data {
+            AttributeData::Code(code) => code,
+            data => panic!("Unexpected attribute {:?}", data),
+        };
+        let opcodes: Vec<&Opcode> = code.bytecode.as_ref().unwrap().opcodes.iter().map(|(_, opcode)| opcode).collect();
+        assert_eq!(opcodes[0], &Opcode::New("ours/shaded/guava/base/Joiner".to_string()));
+        assert_eq!(opcodes[3], &Opcode::Ldc(Loadable::ClassInfo("[Lours/shaded/guava/base/Joiner;".to_string())));
+        match opcodes[4] {
+            Opcode::Invokestatic(member) => {
+                assert_eq!(member.class_name, "ours/shaded/guava/base/Optional");
+                assert_eq!(member.name_and_type.descriptor, "()Lours/shaded/guava/base/Optional;");
+            }
+            opcode => panic!("Unexpected opcode {:?}", opcode),
+        }
+        match &class.attributes[0].data {
+            AttributeData::InnerClasses(entries) => {
+                assert_eq!(entries[0].inner_class_info, "ours/shaded/guava/cache/Cache$Entry");
+                assert_eq!(entries[0].outer_class_info.as_deref(), Some("ours/shaded/guava/cache/Cache"));
+                assert_eq!(entries[0].inner_name.as_deref(), Some("Entry"));
+            }
+            data => panic!("Unexpected attribute {:?}", data),
+        }
+        assert_eq!(class.attributes[1].data, AttributeData::NestHost("ours/shaded/guava/base/Outer".to_string()));
+
+        // The string shares its Utf8 entry with the class name, and keeps the old value
+        assert_eq!(strings(&class), vec!["com/google/common/base/Joiner", "com.google.common.base.Joiner"]);
+        for item in class.constantpool_iter() {
+            if let ConstantPoolItem::ClassInfo(name) = item {
+                assert!(!name.contains("com/google"), "{}", name);
+            }
+        }
+    }
+
+    #[test]
+    fn test_remap_strings() {
+        let mut remapper = Remapper::new();
+        remapper.relocate("com/google/common/**", "ours/shaded/guava/**").remap_strings(true);
+        let class = remapped(&remapper);
+        assert_eq!(strings(&class), vec!["ours/shaded/guava/base/Joiner", "ours.shaded.guava.base.Joiner"]);
+        let pool_size = |class: &ClassFile| class.constantpool_iter().count();
+        let original = parse_class(&assemble(CLASS).unwrap()).unwrap();
+        assert_eq!(pool_size(&class), pool_size(&original));
+    }
+}
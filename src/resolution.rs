@@ -0,0 +1,418 @@
+//! Resolution of symbolic field and method references to the declarations they bind to,
+//! following section 5.4.3 of the JVM spec.
+//!
+//! Resolution finds the field or method that a reference names by looking through the
+//! referenced class and its supertypes, which are loaded from a [`ClassRepository`]. Where the
+//! JVM would throw a `LinkageError` such as `NoSuchMethodError` or `IncompatibleClassChangeError`,
+//! an error naming the problem is returned instead. Access control (section 5.4.4) is not checked,
+//! and neither is the loading constraint on the types in the descriptor.
+
+use std::collections::HashSet;
+use std::rc::Rc;
+
+use crate::bytecode::Opcode;
+use crate::classpath::ClassRepository;
+use crate::constant_pool::MemberRef;
+use crate::{ClassFile, FieldAccessFlags, FieldInfo, MethodAccessFlags, MethodInfo, ParseError};
+
+const OBJECT: &str = "java/lang/Object";
+const SIGNATURE_POLYMORPHIC_CLASSES: [&str; 2] = ["java/lang/invoke/MethodHandle", "java/lang/invoke/VarHandle"];
+
+/// A field declaration found by resolving a field reference.
+#[derive(Clone, Debug)]
+pub struct ResolvedField {
+    /// The class or interface that declares the field, which may be a supertype of the
+    /// class named by the reference.
+    pub class: Rc<ClassFile>,
+    index: usize,
+}
+
+impl ResolvedField {
+    pub fn field(&self) -> &FieldInfo {
+        &self.class.fields[self.index]
+    }
+
+    pub fn is_static(&self) -> bool {
+        self.field().access_flags.contains(FieldAccessFlags::STATIC)
+    }
+}
+
+/// A method declaration found by resolving a method or interface method reference.
+#[derive(Clone, Debug)]
+pub struct ResolvedMethod {
+    /// The class or interface that declares the method, which may be a supertype of the
+    /// class named by the reference.
+    pub class: Rc<ClassFile>,
+    pub(crate) index: usize,
+    /// True if the method is one of the signature polymorphic methods of `MethodHandle` or
+    /// `VarHandle`, such as `invokeExact`, which accept any descriptor. The descriptor of the
+    /// declaration then differs from the one in the reference.
+    pub signature_polymorphic: bool,
+}
+
+impl ResolvedMethod {
+    pub fn method(&self) -> &MethodInfo {
+        &self.class.methods[self.index]
+    }
+
+    pub fn is_static(&self) -> bool {
+        self.method().access_flags.contains(MethodAccessFlags::STATIC)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum ResolvedMember {
+    Field(ResolvedField),
+    Method(ResolvedMethod),
+}
+
+// Arrays have the methods of java/lang/Object, and no fields.
+fn class_to_search(class_name: &str) -> &str {
+    if class_name.starts_with('[') {
+        OBJECT
+    } else {
+        class_name
+    }
+}
+
+fn find_method(class: &Rc<ClassFile>, name: &str, descriptor: &str) -> Option<ResolvedMethod> {
+    class.methods.iter().position(|m| m.name == name && m.descriptor == descriptor).map(|index| ResolvedMethod {
+        class: class.clone(),
+        index,
+        signature_polymorphic: false,
+    })
+}
+
+// Section 2.9.3: a native varargs method of MethodHandle or VarHandle taking a single Object[].
+fn is_signature_polymorphic(class: &ClassFile, method: &MethodInfo) -> bool {
+    SIGNATURE_POLYMORPHIC_CLASSES.contains(&class.this_class.as_str())
+        && method.access_flags.contains(MethodAccessFlags::VARARGS | MethodAccessFlags::NATIVE)
+        && method.descriptor.starts_with("([Ljava/lang/Object;)")
+}
+
+fn lookup_field(
+    repo: &ClassRepository,
+    class_name: &str,
+    member: &MemberRef,
+    visited: &mut HashSet<String>,
+) -> Result<Option<ResolvedField>, ParseError> {
+    let name = &member.name_and_type.name;
+    let descriptor = &member.name_and_type.descriptor;
+    let mut current = Some(class_name.to_string());
+    while let Some(class_name) = current {
+        if !visited.insert(class_name.clone()) {
+            return Ok(None);
+        }
+        let class = repo.load(&class_name)?;
+        if let Some(index) = class.fields.iter().position(|f| &f.name == name && &f.descriptor == descriptor) {
+            return Ok(Some(ResolvedField { class, index }));
+        }
+        for interface in &class.interfaces {
+            if let Some(field) = lookup_field(repo, interface, member, visited)? {
+                return Ok(Some(field));
+            }
+        }
+        current = class.super_class.clone();
+    }
+    Ok(None)
+}
+
+/// Resolves a field reference as described in section 5.4.3.2 of the JVM spec. The field is
+/// looked for in the referenced class, then its superinterfaces, then its superclass and so on.
+/// Fails if no such field exists, which the JVM reports as a `NoSuchFieldError`.
+pub fn resolve_field(repo: &ClassRepository, member: &MemberRef) -> Result<ResolvedField, ParseError> {
+    if !member.class_name.starts_with('[') {
+        if let Some(field) = lookup_field(repo, &member.class_name, member, &mut HashSet::new())? {
+            return Ok(field);
+        }
+    }
+    fail!(
+        "No field {} {} found in {} or its supertypes",
+        member.name_and_type.name,
+        member.name_and_type.descriptor,
+        member.class_name
+    )
+}
+
+/// Returns the maximally-specific superinterface methods of a class or interface for the given
+/// name and descriptor, as defined in section 5.4.3.3 of the JVM spec: the non-private, non-static
+/// methods declared by its superinterfaces that are not overridden by a method declared in
+/// another of its superinterfaces.
+pub fn maximally_specific_methods(
+    repo: &ClassRepository,
+    class_name: &str,
+    name: &str,
+    descriptor: &str,
+) -> Result<Vec<ResolvedMethod>, ParseError> {
+    let mut candidates = Vec::new();
+    for interface in repo.all_interfaces(class_to_search(class_name))? {
+        if let Some(method) = find_method(&repo.load(&interface)?, name, descriptor) {
+            if !method.method().access_flags.intersects(MethodAccessFlags::PRIVATE | MethodAccessFlags::STATIC) {
+                candidates.push(method);
+            }
+        }
+    }
+    let mut result = Vec::new();
+    for method in &candidates {
+        let mut overridden = false;
+        for other in &candidates {
+            let other = &other.class.this_class;
+            if other != &method.class.this_class && repo.is_subtype_of(other, &method.class.this_class)? {
+                overridden = true;
+                break;
+            }
+        }
+        if !overridden {
+            result.push(method.clone());
+        }
+    }
+    Ok(result)
+}
+
+// The last steps of both method and interface method resolution: a single non-abstract maximally
+// specific method is preferred, then any non-private, non-static superinterface method.
+fn lookup_superinterface_method(
+    repo: &ClassRepository,
+    class_name: &str,
+    name: &str,
+    descriptor: &str,
+) -> Result<Option<ResolvedMethod>, ParseError> {
+    let maximal = maximally_specific_methods(repo, class_name, name, descriptor)?;
+    let concrete: Vec<&ResolvedMethod> =
+        maximal.iter().filter(|m| !m.method().access_flags.contains(MethodAccessFlags::ABSTRACT)).collect();
+    if concrete.len() == 1 {
+        return Ok(Some(concrete[0].clone()));
+    }
+    Ok(maximal.into_iter().next())
+}
+
+/// Resolves a method reference as described in section 5.4.3.3 of the JVM spec. The method is
+/// looked for in the referenced class and its superclasses, where a signature polymorphic method
+/// of `MethodHandle` or `VarHandle` matches any descriptor, and then among the methods of its
+/// superinterfaces. Array types are treated as `java/lang/Object`. Fails if the referenced class
+/// is an interface (an `IncompatibleClassChangeError`) or if no such method exists (a
+/// `NoSuchMethodError`).
+pub fn resolve_method(repo: &ClassRepository, member: &MemberRef) -> Result<ResolvedMethod, ParseError> {
+    let class_name = class_to_search(&member.class_name);
+    let name = &member.name_and_type.name;
+    let descriptor = &member.name_and_type.descriptor;
+    if repo.is_interface(class_name)? {
+        fail!("Found interface {} where a class was expected in method reference {}", class_name, name);
+    }
+    for superclass in std::iter::once(class_name.to_string()).chain(repo.superclasses(class_name)?) {
+        let class = repo.load(&superclass)?;
+        let mut named = class.methods.iter().enumerate().filter(|(_, m)| &m.name == name);
+        if let (Some((index, method)), None) = (named.next(), named.next()) {
+            if is_signature_polymorphic(&class, method) {
+                return Ok(ResolvedMethod {
+                    class,
+                    index,
+                    signature_polymorphic: true,
+                });
+            }
+        }
+        if let Some(method) = find_method(&class, name, descriptor) {
+            return Ok(method);
+        }
+    }
+    match lookup_superinterface_method(repo, class_name, name, descriptor)? {
+        Some(method) => Ok(method),
+        None => fail!("No method {}{} found in {} or its supertypes", name, descriptor, member.class_name),
+    }
+}
+
+/// Resolves an interface method reference as described in section 5.4.3.4 of the JVM spec. The
+/// method is looked for in the referenced interface, then among the public instance methods of
+/// `java/lang/Object`, then among the methods of its superinterfaces. Fails if the referenced
+/// class is not an interface (an `IncompatibleClassChangeError`) or if no such method exists (a
+/// `NoSuchMethodError`).
+pub fn resolve_interface_method(repo: &ClassRepository, member: &MemberRef) -> Result<ResolvedMethod, ParseError> {
+    let class_name = &member.class_name;
+    let name = &member.name_and_type.name;
+    let descriptor = &member.name_and_type.descriptor;
+    if !repo.is_interface(class_name)? {
+        fail!("Found class {} where an interface was expected in method reference {}", class_name, name);
+    }
+    if let Some(method) = find_method(&repo.load(class_name)?, name, descriptor) {
+        return Ok(method);
+    }
+    if let Some(method) = find_method(&repo.load(OBJECT)?, name, descriptor) {
+        let flags = method.method().access_flags;
+        if flags.contains(MethodAccessFlags::PUBLIC) && !flags.contains(MethodAccessFlags::STATIC) {
+            return Ok(method);
+        }
+    }
+    match lookup_superinterface_method(repo, class_name, name, descriptor)? {
+        Some(method) => Ok(method),
+        None => fail!("No method {}{} found in {} or its supertypes", name, descriptor, class_name),
+    }
+}
+
+/// Resolves the field or method referenced by a field access or method invocation instruction,
+/// and checks that the member is static exactly when the instruction requires it. Returns
+/// `Ok(None)` for other instructions. Since a MemberRef does not record whether it came from a
+/// `Methodref` or an `InterfaceMethodref`, the references of `invokestatic` and `invokespecial`
+/// are resolved as interface method references when they name an interface.
+pub fn resolve_instruction(repo: &ClassRepository, opcode: &Opcode) -> Result<Option<ResolvedMember>, ParseError> {
+    let (member, resolved, expect_static) = match opcode {
+        Opcode::Getfield(member) | Opcode::Putfield(member) => {
+            (member, ResolvedMember::Field(resolve_field(repo, member)?), false)
+        }
+        Opcode::Getstatic(member) | Opcode::Putstatic(member) => {
+            (member, ResolvedMember::Field(resolve_field(repo, member)?), true)
+        }
+        Opcode::Invokevirtual(member) => (member, ResolvedMember::Method(resolve_method(repo, member)?), false),
+        Opcode::Invokeinterface(member, _) => {
+            (member, ResolvedMember::Method(resolve_interface_method(repo, member)?), false)
+        }
+        Opcode::Invokespecial(member) | Opcode::Invokestatic(member) => {
+            let method = if repo.is_interface(class_to_search(&member.class_name))? {
+                resolve_interface_method(repo, member)?
+            } else {
+                resolve_method(repo, member)?
+            };
+            (member, ResolvedMember::Method(method), matches!(opcode, Opcode::Invokestatic(_)))
+        }
+        _ => return Ok(None),
+    };
+    let is_static = match &resolved {
+        ResolvedMember::Field(field) => field.is_static(),
+        ResolvedMember::Method(method) => method.is_static(),
+    };
+    if is_static != expect_static {
+        fail!(
+            "Expected {} member but found {} member {} in {}",
+            if expect_static { "a static" } else { "an instance" },
+            if is_static { "static" } else { "instance" },
+            member.name_and_type.name,
+            member.class_name
+        );
+    }
+    Ok(Some(resolved))
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::assembly::assemble;
+    use crate::classpath::ClassPath;
+    use crate::constant_pool::NameAndType;
+    use std::collections::HashMap;
+
+    // Each class is written as its header followed by member lines, separated by `,`.
+    pub(crate) fn repository(classes: &[&str]) -> ClassRepository {
+        let mut map = HashMap::new();
+        for class in classes {
+            let mut lines = class.split(',').map(str::trim);
+            let header = lines.next().unwrap();
+            let (name, supertypes) = match header.find(" extends ") {
+                Some(ix) => (&header[..ix], &header[ix + 9..]),
+                None => (header, ""),
+            };
+            let mut text = format!(".version 52 0\n.class public {}\n", name);
+            let mut supertypes = supertypes.split_whitespace();
+            match supertypes.next() {
+                Some(superclass) => text.push_str(&format!(".super {}\n", superclass)),
+                None if !name.ends_with(OBJECT) => text.push_str(".super java/lang/Object\n"),
+                None => (),
+            }
+            let interfaces: Vec<&str> = supertypes.collect();
+            if !interfaces.is_empty() {
+                text.push_str(&format!(".implements {}\n", interfaces.join(" ")));
+            }
+            for member in lines {
+                let end = if member.starts_with("field") { ".end field" } else { ".end method" };
+                text.push_str(&format!(".{}\n{}\n", member, end));
+            }
+            text.push_str(".end class\n");
+            let name = name.rsplit(' ').next().unwrap().to_string();
+            map.insert(name, assemble(&text).unwrap());
+        }
+        let mut classpath = ClassPath::new();
+        classpath.push(map);
+        ClassRepository::new(classpath)
+    }
+
+    fn member(class_name: &str, name: &str, descriptor: &str) -> MemberRef {
+        MemberRef {
+            class_name: class_name.to_string(),
+            name_and_type: NameAndType {
+                name: name.to_string(),
+                descriptor: descriptor.to_string(),
+            },
+        }
+    }
+
+    fn hierarchy() -> ClassRepository {
+        repository(&[
+            "java/lang/Object, method public hashCode ()I, method protected clone ()Ljava/lang/Object;",
+            "interface abstract I, field public static X I, method public abstract m ()V, method public abstract n ()V",
+            "interface abstract J extends java/lang/Object I, method public m ()V, method private p ()V",
+            "interface abstract K, method public abstract n ()V",
+            "abstract A, field protected x I, method public static s ()V",
+            "B extends A J K, field public x J",
+            "C extends B",
+            "abstract java/lang/invoke/MethodHandle, method public final varargs native invokeExact ([Ljava/lang/Object;)Ljava/lang/Object;",
+        ])
+    }
+
+    #[test]
+    fn test_field_resolution() {
+        let repo = hierarchy();
+        let field = resolve_field(&repo, &member("C", "x", "I")).unwrap();
+        assert_eq!(field.class.this_class, "A");
+        assert_eq!(field.field().descriptor, "I");
+        assert_eq!(resolve_field(&repo, &member("C", "x", "J")).unwrap().class.this_class, "B");
+        // Superinterfaces are searched before superclasses
+        assert_eq!(resolve_field(&repo, &member("C", "X", "I")).unwrap().class.this_class, "I");
+        let err = resolve_field(&repo, &member("C", "y", "I")).unwrap_err();
+        assert_eq!(err.to_string(), "No field y I found in C or its supertypes");
+        assert!(resolve_field(&repo, &member("[LC;", "length", "I")).is_err());
+    }
+
+    #[test]
+    fn test_method_resolution() {
+        let repo = hierarchy();
+        assert_eq!(resolve_method(&repo, &member("C", "s", "()V")).unwrap().class.this_class, "A");
+        assert_eq!(resolve_method(&repo, &member("C", "hashCode", "()I")).unwrap().class.this_class, OBJECT);
+        assert_eq!(resolve_method(&repo, &member("[I", "clone", "()Ljava/lang/Object;")).unwrap().class.this_class, OBJECT);
+        // J.m overrides I.m, so it is the only maximally-specific method
+        let maximal = maximally_specific_methods(&repo, "C", "m", "()V").unwrap();
+        assert_eq!(maximal.iter().map(|m| m.class.this_class.as_str()).collect::<Vec<_>>(), vec!["J"]);
+        assert_eq!(resolve_method(&repo, &member("C", "m", "()V")).unwrap().class.this_class, "J");
+        // Both I.n and K.n are abstract and maximally specific, so either may be chosen
+        let maximal = maximally_specific_methods(&repo, "C", "n", "()V").unwrap();
+        assert_eq!(maximal.len(), 2);
+        assert!(resolve_method(&repo, &member("C", "n", "()V")).is_ok());
+        // Private interface methods are not inherited
+        assert!(resolve_method(&repo, &member("C", "p", "()V")).is_err());
+        let err = resolve_method(&repo, &member("I", "m", "()V")).unwrap_err();
+        assert_eq!(err.to_string(), "Found interface I where a class was expected in method reference m");
+
+        let handle = "java/lang/invoke/MethodHandle";
+        let method = resolve_method(&repo, &member(handle, "invokeExact", "(II)V")).unwrap();
+        assert!(method.signature_polymorphic);
+        assert_eq!(method.method().descriptor, "([Ljava/lang/Object;)Ljava/lang/Object;");
+        assert!(resolve_method(&repo, &member(handle, "invoke", "(II)V")).is_err());
+    }
+
+    #[test]
+    fn test_interface_method_resolution() {
+        let repo = hierarchy();
+        assert_eq!(resolve_interface_method(&repo, &member("J", "m", "()V")).unwrap().class.this_class, "J");
+        assert_eq!(resolve_interface_method(&repo, &member("J", "n", "()V")).unwrap().class.this_class, "I");
+        assert_eq!(resolve_interface_method(&repo, &member("K", "hashCode", "()I")).unwrap().class.this_class, OBJECT);
+        // Only public methods of Object are found through interfaces
+        assert!(resolve_interface_method(&repo, &member("K", "clone", "()Ljava/lang/Object;")).is_err());
+        assert!(resolve_interface_method(&repo, &member("B", "m", "()V")).is_err());
+
+        let opcode = Opcode::Invokeinterface(member("J", "m", "()V"), 1);
+        assert!(matches!(resolve_instruction(&repo, &opcode).unwrap(), Some(ResolvedMember::Method(_))));
+        let opcode = Opcode::Invokestatic(member("C", "s", "()V"));
+        assert!(resolve_instruction(&repo, &opcode).unwrap().is_some());
+        let opcode = Opcode::Getstatic(member("C", "x", "I"));
+        let err = resolve_instruction(&repo, &opcode).unwrap_err();
+        assert_eq!(err.to_string(), "Expected a static member but found instance member x in C");
+        assert!(resolve_instruction(&repo, &Opcode::Nop).unwrap().is_none());
+    }
+}
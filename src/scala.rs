@@ -0,0 +1,1270 @@
+//! Decoding of the Scala-specific information in class files. Scala 2 pickles the
+//! Scala-level symbols of each top-level class into a `ScalaSignature` annotation (or into the
+//! `ScalaSig` attribute itself in old compilers), and describes inlining candidates in the
+//! `ScalaInlineInfo` attribute. Scala 3 instead stores the symbols in a separate `.tasty` file,
+//! and the `TASTY` attribute holds the UUID that identifies it.
+
+use std::convert::TryFrom;
+use std::fmt;
+
+use crate::attributes::{AnnotationElementValue, AttributeData};
+use crate::constant_pool::{read_cp_utf8, ConstantPoolReader};
+use crate::{read_u1, read_u2, ClassFile, ParseError};
+
+const SCALA_SIGNATURE_DESCRIPTOR: &str = "Lscala/reflect/ScalaSignature;";
+const SCALA_LONG_SIGNATURE_DESCRIPTOR: &str = "Lscala/reflect/ScalaLongSignature;";
+
+const PICKLE_MAJOR_VERSION: u64 = 5;
+const TASTY_MAGIC: [u8; 4] = [0x5c, 0xa1, 0xab, 0x1f];
+
+// Pickled types and owner chains can refer to each other, so they are not guaranteed to be
+// finite. Give up on anything nested deeper than this.
+const MAX_DEPTH: usize = 64;
+
+// Pickle entry tags
+const TERM_NAME: u8 = 1;
+const TYPE_NAME: u8 = 2;
+const NONE_SYM: u8 = 3;
+const TYPE_SYM: u8 = 4;
+const ALIAS_SYM: u8 = 5;
+const CLASS_SYM: u8 = 6;
+const MODULE_SYM: u8 = 7;
+const VAL_SYM: u8 = 8;
+const EXT_REF: u8 = 9;
+const EXT_MOD_CLASS_REF: u8 = 10;
+const NO_TPE: u8 = 11;
+const NO_PREFIX_TPE: u8 = 12;
+const THIS_TPE: u8 = 13;
+const SINGLE_TPE: u8 = 14;
+const CONSTANT_TPE: u8 = 15;
+const TYPE_REF_TPE: u8 = 16;
+const TYPE_BOUNDS_TPE: u8 = 17;
+const REFINED_TPE: u8 = 18;
+const CLASS_INFO_TPE: u8 = 19;
+const METHOD_TPE: u8 = 20;
+const POLY_TPE: u8 = 21;
+const IMPLICIT_METHOD_TPE: u8 = 22;
+const LITERAL_UNIT: u8 = 24;
+const LITERAL_BOOLEAN: u8 = 25;
+const LITERAL_BYTE: u8 = 26;
+const LITERAL_SHORT: u8 = 27;
+const LITERAL_CHAR: u8 = 28;
+const LITERAL_INT: u8 = 29;
+const LITERAL_LONG: u8 = 30;
+const LITERAL_FLOAT: u8 = 31;
+const LITERAL_DOUBLE: u8 = 32;
+const LITERAL_STRING: u8 = 33;
+const LITERAL_NULL: u8 = 34;
+const LITERAL_CLASS: u8 = 35;
+const LITERAL_ENUM: u8 = 36;
+const ANNOTATED_TPE: u8 = 42;
+const EXISTENTIAL_TPE: u8 = 48;
+const SUPER_TPE: u8 = 52;
+
+bitflags! {
+    /// Symbol flags in the order they are pickled, which differs from the Scala compiler's
+    /// internal order in the lowest 12 bits.
+    pub struct ScalaFlags: u64 {
+        const IMPLICIT = 1 << 0;
+        const FINAL = 1 << 1;
+        const PRIVATE = 1 << 2;
+        const PROTECTED = 1 << 3;
+        const SEALED = 1 << 4;
+        const OVERRIDE = 1 << 5;
+        const CASE = 1 << 6;
+        const ABSTRACT = 1 << 7;
+        const DEFERRED = 1 << 8;
+        const METHOD = 1 << 9;
+        const MODULE = 1 << 10;
+        const INTERFACE = 1 << 11;
+        const MUTABLE = 1 << 12;
+        const PARAM = 1 << 13;
+        const PACKAGE = 1 << 14;
+        const MACRO = 1 << 15;
+        const COVARIANT = 1 << 16;
+        const BYNAMEPARAM = 1 << 16;
+        const CONTRAVARIANT = 1 << 17;
+        const LABEL = 1 << 17;
+        const ABSOVERRIDE = 1 << 18;
+        const LOCAL = 1 << 19;
+        const JAVA = 1 << 20;
+        const SYNTHETIC = 1 << 21;
+        const STABLE = 1 << 22;
+        const STATIC = 1 << 23;
+        const CASEACCESSOR = 1 << 24;
+        const TRAIT = 1 << 25;
+        const DEFAULTPARAM = 1 << 25;
+        const BRIDGE = 1 << 26;
+        const ACCESSOR = 1 << 27;
+        const SUPERACCESSOR = 1 << 28;
+        const PARAMACCESSOR = 1 << 29;
+        const MODULEVAR = 1 << 30;
+        const LAZY = 1 << 31;
+    }
+}
+
+#[cfg(feature = "serde")]
+serde_flags!(
+    ScalaFlags, IMPLICIT, FINAL, PRIVATE, PROTECTED, SEALED, OVERRIDE, CASE, ABSTRACT, DEFERRED, METHOD, MODULE,
+    INTERFACE, MUTABLE, PARAM, PACKAGE, MACRO, COVARIANT, BYNAMEPARAM, CONTRAVARIANT, LABEL, ABSOVERRIDE, LOCAL,
+    JAVA, SYNTHETIC, STABLE, STATIC, CASEACCESSOR, TRAIT, DEFAULTPARAM, BRIDGE, ACCESSOR, SUPERACCESSOR,
+    PARAMACCESSOR, MODULEVAR, LAZY,
+);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ScalaSymbolKind {
+    Class,
+    Trait,
+    Object,
+    /// The class that implements an object, which has the same name as the object.
+    ObjectClass,
+    Method,
+    Value,
+    Variable,
+    Parameter,
+    TypeParameter,
+    TypeMember,
+    TypeAlias,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ScalaSymbol {
+    pub kind: ScalaSymbolKind,
+    /// The name as written in Scala source, e.g. `+` rather than `$plus`.
+    pub name: String,
+    /// The fully qualified name of the enclosing symbol, e.g. `scala.collection.immutable`.
+    pub owner: Option<String>,
+    pub flags: ScalaFlags,
+    /// For `private[x]` and `protected[x]` symbols, the fully qualified name of `x`.
+    pub private_within: Option<String>,
+    /// The symbol's type in Scala syntax, in the form that follows the name in a declaration:
+    /// `[A](x: A): scala.Int` for a method, `: scala.Int` for a value, `[A] extends scala.AnyRef`
+    /// for a class, ` = scala.Int` for a type alias and ` <: scala.AnyRef` for a type member.
+    pub signature: String,
+}
+
+impl ScalaSymbol {
+    pub fn full_name(&self) -> String {
+        match &self.owner {
+            Some(owner) => format!("{}.{}", owner, self.name),
+            None => self.name.clone(),
+        }
+    }
+
+    /// Returns the symbol as it would be declared in Scala source, e.g.
+    /// `implicit final def foo[A](x: A): scala.Int`.
+    pub fn declaration(&self) -> String {
+        let mut text = String::new();
+        let mut modifier = |cond: bool, name: &str| {
+            if cond {
+                text.push_str(name);
+                text.push(' ');
+            }
+        };
+        let access = match (&self.private_within, self.flags) {
+            (Some(within), f) if f.contains(ScalaFlags::PROTECTED) => format!("protected[{}]", within),
+            (Some(within), _) => format!("private[{}]", within),
+            (None, f) if f.contains(ScalaFlags::PRIVATE | ScalaFlags::LOCAL) => "private[this]".to_string(),
+            (None, f) if f.contains(ScalaFlags::PROTECTED | ScalaFlags::LOCAL) => "protected[this]".to_string(),
+            (None, f) if f.contains(ScalaFlags::PRIVATE) => "private".to_string(),
+            (None, f) if f.contains(ScalaFlags::PROTECTED) => "protected".to_string(),
+            (None, _) => String::new(),
+        };
+        modifier(!access.is_empty(), &access);
+        let is_type = matches!(self.kind, ScalaSymbolKind::Class | ScalaSymbolKind::Trait | ScalaSymbolKind::ObjectClass);
+        modifier(self.flags.contains(ScalaFlags::IMPLICIT), "implicit");
+        modifier(self.flags.contains(ScalaFlags::OVERRIDE), "override");
+        modifier(self.flags.contains(ScalaFlags::FINAL), "final");
+        modifier(self.flags.contains(ScalaFlags::SEALED), "sealed");
+        modifier(
+            self.kind == ScalaSymbolKind::Class && self.flags.contains(ScalaFlags::ABSTRACT),
+            "abstract",
+        );
+        modifier(self.flags.contains(ScalaFlags::LAZY), "lazy");
+        modifier(is_type && self.flags.contains(ScalaFlags::CASE), "case");
+        let keyword = match self.kind {
+            ScalaSymbolKind::Class => "class ",
+            ScalaSymbolKind::Trait => "trait ",
+            ScalaSymbolKind::Object | ScalaSymbolKind::ObjectClass => "object ",
+            ScalaSymbolKind::Method => "def ",
+            ScalaSymbolKind::Value => "val ",
+            ScalaSymbolKind::Variable => "var ",
+            ScalaSymbolKind::TypeMember | ScalaSymbolKind::TypeAlias => "type ",
+            ScalaSymbolKind::Parameter | ScalaSymbolKind::TypeParameter => "",
+        };
+        text.push_str(keyword);
+        text.push_str(&self.name);
+        text.push_str(&self.signature);
+        text
+    }
+}
+
+/// The contents of a Scala 2 pickle: every symbol that the compiler recorded for a top-level
+/// class and its companion, including members, parameters and nested classes.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ScalaSignature {
+    pub major_version: u64,
+    pub minor_version: u64,
+    pub symbols: Vec<ScalaSymbol>,
+}
+
+impl ScalaSignature {
+    /// Finds and decodes the pickle in the class's `ScalaSignature` or `ScalaLongSignature`
+    /// annotation, or in the `ScalaSig` attribute for classes compiled by Scala 2.7 and older.
+    /// Returns `Ok(None)` for classes that have no pickle, including the classes that implement
+    /// objects, whose symbols are pickled with the companion class.
+    pub fn from_class(class: &ClassFile) -> Result<Option<ScalaSignature>, ParseError> {
+        let mut scala_sig = None;
+        for attr in &class.attributes {
+            match (attr.name.as_str(), attr.decoded()?) {
+                (_, AttributeData::RuntimeVisibleAnnotations(annotations)) => {
+                    for annotation in annotations {
+                        let long = match annotation.type_descriptor.as_str() {
+                            SCALA_SIGNATURE_DESCRIPTOR => false,
+                            SCALA_LONG_SIGNATURE_DESCRIPTOR => true,
+                            _ => continue,
+                        };
+                        let text = match annotation.elements.iter().find(|e| e.name == "bytes").map(|e| &e.value) {
+                            Some(AnnotationElementValue::StringConstant(text)) if !long => text.clone(),
+                            Some(AnnotationElementValue::ArrayValue(values)) if long => {
+                                let mut text = String::new();
+                                for value in values {
+                                    match value {
+                                        AnnotationElementValue::StringConstant(x) => text.push_str(x),
+                                        _ => fail!("Unexpected element type in ScalaLongSignature bytes"),
+                                    }
+                                }
+                                text
+                            }
+                            _ => fail!("Missing or invalid bytes element in {}", annotation.type_descriptor),
+                        };
+                        let pickle = decode_signature_bytes(&text);
+                        return Self::from_bytes(&pickle).map(Some).map_err(|e| err!(e, "Scala signature"));
+                    }
+                }
+                ("ScalaSig", AttributeData::Other(bytes)) => scala_sig = Some(bytes),
+                _ => (),
+            }
+        }
+        // Newer compilers write a ScalaSig attribute with no entries as a marker
+        if let Some(bytes) = scala_sig {
+            let signature = Self::from_bytes(bytes).map_err(|e| err!(e, "ScalaSig attribute"))?;
+            if !signature.symbols.is_empty() {
+                return Ok(Some(signature));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Decodes a pickle that has already been extracted from its annotation or attribute.
+    pub fn from_bytes(pickle: &[u8]) -> Result<ScalaSignature, ParseError> {
+        let unpickler = Unpickler::new(pickle)?;
+        let mut symbols = Vec::new();
+        for i in 0..unpickler.entries.len() {
+            if let Some(symbol) = unpickler.symbol(i).map_err(|e| err!(e, "entry {}", i))? {
+                symbols.push(symbol);
+            }
+        }
+        Ok(ScalaSignature {
+            major_version: unpickler.major_version,
+            minor_version: unpickler.minor_version,
+            symbols,
+        })
+    }
+
+    pub fn symbols_of_kind(&self, kind: ScalaSymbolKind) -> impl Iterator<Item = &ScalaSymbol> {
+        self.symbols.iter().filter(move |s| s.kind == kind)
+    }
+}
+
+// The signature bytes are stored 7 bits per char, with each group incremented so that zero
+// bytes only appear as the two-byte modified UTF-8 form of \0.
+fn decode_signature_bytes(text: &str) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    let mut buffer = 0u32;
+    let mut bits = 0;
+    for c in text.chars() {
+        buffer |= ((c as u32 + 0x7f) & 0x7f) << bits;
+        bits += 7;
+        if bits >= 8 {
+            bytes.push(buffer as u8);
+            buffer >>= 8;
+            bits -= 8;
+        }
+    }
+    if bits > 0 {
+        bytes.push(buffer as u8);
+    }
+    bytes
+}
+
+// A natural number in a Scala 2 pickle: big-endian groups of 7 bits, with the high bit set on
+// every byte but the last.
+fn read_nat(bytes: &[u8], ix: &mut usize) -> Result<u64, ParseError> {
+    let mut result = 0u64;
+    for _ in 0..10 {
+        let byte = match bytes.get(*ix) {
+            Some(byte) => *byte,
+            None => fail!("Unexpected end of pickle at index {}", *ix),
+        };
+        *ix += 1;
+        result = (result << 7) | u64::from(byte & 0x7f);
+        if byte & 0x80 == 0 {
+            return Ok(result);
+        }
+    }
+    fail!("Invalid natural number ending at index {}", *ix)
+}
+
+struct Entry {
+    tag: u8,
+    start: usize,
+    end: usize,
+}
+
+struct Unpickler<'a> {
+    bytes: &'a [u8],
+    major_version: u64,
+    minor_version: u64,
+    entries: Vec<Entry>,
+}
+
+// The fields common to all symbol entries other than external references.
+struct SymbolInfo {
+    name: usize,
+    owner: usize,
+    flags: ScalaFlags,
+    private_within: Option<usize>,
+    info: usize,
+}
+
+impl<'a> Unpickler<'a> {
+    fn new(bytes: &'a [u8]) -> Result<Self, ParseError> {
+        let mut ix = 0;
+        let major_version = read_nat(bytes, &mut ix)?;
+        let minor_version = read_nat(bytes, &mut ix)?;
+        if major_version != PICKLE_MAJOR_VERSION {
+            fail!("Unsupported pickle version {}.{}", major_version, minor_version);
+        }
+        let count = read_nat(bytes, &mut ix)?;
+        let mut entries = Vec::new();
+        for i in 0..count {
+            let tag = match bytes.get(ix) {
+                Some(tag) => *tag,
+                None => fail!("Unexpected end of pickle reading entry {}", i),
+            };
+            ix += 1;
+            let length = read_nat(bytes, &mut ix)?;
+            if ((bytes.len() - ix) as u64) < length {
+                fail!("Unexpected end of pickle reading entry {}", i);
+            }
+            entries.push(Entry {
+                tag,
+                start: ix,
+                end: ix + length as usize,
+            });
+            ix += length as usize;
+        }
+        Ok(Unpickler {
+            bytes,
+            major_version,
+            minor_version,
+            entries,
+        })
+    }
+
+    fn entry(&self, index: u64) -> Result<&Entry, ParseError> {
+        match usize::try_from(index).ok().and_then(|i| self.entries.get(i)) {
+            Some(entry) => Ok(entry),
+            None => fail!("Pickle entry reference {} out of range", index),
+        }
+    }
+
+    // Returns the references that make up the given entry.
+    fn refs(&self, entry: &Entry) -> Result<Vec<usize>, ParseError> {
+        let mut ix = entry.start;
+        let mut refs = Vec::new();
+        while ix < entry.end {
+            let index = read_nat(&self.bytes[..entry.end], &mut ix)?;
+            self.entry(index)?;
+            refs.push(index as usize);
+        }
+        Ok(refs)
+    }
+
+    fn name(&self, index: usize) -> Result<String, ParseError> {
+        let entry = &self.entries[index];
+        if entry.tag != TERM_NAME && entry.tag != TYPE_NAME {
+            fail!("Expected a name at pickle entry {} but found tag {}", index, entry.tag);
+        }
+        match std::str::from_utf8(&self.bytes[entry.start..entry.end]) {
+            Ok(name) => Ok(decode_name(name)),
+            Err(_) => fail!("Invalid UTF-8 in name at pickle entry {}", index),
+        }
+    }
+
+    fn is_symbol(&self, index: usize) -> bool {
+        (NONE_SYM..=EXT_MOD_CLASS_REF).contains(&self.entries[index].tag)
+    }
+
+    fn symbol_info(&self, entry: &Entry) -> Result<SymbolInfo, ParseError> {
+        let bytes = &self.bytes[..entry.end];
+        let mut ix = entry.start;
+        let read_ref = |ix: &mut usize| -> Result<usize, ParseError> {
+            let index = read_nat(bytes, ix)?;
+            self.entry(index)?;
+            Ok(index as usize)
+        };
+        let name = read_ref(&mut ix)?;
+        let owner = read_ref(&mut ix)?;
+        let flags = ScalaFlags::from_bits_truncate(read_nat(bytes, &mut ix)?);
+        let mut info = read_ref(&mut ix)?;
+        let mut private_within = None;
+        if self.is_symbol(info) {
+            private_within = Some(info);
+            info = read_ref(&mut ix)?;
+        }
+        Ok(SymbolInfo {
+            name,
+            owner,
+            flags,
+            private_within,
+            info,
+        })
+    }
+
+    // Returns the symbol defined by the given entry, or None if the entry is not a symbol
+    // definition.
+    fn symbol(&self, index: usize) -> Result<Option<ScalaSymbol>, ParseError> {
+        let entry = &self.entries[index];
+        if !(TYPE_SYM..=VAL_SYM).contains(&entry.tag) {
+            return Ok(None);
+        }
+        let info = self.symbol_info(entry)?;
+        let kind = match entry.tag {
+            TYPE_SYM if info.flags.contains(ScalaFlags::PARAM) => ScalaSymbolKind::TypeParameter,
+            TYPE_SYM => ScalaSymbolKind::TypeMember,
+            ALIAS_SYM => ScalaSymbolKind::TypeAlias,
+            CLASS_SYM if info.flags.contains(ScalaFlags::MODULE) => ScalaSymbolKind::ObjectClass,
+            CLASS_SYM if info.flags.contains(ScalaFlags::TRAIT) => ScalaSymbolKind::Trait,
+            CLASS_SYM => ScalaSymbolKind::Class,
+            MODULE_SYM => ScalaSymbolKind::Object,
+            _ if info.flags.contains(ScalaFlags::PARAM) => ScalaSymbolKind::Parameter,
+            _ if info.flags.contains(ScalaFlags::METHOD) => ScalaSymbolKind::Method,
+            _ if info.flags.contains(ScalaFlags::MUTABLE) => ScalaSymbolKind::Variable,
+            _ => ScalaSymbolKind::Value,
+        };
+        let signature = match kind {
+            // The info of an object is the type of its class, which has its own entry
+            ScalaSymbolKind::Object => String::new(),
+            ScalaSymbolKind::TypeAlias => format!(" = {}", self.type_(info.info, 0)?),
+            ScalaSymbolKind::Method => self.method_type(info.info, 0)?,
+            _ => match self.entries[info.info].tag {
+                CLASS_INFO_TPE | POLY_TPE | TYPE_BOUNDS_TPE => self.type_(info.info, 0)?,
+                _ => format!(": {}", self.type_(info.info, 0)?),
+            },
+        };
+        Ok(Some(ScalaSymbol {
+            kind,
+            name: self.name(info.name)?,
+            owner: self.full_name(info.owner, 0)?,
+            flags: info.flags,
+            private_within: match info.private_within {
+                Some(index) => self.full_name(index, 0)?,
+                None => None,
+            },
+            signature,
+        }))
+    }
+
+    // Returns the fully qualified name of the given symbol, or None for the root and empty
+    // packages.
+    fn full_name(&self, index: usize, depth: usize) -> Result<Option<String>, ParseError> {
+        if depth >= MAX_DEPTH {
+            fail!("Exceeded the maximum nesting depth of {} for Scala symbol owners", MAX_DEPTH);
+        }
+        let entry = &self.entries[index];
+        let (name, owner) = match entry.tag {
+            NONE_SYM => return Ok(None),
+            EXT_REF | EXT_MOD_CLASS_REF => {
+                let refs = self.refs(entry)?;
+                match refs[..] {
+                    [name] => (name, None),
+                    [name, owner, ..] => (name, Some(owner)),
+                    [] => fail!("Missing name of external reference at pickle entry {}", index),
+                }
+            }
+            TYPE_SYM..=VAL_SYM => {
+                let info = self.symbol_info(entry)?;
+                (info.name, Some(info.owner))
+            }
+            tag => fail!("Expected a symbol at pickle entry {} but found tag {}", index, tag),
+        };
+        let name = self.name(name)?;
+        if name == "<root>" || name == "<empty>" || name == "_root_" {
+            return Ok(None);
+        }
+        match owner {
+            Some(owner) => match self.full_name(owner, depth + 1)? {
+                Some(owner) => Ok(Some(format!("{}.{}", owner, name))),
+                None => Ok(Some(name)),
+            },
+            None => Ok(Some(name)),
+        }
+    }
+
+    // Returns the name to use for a symbol in a type: the simple name for type parameters and
+    // members, and the fully qualified name for everything else.
+    fn type_symbol_name(&self, index: usize, depth: usize) -> Result<String, ParseError> {
+        let entry = &self.entries[index];
+        if entry.tag == TYPE_SYM {
+            return self.name(self.symbol_info(entry)?.name);
+        }
+        Ok(self.full_name(index, depth)?.unwrap_or_else(|| "_root_".to_string()))
+    }
+
+    // Renders a method type, which continues with further parameter lists or a result type.
+    fn method_type(&self, index: usize, depth: usize) -> Result<String, ParseError> {
+        match self.entries[index].tag {
+            METHOD_TPE | IMPLICIT_METHOD_TPE | POLY_TPE => self.type_(index, depth),
+            _ => Ok(format!(": {}", self.type_(index, depth)?)),
+        }
+    }
+
+    fn parameters(&self, params: &[usize], depth: usize) -> Result<Vec<String>, ParseError> {
+        let mut result = Vec::with_capacity(params.len());
+        for param in params {
+            let entry = &self.entries[*param];
+            if !(TYPE_SYM..=VAL_SYM).contains(&entry.tag) {
+                fail!("Expected a parameter at pickle entry {} but found tag {}", param, entry.tag);
+            }
+            let info = self.symbol_info(entry)?;
+            let name = self.name(info.name)?;
+            let text = match self.entries[info.info].tag {
+                TYPE_BOUNDS_TPE => format!("{}{}", name, self.type_(info.info, depth)?),
+                _ => format!("{}: {}", name, self.type_(info.info, depth)?),
+            };
+            let variance = if info.flags.contains(ScalaFlags::COVARIANT) && entry.tag == TYPE_SYM {
+                "+"
+            } else if info.flags.contains(ScalaFlags::CONTRAVARIANT) && entry.tag == TYPE_SYM {
+                "-"
+            } else {
+                ""
+            };
+            let implicit = if result.is_empty() && info.flags.contains(ScalaFlags::IMPLICIT) { "implicit " } else { "" };
+            result.push(format!("{}{}{}", implicit, variance, text));
+        }
+        Ok(result)
+    }
+
+    fn type_(&self, index: usize, depth: usize) -> Result<String, ParseError> {
+        if depth >= MAX_DEPTH {
+            fail!("Exceeded the maximum nesting depth of {} for Scala types", MAX_DEPTH);
+        }
+        let depth = depth + 1;
+        let entry = &self.entries[index];
+        let refs = || self.refs(entry);
+        let first = |refs: &[usize]| match refs.first() {
+            Some(first) => Ok(*first),
+            None => fail!("Missing reference in type at pickle entry {}", index),
+        };
+        Ok(match entry.tag {
+            NO_TPE | NO_PREFIX_TPE => String::new(),
+            THIS_TPE => format!("{}.this.type", self.type_symbol_name(first(&refs()?)?, depth)?),
+            SINGLE_TPE => match refs()?[..] {
+                [_, sym, ..] => format!("{}.type", self.type_symbol_name(sym, depth)?),
+                _ => fail!("Missing symbol in singleton type at pickle entry {}", index),
+            },
+            SUPER_TPE => "super".to_string(),
+            CONSTANT_TPE => self.literal(first(&refs()?)?, depth)?,
+            TYPE_REF_TPE => {
+                let refs = refs()?;
+                let (sym, args) = match &refs[..] {
+                    [_, sym, args @ ..] => (*sym, args),
+                    _ => fail!("Missing symbol in type reference at pickle entry {}", index),
+                };
+                let name = self.type_symbol_name(sym, depth)?;
+                let args = args.iter().map(|arg| self.type_(*arg, depth)).collect::<Result<Vec<_>, _>>()?;
+                match (name.as_str(), &args[..]) {
+                    ("scala.<byname>", [arg]) => format!("=> {}", arg),
+                    ("scala.<repeated>", [arg]) => format!("{}*", arg),
+                    (_, []) => name,
+                    _ => format!("{}[{}]", name, args.join(", ")),
+                }
+            }
+            TYPE_BOUNDS_TPE => match refs()?[..] {
+                [lo, hi] => {
+                    let mut text = String::new();
+                    let lo = self.type_(lo, depth)?;
+                    if lo != "scala.Nothing" {
+                        text.push_str(&format!(" >: {}", lo));
+                    }
+                    let hi = self.type_(hi, depth)?;
+                    if hi != "scala.Any" {
+                        text.push_str(&format!(" <: {}", hi));
+                    }
+                    text
+                }
+                _ => fail!("Invalid type bounds at pickle entry {}", index),
+            },
+            REFINED_TPE | CLASS_INFO_TPE => {
+                let refs = refs()?;
+                let parents = refs
+                    .iter()
+                    .skip(1)
+                    .map(|parent| self.type_(*parent, depth))
+                    .collect::<Result<Vec<_>, _>>()?
+                    .join(" with ");
+                match entry.tag {
+                    CLASS_INFO_TPE if parents.is_empty() => String::new(),
+                    CLASS_INFO_TPE => format!(" extends {}", parents),
+                    _ => parents,
+                }
+            }
+            METHOD_TPE | IMPLICIT_METHOD_TPE => {
+                let refs = refs()?;
+                let result = first(&refs)?;
+                let mut params = self.parameters(&refs[1..], depth)?;
+                if entry.tag == IMPLICIT_METHOD_TPE && !params.is_empty() && !params[0].starts_with("implicit ") {
+                    params[0] = format!("implicit {}", params[0]);
+                }
+                format!("({}){}", params.join(", "), self.method_type(result, depth)?)
+            }
+            POLY_TPE => {
+                let refs = refs()?;
+                let result = first(&refs)?;
+                let type_params = self.parameters(&refs[1..], depth)?;
+                let result = match self.entries[result].tag {
+                    CLASS_INFO_TPE => self.type_(result, depth)?,
+                    _ => self.method_type(result, depth)?,
+                };
+                if type_params.is_empty() {
+                    result
+                } else {
+                    format!("[{}]{}", type_params.join(", "), result)
+                }
+            }
+            ANNOTATED_TPE | EXISTENTIAL_TPE => self.type_(first(&refs()?)?, depth)?,
+            tag => fail!("Unexpected tag {} for type at pickle entry {}", tag, index),
+        })
+    }
+
+    fn literal(&self, index: usize, depth: usize) -> Result<String, ParseError> {
+        let entry = &self.entries[index];
+        let value = || {
+            // Literal values are stored as big-endian, sign-extended integers of the entry length
+            let bytes = &self.bytes[entry.start..entry.end];
+            if bytes.len() > 8 {
+                fail!("Literal too long at pickle entry {}", index);
+            }
+            let mut value = 0i64;
+            for byte in bytes {
+                value = (value << 8) | i64::from(*byte);
+            }
+            let shift = 64 - 8 * bytes.len() as u32;
+            Ok(value.checked_shl(shift).map_or(0, |x| x >> shift))
+        };
+        Ok(match entry.tag {
+            LITERAL_UNIT => "()".to_string(),
+            LITERAL_BOOLEAN => (value()? != 0).to_string(),
+            LITERAL_BYTE | LITERAL_SHORT | LITERAL_INT => value()?.to_string(),
+            LITERAL_LONG => format!("{}L", value()?),
+            LITERAL_CHAR => format!("{:?}", char::from_u32(value()? as u32).unwrap_or(char::REPLACEMENT_CHARACTER)),
+            LITERAL_FLOAT => format!("{:?}F", f32::from_bits(value()? as u32)),
+            LITERAL_DOUBLE => format!("{:?}", f64::from_bits(value()? as u64)),
+            LITERAL_STRING => format!("{:?}", self.name(self.first_ref(entry, index)?)?),
+            LITERAL_NULL => "null".to_string(),
+            LITERAL_CLASS => format!("classOf[{}]", self.type_(self.first_ref(entry, index)?, depth)?),
+            LITERAL_ENUM => self.full_name(self.first_ref(entry, index)?, depth)?.unwrap_or_default(),
+            tag => fail!("Unexpected tag {} for literal at pickle entry {}", tag, index),
+        })
+    }
+
+    fn first_ref(&self, entry: &Entry, index: usize) -> Result<usize, ParseError> {
+        match self.refs(entry)?.first() {
+            Some(first) => Ok(*first),
+            None => fail!("Missing reference at pickle entry {}", index),
+        }
+    }
+}
+
+// Decodes the names that the compiler gives to operators, e.g. `$plus$eq` to `+=`.
+fn decode_name(name: &str) -> String {
+    const OPERATORS: &[(&str, char)] = &[
+        ("tilde", '~'),
+        ("eq", '='),
+        ("less", '<'),
+        ("greater", '>'),
+        ("bang", '!'),
+        ("hash", '#'),
+        ("percent", '%'),
+        ("up", '^'),
+        ("amp", '&'),
+        ("bar", '|'),
+        ("times", '*'),
+        ("div", '/'),
+        ("plus", '+'),
+        ("minus", '-'),
+        ("colon", ':'),
+        ("bslash", '\\'),
+        ("qmark", '?'),
+        ("at", '@'),
+    ];
+    if !name.contains('$') {
+        return name.to_string();
+    }
+    let mut result = String::new();
+    let mut rest = name;
+    'outer: while let Some(position) = rest.find('$') {
+        result.push_str(&rest[..position]);
+        rest = &rest[position..];
+        for (code, op) in OPERATORS {
+            if rest[1..].starts_with(code) {
+                result.push(*op);
+                rest = &rest[1 + code.len()..];
+                continue 'outer;
+            }
+        }
+        // $uXXXX for other characters
+        if rest.len() >= 6 && rest.as_bytes()[1] == b'u' {
+            if let Some(c) = u32::from_str_radix(&rest[2..6], 16).ok().and_then(char::from_u32) {
+                result.push(c);
+                rest = &rest[6..];
+                continue;
+            }
+        }
+        result.push('$');
+        rest = &rest[1..];
+    }
+    result.push_str(rest);
+    result
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ScalaInlineMethod {
+    pub name: String,
+    pub descriptor: String,
+    pub effectively_final: bool,
+    pub annotated_inline: bool,
+    pub annotated_no_inline: bool,
+}
+
+/// The contents of the `ScalaInlineInfo` attribute, which the Scala 2.12+ optimizer uses to
+/// decide what can be inlined across compilation units.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ScalaInlineInfo {
+    pub version: u8,
+    pub effectively_final: bool,
+    /// The name and descriptor of the single abstract method, for classes that have one.
+    pub sam: Option<(String, String)>,
+    pub methods: Vec<ScalaInlineMethod>,
+}
+
+impl ScalaInlineInfo {
+    pub fn from_class(class: &ClassFile) -> Result<Option<ScalaInlineInfo>, ParseError> {
+        for attr in &class.attributes {
+            if attr.name != "ScalaInlineInfo" {
+                continue;
+            }
+            if let AttributeData::Other(bytes) = attr.decoded()? {
+                let pool = ConstantPoolReader::unlimited(&class.constant_pool);
+                return Self::read(bytes, &pool).map(Some).map_err(|e| err!(e, "ScalaInlineInfo attribute"));
+            }
+        }
+        Ok(None)
+    }
+
+    fn read(bytes: &[u8], pool: &ConstantPoolReader) -> Result<ScalaInlineInfo, ParseError> {
+        let mut ix = 0;
+        let version = read_u1(bytes, &mut ix)?;
+        if version != 1 {
+            fail!("Unsupported version {}", version);
+        }
+        let flags = read_u1(bytes, &mut ix)?;
+        // Flag 2 marked classes with a self type in older compilers, followed by its name
+        if flags & 2 != 0 {
+            read_u2(bytes, &mut ix)?;
+        }
+        let sam = if flags & 4 != 0 {
+            let name = read_cp_utf8(bytes, &mut ix, pool).map_err(|e| err!(e, "SAM name"))?;
+            let descriptor = read_cp_utf8(bytes, &mut ix, pool).map_err(|e| err!(e, "SAM descriptor"))?;
+            Some((name, descriptor))
+        } else {
+            None
+        };
+        let count = read_u2(bytes, &mut ix)?;
+        let mut methods = Vec::with_capacity(crate::bounded_capacity(count, bytes, ix));
+        for i in 0..count {
+            let name = read_cp_utf8(bytes, &mut ix, pool).map_err(|e| err!(e, "name of method {}", i))?;
+            let descriptor = read_cp_utf8(bytes, &mut ix, pool).map_err(|e| err!(e, "descriptor of method {}", i))?;
+            let flags = read_u1(bytes, &mut ix)?;
+            methods.push(ScalaInlineMethod {
+                name,
+                descriptor,
+                effectively_final: flags & 1 != 0,
+                annotated_inline: flags & 4 != 0,
+                annotated_no_inline: flags & 8 != 0,
+            });
+        }
+        if ix != bytes.len() {
+            fail!("Found {} unexpected trailing bytes", bytes.len() - ix);
+        }
+        Ok(ScalaInlineInfo {
+            version,
+            effectively_final: flags & 1 != 0,
+            sam,
+            methods,
+        })
+    }
+}
+
+/// The UUID that links a Scala 3 class file to the `.tasty` file holding its symbols.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TastyUuid(pub [u8; 16]);
+
+impl fmt::Display for TastyUuid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, byte) in self.0.iter().enumerate() {
+            if i == 4 || i == 6 || i == 8 || i == 10 {
+                write!(f, "-")?;
+            }
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+impl TastyUuid {
+    /// Returns the UUID from the class's `TASTY` attribute, or `Ok(None)` if it has none.
+    pub fn from_class(class: &ClassFile) -> Result<Option<TastyUuid>, ParseError> {
+        for attr in &class.attributes {
+            if attr.name != "TASTY" {
+                continue;
+            }
+            if let AttributeData::Other(bytes) = attr.decoded()? {
+                // Early Scala 3 compilers put the whole TASTy file in the attribute
+                if bytes.starts_with(&TASTY_MAGIC) {
+                    return TastyHeader::parse(bytes).map(|header| Some(header.uuid)).map_err(|e| err!(e, "TASTY attribute"));
+                }
+                match <[u8; 16]>::try_from(&bytes[..]) {
+                    Ok(uuid) => return Ok(Some(TastyUuid(uuid))),
+                    Err(_) => fail!("Unexpected TASTY attribute length {}", bytes.len()),
+                }
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// The header at the start of a `.tasty` file.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TastyHeader {
+    pub major_version: u64,
+    pub minor_version: u64,
+    /// Non-zero for files written by experimental compiler releases.
+    pub experimental_version: u64,
+    /// The version of the compiler that wrote the file, e.g. `Scala 3.3.1`.
+    pub tooling_version: String,
+    pub uuid: TastyUuid,
+}
+
+impl TastyHeader {
+    pub fn parse(bytes: &[u8]) -> Result<TastyHeader, ParseError> {
+        if !bytes.starts_with(&TASTY_MAGIC) {
+            fail!("Invalid TASTy magic number");
+        }
+        let mut ix = TASTY_MAGIC.len();
+        let major_version = read_tasty_nat(bytes, &mut ix)?;
+        let minor_version = read_tasty_nat(bytes, &mut ix)?;
+        // Versions before 28 (Scala 3.0) had no experimental or tooling versions
+        let (experimental_version, tooling_version) = if major_version >= 28 {
+            let experimental_version = read_tasty_nat(bytes, &mut ix)?;
+            let length = read_tasty_nat(bytes, &mut ix)?;
+            if ((bytes.len() - ix) as u64) < length {
+                fail!("Unexpected end of TASTy header reading tooling version");
+            }
+            let tooling = &bytes[ix..ix + length as usize];
+            ix += length as usize;
+            match std::str::from_utf8(tooling) {
+                Ok(tooling) => (experimental_version, tooling.to_string()),
+                Err(_) => fail!("Invalid UTF-8 in TASTy tooling version"),
+            }
+        } else {
+            (0, String::new())
+        };
+        let uuid = match bytes.get(ix..ix + 16).map(<[u8; 16]>::try_from) {
+            Some(Ok(uuid)) => TastyUuid(uuid),
+            _ => fail!("Unexpected end of TASTy header reading UUID"),
+        };
+        Ok(TastyHeader {
+            major_version,
+            minor_version,
+            experimental_version,
+            tooling_version,
+            uuid,
+        })
+    }
+}
+
+// A natural number in a TASTy file: big-endian groups of 7 bits, with the high bit set on the
+// last byte only. This is the opposite convention to Scala 2 pickles.
+fn read_tasty_nat(bytes: &[u8], ix: &mut usize) -> Result<u64, ParseError> {
+    let mut result = 0u64;
+    for _ in 0..10 {
+        let byte = match bytes.get(*ix) {
+            Some(byte) => *byte,
+            None => fail!("Unexpected end of TASTy data at index {}", *ix),
+        };
+        *ix += 1;
+        result = (result << 7) | u64::from(byte & 0x7f);
+        if byte & 0x80 != 0 {
+            return Ok(result);
+        }
+    }
+    fail!("Invalid natural number ending at index {}", *ix)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::attributes::{Annotation, AnnotationElement, AttributeInfo};
+
+    #[derive(Default)]
+    struct Pickle {
+        entries: Vec<(u8, Vec<u8>)>,
+    }
+
+    fn nat(out: &mut Vec<u8>, value: u64) {
+        let mut groups = vec![(value & 0x7f) as u8];
+        let mut rest = value >> 7;
+        while rest != 0 {
+            groups.push((rest & 0x7f) as u8 | 0x80);
+            rest >>= 7;
+        }
+        out.extend(groups.iter().rev());
+    }
+
+    impl Pickle {
+        fn reserve(&mut self) -> usize {
+            self.entries.push((0, Vec::new()));
+            self.entries.len() - 1
+        }
+
+        fn set(&mut self, index: usize, tag: u8, refs: &[u64]) -> usize {
+            let mut data = Vec::new();
+            for r in refs {
+                nat(&mut data, *r);
+            }
+            self.entries[index] = (tag, data);
+            index
+        }
+
+        fn add(&mut self, tag: u8, refs: &[usize]) -> usize {
+            let index = self.reserve();
+            self.set(index, tag, &refs.iter().map(|r| *r as u64).collect::<Vec<_>>())
+        }
+
+        fn name(&mut self, tag: u8, name: &str) -> usize {
+            self.entries.push((tag, name.as_bytes().to_vec()));
+            self.entries.len() - 1
+        }
+
+        fn external(&mut self, tag: u8, name: &str, owner: Option<usize>) -> usize {
+            let name_tag = if tag == EXT_REF { TYPE_NAME } else { TERM_NAME };
+            let name = self.name(name_tag, name);
+            match owner {
+                Some(owner) => self.add(tag, &[name, owner]),
+                None => self.add(tag, &[name]),
+            }
+        }
+
+        fn type_ref(&mut self, sym: usize, args: &[usize]) -> usize {
+            let no_prefix = self.add(NO_PREFIX_TPE, &[]);
+            let mut refs = vec![no_prefix, sym];
+            refs.extend(args);
+            self.add(TYPE_REF_TPE, &refs)
+        }
+
+        // A reference to a class through the this-type of its package, as scalac writes them.
+        fn this_type_ref(&mut self, package: usize, sym: usize) -> usize {
+            let prefix = self.add(THIS_TPE, &[package]);
+            self.add(TYPE_REF_TPE, &[prefix, sym])
+        }
+
+        fn to_bytes(&self) -> Vec<u8> {
+            let mut out = Vec::new();
+            nat(&mut out, 5);
+            nat(&mut out, 0);
+            nat(&mut out, self.entries.len() as u64);
+            for (tag, data) in &self.entries {
+                out.push(*tag);
+                nat(&mut out, data.len() as u64);
+                out.extend(data);
+            }
+            out
+        }
+    }
+
+    // The pickle for:
+    //   package p
+    //   class Foo[+A] { def bar(y: A): List[A] }
+    //   object Foo
+    //   trait T { private[p] def +: Int }
+    fn pickle() -> Vec<u8> {
+        let mut p = Pickle::default();
+        let scala = p.external(EXT_MOD_CLASS_REF, "scala", None);
+        let any_ref = p.external(EXT_REF, "AnyRef", Some(scala));
+        let any = p.external(EXT_REF, "Any", Some(scala));
+        let nothing = p.external(EXT_REF, "Nothing", Some(scala));
+        let list = p.external(EXT_REF, "List", Some(scala));
+        let int = p.external(EXT_REF, "Int", Some(scala));
+        let package = p.external(EXT_MOD_CLASS_REF, "p", None);
+        let any_ref_type = p.type_ref(any_ref, &[]);
+
+        let foo_name = p.name(TYPE_NAME, "Foo");
+        let foo = p.reserve();
+        let a_name = p.name(TYPE_NAME, "A");
+        let a = p.reserve();
+        let foo_info = p.add(CLASS_INFO_TPE, &[foo, any_ref_type]);
+        let foo_poly = p.add(POLY_TPE, &[foo_info, a]);
+        p.set(foo, CLASS_SYM, &[foo_name as u64, package as u64, 0, foo_poly as u64]);
+        let nothing_type = p.type_ref(nothing, &[]);
+        let any_type = p.type_ref(any, &[]);
+        let bounds = p.add(TYPE_BOUNDS_TPE, &[nothing_type, any_type]);
+        let flags = (ScalaFlags::PARAM | ScalaFlags::COVARIANT).bits();
+        p.set(a, TYPE_SYM, &[a_name as u64, foo as u64, flags, bounds as u64]);
+
+        let bar_name = p.name(TERM_NAME, "bar");
+        let bar = p.reserve();
+        let y_name = p.name(TERM_NAME, "y");
+        let a_type = p.type_ref(a, &[]);
+        let y = p.add(VAL_SYM, &[y_name, bar, ScalaFlags::PARAM.bits() as usize, a_type]);
+        let list_type = p.type_ref(list, &[a_type]);
+        let bar_type = p.add(METHOD_TPE, &[list_type, y]);
+        p.set(bar, VAL_SYM, &[bar_name as u64, foo as u64, ScalaFlags::METHOD.bits(), bar_type as u64]);
+
+        let module_name = p.name(TERM_NAME, "Foo");
+        let module = p.reserve();
+        let module_class = p.reserve();
+        let module_class_type = p.type_ref(module_class, &[]);
+        let module_info = p.add(CLASS_INFO_TPE, &[module_class, any_ref_type]);
+        let flags = ScalaFlags::MODULE.bits();
+        p.set(module, MODULE_SYM, &[module_name as u64, package as u64, flags, module_class_type as u64]);
+        p.set(module_class, CLASS_SYM, &[foo_name as u64, package as u64, flags, module_info as u64]);
+
+        let t_name = p.name(TYPE_NAME, "T");
+        let t = p.reserve();
+        let t_info = p.add(CLASS_INFO_TPE, &[t, any_ref_type]);
+        let flags = (ScalaFlags::TRAIT | ScalaFlags::ABSTRACT | ScalaFlags::INTERFACE).bits();
+        p.set(t, CLASS_SYM, &[t_name as u64, package as u64, flags, t_info as u64]);
+        let plus_name = p.name(TERM_NAME, "$plus");
+        let int_type = p.type_ref(int, &[]);
+        let plus_type = p.add(POLY_TPE, &[int_type]);
+        let flags = (ScalaFlags::METHOD | ScalaFlags::DEFERRED).bits();
+        p.add(VAL_SYM, &[plus_name, t, flags as usize, package, plus_type]);
+        p.to_bytes()
+    }
+
+    // The pickle for the following class, with the entries scalac writes for it: the field and
+    // accessor for the class parameter, the constructor, and a constant value:
+    //   package demo
+    //   class Counter(val start: Int) { final val Max = -1; def next: Int = start + 1 }
+    fn counter_pickle() -> Vec<u8> {
+        let mut p = Pickle::default();
+        let counter_name = p.name(TYPE_NAME, "Counter");
+        let counter = p.reserve();
+        let demo = p.external(EXT_MOD_CLASS_REF, "demo", None);
+        let scala = p.external(EXT_MOD_CLASS_REF, "scala", None);
+        let any_ref = p.external(EXT_REF, "AnyRef", Some(scala));
+        let any_ref_type = p.this_type_ref(scala, any_ref);
+        let counter_info = p.add(CLASS_INFO_TPE, &[counter, any_ref_type]);
+        p.set(counter, CLASS_SYM, &[counter_name as u64, demo as u64, 0, counter_info as u64]);
+        let int = p.external(EXT_REF, "Int", Some(scala));
+        let int_type = p.this_type_ref(scala, int);
+
+        let flags = ScalaFlags::PRIVATE | ScalaFlags::LOCAL | ScalaFlags::PARAMACCESSOR;
+        let field_name = p.name(TERM_NAME, "start ");
+        p.add(VAL_SYM, &[field_name, counter, flags.bits() as usize, int_type]);
+        let flags = ScalaFlags::METHOD | ScalaFlags::STABLE | ScalaFlags::ACCESSOR | ScalaFlags::PARAMACCESSOR;
+        let start_name = p.name(TERM_NAME, "start");
+        let start_type = p.add(POLY_TPE, &[int_type]);
+        p.add(VAL_SYM, &[start_name, counter, flags.bits() as usize, start_type]);
+
+        let init_name = p.name(TERM_NAME, "<init>");
+        let init = p.reserve();
+        let param = p.add(VAL_SYM, &[start_name, init, ScalaFlags::PARAM.bits() as usize, int_type]);
+        let counter_type = p.this_type_ref(demo, counter);
+        let init_type = p.add(METHOD_TPE, &[counter_type, param]);
+        p.set(init, VAL_SYM, &[init_name as u64, counter as u64, ScalaFlags::METHOD.bits(), init_type as u64]);
+
+        p.entries.push((LITERAL_INT, vec![0xff]));
+        let minus_one = p.entries.len() - 1;
+        let constant = p.add(CONSTANT_TPE, &[minus_one]);
+        let flags = ScalaFlags::PRIVATE | ScalaFlags::LOCAL | ScalaFlags::FINAL;
+        let max_field_name = p.name(TERM_NAME, "Max ");
+        p.add(VAL_SYM, &[max_field_name, counter, flags.bits() as usize, constant]);
+        let flags = ScalaFlags::METHOD | ScalaFlags::STABLE | ScalaFlags::ACCESSOR | ScalaFlags::FINAL;
+        let max_name = p.name(TERM_NAME, "Max");
+        let max_type = p.add(POLY_TPE, &[constant]);
+        p.add(VAL_SYM, &[max_name, counter, flags.bits() as usize, max_type]);
+
+        let next_name = p.name(TERM_NAME, "next");
+        let next_type = p.add(POLY_TPE, &[int_type]);
+        p.add(VAL_SYM, &[next_name, counter, ScalaFlags::METHOD.bits() as usize, next_type]);
+        p.to_bytes()
+    }
+
+    // Packs bytes 7 bits per char, the way scalac writes the ScalaSignature annotation.
+    fn encode_signature_bytes(bytes: &[u8]) -> String {
+        let mut result = String::new();
+        let mut buffer = 0u32;
+        let mut bits = 0;
+        for byte in bytes {
+            buffer |= u32::from(*byte) << bits;
+            bits += 8;
+            while bits >= 7 {
+                result.push(char::from(((buffer & 0x7f) as u8 + 1) & 0x7f));
+                buffer >>= 7;
+                bits -= 7;
+            }
+        }
+        if bits > 0 {
+            result.push(char::from(((buffer & 0x7f) as u8 + 1) & 0x7f));
+        }
+        result
+    }
+
+    #[test]
+    fn test_scala_signature() {
+        let text = ".version 52 0\n.class p/Foo\n.end class\n";
+        let mut class = crate::assembly::parse_assembly(text).unwrap();
+        assert_eq!(ScalaSignature::from_class(&class).unwrap(), None);
+        class.attributes.push(AttributeInfo {
+            name: "RuntimeVisibleAnnotations".to_string(),
+            data: AttributeData::RuntimeVisibleAnnotations(vec![Annotation {
+                type_descriptor: SCALA_SIGNATURE_DESCRIPTOR.to_string(),
+                elements: vec![AnnotationElement {
+                    name: "bytes".to_string(),
+                    value: AnnotationElementValue::StringConstant(encode_signature_bytes(&pickle())),
+                }],
+            }]),
+        });
+        let signature = ScalaSignature::from_class(&class).unwrap().unwrap();
+        assert_eq!(signature, ScalaSignature::from_bytes(&pickle()).unwrap());
+        assert_eq!(signature.major_version, 5);
+
+        let declarations = |kind| signature.symbols_of_kind(kind).map(|s| (s.full_name(), s.declaration())).collect::<Vec<_>>();
+        let pair = |name: &str, declaration: &str| (name.to_string(), declaration.to_string());
+        assert_eq!(declarations(ScalaSymbolKind::Class), vec![pair("p.Foo", "class Foo[+A] extends scala.AnyRef")]);
+        assert_eq!(declarations(ScalaSymbolKind::TypeParameter), vec![pair("p.Foo.A", "A")]);
+        assert_eq!(declarations(ScalaSymbolKind::Parameter), vec![pair("p.Foo.bar.y", "y: A")]);
+        assert_eq!(
+            declarations(ScalaSymbolKind::Method),
+            vec![pair("p.Foo.bar", "def bar(y: A): scala.List[A]"), pair("p.T.+", "private[p] def +: scala.Int")]
+        );
+        assert_eq!(declarations(ScalaSymbolKind::Object), vec![pair("p.Foo", "object Foo")]);
+        assert_eq!(declarations(ScalaSymbolKind::ObjectClass), vec![pair("p.Foo", "object Foo extends scala.AnyRef")]);
+        assert_eq!(declarations(ScalaSymbolKind::Trait), vec![pair("p.T", "trait T extends scala.AnyRef")]);
+
+        assert!(ScalaSignature::from_bytes(&pickle()[..40]).is_err());
+        assert_eq!(decode_name("$plus$eq$u00E9x$"), "+=éx$");
+    }
+
+    #[test]
+    fn test_scala_signature_class_file() {
+        // Goes through the class file bytes, where scalac's zero chars are written as C0 80
+        let text = ".version 52 0\n.class public demo/Counter\n.super java/lang/Object\n.end class\n";
+        let mut class = crate::assembly::parse_assembly(text).unwrap();
+        let encoded = encode_signature_bytes(&counter_pickle());
+        assert!(encoded.contains('\0'));
+        class.attributes.push(AttributeInfo {
+            name: "RuntimeVisibleAnnotations".to_string(),
+            data: AttributeData::RuntimeVisibleAnnotations(vec![Annotation {
+                type_descriptor: SCALA_SIGNATURE_DESCRIPTOR.to_string(),
+                elements: vec![AnnotationElement {
+                    name: "bytes".to_string(),
+                    value: AnnotationElementValue::StringConstant(encoded),
+                }],
+            }]),
+        });
+        let bytes = crate::writer::write_class(&class).unwrap();
+        assert!(bytes.windows(2).any(|w| w == [0xc0, 0x80]));
+        let class = crate::parse_class(&bytes).unwrap();
+        let signature = ScalaSignature::from_class(&class).unwrap().unwrap();
+
+        let declarations = signature.symbols.iter().map(|s| (s.full_name(), s.declaration())).collect::<Vec<_>>();
+        let pair = |name: &str, declaration: &str| (name.to_string(), declaration.to_string());
+        assert_eq!(
+            declarations,
+            vec![
+                pair("demo.Counter", "class Counter extends scala.AnyRef"),
+                pair("demo.Counter.start ", "private[this] val start : scala.Int"),
+                pair("demo.Counter.start", "def start: scala.Int"),
+                pair("demo.Counter.<init>", "def <init>(start: scala.Int): demo.Counter"),
+                pair("demo.Counter.<init>.start", "start: scala.Int"),
+                pair("demo.Counter.Max ", "private[this] final val Max : -1"),
+                pair("demo.Counter.Max", "final def Max: -1"),
+                pair("demo.Counter.next", "def next: scala.Int"),
+            ]
+        );
+        let kinds = signature.symbols.iter().map(|s| s.kind).collect::<Vec<_>>();
+        assert_eq!(kinds[1..5], [ScalaSymbolKind::Value, ScalaSymbolKind::Method, ScalaSymbolKind::Method, ScalaSymbolKind::Parameter]);
+    }
+
+    #[test]
+    fn test_scala_inline_info() {
+        let text = ".version 52 0\n.class Foo\n.method public foo ()V\n.code stack 0 locals 1\nreturn\n.end code\n.end method\n.end class\n";
+        let mut class = crate::assembly::parse_assembly(text).unwrap();
+        let utf8_index = |value: &str| {
+            class
+                .constant_pool
+                .iter()
+                .position(|entry| matches!(&**entry, crate::constant_pool::ConstantPoolEntry::Utf8(s) if s == value))
+                .unwrap() as u16
+        };
+        let (name, descriptor) = (utf8_index("foo"), utf8_index("()V"));
+        let mut bytes = vec![1, 5];
+        for index in [name, descriptor, 1, name, descriptor] {
+            bytes.extend(index.to_be_bytes());
+        }
+        bytes.push(9);
+        class.attributes.push(AttributeInfo { name: "ScalaInlineInfo".to_string(), data: AttributeData::Other(bytes) });
+
+        let info = ScalaInlineInfo::from_class(&class).unwrap().unwrap();
+        assert!(info.effectively_final);
+        assert_eq!(info.sam, Some(("foo".to_string(), "()V".to_string())));
+        assert_eq!(info.methods.len(), 1);
+        let method = &info.methods[0];
+        assert_eq!((method.name.as_str(), method.descriptor.as_str()), ("foo", "()V"));
+        assert!(method.effectively_final && method.annotated_no_inline && !method.annotated_inline);
+    }
+
+    #[test]
+    fn test_tasty() {
+        let uuid = [0x12, 0x34, 0x56, 0x78, 0x9a, 0xbc, 0xde, 0xf0, 1, 2, 3, 4, 5, 6, 7, 8];
+        let mut header = TASTY_MAGIC.to_vec();
+        header.extend([28 | 0x80, 3 | 0x80, 0x80, 11 | 0x80]);
+        header.extend(b"Scala 3.3.1");
+        header.extend(uuid);
+        let parsed = TastyHeader::parse(&header).unwrap();
+        assert_eq!((parsed.major_version, parsed.minor_version, parsed.experimental_version), (28, 3, 0));
+        assert_eq!(parsed.tooling_version, "Scala 3.3.1");
+        assert_eq!(parsed.uuid.to_string(), "12345678-9abc-def0-0102-030405060708");
+        assert!(TastyHeader::parse(&header[..header.len() - 1]).is_err());
+
+        let text = ".version 52 0\n.class Foo\n.end class\n";
+        let mut class = crate::assembly::parse_assembly(text).unwrap();
+        assert_eq!(TastyUuid::from_class(&class).unwrap(), None);
+        class.attributes.push(AttributeInfo { name: "TASTY".to_string(), data: AttributeData::Other(uuid.to_vec()) });
+        assert_eq!(TastyUuid::from_class(&class).unwrap(), Some(parsed.uuid));
+        class.attributes[0].data = AttributeData::Other(header);
+        assert_eq!(TastyUuid::from_class(&class).unwrap(), Some(parsed.uuid));
+    }
+}
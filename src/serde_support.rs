@@ -0,0 +1,113 @@
+// Access flags are serialized as lists of flag names rather than as raw bits, so that
+// serialized classes are readable. Each flags type lists its own names, since some bits
+// have different meanings in different places.
+macro_rules! serde_flags {
+    ($flags:ident, $($name:ident),* $(,)?) => {
+        impl serde::Serialize for $flags {
+            fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                let mut names: Vec<&str> = Vec::new();
+                $(
+                    if self.contains($flags::$name) {
+                        names.push(stringify!($name));
+                    }
+                )*
+                names.serialize(serializer)
+            }
+        }
+
+        impl<'de> serde::Deserialize<'de> for $flags {
+            fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                let mut flags = $flags::empty();
+                for name in Vec::<String>::deserialize(deserializer)? {
+                    flags |= match name.as_str() {
+                        $(stringify!($name) => $flags::$name,)*
+                        _ => return Err(serde::de::Error::unknown_variant(&name, &[$(stringify!($name)),*])),
+                    };
+                }
+                Ok(flags)
+            }
+        }
+    };
+}
+
+// Floats that JSON and similar formats cannot represent are written as strings: "NaN",
+// "inf" and "-inf", or the raw bits in hex for NaNs other than the canonical one.
+macro_rules! serde_float {
+    ($module:ident, $float:ident, $width:expr) => {
+        pub(crate) mod $module {
+            use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+            pub(crate) fn serialize<S: Serializer>(value: &$float, serializer: S) -> Result<S::Ok, S::Error> {
+                if value.is_finite() || !serializer.is_human_readable() {
+                    value.serialize(serializer)
+                } else if value.is_nan() && value.to_bits() != $float::NAN.to_bits() {
+                    format!("0x{:0width$x}", value.to_bits(), width = $width).serialize(serializer)
+                } else {
+                    format!("{:?}", value).serialize(serializer)
+                }
+            }
+
+            pub(crate) fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<$float, D::Error> {
+                #[derive(Deserialize)]
+                #[serde(untagged)]
+                enum Repr {
+                    Number($float),
+                    Text(String),
+                }
+                if !deserializer.is_human_readable() {
+                    return $float::deserialize(deserializer);
+                }
+                match Repr::deserialize(deserializer)? {
+                    Repr::Number(value) => Ok(value),
+                    Repr::Text(text) => {
+                        let value = match text.strip_prefix("0x") {
+                            Some(digits) => {
+                                let bits = match $width {
+                                    8 => u32::from_str_radix(digits, 16).map(u64::from),
+                                    _ => u64::from_str_radix(digits, 16),
+                                };
+                                bits.map(|bits| $float::from_bits(bits as _)).ok()
+                            }
+                            None => text.parse().ok().filter(|value: &$float| !value.is_finite()),
+                        };
+                        value.ok_or_else(|| serde::de::Error::custom(format!("invalid floating point value {}", text)))
+                    }
+                }
+            }
+        }
+    };
+}
+
+serde_float!(float, f32, 8);
+serde_float!(double, f64, 16);
+
+// The constant pool of a class is written in the indexed form it has in the class file. To
+// read it back, it is written out in the class file format and parsed again, which checks
+// that the entries refer to each other correctly.
+pub(crate) mod constant_pool {
+    use std::rc::Rc;
+
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    use crate::constant_pool::{indexed_constant_pool, read_constant_pool, ConstantPoolEntry, IndexedEntry};
+    use crate::writer::ConstantPoolWriter;
+
+    pub(crate) fn serialize<S: Serializer>(pool: &[Rc<ConstantPoolEntry>], serializer: S) -> Result<S::Ok, S::Error> {
+        indexed_constant_pool(pool).serialize(serializer)
+    }
+
+    pub(crate) fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<Rc<ConstantPoolEntry>>, D::Error> {
+        let entries = Vec::<Option<IndexedEntry>>::deserialize(deserializer)?;
+        if entries.is_empty() {
+            return Ok(Vec::new());
+        }
+        if entries[0].is_some() || entries.len() > usize::from(u16::MAX) {
+            return Err(serde::de::Error::custom("invalid constant pool"));
+        }
+        let mut bytes = Vec::new();
+        ConstantPoolWriter::from_entries(entries).write(&mut bytes);
+        // The entries were checked against the class version when the class was parsed, so
+        // all entry types are accepted here
+        read_constant_pool(&bytes, &mut 0, u16::MAX).map_err(serde::de::Error::custom)
+    }
+}
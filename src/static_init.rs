@@ -0,0 +1,1226 @@
+//! Recovering the values that a static initializer assigns to the static fields of its class,
+//! for constants that have no `ConstantValue` attribute, such as `static final` arrays, strings
+//! built in `<clinit>`, and enum constants.
+//!
+//! The `<clinit>` method is evaluated symbolically, on its own: values that come from outside the
+//! class, such as the results of methods of other classes and the static fields of other classes,
+//! are unknown, and so is anything computed from them. Static methods of the class itself are
+//! evaluated when they are called. Objects and arrays that are passed to methods of other classes,
+//! or whose fields are written, are unknown from then on, since they may have been changed.
+//! `StringBuilder`, `StringBuffer`, a few methods of `String` and string concatenation with
+//! invokedynamic are evaluated. Methods of other classes are assumed not to write the static
+//! fields of the class.
+//!
+//! Evaluation follows the branches the initializer takes. If a branch depends on an unknown value,
+//! an exception would be thrown, or evaluation takes too long, it stops, and every field that any
+//! method of the class assigns is reported as unknown.
+
+use std::convert::TryFrom;
+
+use crate::attributes::{AttributeData, AttributeInfo};
+use crate::bytecode::{Opcode, PrimitiveArrayType};
+use crate::constant_pool::{LiteralConstant, Loadable, MemberRef};
+use crate::interpreter::{
+    alloc_array, array_size, compute, java_double, java_float, narrow, operation, string_concat, Value,
+};
+use crate::names::split_method_descriptor;
+use crate::{ClassFile, FieldAccessFlags, MethodAccessFlags, ParseError};
+
+const STRING: &str = "java/lang/String";
+const BUILDERS: [&str; 2] = ["java/lang/StringBuilder", "java/lang/StringBuffer"];
+
+/// A value that a static initializer computes.
+#[derive(Clone, Debug, PartialEq)]
+pub enum StaticValue {
+    Int(i32),
+    Long(i64),
+    Float(f32),
+    Double(f64),
+    Null,
+    String(String),
+    /// A class literal, such as `String.class`.
+    Class(String),
+    /// An array, with the descriptor of its component type, such as `I` for an `int[]`.
+    Array { component: String, elements: Vec<StaticValue> },
+    /// An object created with `new` and initialized by the constructor with the given descriptor.
+    Object {
+        class_name: String,
+        constructor: String,
+        arguments: Vec<StaticValue>,
+    },
+}
+
+impl StaticValue {
+    /// Returns the name and ordinal of an enum constant, which javac passes as the first two
+    /// arguments of an enum's constructor.
+    pub fn enum_constant(&self) -> Option<(&str, i32)> {
+        match self {
+            StaticValue::Object { constructor, arguments, .. } if constructor.starts_with("(Ljava/lang/String;I") => {
+                match arguments.as_slice() {
+                    [StaticValue::String(name), StaticValue::Int(ordinal), ..] => Some((name, *ordinal)),
+                    _ => None,
+                }
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Limits on how long a static initializer is evaluated before evaluation stops, leaving the
+/// fields that it assigns unknown.
+#[derive(Clone, Debug)]
+pub struct StaticInitOptions {
+    max_steps: u64,
+    max_depth: usize,
+}
+
+impl Default for StaticInitOptions {
+    fn default() -> Self {
+        Self {
+            max_steps: 1_000_000,
+            max_depth: 16,
+        }
+    }
+}
+
+impl StaticInitOptions {
+    /// Sets the number of instructions that may be evaluated. Allocating an array counts as one
+    /// step per element. The default is 1,000,000.
+    pub fn max_steps(&mut self, steps: u64) -> &mut StaticInitOptions {
+        self.max_steps = steps;
+        self
+    }
+
+    /// Sets how deeply calls to the static methods of the class may be nested, counting the
+    /// initializer itself. The default is 16.
+    pub fn max_depth(&mut self, depth: usize) -> &mut StaticInitOptions {
+        self.max_depth = depth;
+        self
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct StaticFieldValue {
+    pub name: String,
+    pub descriptor: String,
+    /// The value of the field once the class is initialized, or None if it depends on something
+    /// outside the class. Fields that are not final may be changed later.
+    pub value: Option<StaticValue>,
+}
+
+// Why evaluation stopped before the initializer returned.
+enum Stop {
+    // Something that cannot be evaluated, after which no assigned field is known.
+    Unknown,
+    Error(ParseError),
+}
+
+impl From<ParseError> for Stop {
+    fn from(e: ParseError) -> Self {
+        Stop::Error(e)
+    }
+}
+
+// A value that evaluation computes, which is unknown if it depends on something outside the
+// class. Known references are indices into the evaluator's heap.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Sym {
+    Known(Value),
+    Unknown,
+    UnknownWide,
+}
+
+impl Sym {
+    fn unknown(descriptor: &str) -> Sym {
+        match descriptor.as_bytes().first() {
+            Some(b'J') | Some(b'D') => Sym::UnknownWide,
+            _ => Sym::Unknown,
+        }
+    }
+
+    fn is_wide(&self) -> bool {
+        match self {
+            Sym::Known(value) => value.is_wide(),
+            Sym::Unknown => false,
+            Sym::UnknownWide => true,
+        }
+    }
+
+    fn narrow(self, descriptor: &str) -> Sym {
+        match self {
+            Sym::Known(value) => Sym::Known(narrow(descriptor, value)),
+            sym => sym,
+        }
+    }
+}
+
+enum Entry {
+    String(String),
+    Class(String),
+    Array { component: String, elements: Vec<Sym> },
+    // An object whose constructor has not run yet has no constructor
+    Object { class_name: String, constructor: Option<(String, Vec<Sym>)> },
+    Builder(String),
+    // An object or array whose contents are unknown
+    Unknown,
+}
+
+struct Frame {
+    locals: Vec<Sym>,
+    stack: Vec<Sym>,
+}
+
+impl Frame {
+    fn push(&mut self, value: Sym) {
+        self.stack.push(value);
+    }
+
+    fn pop(&mut self) -> Result<Sym, Stop> {
+        self.stack.pop().ok_or(Stop::Unknown)
+    }
+
+    fn pop_n(&mut self, count: usize) -> Result<Vec<Sym>, Stop> {
+        if self.stack.len() < count {
+            return Err(Stop::Unknown);
+        }
+        Ok(self.stack.split_off(self.stack.len() - count))
+    }
+
+    // Pops an int that decides a branch, which has to be known
+    fn pop_int(&mut self) -> Result<i32, Stop> {
+        match self.pop()? {
+            Sym::Known(Value::Int(v)) => Ok(v),
+            _ => Err(Stop::Unknown),
+        }
+    }
+
+    fn pop_reference(&mut self) -> Result<Sym, Stop> {
+        match self.pop()? {
+            value @ Sym::Known(Value::Null) | value @ Sym::Known(Value::Reference(_)) => Ok(value),
+            _ => Err(Stop::Unknown),
+        }
+    }
+
+    fn load(&self, index: u16) -> Result<Sym, Stop> {
+        self.locals.get(index as usize).copied().ok_or(Stop::Unknown)
+    }
+
+    fn store(&mut self, index: usize, value: Sym) -> Result<(), Stop> {
+        let end = if value.is_wide() { index + 2 } else { index + 1 };
+        if end > self.locals.len() {
+            return Err(Stop::Unknown);
+        }
+        self.locals[index] = value;
+        if value.is_wide() {
+            self.locals[index + 1] = Sym::Unknown;
+        }
+        Ok(())
+    }
+}
+
+enum Flow {
+    Next,
+    Jump(i32),
+    Goto(usize),
+    Return(Option<Sym>),
+}
+
+struct Evaluator<'a> {
+    class: &'a ClassFile,
+    opts: &'a StaticInitOptions,
+    heap: Vec<Entry>,
+    statics: Vec<(String, String, Sym)>,
+    steps: u64,
+}
+
+/// Evaluates the static initializer of a class, and returns the value of each of its static
+/// fields once it has run, in declaration order. Fields that the initializer does not assign have
+/// the value of their `ConstantValue` attribute, or their default value. Fails if the bytecode of
+/// a method that has to be evaluated was not parsed.
+pub fn static_field_values(class: &ClassFile) -> Result<Vec<StaticFieldValue>, ParseError> {
+    static_field_values_with_options(class, &StaticInitOptions::default())
+}
+
+pub fn static_field_values_with_options(class: &ClassFile, opts: &StaticInitOptions) -> Result<Vec<StaticFieldValue>, ParseError> {
+    let mut evaluator = Evaluator {
+        class,
+        opts,
+        heap: Vec::new(),
+        statics: Vec::new(),
+        steps: 0,
+    };
+    for field in class.fields.iter().filter(|f| f.access_flags.contains(FieldAccessFlags::STATIC)) {
+        let mut value = Sym::Known(Value::default_for(&field.descriptor));
+        for attr in field.attributes.iter().filter(|attr| attr.name == "ConstantValue") {
+            if let AttributeData::ConstantValue(literal) = attr.decoded()? {
+                value = evaluator.literal(literal);
+            }
+        }
+        evaluator.statics.push((field.name.clone(), field.descriptor.clone(), value));
+    }
+    let completed = match class.methods.iter().position(|m| m.name == "<clinit>") {
+        Some(index) => match evaluator.run(index, Vec::new(), 0) {
+            Ok(_) => true,
+            Err(Stop::Unknown) => false,
+            Err(Stop::Error(e)) => return Err(err!(e, "static initializer of class {}", class.this_class)),
+        },
+        None => true,
+    };
+    let assigned = if completed { Vec::new() } else { assigned_fields(class)? };
+    Ok(evaluator
+        .statics
+        .iter()
+        .map(|(name, descriptor, value)| StaticFieldValue {
+            name: name.clone(),
+            descriptor: descriptor.clone(),
+            value: if assigned.contains(&(name.as_str(), descriptor.as_str())) {
+                None
+            } else {
+                evaluator.value(*value, &mut Vec::new())
+            },
+        })
+        .collect())
+}
+
+// The static fields of the class that any of its methods assign.
+fn assigned_fields(class: &ClassFile) -> Result<Vec<(&str, &str)>, ParseError> {
+    let mut assigned = Vec::new();
+    for method in &class.methods {
+        for attr in method.attributes.iter().filter(|attr| attr.name == "Code") {
+            if let AttributeData::Code(code) = attr.decoded()? {
+                for (_, opcode) in code.bytecode.iter().flat_map(|bytecode| &bytecode.opcodes) {
+                    if let Opcode::Putstatic(member) = opcode {
+                        if member.class_name == class.this_class {
+                            assigned.push((member.name_and_type.name.as_str(), member.name_and_type.descriptor.as_str()));
+                        }
+                    }
+                }
+            }
+        }
+    }
+    Ok(assigned)
+}
+
+impl<'a> Evaluator<'a> {
+    fn alloc(&mut self, entry: Entry) -> Sym {
+        self.heap.push(entry);
+        Sym::Known(Value::Reference(self.heap.len() - 1))
+    }
+
+    fn tick(&mut self, steps: u64) -> Result<(), Stop> {
+        self.steps = self.steps.saturating_add(steps);
+        if self.steps > self.opts.max_steps {
+            return Err(Stop::Unknown);
+        }
+        Ok(())
+    }
+
+    fn literal(&mut self, literal: &LiteralConstant) -> Sym {
+        match literal {
+            LiteralConstant::Integer(v) => Sym::Known(Value::Int(*v)),
+            LiteralConstant::Float(v) => Sym::Known(Value::Float(*v)),
+            LiteralConstant::Long(v) => Sym::Known(Value::Long(*v)),
+            LiteralConstant::Double(v) => Sym::Known(Value::Double(*v)),
+            LiteralConstant::String(s) => self.alloc(Entry::String(s.clone())),
+            LiteralConstant::StringBytes(b) => self.alloc(Entry::String(String::from_utf8_lossy(b).into_owned())),
+        }
+    }
+
+    // Converts a value to the value reported, or None if any part of it is unknown.
+    fn value(&self, sym: Sym, visiting: &mut Vec<usize>) -> Option<StaticValue> {
+        Some(match sym {
+            Sym::Known(Value::Int(v)) => StaticValue::Int(v),
+            Sym::Known(Value::Long(v)) => StaticValue::Long(v),
+            Sym::Known(Value::Float(v)) => StaticValue::Float(v),
+            Sym::Known(Value::Double(v)) => StaticValue::Double(v),
+            Sym::Known(Value::Null) => StaticValue::Null,
+            Sym::Known(Value::Reference(index)) if !visiting.contains(&index) => {
+                visiting.push(index);
+                let value = match &self.heap[index] {
+                    Entry::String(s) => StaticValue::String(s.clone()),
+                    Entry::Class(name) => StaticValue::Class(name.clone()),
+                    Entry::Array { component, elements } => StaticValue::Array {
+                        component: component.clone(),
+                        elements: elements.iter().map(|e| self.value(*e, visiting)).collect::<Option<_>>()?,
+                    },
+                    Entry::Object {
+                        class_name,
+                        constructor: Some((constructor, arguments)),
+                    } => StaticValue::Object {
+                        class_name: class_name.clone(),
+                        constructor: constructor.clone(),
+                        arguments: arguments.iter().map(|a| self.value(*a, visiting)).collect::<Option<_>>()?,
+                    },
+                    _ => return None,
+                };
+                visiting.pop();
+                value
+            }
+            _ => return None,
+        })
+    }
+
+    // Marks the objects and arrays reachable from a value as unknown, for when code outside the
+    // class may change them.
+    fn escape(&mut self, sym: Sym) {
+        let mut pending = vec![sym];
+        while let Some(Sym::Known(Value::Reference(index))) = pending.pop() {
+            match std::mem::replace(&mut self.heap[index], Entry::Unknown) {
+                entry @ Entry::String(_) | entry @ Entry::Class(_) => self.heap[index] = entry,
+                Entry::Array { elements, .. } => pending.extend(elements),
+                Entry::Object {
+                    constructor: Some((_, arguments)),
+                    ..
+                } => pending.extend(arguments),
+                _ => (),
+            }
+        }
+    }
+
+    // Formats a value the way string concatenation does, if it is known.
+    fn string_of(&self, descriptor: &str, sym: Sym) -> Option<String> {
+        Some(match (descriptor, sym) {
+            ("Z", Sym::Known(Value::Int(v))) => (v != 0).to_string(),
+            ("C", Sym::Known(Value::Int(v))) => String::from_utf16_lossy(&[v as u16]),
+            (_, Sym::Known(Value::Int(v))) => v.to_string(),
+            (_, Sym::Known(Value::Long(v))) => v.to_string(),
+            (_, Sym::Known(Value::Float(v))) => java_float(v),
+            (_, Sym::Known(Value::Double(v))) => java_double(v),
+            (_, Sym::Known(Value::Null)) => "null".to_string(),
+            (_, Sym::Known(Value::Reference(index))) => match &self.heap[index] {
+                Entry::String(s) => s.clone(),
+                _ => return None,
+            },
+            _ => return None,
+        })
+    }
+
+    fn run(&mut self, index: usize, args: Vec<Sym>, depth: usize) -> Result<Option<Sym>, Stop> {
+        let class = self.class;
+        let method = &class.methods[index];
+        if depth >= self.opts.max_depth {
+            return Err(Stop::Unknown);
+        }
+        let code = method.attributes.iter().find(|attr| attr.name == "Code").map(AttributeInfo::decoded).transpose()?;
+        let code = match code {
+            Some(AttributeData::Code(code)) => code,
+            _ => return Err(Stop::Unknown),
+        };
+        let bytecode = match &code.bytecode {
+            Some(bytecode) => bytecode,
+            None => return Err(err!("Bytecode was not parsed for method {}{}", method.name, method.descriptor).into()),
+        };
+        let mut frame = Frame {
+            locals: vec![Sym::Unknown; code.max_locals as usize],
+            stack: Vec::with_capacity(code.max_stack as usize),
+        };
+        let mut slot = 0;
+        for arg in args {
+            frame.store(slot, arg)?;
+            slot += if arg.is_wide() { 2 } else { 1 };
+        }
+        let mut pc = 0;
+        loop {
+            self.tick(1)?;
+            let (offset, opcode) = bytecode.opcodes.get(pc).ok_or(Stop::Unknown)?;
+            let target = match self.step(opcode, &mut frame, depth)? {
+                Flow::Next => {
+                    pc += 1;
+                    continue;
+                }
+                Flow::Jump(jump) => usize::try_from(*offset as i64 + jump as i64).map_err(|_| Stop::Unknown)?,
+                Flow::Goto(target) => target,
+                Flow::Return(value) => return Ok(value),
+            };
+            if let Opcode::Jsr(_) = opcode {
+                let next = bytecode.opcodes.get(pc + 1).ok_or(Stop::Unknown)?.0;
+                frame.push(Sym::Known(Value::ReturnAddress(next)));
+            }
+            pc = bytecode.get_opcode_index(target).ok_or(Stop::Unknown)?;
+        }
+    }
+
+    fn step(&mut self, opcode: &Opcode, frame: &mut Frame, depth: usize) -> Result<Flow, Stop> {
+        macro_rules! branch {
+            ($condition:expr, $jump:expr) => {
+                if $condition {
+                    return Ok(Flow::Jump(*$jump));
+                }
+            };
+        }
+
+        if let Some((params, result)) = operation(opcode) {
+            let operands = frame.pop_n(params.len())?;
+            let known = operands.iter().map(|operand| match operand {
+                Sym::Known(value) => Some(*value),
+                _ => None,
+            });
+            let value = match known.collect::<Option<Vec<_>>>() {
+                // Division by zero throws, and so stops evaluation
+                Some(operands) => match compute(opcode, &operands) {
+                    Ok(Some(value)) => Sym::Known(value),
+                    _ => return Err(Stop::Unknown),
+                },
+                None => Sym::unknown(result),
+            };
+            frame.push(value);
+            return Ok(Flow::Next);
+        }
+        match opcode {
+            Opcode::Nop | Opcode::Checkcast(_) => (),
+            Opcode::AconstNull => frame.push(Sym::Known(Value::Null)),
+            Opcode::IconstM1 => frame.push(Sym::Known(Value::Int(-1))),
+            Opcode::Iconst0 => frame.push(Sym::Known(Value::Int(0))),
+            Opcode::Iconst1 => frame.push(Sym::Known(Value::Int(1))),
+            Opcode::Iconst2 => frame.push(Sym::Known(Value::Int(2))),
+            Opcode::Iconst3 => frame.push(Sym::Known(Value::Int(3))),
+            Opcode::Iconst4 => frame.push(Sym::Known(Value::Int(4))),
+            Opcode::Iconst5 => frame.push(Sym::Known(Value::Int(5))),
+            Opcode::Lconst0 => frame.push(Sym::Known(Value::Long(0))),
+            Opcode::Lconst1 => frame.push(Sym::Known(Value::Long(1))),
+            Opcode::Fconst0 => frame.push(Sym::Known(Value::Float(0.0))),
+            Opcode::Fconst1 => frame.push(Sym::Known(Value::Float(1.0))),
+            Opcode::Fconst2 => frame.push(Sym::Known(Value::Float(2.0))),
+            Opcode::Dconst0 => frame.push(Sym::Known(Value::Double(0.0))),
+            Opcode::Dconst1 => frame.push(Sym::Known(Value::Double(1.0))),
+            Opcode::Bipush(v) => frame.push(Sym::Known(Value::Int(*v as i32))),
+            Opcode::Sipush(v) => frame.push(Sym::Known(Value::Int(*v as i32))),
+            Opcode::Ldc(loadable) | Opcode::LdcW(loadable) | Opcode::Ldc2W(loadable) => {
+                let value = match loadable {
+                    Loadable::LiteralConstant(literal) => self.literal(literal),
+                    Loadable::ClassInfo(name) => self.alloc(Entry::Class(name.clone())),
+                    _ => Sym::Unknown,
+                };
+                frame.push(value);
+            }
+            Opcode::Iload(n) | Opcode::Lload(n) | Opcode::Fload(n) | Opcode::Dload(n) | Opcode::Aload(n) => {
+                let value = frame.load(*n)?;
+                frame.push(value);
+            }
+            Opcode::Istore(n) | Opcode::Lstore(n) | Opcode::Fstore(n) | Opcode::Dstore(n) | Opcode::Astore(n) => {
+                let value = frame.pop()?;
+                frame.store(*n as usize, value)?;
+            }
+            Opcode::Iinc(n, delta) => match frame.load(*n)? {
+                Sym::Known(Value::Int(v)) => frame.store(*n as usize, Sym::Known(Value::Int(v.wrapping_add(*delta as i32))))?,
+                _ => frame.store(*n as usize, Sym::Unknown)?,
+            },
+            Opcode::Iaload | Opcode::Laload | Opcode::Faload | Opcode::Daload | Opcode::Aaload | Opcode::Baload | Opcode::Caload | Opcode::Saload => {
+                let index = frame.pop()?;
+                let array = frame.pop()?;
+                let unknown = if matches!(opcode, Opcode::Laload | Opcode::Daload) { Sym::UnknownWide } else { Sym::Unknown };
+                let element = match (array, index) {
+                    (Sym::Known(Value::Null), _) => return Err(Stop::Unknown),
+                    (Sym::Known(Value::Reference(array)), Sym::Known(Value::Int(index))) => match &self.heap[array] {
+                        Entry::Array { elements, .. } => {
+                            *usize::try_from(index).ok().and_then(|i| elements.get(i)).ok_or(Stop::Unknown)?
+                        }
+                        _ => unknown,
+                    },
+                    _ => unknown,
+                };
+                frame.push(element);
+            }
+            Opcode::Iastore | Opcode::Lastore | Opcode::Fastore | Opcode::Dastore | Opcode::Aastore | Opcode::Bastore | Opcode::Castore | Opcode::Sastore => {
+                let value = frame.pop()?;
+                let index = frame.pop()?;
+                let array = frame.pop()?;
+                match array {
+                    Sym::Known(Value::Null) => return Err(Stop::Unknown),
+                    Sym::Known(Value::Reference(array)) => match (&mut self.heap[array], index) {
+                        (Entry::Array { component, elements }, Sym::Known(Value::Int(index))) => {
+                            let element = usize::try_from(index).ok().and_then(|i| elements.get_mut(i)).ok_or(Stop::Unknown)?;
+                            *element = value.narrow(component);
+                        }
+                        (entry @ Entry::Array { .. }, _) => {
+                            *entry = Entry::Unknown;
+                            self.escape(value);
+                        }
+                        _ => self.escape(value),
+                    },
+                    _ => self.escape(value),
+                }
+            }
+            Opcode::Pop => {
+                frame.pop()?;
+            }
+            Opcode::Pop2 => {
+                if !frame.pop()?.is_wide() {
+                    frame.pop()?;
+                }
+            }
+            Opcode::Dup => {
+                let v1 = frame.pop()?;
+                frame.stack.extend_from_slice(&[v1, v1]);
+            }
+            Opcode::DupX1 => {
+                let v1 = frame.pop()?;
+                let v2 = frame.pop()?;
+                frame.stack.extend_from_slice(&[v1, v2, v1]);
+            }
+            Opcode::DupX2 => {
+                let v1 = frame.pop()?;
+                let v2 = frame.pop()?;
+                if v2.is_wide() {
+                    frame.stack.extend_from_slice(&[v1, v2, v1]);
+                } else {
+                    let v3 = frame.pop()?;
+                    frame.stack.extend_from_slice(&[v1, v3, v2, v1]);
+                }
+            }
+            Opcode::Dup2 => {
+                let v1 = frame.pop()?;
+                if v1.is_wide() {
+                    frame.stack.extend_from_slice(&[v1, v1]);
+                } else {
+                    let v2 = frame.pop()?;
+                    frame.stack.extend_from_slice(&[v2, v1, v2, v1]);
+                }
+            }
+            Opcode::Dup2X1 => {
+                let v1 = frame.pop()?;
+                let v2 = frame.pop()?;
+                if v1.is_wide() {
+                    frame.stack.extend_from_slice(&[v1, v2, v1]);
+                } else {
+                    let v3 = frame.pop()?;
+                    frame.stack.extend_from_slice(&[v2, v1, v3, v2, v1]);
+                }
+            }
+            Opcode::Dup2X2 => {
+                let v1 = frame.pop()?;
+                let v2 = frame.pop()?;
+                match (v1.is_wide(), v2.is_wide()) {
+                    (true, true) => frame.stack.extend_from_slice(&[v1, v2, v1]),
+                    (true, false) => {
+                        let v3 = frame.pop()?;
+                        frame.stack.extend_from_slice(&[v1, v3, v2, v1]);
+                    }
+                    (false, _) => {
+                        let v3 = frame.pop()?;
+                        if v3.is_wide() {
+                            frame.stack.extend_from_slice(&[v2, v1, v3, v2, v1]);
+                        } else {
+                            let v4 = frame.pop()?;
+                            frame.stack.extend_from_slice(&[v2, v1, v4, v3, v2, v1]);
+                        }
+                    }
+                }
+            }
+            Opcode::Swap => {
+                let v1 = frame.pop()?;
+                let v2 = frame.pop()?;
+                frame.stack.extend_from_slice(&[v1, v2]);
+            }
+            Opcode::Ifeq(jump) => branch!(frame.pop_int()? == 0, jump),
+            Opcode::Ifne(jump) => branch!(frame.pop_int()? != 0, jump),
+            Opcode::Iflt(jump) => branch!(frame.pop_int()? < 0, jump),
+            Opcode::Ifge(jump) => branch!(frame.pop_int()? >= 0, jump),
+            Opcode::Ifgt(jump) => branch!(frame.pop_int()? > 0, jump),
+            Opcode::Ifle(jump) => branch!(frame.pop_int()? <= 0, jump),
+            Opcode::IfIcmpeq(jump) | Opcode::IfIcmpne(jump) | Opcode::IfIcmplt(jump) | Opcode::IfIcmpge(jump) | Opcode::IfIcmpgt(jump) | Opcode::IfIcmple(jump) => {
+                let b = frame.pop_int()?;
+                let a = frame.pop_int()?;
+                let taken = match opcode {
+                    Opcode::IfIcmpeq(_) => a == b,
+                    Opcode::IfIcmpne(_) => a != b,
+                    Opcode::IfIcmplt(_) => a < b,
+                    Opcode::IfIcmpge(_) => a >= b,
+                    Opcode::IfIcmpgt(_) => a > b,
+                    _ => a <= b,
+                };
+                branch!(taken, jump);
+            }
+            Opcode::IfAcmpeq(jump) | Opcode::IfAcmpne(jump) => {
+                let b = frame.pop_reference()?;
+                let a = frame.pop_reference()?;
+                branch!((a == b) == matches!(opcode, Opcode::IfAcmpeq(_)), jump);
+            }
+            Opcode::Ifnull(jump) => branch!(frame.pop_reference()? == Sym::Known(Value::Null), jump),
+            Opcode::Ifnonnull(jump) => branch!(frame.pop_reference()? != Sym::Known(Value::Null), jump),
+            // The return address of jsr is pushed once the jump is taken
+            Opcode::Goto(jump) | Opcode::Jsr(jump) => return Ok(Flow::Jump(*jump)),
+            Opcode::Ret(n) => match frame.load(*n)? {
+                Sym::Known(Value::ReturnAddress(target)) => return Ok(Flow::Goto(target)),
+                _ => return Err(Stop::Unknown),
+            },
+            Opcode::Tableswitch(table) => {
+                let index = frame.pop_int()? as i64 - table.low as i64;
+                let jump = usize::try_from(index).ok().and_then(|i| table.jumps.get(i)).unwrap_or(&table.default);
+                return Ok(Flow::Jump(*jump));
+            }
+            Opcode::Lookupswitch(table) => {
+                let key = frame.pop_int()?;
+                let jump = table.match_offsets.iter().find(|(k, _)| *k == key).map_or(table.default, |(_, jump)| *jump);
+                return Ok(Flow::Jump(jump));
+            }
+            Opcode::Ireturn | Opcode::Lreturn | Opcode::Freturn | Opcode::Dreturn | Opcode::Areturn => {
+                return Ok(Flow::Return(Some(frame.pop()?)));
+            }
+            Opcode::Return => return Ok(Flow::Return(None)),
+            Opcode::Getstatic(member) => {
+                let value = match self.own_static(member) {
+                    Some(index) => self.statics[index].2,
+                    None => Sym::unknown(&member.name_and_type.descriptor),
+                };
+                frame.push(value);
+            }
+            Opcode::Putstatic(member) => {
+                let value = frame.pop()?;
+                match self.own_static(member) {
+                    Some(index) => {
+                        let field = &mut self.statics[index];
+                        field.2 = value.narrow(&field.1);
+                    }
+                    None => self.escape(value),
+                }
+            }
+            Opcode::Getfield(member) => {
+                if frame.pop()? == Sym::Known(Value::Null) {
+                    return Err(Stop::Unknown);
+                }
+                frame.push(Sym::unknown(&member.name_and_type.descriptor));
+            }
+            Opcode::Putfield(_) => {
+                let value = frame.pop()?;
+                let object = frame.pop()?;
+                if object == Sym::Known(Value::Null) {
+                    return Err(Stop::Unknown);
+                }
+                self.escape(object);
+                self.escape(value);
+            }
+            Opcode::Invokestatic(member) => self.invoke(frame, member, false, depth)?,
+            Opcode::Invokespecial(member) | Opcode::Invokevirtual(member) | Opcode::Invokeinterface(member, _) => {
+                self.invoke(frame, member, true, depth)?
+            }
+            Opcode::Invokedynamic(dynamic) => {
+                let descriptor = &dynamic.name_and_type.descriptor;
+                let (params, return_type) = split_method_descriptor(descriptor).ok_or(Stop::Unknown)?;
+                let args = frame.pop_n(params.len())?;
+                let strings = params.iter().zip(&args).map(|(param, arg)| self.string_of(param, *arg)).collect::<Option<Vec<_>>>();
+                match strings.and_then(|strings| string_concat(self.class, dynamic, &strings).ok()) {
+                    Some(result) => {
+                        let value = self.alloc(Entry::String(result));
+                        frame.push(value);
+                    }
+                    None => {
+                        for arg in args {
+                            self.escape(arg);
+                        }
+                        frame.push(Sym::unknown(return_type));
+                    }
+                }
+            }
+            Opcode::New(class_name) => {
+                let value = self.alloc(Entry::Object {
+                    class_name: class_name.clone(),
+                    constructor: None,
+                });
+                frame.push(value);
+            }
+            Opcode::Newarray(array_type) => {
+                let component = match array_type {
+                    PrimitiveArrayType::Boolean => "Z",
+                    PrimitiveArrayType::Char => "C",
+                    PrimitiveArrayType::Float => "F",
+                    PrimitiveArrayType::Double => "D",
+                    PrimitiveArrayType::Byte => "B",
+                    PrimitiveArrayType::Short => "S",
+                    PrimitiveArrayType::Int => "I",
+                    PrimitiveArrayType::Long => "J",
+                };
+                let counts = frame.pop_n(1)?;
+                let array = self.new_array(&format!("[{}", component), &counts)?;
+                frame.push(array);
+            }
+            Opcode::Anewarray(class_name) => {
+                let counts = frame.pop_n(1)?;
+                let descriptor = if class_name.starts_with('[') { format!("[{}", class_name) } else { format!("[L{};", class_name) };
+                let array = self.new_array(&descriptor, &counts)?;
+                frame.push(array);
+            }
+            Opcode::Multianewarray(descriptor, dimensions) => {
+                let counts = frame.pop_n(*dimensions as usize)?;
+                let array = self.new_array(descriptor, &counts)?;
+                frame.push(array);
+            }
+            Opcode::Arraylength => {
+                let length = match frame.pop()? {
+                    Sym::Known(Value::Null) => return Err(Stop::Unknown),
+                    Sym::Known(Value::Reference(index)) => match &self.heap[index] {
+                        Entry::Array { elements, .. } => Sym::Known(Value::Int(elements.len() as i32)),
+                        _ => Sym::Unknown,
+                    },
+                    _ => Sym::Unknown,
+                };
+                frame.push(length);
+            }
+            Opcode::Instanceof(_) => {
+                let value = frame.pop()?;
+                frame.push(if value == Sym::Known(Value::Null) { Sym::Known(Value::Int(0)) } else { Sym::Unknown });
+            }
+            Opcode::Monitorenter | Opcode::Monitorexit => {
+                if frame.pop()? == Sym::Known(Value::Null) {
+                    return Err(Stop::Unknown);
+                }
+            }
+            _ => return Err(Stop::Unknown),
+        }
+        Ok(Flow::Next)
+    }
+
+    fn own_static(&self, member: &MemberRef) -> Option<usize> {
+        if member.class_name != self.class.this_class {
+            return None;
+        }
+        let name_and_type = &member.name_and_type;
+        self.statics.iter().position(|(name, descriptor, _)| *name == name_and_type.name && *descriptor == name_and_type.descriptor)
+    }
+
+    fn new_array(&mut self, descriptor: &str, counts: &[Sym]) -> Result<Sym, Stop> {
+        let mut lengths = Vec::new();
+        for count in counts {
+            match count {
+                Sym::Known(Value::Int(count)) if *count < 0 => return Err(Stop::Unknown),
+                Sym::Known(Value::Int(count)) => lengths.push(*count as usize),
+                _ => return Ok(self.alloc(Entry::Unknown)),
+            }
+        }
+        self.tick(array_size(&lengths))?;
+        let heap = &mut self.heap;
+        let array = alloc_array(descriptor, &lengths, &mut |component, elements| {
+            heap.push(Entry::Array {
+                component: component.to_string(),
+                elements: elements.into_iter().map(Sym::Known).collect(),
+            });
+            Value::Reference(heap.len() - 1)
+        })?;
+        Ok(Sym::Known(array))
+    }
+
+    fn invoke(&mut self, frame: &mut Frame, member: &MemberRef, has_receiver: bool, depth: usize) -> Result<(), Stop> {
+        let name = &member.name_and_type.name;
+        let descriptor = &member.name_and_type.descriptor;
+        let (params, return_type) = split_method_descriptor(descriptor).ok_or(Stop::Unknown)?;
+        let args = frame.pop_n(params.len() + has_receiver as usize)?;
+        if has_receiver && args[0] == Sym::Known(Value::Null) {
+            return Err(Stop::Unknown);
+        }
+        let result = if !has_receiver && member.class_name == self.class.this_class {
+            let method = self.class.methods.iter().position(|m| {
+                m.name == *name
+                    && m.descriptor == *descriptor
+                    && m.access_flags.contains(MethodAccessFlags::STATIC)
+                    && !m.access_flags.intersects(MethodAccessFlags::NATIVE | MethodAccessFlags::ABSTRACT)
+            });
+            match method {
+                Some(index) => self.run(index, args, depth + 1)?,
+                None => self.call_unknown(args, return_type),
+            }
+        } else if has_receiver && name == "<init>" {
+            self.construct(descriptor, &args);
+            None
+        } else {
+            match self.builtin(member, &params, &args) {
+                Some(result) => result,
+                None => self.call_unknown(args, return_type),
+            }
+        };
+        match result {
+            Some(value) if return_type != "V" => frame.push(value),
+            None if return_type == "V" => (),
+            _ => return Err(Stop::Unknown),
+        }
+        Ok(())
+    }
+
+    fn call_unknown(&mut self, args: Vec<Sym>, return_type: &str) -> Option<Sym> {
+        for arg in args {
+            self.escape(arg);
+        }
+        match return_type {
+            "V" => None,
+            return_type => Some(Sym::unknown(return_type)),
+        }
+    }
+
+    // Records the arguments of a constructor, if they are all known.
+    fn construct(&mut self, descriptor: &str, args: &[Sym]) {
+        let index = match args[0] {
+            Sym::Known(Value::Reference(index)) => index,
+            _ => {
+                args.iter().for_each(|arg| self.escape(*arg));
+                return;
+            }
+        };
+        let class_name = match &self.heap[index] {
+            Entry::Object { class_name, constructor: None } => class_name.clone(),
+            _ => {
+                args.iter().for_each(|arg| self.escape(*arg));
+                return;
+            }
+        };
+        let arguments = &args[1..];
+        if BUILDERS.contains(&class_name.as_str()) {
+            self.heap[index] = match (descriptor, arguments) {
+                ("()V", _) | ("(I)V", _) => Entry::Builder(String::new()),
+                ("(Ljava/lang/String;)V", [s]) | ("(Ljava/lang/CharSequence;)V", [s]) if *s != Sym::Known(Value::Null) => {
+                    match self.string_of(STRING, *s) {
+                        Some(s) => Entry::Builder(s),
+                        None => Entry::Unknown,
+                    }
+                }
+                _ => Entry::Unknown,
+            };
+        } else if arguments.iter().all(|arg| self.value(*arg, &mut Vec::new()).is_some()) {
+            self.heap[index] = Entry::Object {
+                class_name,
+                constructor: Some((descriptor.to_string(), arguments.to_vec())),
+            };
+        } else {
+            args.iter().for_each(|arg| self.escape(*arg));
+        }
+    }
+
+    // Evaluates the methods of StringBuilder, StringBuffer and String that string building uses.
+    // Returns None for other methods.
+    fn builtin(&mut self, member: &MemberRef, params: &[&str], args: &[Sym]) -> Option<Option<Sym>> {
+        let class_name = member.class_name.as_str();
+        let name = member.name_and_type.name.as_str();
+        if BUILDERS.contains(&class_name) {
+            let index = match args[0] {
+                Sym::Known(Value::Reference(index)) => index,
+                _ => return None,
+            };
+            return match (name, params) {
+                ("append", [param]) => {
+                    let text = self.string_of(param, args[1]);
+                    match (&mut self.heap[index], text) {
+                        (Entry::Builder(s), Some(text)) => s.push_str(&text),
+                        (entry, _) => *entry = Entry::Unknown,
+                    }
+                    Some(Some(args[0]))
+                }
+                ("toString", []) => match &self.heap[index] {
+                    Entry::Builder(s) => {
+                        let s = s.clone();
+                        Some(Some(self.alloc(Entry::String(s))))
+                    }
+                    _ => Some(Some(Sym::Unknown)),
+                },
+                ("length", []) => match &self.heap[index] {
+                    Entry::Builder(s) => Some(Some(Sym::Known(Value::Int(s.encode_utf16().count() as i32)))),
+                    _ => Some(Some(Sym::Unknown)),
+                },
+                _ => None,
+            };
+        }
+        if class_name != STRING {
+            return None;
+        }
+        let strings = params.iter().zip(args.iter().skip(args.len() - params.len())).map(|(p, a)| self.string_of(p, *a));
+        let strings = strings.collect::<Option<Vec<_>>>();
+        let this = if args.len() > params.len() { self.string_of(STRING, args[0]) } else { None };
+        let result = match (name, this, strings) {
+            ("valueOf", None, Some(strings)) if strings.len() == 1 && !params[0].starts_with('[') => {
+                self.alloc(Entry::String(strings[0].clone()))
+            }
+            ("concat", Some(this), Some(strings)) if args[1] != Sym::Known(Value::Null) => self.alloc(Entry::String(this + &strings[0])),
+            ("length", Some(this), _) => Sym::Known(Value::Int(this.encode_utf16().count() as i32)),
+            ("intern", Some(_), _) | ("toString", Some(_), _) => args[0],
+            _ => return None,
+        };
+        Some(Some(result))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembly::parse_assembly;
+    use crate::attributes::CodeData;
+
+    fn values(text: &str) -> Vec<(String, Option<StaticValue>)> {
+        let class = parse_assembly(&format!(".version 52 0\n{}\n.end class\n", text)).unwrap();
+        static_field_values(&class).unwrap().into_iter().map(|f| (f.name, f.value)).collect()
+    }
+
+    fn string(s: &str) -> StaticValue {
+        StaticValue::String(s.to_string())
+    }
+
+    #[test]
+    fn test_enum_constants() {
+        let fields = values(
+            r#"
+.class public final super enum Color
+.super java/lang/Enum
+.field public static final enum RED LColor;
+.end field
+.field public static final enum GREEN LColor;
+.end field
+.field private static final synthetic $VALUES [LColor;
+.end field
+.method private static synthetic $values ()[LColor;
+    .code stack 4 locals 0
+        iconst_2
+        anewarray Color
+        dup
+        iconst_0
+        getstatic Color RED LColor;
+        aastore
+        dup
+        iconst_1
+        getstatic Color GREEN LColor;
+        aastore
+        areturn
+    .end code
+.end method
+.method static <clinit> ()V
+    .code stack 4 locals 0
+        new Color
+        dup
+        ldc string "RED"
+        iconst_0
+        invokespecial Color <init> (Ljava/lang/String;I)V
+        putstatic Color RED LColor;
+        new Color
+        dup
+        ldc string "GREEN"
+        iconst_1
+        invokespecial Color <init> (Ljava/lang/String;I)V
+        putstatic Color GREEN LColor;
+        invokestatic Color $values ()[LColor;
+        putstatic Color $VALUES [LColor;
+        return
+    .end code
+.end method
+"#,
+        );
+        let red = fields[0].1.as_ref().unwrap();
+        assert_eq!(red.enum_constant(), Some(("RED", 0)));
+        match &fields[2].1 {
+            Some(StaticValue::Array { component, elements }) => {
+                assert_eq!(component, "LColor;");
+                assert_eq!(elements[0], *red);
+                assert_eq!(elements[1].enum_constant(), Some(("GREEN", 1)));
+            }
+            value => panic!("Unexpected value {:?}", value),
+        }
+    }
+
+    #[test]
+    fn test_computed_constants() {
+        let fields = values(
+            r#"
+.class public Tables
+.super java/lang/Object
+.field static final SQUARES [I
+.end field
+.field static final NAME Ljava/lang/String;
+.end field
+.field static final BIG J
+.end field
+.field static final TYPE Ljava/lang/Class;
+.end field
+.field static final STARTED J
+.end field
+.field static final LIST Ljava/util/List;
+.end field
+.field static final FLAGS [Z
+.end field
+.method static <clinit> ()V
+    .code stack 5 locals 1
+        iconst_4
+        newarray int
+        putstatic Tables SQUARES [I
+        iconst_0
+        istore_0
+Loop:   iload_0
+        iconst_4
+        if_icmpge Done
+        getstatic Tables SQUARES [I
+        iload_0
+        iload_0
+        iload_0
+        imul
+        iastore
+        iinc 0 1
+        goto Loop
+Done:   new java/lang/StringBuilder
+        dup
+        invokespecial java/lang/StringBuilder <init> ()V
+        ldc string "v"
+        invokevirtual java/lang/StringBuilder append (Ljava/lang/String;)Ljava/lang/StringBuilder;
+        getstatic Tables SQUARES [I
+        iconst_3
+        iaload
+        invokevirtual java/lang/StringBuilder append (I)Ljava/lang/StringBuilder;
+        bipush 46
+        invokevirtual java/lang/StringBuilder append (C)Ljava/lang/StringBuilder;
+        invokevirtual java/lang/StringBuilder toString ()Ljava/lang/String;
+        putstatic Tables NAME Ljava/lang/String;
+        ldc2_w long 1
+        bipush 40
+        lshl
+        putstatic Tables BIG J
+        ldc class java/lang/String
+        putstatic Tables TYPE Ljava/lang/Class;
+        invokestatic java/lang/System currentTimeMillis ()J
+        putstatic Tables STARTED J
+        new java/util/ArrayList
+        dup
+        invokespecial java/util/ArrayList <init> ()V
+        dup
+        getstatic Tables NAME Ljava/lang/String;
+        invokeinterface java/util/List add (Ljava/lang/Object;)Z 2
+        pop
+        putstatic Tables LIST Ljava/util/List;
+        iconst_1
+        newarray boolean
+        dup
+        iconst_0
+        iconst_3
+        bastore
+        putstatic Tables FLAGS [Z
+        return
+    .end code
+.end method
+"#,
+        );
+        let squares = StaticValue::Array {
+            component: "I".to_string(),
+            elements: vec![StaticValue::Int(0), StaticValue::Int(1), StaticValue::Int(4), StaticValue::Int(9)],
+        };
+        let flags = StaticValue::Array {
+            component: "Z".to_string(),
+            elements: vec![StaticValue::Int(1)],
+        };
+        assert_eq!(
+            fields,
+            vec![
+                ("SQUARES".to_string(), Some(squares)),
+                ("NAME".to_string(), Some(string("v9."))),
+                ("BIG".to_string(), Some(StaticValue::Long(1 << 40))),
+                ("TYPE".to_string(), Some(StaticValue::Class("java/lang/String".to_string()))),
+                ("STARTED".to_string(), None),
+                ("LIST".to_string(), None),
+                ("FLAGS".to_string(), Some(flags)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_limits() {
+        let text = r#"
+.version 52 0
+.class public Limits
+.super java/lang/Object
+.field static final SUM I
+.end field
+.field static final DEPTH I
+.end field
+.field static final GRID [[I
+.end field
+.method static depth (I)I
+    .code stack 2 locals 1
+        iload_0
+        ifle Done
+        iload_0
+        iconst_1
+        isub
+        invokestatic Limits depth (I)I
+        iconst_1
+        iadd
+        ireturn
+Done:   iconst_0
+        ireturn
+    .end code
+.end method
+.method static <clinit> ()V
+    .code stack 2 locals 1
+        iconst_0
+        istore_0
+Loop:   iload_0
+        bipush 100
+        if_icmpge Done
+        iinc 0 1
+        goto Loop
+Done:   iload_0
+        putstatic Limits SUM I
+        iconst_5
+        invokestatic Limits depth (I)I
+        putstatic Limits DEPTH I
+        iconst_2
+        iconst_2
+        multianewarray [[I 2
+        putstatic Limits GRID [[I
+        return
+    .end code
+.end method
+.end class
+"#;
+        let mut class = parse_assembly(text).unwrap();
+        let value = |opts: &StaticInitOptions, name: &str| {
+            let fields = static_field_values_with_options(&class, opts).unwrap();
+            fields.into_iter().find(|f| f.name == name).unwrap().value
+        };
+        let opts = StaticInitOptions::default();
+        assert_eq!(value(&opts, "SUM"), Some(StaticValue::Int(100)));
+        assert_eq!(value(&opts, "DEPTH"), Some(StaticValue::Int(5)));
+        assert_eq!(value(StaticInitOptions::default().max_steps(100), "SUM"), None);
+        assert_eq!(value(StaticInitOptions::default().max_depth(5), "DEPTH"), None);
+        assert_eq!(value(StaticInitOptions::default().max_depth(7), "DEPTH"), Some(StaticValue::Int(5)));
+
+        // Classes built in memory are not checked as parsed ones are
+        let index = class.methods.iter().position(|m| m.name == "<clinit>").unwrap();
+        if let AttributeData::Code(CodeData { bytecode: Some(bytecode), .. }) = &mut class.methods[index].attributes[0].data {
+            let (_, opcode) = bytecode.opcodes.iter_mut().find(|(_, opcode)| matches!(opcode, Opcode::Multianewarray(..))).unwrap();
+            *opcode = Opcode::Multianewarray("[[I".to_string(), 0);
+        }
+        assert!(static_field_values(&class).is_err());
+    }
+
+    #[test]
+    fn test_unknown_branch() {
+        let fields = values(
+            r#"
+.class public Flags
+.super java/lang/Object
+.field static final DEBUG Z
+.end field
+.field static final LEVEL I
+.end field
+.field static final NAME Ljava/lang/String;
+.end field
+.field static final VERSION I
+.end field
+.method static <clinit> ()V
+    .code stack 2 locals 0
+        ldc string "flags"
+        putstatic Flags NAME Ljava/lang/String;
+        ldc string "debug"
+        invokestatic java/lang/Boolean getBoolean (Ljava/lang/String;)Z
+        dup
+        putstatic Flags DEBUG Z
+        ifeq Quiet
+        iconst_5
+        putstatic Flags LEVEL I
+Quiet:  return
+    .end code
+.end method
+"#,
+        );
+        // Evaluation stops at the branch, so only the field that is never assigned is known
+        assert_eq!(
+            fields,
+            vec![
+                ("DEBUG".to_string(), None),
+                ("LEVEL".to_string(), None),
+                ("NAME".to_string(), None),
+                ("VERSION".to_string(), Some(StaticValue::Int(0))),
+            ]
+        );
+    }
+}
@@ -0,0 +1,1116 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::convert::TryFrom;
+
+use crate::attributes::{AttributeData, AttributeInfo, CodeData};
+use crate::bytecode::{ByteCode, JumpOffset, Opcode, PrimitiveArrayType};
+use crate::constant_pool::{LiteralConstant, Loadable, MemberRef};
+use crate::names::split_method_descriptor;
+use crate::{ClassFile, MethodAccessFlags, MethodInfo, ParseError};
+
+/// The type of a local variable or operand stack entry as inferred by the verifier.
+/// Long and Double values occupy two local variable slots, the second of which is Top,
+/// but only a single operand stack entry.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum InferredType {
+    Top,
+    Integer,
+    Float,
+    Long,
+    Double,
+    Null,
+    UninitializedThis,
+    Uninitialized { code_offset: usize },
+    /// A class or interface type given by binary name, or an array type given by descriptor.
+    Object { class_name: String },
+    /// A return address pushed by a jsr instruction into the subroutine starting at the
+    /// given code offset.
+    ReturnAddress { subroutine: usize },
+}
+
+impl InferredType {
+    fn from_descriptor(descriptor: &str) -> InferredType {
+        match descriptor.as_bytes()[0] {
+            b'B' | b'C' | b'I' | b'S' | b'Z' => InferredType::Integer,
+            b'F' => InferredType::Float,
+            b'J' => InferredType::Long,
+            b'D' => InferredType::Double,
+            b'L' => InferredType::Object { class_name: descriptor[1..descriptor.len() - 1].to_string() },
+            _ => InferredType::Object { class_name: descriptor.to_string() },
+        }
+    }
+
+    fn object(class_name: &str) -> InferredType {
+        InferredType::Object { class_name: class_name.to_string() }
+    }
+
+    fn is_category2(&self) -> bool {
+        matches!(self, InferredType::Long | InferredType::Double)
+    }
+
+    fn is_reference(&self) -> bool {
+        matches!(self, InferredType::Null | InferredType::UninitializedThis | InferredType::Uninitialized { .. } | InferredType::Object { .. })
+    }
+
+    fn is_initialized_reference(&self) -> bool {
+        matches!(self, InferredType::Null | InferredType::Object { .. })
+    }
+
+    fn array_component(&self) -> Option<InferredType> {
+        match self {
+            InferredType::Object { class_name } if class_name.starts_with('[') => Some(InferredType::from_descriptor(&class_name[1..])),
+            _ => None,
+        }
+    }
+}
+
+/// Answers subtyping questions about classes that are not part of the method being verified.
+/// The verifier only ever asks about binary class names and array descriptors.
+pub trait SubtypeOracle {
+    /// Returns true if a value of type `sub` may be used where a value of type `sup` is expected.
+    fn is_assignable(&self, sub: &str, sup: &str) -> bool;
+
+    /// Returns the type that values of both `a` and `b` are assignable to, used when merging
+    /// frames at control flow join points.
+    fn common_supertype(&self, a: &str, b: &str) -> String;
+}
+
+/// A `SubtypeOracle` that knows nothing about the class hierarchy. Every reference type is
+/// assumed to be assignable to every other, and distinct types merge to `java/lang/Object`.
+/// This mirrors how the old verifier treats interface types, and makes it possible to check
+/// everything except reference assignability without loading any other classes.
+#[derive(Debug, Default)]
+pub struct LenientOracle;
+
+impl SubtypeOracle for LenientOracle {
+    fn is_assignable(&self, _sub: &str, _sup: &str) -> bool {
+        true
+    }
+
+    fn common_supertype(&self, a: &str, b: &str) -> String {
+        if a == b {
+            a.to_string()
+        } else {
+            "java/lang/Object".to_string()
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Frame {
+    pub locals: Vec<InferredType>,
+    pub stack: Vec<InferredType>,
+}
+
+impl Frame {
+    fn stack_size(&self) -> usize {
+        self.stack.iter().map(|t| if t.is_category2() { 2 } else { 1 }).sum()
+    }
+
+    fn pop(&mut self) -> Result<InferredType, ParseError> {
+        match self.stack.pop() {
+            Some(t) => Ok(t),
+            None => fail!("Operand stack underflow"),
+        }
+    }
+
+    fn pop_cat1(&mut self) -> Result<InferredType, ParseError> {
+        let t = self.pop()?;
+        if t.is_category2() {
+            fail!("Expected category 1 value on operand stack but found {:?}", t);
+        }
+        Ok(t)
+    }
+
+    fn pop_expect(&mut self, expected: InferredType) -> Result<(), ParseError> {
+        let t = self.pop()?;
+        if t != expected {
+            fail!("Expected {:?} on operand stack but found {:?}", expected, t);
+        }
+        Ok(())
+    }
+
+    fn pop_reference(&mut self) -> Result<InferredType, ParseError> {
+        let t = self.pop()?;
+        if !t.is_reference() {
+            fail!("Expected reference on operand stack but found {:?}", t);
+        }
+        Ok(t)
+    }
+
+    fn get_local(&self, index: u16, expected: &InferredType) -> Result<InferredType, ParseError> {
+        let ix = usize::from(index);
+        let t = match self.locals.get(ix) {
+            Some(t) => t.clone(),
+            None => fail!("Local variable index {} out of range", index),
+        };
+        let ok = match expected {
+            InferredType::Object { .. } => t.is_reference(),
+            _ => &t == expected,
+        };
+        if !ok {
+            fail!("Expected {:?} in local variable {} but found {:?}", expected, index, t);
+        }
+        if t.is_category2() && self.locals.get(ix + 1) != Some(&InferredType::Top) {
+            fail!("Second half of local variable {} is not available", index);
+        }
+        Ok(t)
+    }
+
+    fn set_local(&mut self, index: u16, t: InferredType) -> Result<(), ParseError> {
+        let ix = usize::from(index);
+        let width = if t.is_category2() { 2 } else { 1 };
+        if ix + width > self.locals.len() {
+            fail!("Local variable index {} out of range", index);
+        }
+        // Storing into the second half of a category 2 value invalidates the first half.
+        if ix > 0 && self.locals[ix - 1].is_category2() {
+            self.locals[ix - 1] = InferredType::Top;
+        }
+        self.locals[ix] = t;
+        if width == 2 {
+            self.locals[ix + 1] = InferredType::Top;
+        }
+        Ok(())
+    }
+
+    fn replace_uninitialized(&mut self, uninit: &InferredType, init: &InferredType) {
+        for t in self.locals.iter_mut().chain(self.stack.iter_mut()) {
+            if t == uninit {
+                *t = init.clone();
+            }
+        }
+    }
+}
+
+struct Subroutine {
+    callers: Vec<usize>,
+    locals_used: HashSet<u16>,
+}
+
+struct Verifier<'a> {
+    class: &'a ClassFile,
+    method: &'a MethodInfo,
+    code: &'a CodeData,
+    bytecode: &'a ByteCode,
+    oracle: &'a dyn SubtypeOracle,
+    return_type: Option<InferredType>,
+    frames: Vec<Option<Frame>>,
+    worklist: VecDeque<usize>,
+    subroutines: HashMap<usize, Subroutine>,
+    ret_frames: HashMap<usize, Frame>,
+}
+
+fn jump_target(bytecode: &ByteCode, offset: usize, jump: JumpOffset) -> Result<usize, ParseError> {
+    let target = i64::try_from(offset).map_err(|_| err!("Unable to convert offset"))? + i64::from(jump);
+    let target = usize::try_from(target).map_err(|_| err!("Invalid destination after applying jump"))?;
+    match bytecode.get_opcode_index(target) {
+        Some(ix) => Ok(ix),
+        None => fail!("Invalid opcode offset {} after applying jump", target),
+    }
+}
+
+fn local_access(opcode: &Opcode) -> Option<(u16, bool)> {
+    match opcode {
+        Opcode::Iload(n) | Opcode::Fload(n) | Opcode::Aload(n) |
+        Opcode::Istore(n) | Opcode::Fstore(n) | Opcode::Astore(n) |
+        Opcode::Iinc(n, _) | Opcode::Ret(n) => Some((*n, false)),
+        Opcode::Lload(n) | Opcode::Dload(n) |
+        Opcode::Lstore(n) | Opcode::Dstore(n) => Some((*n, true)),
+        _ => None,
+    }
+}
+
+fn branch_targets(opcode: &Opcode) -> Vec<JumpOffset> {
+    match opcode {
+        Opcode::Goto(j) |
+        Opcode::IfAcmpeq(j) |
+        Opcode::IfAcmpne(j) |
+        Opcode::IfIcmpeq(j) |
+        Opcode::IfIcmpge(j) |
+        Opcode::IfIcmpgt(j) |
+        Opcode::IfIcmple(j) |
+        Opcode::IfIcmplt(j) |
+        Opcode::IfIcmpne(j) |
+        Opcode::Ifeq(j) |
+        Opcode::Ifge(j) |
+        Opcode::Ifgt(j) |
+        Opcode::Ifle(j) |
+        Opcode::Iflt(j) |
+        Opcode::Ifne(j) |
+        Opcode::Ifnonnull(j) |
+        Opcode::Ifnull(j) => vec![*j],
+        Opcode::Lookupswitch(table) => {
+            let mut jumps = vec![table.default];
+            jumps.extend(table.match_offsets.iter().map(|(_, j)| *j));
+            jumps
+        }
+        Opcode::Tableswitch(table) => {
+            let mut jumps = vec![table.default];
+            jumps.extend(table.jumps.iter());
+            jumps
+        }
+        _ => vec![],
+    }
+}
+
+fn falls_through(opcode: &Opcode) -> bool {
+    !matches!(opcode,
+        Opcode::Goto(_) |
+        Opcode::Jsr(_) |
+        Opcode::Ret(_) |
+        Opcode::Athrow |
+        Opcode::Ireturn |
+        Opcode::Lreturn |
+        Opcode::Freturn |
+        Opcode::Dreturn |
+        Opcode::Areturn |
+        Opcode::Return |
+        Opcode::Lookupswitch(_) |
+        Opcode::Tableswitch(_))
+}
+
+impl<'a> Verifier<'a> {
+    fn handlers_for(&self, index: usize) -> Result<Vec<(usize, InferredType)>, ParseError> {
+        let offset = self.bytecode.opcodes[index].0;
+        let mut handlers = Vec::new();
+        for (i, entry) in self.code.exception_table.iter().enumerate() {
+            if offset >= usize::from(entry.start_pc) && offset < usize::from(entry.end_pc) {
+                let handler = match self.bytecode.get_opcode_index(entry.handler_pc.into()) {
+                    Some(ix) => ix,
+                    None => fail!(("Invalid handler_pc {}", entry.handler_pc), ("exception table entry {}", i)),
+                };
+                let catch_type = entry.catch_type.as_deref().unwrap_or("java/lang/Throwable");
+                handlers.push((handler, InferredType::object(catch_type)));
+            }
+        }
+        Ok(handlers)
+    }
+
+    fn successors(&self, index: usize) -> Result<Vec<usize>, ParseError> {
+        let (offset, opcode) = &self.bytecode.opcodes[index];
+        let mut succ = Vec::new();
+        for jump in branch_targets(opcode) {
+            succ.push(jump_target(self.bytecode, *offset, jump)?);
+        }
+        if falls_through(opcode) {
+            if index + 1 >= self.bytecode.opcodes.len() {
+                fail!("Execution falls off the end of the code");
+            }
+            succ.push(index + 1);
+        }
+        Ok(succ)
+    }
+
+    /// Finds all subroutines and the local variables each of them touches, including
+    /// those touched by subroutines they call in turn.
+    fn find_subroutines(&mut self) -> Result<(), ParseError> {
+        let mut nested: HashMap<usize, HashSet<usize>> = HashMap::new();
+        for (i, (offset, opcode)) in self.bytecode.opcodes.iter().enumerate() {
+            if let Opcode::Jsr(j) = opcode {
+                if self.class.major_version >= 51 {
+                    fail!("Found jsr instruction in class file of major version {}", self.class.major_version);
+                }
+                let start = jump_target(self.bytecode, *offset, *j)?;
+                self.subroutines.entry(start).or_insert_with(|| Subroutine { callers: Vec::new(), locals_used: HashSet::new() }).callers.push(i);
+            } else if let Opcode::Ret(_) = opcode {
+                if self.class.major_version >= 51 {
+                    fail!("Found ret instruction in class file of major version {}", self.class.major_version);
+                }
+            }
+        }
+        let starts: Vec<usize> = self.subroutines.keys().copied().collect();
+        for start in starts {
+            let mut seen = HashSet::new();
+            let mut pending = vec![start];
+            let mut used = HashSet::new();
+            let mut calls = HashSet::new();
+            while let Some(ix) = pending.pop() {
+                if !seen.insert(ix) {
+                    continue;
+                }
+                let (offset, opcode) = &self.bytecode.opcodes[ix];
+                if let Some((n, wide)) = local_access(opcode) {
+                    used.insert(n);
+                    if wide {
+                        used.insert(n + 1);
+                    }
+                }
+                match opcode {
+                    Opcode::Ret(_) => continue,
+                    Opcode::Jsr(j) => {
+                        calls.insert(jump_target(self.bytecode, *offset, *j)?);
+                        pending.push(ix + 1);
+                    }
+                    _ => pending.extend(self.successors(ix)?),
+                }
+                pending.extend(self.handlers_for(ix)?.into_iter().map(|(h, _)| h));
+            }
+            self.subroutines.get_mut(&start).unwrap().locals_used = used;
+            nested.insert(start, calls);
+        }
+        // A subroutine also modifies whatever the subroutines it calls modify.
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for (start, calls) in &nested {
+                let mut extra = HashSet::new();
+                for callee in calls {
+                    extra.extend(self.subroutines[callee].locals_used.iter().copied());
+                }
+                let sub = self.subroutines.get_mut(start).unwrap();
+                let before = sub.locals_used.len();
+                sub.locals_used.extend(extra);
+                changed |= sub.locals_used.len() != before;
+            }
+        }
+        Ok(())
+    }
+
+    fn merge_type(&self, a: &InferredType, b: &InferredType) -> InferredType {
+        match (a, b) {
+            _ if a == b => a.clone(),
+            (InferredType::Null, InferredType::Object { .. }) => b.clone(),
+            (InferredType::Object { .. }, InferredType::Null) => a.clone(),
+            (InferredType::Object { class_name: x }, InferredType::Object { class_name: y }) => {
+                InferredType::object(&self.merge_class_names(x, y))
+            }
+            _ => InferredType::Top,
+        }
+    }
+
+    fn merge_class_names(&self, a: &str, b: &str) -> String {
+        if a.starts_with('[') && b.starts_with('[') {
+            let (ca, cb) = (&a[1..], &b[1..]);
+            if ca.starts_with(['L', '[']) && cb.starts_with(['L', '[']) {
+                let component = |d: &str| if d.starts_with('L') { d[1..d.len() - 1].to_string() } else { d.to_string() };
+                let merged = self.merge_class_names(&component(ca), &component(cb));
+                return if merged.starts_with('[') { format!("[{}", merged) } else { format!("[L{};", merged) };
+            }
+            return "java/lang/Object".to_string();
+        }
+        if a.starts_with('[') || b.starts_with('[') {
+            return "java/lang/Object".to_string();
+        }
+        self.oracle.common_supertype(a, b)
+    }
+
+    fn merge_into(&mut self, index: usize, frame: &Frame) -> Result<(), ParseError> {
+        let merged = match &self.frames[index] {
+            None => frame.clone(),
+            Some(existing) => {
+                if existing.stack.len() != frame.stack.len() {
+                    fail!(("Inconsistent operand stack height at join point"), ("opcode at offset {}", self.bytecode.opcodes[index].0));
+                }
+                let mut stack = Vec::with_capacity(frame.stack.len());
+                for (a, b) in existing.stack.iter().zip(frame.stack.iter()) {
+                    let t = self.merge_type(a, b);
+                    if t == InferredType::Top {
+                        fail!(("Incompatible operand stack types {:?} and {:?} at join point", a, b), ("opcode at offset {}", self.bytecode.opcodes[index].0));
+                    }
+                    stack.push(t);
+                }
+                let locals = existing.locals.iter().zip(frame.locals.iter()).map(|(a, b)| self.merge_type(a, b)).collect();
+                Frame { locals, stack }
+            }
+        };
+        if self.frames[index].as_ref() != Some(&merged) {
+            self.frames[index] = Some(merged);
+            if !self.worklist.contains(&index) {
+                self.worklist.push_back(index);
+            }
+        }
+        Ok(())
+    }
+
+    fn check_assignable(&self, actual: &InferredType, expected: &InferredType) -> Result<(), ParseError> {
+        let ok = match (actual, expected) {
+            (InferredType::Null, InferredType::Object { .. }) => true,
+            (InferredType::Object { class_name: a }, InferredType::Object { class_name: e }) => {
+                a == e || e == "java/lang/Object" || self.oracle.is_assignable(a, e)
+            }
+            _ => actual == expected,
+        };
+        if !ok {
+            fail!("Expected {:?} but found {:?}", expected, actual);
+        }
+        Ok(())
+    }
+
+    fn pop_assignable(&self, frame: &mut Frame, expected: &InferredType) -> Result<(), ParseError> {
+        let t = frame.pop()?;
+        self.check_assignable(&t, expected)
+    }
+
+    fn pop_array(&self, frame: &mut Frame) -> Result<Option<InferredType>, ParseError> {
+        frame.pop_expect(InferredType::Integer)?;
+        let array = frame.pop()?;
+        match array {
+            InferredType::Null => Ok(None),
+            _ => match array.array_component() {
+                Some(component) => Ok(Some(component)),
+                None => fail!("Expected array reference but found {:?}", array),
+            },
+        }
+    }
+
+    fn array_load(&self, frame: &mut Frame, allowed: &[&str], pushed: InferredType) -> Result<(), ParseError> {
+        let array_type = match frame.stack.len().checked_sub(2).map(|ix| &frame.stack[ix]) {
+            Some(InferredType::Object { class_name }) => Some(class_name.clone()),
+            _ => None,
+        };
+        self.pop_array(frame)?;
+        if let Some(array_type) = array_type {
+            if !allowed.contains(&array_type.as_str()) {
+                fail!("Unexpected array type {}", array_type);
+            }
+        }
+        frame.stack.push(pushed);
+        Ok(())
+    }
+
+    fn array_store(&self, frame: &mut Frame, allowed: &[&str], value: InferredType) -> Result<(), ParseError> {
+        frame.pop_expect(value)?;
+        self.array_load(frame, allowed, InferredType::Top)?;
+        frame.stack.pop();
+        Ok(())
+    }
+
+    fn binary(frame: &mut Frame, t: InferredType) -> Result<(), ParseError> {
+        frame.pop_expect(t.clone())?;
+        frame.pop_expect(t.clone())?;
+        frame.stack.push(t);
+        Ok(())
+    }
+
+    fn convert(frame: &mut Frame, from: InferredType, to: InferredType) -> Result<(), ParseError> {
+        frame.pop_expect(from)?;
+        frame.stack.push(to);
+        Ok(())
+    }
+
+    fn invoke(&self, frame: &mut Frame, member: &MemberRef, has_receiver: bool, special: bool) -> Result<(), ParseError> {
+        let (params, ret) = match split_method_descriptor(&member.name_and_type.descriptor) {
+            Some(parts) => parts,
+            None => fail!("Invalid method descriptor {}", member.name_and_type.descriptor),
+        };
+        for param in params.iter().rev() {
+            self.pop_assignable(frame, &InferredType::from_descriptor(param))?;
+        }
+        if has_receiver {
+            let receiver = frame.pop_reference()?;
+            if special && member.name_and_type.name == "<init>" {
+                let initialized = match &receiver {
+                    InferredType::UninitializedThis => InferredType::object(&self.class.this_class),
+                    InferredType::Uninitialized { code_offset } => {
+                        match self.bytecode.get_opcode_index(*code_offset).map(|ix| &self.bytecode.opcodes[ix].1) {
+                            Some(Opcode::New(class_name)) => InferredType::object(class_name),
+                            _ => fail!("Uninitialized type does not refer to a new instruction at offset {}", code_offset),
+                        }
+                    }
+                    _ => fail!("Expected uninitialized object for <init> call but found {:?}", receiver),
+                };
+                frame.replace_uninitialized(&receiver, &initialized);
+            } else {
+                if !receiver.is_initialized_reference() {
+                    fail!("Method invoked on uninitialized object {:?}", receiver);
+                }
+                self.check_assignable(&receiver, &InferredType::object(&member.class_name))?;
+            }
+        }
+        if ret != "V" {
+            frame.stack.push(InferredType::from_descriptor(ret));
+        }
+        Ok(())
+    }
+
+    fn check_return(&self, frame: &mut Frame, expected: Option<InferredType>) -> Result<(), ParseError> {
+        match (&self.return_type, &expected) {
+            (None, None) => {
+                if self.method.name == "<init>" && frame.locals.first() == Some(&InferredType::UninitializedThis) {
+                    fail!("Constructor returns without initializing this");
+                }
+                Ok(())
+            }
+            (Some(declared), Some(InferredType::Object { .. })) => {
+                let t = frame.pop_reference()?;
+                if !t.is_initialized_reference() {
+                    fail!("Returning uninitialized object");
+                }
+                self.check_assignable(&t, declared)
+            }
+            (Some(declared), Some(t)) if declared == t => frame.pop_expect(t.clone()),
+            _ => fail!("Return instruction does not match method return type"),
+        }
+    }
+
+    fn execute(&self, offset: usize, opcode: &Opcode, frame: &mut Frame) -> Result<(), ParseError> {
+        use InferredType as T;
+        match opcode {
+            Opcode::Nop => (),
+            Opcode::AconstNull => frame.stack.push(T::Null),
+            Opcode::IconstM1 | Opcode::Iconst0 | Opcode::Iconst1 | Opcode::Iconst2 |
+            Opcode::Iconst3 | Opcode::Iconst4 | Opcode::Iconst5 |
+            Opcode::Bipush(_) | Opcode::Sipush(_) => frame.stack.push(T::Integer),
+            Opcode::Lconst0 | Opcode::Lconst1 => frame.stack.push(T::Long),
+            Opcode::Fconst0 | Opcode::Fconst1 | Opcode::Fconst2 => frame.stack.push(T::Float),
+            Opcode::Dconst0 | Opcode::Dconst1 => frame.stack.push(T::Double),
+            Opcode::Ldc(loadable) | Opcode::LdcW(loadable) | Opcode::Ldc2W(loadable) => {
+                let wide = matches!(opcode, Opcode::Ldc2W(_));
+                let t = match loadable {
+                    Loadable::LiteralConstant(LiteralConstant::Integer(_)) => T::Integer,
+                    Loadable::LiteralConstant(LiteralConstant::Float(_)) => T::Float,
+                    Loadable::LiteralConstant(LiteralConstant::Long(_)) => T::Long,
+                    Loadable::LiteralConstant(LiteralConstant::Double(_)) => T::Double,
+                    Loadable::LiteralConstant(LiteralConstant::String(_)) |
+                    Loadable::LiteralConstant(LiteralConstant::StringBytes(_)) => T::object("java/lang/String"),
+                    Loadable::ClassInfo(_) => T::object("java/lang/Class"),
+                    Loadable::MethodHandle(_) => T::object("java/lang/invoke/MethodHandle"),
+                    Loadable::MethodType(_) => T::object("java/lang/invoke/MethodType"),
+                    Loadable::Dynamic(dynamic) => T::from_descriptor(&dynamic.name_and_type.descriptor),
+                };
+                if t.is_category2() != wide {
+                    fail!("Constant of type {:?} loaded with wrong ldc variant", t);
+                }
+                frame.stack.push(t);
+            }
+            Opcode::Iload(n) => frame.stack.push(frame.get_local(*n, &T::Integer)?),
+            Opcode::Lload(n) => frame.stack.push(frame.get_local(*n, &T::Long)?),
+            Opcode::Fload(n) => frame.stack.push(frame.get_local(*n, &T::Float)?),
+            Opcode::Dload(n) => frame.stack.push(frame.get_local(*n, &T::Double)?),
+            Opcode::Aload(n) => frame.stack.push(frame.get_local(*n, &T::object("java/lang/Object"))?),
+            Opcode::Iaload => self.array_load(frame, &["[I"], T::Integer)?,
+            Opcode::Laload => self.array_load(frame, &["[J"], T::Long)?,
+            Opcode::Faload => self.array_load(frame, &["[F"], T::Float)?,
+            Opcode::Daload => self.array_load(frame, &["[D"], T::Double)?,
+            Opcode::Baload => self.array_load(frame, &["[B", "[Z"], T::Integer)?,
+            Opcode::Caload => self.array_load(frame, &["[C"], T::Integer)?,
+            Opcode::Saload => self.array_load(frame, &["[S"], T::Integer)?,
+            Opcode::Aaload => {
+                let component = self.pop_array(frame)?;
+                match component {
+                    None => frame.stack.push(T::Null),
+                    Some(t @ T::Object { .. }) => frame.stack.push(t),
+                    Some(t) => fail!("Expected array of references but found array of {:?}", t),
+                }
+            }
+            Opcode::Istore(n) => { frame.pop_expect(T::Integer)?; frame.set_local(*n, T::Integer)?; }
+            Opcode::Lstore(n) => { frame.pop_expect(T::Long)?; frame.set_local(*n, T::Long)?; }
+            Opcode::Fstore(n) => { frame.pop_expect(T::Float)?; frame.set_local(*n, T::Float)?; }
+            Opcode::Dstore(n) => { frame.pop_expect(T::Double)?; frame.set_local(*n, T::Double)?; }
+            Opcode::Astore(n) => {
+                let t = frame.pop()?;
+                if !t.is_reference() && !matches!(t, T::ReturnAddress { .. }) {
+                    fail!("Expected reference or return address on operand stack but found {:?}", t);
+                }
+                frame.set_local(*n, t)?;
+            }
+            Opcode::Iastore => self.array_store(frame, &["[I"], T::Integer)?,
+            Opcode::Lastore => self.array_store(frame, &["[J"], T::Long)?,
+            Opcode::Fastore => self.array_store(frame, &["[F"], T::Float)?,
+            Opcode::Dastore => self.array_store(frame, &["[D"], T::Double)?,
+            Opcode::Bastore => self.array_store(frame, &["[B", "[Z"], T::Integer)?,
+            Opcode::Castore => self.array_store(frame, &["[C"], T::Integer)?,
+            Opcode::Sastore => self.array_store(frame, &["[S"], T::Integer)?,
+            Opcode::Aastore => {
+                // The component type is checked at runtime, so any reference may be stored.
+                let value = frame.pop()?;
+                if !value.is_initialized_reference() {
+                    fail!("Expected reference on operand stack but found {:?}", value);
+                }
+                match self.pop_array(frame)? {
+                    None | Some(T::Object { .. }) => (),
+                    Some(t) => fail!("Expected array of references but found array of {:?}", t),
+                }
+            }
+            Opcode::Pop => { frame.pop_cat1()?; }
+            Opcode::Pop2 => {
+                if !frame.pop()?.is_category2() {
+                    frame.pop_cat1()?;
+                }
+            }
+            Opcode::Dup => {
+                let v1 = frame.pop_cat1()?;
+                frame.stack.push(v1.clone());
+                frame.stack.push(v1);
+            }
+            Opcode::DupX1 => {
+                let v1 = frame.pop_cat1()?;
+                let v2 = frame.pop_cat1()?;
+                frame.stack.extend(vec![v1.clone(), v2, v1]);
+            }
+            Opcode::DupX2 => {
+                let v1 = frame.pop_cat1()?;
+                let v2 = frame.pop()?;
+                if v2.is_category2() {
+                    frame.stack.extend(vec![v1.clone(), v2, v1]);
+                } else {
+                    let v3 = frame.pop_cat1()?;
+                    frame.stack.extend(vec![v1.clone(), v3, v2, v1]);
+                }
+            }
+            Opcode::Dup2 => {
+                let v1 = frame.pop()?;
+                if v1.is_category2() {
+                    frame.stack.extend(vec![v1.clone(), v1]);
+                } else {
+                    let v2 = frame.pop_cat1()?;
+                    frame.stack.extend(vec![v2.clone(), v1.clone(), v2, v1]);
+                }
+            }
+            Opcode::Dup2X1 => {
+                let v1 = frame.pop()?;
+                if v1.is_category2() {
+                    let v2 = frame.pop_cat1()?;
+                    frame.stack.extend(vec![v1.clone(), v2, v1]);
+                } else {
+                    let v2 = frame.pop_cat1()?;
+                    let v3 = frame.pop_cat1()?;
+                    frame.stack.extend(vec![v2.clone(), v1.clone(), v3, v2, v1]);
+                }
+            }
+            Opcode::Dup2X2 => {
+                let v1 = frame.pop()?;
+                if v1.is_category2() {
+                    let v2 = frame.pop()?;
+                    if v2.is_category2() {
+                        frame.stack.extend(vec![v1.clone(), v2, v1]);
+                    } else {
+                        let v3 = frame.pop_cat1()?;
+                        frame.stack.extend(vec![v1.clone(), v3, v2, v1]);
+                    }
+                } else {
+                    let v2 = frame.pop_cat1()?;
+                    let v3 = frame.pop()?;
+                    if v3.is_category2() {
+                        frame.stack.extend(vec![v2.clone(), v1.clone(), v3, v2, v1]);
+                    } else {
+                        let v4 = frame.pop_cat1()?;
+                        frame.stack.extend(vec![v2.clone(), v1.clone(), v4, v3, v2, v1]);
+                    }
+                }
+            }
+            Opcode::Swap => {
+                let v1 = frame.pop_cat1()?;
+                let v2 = frame.pop_cat1()?;
+                frame.stack.push(v1);
+                frame.stack.push(v2);
+            }
+            Opcode::Iadd | Opcode::Isub | Opcode::Imul | Opcode::Idiv | Opcode::Irem |
+            Opcode::Iand | Opcode::Ior | Opcode::Ixor |
+            Opcode::Ishl | Opcode::Ishr | Opcode::Iushr => Self::binary(frame, T::Integer)?,
+            Opcode::Ladd | Opcode::Lsub | Opcode::Lmul | Opcode::Ldiv | Opcode::Lrem |
+            Opcode::Land | Opcode::Lor | Opcode::Lxor => Self::binary(frame, T::Long)?,
+            Opcode::Lshl | Opcode::Lshr | Opcode::Lushr => {
+                frame.pop_expect(T::Integer)?;
+                Self::convert(frame, T::Long, T::Long)?;
+            }
+            Opcode::Fadd | Opcode::Fsub | Opcode::Fmul | Opcode::Fdiv | Opcode::Frem => Self::binary(frame, T::Float)?,
+            Opcode::Dadd | Opcode::Dsub | Opcode::Dmul | Opcode::Ddiv | Opcode::Drem => Self::binary(frame, T::Double)?,
+            Opcode::Ineg => Self::convert(frame, T::Integer, T::Integer)?,
+            Opcode::Lneg => Self::convert(frame, T::Long, T::Long)?,
+            Opcode::Fneg => Self::convert(frame, T::Float, T::Float)?,
+            Opcode::Dneg => Self::convert(frame, T::Double, T::Double)?,
+            Opcode::Iinc(n, _) => { frame.get_local(*n, &T::Integer)?; }
+            Opcode::I2l => Self::convert(frame, T::Integer, T::Long)?,
+            Opcode::I2f => Self::convert(frame, T::Integer, T::Float)?,
+            Opcode::I2d => Self::convert(frame, T::Integer, T::Double)?,
+            Opcode::L2i => Self::convert(frame, T::Long, T::Integer)?,
+            Opcode::L2f => Self::convert(frame, T::Long, T::Float)?,
+            Opcode::L2d => Self::convert(frame, T::Long, T::Double)?,
+            Opcode::F2i => Self::convert(frame, T::Float, T::Integer)?,
+            Opcode::F2l => Self::convert(frame, T::Float, T::Long)?,
+            Opcode::F2d => Self::convert(frame, T::Float, T::Double)?,
+            Opcode::D2i => Self::convert(frame, T::Double, T::Integer)?,
+            Opcode::D2l => Self::convert(frame, T::Double, T::Long)?,
+            Opcode::D2f => Self::convert(frame, T::Double, T::Float)?,
+            Opcode::I2b | Opcode::I2c | Opcode::I2s => Self::convert(frame, T::Integer, T::Integer)?,
+            Opcode::Lcmp => { frame.pop_expect(T::Long)?; Self::convert(frame, T::Long, T::Integer)?; }
+            Opcode::Fcmpl | Opcode::Fcmpg => { frame.pop_expect(T::Float)?; Self::convert(frame, T::Float, T::Integer)?; }
+            Opcode::Dcmpl | Opcode::Dcmpg => { frame.pop_expect(T::Double)?; Self::convert(frame, T::Double, T::Integer)?; }
+            Opcode::Ifeq(_) | Opcode::Ifne(_) | Opcode::Iflt(_) |
+            Opcode::Ifge(_) | Opcode::Ifgt(_) | Opcode::Ifle(_) => frame.pop_expect(T::Integer)?,
+            Opcode::IfIcmpeq(_) | Opcode::IfIcmpne(_) | Opcode::IfIcmplt(_) |
+            Opcode::IfIcmpge(_) | Opcode::IfIcmpgt(_) | Opcode::IfIcmple(_) => {
+                frame.pop_expect(T::Integer)?;
+                frame.pop_expect(T::Integer)?;
+            }
+            Opcode::IfAcmpeq(_) | Opcode::IfAcmpne(_) => {
+                frame.pop_reference()?;
+                frame.pop_reference()?;
+            }
+            Opcode::Ifnull(_) | Opcode::Ifnonnull(_) => { frame.pop_reference()?; }
+            Opcode::Goto(_) => (),
+            Opcode::Jsr(j) => {
+                let target = jump_target(self.bytecode, offset, *j)?;
+                frame.stack.push(T::ReturnAddress { subroutine: self.bytecode.opcodes[target].0 });
+            }
+            Opcode::Ret(n) => {
+                match frame.locals.get(usize::from(*n)) {
+                    Some(T::ReturnAddress { .. }) => (),
+                    t => fail!("Expected return address in local variable {} but found {:?}", n, t),
+                }
+            }
+            Opcode::Tableswitch(_) | Opcode::Lookupswitch(_) => frame.pop_expect(T::Integer)?,
+            Opcode::Ireturn => self.check_return(frame, Some(T::Integer))?,
+            Opcode::Lreturn => self.check_return(frame, Some(T::Long))?,
+            Opcode::Freturn => self.check_return(frame, Some(T::Float))?,
+            Opcode::Dreturn => self.check_return(frame, Some(T::Double))?,
+            Opcode::Areturn => self.check_return(frame, Some(T::object("java/lang/Object")))?,
+            Opcode::Return => self.check_return(frame, None)?,
+            Opcode::Getstatic(member) => frame.stack.push(T::from_descriptor(&member.name_and_type.descriptor)),
+            Opcode::Putstatic(member) => self.pop_assignable(frame, &T::from_descriptor(&member.name_and_type.descriptor))?,
+            Opcode::Getfield(member) => {
+                let receiver = frame.pop()?;
+                self.check_assignable(&receiver, &T::object(&member.class_name))?;
+                frame.stack.push(T::from_descriptor(&member.name_and_type.descriptor));
+            }
+            Opcode::Putfield(member) => {
+                self.pop_assignable(frame, &T::from_descriptor(&member.name_and_type.descriptor))?;
+                let receiver = frame.pop()?;
+                // Constructors may assign fields declared by this class before calling super.<init>
+                let own_field = receiver == T::UninitializedThis && member.class_name == self.class.this_class;
+                if !own_field {
+                    self.check_assignable(&receiver, &T::object(&member.class_name))?;
+                }
+            }
+            Opcode::Invokevirtual(member) => self.invoke(frame, member, true, false)?,
+            Opcode::Invokespecial(member) => self.invoke(frame, member, true, true)?,
+            Opcode::Invokestatic(member) => self.invoke(frame, member, false, false)?,
+            Opcode::Invokeinterface(member, _) => {
+                // Interface types are treated as java/lang/Object, so any receiver is accepted.
+                let object_member = MemberRef {
+                    class_name: "java/lang/Object".to_string(),
+                    name_and_type: crate::constant_pool::NameAndType {
+                        name: member.name_and_type.name.clone(),
+                        descriptor: member.name_and_type.descriptor.clone(),
+                    },
+                };
+                self.invoke(frame, &object_member, true, false)?;
+            }
+            Opcode::Invokedynamic(indy) => {
+                let member = MemberRef {
+                    class_name: "java/lang/Object".to_string(),
+                    name_and_type: crate::constant_pool::NameAndType {
+                        name: indy.name_and_type.name.clone(),
+                        descriptor: indy.name_and_type.descriptor.clone(),
+                    },
+                };
+                self.invoke(frame, &member, false, false)?;
+            }
+            Opcode::New(_) => frame.stack.push(T::Uninitialized { code_offset: offset }),
+            Opcode::Newarray(primitive) => {
+                frame.pop_expect(T::Integer)?;
+                let descriptor = match primitive {
+                    PrimitiveArrayType::Boolean => "[Z",
+                    PrimitiveArrayType::Char => "[C",
+                    PrimitiveArrayType::Float => "[F",
+                    PrimitiveArrayType::Double => "[D",
+                    PrimitiveArrayType::Byte => "[B",
+                    PrimitiveArrayType::Short => "[S",
+                    PrimitiveArrayType::Int => "[I",
+                    PrimitiveArrayType::Long => "[J",
+                };
+                frame.stack.push(T::object(descriptor));
+            }
+            Opcode::Anewarray(class_name) => {
+                frame.pop_expect(T::Integer)?;
+                let descriptor = if class_name.starts_with('[') { format!("[{}", class_name) } else { format!("[L{};", class_name) };
+                frame.stack.push(T::Object { class_name: descriptor });
+            }
+            Opcode::Multianewarray(class_name, dimensions) => {
+                for _ in 0..*dimensions {
+                    frame.pop_expect(T::Integer)?;
+                }
+                frame.stack.push(T::object(class_name));
+            }
+            Opcode::Arraylength => {
+                let array = frame.pop()?;
+                if array != T::Null && array.array_component().is_none() {
+                    fail!("Expected array reference but found {:?}", array);
+                }
+                frame.stack.push(T::Integer);
+            }
+            Opcode::Athrow => self.pop_assignable(frame, &T::object("java/lang/Throwable"))?,
+            Opcode::Checkcast(class_name) => {
+                if !frame.pop()?.is_initialized_reference() {
+                    fail!("Expected initialized reference for checkcast");
+                }
+                frame.stack.push(T::object(class_name));
+            }
+            Opcode::Instanceof(_) => {
+                if !frame.pop()?.is_initialized_reference() {
+                    fail!("Expected initialized reference for instanceof");
+                }
+                frame.stack.push(T::Integer);
+            }
+            Opcode::Monitorenter | Opcode::Monitorexit => {
+                if !frame.pop()?.is_initialized_reference() {
+                    fail!("Expected initialized reference for monitor instruction");
+                }
+            }
+            Opcode::Breakpoint | Opcode::Impdep1 | Opcode::Impdep2 => fail!("Reserved opcode found"),
+        };
+        if frame.stack_size() > usize::from(self.code.max_stack) {
+            fail!("Operand stack overflow; max_stack is {}", self.code.max_stack);
+        }
+        Ok(())
+    }
+
+    fn initial_frame(&self) -> Result<Frame, ParseError> {
+        let (params, _) = match split_method_descriptor(&self.method.descriptor) {
+            Some(parts) => parts,
+            None => fail!("Invalid method descriptor"),
+        };
+        let mut locals = Vec::new();
+        if !self.method.access_flags.contains(MethodAccessFlags::STATIC) {
+            if self.method.name == "<init>" && self.class.this_class != "java/lang/Object" {
+                locals.push(InferredType::UninitializedThis);
+            } else {
+                locals.push(InferredType::object(&self.class.this_class));
+            }
+        }
+        for param in params {
+            let t = InferredType::from_descriptor(param);
+            let wide = t.is_category2();
+            locals.push(t);
+            if wide {
+                locals.push(InferredType::Top);
+            }
+        }
+        if locals.len() > usize::from(self.code.max_locals) {
+            fail!("Method arguments require {} locals but max_locals is {}", locals.len(), self.code.max_locals);
+        }
+        locals.resize(self.code.max_locals.into(), InferredType::Top);
+        Ok(Frame { locals, stack: Vec::new() })
+    }
+
+    fn process(&mut self, index: usize) -> Result<(), ParseError> {
+        let (offset, opcode) = &self.bytecode.opcodes[index];
+        let before = self.frames[index].clone().unwrap();
+        let mut after = before.clone();
+        self.execute(*offset, opcode, &mut after)?;
+
+        for (handler, catch_type) in self.handlers_for(index)? {
+            for locals in &[&before.locals, &after.locals] {
+                let handler_frame = Frame { locals: locals.to_vec(), stack: vec![catch_type.clone()] };
+                self.merge_into(handler, &handler_frame)?;
+            }
+        }
+
+        match opcode {
+            Opcode::Jsr(j) => {
+                let target = jump_target(self.bytecode, *offset, *j)?;
+                self.merge_into(target, &after)?;
+                let sub = self.bytecode.opcodes[target].0;
+                if let Some(ret_frame) = self.ret_frames.get(&target).cloned() {
+                    self.return_from_subroutine(target, sub, index, &ret_frame)?;
+                }
+            }
+            Opcode::Ret(n) => {
+                let sub = match &after.locals[usize::from(*n)] {
+                    InferredType::ReturnAddress { subroutine } => *subroutine,
+                    _ => unreachable!(),
+                };
+                let start = self.bytecode.get_opcode_index(sub).unwrap();
+                let merged = match self.ret_frames.get(&start) {
+                    None => after.clone(),
+                    Some(existing) => {
+                        let mut merged = existing.clone();
+                        if merged.stack.len() != after.stack.len() {
+                            fail!("Inconsistent operand stack height when returning from subroutine");
+                        }
+                        for (a, b) in merged.locals.iter_mut().zip(after.locals.iter()) {
+                            *a = self.merge_type(a, b);
+                        }
+                        for (a, b) in merged.stack.iter_mut().zip(after.stack.iter()) {
+                            *a = self.merge_type(a, b);
+                        }
+                        merged
+                    }
+                };
+                self.ret_frames.insert(start, merged.clone());
+                let callers = self.subroutines[&start].callers.clone();
+                for caller in callers {
+                    self.return_from_subroutine(start, sub, caller, &merged)?;
+                }
+            }
+            _ => {
+                for succ in self.successors(index)? {
+                    self.merge_into(succ, &after)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn return_from_subroutine(&mut self, start: usize, sub: usize, caller: usize, ret_frame: &Frame) -> Result<(), ParseError> {
+        let call_frame = match &self.frames[caller] {
+            Some(frame) => frame.clone(),
+            None => return Ok(()),
+        };
+        if caller + 1 >= self.bytecode.opcodes.len() {
+            fail!("Execution falls off the end of the code after returning from subroutine at offset {}", sub);
+        }
+        let used = &self.subroutines[&start].locals_used;
+        let locals = (0..call_frame.locals.len()).map(|i| {
+            let used_by_sub = u16::try_from(i).map(|i| used.contains(&i)).unwrap_or(false);
+            if used_by_sub { ret_frame.locals[i].clone() } else { call_frame.locals[i].clone() }
+        }).collect();
+        let frame = Frame { locals, stack: ret_frame.stack.clone() };
+        self.merge_into(caller + 1, &frame)
+    }
+
+    fn run(&mut self) -> Result<(), ParseError> {
+        if self.bytecode.opcodes.is_empty() {
+            fail!("Method has no code");
+        }
+        self.find_subroutines()?;
+        let initial = self.initial_frame()?;
+        self.merge_into(0, &initial)?;
+        while let Some(index) = self.worklist.pop_front() {
+            self.process(index).map_err(|e| err!(e, "opcode at offset {}", self.bytecode.opcodes[index].0))?;
+        }
+        Ok(())
+    }
+}
+
+/// Infers the types of the local variables and operand stack entries on entry to every
+/// instruction of the given method, using the dataflow analysis from JVMS 4.10.2. This is
+/// how class files with major version below 50 are verified, since they carry no
+/// StackMapTable attributes. The returned vector is indexed the same way as the
+/// `opcodes` field of the method's `ByteCode`; unreachable instructions have no frame.
+/// Fails if the method has no Code attribute, if its bytecode was not parsed, or if
+/// a type error is found.
+pub fn infer_frames(class: &ClassFile, method: &MethodInfo, oracle: &dyn SubtypeOracle) -> Result<Vec<Option<Frame>>, ParseError> {
+    let code = method.attributes.iter().find(|attr| attr.name == "Code").map(AttributeInfo::decoded).transpose()?;
+    let code = match code {
+        Some(AttributeData::Code(code)) => code,
+        _ => fail!("No Code attribute found for method {}{}", method.name, method.descriptor),
+    };
+    let bytecode = match &code.bytecode {
+        Some(bytecode) => bytecode,
+        None => fail!("Bytecode was not parsed for method {}{}", method.name, method.descriptor),
+    };
+    let return_type = match split_method_descriptor(&method.descriptor) {
+        Some((_, "V")) => None,
+        Some((_, ret)) => Some(InferredType::from_descriptor(ret)),
+        None => fail!("Invalid method descriptor for method {}{}", method.name, method.descriptor),
+    };
+    let mut verifier = Verifier {
+        class,
+        method,
+        code,
+        bytecode,
+        oracle,
+        return_type,
+        frames: vec![None; bytecode.opcodes.len()],
+        worklist: VecDeque::new(),
+        subroutines: HashMap::new(),
+        ret_frames: HashMap::new(),
+    };
+    verifier.run().map_err(|e| err!(e, "method {}{}", method.name, method.descriptor))?;
+    Ok(verifier.frames)
+}
+
+/// Verifies every method with a Code attribute in the class by type inference.
+/// The class must have been parsed with bytecode parsing enabled.
+pub fn verify_class(class: &ClassFile, oracle: &dyn SubtypeOracle) -> Result<(), ParseError> {
+    for method in &class.methods {
+        if method.access_flags.intersects(MethodAccessFlags::ABSTRACT | MethodAccessFlags::NATIVE) {
+            continue;
+        }
+        infer_frames(class, method, oracle)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::attributes::AttributeInfo;
+    use crate::ClassAccessFlags;
+    use std::rc::Rc;
+
+    fn class_with(descriptor: &str, max_locals: u16, opcodes: Vec<(usize, Opcode)>) -> ClassFile {
+        let code = CodeData {
+            max_stack: 4,
+            max_locals,
+            code: Vec::new(),
+            bytecode: Some(ByteCode { opcodes }),
+            exception_table: Vec::new(),
+            attributes: Vec::new(),
+        };
+        ClassFile {
+            major_version: 49,
+            minor_version: 0,
+            constant_pool: vec![Rc::new(crate::constant_pool::ConstantPoolEntry::Zero)],
+            access_flags: ClassAccessFlags::PUBLIC,
+            this_class: "Test".to_string(),
+            super_class: Some("java/lang/Object".to_string()),
+            interfaces: Vec::new(),
+            fields: Vec::new(),
+            methods: vec![MethodInfo {
+                access_flags: MethodAccessFlags::STATIC,
+                name: "test".to_string(),
+                descriptor: descriptor.to_string(),
+                attributes: vec![AttributeInfo { name: "Code".to_string(), data: AttributeData::Code(code) }],
+            }],
+            attributes: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_merge_at_join() {
+        // static int test(int x) { int y; if (x == 0) y = 1; else y = 2; return y; }
+        let class = class_with("(I)I", 2, vec![
+            (0, Opcode::Iload(0)),
+            (1, Opcode::Ifeq(7)),
+            (4, Opcode::Iconst2),
+            (5, Opcode::Goto(4)),
+            (8, Opcode::Iconst1),
+            (9, Opcode::Istore(1)),
+            (10, Opcode::Iload(1)),
+            (11, Opcode::Ireturn),
+        ]);
+        let frames = infer_frames(&class, &class.methods[0], &LenientOracle).unwrap();
+        assert_eq!(frames[5].as_ref().unwrap().stack, vec![InferredType::Integer]);
+        assert_eq!(frames[7].as_ref().unwrap().locals, vec![InferredType::Integer, InferredType::Integer]);
+    }
+
+    #[test]
+    fn test_type_error() {
+        let class = class_with("(J)I", 2, vec![
+            (0, Opcode::Iload(0)),
+            (1, Opcode::Ireturn),
+        ]);
+        assert!(verify_class(&class, &LenientOracle).is_err());
+
+        let class = class_with("()V", 1, vec![
+            (0, Opcode::Iconst0),
+            (1, Opcode::Istore(0)),
+            (2, Opcode::Iload(0)),
+            (3, Opcode::Ifeq(-3)),
+            (6, Opcode::AconstNull),
+            (7, Opcode::Goto(-5)),
+        ]);
+        // The stack height differs when the back edge reaches offset 2
+        assert!(verify_class(&class, &LenientOracle).is_err());
+    }
+
+    #[test]
+    fn test_subroutine() {
+        // static void test() { try { } finally { int z = 0; } } compiled with jsr/ret
+        let class = class_with("()V", 3, vec![
+            (0, Opcode::Jsr(4)),
+            (3, Opcode::Return),
+            (4, Opcode::Astore(0)),
+            (5, Opcode::Iconst0),
+            (6, Opcode::Istore(1)),
+            (7, Opcode::Ret(0)),
+        ]);
+        let frames = infer_frames(&class, &class.methods[0], &LenientOracle).unwrap();
+        let after_call = frames[1].as_ref().unwrap();
+        assert!(after_call.stack.is_empty());
+        assert_eq!(after_call.locals[1], InferredType::Integer);
+    }
+}
@@ -0,0 +1,1307 @@
+use std::collections::HashMap;
+use std::convert::TryFrom;
+
+use crate::attributes::{
+    Annotation, AnnotationElementValue, AttributeData, AttributeInfo, AttributeLocation, CodeData, StackMapEntry,
+    TypeAnnotation, TypeAnnotationTarget, TypeAnnotationTargetPathKind, VerificationType,
+};
+use crate::bytecode::{JumpOffset, Opcode, PrimitiveArrayType};
+use crate::constant_pool::{
+    indexed_constant_pool, BootstrapArgument, IndexedEntry, InvokeDynamic, LiteralConstant, Loadable, MemberKind,
+    MemberRef, MethodHandle, NameAndType, ReferenceKind,
+};
+use crate::{ClassFile, ParseError};
+
+fn write_u1(out: &mut Vec<u8>, v: u8) {
+    out.push(v);
+}
+
+fn write_u2(out: &mut Vec<u8>, v: u16) {
+    out.extend_from_slice(&v.to_be_bytes());
+}
+
+fn write_u4(out: &mut Vec<u8>, v: u32) {
+    out.extend_from_slice(&v.to_be_bytes());
+}
+
+fn write_count(out: &mut Vec<u8>, count: usize, what: &str) -> Result<(), ParseError> {
+    match u16::try_from(count) {
+        Ok(count) => write_u2(out, count),
+        Err(_) => fail!("Too many {} ({}) to write", what, count),
+    }
+    Ok(())
+}
+
+fn write_u1_count(out: &mut Vec<u8>, count: usize, what: &str) -> Result<(), ParseError> {
+    match u8::try_from(count) {
+        Ok(count) => write_u1(out, count),
+        Err(_) => fail!("Too many {} ({}) to write", what, count),
+    }
+    Ok(())
+}
+
+fn write_length(out: &mut Vec<u8>, length: usize, what: &str) -> Result<(), ParseError> {
+    match u32::try_from(length) {
+        Ok(length) => write_u4(out, length),
+        Err(_) => fail!("{} is too long to write", what),
+    }
+    Ok(())
+}
+
+fn reference_kind_byte(kind: ReferenceKind) -> u8 {
+    match kind {
+        ReferenceKind::GetField => 1,
+        ReferenceKind::GetStatic => 2,
+        ReferenceKind::PutField => 3,
+        ReferenceKind::PutStatic => 4,
+        ReferenceKind::InvokeVirtual => 5,
+        ReferenceKind::InvokeStatic => 6,
+        ReferenceKind::InvokeSpecial => 7,
+        ReferenceKind::NewInvokeSpecial => 8,
+        ReferenceKind::InvokeInterface => 9,
+    }
+}
+
+/// Builds the constant pool for a class file being written. Entries are deduplicated, so
+/// adding a constant that is already present returns the index of the existing entry.
+pub(crate) struct ConstantPoolWriter {
+    entries: Vec<Option<IndexedEntry>>,
+    lookup: HashMap<IndexedEntry, u16>,
+}
+
+impl ConstantPoolWriter {
+    pub(crate) fn new() -> Self {
+        ConstantPoolWriter {
+            entries: vec![None],
+            lookup: HashMap::new(),
+        }
+    }
+
+    /// Creates a constant pool writer that starts out with the entries of an existing constant
+    /// pool, at the same indices.
+    pub(crate) fn from_class(class: &ClassFile) -> Self {
+        if class.constant_pool.is_empty() {
+            return Self::new();
+        }
+        Self::from_entries(indexed_constant_pool(&class.constant_pool))
+    }
+
+    /// Creates a constant pool writer that starts out with the given entries, which must
+    /// begin with the unused entry at index zero.
+    pub(crate) fn from_entries(entries: Vec<Option<IndexedEntry>>) -> Self {
+        let mut lookup = HashMap::new();
+        for (i, entry) in entries.iter().enumerate() {
+            if let Some(entry) = entry {
+                lookup.entry(entry.clone()).or_insert(i as u16);
+            }
+        }
+        ConstantPoolWriter { entries, lookup }
+    }
+
+    fn add(&mut self, entry: IndexedEntry) -> Result<u16, ParseError> {
+        if let Some(index) = self.lookup.get(&entry) {
+            return Ok(*index);
+        }
+        let wide = matches!(entry, IndexedEntry::Long(_) | IndexedEntry::Double(_));
+        let index = self.entries.len();
+        // The constant_pool_count field is a u2 that is one more than the last index used
+        if index + if wide { 2 } else { 1 } > usize::from(u16::MAX) {
+            fail!("Too many constant pool entries to write");
+        }
+        let index = index as u16;
+        self.lookup.insert(entry.clone(), index);
+        self.entries.push(Some(entry));
+        if wide {
+            self.entries.push(None);
+        }
+        Ok(index)
+    }
+
+    fn find(&self, entry: &IndexedEntry) -> Option<u16> {
+        self.lookup.get(entry).copied()
+    }
+
+    pub(crate) fn is_interface_method_ref(&self, index: u16) -> bool {
+        matches!(self.entries.get(usize::from(index)), Some(Some(IndexedEntry::InterfaceMethodRef(_, _))))
+    }
+
+    pub(crate) fn utf8(&mut self, value: &str) -> Result<u16, ParseError> {
+        if cesu8::to_java_cesu8(value).len() > usize::from(u16::MAX) {
+            fail!("String of length {} is too long for the constant pool", value.len());
+        }
+        self.add(IndexedEntry::Utf8(value.to_string()))
+    }
+
+    fn utf8_bytes(&mut self, value: &[u8]) -> Result<u16, ParseError> {
+        if value.len() > usize::from(u16::MAX) {
+            fail!("String of length {} is too long for the constant pool", value.len());
+        }
+        self.add(IndexedEntry::Utf8Bytes(value.to_vec()))
+    }
+
+    fn utf8_opt(&mut self, value: &Option<String>) -> Result<u16, ParseError> {
+        match value {
+            Some(value) => self.utf8(value),
+            None => Ok(0),
+        }
+    }
+
+    pub(crate) fn class(&mut self, name: &str) -> Result<u16, ParseError> {
+        let name = self.utf8(name)?;
+        self.add(IndexedEntry::ClassInfo(name))
+    }
+
+    fn class_opt(&mut self, name: &Option<String>) -> Result<u16, ParseError> {
+        match name {
+            Some(name) => self.class(name),
+            None => Ok(0),
+        }
+    }
+
+    fn module(&mut self, name: &str) -> Result<u16, ParseError> {
+        let name = self.utf8(name)?;
+        self.add(IndexedEntry::ModuleInfo(name))
+    }
+
+    fn package(&mut self, name: &str) -> Result<u16, ParseError> {
+        let name = self.utf8(name)?;
+        self.add(IndexedEntry::PackageInfo(name))
+    }
+
+    fn name_and_type(&mut self, name_and_type: &NameAndType) -> Result<u16, ParseError> {
+        let name = self.utf8(&name_and_type.name)?;
+        let descriptor = self.utf8(&name_and_type.descriptor)?;
+        self.add(IndexedEntry::NameAndType(name, descriptor))
+    }
+
+    fn member_entry(kind: MemberKind, class: u16, name_and_type: u16) -> IndexedEntry {
+        match kind {
+            MemberKind::Field => IndexedEntry::FieldRef(class, name_and_type),
+            MemberKind::Method => IndexedEntry::MethodRef(class, name_and_type),
+            MemberKind::InterfaceMethod => IndexedEntry::InterfaceMethodRef(class, name_and_type),
+        }
+    }
+
+    pub(crate) fn member_ref(&mut self, kind: MemberKind, class_name: &str, name_and_type: &NameAndType) -> Result<u16, ParseError> {
+        let class = self.class(class_name)?;
+        let name_and_type = self.name_and_type(name_and_type)?;
+        self.add(Self::member_entry(kind, class, name_and_type))
+    }
+
+    /// Adds a reference to a method that may be either a class or an interface method, as
+    /// used by the invokespecial and invokestatic instructions. An existing entry of either
+    /// kind is reused, and a new entry is added as a Methodref.
+    fn any_method_ref(&mut self, member: &MemberRef) -> Result<u16, ParseError> {
+        let class = self.class(&member.class_name)?;
+        let name_and_type = self.name_and_type(&member.name_and_type)?;
+        if let Some(index) = self.find(&IndexedEntry::MethodRef(class, name_and_type)) {
+            return Ok(index);
+        }
+        if let Some(index) = self.find(&IndexedEntry::InterfaceMethodRef(class, name_and_type)) {
+            return Ok(index);
+        }
+        self.add(IndexedEntry::MethodRef(class, name_and_type))
+    }
+
+    fn literal(&mut self, literal: &LiteralConstant) -> Result<u16, ParseError> {
+        let entry = self.literal_entry(literal)?;
+        self.add(entry)
+    }
+
+    /// Adds the entries that a literal constant refers to, and returns the entry for the
+    /// constant itself without adding it.
+    fn literal_entry(&mut self, literal: &LiteralConstant) -> Result<IndexedEntry, ParseError> {
+        let entry = match literal {
+            LiteralConstant::Integer(v) => IndexedEntry::Integer(*v),
+            LiteralConstant::Float(v) => IndexedEntry::Float(v.to_bits()),
+            LiteralConstant::Long(v) => IndexedEntry::Long(*v),
+            LiteralConstant::Double(v) => IndexedEntry::Double(v.to_bits()),
+            LiteralConstant::String(s) => IndexedEntry::String(self.utf8(s)?),
+            LiteralConstant::StringBytes(b) => IndexedEntry::String(self.utf8_bytes(b)?),
+        };
+        Ok(entry)
+    }
+
+    fn method_handle(&mut self, handle: &MethodHandle) -> Result<u16, ParseError> {
+        let member = self.member_ref(handle.member_kind, &handle.class_name, &handle.member_ref)?;
+        self.add(IndexedEntry::MethodHandle(handle.kind, member))
+    }
+
+    fn method_type(&mut self, descriptor: &str) -> Result<u16, ParseError> {
+        let descriptor = self.utf8(descriptor)?;
+        self.add(IndexedEntry::MethodType(descriptor))
+    }
+
+    pub(crate) fn loadable(&mut self, loadable: &Loadable) -> Result<u16, ParseError> {
+        let entry = self.loadable_entry(loadable)?;
+        self.add(entry)
+    }
+
+    fn loadable_entry(&mut self, loadable: &Loadable) -> Result<IndexedEntry, ParseError> {
+        let entry = match loadable {
+            Loadable::LiteralConstant(literal) => return self.literal_entry(literal),
+            Loadable::ClassInfo(name) => IndexedEntry::ClassInfo(self.utf8(name)?),
+            Loadable::MethodHandle(handle) => {
+                let member = self.member_ref(handle.member_kind, &handle.class_name, &handle.member_ref)?;
+                IndexedEntry::MethodHandle(handle.kind, member)
+            }
+            Loadable::MethodType(descriptor) => IndexedEntry::MethodType(self.utf8(descriptor)?),
+            Loadable::Dynamic(dynamic) => {
+                let name_and_type = self.name_and_type(&dynamic.name_and_type)?;
+                IndexedEntry::Dynamic(dynamic.attr_index, name_and_type)
+            }
+        };
+        Ok(entry)
+    }
+
+    /// Adds loadable constants so that they get the lowest free indices, with the entries
+    /// they refer to placed after all of them. This keeps as many of them as possible within
+    /// reach of the ldc instruction, which can only use the first 256 entries of the pool.
+    pub(crate) fn add_low_loadables(&mut self, loadables: &[&Loadable]) -> Result<(), ParseError> {
+        let is_wide = |entry: &IndexedEntry| matches!(entry, IndexedEntry::Long(_) | IndexedEntry::Double(_));
+        // Whether a constant is new does not depend on where entries are placed, so a trial
+        // run tells how many slots to set aside
+        let mut trial = ConstantPoolWriter {
+            entries: self.entries.clone(),
+            lookup: self.lookup.clone(),
+        };
+        let mut count = 0;
+        for loadable in loadables {
+            let entry = trial.loadable_entry(loadable)?;
+            if !is_wide(&entry) && trial.find(&entry).is_none() {
+                count += 1;
+            }
+            trial.add(entry)?;
+        }
+        let mut next = self.entries.len();
+        self.entries.resize(next + count, None);
+        for loadable in loadables {
+            let entry = self.loadable_entry(loadable)?;
+            if is_wide(&entry) || self.find(&entry).is_some() {
+                self.add(entry)?;
+                continue;
+            }
+            self.lookup.insert(entry.clone(), next as u16);
+            self.entries[next] = Some(entry);
+            next += 1;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn invoke_dynamic(&mut self, invoke_dynamic: &InvokeDynamic) -> Result<u16, ParseError> {
+        let name_and_type = self.name_and_type(&invoke_dynamic.name_and_type)?;
+        self.add(IndexedEntry::InvokeDynamic(invoke_dynamic.attr_index, name_and_type))
+    }
+
+    fn bootstrap_argument(&mut self, argument: &BootstrapArgument) -> Result<u16, ParseError> {
+        match argument {
+            BootstrapArgument::LiteralConstant(literal) => self.literal(literal),
+            BootstrapArgument::ClassInfo(name) => self.class(name),
+            BootstrapArgument::MethodHandle(handle) => self.method_handle(handle),
+            BootstrapArgument::MethodType(descriptor) => self.method_type(descriptor),
+        }
+    }
+
+    pub(crate) fn write(&self, out: &mut Vec<u8>) {
+        // The size of the pool is checked as entries are added
+        write_u2(out, self.entries.len() as u16);
+        for entry in self.entries.iter().flatten() {
+            match entry {
+                IndexedEntry::Utf8(x) => {
+                    let bytes = cesu8::to_java_cesu8(x);
+                    write_u1(out, 1);
+                    write_u2(out, bytes.len() as u16);
+                    out.extend_from_slice(&bytes);
+                }
+                IndexedEntry::Utf8Bytes(x) => {
+                    write_u1(out, 1);
+                    write_u2(out, x.len() as u16);
+                    out.extend_from_slice(x);
+                }
+                IndexedEntry::Integer(v) => {
+                    write_u1(out, 3);
+                    out.extend_from_slice(&v.to_be_bytes());
+                }
+                IndexedEntry::Float(v) => {
+                    write_u1(out, 4);
+                    write_u4(out, *v);
+                }
+                IndexedEntry::Long(v) => {
+                    write_u1(out, 5);
+                    out.extend_from_slice(&v.to_be_bytes());
+                }
+                IndexedEntry::Double(v) => {
+                    write_u1(out, 6);
+                    out.extend_from_slice(&v.to_be_bytes());
+                }
+                IndexedEntry::ClassInfo(x) => {
+                    write_u1(out, 7);
+                    write_u2(out, *x);
+                }
+                IndexedEntry::String(x) => {
+                    write_u1(out, 8);
+                    write_u2(out, *x);
+                }
+                IndexedEntry::FieldRef(x, y) => {
+                    write_u1(out, 9);
+                    write_u2(out, *x);
+                    write_u2(out, *y);
+                }
+                IndexedEntry::MethodRef(x, y) => {
+                    write_u1(out, 10);
+                    write_u2(out, *x);
+                    write_u2(out, *y);
+                }
+                IndexedEntry::InterfaceMethodRef(x, y) => {
+                    write_u1(out, 11);
+                    write_u2(out, *x);
+                    write_u2(out, *y);
+                }
+                IndexedEntry::NameAndType(x, y) => {
+                    write_u1(out, 12);
+                    write_u2(out, *x);
+                    write_u2(out, *y);
+                }
+                IndexedEntry::MethodHandle(x, y) => {
+                    write_u1(out, 15);
+                    write_u1(out, reference_kind_byte(*x));
+                    write_u2(out, *y);
+                }
+                IndexedEntry::MethodType(x) => {
+                    write_u1(out, 16);
+                    write_u2(out, *x);
+                }
+                IndexedEntry::Dynamic(x, y) => {
+                    write_u1(out, 17);
+                    write_u2(out, *x);
+                    write_u2(out, *y);
+                }
+                IndexedEntry::InvokeDynamic(x, y) => {
+                    write_u1(out, 18);
+                    write_u2(out, *x);
+                    write_u2(out, *y);
+                }
+                IndexedEntry::ModuleInfo(x) => {
+                    write_u1(out, 19);
+                    write_u2(out, *x);
+                }
+                IndexedEntry::PackageInfo(x) => {
+                    write_u1(out, 20);
+                    write_u2(out, *x);
+                }
+            }
+        }
+    }
+}
+
+/// Writes the instruction for a local variable opcode. Indices 0 to 3 of most opcodes have a
+/// one-byte form, indices up to 255 have a two-byte form, and all indices have a four-byte
+/// form using the wide modifier. The form of the given size is used if there is one for the
+/// index, and the shortest form otherwise.
+fn write_local(out: &mut Vec<u8>, opcode: u8, short_opcode: Option<u8>, index: u16, size: Option<usize>) {
+    let short = short_opcode.filter(|_| index <= 3);
+    let narrow = index <= 0xff;
+    match size {
+        Some(4) => (),
+        Some(2) if narrow => {
+            out.push(opcode);
+            out.push(index as u8);
+            return;
+        }
+        _ => {
+            if let Some(short) = short {
+                out.push(short + index as u8);
+                return;
+            }
+            if narrow {
+                out.push(opcode);
+                out.push(index as u8);
+                return;
+            }
+        }
+    }
+    out.push(0xc4);
+    out.push(opcode);
+    write_u2(out, index);
+}
+
+fn write_branch(out: &mut Vec<u8>, opcode: u8, wide_opcode: Option<u8>, jump: JumpOffset, size: Option<usize>) -> Result<(), ParseError> {
+    match (i16::try_from(jump), wide_opcode) {
+        (_, Some(wide_opcode)) if size == Some(5) => {
+            out.push(wide_opcode);
+            out.extend_from_slice(&jump.to_be_bytes());
+        }
+        (Ok(narrow_jump), _) => {
+            out.push(opcode);
+            out.extend_from_slice(&narrow_jump.to_be_bytes());
+        }
+        (Err(_), Some(wide_opcode)) => {
+            out.push(wide_opcode);
+            out.extend_from_slice(&jump.to_be_bytes());
+        }
+        (Err(_), None) => fail!("Jump offset {} does not fit in a branch instruction", jump),
+    }
+    Ok(())
+}
+
+pub(crate) fn switch_padding(out: &mut Vec<u8>, offset: usize) {
+    // The operands of switch instructions start at the next multiple of four bytes
+    // after the opcode, counted from the start of the code.
+    for _ in 0..(3 - offset % 4) {
+        out.push(0);
+    }
+}
+
+/// Encodes a single instruction located at the given offset of the code, adding the constants
+/// it refers to to the pool. Opcodes that have several encodings (such as the narrow and wide
+/// forms of local variable instructions, or goto and goto_w) use the encoding of the given
+/// size if possible, or the shortest one that can hold the operands otherwise.
+fn encode_instruction(opcode: &Opcode, offset: usize, size: Option<usize>, pool: &mut ConstantPoolWriter, out: &mut Vec<u8>) -> Result<(), ParseError> {
+    let simple = match opcode {
+        Opcode::Nop => 0x00,
+        Opcode::AconstNull => 0x01,
+        Opcode::IconstM1 => 0x02,
+        Opcode::Iconst0 => 0x03,
+        Opcode::Iconst1 => 0x04,
+        Opcode::Iconst2 => 0x05,
+        Opcode::Iconst3 => 0x06,
+        Opcode::Iconst4 => 0x07,
+        Opcode::Iconst5 => 0x08,
+        Opcode::Lconst0 => 0x09,
+        Opcode::Lconst1 => 0x0a,
+        Opcode::Fconst0 => 0x0b,
+        Opcode::Fconst1 => 0x0c,
+        Opcode::Fconst2 => 0x0d,
+        Opcode::Dconst0 => 0x0e,
+        Opcode::Dconst1 => 0x0f,
+        Opcode::Iaload => 0x2e,
+        Opcode::Laload => 0x2f,
+        Opcode::Faload => 0x30,
+        Opcode::Daload => 0x31,
+        Opcode::Aaload => 0x32,
+        Opcode::Baload => 0x33,
+        Opcode::Caload => 0x34,
+        Opcode::Saload => 0x35,
+        Opcode::Iastore => 0x4f,
+        Opcode::Lastore => 0x50,
+        Opcode::Fastore => 0x51,
+        Opcode::Dastore => 0x52,
+        Opcode::Aastore => 0x53,
+        Opcode::Bastore => 0x54,
+        Opcode::Castore => 0x55,
+        Opcode::Sastore => 0x56,
+        Opcode::Pop => 0x57,
+        Opcode::Pop2 => 0x58,
+        Opcode::Dup => 0x59,
+        Opcode::DupX1 => 0x5a,
+        Opcode::DupX2 => 0x5b,
+        Opcode::Dup2 => 0x5c,
+        Opcode::Dup2X1 => 0x5d,
+        Opcode::Dup2X2 => 0x5e,
+        Opcode::Swap => 0x5f,
+        Opcode::Iadd => 0x60,
+        Opcode::Ladd => 0x61,
+        Opcode::Fadd => 0x62,
+        Opcode::Dadd => 0x63,
+        Opcode::Isub => 0x64,
+        Opcode::Lsub => 0x65,
+        Opcode::Fsub => 0x66,
+        Opcode::Dsub => 0x67,
+        Opcode::Imul => 0x68,
+        Opcode::Lmul => 0x69,
+        Opcode::Fmul => 0x6a,
+        Opcode::Dmul => 0x6b,
+        Opcode::Idiv => 0x6c,
+        Opcode::Ldiv => 0x6d,
+        Opcode::Fdiv => 0x6e,
+        Opcode::Ddiv => 0x6f,
+        Opcode::Irem => 0x70,
+        Opcode::Lrem => 0x71,
+        Opcode::Frem => 0x72,
+        Opcode::Drem => 0x73,
+        Opcode::Ineg => 0x74,
+        Opcode::Lneg => 0x75,
+        Opcode::Fneg => 0x76,
+        Opcode::Dneg => 0x77,
+        Opcode::Ishl => 0x78,
+        Opcode::Lshl => 0x79,
+        Opcode::Ishr => 0x7a,
+        Opcode::Lshr => 0x7b,
+        Opcode::Iushr => 0x7c,
+        Opcode::Lushr => 0x7d,
+        Opcode::Iand => 0x7e,
+        Opcode::Land => 0x7f,
+        Opcode::Ior => 0x80,
+        Opcode::Lor => 0x81,
+        Opcode::Ixor => 0x82,
+        Opcode::Lxor => 0x83,
+        Opcode::I2l => 0x85,
+        Opcode::I2f => 0x86,
+        Opcode::I2d => 0x87,
+        Opcode::L2i => 0x88,
+        Opcode::L2f => 0x89,
+        Opcode::L2d => 0x8a,
+        Opcode::F2i => 0x8b,
+        Opcode::F2l => 0x8c,
+        Opcode::F2d => 0x8d,
+        Opcode::D2i => 0x8e,
+        Opcode::D2l => 0x8f,
+        Opcode::D2f => 0x90,
+        Opcode::I2b => 0x91,
+        Opcode::I2c => 0x92,
+        Opcode::I2s => 0x93,
+        Opcode::Lcmp => 0x94,
+        Opcode::Fcmpl => 0x95,
+        Opcode::Fcmpg => 0x96,
+        Opcode::Dcmpl => 0x97,
+        Opcode::Dcmpg => 0x98,
+        Opcode::Ireturn => 0xac,
+        Opcode::Lreturn => 0xad,
+        Opcode::Freturn => 0xae,
+        Opcode::Dreturn => 0xaf,
+        Opcode::Areturn => 0xb0,
+        Opcode::Return => 0xb1,
+        Opcode::Arraylength => 0xbe,
+        Opcode::Athrow => 0xbf,
+        Opcode::Monitorenter => 0xc2,
+        Opcode::Monitorexit => 0xc3,
+        Opcode::Breakpoint => 0xca,
+        Opcode::Impdep1 => 0xfe,
+        Opcode::Impdep2 => 0xff,
+        _ => return encode_operand_instruction(opcode, offset, size, pool, out),
+    };
+    out.push(simple);
+    Ok(())
+}
+
+fn encode_operand_instruction(opcode: &Opcode, offset: usize, size: Option<usize>, pool: &mut ConstantPoolWriter, out: &mut Vec<u8>) -> Result<(), ParseError> {
+    match opcode {
+        Opcode::Bipush(v) => {
+            out.push(0x10);
+            out.push(*v as u8);
+        }
+        Opcode::Sipush(v) => {
+            out.push(0x11);
+            out.extend_from_slice(&v.to_be_bytes());
+        }
+        Opcode::Ldc(loadable) => {
+            let index = pool.loadable(loadable)?;
+            match u8::try_from(index) {
+                Ok(index) => {
+                    out.push(0x12);
+                    out.push(index);
+                }
+                Err(_) => fail!("Constant pool index {} is too large for ldc", index),
+            }
+        }
+        Opcode::LdcW(loadable) => {
+            out.push(0x13);
+            write_u2(out, pool.loadable(loadable)?);
+        }
+        Opcode::Ldc2W(loadable) => {
+            out.push(0x14);
+            write_u2(out, pool.loadable(loadable)?);
+        }
+        Opcode::Iload(n) => write_local(out, 0x15, Some(0x1a), *n, size),
+        Opcode::Lload(n) => write_local(out, 0x16, Some(0x1e), *n, size),
+        Opcode::Fload(n) => write_local(out, 0x17, Some(0x22), *n, size),
+        Opcode::Dload(n) => write_local(out, 0x18, Some(0x26), *n, size),
+        Opcode::Aload(n) => write_local(out, 0x19, Some(0x2a), *n, size),
+        Opcode::Istore(n) => write_local(out, 0x36, Some(0x3b), *n, size),
+        Opcode::Lstore(n) => write_local(out, 0x37, Some(0x3f), *n, size),
+        Opcode::Fstore(n) => write_local(out, 0x38, Some(0x43), *n, size),
+        Opcode::Dstore(n) => write_local(out, 0x39, Some(0x47), *n, size),
+        Opcode::Astore(n) => write_local(out, 0x3a, Some(0x4b), *n, size),
+        Opcode::Ret(n) => write_local(out, 0xa9, None, *n, size),
+        Opcode::Iinc(n, v) => match (u8::try_from(*n), i8::try_from(*v)) {
+            (Ok(narrow_index), Ok(narrow_value)) if size != Some(6) => {
+                out.push(0x84);
+                out.push(narrow_index);
+                out.push(narrow_value as u8);
+            }
+            _ => {
+                out.push(0xc4);
+                out.push(0x84);
+                write_u2(out, *n);
+                out.extend_from_slice(&v.to_be_bytes());
+            }
+        },
+        Opcode::Ifeq(j) => write_branch(out, 0x99, None, *j, size)?,
+        Opcode::Ifne(j) => write_branch(out, 0x9a, None, *j, size)?,
+        Opcode::Iflt(j) => write_branch(out, 0x9b, None, *j, size)?,
+        Opcode::Ifge(j) => write_branch(out, 0x9c, None, *j, size)?,
+        Opcode::Ifgt(j) => write_branch(out, 0x9d, None, *j, size)?,
+        Opcode::Ifle(j) => write_branch(out, 0x9e, None, *j, size)?,
+        Opcode::IfIcmpeq(j) => write_branch(out, 0x9f, None, *j, size)?,
+        Opcode::IfIcmpne(j) => write_branch(out, 0xa0, None, *j, size)?,
+        Opcode::IfIcmplt(j) => write_branch(out, 0xa1, None, *j, size)?,
+        Opcode::IfIcmpge(j) => write_branch(out, 0xa2, None, *j, size)?,
+        Opcode::IfIcmpgt(j) => write_branch(out, 0xa3, None, *j, size)?,
+        Opcode::IfIcmple(j) => write_branch(out, 0xa4, None, *j, size)?,
+        Opcode::IfAcmpeq(j) => write_branch(out, 0xa5, None, *j, size)?,
+        Opcode::IfAcmpne(j) => write_branch(out, 0xa6, None, *j, size)?,
+        Opcode::Goto(j) => write_branch(out, 0xa7, Some(0xc8), *j, size)?,
+        Opcode::Jsr(j) => write_branch(out, 0xa8, Some(0xc9), *j, size)?,
+        Opcode::Ifnull(j) => write_branch(out, 0xc6, None, *j, size)?,
+        Opcode::Ifnonnull(j) => write_branch(out, 0xc7, None, *j, size)?,
+        Opcode::Tableswitch(table) => {
+            if i64::from(table.high) - i64::from(table.low) + 1 != table.jumps.len() as i64 {
+                fail!("Tableswitch range {} to {} does not match {} jump offsets", table.low, table.high, table.jumps.len());
+            }
+            out.push(0xaa);
+            switch_padding(out, offset);
+            out.extend_from_slice(&table.default.to_be_bytes());
+            out.extend_from_slice(&table.low.to_be_bytes());
+            out.extend_from_slice(&table.high.to_be_bytes());
+            for jump in &table.jumps {
+                out.extend_from_slice(&jump.to_be_bytes());
+            }
+        }
+        Opcode::Lookupswitch(table) => {
+            out.push(0xab);
+            switch_padding(out, offset);
+            out.extend_from_slice(&table.default.to_be_bytes());
+            match i32::try_from(table.match_offsets.len()) {
+                Ok(count) => out.extend_from_slice(&count.to_be_bytes()),
+                Err(_) => fail!("Too many match offsets in lookupswitch"),
+            }
+            for (key, jump) in &table.match_offsets {
+                out.extend_from_slice(&key.to_be_bytes());
+                out.extend_from_slice(&jump.to_be_bytes());
+            }
+        }
+        Opcode::Getstatic(member) => {
+            out.push(0xb2);
+            write_u2(out, pool.member_ref(MemberKind::Field, &member.class_name, &member.name_and_type)?);
+        }
+        Opcode::Putstatic(member) => {
+            out.push(0xb3);
+            write_u2(out, pool.member_ref(MemberKind::Field, &member.class_name, &member.name_and_type)?);
+        }
+        Opcode::Getfield(member) => {
+            out.push(0xb4);
+            write_u2(out, pool.member_ref(MemberKind::Field, &member.class_name, &member.name_and_type)?);
+        }
+        Opcode::Putfield(member) => {
+            out.push(0xb5);
+            write_u2(out, pool.member_ref(MemberKind::Field, &member.class_name, &member.name_and_type)?);
+        }
+        Opcode::Invokevirtual(member) => {
+            out.push(0xb6);
+            write_u2(out, pool.member_ref(MemberKind::Method, &member.class_name, &member.name_and_type)?);
+        }
+        Opcode::Invokespecial(member) => {
+            out.push(0xb7);
+            write_u2(out, pool.any_method_ref(member)?);
+        }
+        Opcode::Invokestatic(member) => {
+            out.push(0xb8);
+            write_u2(out, pool.any_method_ref(member)?);
+        }
+        Opcode::Invokeinterface(member, count) => {
+            out.push(0xb9);
+            write_u2(out, pool.member_ref(MemberKind::InterfaceMethod, &member.class_name, &member.name_and_type)?);
+            out.push(*count);
+            out.push(0);
+        }
+        Opcode::Invokedynamic(invoke_dynamic) => {
+            out.push(0xba);
+            write_u2(out, pool.invoke_dynamic(invoke_dynamic)?);
+            write_u2(out, 0);
+        }
+        Opcode::New(class_name) => {
+            out.push(0xbb);
+            write_u2(out, pool.class(class_name)?);
+        }
+        Opcode::Newarray(array_type) => {
+            out.push(0xbc);
+            out.push(match array_type {
+                PrimitiveArrayType::Boolean => 4,
+                PrimitiveArrayType::Char => 5,
+                PrimitiveArrayType::Float => 6,
+                PrimitiveArrayType::Double => 7,
+                PrimitiveArrayType::Byte => 8,
+                PrimitiveArrayType::Short => 9,
+                PrimitiveArrayType::Int => 10,
+                PrimitiveArrayType::Long => 11,
+            });
+        }
+        Opcode::Anewarray(class_name) => {
+            out.push(0xbd);
+            write_u2(out, pool.class(class_name)?);
+        }
+        Opcode::Checkcast(class_name) => {
+            out.push(0xc0);
+            write_u2(out, pool.class(class_name)?);
+        }
+        Opcode::Instanceof(class_name) => {
+            out.push(0xc1);
+            write_u2(out, pool.class(class_name)?);
+        }
+        Opcode::Multianewarray(class_name, dimensions) => {
+            out.push(0xc5);
+            write_u2(out, pool.class(class_name)?);
+            out.push(*dimensions);
+        }
+        _ => unreachable!("Opcodes without operands are encoded by encode_instruction"),
+    }
+    Ok(())
+}
+
+/// Returns the code bytes for a Code attribute. If the bytecode was parsed, it is encoded
+/// from the opcodes, using the opcode offsets to pick between the different encodings of
+/// opcodes that have more than one. Otherwise the raw code bytes are used as they are.
+pub(crate) fn encode_code(code: &CodeData, pool: &mut ConstantPoolWriter) -> Result<Vec<u8>, ParseError> {
+    let bytecode = match &code.bytecode {
+        Some(bytecode) => bytecode,
+        None => return Ok(code.code.clone()),
+    };
+    let mut out = Vec::with_capacity(code.code.len());
+    for (i, (offset, opcode)) in bytecode.opcodes.iter().enumerate() {
+        if out.len() != *offset {
+            fail!("Opcode at offset {} does not start where the previous opcode ends, at offset {}", offset, out.len());
+        }
+        let end = match bytecode.opcodes.get(i + 1) {
+            Some((next_offset, _)) => *next_offset,
+            None => code.code.len(),
+        };
+        let size = end.checked_sub(*offset).filter(|size| *size > 0);
+        encode_instruction(opcode, *offset, size, pool, &mut out).map_err(|e| err!(e, "opcode at offset {}", offset))?;
+    }
+    Ok(out)
+}
+
+/// Returns the target_type value for a type annotation. The parsed target information does
+/// not always determine this outside of code; for example an empty target may be a field type,
+/// a method return type or a method receiver type. In those cases the most common target type
+/// that fits the location of the annotation is picked.
+fn type_annotation_target_type(target: &TypeAnnotationTarget, location: AttributeLocation) -> Result<u8, ParseError> {
+    let in_class = location == AttributeLocation::ClassFile;
+    let target_type = match target {
+        TypeAnnotationTarget::TypeParameter { .. } => if in_class { 0x00 } else { 0x01 },
+        TypeAnnotationTarget::Supertype { .. } => 0x10,
+        TypeAnnotationTarget::TypeParameterBound { .. } => if in_class { 0x11 } else { 0x12 },
+        TypeAnnotationTarget::Empty => if location == AttributeLocation::Method { 0x14 } else { 0x13 },
+        TypeAnnotationTarget::FormalParameter { .. } => 0x16,
+        TypeAnnotationTarget::Throws { .. } => 0x17,
+        TypeAnnotationTarget::LocalVar(_) => 0x40,
+        TypeAnnotationTarget::Catch { .. } => 0x42,
+        TypeAnnotationTarget::Offset { target_type: target_type @ 0x43..=0x46, .. } => *target_type,
+        TypeAnnotationTarget::TypeArgument { target_type: target_type @ 0x47..=0x4B, .. } => *target_type,
+        TypeAnnotationTarget::Offset { target_type, .. } | TypeAnnotationTarget::TypeArgument { target_type, .. } => {
+            fail!("Invalid target type {} for an instruction type annotation", target_type)
+        }
+    };
+    Ok(target_type)
+}
+
+fn write_verification_type(out: &mut Vec<u8>, verification_type: &VerificationType, pool: &mut ConstantPoolWriter) -> Result<(), ParseError> {
+    match verification_type {
+        VerificationType::Top => write_u1(out, 0),
+        VerificationType::Integer => write_u1(out, 1),
+        VerificationType::Float => write_u1(out, 2),
+        VerificationType::Double => write_u1(out, 3),
+        VerificationType::Long => write_u1(out, 4),
+        VerificationType::Null => write_u1(out, 5),
+        VerificationType::UninitializedThis => write_u1(out, 6),
+        VerificationType::Object { class_name } => {
+            write_u1(out, 7);
+            write_u2(out, pool.class(class_name)?);
+        }
+        VerificationType::Uninitialized { code_offset } => {
+            write_u1(out, 8);
+            write_u2(out, *code_offset);
+        }
+    }
+    Ok(())
+}
+
+fn write_verification_types(out: &mut Vec<u8>, verification_types: &[VerificationType], pool: &mut ConstantPoolWriter) -> Result<(), ParseError> {
+    for verification_type in verification_types {
+        write_verification_type(out, verification_type, pool)?;
+    }
+    Ok(())
+}
+
+/// Writes a stack map frame using the most compact frame type that can represent it.
+fn write_stack_map_entry(out: &mut Vec<u8>, entry: &StackMapEntry, pool: &mut ConstantPoolWriter) -> Result<(), ParseError> {
+    match entry {
+        StackMapEntry::Same { offset_delta } if *offset_delta < 64 => write_u1(out, *offset_delta as u8),
+        StackMapEntry::Same { offset_delta } => {
+            write_u1(out, 251);
+            write_u2(out, *offset_delta);
+        }
+        StackMapEntry::SameLocals1StackItem { offset_delta, stack } => {
+            if *offset_delta < 64 {
+                write_u1(out, 64 + *offset_delta as u8);
+            } else {
+                write_u1(out, 247);
+                write_u2(out, *offset_delta);
+            }
+            write_verification_type(out, stack, pool)?;
+        }
+        StackMapEntry::Chop { offset_delta, chop_count } => {
+            if !(1..=3).contains(chop_count) {
+                fail!("Invalid chop count {}", chop_count);
+            }
+            write_u1(out, 251 - *chop_count as u8);
+            write_u2(out, *offset_delta);
+        }
+        StackMapEntry::Append { offset_delta, locals } => {
+            if !(1..=3).contains(&locals.len()) {
+                fail!("Invalid number of appended locals {}", locals.len());
+            }
+            write_u1(out, 251 + locals.len() as u8);
+            write_u2(out, *offset_delta);
+            write_verification_types(out, locals, pool)?;
+        }
+        StackMapEntry::FullFrame { offset_delta, locals, stack } => {
+            write_u1(out, 255);
+            write_u2(out, *offset_delta);
+            write_count(out, locals.len(), "locals")?;
+            write_verification_types(out, locals, pool)?;
+            write_count(out, stack.len(), "stack entries")?;
+            write_verification_types(out, stack, pool)?;
+        }
+    }
+    Ok(())
+}
+
+fn write_element_value(out: &mut Vec<u8>, value: &AnnotationElementValue, pool: &mut ConstantPoolWriter) -> Result<(), ParseError> {
+    match value {
+        AnnotationElementValue::ByteConstant(v) => {
+            write_u1(out, b'B');
+            write_u2(out, pool.add(IndexedEntry::Integer(*v))?);
+        }
+        AnnotationElementValue::CharConstant(v) => {
+            write_u1(out, b'C');
+            write_u2(out, pool.add(IndexedEntry::Integer(*v))?);
+        }
+        AnnotationElementValue::DoubleConstant(v) => {
+            write_u1(out, b'D');
+            write_u2(out, pool.add(IndexedEntry::Double(v.to_bits()))?);
+        }
+        AnnotationElementValue::FloatConstant(v) => {
+            write_u1(out, b'F');
+            write_u2(out, pool.add(IndexedEntry::Float(v.to_bits()))?);
+        }
+        AnnotationElementValue::IntConstant(v) => {
+            write_u1(out, b'I');
+            write_u2(out, pool.add(IndexedEntry::Integer(*v))?);
+        }
+        AnnotationElementValue::LongConstant(v) => {
+            write_u1(out, b'J');
+            write_u2(out, pool.add(IndexedEntry::Long(*v))?);
+        }
+        AnnotationElementValue::ShortConstant(v) => {
+            write_u1(out, b'S');
+            write_u2(out, pool.add(IndexedEntry::Integer(*v))?);
+        }
+        AnnotationElementValue::BooleanConstant(v) => {
+            write_u1(out, b'Z');
+            write_u2(out, pool.add(IndexedEntry::Integer(*v))?);
+        }
+        AnnotationElementValue::StringConstant(s) => {
+            write_u1(out, b's');
+            write_u2(out, pool.utf8(s)?);
+        }
+        AnnotationElementValue::EnumConstant { type_name, const_name } => {
+            write_u1(out, b'e');
+            write_u2(out, pool.utf8(type_name)?);
+            write_u2(out, pool.utf8(const_name)?);
+        }
+        AnnotationElementValue::ClassLiteral { class_name } => {
+            write_u1(out, b'c');
+            write_u2(out, pool.utf8(class_name)?);
+        }
+        AnnotationElementValue::AnnotationValue(annotation) => {
+            write_u1(out, b'@');
+            write_annotation(out, annotation, pool)?;
+        }
+        AnnotationElementValue::ArrayValue(values) => {
+            write_u1(out, b'[');
+            write_count(out, values.len(), "array values")?;
+            for value in values {
+                write_element_value(out, value, pool)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn write_annotation(out: &mut Vec<u8>, annotation: &Annotation, pool: &mut ConstantPoolWriter) -> Result<(), ParseError> {
+    write_u2(out, pool.utf8(&annotation.type_descriptor)?);
+    write_count(out, annotation.elements.len(), "annotation elements")?;
+    for element in &annotation.elements {
+        write_u2(out, pool.utf8(&element.name)?);
+        write_element_value(out, &element.value, pool)?;
+    }
+    Ok(())
+}
+
+fn write_annotations(out: &mut Vec<u8>, annotations: &[Annotation], pool: &mut ConstantPoolWriter) -> Result<(), ParseError> {
+    write_count(out, annotations.len(), "annotations")?;
+    for annotation in annotations {
+        write_annotation(out, annotation, pool)?;
+    }
+    Ok(())
+}
+
+fn write_type_annotation(out: &mut Vec<u8>, annotation: &TypeAnnotation, pool: &mut ConstantPoolWriter, location: AttributeLocation) -> Result<(), ParseError> {
+    write_u1(out, type_annotation_target_type(&annotation.target_type, location)?);
+    match &annotation.target_type {
+        TypeAnnotationTarget::TypeParameter { index } |
+        TypeAnnotationTarget::FormalParameter { index } => write_u1(out, *index),
+        TypeAnnotationTarget::Supertype { index } |
+        TypeAnnotationTarget::Throws { index } => write_u2(out, *index),
+        TypeAnnotationTarget::TypeParameterBound { type_parameter_index, bound_index } => {
+            write_u1(out, *type_parameter_index);
+            write_u1(out, *bound_index);
+        }
+        TypeAnnotationTarget::Empty => (),
+        TypeAnnotationTarget::LocalVar(entries) => {
+            write_count(out, entries.len(), "local variable targets")?;
+            for entry in entries {
+                write_u2(out, entry.start_pc);
+                write_u2(out, entry.length);
+                write_u2(out, entry.index);
+            }
+        }
+        TypeAnnotationTarget::Catch { exception_table_index } => write_u2(out, *exception_table_index),
+        TypeAnnotationTarget::Offset { offset, .. } => write_u2(out, *offset),
+        TypeAnnotationTarget::TypeArgument { offset, type_argument_index, .. } => {
+            write_u2(out, *offset);
+            write_u1(out, *type_argument_index);
+        }
+    }
+    write_u1_count(out, annotation.target_path.len(), "type path entries")?;
+    for entry in &annotation.target_path {
+        write_u1(out, match entry.path_kind {
+            TypeAnnotationTargetPathKind::DeeperArray => 0,
+            TypeAnnotationTargetPathKind::DeeperNested => 1,
+            TypeAnnotationTargetPathKind::WildcardTypeArgument => 2,
+            TypeAnnotationTargetPathKind::TypeArgument => 3,
+        });
+        write_u1(out, entry.argument_index);
+    }
+    write_annotation(out, &annotation.annotation, pool)
+}
+
+fn write_attribute_data(out: &mut Vec<u8>, data: &AttributeData, pool: &mut ConstantPoolWriter, location: AttributeLocation) -> Result<(), ParseError> {
+    match data {
+        AttributeData::ConstantValue(literal) => write_u2(out, pool.literal(literal)?),
+        AttributeData::Code(code) => {
+            write_u2(out, code.max_stack);
+            write_u2(out, code.max_locals);
+            let bytes = encode_code(code, pool)?;
+            write_length(out, bytes.len(), "Code")?;
+            out.extend_from_slice(&bytes);
+            write_count(out, code.exception_table.len(), "exception table entries")?;
+            for entry in &code.exception_table {
+                write_u2(out, entry.start_pc);
+                write_u2(out, entry.end_pc);
+                write_u2(out, entry.handler_pc);
+                write_u2(out, pool.class_opt(&entry.catch_type)?);
+            }
+            write_attributes(out, &code.attributes, pool, AttributeLocation::Code)?;
+        }
+        AttributeData::StackMapTable(entries) => {
+            write_count(out, entries.len(), "stack map frames")?;
+            for (i, entry) in entries.iter().enumerate() {
+                write_stack_map_entry(out, entry, pool).map_err(|e| err!(e, "stack map entry {}", i))?;
+            }
+        }
+        AttributeData::Exceptions(exceptions) |
+        AttributeData::NestMembers(exceptions) => {
+            write_count(out, exceptions.len(), "classes")?;
+            for exception in exceptions {
+                write_u2(out, pool.class(exception)?);
+            }
+        }
+        AttributeData::InnerClasses(entries) => {
+            write_count(out, entries.len(), "inner classes")?;
+            for entry in entries {
+                write_u2(out, pool.class(&entry.inner_class_info)?);
+                write_u2(out, pool.class_opt(&entry.outer_class_info)?);
+                write_u2(out, pool.utf8_opt(&entry.inner_name)?);
+                write_u2(out, entry.access_flags.bits());
+            }
+        }
+        AttributeData::EnclosingMethod { class_name, method } => {
+            write_u2(out, pool.class(class_name)?);
+            match method {
+                Some(method) => write_u2(out, pool.name_and_type(method)?),
+                None => write_u2(out, 0),
+            }
+        }
+        AttributeData::Synthetic |
+        AttributeData::Deprecated => (),
+        AttributeData::Signature(value) |
+        AttributeData::SourceFile(value) => write_u2(out, pool.utf8(value)?),
+        AttributeData::SourceDebugExtension(value) => out.extend_from_slice(&cesu8::to_java_cesu8(value)),
+        AttributeData::LineNumberTable(entries) => {
+            write_count(out, entries.len(), "line numbers")?;
+            for entry in entries {
+                write_u2(out, entry.start_pc);
+                write_u2(out, entry.line_number);
+            }
+        }
+        AttributeData::LocalVariableTable(entries) => {
+            write_count(out, entries.len(), "local variables")?;
+            for entry in entries {
+                write_u2(out, entry.start_pc);
+                write_u2(out, entry.length);
+                write_u2(out, pool.utf8(&entry.name)?);
+                write_u2(out, pool.utf8(&entry.descriptor)?);
+                write_u2(out, entry.index);
+            }
+        }
+        AttributeData::LocalVariableTypeTable(entries) => {
+            write_count(out, entries.len(), "local variables")?;
+            for entry in entries {
+                write_u2(out, entry.start_pc);
+                write_u2(out, entry.length);
+                write_u2(out, pool.utf8(&entry.name)?);
+                write_u2(out, pool.utf8(&entry.signature)?);
+                write_u2(out, entry.index);
+            }
+        }
+        AttributeData::RuntimeVisibleAnnotations(annotations) |
+        AttributeData::RuntimeInvisibleAnnotations(annotations) => write_annotations(out, annotations, pool)?,
+        AttributeData::RuntimeVisibleParameterAnnotations(parameters) |
+        AttributeData::RuntimeInvisibleParameterAnnotations(parameters) => {
+            write_u1_count(out, parameters.len(), "parameters")?;
+            for parameter in parameters {
+                write_annotations(out, &parameter.annotations, pool)?;
+            }
+        }
+        AttributeData::RuntimeVisibleTypeAnnotations(annotations) |
+        AttributeData::RuntimeInvisibleTypeAnnotations(annotations) => {
+            write_count(out, annotations.len(), "type annotations")?;
+            for annotation in annotations {
+                write_type_annotation(out, annotation, pool, location)?;
+            }
+        }
+        AttributeData::AnnotationDefault(value) => write_element_value(out, value, pool)?,
+        AttributeData::BootstrapMethods(entries) => {
+            write_count(out, entries.len(), "bootstrap methods")?;
+            for entry in entries {
+                write_u2(out, pool.method_handle(&entry.method)?);
+                write_count(out, entry.arguments.len(), "bootstrap arguments")?;
+                for argument in &entry.arguments {
+                    write_u2(out, pool.bootstrap_argument(argument)?);
+                }
+            }
+        }
+        AttributeData::MethodParameters(entries) => {
+            write_u1_count(out, entries.len(), "method parameters")?;
+            for entry in entries {
+                write_u2(out, pool.utf8_opt(&entry.name)?);
+                write_u2(out, entry.access_flags.bits());
+            }
+        }
+        AttributeData::Module(module) => {
+            write_u2(out, pool.module(&module.name)?);
+            write_u2(out, module.access_flags.bits());
+            write_u2(out, pool.utf8_opt(&module.version)?);
+            write_count(out, module.requires.len(), "requires entries")?;
+            for entry in &module.requires {
+                write_u2(out, pool.module(&entry.name)?);
+                write_u2(out, entry.flags.bits());
+                write_u2(out, pool.utf8_opt(&entry.version)?);
+            }
+            write_count(out, module.exports.len(), "exports entries")?;
+            for entry in &module.exports {
+                write_u2(out, pool.package(&entry.package_name)?);
+                write_u2(out, entry.flags.bits());
+                write_count(out, entry.exports_to.len(), "exports_to entries")?;
+                for name in &entry.exports_to {
+                    write_u2(out, pool.module(name)?);
+                }
+            }
+            write_count(out, module.opens.len(), "opens entries")?;
+            for entry in &module.opens {
+                write_u2(out, pool.package(&entry.package_name)?);
+                write_u2(out, entry.flags.bits());
+                write_count(out, entry.opens_to.len(), "opens_to entries")?;
+                for name in &entry.opens_to {
+                    write_u2(out, pool.module(name)?);
+                }
+            }
+            write_count(out, module.uses.len(), "uses entries")?;
+            for name in &module.uses {
+                write_u2(out, pool.class(name)?);
+            }
+            write_count(out, module.provides.len(), "provides entries")?;
+            for entry in &module.provides {
+                write_u2(out, pool.class(&entry.service_interface_name)?);
+                write_count(out, entry.provides_with.len(), "provides_with entries")?;
+                for name in &entry.provides_with {
+                    write_u2(out, pool.class(name)?);
+                }
+            }
+        }
+        AttributeData::ModulePackages(packages) => {
+            write_count(out, packages.len(), "packages")?;
+            for package in packages {
+                write_u2(out, pool.package(package)?);
+            }
+        }
+        AttributeData::ModuleMainClass(class_name) |
+        AttributeData::NestHost(class_name) => write_u2(out, pool.class(class_name)?),
+        AttributeData::Record(components) => {
+            write_count(out, components.len(), "record components")?;
+            for (i, component) in components.iter().enumerate() {
+                write_u2(out, pool.utf8(&component.name)?);
+                write_u2(out, pool.utf8(&component.descriptor)?);
+                write_attributes(out, &component.attributes, pool, AttributeLocation::RecordComponent).map_err(|e| err!(e, "entry {}", i))?;
+            }
+        }
+        AttributeData::Other(bytes) => out.extend_from_slice(bytes),
+        AttributeData::Lazy(lazy) => write_attribute_data(out, lazy.decode()?, pool, location)?,
+    }
+    Ok(())
+}
+
+fn write_attributes(out: &mut Vec<u8>, attributes: &[AttributeInfo], pool: &mut ConstantPoolWriter, location: AttributeLocation) -> Result<(), ParseError> {
+    write_count(out, attributes.len(), "attributes")?;
+    for (i, attr) in attributes.iter().enumerate() {
+        write_u2(out, pool.utf8(&attr.name)?);
+        let mut data = Vec::new();
+        write_attribute_data(&mut data, &attr.data, pool, location).map_err(|e| err!(e, "{} attribute {}", attr.name, i))?;
+        write_length(out, data.len(), &attr.name)?;
+        out.extend_from_slice(&data);
+    }
+    Ok(())
+}
+
+pub(crate) fn write_class_with_pool(class: &ClassFile, pool: &mut ConstantPoolWriter) -> Result<Vec<u8>, ParseError> {
+    let mut body = Vec::new();
+    write_u2(&mut body, class.access_flags.bits());
+    write_u2(&mut body, pool.class(&class.this_class).map_err(|e| err!(e, "this_class"))?);
+    write_u2(&mut body, pool.class_opt(&class.super_class).map_err(|e| err!(e, "super_class"))?);
+    write_count(&mut body, class.interfaces.len(), "interfaces")?;
+    for interface in &class.interfaces {
+        write_u2(&mut body, pool.class(interface)?);
+    }
+    write_count(&mut body, class.fields.len(), "fields")?;
+    for (i, field) in class.fields.iter().enumerate() {
+        write_u2(&mut body, field.access_flags.bits());
+        write_u2(&mut body, pool.utf8(&field.name).map_err(|e| err!(e, "name of class field {}", i))?);
+        write_u2(&mut body, pool.utf8(&field.descriptor).map_err(|e| err!(e, "descriptor of class field {}", i))?);
+        write_attributes(&mut body, &field.attributes, pool, AttributeLocation::Field).map_err(|e| err!(e, "class field {}", i))?;
+    }
+    write_count(&mut body, class.methods.len(), "methods")?;
+    for (i, method) in class.methods.iter().enumerate() {
+        write_u2(&mut body, method.access_flags.bits());
+        write_u2(&mut body, pool.utf8(&method.name).map_err(|e| err!(e, "name of class method {}", i))?);
+        write_u2(&mut body, pool.utf8(&method.descriptor).map_err(|e| err!(e, "descriptor of class method {}", i))?);
+        write_attributes(&mut body, &method.attributes, pool, AttributeLocation::Method).map_err(|e| err!(e, "class method {}", i))?;
+    }
+    write_attributes(&mut body, &class.attributes, pool, AttributeLocation::ClassFile).map_err(|e| err!(e, "class"))?;
+
+    let mut out = Vec::new();
+    write_u4(&mut out, 0xCAFE_BABE);
+    write_u2(&mut out, class.minor_version);
+    write_u2(&mut out, class.major_version);
+    pool.write(&mut out);
+    out.extend_from_slice(&body);
+    Ok(out)
+}
+
+/// Serializes a class to the class file format. If the class was parsed from a class file,
+/// its constant pool is kept as it was, with any constants that are newly needed appended
+/// at the end. This keeps the constant pool indices found in raw data, such as the code of
+/// methods whose bytecode was not parsed and the contents of attributes that are not
+/// recognized, valid in the output. Otherwise a new constant pool is built, with the
+/// constants loaded by ldc instructions placed first so that they are within its reach.
+///
+/// Some details of the input class file are not kept when it is parsed, and are normalized
+/// when it is written. Access flag bits with no defined meaning are dropped, parsed bytecode
+/// refers to the first of any duplicate constant pool entries, stack map frames are written
+/// using the most compact frame type, and the target_type of type annotations outside of
+/// code is derived from where the annotation is found, so (for instance) annotations on
+/// method receiver types are written as annotations on the method return type.
+pub fn write_class(class: &ClassFile) -> Result<Vec<u8>, ParseError> {
+    let mut pool = ConstantPoolWriter::from_class(class);
+    if class.constant_pool.is_empty() {
+        let mut loadables = Vec::new();
+        for method in &class.methods {
+            for attr in &method.attributes {
+                if let Ok(AttributeData::Code(CodeData { bytecode: Some(bytecode), .. })) = attr.decoded() {
+                    for (_, opcode) in &bytecode.opcodes {
+                        if let Opcode::Ldc(loadable) = opcode {
+                            loadables.push(loadable);
+                        }
+                    }
+                }
+            }
+        }
+        pool.add_low_loadables(&loadables)?;
+    }
+    write_class_with_pool(class, &mut pool)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembly::{assemble, print_assembly};
+    use crate::parse_class;
+
+    // A class with one method whose code carries a type annotation of every instruction
+    // target type, all on the invokespecial at offset 1.
+    fn class_bytes() -> Vec<u8> {
+        let mut pool = Vec::new();
+        for text in ["Test", "java/lang/Object", "run", "()V", "Code", "RuntimeVisibleTypeAnnotations", "LA;"] {
+            pool.push(1);
+            pool.extend((text.len() as u16).to_be_bytes());
+            pool.extend(text.as_bytes());
+        }
+        pool.extend([7, 0, 1, 7, 0, 2, 12, 0, 3, 0, 4, 10, 0, 8, 0, 10]);
+
+        let mut annotations = vec![0, 9];
+        for target_type in 0x43..=0x4b {
+            annotations.extend([target_type, 0, 1]);
+            if target_type >= 0x47 {
+                annotations.push(target_type - 0x47);
+            }
+            annotations.extend([0, 0, 7, 0, 0]);
+        }
+        let mut code = vec![0, 2, 0, 1, 0, 0, 0, 5, 0x2a, 0xb7, 0, 11, 0xb1, 0, 0, 0, 1, 0, 6];
+        code.extend((annotations.len() as u32).to_be_bytes());
+        code.extend(annotations);
+
+        let mut bytes = vec![0xca, 0xfe, 0xba, 0xbe, 0, 0, 0, 52, 0, 12];
+        bytes.extend(pool);
+        bytes.extend([0, 0x21, 0, 8, 0, 9, 0, 0, 0, 0, 0, 1, 0, 1, 0, 3, 0, 4, 0, 1, 0, 5]);
+        bytes.extend((code.len() as u32).to_be_bytes());
+        bytes.extend(code);
+        bytes.extend([0, 0]);
+        bytes
+    }
+
+    #[test]
+    fn test_type_annotation_round_trip() {
+        let bytes = class_bytes();
+        let class = parse_class(&bytes).unwrap();
+        let written = write_class(&class).unwrap();
+        assert_eq!(written, bytes);
+        assert_eq!(parse_class(&written).unwrap(), class);
+        let text = print_assembly(&class).unwrap();
+        assert!(text.contains("typeargument constructorreference "), "{}", text);
+        assert_eq!(print_assembly(&parse_class(&assemble(&text).unwrap()).unwrap()).unwrap(), text);
+
+        let mut class = class;
+        let code = match &mut class.methods[0].attributes[0].data {
+            AttributeData::Code(code) => code,
+            _ => panic!("Expected a Code attribute"),
+        };
+        match &mut code.attributes[0].data {
+            AttributeData::RuntimeVisibleTypeAnnotations(annotations) => {
+                annotations[0].target_type = TypeAnnotationTarget::Offset { target_type: 0x47, offset: 1 };
+            }
+            _ => panic!("Expected type annotations"),
+        }
+        assert!(write_class(&class).is_err());
+    }
+}